@@ -0,0 +1,8 @@
+//! The version of the host interface Floneum plugins are built against. Both the host and the plugin macro depend on
+//! this crate so they always agree on the version.
+
+/// The version of the host interface plugins are built against. Bump this whenever plugins built for the previous
+/// version can't be loaded anymore.
+///
+/// - v4: Plugins must embed a manifest with the capabilities they need.
+pub const CURRENT_BINDING_VERSION: usize = 4;
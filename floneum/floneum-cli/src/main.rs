@@ -112,6 +112,7 @@ async fn package_and_build(
             .with_extension("wasm");
         let plugin = load_plugin(&build_path, Default::default());
         let instance = plugin.instance().await.unwrap();
+        let capabilities = plugin.capabilities().await.unwrap().to_vec();
         let info = instance.metadata();
         let name = &info.name;
         let version = this_package.version.to_string();
@@ -137,7 +138,8 @@ async fn package_and_build(
             description,
             &binding_version,
         )
-        .with_authors(authors);
+        .with_authors(authors)
+        .with_capabilities(capabilities);
 
         // Normalize case to lowercase for github
         let package_path = package_path.join(name.to_lowercase());
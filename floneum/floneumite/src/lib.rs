@@ -5,12 +5,15 @@ use once_cell::sync::Lazy;
 mod package;
 pub use package::{Category, PackageStructure};
 
+mod manifest;
+pub use manifest::{Capability, ManifestError, PluginManifest, MANIFEST_SECTION};
+
 mod index;
 pub use index::{FloneumPackageIndex, PackageIndexEntry};
 
 pub use crate::package::Config;
 
-pub const CURRENT_BINDING_VERSION: usize = 3;
+pub use floneum_binding_version::CURRENT_BINDING_VERSION;
 
 /// The path to the floneum packages directory.
 #[tracing::instrument]
@@ -0,0 +1,267 @@
+use core::fmt;
+use std::{
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the custom WASM section the plugin manifest is stored in.
+pub const MANIFEST_SECTION: &str = "floneum-manifest";
+
+/// A host capability a plugin can request.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    /// Make HTTP requests
+    Network,
+    /// Read and write files in the plugin sandbox folder
+    Filesystem,
+    /// Open and control browser pages
+    Browser,
+    /// Load and run text generation and embedding models
+    Models,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [
+        Capability::Network,
+        Capability::Filesystem,
+        Capability::Browser,
+        Capability::Models,
+    ];
+}
+
+impl FromStr for Capability {
+    type Err = ManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "network" => Ok(Capability::Network),
+            "filesystem" | "fs" => Ok(Capability::Filesystem),
+            "browser" => Ok(Capability::Browser),
+            "models" => Ok(Capability::Models),
+            _ => Err(ManifestError::UnknownCapability(s.to_string())),
+        }
+    }
+}
+
+impl Display for Capability {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Network => write!(f, "network"),
+            Capability::Filesystem => write!(f, "filesystem"),
+            Capability::Browser => write!(f, "browser"),
+            Capability::Models => write!(f, "models"),
+        }
+    }
+}
+
+/// An error that can occur while reading or checking a plugin manifest.
+#[derive(Debug)]
+pub enum ManifestError {
+    /// The WASM binary could not be read.
+    InvalidWasm(String),
+    /// The manifest section is not valid TOML.
+    InvalidManifest(toml::de::Error),
+    /// The manifest requests a capability the host does not know about.
+    UnknownCapability(String),
+    /// The plugin was built without a manifest, so it can't declare the capabilities it needs.
+    MissingManifest,
+    /// The plugin was built for a different version of the host interface.
+    IncompatibleBindingVersion {
+        plugin: String,
+        required: usize,
+        host: usize,
+    },
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestError::InvalidWasm(err) => write!(f, "Invalid plugin binary: {err}"),
+            ManifestError::InvalidManifest(err) => write!(f, "Invalid plugin manifest: {err}"),
+            ManifestError::UnknownCapability(capability) => {
+                write!(f, "Unknown plugin capability {capability:?}")
+            }
+            ManifestError::MissingManifest => write!(
+                f,
+                "Plugin has no manifest, so it can't declare the capabilities it needs. Rebuild the plugin with the latest version of floneum_rust (host interface v{}) and list its capabilities with the #[capabilities(...)] attribute.",
+                crate::CURRENT_BINDING_VERSION
+            ),
+            ManifestError::IncompatibleBindingVersion {
+                plugin,
+                required,
+                host,
+            } => {
+                if required < host {
+                    write!(f, "Plugin {plugin} was built for host interface v{required}, but this version of Floneum only supports v{host}. Rebuild the plugin with the latest version of floneum_rust.")
+                } else {
+                    write!(f, "Plugin {plugin} requires host interface v{required}, but this version of Floneum only supports v{host}. Update Floneum to use this plugin.")
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+/// A manifest embedded in the plugin's WASM binary that describes the plugin and what it is allowed to do.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PluginManifest {
+    pub name: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+    pub binding_version: usize,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+}
+
+fn default_version() -> String {
+    "0.1".to_string()
+}
+
+impl PluginManifest {
+    pub fn new(name: &str, version: &str, capabilities: Vec<Capability>) -> Self {
+        Self {
+            name: name.to_string(),
+            version: version.to_string(),
+            binding_version: crate::CURRENT_BINDING_VERSION,
+            capabilities,
+        }
+    }
+
+    /// Read the manifest from the custom section of a WASM binary. Returns `None` if the plugin was built without a manifest.
+    pub fn from_wasm(bytes: &[u8]) -> Result<Option<Self>, ManifestError> {
+        match find_custom_section(bytes, MANIFEST_SECTION)? {
+            Some(section) => {
+                let text = std::str::from_utf8(section)
+                    .map_err(|err| ManifestError::InvalidWasm(err.to_string()))?;
+                let manifest = toml::from_str(text).map_err(ManifestError::InvalidManifest)?;
+                Ok(Some(manifest))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Check that the plugin was built for the host interface this version of floneum supports.
+    pub fn check_compatibility(&self) -> Result<(), ManifestError> {
+        if self.binding_version != crate::CURRENT_BINDING_VERSION {
+            return Err(ManifestError::IncompatibleBindingVersion {
+                plugin: self.name.clone(),
+                required: self.binding_version,
+                host: crate::CURRENT_BINDING_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check if the plugin requested a capability.
+    pub fn allows(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+fn read_leb128(bytes: &[u8], position: &mut usize) -> Result<usize, ManifestError> {
+    let mut result = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes
+            .get(*position)
+            .ok_or_else(|| ManifestError::InvalidWasm("unexpected end of binary".to_string()))?;
+        *position += 1;
+        result |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+        if shift >= usize::BITS {
+            return Err(ManifestError::InvalidWasm("integer too large".to_string()));
+        }
+    }
+}
+
+fn find_custom_section<'a>(bytes: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, ManifestError> {
+    const HEADER: [u8; 4] = *b"\0asm";
+    if bytes.len() < 8 || bytes[..4] != HEADER {
+        return Err(ManifestError::InvalidWasm(
+            "missing wasm header".to_string(),
+        ));
+    }
+    let mut position = 8;
+    while position < bytes.len() {
+        let id = bytes[position];
+        position += 1;
+        let size = read_leb128(bytes, &mut position)?;
+        let end = position
+            .checked_add(size)
+            .filter(|end| *end <= bytes.len())
+            .ok_or_else(|| ManifestError::InvalidWasm("section out of bounds".to_string()))?;
+        if id == 0 {
+            let mut name_position = position;
+            let name_len = read_leb128(bytes, &mut name_position)?;
+            let name_end = name_position.checked_add(name_len).ok_or_else(|| {
+                ManifestError::InvalidWasm("section name out of bounds".to_string())
+            })?;
+            if name_end <= end && &bytes[name_position..name_end] == name.as_bytes() {
+                return Ok(Some(&bytes[name_end..end]));
+            }
+        }
+        position = end;
+    }
+    Ok(None)
+}
+
+#[test]
+fn reads_manifest_from_custom_section() {
+    let manifest = PluginManifest::new("Read Rss", "0.2.0", vec![Capability::Network]);
+    let contents = toml::to_string(&manifest).unwrap();
+
+    let mut section = Vec::new();
+    section.push(MANIFEST_SECTION.len() as u8);
+    section.extend_from_slice(MANIFEST_SECTION.as_bytes());
+    section.extend_from_slice(contents.as_bytes());
+
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    wasm.push(0);
+    let mut size = section.len();
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            wasm.push(byte);
+            break;
+        }
+        wasm.push(byte | 0x80);
+    }
+    wasm.extend_from_slice(&section);
+
+    let read = PluginManifest::from_wasm(&wasm).unwrap().unwrap();
+    assert_eq!(read, manifest);
+    assert!(read.allows(Capability::Network));
+    assert!(!read.allows(Capability::Filesystem));
+    assert!(read.check_compatibility().is_ok());
+
+    assert_eq!(PluginManifest::from_wasm(b"\0asm\x01\0\0\0").unwrap(), None);
+}
+
+#[test]
+fn rejects_section_name_that_overflows() {
+    let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+    // A custom section whose name length is usize::MAX
+    let mut name_len = Vec::new();
+    let mut size = usize::MAX;
+    while size != 0 {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        name_len.push(if size == 0 { byte } else { byte | 0x80 });
+    }
+    wasm.push(0);
+    wasm.push(name_len.len() as u8);
+    wasm.extend_from_slice(&name_len);
+
+    assert!(matches!(
+        PluginManifest::from_wasm(&wasm),
+        Err(ManifestError::InvalidWasm(_))
+    ));
+}
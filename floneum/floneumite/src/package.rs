@@ -6,6 +6,8 @@ use std::{
 
 use serde::{Deserialize, Serialize};
 
+use crate::Capability;
+
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Config {
     packages: Vec<PackageStructure>,
@@ -90,6 +92,8 @@ pub struct PackageStructure {
     pub package_version: String,
     #[serde(default = "current_binding_version")]
     pub binding_version: String,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
 }
 
 fn default_version() -> String {
@@ -115,10 +119,18 @@ impl PackageStructure {
             package_version: version.to_string(),
             binding_version: binding_version.to_string(),
             authors: Vec::new(),
+            capabilities: Vec::new(),
         }
     }
 
     pub fn with_authors(self, authors: Vec<String>) -> Self {
         Self { authors, ..self }
     }
+
+    pub fn with_capabilities(self, capabilities: Vec<Capability>) -> Self {
+        Self {
+            capabilities,
+            ..self
+        }
+    }
 }
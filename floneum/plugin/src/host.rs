@@ -5,6 +5,7 @@ use main::imports::{self};
 use main::types::{EmbeddingDbResource, EmbeddingModelResource, TextGenerationModelResource};
 use std::ops::Deref;
 
+use floneumite::Capability;
 use kalosm::language::DynamicNodeId;
 use once_cell::sync::Lazy;
 
@@ -60,6 +61,7 @@ impl SharedPluginState {
 pub struct State {
     pub(crate) shared: SharedPluginState,
     pub(crate) plugin_state: HashMap<Vec<u8>, Vec<u8>>,
+    pub(crate) capabilities: Vec<Capability>,
     pub(crate) table: ResourceTable,
     pub(crate) ctx: WasiCtx,
 }
@@ -73,26 +75,42 @@ impl Deref for State {
 }
 
 impl State {
-    pub fn new(shared: SharedPluginState) -> Self {
-        let sandbox = Path::new("./sandbox");
-        std::fs::create_dir_all(sandbox).unwrap();
+    pub fn new(shared: SharedPluginState, capabilities: Vec<Capability>) -> Self {
         let mut ctx = WasiCtxBuilder::new();
         let ctx_builder = ctx
             .inherit_stderr()
             .inherit_stdin()
             .inherit_stdio()
-            .inherit_stdout()
-            .preopened_dir(sandbox, "./", DirPerms::all(), FilePerms::all())
-            .unwrap();
+            .inherit_stdout();
+        // Only give the plugin access to the sandbox folder if it asked for it
+        if capabilities.contains(&Capability::Filesystem) {
+            let sandbox = Path::new("./sandbox");
+            std::fs::create_dir_all(sandbox).unwrap();
+            ctx_builder
+                .preopened_dir(sandbox, "./", DirPerms::all(), FilePerms::all())
+                .unwrap();
+        }
         let table = ResourceTable::new();
         let ctx = ctx_builder.build();
         State {
             plugin_state: Default::default(),
+            capabilities,
             shared,
             table,
             ctx,
         }
     }
+
+    /// Make sure the plugin requested a capability in its manifest before using it.
+    fn require(&self, capability: Capability) -> wasmtime::Result<()> {
+        if self.capabilities.contains(&capability) {
+            Ok(())
+        } else {
+            Err(wasmtime::Error::msg(format!(
+                "This plugin did not request the {capability} capability. Add #[capabilities({capability})] to the plugin to use it."
+            )))
+        }
+    }
 }
 
 impl WasiView for State {
@@ -114,6 +132,7 @@ impl main::types::Host for State {
         url: String,
         headers: Vec<main::types::Header>,
     ) -> std::result::Result<String, wasmtime::Error> {
+        self.require(Capability::Network)?;
         let mut headers = headers
             .into_iter()
             .map(|header| {
@@ -144,6 +163,7 @@ impl main::types::Host for State {
         mode: main::types::BrowserMode,
         url: String,
     ) -> wasmtime::Result<main::types::PageResource> {
+        self.require(Capability::Browser)?;
         self.resources.impl_create_page(mode, url)
     }
 
@@ -257,6 +277,7 @@ impl main::types::Host for State {
         &mut self,
         ty: main::types::ModelType,
     ) -> wasmtime::Result<TextGenerationModelResource> {
+        self.require(Capability::Models)?;
         Ok(self.resources.impl_create_text_generation_model(ty))
     }
 
@@ -303,6 +324,7 @@ impl main::types::Host for State {
         &mut self,
         ty: main::types::EmbeddingModelType,
     ) -> wasmtime::Result<EmbeddingModelResource> {
+        self.require(Capability::Models)?;
         self.resources.impl_create_embedding_model(ty)
     }
 
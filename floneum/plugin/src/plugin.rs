@@ -7,7 +7,10 @@ use crate::host::LINKER;
 use crate::resource::ResourceStorage;
 use crate::Both;
 use anyhow::Error;
+use floneumite::Capability;
+use floneumite::ManifestError;
 use floneumite::PackageIndexEntry;
+use floneumite::PluginManifest;
 
 use std::future::Future;
 use std::path::Path;
@@ -40,6 +43,7 @@ pub fn load_plugin_from_source(source: PackageIndexEntry, resources: ResourceSto
         source,
         shared: SharedPluginState::new(resources),
         component: once_cell::sync::OnceCell::new(),
+        capabilities: once_cell::sync::OnceCell::new(),
         definition: once_cell::sync::OnceCell::new(),
         metadata: md,
     }
@@ -65,6 +69,7 @@ pub struct Plugin {
     shared: SharedPluginState,
     source: PackageIndexEntry,
     component: once_cell::sync::OnceCell<Component>,
+    capabilities: once_cell::sync::OnceCell<Vec<Capability>>,
     definition: once_cell::sync::OnceCell<Definition>,
     metadata: once_cell::sync::OnceCell<PluginMetadata>,
 }
@@ -101,6 +106,11 @@ impl Plugin {
         let bytes = self.source.wasm_bytes().await?;
         let size = bytes.len();
         log::info!("read plugin ({:01} mb)", size as f64 / (1024. * 1024.));
+        // Check the manifest before we try to link the plugin so old plugins fail with a useful error
+        let manifest = PluginManifest::from_wasm(&bytes)?.ok_or(ManifestError::MissingManifest)?;
+        manifest.check_compatibility()?;
+        let capabilities = manifest.capabilities;
+        let _ = self.capabilities.set(capabilities);
         // then we transform module to component.
         // remember to get wasi_snapshot_preview1.wasm first.
         let component = ComponentEncoder::default()
@@ -145,11 +155,20 @@ impl Plugin {
         Ok(self.metadata.get().unwrap())
     }
 
+    /// Get the capabilities the plugin requested in its manifest.
+    pub async fn capabilities(&self) -> anyhow::Result<&[Capability]> {
+        self.component().await?;
+        Ok(self.capabilities.get().unwrap())
+    }
+
     async fn create_world(&self) -> anyhow::Result<(wasmtime::Store<State>, Both)> {
+        let component = self.component().await?;
         // create the store of models
-        let state = State::new(self.shared.clone());
+        let state = State::new(
+            self.shared.clone(),
+            self.capabilities.get().unwrap().clone(),
+        );
         let mut store = Store::new(&ENGINE, state);
-        let component = self.component().await?;
         let (world, _instance) = Both::instantiate_async(&mut store, component, &LINKER)
             .await
             .unwrap();
@@ -1,6 +1,7 @@
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(models)]
 /// Creates embeddings for some text.
 ///
 /// An embedding is a representation of something like the "meaning" of some text. You can use embeddings with embedding databases to find documents similar to anther document.
@@ -1,6 +1,7 @@
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(models)]
 /// Creates a database of embeddings. (A database is just a different way to store information, in this cases this stores documents in a way that makes it easy to find other documents with similar meanings)
 ///
 /// When using this embedding database, you must use the same model to generate the embeddings you insert into this database.
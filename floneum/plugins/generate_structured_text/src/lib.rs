@@ -6,6 +6,7 @@ use std::vec;
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(models)]
 /// Calls a large language model to generate structured text. You can create a template for the language model to fill in. The model will fill in any segments that contain {**type**} where **type** is "", bool, or #
 ///
 /// It is important to keep in mind that the language model is just generating text. Because the model is merely continuing the text you give it, the formatting of that text can be important.
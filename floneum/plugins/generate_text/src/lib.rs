@@ -3,6 +3,7 @@
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(models)]
 /// Calls a large language model to generate text.
 ///
 /// It is important to keep in mind that the language model is just generating text. Because the model is merely continuing the text you give it, the formatting of that text can be important.
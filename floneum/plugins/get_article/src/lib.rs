@@ -2,6 +2,7 @@ use floneum_rust::*;
 use url::Url;
 
 #[export_plugin]
+#[capabilities(browser)]
 /// Read an article from a URL
 ///
 /// ### Examples
@@ -1,6 +1,7 @@
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(browser)]
 /// Navigate a tab to a URL
 ///
 /// ### Examples
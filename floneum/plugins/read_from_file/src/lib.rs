@@ -1,6 +1,7 @@
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(filesystem)]
 /// Reads some text from a file at the given path (in the /sandbox directory)
 ///
 /// ### Examples
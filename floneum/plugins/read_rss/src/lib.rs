@@ -3,6 +3,7 @@ use rss::Channel;
 use url::Url;
 
 #[export_plugin]
+#[capabilities(network)]
 /// Reads a rss stream from a url
 ///
 /// ### Examples
@@ -4,6 +4,7 @@ use floneum_rust::*;
 use nipper::Document;
 
 #[export_plugin]
+#[capabilities(browser)]
 /// Searches wikipedia, fetches the top article from wikipedia, and returns it as text
 fn search_engine(query: String) -> String {
     let url = format!(
@@ -3,6 +3,7 @@ use std::io::Write;
 use floneum_rust::*;
 
 #[export_plugin]
+#[capabilities(filesystem)]
 /// Writes some text to a file at the given path (in the /sandbox directory)
 ///
 /// ### Examples
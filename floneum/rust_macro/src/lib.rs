@@ -17,6 +17,11 @@ mod inner {
 
 use inner::plugins::main::types::{PrimitiveValueType, ValueType};
 
+use floneum_binding_version::CURRENT_BINDING_VERSION;
+
+/// The capabilities a plugin can request from the host. These must match `floneumite::Capability`.
+const CAPABILITIES: [&str; 4] = ["network", "filesystem", "browser", "models"];
+
 macro_rules! try_parse_quote {
     ($($tokens:tt)*) => {
         match syn::parse2(quote!($($tokens)*)) {
@@ -69,6 +74,49 @@ pub fn export_plugin(args: TokenStream, input: TokenStream) -> TokenStream {
     }
     let examples = examples.unwrap_or_else(|| quote! {Vec::new()});
 
+    // Collect the capabilities the plugin requests from `#[capabilities(network, models)]`
+    let mut capabilities: Vec<String> = Vec::new();
+    let mut capability_error = None;
+    input.attrs.retain(|attr| {
+        if !attr.path().is_ident("capabilities") {
+            return true;
+        }
+        if let Err(err) = attr.parse_nested_meta(|meta| {
+            let capability = meta
+                .path
+                .get_ident()
+                .map(|ident| ident.to_string())
+                .unwrap_or_default();
+            if !CAPABILITIES.contains(&capability.as_str()) {
+                return Err(meta.error(format!(
+                    "Unknown capability. Expected one of {}",
+                    CAPABILITIES.join(", ")
+                )));
+            }
+            capabilities.push(capability);
+            Ok(())
+        }) {
+            capability_error = Some(err);
+        }
+        false
+    });
+    if let Some(err) = capability_error {
+        return err.to_compile_error().into();
+    }
+    let manifest = {
+        let version = std::env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "0.1.0".to_string());
+        let capabilities = capabilities
+            .iter()
+            .map(|capability| format!("{capability:?}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "name = {function_name:?}\nversion = {version:?}\nbinding_version = {CURRENT_BINDING_VERSION}\ncapabilities = [{capabilities}]\n"
+        )
+    };
+    let manifest_len = manifest.len();
+    let manifest = syn::LitByteStr::new(manifest.as_bytes(), Span::call_site());
+
     let mut input_names: Vec<String> = Vec::new();
     let mut input_idents: Vec<Ident> = Vec::new();
     let mut input_types: Vec<IoDefinitionType> = Vec::new();
@@ -146,6 +194,10 @@ pub fn export_plugin(args: TokenStream, input: TokenStream) -> TokenStream {
     TokenStream::from(quote! {
         ::floneum_rust::export!(Plugin);
 
+        #[used]
+        #[link_section = "floneum-manifest"]
+        static __FLONEUM_MANIFEST: [u8; #manifest_len] = *#manifest;
+
         #input
 
         pub struct Plugin;
@@ -1,16 +1,19 @@
-use hf_hub::{Repo, RepoType};
 use httpdate::parse_http_date;
-use kalosm_model_types::{FileLoadingProgress, FileSource};
+use kalosm_model_types::{FileLoadingProgress, FileSource, RetryPolicy, RetryReason};
 use reqwest::{
     header::{HeaderValue, CONTENT_LENGTH, LAST_MODIFIED, RANGE},
     IntoUrl,
 };
 use reqwest::{Response, StatusCode};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 
+use crate::{CacheStorage, KalosmConfig};
+
 #[derive(Debug, thiserror::Error)]
 pub enum CacheError {
     #[error("Hugging Face API error: {0}")]
@@ -23,6 +26,31 @@ pub enum CacheError {
     Http(#[from] reqwest::Error),
     #[error("Unexpected status code: {0}")]
     UnexpectedStatusCode(StatusCode),
+    #[error("Invalid Ollama manifest: {0}")]
+    InvalidOllamaManifest(#[from] serde_json::Error),
+    #[error("The Ollama model {0} does not contain a model file")]
+    NoOllamaModelFile(String),
+    #[error("Invalid Hugging Face repository info: {0}")]
+    InvalidRepoInfo(#[source] serde_json::Error),
+    #[error("Cache storage error: {0}")]
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+    #[error("{0} is not in the cache and downloads are disabled in offline mode")]
+    Offline(String),
+    #[error(
+        "{0} is held in memory and has no path on disk; read it with `Cache::get_bytes` instead"
+    )]
+    InMemory(String),
+}
+
+impl CacheError {
+    /// Get the reason the download failed if it may succeed when retried.
+    fn retry_reason(&self) -> Option<RetryReason> {
+        match self {
+            CacheError::Http(err) => RetryReason::from_reqwest_error(err),
+            CacheError::UnexpectedStatusCode(status) => Some(RetryReason::Status(status.as_u16())),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +58,17 @@ pub struct Cache {
     location: PathBuf,
     /// The huggingface token to use (defaults to the token set with `huggingface-cli login`)
     huggingface_token: Option<String>,
+    /// The policy for retrying failed downloads
+    retry_policy: RetryPolicy,
+    /// A shared storage behind the local cache directory
+    storage: Option<Arc<dyn CacheStorage>>,
+    /// The Hugging Face endpoint to download from (defaults to the `HF_ENDPOINT` environment variable, and then
+    /// `https://huggingface.co`)
+    huggingface_endpoint: Option<String>,
+    /// Endpoints that replace the default endpoint for specific Hugging Face repositories
+    huggingface_mirrors: HashMap<String, String>,
+    /// Only use files that are already cached
+    offline: bool,
 }
 
 impl Cache {
@@ -38,16 +77,101 @@ impl Cache {
         Self {
             location,
             huggingface_token: None,
+            retry_policy: RetryPolicy::default(),
+            storage: None,
+            huggingface_endpoint: None,
+            huggingface_mirrors: HashMap::new(),
+            offline: KalosmConfig::global().offline(),
         }
     }
 
+    /// Only use files that are already in the cache. Loading a file that isn't cached fails with
+    /// [`CacheError::Offline`] instead of downloading it. (Defaults to the offline setting in the [`KalosmConfig`])
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Set the policy for retrying downloads that fail with transient errors. Interrupted downloads resume from
+    /// where they stopped. (Defaults to [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// Set the Hugging Face token to use for downloading (defaults to the token set with `huggingface-cli login`, and then the environment variable `HF_TOKEN`)
     pub fn with_huggingface_token(mut self, token: Option<String>) -> Self {
         self.huggingface_token = token;
         self
     }
 
-    /// Check if the file exists locally (if it is a local file or if it has been downloaded)
+    /// Set the Hugging Face endpoint files are downloaded from. This can be a mirror like `https://hf-mirror.com` or
+    /// an internal artifact server that serves the same `{endpoint}/{model_id}/resolve/{revision}/{file}` paths as
+    /// Hugging Face. (Defaults to the `HF_ENDPOINT` environment variable, and then `https://huggingface.co`)
+    ///
+    /// Files are cached under the same path regardless of the endpoint they were downloaded from.
+    pub fn with_huggingface_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.huggingface_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Download the files of one Hugging Face repository from a different endpoint than the rest of the cache. This
+    /// is useful when only some models are mirrored on an internal server.
+    ///
+    /// ```rust, no_run
+    /// use kalosm_common::Cache;
+    ///
+    /// let cache = Cache::default()
+    ///     .with_huggingface_endpoint("https://hf-mirror.com")
+    ///     .with_huggingface_mirror(
+    ///         "meta-llama/Llama-3.2-1B-Instruct",
+    ///         "https://artifacts.example.com/huggingface",
+    ///     );
+    /// ```
+    pub fn with_huggingface_mirror(
+        mut self,
+        model_id: impl Into<String>,
+        endpoint: impl Into<String>,
+    ) -> Self {
+        self.huggingface_mirrors
+            .insert(model_id.into(), endpoint.into());
+        self
+    }
+
+    /// Get the endpoint files from a Hugging Face repository are downloaded from.
+    pub fn huggingface_endpoint(&self, model_id: &str) -> String {
+        let endpoint = match self.huggingface_mirrors.get(model_id) {
+            Some(mirror) => mirror.clone(),
+            None => self
+                .huggingface_endpoint
+                .clone()
+                .or_else(|| std::env::var("HF_ENDPOINT").ok())
+                .unwrap_or_else(|| "https://huggingface.co".to_string()),
+        };
+        endpoint.trim_end_matches('/').to_string()
+    }
+
+    /// Get the url of a file in a Hugging Face repository.
+    fn huggingface_url(&self, model_id: &str, revision: &str, file: &str) -> String {
+        let endpoint = self.huggingface_endpoint(model_id);
+        let revision = revision.replace('/', "%2F");
+        format!("{endpoint}/{model_id}/resolve/{revision}/{file}")
+    }
+
+    /// Set a shared [`CacheStorage`] behind the local cache directory. Files missing from the local cache are
+    /// copied from the storage before they are downloaded, and new downloads are copied into the storage.
+    pub fn with_storage(mut self, storage: impl CacheStorage) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Get the directory files are cached in
+    pub fn location(&self) -> &std::path::Path {
+        &self.location
+    }
+
+    /// Check if the file exists locally (if it is a local file or if it has been downloaded). Files that are only in
+    /// the [`CacheStorage`] are not included.
     pub fn exists(&self, source: &FileSource) -> bool {
         match source {
             FileSource::HuggingFace {
@@ -61,10 +185,96 @@ impl Cache {
                 complete_download.exists()
             }
             FileSource::Local(path) => path.exists(),
+            FileSource::Bytes { .. } => true,
+            FileSource::Ollama { model, tag } => {
+                crate::ollama::local_blob(model, tag).is_some()
+                    || self.cached_ollama_blob(model, tag).is_some()
+            }
+        }
+    }
+
+    /// Remove a downloaded Hugging Face file from the cache. Local files, in-memory files and Ollama blobs are left
+    /// untouched.
+    pub async fn remove(&self, source: &FileSource) -> std::io::Result<()> {
+        if let FileSource::HuggingFace {
+            model_id,
+            revision,
+            file,
+        } = source
+        {
+            let complete_download = self.location.join(model_id).join(revision).join(file);
+            match tokio::fs::remove_file(complete_download).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the path of an Ollama model that was downloaded into this cache without checking for updates.
+    fn cached_ollama_blob(&self, model: &str, tag: &str) -> Option<PathBuf> {
+        let path = crate::ollama::cache_dir(&self.location, model);
+        let manifest = std::fs::read(path.join("manifests").join(tag)).ok()?;
+        let manifest = crate::ollama::OllamaManifest::parse(&manifest).ok()?;
+        let file_name = crate::ollama::blob_file_name(manifest.model_digest()?);
+        Some(path.join(format!("{file_name}.gguf")))
+            .filter(|complete_download| complete_download.exists())
+    }
+
+    /// List the files in a Hugging Face model repository
+    pub async fn huggingface_files(
+        &self,
+        model_id: &str,
+        revision: &str,
+    ) -> Result<Vec<String>, CacheError> {
+        #[derive(serde::Deserialize)]
+        struct RepoInfo {
+            siblings: Vec<RepoFile>,
+        }
+        #[derive(serde::Deserialize)]
+        struct RepoFile {
+            rfilename: String,
+        }
+
+        if self.offline {
+            return Err(CacheError::Offline(format!("The file list of {model_id}")));
         }
+        let token = self.huggingface_token.clone().or_else(huggingface_token);
+        let endpoint = self.huggingface_endpoint(model_id);
+        let url = format!("{endpoint}/api/models/{model_id}/revision/{revision}");
+        let response = reqwest::Client::new()
+            .get(&url)
+            .with_authorization_header(token)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(CacheError::UnexpectedStatusCode(response.status()));
+        }
+        let info: RepoInfo = serde_json::from_slice(&response.bytes().await?)
+            .map_err(CacheError::InvalidRepoInfo)?;
+        Ok(info
+            .siblings
+            .into_iter()
+            .map(|file| file.rfilename)
+            .collect())
+    }
+
+    /// Get the contents of a file, downloading it if necessary. In-memory files are returned without copying them.
+    /// This is useful for small files like tokenizers and configs.
+    pub async fn get_bytes(
+        &self,
+        source: &FileSource,
+        progress: impl FnMut(FileLoadingProgress),
+    ) -> Result<Arc<[u8]>, CacheError> {
+        if let FileSource::Bytes { bytes, .. } = source {
+            return Ok(bytes.clone());
+        }
+        let path = self.get(source, progress).await?;
+        Ok(tokio::fs::read(path).await?.into())
     }
 
-    /// Get the file from the cache, downloading it if necessary
+    /// Get the file from the cache, downloading it if necessary. In-memory files have no path, so they are never
+    /// written to disk and fail with [`CacheError::InMemory`]. Use [`Cache::get_bytes`] to read them.
     pub async fn get(
         &self,
         source: &FileSource,
@@ -80,14 +290,18 @@ impl Cache {
 
                 let path = self.location.join(model_id).join(revision);
                 let complete_download = path.join(file);
+                if !complete_download.exists() && self.fetch_from_storage(&complete_download).await
+                {
+                    return Ok(complete_download);
+                }
+                if self.offline {
+                    return match complete_download.exists() {
+                        true => Ok(complete_download),
+                        false => Err(CacheError::Offline(source.to_string())),
+                    };
+                }
 
-                let repo = Repo::with_revision(
-                    model_id.to_string(),
-                    RepoType::Model,
-                    revision.to_string(),
-                );
-                let api = hf_hub::api::sync::Api::new()?.repo(repo);
-                let url = api.url(file);
+                let url = self.huggingface_url(model_id, revision, file);
                 let client = reqwest::Client::new();
                 tracing::trace!("Fetching metadata for {file} from {url}");
                 let response = client
@@ -118,14 +332,11 @@ impl Cache {
                     }
                 }
                 let incomplete_download = path.join(format!("{}.partial", file));
-
-                tracing::trace!("Downloading into {:?}", incomplete_download);
-
-                download_into(
-                    url,
+                self.download_with_retry(
+                    &client,
+                    &url,
                     &incomplete_download,
-                    response?,
-                    client,
+                    response,
                     token,
                     progress,
                 )
@@ -133,23 +344,308 @@ impl Cache {
 
                 // Rename the file to remove the .partial extension
                 tokio::fs::rename(&incomplete_download, &complete_download).await?;
+                self.store_in_storage(&complete_download).await;
 
                 Ok(complete_download)
             }
             FileSource::Local(path) => Ok(path.clone()),
+            FileSource::Bytes { name, .. } => Err(CacheError::InMemory(name.clone())),
+            FileSource::Ollama { model, tag } => {
+                // Use the model file from Ollama if the model was already pulled
+                if let Some(blob) = crate::ollama::local_blob(model, tag) {
+                    tracing::trace!("Using the Ollama blob at {}", blob.display());
+                    return Ok(blob);
+                }
+
+                let path = crate::ollama::cache_dir(&self.location, model);
+                let manifest_path = path.join("manifests").join(tag);
+                if self.offline {
+                    return self
+                        .cached_ollama_blob(model, tag)
+                        .ok_or_else(|| CacheError::Offline(source.to_string()));
+                }
+                let client = reqwest::Client::new();
+                let url = crate::ollama::manifest_url(model, tag);
+                tracing::trace!("Fetching the Ollama manifest from {url}");
+                let response = client
+                    .get(&url)
+                    .header(
+                        reqwest::header::ACCEPT,
+                        "application/vnd.docker.distribution.manifest.v2+json",
+                    )
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                let manifest = match response {
+                    Ok(response) => {
+                        let manifest = response.bytes().await?;
+                        tokio::fs::create_dir_all(manifest_path.parent().unwrap()).await?;
+                        tokio::fs::write(&manifest_path, &manifest).await?;
+                        manifest.to_vec()
+                    }
+                    // If we are offline, we can use the manifest from the last download
+                    Err(err) => match tokio::fs::read(&manifest_path).await {
+                        Ok(manifest) => manifest,
+                        Err(_) => return Err(err.into()),
+                    },
+                };
+                let manifest = crate::ollama::OllamaManifest::parse(&manifest)?;
+                let digest = manifest
+                    .model_digest()
+                    .ok_or_else(|| CacheError::NoOllamaModelFile(format!("{model}:{tag}")))?;
+
+                // Blobs are content addressed, so a finished download never needs to be checked again
+                let file_name = crate::ollama::blob_file_name(digest);
+                let complete_download = path.join(format!("{file_name}.gguf"));
+                if complete_download.exists() || self.fetch_from_storage(&complete_download).await {
+                    return Ok(complete_download);
+                }
+
+                let url = crate::ollama::blob_url(model, digest);
+                let response = client.head(&url).send().await;
+                let incomplete_download = path.join(format!("{file_name}.gguf.partial"));
+                self.download_with_retry(
+                    &client,
+                    &url,
+                    &incomplete_download,
+                    response,
+                    None,
+                    progress,
+                )
+                .await?;
+                tokio::fs::rename(&incomplete_download, &complete_download).await?;
+                self.store_in_storage(&complete_download).await;
+
+                Ok(complete_download)
+            }
+        }
+    }
+
+    /// Read a range of bytes from a file without downloading the whole file. Files that are local or already cached
+    /// are read from disk, and remote files are fetched with an HTTP range request. The range is clamped to the end
+    /// of the file.
+    pub async fn read_range(
+        &self,
+        source: &FileSource,
+        range: std::ops::Range<u64>,
+    ) -> Result<Vec<u8>, CacheError> {
+        let (url, token) = match source {
+            FileSource::HuggingFace {
+                model_id,
+                revision,
+                file,
+            } => {
+                let complete_download = self.location.join(model_id).join(revision).join(file);
+                if complete_download.exists() {
+                    return read_file_range(&complete_download, range).await;
+                }
+                if self.offline {
+                    return Err(CacheError::Offline(source.to_string()));
+                }
+                let token = self.huggingface_token.clone().or_else(huggingface_token);
+                (self.huggingface_url(model_id, revision, file), token)
+            }
+            FileSource::Local(path) => return read_file_range(path, range).await,
+            FileSource::Bytes { bytes, .. } => {
+                let start = (range.start as usize).min(bytes.len());
+                let end = (range.end as usize).clamp(start, bytes.len());
+                return Ok(bytes[start..end].to_vec());
+            }
+            FileSource::Ollama { model, tag } => {
+                if let Some(blob) = crate::ollama::local_blob(model, tag) {
+                    return read_file_range(&blob, range).await;
+                }
+                if self.offline {
+                    let blob = self
+                        .cached_ollama_blob(model, tag)
+                        .ok_or_else(|| CacheError::Offline(source.to_string()))?;
+                    return read_file_range(&blob, range).await;
+                }
+                let response = reqwest::Client::new()
+                    .get(crate::ollama::manifest_url(model, tag))
+                    .header(
+                        reqwest::header::ACCEPT,
+                        "application/vnd.docker.distribution.manifest.v2+json",
+                    )
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                let manifest = crate::ollama::OllamaManifest::parse(&response.bytes().await?)?;
+                let digest = manifest
+                    .model_digest()
+                    .ok_or_else(|| CacheError::NoOllamaModelFile(format!("{model}:{tag}")))?;
+                let path = crate::ollama::cache_dir(&self.location, model);
+                let file_name = crate::ollama::blob_file_name(digest);
+                let complete_download = path.join(format!("{file_name}.gguf"));
+                if complete_download.exists() {
+                    return read_file_range(&complete_download, range).await;
+                }
+                (crate::ollama::blob_url(model, digest), None)
+            }
+        };
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        tracing::trace!("Fetching bytes {range:?} from {url}");
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header(RANGE, format!("bytes={}-{}", range.start, range.end - 1))
+            .with_authorization_header(token)
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(CacheError::UnexpectedStatusCode(status));
+        }
+        let bytes = response.bytes().await?;
+        // Servers that ignore the range header send the whole file
+        if status == StatusCode::PARTIAL_CONTENT {
+            Ok(bytes.to_vec())
+        } else {
+            let start = (range.start as usize).min(bytes.len());
+            let end = (range.end as usize).min(bytes.len());
+            Ok(bytes[start..end].to_vec())
+        }
+    }
+
+    /// Get the key of a file in the local cache directory in the [`CacheStorage`].
+    fn storage_key(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.location).ok()?;
+        let components: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        Some(components.join("/"))
+    }
+
+    /// Try to copy a file into the local cache directory from the [`CacheStorage`]. Returns true if the file was
+    /// copied.
+    async fn fetch_from_storage(&self, path: &Path) -> bool {
+        let (Some(storage), Some(key)) = (&self.storage, self.storage_key(path)) else {
+            return false;
+        };
+        let partial = path.with_file_name(format!(
+            "{}.fetch",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        if let Some(parent) = path.parent() {
+            if let Err(err) = tokio::fs::create_dir_all(parent).await {
+                tracing::warn!(
+                    "Failed to create the cache directory {}: {err}",
+                    parent.display()
+                );
+                return false;
+            }
+        }
+        let result = match storage.fetch(&key, &partial).await {
+            Ok(true) => tokio::fs::rename(&partial, path)
+                .await
+                .map(|()| true)
+                .map_err(CacheError::from),
+            result => result,
+        };
+        match result {
+            Ok(fetched) => {
+                if fetched {
+                    tracing::trace!("Copied {key} from the cache storage");
+                }
+                fetched
+            }
+            Err(err) => {
+                tracing::warn!("Failed to copy {key} from the cache storage: {err}");
+                _ = tokio::fs::remove_file(&partial).await;
+                false
+            }
+        }
+    }
+
+    /// Copy a new download into the [`CacheStorage`]. Failures are logged because the file is already in the local
+    /// cache.
+    async fn store_in_storage(&self, path: &Path) {
+        let (Some(storage), Some(key)) = (&self.storage, self.storage_key(path)) else {
+            return;
+        };
+        if let Err(err) = storage.store(&key, path).await {
+            tracing::warn!("Failed to copy {key} into the cache storage: {err}");
+        }
+    }
+
+    /// Download a file, retrying with the [`RetryPolicy`] of the cache if the download fails with a transient error.
+    /// Each retry resumes from the end of the partial file.
+    async fn download_with_retry(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        incomplete_download: &PathBuf,
+        mut response: Result<Response, reqwest::Error>,
+        token: Option<String>,
+        mut progress: impl FnMut(FileLoadingProgress),
+    ) -> Result<(), CacheError> {
+        tracing::trace!("Downloading into {:?}", incomplete_download);
+
+        let mut retry = 0;
+        loop {
+            let result = match response {
+                Ok(head) => {
+                    download_into(
+                        url,
+                        incomplete_download,
+                        head,
+                        client.clone(),
+                        token.clone(),
+                        &mut progress,
+                    )
+                    .await
+                }
+                Err(err) => Err(err.into()),
+            };
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            let should_retry = retry + 1 < self.retry_policy.max_attempts()
+                && err
+                    .retry_reason()
+                    .is_some_and(|reason| self.retry_policy.should_retry(reason));
+            if !should_retry {
+                return Err(err);
+            }
+            let backoff = self.retry_policy.backoff(retry);
+            tracing::warn!("Download of {url} failed: {err}. Retrying in {backoff:?}");
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+            response = client
+                .head(url)
+                .with_authorization_header(token.clone())
+                .send()
+                .await;
         }
     }
 }
 
 impl Default for Cache {
     fn default() -> Self {
-        Self {
-            location: dirs::data_dir().unwrap().join("kalosm").join("cache"),
-            huggingface_token: None,
-        }
+        let config = KalosmConfig::global();
+        let location = match config.cache_dir() {
+            Some(location) => location.to_path_buf(),
+            None => dirs::data_dir().unwrap().join("kalosm").join("cache"),
+        };
+        Self::new(location).with_huggingface_token(config.huggingface_token().map(String::from))
     }
 }
 
+async fn read_file_range(path: &Path, range: std::ops::Range<u64>) -> Result<Vec<u8>, CacheError> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = File::open(path).await?;
+    file.seek(std::io::SeekFrom::Start(range.start)).await?;
+    let mut bytes = Vec::new();
+    file.take(range.end.saturating_sub(range.start))
+        .read_to_end(&mut bytes)
+        .await?;
+    Ok(bytes)
+}
+
 async fn download_into<U: IntoUrl>(
     url: U,
     file: &PathBuf,
@@ -265,3 +761,43 @@ fn huggingface_token() -> Option<String> {
     let cache = hf_hub::Cache::default();
     cache.token().or_else(|| std::env::var("HF_TOKEN").ok())
 }
+
+#[test]
+fn huggingface_mirrors_replace_the_endpoint() {
+    let cache = Cache::new(PathBuf::from("cache"))
+        .with_huggingface_endpoint("https://hf-mirror.com/")
+        .with_huggingface_mirror("org/private", "https://artifacts.example.com/hf");
+    assert_eq!(
+        cache.huggingface_url("org/public", "main", "model.gguf"),
+        "https://hf-mirror.com/org/public/resolve/main/model.gguf"
+    );
+    assert_eq!(
+        cache.huggingface_url("org/private", "refs/pr/1", "model.gguf"),
+        "https://artifacts.example.com/hf/org/private/resolve/refs%2Fpr%2F1/model.gguf"
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn byte_sources_are_read_without_touching_the_cache() {
+    // A directory can't be created inside /dev/null, so any write to the cache fails
+    let cache = Cache::new(PathBuf::from("/dev/null/kalosm-cache"));
+    let source = FileSource::bytes("tokenizer.json", b"{\"version\": \"1.0\"}".to_vec());
+    assert!(cache.exists(&source));
+    let bytes = cache.get_bytes(&source, |_| {}).await.unwrap();
+    assert_eq!(&*bytes, b"{\"version\": \"1.0\"}");
+    assert_eq!(
+        cache.read_range(&source, 1..10).await.unwrap(),
+        b"\"version\"".to_vec()
+    );
+    assert_eq!(
+        cache.read_range(&source, 17..100).await.unwrap(),
+        b"}".to_vec()
+    );
+    assert!(matches!(
+        cache.get(&source, |_| {}).await,
+        Err(CacheError::InMemory(_))
+    ));
+    cache.remove(&source).await.unwrap();
+    assert!(!cache.location().exists());
+}
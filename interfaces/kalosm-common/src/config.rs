@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Runtime configuration shared by every kalosm model. The default [`Cache`](crate::Cache) and device that model
+/// builders use come from the global config, so deployments can change where models are stored, which device they
+/// run on, or turn off downloads without recompiling.
+///
+/// The global config is loaded the first time it is used from an optional TOML file and then environment variables,
+/// which take precedence over the file:
+///
+/// | Setting | TOML key | Environment variable |
+/// |---------|----------|----------------------|
+/// | Cache directory | `cache_dir` | `KALOSM_CACHE_DIR` |
+/// | Hugging Face token | `huggingface_token` | `HF_TOKEN` |
+/// | Default device (`auto`, `cpu`, `cuda:0`, `metal:0`) | `device` | `KALOSM_DEVICE` |
+/// | Offline mode | `offline` | `KALOSM_OFFLINE` or `HF_HUB_OFFLINE` |
+/// | CPU thread count | `threads` | `KALOSM_THREADS` |
+///
+/// The thread count doesn't change the process environment or the global rayon pool. Models that support it run in
+/// their own [`cpu_thread_pool`](crate::cpu_thread_pool) with that many threads.
+///
+/// The file is read from the path in `KALOSM_CONFIG`, or `kalosm/config.toml` in the user's config directory if it
+/// exists:
+///
+/// ```toml
+/// cache_dir = "/var/lib/kalosm"
+/// device = "cuda:1"
+/// offline = true
+/// threads = 8
+/// ```
+///
+/// Applications can also set the global config in code before loading any models:
+///
+/// ```rust
+/// use kalosm_common::*;
+///
+/// # fn main() -> Result<(), ConfigError> {
+/// KalosmConfig::load()?
+///     .with_cache_dir("./models")
+///     .with_offline(true)
+///     .set_global()
+///     .expect("the config is set before any model loads");
+/// assert!(KalosmConfig::global().offline());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KalosmConfig {
+    cache_dir: Option<PathBuf>,
+    huggingface_token: Option<String>,
+    device: DevicePreference,
+    offline: bool,
+    threads: Option<usize>,
+}
+
+/// An error loading a [`KalosmConfig`].
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    #[error("Failed to read the config file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    /// The config file is not valid TOML.
+    #[error("Failed to parse the config file: {0}")]
+    Toml(#[from] toml::de::Error),
+    /// A setting has a value that can't be parsed.
+    #[error("Invalid value {value:?} for {key}")]
+    InvalidValue {
+        /// The name of the setting.
+        key: String,
+        /// The value that could not be parsed.
+        value: String,
+    },
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    cache_dir: Option<PathBuf>,
+    huggingface_token: Option<String>,
+    device: Option<String>,
+    offline: Option<bool>,
+    threads: Option<usize>,
+}
+
+static GLOBAL_CONFIG: OnceLock<KalosmConfig> = OnceLock::new();
+
+impl KalosmConfig {
+    /// Create a config with every setting at its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the config from the config file (if one exists) and then environment variables.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = match std::env::var_os("KALOSM_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir()
+                .map(|dir| dir.join("kalosm").join("config.toml"))
+                .filter(|path| path.exists()),
+        };
+        let config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.with_env()
+    }
+
+    /// Read the config from a TOML file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+        Self::from_toml(&contents)
+    }
+
+    /// Parse the config from the contents of a TOML file.
+    pub fn from_toml(contents: &str) -> Result<Self, ConfigError> {
+        let file: ConfigFile = toml::from_str(contents)?;
+        Ok(Self {
+            cache_dir: file.cache_dir,
+            huggingface_token: file.huggingface_token,
+            device: file
+                .device
+                .map(|device| parse("device", &device))
+                .transpose()?
+                .unwrap_or_default(),
+            offline: file.offline.unwrap_or_default(),
+            threads: file.threads,
+        })
+    }
+
+    /// Override the settings in this config with any that are set in environment variables.
+    pub fn with_env(mut self) -> Result<Self, ConfigError> {
+        let var = |key: &str| std::env::var(key).ok().filter(|value| !value.is_empty());
+        if let Some(cache_dir) = var("KALOSM_CACHE_DIR") {
+            self.cache_dir = Some(cache_dir.into());
+        }
+        if let Some(token) = var("HF_TOKEN") {
+            self.huggingface_token = Some(token);
+        }
+        if let Some(device) = var("KALOSM_DEVICE") {
+            self.device = parse("KALOSM_DEVICE", &device)?;
+        }
+        for key in ["KALOSM_OFFLINE", "HF_HUB_OFFLINE"] {
+            if let Some(offline) = var(key) {
+                self.offline = parse_bool(key, &offline)?;
+                break;
+            }
+        }
+        if let Some(threads) = var("KALOSM_THREADS") {
+            self.threads = Some(parse("KALOSM_THREADS", &threads)?);
+        }
+        Ok(self)
+    }
+
+    /// Get the global config. It is loaded with [`KalosmConfig::load`] the first time it is used unless it was set
+    /// with [`KalosmConfig::set_global`]. If loading fails, the error is logged and the default config is used.
+    pub fn global() -> &'static Self {
+        GLOBAL_CONFIG.get_or_init(|| {
+            Self::load().unwrap_or_else(|err| {
+                tracing::error!("Failed to load the kalosm config: {err}");
+                Self::default()
+            })
+        })
+    }
+
+    /// Set the global config. This must be called before any model is loaded. If the global config was already
+    /// loaded, this config is returned as an error.
+    pub fn set_global(self) -> Result<(), Self> {
+        let mut config = Some(self);
+        GLOBAL_CONFIG.get_or_init(|| config.take().unwrap());
+        match config {
+            Some(config) => Err(config),
+            None => Ok(()),
+        }
+    }
+
+    /// Set the directory models are downloaded to.
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Set the Hugging Face token used to download models.
+    pub fn with_huggingface_token(mut self, token: impl Into<String>) -> Self {
+        self.huggingface_token = Some(token.into());
+        self
+    }
+
+    /// Set the device models run on when the builder doesn't set one.
+    pub fn with_device(mut self, device: DevicePreference) -> Self {
+        self.device = device;
+        self
+    }
+
+    /// Set whether models are only loaded from the cache. In offline mode, loading a model that isn't already
+    /// downloaded fails instead of making any network requests.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Set the number of threads used for inference on the CPU. Models that don't set their own thread count run in a
+    /// [`cpu_thread_pool`](crate::cpu_thread_pool) with this many threads instead of the global rayon pool.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Get the directory models are downloaded to, if it is set.
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    /// Get the Hugging Face token used to download models, if it is set.
+    pub fn huggingface_token(&self) -> Option<&str> {
+        self.huggingface_token.as_deref()
+    }
+
+    /// Get the device models run on when the builder doesn't set one.
+    pub fn device(&self) -> DevicePreference {
+        self.device
+    }
+
+    /// Check if models are only loaded from the cache.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Get the number of threads used for inference on the CPU, if it is set.
+    pub fn threads(&self) -> Option<usize> {
+        self.threads
+    }
+}
+
+/// The device models run on by default. See [`KalosmConfig::with_device`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DevicePreference {
+    /// Use the first accelerator that is available, or the CPU if there is none.
+    #[default]
+    Auto,
+    /// Always run on the CPU.
+    Cpu,
+    /// Run on the CUDA device with this index.
+    Cuda(usize),
+    /// Run on the Metal device with this index.
+    Metal(usize),
+}
+
+impl DevicePreference {
+    /// Create the candle device. Returns `None` for [`DevicePreference::Auto`].
+    pub fn device(&self) -> Option<candle_core::Result<candle_core::Device>> {
+        match *self {
+            Self::Auto => None,
+            Self::Cpu => Some(Ok(candle_core::Device::Cpu)),
+            Self::Cuda(index) => Some(candle_core::Device::new_cuda(index)),
+            Self::Metal(index) => Some(candle_core::Device::new_metal(index)),
+        }
+    }
+}
+
+impl FromStr for DevicePreference {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, index) = match s.split_once(':') {
+            Some((name, index)) => (name, index.parse().map_err(|_| ())?),
+            None => (s, 0),
+        };
+        match name.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Self::Auto),
+            "cpu" => Ok(Self::Cpu),
+            "cuda" | "gpu" => Ok(Self::Cuda(index)),
+            "metal" => Ok(Self::Metal(index)),
+            _ => Err(()),
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, ConfigError> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+#[test]
+fn config_from_toml() {
+    let config = KalosmConfig::from_toml(
+        r#"
+        cache_dir = "/models"
+        device = "cuda:1"
+        offline = true
+        threads = 4
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config,
+        KalosmConfig::new()
+            .with_cache_dir("/models")
+            .with_device(DevicePreference::Cuda(1))
+            .with_offline(true)
+            .with_threads(4)
+    );
+    assert!(KalosmConfig::from_toml("device = \"tpu\"").is_err());
+    assert!(KalosmConfig::from_toml("unknown = 1").is_err());
+    assert_eq!("CPU".parse(), Ok(DevicePreference::Cpu));
+}
@@ -0,0 +1,109 @@
+use candle_core::{DType, Tensor, D};
+
+/// How the keys and values in a [`PagedKvCache`](crate::PagedKvCache) are stored. Quantized caches store each
+/// token of each head as small integers with one f32 scale. [`PagedKvCache::attend`](crate::PagedKvCache::attend)
+/// dequantizes one block at a time as it reads the cache, so the full cache is never stored in full precision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum KvCacheQuantization {
+    /// Store the keys and values in the type the model produces them in.
+    #[default]
+    None,
+    /// Store the keys and values as 8 bit integers.
+    Q8,
+    /// Store the keys and values as 4 bit integers, packed two to a byte. The last dimension of the keys and values
+    /// must be even.
+    Q4,
+}
+
+impl KvCacheQuantization {
+    /// The largest integer a value is scaled to and the offset that makes the integers unsigned.
+    fn levels(&self) -> f64 {
+        match self {
+            Self::None => 0.,
+            Self::Q8 => 127.,
+            Self::Q4 => 7.,
+        }
+    }
+
+    fn offset(&self) -> f64 {
+        match self {
+            Self::None => 0.,
+            Self::Q8 => 128.,
+            Self::Q4 => 8.,
+        }
+    }
+
+    /// Get the tensors that store `tensor`: the tensor itself if the cache is not quantized, or the packed integers
+    /// and the scale of each row along the last dimension.
+    pub(crate) fn quantize(&self, tensor: &Tensor) -> candle_core::Result<Vec<Tensor>> {
+        if *self == Self::None {
+            return Ok(vec![tensor.clone()]);
+        }
+        let tensor = tensor.to_dtype(DType::F32)?;
+        let scale =
+            (tensor.abs()?.max_keepdim(D::Minus1)? / self.levels())?.clamp(1e-8, f32::MAX)?;
+        let integers = ((tensor.broadcast_div(&scale)?.round()? + self.offset())?)
+            .clamp(0., 2. * self.offset() - 1.)?;
+        let integers = match self {
+            Self::Q4 => {
+                let mut dims = integers.dims().to_vec();
+                let last = dims.pop().expect("keys and values have dimensions");
+                if last % 2 != 0 {
+                    candle_core::bail!(
+                        "q4 kv cache quantization requires an even head dimension, found {last}"
+                    );
+                }
+                dims.extend([last / 2, 2]);
+                let pairs = integers.reshape(dims)?;
+                let high = pairs.narrow(D::Minus1, 0, 1)?.squeeze(D::Minus1)?;
+                let low = pairs.narrow(D::Minus1, 1, 1)?.squeeze(D::Minus1)?;
+                ((high * 16.)? + low)?
+            }
+            _ => integers,
+        };
+        Ok(vec![integers.to_dtype(DType::U8)?, scale])
+    }
+
+    /// Turn the tensors from [`Self::quantize`] back into one tensor of the given type.
+    pub(crate) fn dequantize(&self, parts: &[Tensor], dtype: DType) -> candle_core::Result<Tensor> {
+        let [integers, scale] = parts else {
+            return Ok(parts[0].clone());
+        };
+        let integers = integers.to_dtype(DType::F32)?;
+        let integers = match self {
+            Self::Q4 => {
+                let high = (&integers / 16.)?.floor()?;
+                let low = (&integers - (&high * 16.)?)?;
+                let mut dims = integers.dims().to_vec();
+                *dims.last_mut().expect("keys and values have dimensions") *= 2;
+                Tensor::stack(&[high, low], D::Minus1)?.reshape(dims)?
+            }
+            _ => integers,
+        };
+        (integers - self.offset())?
+            .broadcast_mul(scale)?
+            .to_dtype(dtype)
+    }
+}
+
+#[test]
+fn quantized_round_trip() {
+    let device = candle_core::Device::Cpu;
+    let tensor = Tensor::new(&[[[[0.5f32, -1.0, 0.25, 0.0]]]], &device).unwrap();
+    for quantization in [KvCacheQuantization::Q8, KvCacheQuantization::Q4] {
+        let parts = quantization.quantize(&tensor).unwrap();
+        let round_trip = quantization.dequantize(&parts, DType::F32).unwrap();
+        let error = (round_trip - &tensor)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(
+            error <= 1. / quantization.levels() as f32,
+            "{quantization:?}: {error}"
+        );
+    }
+}
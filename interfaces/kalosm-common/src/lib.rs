@@ -4,18 +4,30 @@ use candle_core::{backend::BackendStorage, utils::*, Device, Storage, Tensor, Wi
 
 mod cache;
 pub use cache::*;
+mod config;
+pub use config::*;
 mod kv_cache;
 pub use kv_cache::*;
+mod kv_quantization;
+pub use kv_quantization::*;
 mod mask;
 pub use mask::*;
+mod ollama;
+mod paged_kv_cache;
+pub use paged_kv_cache::*;
+mod storage;
+pub use storage::*;
 
-/// Create a candle device that uses any available accelerator.
+/// Create a candle device that uses any available accelerator. If the device is set in the [`KalosmConfig`], that
+/// device is used instead.
 pub fn accelerated_device_if_available() -> candle_core::Result<Device> {
     static DEVICE: OnceLock<Device> = OnceLock::new();
     if let Some(device) = DEVICE.get() {
         return Ok(device.clone());
     }
-    let device = if cuda_is_available() {
+    let device = if let Some(device) = KalosmConfig::global().device().device() {
+        device?
+    } else if cuda_is_available() {
         Device::new_cuda(0)?
     } else if metal_is_available() {
         Device::new_metal(0)?
@@ -34,6 +46,56 @@ pub fn accelerated_device_if_available() -> candle_core::Result<Device> {
     Ok(device)
 }
 
+/// Create the first device in a list of preferences that is available. Devices that fail to open (for example a
+/// CUDA device when the `cuda` feature is disabled, or a GPU index that doesn't exist) are skipped.
+/// [`DevicePreference::Auto`] always resolves to [`accelerated_device_if_available`]. If the list is empty,
+/// [`accelerated_device_if_available`] is used. If no device opens, the error from the last device is returned.
+pub fn first_available_device(
+    preferences: impl IntoIterator<Item = DevicePreference>,
+) -> candle_core::Result<Device> {
+    let mut last_error = None;
+    for preference in preferences {
+        let device = match preference.device() {
+            Some(device) => device,
+            None => accelerated_device_if_available(),
+        };
+        match device {
+            Ok(device) => return Ok(device),
+            Err(err) => {
+                tracing::trace!("Device {preference:?} is not available: {err}");
+                last_error = Some(err);
+            }
+        }
+    }
+    match last_error {
+        Some(err) => Err(err),
+        None => accelerated_device_if_available(),
+    }
+}
+
+/// Create a thread pool for CPU inference with a fixed number of threads. Running a model inside the pool with
+/// [`rayon::ThreadPool::install`] caps the threads candle uses for matmuls so the model doesn't starve the rest of the
+/// application. If `pin_threads` is true, each thread is pinned to its own core.
+pub fn cpu_thread_pool(
+    threads: usize,
+    pin_threads: bool,
+) -> Result<rayon::ThreadPool, rayon::ThreadPoolBuildError> {
+    let cores = if pin_threads {
+        core_affinity::get_core_ids().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|index| format!("kalosm-cpu-{index}"))
+        .start_handler(move |index| {
+            if !cores.is_empty() && !core_affinity::set_for_current(cores[index % cores.len()]) {
+                tracing::warn!("Failed to pin inference thread {index} to a core");
+            }
+        })
+        .build()
+}
+
 /// Wrap a closure in a release pool if the metal feature is enabled
 pub fn maybe_autoreleasepool<T>(f: impl FnOnce() -> T) -> T {
     #[cfg(feature = "metal")]
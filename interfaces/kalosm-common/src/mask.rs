@@ -28,6 +28,7 @@ impl MaskCache {
             let mask = AttentionMask {
                 mask,
                 on_true: OnceLock::new(),
+                causal: true,
             };
             masks.insert(seq_len, mask.clone());
             mask
@@ -46,6 +47,7 @@ impl MaskCache {
         Ok(AttentionMask {
             mask: mask_tensor,
             on_true: mask.on_true,
+            causal: true,
         })
     }
 }
@@ -54,6 +56,9 @@ impl MaskCache {
 pub struct AttentionMask {
     pub mask: Tensor,
     pub on_true: OnceLock<Tensor>,
+    /// If the mask only hides the keys after each query, with the queries aligned with the last keys. Fused
+    /// attention kernels can apply a causal mask without reading it.
+    pub causal: bool,
 }
 
 impl AttentionMask {
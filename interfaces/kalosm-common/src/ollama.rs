@@ -0,0 +1,118 @@
+//! Resolve models in the Ollama library to the blob that holds the gguf model file.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::CacheError;
+
+/// The registry `ollama pull` downloads models from
+const OLLAMA_REGISTRY: &str = "registry.ollama.ai";
+
+/// The media type of the layer that contains the gguf model file
+const MODEL_MEDIA_TYPE: &str = "application/vnd.ollama.image.model";
+
+/// The manifest of a model in the Ollama registry. Manifests use the docker distribution format.
+#[derive(Deserialize)]
+pub(crate) struct OllamaManifest {
+    layers: Vec<OllamaLayer>,
+}
+
+#[derive(Deserialize)]
+struct OllamaLayer {
+    #[serde(rename = "mediaType")]
+    media_type: String,
+    digest: String,
+}
+
+impl OllamaManifest {
+    /// Parse a manifest from json.
+    pub(crate) fn parse(json: &[u8]) -> Result<Self, CacheError> {
+        Ok(serde_json::from_slice(json)?)
+    }
+
+    /// Get the digest of the model layer like `sha256:abc...`.
+    pub(crate) fn model_digest(&self) -> Option<&str> {
+        self.layers
+            .iter()
+            .find(|layer| layer.media_type == MODEL_MEDIA_TYPE)
+            .map(|layer| layer.digest.as_str())
+    }
+}
+
+/// Split a model name into the namespace and model. Models in the official library use the `library` namespace.
+pub(crate) fn split_namespace(model: &str) -> (&str, &str) {
+    model.split_once('/').unwrap_or(("library", model))
+}
+
+/// Get the url of the manifest for a model in the registry.
+pub(crate) fn manifest_url(model: &str, tag: &str) -> String {
+    let (namespace, model) = split_namespace(model);
+    format!("https://{OLLAMA_REGISTRY}/v2/{namespace}/{model}/manifests/{tag}")
+}
+
+/// Get the url of a blob for a model in the registry.
+pub(crate) fn blob_url(model: &str, digest: &str) -> String {
+    let (namespace, model) = split_namespace(model);
+    format!("https://{OLLAMA_REGISTRY}/v2/{namespace}/{model}/blobs/{digest}")
+}
+
+/// Get the file name of a blob. Ollama stores blobs as `sha256-<hash>` because `:` is not allowed in file names on
+/// every platform.
+pub(crate) fn blob_file_name(digest: &str) -> String {
+    digest.replace(':', "-")
+}
+
+/// Get the folder Ollama stores models in. This is `OLLAMA_MODELS` if it is set or `~/.ollama/models`.
+fn ollama_models_dir() -> Option<PathBuf> {
+    match std::env::var_os("OLLAMA_MODELS") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => dirs::home_dir().map(|home| home.join(".ollama").join("models")),
+    }
+}
+
+/// Get the model file of a model that was already pulled with Ollama if it exists.
+pub(crate) fn local_blob(model: &str, tag: &str) -> Option<PathBuf> {
+    let models = ollama_models_dir()?;
+    let (namespace, model) = split_namespace(model);
+    let manifest_path = models
+        .join("manifests")
+        .join(OLLAMA_REGISTRY)
+        .join(namespace)
+        .join(model)
+        .join(tag);
+    let manifest = OllamaManifest::parse(&std::fs::read(manifest_path).ok()?).ok()?;
+    let blob = models
+        .join("blobs")
+        .join(blob_file_name(manifest.model_digest()?));
+    blob.exists().then_some(blob)
+}
+
+/// Get the folder kalosm caches a model from the Ollama registry in.
+pub(crate) fn cache_dir(location: &Path, model: &str) -> PathBuf {
+    let (namespace, model) = split_namespace(model);
+    location.join("ollama").join(namespace).join(model)
+}
+
+#[test]
+fn parses_ollama_manifests() {
+    let manifest = br#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+        "config": {"mediaType": "application/vnd.docker.container.image.v1+json", "digest": "sha256:34", "size": 561},
+        "layers": [
+            {"mediaType": "application/vnd.ollama.image.model", "digest": "sha256:dde5", "size": 2019377376},
+            {"mediaType": "application/vnd.ollama.image.template", "digest": "sha256:966d", "size": 1429}
+        ]
+    }"#;
+    let manifest = OllamaManifest::parse(manifest).unwrap();
+    assert_eq!(manifest.model_digest(), Some("sha256:dde5"));
+    assert_eq!(blob_file_name("sha256:dde5"), "sha256-dde5");
+    assert_eq!(
+        manifest_url("llama3.2", "3b"),
+        "https://registry.ollama.ai/v2/library/llama3.2/manifests/3b"
+    );
+    assert_eq!(
+        blob_url("someone/model", "sha256:dde5"),
+        "https://registry.ollama.ai/v2/someone/model/blobs/sha256:dde5"
+    );
+}
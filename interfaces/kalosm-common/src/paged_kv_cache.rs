@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use candle_core::{DType, Device, DeviceLocation, Shape, Tensor, D};
+
+use crate::KvCacheQuantization;
+
+/// The number of tokens stored in each block if no block size is set.
+pub const DEFAULT_KV_BLOCK_SIZE: usize = 64;
+
+/// The number of unused blocks of each layout the allocator keeps around for reuse if no limit is set.
+const DEFAULT_MAX_FREE_BLOCKS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlockLayout {
+    dims: Vec<usize>,
+    dtype: DType,
+    location: DeviceLocation,
+}
+
+impl BlockLayout {
+    fn of(tensor: &Tensor) -> Self {
+        Self {
+            dims: tensor.dims().to_vec(),
+            dtype: tensor.dtype(),
+            location: tensor.device().location(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AllocatorState {
+    free: HashMap<BlockLayout, Vec<Tensor>>,
+    free_count: usize,
+    used_count: usize,
+    used_bytes: usize,
+}
+
+/// A pool of fixed size key/value blocks that is shared between [`PagedKvCache`]s. Blocks that a cache no longer uses
+/// are returned to the pool as soon as the cache is reset or dropped and reused by the next cache that needs memory.
+/// Once more than the maximum number of free blocks are in the pool, extra blocks are freed on the device.
+#[derive(Debug, Clone)]
+pub struct BlockAllocator {
+    block_size: usize,
+    max_free_blocks: usize,
+    state: Arc<Mutex<AllocatorState>>,
+}
+
+impl Default for BlockAllocator {
+    fn default() -> Self {
+        Self::new(DEFAULT_KV_BLOCK_SIZE)
+    }
+}
+
+impl BlockAllocator {
+    /// Create a new allocator for blocks that hold `block_size` tokens.
+    pub fn new(block_size: usize) -> Self {
+        Self {
+            block_size: block_size.max(1),
+            max_free_blocks: DEFAULT_MAX_FREE_BLOCKS,
+            state: Default::default(),
+        }
+    }
+
+    /// Get the allocator that is shared by every cache in the process that doesn't use its own allocator.
+    pub fn global() -> &'static Self {
+        static GLOBAL: OnceLock<BlockAllocator> = OnceLock::new();
+        GLOBAL.get_or_init(BlockAllocator::default)
+    }
+
+    /// Set the maximum number of unused blocks to keep for reuse. (Defaults to 256)
+    pub fn with_max_free_blocks(mut self, max_free_blocks: usize) -> Self {
+        self.max_free_blocks = max_free_blocks;
+        self
+    }
+
+    /// Get the number of tokens each block holds.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Get the number of blocks that are currently used by a cache.
+    pub fn used_blocks(&self) -> usize {
+        self.state.lock().unwrap().used_count
+    }
+
+    /// Get the number of bytes in the blocks that are currently used by a cache.
+    pub fn used_bytes(&self) -> usize {
+        self.state.lock().unwrap().used_bytes
+    }
+
+    /// Get the number of unused blocks that are kept for reuse.
+    pub fn free_blocks(&self) -> usize {
+        self.state.lock().unwrap().free_count
+    }
+
+    /// Free every unused block on the device.
+    pub fn release_free_blocks(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.free.clear();
+        state.free_count = 0;
+    }
+
+    fn allocate(
+        &self,
+        dims: &[usize],
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let layout = BlockLayout {
+            dims: dims.to_vec(),
+            dtype,
+            location: device.location(),
+        };
+        let bytes = dims.iter().product::<usize>() * dtype.size_in_bytes();
+        {
+            let mut state = self.state.lock().unwrap();
+            state.used_count += 1;
+            state.used_bytes += bytes;
+            if let Some(tensor) = state.free.get_mut(&layout).and_then(Vec::pop) {
+                state.free_count -= 1;
+                return Ok(tensor);
+            }
+        }
+        Tensor::zeros(Shape::from(dims), dtype, device).inspect_err(|_| {
+            let mut state = self.state.lock().unwrap();
+            state.used_count -= 1;
+            state.used_bytes -= bytes;
+        })
+    }
+
+    fn release(&self, tensor: Tensor) {
+        let mut state = self.state.lock().unwrap();
+        state.used_count -= 1;
+        state.used_bytes -= tensor.elem_count() * tensor.dtype().size_in_bytes();
+        if state.free_count < self.max_free_blocks {
+            state.free_count += 1;
+            state
+                .free
+                .entry(BlockLayout::of(&tensor))
+                .or_default()
+                .push(tensor);
+        }
+    }
+}
+
+/// One block of keys or values. The tensor is returned to the allocator when the block is dropped.
+#[derive(Debug)]
+struct Block {
+    tensor: Tensor,
+    allocator: BlockAllocator,
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        self.allocator.release(self.tensor.clone());
+    }
+}
+
+/// The blocks that store one side (keys or values) of a [`PagedKvCache`]. Quantized caches store a second list of
+/// blocks with the scales.
+#[derive(Debug, Clone, Default)]
+struct Pages {
+    data: Vec<Arc<Block>>,
+    scales: Vec<Arc<Block>>,
+}
+
+impl Pages {
+    fn clear(&mut self) {
+        self.data.clear();
+        self.scales.clear();
+    }
+
+    fn lists(&mut self) -> [&mut Vec<Arc<Block>>; 2] {
+        [&mut self.data, &mut self.scales]
+    }
+
+    /// Write the stored parts of a chunk of tokens at `offset` in the last block. A new block is started if the
+    /// offset is zero.
+    fn write(
+        &mut self,
+        allocator: &BlockAllocator,
+        concat_dim: usize,
+        offset: usize,
+        parts: &[Tensor],
+    ) -> candle_core::Result<()> {
+        for (blocks, part) in self.lists().into_iter().zip(parts) {
+            if offset == 0 {
+                let mut dims = part.dims().to_vec();
+                dims[concat_dim] = allocator.block_size;
+                let tensor = allocator.allocate(&dims, part.dtype(), part.device())?;
+                blocks.push(Arc::new(Block {
+                    tensor,
+                    allocator: allocator.clone(),
+                }));
+            } else {
+                make_unique(
+                    blocks
+                        .last_mut()
+                        .expect("the cache has a partially filled block"),
+                    allocator,
+                    concat_dim,
+                    offset,
+                )?;
+            }
+            let block = blocks.last().expect("a block was just added");
+            block
+                .tensor
+                .slice_set(&part.contiguous()?, concat_dim, offset)?;
+        }
+        Ok(())
+    }
+
+    /// Get `len` tokens starting at `start` from the block at `index` in each list of blocks.
+    fn block(
+        &self,
+        index: usize,
+        concat_dim: usize,
+        start: usize,
+        len: usize,
+    ) -> candle_core::Result<Vec<Tensor>> {
+        [&self.data, &self.scales]
+            .into_iter()
+            .filter(|blocks| !blocks.is_empty())
+            .map(|blocks| blocks[index].tensor.narrow(concat_dim, start, len))
+            .collect()
+    }
+
+    /// Get the first `len` tokens of each list of blocks.
+    fn gather(
+        &self,
+        concat_dim: usize,
+        block_size: usize,
+        len: usize,
+    ) -> candle_core::Result<Vec<Tensor>> {
+        [&self.data, &self.scales]
+            .into_iter()
+            .filter(|blocks| !blocks.is_empty())
+            .map(|blocks| {
+                let parts = blocks
+                    .iter()
+                    .enumerate()
+                    .map(|(i, block)| {
+                        let len = (len - i * block_size).min(block_size);
+                        block.tensor.narrow(concat_dim, 0, len)
+                    })
+                    .collect::<candle_core::Result<Vec<_>>>()?;
+                match parts.as_slice() {
+                    [part] => Ok(part.clone()),
+                    parts => Tensor::cat(parts, concat_dim),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Copy a block if it is shared with a clone of the cache so writing to it doesn't change the clone.
+fn make_unique(
+    block: &mut Arc<Block>,
+    allocator: &BlockAllocator,
+    concat_dim: usize,
+    filled: usize,
+) -> candle_core::Result<()> {
+    if Arc::strong_count(block) > 1 {
+        let tensor = allocator.allocate(
+            block.tensor.dims(),
+            block.tensor.dtype(),
+            block.tensor.device(),
+        )?;
+        let filled_part = block.tensor.narrow(concat_dim, 0, filled)?.contiguous()?;
+        tensor.slice_set(&filled_part, concat_dim, 0)?;
+        *block = Arc::new(Block {
+            tensor,
+            allocator: allocator.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// A key/value cache that stores tokens in fixed size blocks from a shared [`BlockAllocator`] instead of one
+/// contiguous tensor. Growing the cache only allocates a new block, and the blocks of a cache are reused by other
+/// caches as soon as it is reset or dropped.
+///
+/// Cloning the cache is cheap: full blocks are shared between the clones and the last block is copied the next time
+/// either clone appends to it.
+///
+/// Reading the keys and values with [`PagedKvCache::k`] or [`PagedKvCache::append`] copies every block into one
+/// tensor. When decoding one token at a time, use [`PagedKvCache::push`] and [`PagedKvCache::attend`] instead which
+/// read the blocks in place.
+#[derive(Debug, Clone)]
+pub struct PagedKvCache {
+    allocator: BlockAllocator,
+    keys: Pages,
+    values: Pages,
+    len: usize,
+    concat_dim: usize,
+    max_seq_len: usize,
+    quantization: KvCacheQuantization,
+    /// The type of the keys and values before they were quantized
+    dtype: Option<DType>,
+}
+
+impl PagedKvCache {
+    /// Create a new cache with the given max sequence length that allocates from the global [`BlockAllocator`].
+    pub fn new(concat_dim: usize, max_seq_len: usize) -> Self {
+        Self::with_allocator(BlockAllocator::global().clone(), concat_dim, max_seq_len)
+    }
+
+    /// Create a new cache with the given max sequence length that allocates from an allocator.
+    pub fn with_allocator(
+        allocator: BlockAllocator,
+        concat_dim: usize,
+        max_seq_len: usize,
+    ) -> Self {
+        Self {
+            allocator,
+            keys: Pages::default(),
+            values: Pages::default(),
+            len: 0,
+            concat_dim,
+            max_seq_len,
+            quantization: KvCacheQuantization::None,
+            dtype: None,
+        }
+    }
+
+    /// Set how the keys and values are stored. This resets the cache. (Defaults to [`KvCacheQuantization::None`])
+    pub fn with_quantization(mut self, quantization: KvCacheQuantization) -> Self {
+        self.reset();
+        self.quantization = quantization;
+        self
+    }
+
+    /// Get how the keys and values are stored.
+    pub fn quantization(&self) -> KvCacheQuantization {
+        self.quantization
+    }
+
+    /// Get the number of tokens in the cache.
+    pub fn current_seq_len(&self) -> usize {
+        self.len
+    }
+
+    /// Get the number of blocks the cache uses for keys and values each.
+    pub fn block_count(&self) -> usize {
+        self.keys.data.len()
+    }
+
+    /// Reset the cache and return its blocks to the allocator.
+    pub fn reset(&mut self) {
+        self.keys.clear();
+        self.values.clear();
+        self.len = 0;
+        self.dtype = None;
+    }
+
+    /// Keep only the first `len` tokens in the cache. Blocks after them are released, and a partially kept block is
+    /// copied before it is written to if it is shared with a clone.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        if len == 0 {
+            self.reset();
+            return;
+        }
+        let blocks = len.div_ceil(self.allocator.block_size);
+        for pages in [&mut self.keys, &mut self.values] {
+            for list in pages.lists() {
+                list.truncate(blocks);
+            }
+        }
+        self.len = len;
+    }
+
+    /// Get all of the keys in the cache.
+    pub fn k(&self) -> candle_core::Result<Option<Tensor>> {
+        self.gather(&self.keys)
+    }
+
+    /// Get all of the values in the cache.
+    pub fn v(&self) -> candle_core::Result<Option<Tensor>> {
+        self.gather(&self.values)
+    }
+
+    /// Append new keys and values to the cache and get all of the keys and values in the cache. This copies every block
+    /// into one tensor, so prefer [`Self::push`] and [`Self::attend`] when decoding.
+    pub fn append(&mut self, k: &Tensor, v: &Tensor) -> candle_core::Result<(Tensor, Tensor)> {
+        self.push(k, v)?;
+        let k = self.k()?.expect("the cache is not empty");
+        let v = self.v()?.expect("the cache is not empty");
+        Ok((k, v))
+    }
+
+    /// Append new keys and values to the cache without reading the cache back.
+    pub fn push(&mut self, k: &Tensor, v: &Tensor) -> candle_core::Result<()> {
+        let k = k.contiguous()?;
+        let v = v.contiguous()?;
+        let seq_len = k.dim(self.concat_dim)?;
+        // The key and value token length must be the same.
+        debug_assert_eq!(seq_len, v.dim(self.concat_dim)?);
+        if self.len + seq_len > self.max_seq_len {
+            candle_core::bail!(
+                "kv-cache: above max-seq-len {}+{seq_len}>{}",
+                self.len,
+                self.max_seq_len
+            )
+        }
+        self.dtype.get_or_insert(k.dtype());
+
+        let block_size = self.allocator.block_size;
+        let mut written = 0;
+        while written < seq_len {
+            let offset = self.len % block_size;
+            let count = (block_size - offset).min(seq_len - written);
+            for (pages, source) in [(&mut self.keys, &k), (&mut self.values, &v)] {
+                let chunk = source.narrow(self.concat_dim, written, count)?;
+                let parts = self.quantization.quantize(&chunk)?;
+                pages.write(&self.allocator, self.concat_dim, offset, &parts)?;
+            }
+            written += count;
+            self.len += count;
+        }
+        Ok(())
+    }
+
+    /// Run attention from `query` over the cached keys and values one block at a time without copying the blocks into
+    /// one tensor. The query has the shape `(batch, heads, q_len, head_dim)` and every query attends to every cached
+    /// token, so this is meant for decoding a single token.
+    ///
+    /// If `logit_cap` is set, the attention logits are soft capped with `tanh`. If `window` is set, only the most
+    /// recent `window` tokens are read. A window of zero tokens is an error because there is nothing to attend to.
+    pub fn attend(
+        &self,
+        query: &Tensor,
+        scale: f64,
+        logit_cap: Option<f64>,
+        window: Option<usize>,
+    ) -> candle_core::Result<Tensor> {
+        let Some(dtype) = self.dtype.filter(|_| self.len > 0) else {
+            candle_core::bail!("kv-cache: cannot attend to an empty cache")
+        };
+        if window == Some(0) {
+            candle_core::bail!("kv-cache: cannot attend to a window of zero tokens")
+        }
+        let block_size = self.allocator.block_size;
+        let first = window.map_or(0, |window| self.len.saturating_sub(window));
+        let query = query.to_dtype(DType::F32)?;
+
+        // The running max logit, softmax denominator and weighted sum of values of each query
+        let mut state: Option<(Tensor, Tensor, Tensor)> = None;
+        for index in first / block_size..self.block_count() {
+            let block_start = index * block_size;
+            let start = first.saturating_sub(block_start);
+            let len = (self.len - block_start).min(block_size) - start;
+            let read = |pages: &Pages| {
+                let parts = pages.block(index, self.concat_dim, start, len)?;
+                self.quantization
+                    .dequantize(&parts, dtype)?
+                    .to_dtype(DType::F32)?
+                    .contiguous()
+            };
+            let keys = read(&self.keys)?;
+            let values = read(&self.values)?;
+
+            let mut logits = (query.matmul(&keys.t()?)? * scale)?;
+            if let Some(cap) = logit_cap {
+                logits = ((logits / cap)?.tanh()? * cap)?;
+            }
+            let block_max = logits.max_keepdim(D::Minus1)?;
+            state = Some(match state {
+                None => {
+                    let weights = logits.broadcast_sub(&block_max)?.exp()?;
+                    let sum = weights.sum_keepdim(D::Minus1)?;
+                    (block_max, sum, weights.matmul(&values)?)
+                }
+                Some((max, sum, output)) => {
+                    let new_max = max.maximum(&block_max)?;
+                    let correction = (max - &new_max)?.exp()?;
+                    let weights = logits.broadcast_sub(&new_max)?.exp()?;
+                    let sum = ((sum * &correction)? + weights.sum_keepdim(D::Minus1)?)?;
+                    let output = (output.broadcast_mul(&correction)? + weights.matmul(&values)?)?;
+                    (new_max, sum, output)
+                }
+            });
+        }
+
+        let Some((_, sum, output)) = state else {
+            candle_core::bail!("kv-cache: the attention window does not contain any tokens")
+        };
+        output.broadcast_div(&sum)?.to_dtype(dtype)
+    }
+
+    fn gather(&self, pages: &Pages) -> candle_core::Result<Option<Tensor>> {
+        let Some(dtype) = self.dtype.filter(|_| self.len > 0) else {
+            return Ok(None);
+        };
+        let parts = pages.gather(self.concat_dim, self.allocator.block_size, self.len)?;
+        self.quantization.dequantize(&parts, dtype).map(Some)
+    }
+}
+
+#[test]
+fn paged_cache_spans_blocks_and_recycles_them() {
+    let allocator = BlockAllocator::new(4);
+    let mut cache = PagedKvCache::with_allocator(allocator.clone(), 2, 64);
+    let device = Device::Cpu;
+    let tokens = Tensor::arange(0f32, 10., &device)
+        .unwrap()
+        .reshape((1, 1, 10, 1))
+        .unwrap();
+    let (k, _) = cache.append(&tokens, &tokens).unwrap();
+    assert_eq!(
+        k.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        (0..10).map(|x| x as f32).collect::<Vec<_>>()
+    );
+    assert_eq!(cache.block_count(), 3);
+
+    // Appending to a clone doesn't change the original
+    let snapshot = cache.clone();
+    let next = Tensor::new(&[[[[100f32]]]], &device).unwrap();
+    cache.append(&next, &next).unwrap();
+    assert_eq!(snapshot.current_seq_len(), 10);
+    let snapshot_k = snapshot.k().unwrap().unwrap().flatten_all().unwrap();
+    assert_eq!(snapshot_k.to_vec1::<f32>().unwrap()[9], 9.);
+
+    drop(snapshot);
+    cache.reset();
+    assert_eq!(allocator.used_blocks(), 0);
+    assert!(allocator.free_blocks() > 0);
+}
+
+#[test]
+fn truncated_clone_shares_the_prefix() {
+    let allocator = BlockAllocator::new(4);
+    let mut cache = PagedKvCache::with_allocator(allocator, 2, 64);
+    let device = Device::Cpu;
+    let tokens = Tensor::arange(0f32, 10., &device)
+        .unwrap()
+        .reshape((1, 1, 10, 1))
+        .unwrap();
+    cache.append(&tokens, &tokens).unwrap();
+
+    let mut prefix = cache.clone();
+    prefix.truncate(6);
+    assert_eq!(prefix.block_count(), 2);
+    let next = Tensor::new(&[[[[100f32]]]], &device).unwrap();
+    let (k, _) = prefix.append(&next, &next).unwrap();
+    assert_eq!(
+        k.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        [0., 1., 2., 3., 4., 5., 100.]
+    );
+    // Writing to the shared block copied it
+    let k = cache.k().unwrap().unwrap().flatten_all().unwrap();
+    assert_eq!(k.to_vec1::<f32>().unwrap()[6], 6.);
+}
+
+#[test]
+fn attend_reads_blocks_in_place() {
+    let allocator = BlockAllocator::new(4);
+    let mut cache = PagedKvCache::with_allocator(allocator, 2, 64);
+    let device = Device::Cpu;
+    let keys = Tensor::randn(0f32, 1., (1, 2, 10, 8), &device).unwrap();
+    let values = Tensor::randn(0f32, 1., (1, 2, 10, 8), &device).unwrap();
+    let query = Tensor::randn(0f32, 1., (1, 2, 1, 8), &device).unwrap();
+    cache.push(&keys, &values).unwrap();
+
+    for window in [None, Some(5)] {
+        let start = window.map_or(0, |window| 10 - window);
+        let keys = keys.narrow(2, start, 10 - start).unwrap();
+        let values = values.narrow(2, start, 10 - start).unwrap();
+        let weights = (query.matmul(&keys.t().unwrap()).unwrap() * 0.5).unwrap();
+        let weights = candle_nn::ops::softmax_last_dim(&weights).unwrap();
+        let expected = weights.matmul(&values).unwrap();
+
+        let output = cache.attend(&query, 0.5, None, window).unwrap();
+        let error = (output - expected)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(error < 1e-5, "{window:?}: {error}");
+    }
+
+    // A window without any tokens is an error instead of a panic
+    assert!(cache.attend(&query, 0.5, None, Some(0)).is_err());
+    assert!(PagedKvCache::with_allocator(BlockAllocator::new(4), 2, 64)
+        .attend(&query, 0.5, None, None)
+        .is_err());
+}
+
+#[test]
+fn quantized_paged_cache_uses_less_memory() {
+    let device = Device::Cpu;
+    let keys = Tensor::randn(0f32, 1., (1, 2, 10, 8), &device).unwrap();
+    let values = Tensor::randn(0f32, 1., (1, 2, 10, 8), &device).unwrap();
+    let query = Tensor::randn(0f32, 1., (1, 2, 1, 8), &device).unwrap();
+
+    let full_allocator = BlockAllocator::new(4);
+    let mut full = PagedKvCache::with_allocator(full_allocator.clone(), 2, 64);
+    full.push(&keys, &values).unwrap();
+    let quantized_allocator = BlockAllocator::new(4);
+    let mut quantized = PagedKvCache::with_allocator(quantized_allocator.clone(), 2, 64)
+        .with_quantization(KvCacheQuantization::Q8);
+    quantized.push(&keys, &values).unwrap();
+
+    // Each row of 8 f32s is stored as 8 bytes and one f32 scale
+    let full_bytes = full_allocator.used_bytes();
+    let quantized_bytes = quantized_allocator.used_bytes();
+    assert_eq!(full_bytes, 2 * 3 * 2 * 4 * 8 * 4);
+    assert_eq!(quantized_bytes, 2 * 3 * 2 * 4 * (8 + 4));
+
+    // Attending dequantizes blocks without allocating from the pool and matches the full precision cache closely
+    let expected = full.attend(&query, 0.5, None, None).unwrap();
+    let output = quantized.attend(&query, 0.5, None, None).unwrap();
+    assert_eq!(quantized_allocator.used_bytes(), quantized_bytes);
+    let error = (output - expected)
+        .unwrap()
+        .abs()
+        .unwrap()
+        .max_all()
+        .unwrap()
+        .to_scalar::<f32>()
+        .unwrap();
+    assert!(error < 0.1, "{error}");
+
+    drop(quantized);
+    assert_eq!(quantized_allocator.used_bytes(), 0);
+}
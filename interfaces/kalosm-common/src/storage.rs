@@ -0,0 +1,153 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use reqwest::StatusCode;
+use tokio::io::AsyncWriteExt;
+
+use crate::CacheError;
+
+/// A boxed future returned by [`CacheStorage`] methods.
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, CacheError>> + Send + 'a>>;
+
+/// A shared store for cached model files. Models are always loaded from the local cache directory, so the storage
+/// acts as a second tier behind it: files missing from the local cache are copied from the storage before they are
+/// downloaded, and new downloads are copied into the storage for other machines to use.
+///
+/// Keys are the path of the file relative to the cache directory with `/` separators, like
+/// `bartowski/gemma-2-2b-it-GGUF/main/gemma-2-2b-it-Q4_K_M.gguf`.
+///
+/// ```rust, no_run
+/// use kalosm_common::{Cache, DirectoryStorage};
+///
+/// // Share downloads between containers through a network mount
+/// let cache = Cache::default().with_storage(DirectoryStorage::new("/mnt/models"));
+/// ```
+pub trait CacheStorage: std::fmt::Debug + Send + Sync + 'static {
+    /// Copy the file stored under the key to the destination. Returns `false` if the storage does not contain the
+    /// file.
+    fn fetch<'a>(&'a self, key: &'a str, destination: &'a Path) -> StorageFuture<'a, bool>;
+
+    /// Copy a file from the local cache into the storage under the key.
+    fn store<'a>(&'a self, key: &'a str, source: &'a Path) -> StorageFuture<'a, ()>;
+}
+
+/// A [`CacheStorage`] in a directory. The directory can be a network share mounted in each container.
+#[derive(Debug, Clone)]
+pub struct DirectoryStorage {
+    root: PathBuf,
+}
+
+impl DirectoryStorage {
+    /// Create a new storage in a directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl CacheStorage for DirectoryStorage {
+    fn fetch<'a>(&'a self, key: &'a str, destination: &'a Path) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            if !tokio::fs::try_exists(&path).await? {
+                return Ok(false);
+            }
+            tokio::fs::copy(&path, destination).await?;
+            Ok(true)
+        })
+    }
+
+    fn store<'a>(&'a self, key: &'a str, source: &'a Path) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // Copy into a temporary file first so other machines never see a partial file
+            let partial = path.with_file_name(format!(
+                "{}.partial",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            tokio::fs::copy(source, &partial).await?;
+            tokio::fs::rename(&partial, &path).await?;
+            Ok(())
+        })
+    }
+}
+
+/// A [`CacheStorage`] in an object store that supports plain HTTP `GET` and `PUT` requests, like an S3-compatible
+/// bucket behind a gateway or with a bucket policy that allows the requests. Objects are stored at
+/// `{base_url}/{key}`.
+///
+/// Implement [`CacheStorage`] with the SDK of your object store if requests need to be signed.
+#[derive(Debug, Clone)]
+pub struct HttpStorage {
+    base_url: String,
+    bearer_token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl HttpStorage {
+    /// Create a new storage at a base URL.
+    pub fn new(base_url: impl ToString) -> Self {
+        Self {
+            base_url: base_url.to_string().trim_end_matches('/').to_string(),
+            bearer_token: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Set a bearer token to send with every request.
+    pub fn with_bearer_token(mut self, token: impl ToString) -> Self {
+        self.bearer_token = Some(token.to_string());
+        self
+    }
+
+    fn request(&self, method: reqwest::Method, key: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}/{key}", self.base_url));
+        match &self.bearer_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+impl CacheStorage for HttpStorage {
+    fn fetch<'a>(&'a self, key: &'a str, destination: &'a Path) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let mut response = self.request(reqwest::Method::GET, key).send().await?;
+            match response.status() {
+                StatusCode::NOT_FOUND => return Ok(false),
+                status if !status.is_success() => {
+                    return Err(CacheError::UnexpectedStatusCode(status))
+                }
+                _ => {}
+            }
+            let mut file = tokio::fs::File::create(destination).await?;
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(true)
+        })
+    }
+
+    fn store<'a>(&'a self, key: &'a str, source: &'a Path) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let file = tokio::fs::File::open(source).await?;
+            let length = file.metadata().await?.len();
+            let response = self
+                .request(reqwest::Method::PUT, key)
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .body(file)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                return Err(CacheError::UnexpectedStatusCode(response.status()));
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use super::document::{Document, IntoDocument, IntoDocuments};
+
+/// An error that can occur when reading a chat export.
+#[derive(Debug, thiserror::Error)]
+pub enum ChatExportError {
+    /// An error reading the export
+    #[error("Failed to read chat export: {0}")]
+    Read(#[from] std::io::Error),
+    /// An error parsing a file in the export
+    #[error("Failed to parse chat export: {0}")]
+    Parse(#[from] serde_json::Error),
+}
+
+/// A single message from a chat export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedMessage {
+    /// The display name of the author of the message.
+    pub author: String,
+    /// The time the message was sent.
+    pub timestamp: DateTime<Utc>,
+    /// The text of the message.
+    pub text: String,
+}
+
+/// A group of messages from a channel or thread in a chat export.
+///
+/// When converted into a [`Document`], every message is written on its own line, so [`ChunkStrategy::Paragraph`](crate::search::ChunkStrategy::Paragraph)
+/// can be used to chunk the conversation without splitting messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversation {
+    /// The name of the channel the conversation happened in.
+    pub channel: String,
+    /// The id of the thread the conversation happened in, if any.
+    pub thread: Option<String>,
+    /// The messages in the conversation sorted by time.
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl Conversation {
+    /// Get the time the first message in the conversation was sent.
+    pub fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.messages.first().map(|message| message.timestamp)
+    }
+
+    /// Get the time the last message in the conversation was sent.
+    pub fn ended_at(&self) -> Option<DateTime<Utc>> {
+        self.messages.last().map(|message| message.timestamp)
+    }
+}
+
+impl IntoDocument for Conversation {
+    type Error = std::convert::Infallible;
+
+    async fn into_document(self) -> Result<Document, Self::Error> {
+        let mut title = format!("#{}", self.channel);
+        if let Some(started_at) = self.started_at() {
+            title += &format!(" ({})", started_at.format("%Y-%m-%d %H:%M"));
+        }
+        if self.thread.is_some() {
+            title += " thread";
+        }
+        let mut body = String::new();
+        for message in &self.messages {
+            // Keep each message on a single line so chunking by lines respects message boundaries
+            let text = message
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            body += &format!(
+                "[{}] {}: {}\n",
+                message.timestamp.format("%Y-%m-%d %H:%M"),
+                message.author,
+                text
+            );
+        }
+        let mut document = Document::from_parts(title, body);
+        if let Some(started_at) = self.started_at() {
+            document.set_created_at(started_at);
+        }
+        if let Some(ended_at) = self.ended_at() {
+            document.set_updated_at(ended_at);
+        }
+        Ok(document)
+    }
+}
+
+/// Settings for how messages that are not part of a thread are grouped into [`Conversation`]s.
+///
+/// A new conversation starts when there is a long gap between messages or the current conversation gets too long.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConversationGrouping {
+    max_gap: Duration,
+    max_messages: usize,
+}
+
+impl Default for ConversationGrouping {
+    fn default() -> Self {
+        Self {
+            max_gap: Duration::from_secs(30 * 60),
+            max_messages: 50,
+        }
+    }
+}
+
+impl ConversationGrouping {
+    /// Create the default grouping that splits conversations after 30 minutes of silence or 50 messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the longest gap between two messages in the same conversation.
+    pub fn with_max_gap(mut self, max_gap: Duration) -> Self {
+        self.max_gap = max_gap;
+        self
+    }
+
+    /// Set the maximum number of messages in a conversation.
+    pub fn with_max_messages(mut self, max_messages: usize) -> Self {
+        self.max_messages = max_messages.max(1);
+        self
+    }
+
+    /// Split the messages from a channel into conversations.
+    pub fn group(&self, channel: &str, mut messages: Vec<ExportedMessage>) -> Vec<Conversation> {
+        messages.sort_by_key(|message| message.timestamp);
+        let mut conversations = Vec::new();
+        let mut current: Vec<ExportedMessage> = Vec::new();
+        for message in messages {
+            let split = current.last().is_some_and(|last| {
+                let gap = (message.timestamp - last.timestamp)
+                    .to_std()
+                    .unwrap_or_default();
+                gap > self.max_gap || current.len() >= self.max_messages
+            });
+            if split {
+                conversations.push(Conversation {
+                    channel: channel.to_string(),
+                    thread: None,
+                    messages: std::mem::take(&mut current),
+                });
+            }
+            current.push(message);
+        }
+        if !current.is_empty() {
+            conversations.push(Conversation {
+                channel: channel.to_string(),
+                thread: None,
+                messages: current,
+            });
+        }
+        conversations
+    }
+}
+
+#[derive(Deserialize)]
+struct SlackUser {
+    id: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    real_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SlackUserProfile {
+    #[serde(default)]
+    real_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SlackMessage {
+    #[serde(default)]
+    subtype: Option<String>,
+    #[serde(default)]
+    user: Option<String>,
+    #[serde(default)]
+    user_profile: Option<SlackUserProfile>,
+    #[serde(default)]
+    text: String,
+    ts: String,
+    #[serde(default)]
+    thread_ts: Option<String>,
+}
+
+fn slack_timestamp(ts: &str) -> Option<DateTime<Utc>> {
+    let (seconds, fraction) = ts.split_once('.').unwrap_or((ts, "0"));
+    let seconds = seconds.parse().ok()?;
+    let micros: u32 = format!("{fraction:0<6}")[..6].parse().ok()?;
+    DateTime::from_timestamp(seconds, micros * 1000)
+}
+
+/// Replace `<@U123>` mentions and `<https://link|label>` links with readable text.
+fn slack_text(text: &str, users: &HashMap<String, String>) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        let inner = &rest[start + 1..start + end];
+        if let Some(id) = inner.strip_prefix('@') {
+            let id = id.split('|').next().unwrap_or(id);
+            output.push('@');
+            output.push_str(users.get(id).map(|name| name.as_str()).unwrap_or(id));
+        } else if let Some(channel) = inner.strip_prefix('#') {
+            output.push('#');
+            output.push_str(channel.rsplit('|').next().unwrap_or(channel));
+        } else {
+            output.push_str(inner.rsplit('|').next().unwrap_or(inner));
+        }
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    output
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// An unzipped [Slack workspace export](https://slack.com/help/articles/201658943). Threads are converted into
+/// their own [`Document`]s and the rest of the messages in each channel are grouped with [`ConversationGrouping`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let export = SlackExport::new("./slack-export");
+///     for conversation in export.conversations().await.unwrap() {
+///         println!("#{}: {} messages", conversation.channel, conversation.messages.len());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SlackExport {
+    path: PathBuf,
+    grouping: ConversationGrouping,
+}
+
+impl SlackExport {
+    /// Create a new slack export source from the path to the unzipped export.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            grouping: ConversationGrouping::default(),
+        }
+    }
+
+    /// Set how messages outside of threads are grouped into conversations.
+    pub fn with_grouping(mut self, grouping: ConversationGrouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    async fn users(&self) -> Result<HashMap<String, String>, ChatExportError> {
+        let path = self.path.join("users.json");
+        if !path.is_file() {
+            return Ok(HashMap::new());
+        }
+        let users: Vec<SlackUser> = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+        Ok(users
+            .into_iter()
+            .map(|user| {
+                let name = user
+                    .real_name
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or(user.name);
+                (user.id, name)
+            })
+            .collect())
+    }
+
+    /// Read every conversation in the export.
+    pub async fn conversations(&self) -> Result<Vec<Conversation>, ChatExportError> {
+        let users = self.users().await?;
+        let mut conversations = Vec::new();
+
+        let mut read_dir = tokio::fs::read_dir(&self.path).await?;
+        let mut channels = Vec::new();
+        while let Some(entry) = read_dir.next_entry().await? {
+            if entry.file_type().await?.is_dir() {
+                channels.push(entry.path());
+            }
+        }
+        channels.sort();
+
+        for channel_path in channels {
+            let channel = channel_path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let mut channel_messages = Vec::new();
+            let mut threads: BTreeMap<String, Vec<ExportedMessage>> = BTreeMap::new();
+
+            let mut days = tokio::fs::read_dir(&channel_path).await?;
+            while let Some(day) = days.next_entry().await? {
+                let path = day.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let messages: Vec<SlackMessage> =
+                    serde_json::from_slice(&tokio::fs::read(&path).await?)?;
+                for message in messages {
+                    // Skip join, leave and topic change messages
+                    if message.subtype.as_deref().is_some_and(|subtype| {
+                        !matches!(subtype, "bot_message" | "thread_broadcast" | "file_share")
+                    }) {
+                        continue;
+                    }
+                    let Some(timestamp) = slack_timestamp(&message.ts) else {
+                        continue;
+                    };
+                    let author = message
+                        .user_profile
+                        .and_then(|profile| profile.real_name)
+                        .or_else(|| message.user.as_ref().and_then(|id| users.get(id).cloned()))
+                        .or(message.user)
+                        .unwrap_or_else(|| "unknown".to_string());
+                    let exported = ExportedMessage {
+                        author,
+                        timestamp,
+                        text: slack_text(&message.text, &users),
+                    };
+                    match message.thread_ts {
+                        Some(thread_ts) => threads.entry(thread_ts).or_default().push(exported),
+                        None => channel_messages.push(exported),
+                    }
+                }
+            }
+
+            conversations.extend(self.grouping.group(&channel, channel_messages));
+            for (thread, mut messages) in threads {
+                messages.sort_by_key(|message| message.timestamp);
+                conversations.push(Conversation {
+                    channel: channel.clone(),
+                    thread: Some(thread),
+                    messages,
+                });
+            }
+        }
+
+        Ok(conversations)
+    }
+}
+
+impl IntoDocuments for SlackExport {
+    type Error = ChatExportError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let conversations = self.conversations().await?;
+        conversations
+            .into_documents()
+            .await
+            .map_err(|err| match err {})
+    }
+}
+
+#[derive(Deserialize)]
+struct DiscordChannelExport {
+    channel: DiscordChannel,
+    messages: Vec<DiscordMessage>,
+}
+
+#[derive(Deserialize)]
+struct DiscordChannel {
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DiscordMessage {
+    timestamp: String,
+    #[serde(default)]
+    content: String,
+    author: DiscordAuthor,
+}
+
+#[derive(Deserialize)]
+struct DiscordAuthor {
+    name: String,
+    #[serde(default)]
+    nickname: Option<String>,
+}
+
+/// A Discord channel export in the JSON format created by [DiscordChatExporter](https://github.com/Tyrrrz/DiscordChatExporter).
+/// The path can either be a single exported channel or a folder of exported channels. Messages are grouped into
+/// conversations with [`ConversationGrouping`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let export = DiscordExport::new("./discord-export").with_grouping(
+///         ConversationGrouping::new().with_max_gap(Duration::from_secs(60 * 60)),
+///     );
+///     let documents = export.into_documents().await.unwrap();
+///     println!("{} conversations", documents.len());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DiscordExport {
+    path: PathBuf,
+    grouping: ConversationGrouping,
+}
+
+impl DiscordExport {
+    /// Create a new discord export source from the path to an exported channel or a folder of exported channels.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            grouping: ConversationGrouping::default(),
+        }
+    }
+
+    /// Set how messages are grouped into conversations.
+    pub fn with_grouping(mut self, grouping: ConversationGrouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    async fn read_channel(&self, path: &Path) -> Result<Vec<Conversation>, ChatExportError> {
+        let export: DiscordChannelExport = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+        let messages = export
+            .messages
+            .into_iter()
+            .filter(|message| !message.content.trim().is_empty())
+            .filter_map(|message| {
+                let timestamp = DateTime::parse_from_rfc3339(&message.timestamp).ok()?;
+                Some(ExportedMessage {
+                    author: message.author.nickname.unwrap_or(message.author.name),
+                    timestamp: timestamp.with_timezone(&Utc),
+                    text: message.content,
+                })
+            })
+            .collect();
+        Ok(self.grouping.group(&export.channel.name, messages))
+    }
+
+    /// Read every conversation in the export.
+    pub async fn conversations(&self) -> Result<Vec<Conversation>, ChatExportError> {
+        if self.path.is_file() {
+            return self.read_channel(&self.path).await;
+        }
+        let mut files = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&self.path).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        let mut conversations = Vec::new();
+        for file in files {
+            conversations.extend(self.read_channel(&file).await?);
+        }
+        Ok(conversations)
+    }
+}
+
+impl IntoDocuments for DiscordExport {
+    type Error = ChatExportError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let conversations = self.conversations().await?;
+        conversations
+            .into_documents()
+            .await
+            .map_err(|err| match err {})
+    }
+}
+
+#[test]
+fn groups_messages_into_conversations() {
+    let message = |minute: i64, text: &str| ExportedMessage {
+        author: "alice".to_string(),
+        timestamp: DateTime::from_timestamp(minute * 60, 0).unwrap(),
+        text: text.to_string(),
+    };
+    let messages = vec![
+        message(0, "hi"),
+        message(5, "anyone here?"),
+        message(120, "back"),
+        message(121, "still here"),
+        message(122, "hello"),
+    ];
+    let grouping = ConversationGrouping::new().with_max_messages(2);
+    let conversations = grouping.group("general", messages);
+    let sizes: Vec<_> = conversations
+        .iter()
+        .map(|conversation| conversation.messages.len())
+        .collect();
+    assert_eq!(sizes, vec![2, 2, 1]);
+    assert_eq!(conversations[1].messages[0].text, "back");
+}
+
+#[test]
+fn formats_slack_text() {
+    let users = HashMap::from([("U1".to_string(), "Alice".to_string())]);
+    assert_eq!(
+        slack_text(
+            "<@U1> see <https://kalosm.dev|the docs> &amp; <#C1|general>",
+            &users
+        ),
+        "@Alice see the docs & #general"
+    );
+    assert_eq!(
+        slack_timestamp("1704067200.000200")
+            .unwrap()
+            .timestamp_micros(),
+        1704067200000200
+    );
+}
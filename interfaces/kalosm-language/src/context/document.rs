@@ -1,4 +1,5 @@
-use std::{convert::Infallible, future::Future};
+use kalosm_language_model::{RetryPolicy, RetryReason};
+use std::{collections::BTreeMap, convert::Infallible, future::Future};
 use url::Url;
 pub use whatlang::Lang;
 
@@ -10,6 +11,8 @@ pub struct Document {
     summary: Option<String>,
     created_at: Option<chrono::DateTime<chrono::Utc>>,
     updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    metadata: BTreeMap<String, serde_json::Value>,
 }
 
 impl Document {
@@ -31,6 +34,7 @@ impl Document {
             summary: None,
             created_at: None,
             updated_at: None,
+            metadata: BTreeMap::new(),
         }
     }
 
@@ -49,6 +53,27 @@ impl Document {
         self.updated_at = Some(updated_at);
     }
 
+    /// Set a metadata field of the document, like the sender of an email. Metadata is not part of the body, so it
+    /// can be used to filter or group documents without affecting the text that is embedded or searched.
+    pub fn set_metadata(&mut self, key: impl Into<String>, value: impl Into<serde_json::Value>) {
+        self.metadata.insert(key.into(), value.into());
+    }
+
+    /// Get a metadata field of the document.
+    pub fn metadata(&self, key: &str) -> Option<&serde_json::Value> {
+        self.metadata.get(key)
+    }
+
+    /// Get every metadata field of the document.
+    pub fn all_metadata(&self) -> &BTreeMap<String, serde_json::Value> {
+        &self.metadata
+    }
+
+    /// Replace the body of the document.
+    pub(crate) fn set_body(&mut self, body: impl Into<String>) {
+        self.body = body.into();
+    }
+
     /// Get the title of the document.
     pub fn title(&self) -> &str {
         &self.title
@@ -165,10 +190,35 @@ pub enum ExtractDocumentError {
 }
 
 pub(crate) async fn get_article(url: Url) -> Result<Document, ExtractDocumentError> {
-    let html = reqwest::get(url.clone()).await?.text().await?;
+    let html = fetch_text(url, &RetryPolicy::default()).await?;
     extract_article(&html)
 }
 
+/// Fetch the text at a URL. Transient failures like rate limits or dropped connections are retried with the retry policy.
+pub(crate) async fn fetch_text(
+    url: Url,
+    retry_policy: &RetryPolicy,
+) -> Result<String, reqwest::Error> {
+    retry_policy
+        .retry(
+            || {
+                let url = url.clone();
+                async move {
+                    let response = reqwest::get(url).await?;
+                    let status = response.status().as_u16();
+                    if retry_policy.should_retry(RetryReason::Status(status)) {
+                        response.error_for_status()?.text().await
+                    } else {
+                        response.text().await
+                    }
+                }
+            },
+            RetryReason::from_reqwest_error,
+            tokio::time::sleep,
+        )
+        .await
+}
+
 pub(crate) fn extract_article(html: &str) -> Result<Document, ExtractDocumentError> {
     let cleaned =
         readability::extractor::extract(&mut html.as_bytes(), &Url::parse("https://example.com")?)
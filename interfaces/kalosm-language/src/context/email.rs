@@ -0,0 +1,451 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use mail_parser::{MessageParser, MimeHeaders};
+
+use super::document::{Document, IntoDocument, IntoDocuments};
+use super::{extract_article, FsDocumentError};
+
+/// An error that can occur when reading messages from a mailbox.
+#[derive(Debug, thiserror::Error)]
+pub enum MailboxError {
+    /// An error reading the mailbox
+    #[error("Failed to read mailbox: {0}")]
+    Read(#[from] std::io::Error),
+    /// The path is not a mailbox in the expected format
+    #[error("The path is not a {0} mailbox")]
+    WrongFormat(&'static str),
+}
+
+/// An attachment of an [`Email`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailAttachment {
+    /// The file name of the attachment.
+    pub name: Option<String>,
+    /// The mime type of the attachment.
+    pub content_type: Option<String>,
+    /// The text extracted from the attachment if it is a text, html or pdf file.
+    pub text: Option<String>,
+}
+
+/// A single email message. When it is converted into a [`Document`], the `thread_id`, `message_id`, `sender`,
+/// `recipients` and `date` (RFC 3339) are stored in the [`Document::metadata`] of the document.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// let email = Email::from_bytes(&std::fs::read("./message.eml").unwrap()).unwrap();
+/// println!("{} from {:?}", email.subject, email.from);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    /// The subject of the email.
+    pub subject: String,
+    /// The sender of the email.
+    pub from: Option<String>,
+    /// The recipients of the email including any cc recipients.
+    pub to: Vec<String>,
+    /// The time the email was sent.
+    pub date: Option<DateTime<Utc>>,
+    /// The message id of the email.
+    pub message_id: Option<String>,
+    /// The message id of the first message in the thread this email belongs to.
+    pub thread_id: Option<String>,
+    /// The text of the email.
+    pub body: String,
+    /// The attachments of the email.
+    pub attachments: Vec<EmailAttachment>,
+}
+
+fn format_address(addr: &mail_parser::Addr) -> Option<String> {
+    match (addr.name(), addr.address()) {
+        (Some(name), Some(address)) => Some(format!("{name} <{address}>")),
+        (None, Some(address)) => Some(address.to_string()),
+        (Some(name), None) => Some(name.to_string()),
+        (None, None) => None,
+    }
+}
+
+fn message_ids(header: &str) -> impl Iterator<Item = &str> {
+    header
+        .split('<')
+        .skip(1)
+        .filter_map(|id| id.split_once('>').map(|(id, _)| id.trim()))
+}
+
+fn attachment_text(part: &mail_parser::MessagePart) -> Option<String> {
+    let content_type = part.content_type()?;
+    match (content_type.ctype(), content_type.subtype()) {
+        ("text", Some("html")) => {
+            let html = part.text_contents()?;
+            extract_article(html)
+                .ok()
+                .map(|document| document.body().to_string())
+        }
+        ("text", _) => part.text_contents().map(|text| text.to_string()),
+        ("application", Some("pdf")) => {
+            let pdf = lopdf::Document::load_mem(part.contents()).ok()?;
+            let text = super::get_pdf_text(&pdf).ok()?;
+            Some(text.into_text())
+        }
+        _ => None,
+    }
+}
+
+impl Email {
+    /// Parse an email from the raw RFC 5322 message. Returns `None` if the bytes are not a valid message.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let message = MessageParser::default().parse(bytes)?;
+
+        let from = message
+            .from()
+            .and_then(|from| from.first())
+            .and_then(format_address);
+        let to = message
+            .to()
+            .into_iter()
+            .chain(message.cc())
+            .flat_map(|addresses| addresses.iter())
+            .filter_map(format_address)
+            .collect();
+        let date = message
+            .date()
+            .and_then(|date| DateTime::from_timestamp(date.to_timestamp(), 0));
+        let message_id = message.message_id().map(|id| id.to_string());
+        // The first message in the references header is the root of the thread
+        let thread_id = message
+            .header_raw("References")
+            .and_then(|references| message_ids(references).next())
+            .or_else(|| {
+                message
+                    .header_raw("In-Reply-To")
+                    .and_then(|in_reply_to| message_ids(in_reply_to).next())
+            })
+            .map(|id| id.to_string())
+            .or_else(|| message_id.clone());
+
+        let attachments = message
+            .attachments()
+            .map(|part| EmailAttachment {
+                name: part.attachment_name().map(|name| name.to_string()),
+                content_type: part.content_type().map(|content_type| {
+                    match content_type.subtype() {
+                        Some(subtype) => format!("{}/{}", content_type.ctype(), subtype),
+                        None => content_type.ctype().to_string(),
+                    }
+                }),
+                text: attachment_text(part),
+            })
+            .collect();
+
+        Some(Self {
+            subject: message.subject().unwrap_or_default().to_string(),
+            from,
+            to,
+            date,
+            message_id,
+            thread_id,
+            body: message
+                .body_text(0)
+                .map(|body| body.to_string())
+                .unwrap_or_default(),
+            attachments,
+        })
+    }
+}
+
+impl IntoDocument for Email {
+    type Error = std::convert::Infallible;
+
+    async fn into_document(self) -> Result<Document, Self::Error> {
+        let mut body = String::new();
+        if let Some(from) = &self.from {
+            body += &format!("From: {from}\n");
+        }
+        if !self.to.is_empty() {
+            body += &format!("To: {}\n", self.to.join(", "));
+        }
+        if let Some(date) = &self.date {
+            body += &format!("Date: {}\n", date.to_rfc2822());
+        }
+        body.push('\n');
+        body += self.body.trim();
+        for attachment in &self.attachments {
+            if let Some(text) = &attachment.text {
+                let name = attachment.name.as_deref().unwrap_or("attachment");
+                body += &format!("\n\nAttachment {name}:\n{}", text.trim());
+            }
+        }
+
+        let mut document = Document::from_parts(self.subject, body);
+        if let Some(date) = self.date {
+            document.set_created_at(date);
+            document.set_metadata("date", date.to_rfc3339());
+        }
+        if let Some(thread_id) = self.thread_id {
+            document.set_metadata("thread_id", thread_id);
+        }
+        if let Some(message_id) = self.message_id {
+            document.set_metadata("message_id", message_id);
+        }
+        if let Some(from) = self.from {
+            document.set_metadata("sender", from);
+        }
+        document.set_metadata("recipients", self.to);
+        Ok(document)
+    }
+}
+
+/// Split an mbox file into the raw messages it contains.
+fn split_mbox(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    let mut previous_blank = true;
+    for line in bytes.split_inclusive(|&b| b == b'\n') {
+        if previous_blank && line.starts_with(b"From ") {
+            messages.extend(current.take());
+            current = Some(Vec::new());
+            previous_blank = false;
+            continue;
+        }
+        previous_blank = line.trim_ascii().is_empty();
+        if let Some(current) = &mut current {
+            // Lines that start with "From " are escaped with a ">" in the body of the message
+            if is_escaped_from_line(line) {
+                current.extend_from_slice(&line[1..]);
+            } else {
+                current.extend_from_slice(line);
+            }
+        }
+    }
+    messages.extend(current);
+    messages
+}
+
+fn is_escaped_from_line(line: &[u8]) -> bool {
+    match line.iter().position(|&b| b != b'>') {
+        Some(start) if start > 0 => line[start..].starts_with(b"From "),
+        _ => false,
+    }
+}
+
+/// An mbox file that contains many email messages. Each message is converted into a [`Document`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mbox = Mbox::new("./inbox.mbox").unwrap();
+///     for email in mbox.emails().await.unwrap() {
+///         println!("{} ({:?})", email.subject, email.thread_id);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Mbox {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for Mbox {
+    type Error = FsDocumentError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        }
+        Ok(Self { path })
+    }
+}
+
+impl Mbox {
+    /// Try to create a new mbox source from a path.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, FsDocumentError> {
+        Self::try_from(path.into())
+    }
+
+    /// Read and parse every message in the mbox file. Messages that cannot be parsed are skipped.
+    pub async fn emails(&self) -> Result<Vec<Email>, MailboxError> {
+        let bytes = tokio::fs::read(&self.path).await?;
+        let messages = split_mbox(&bytes);
+        if messages.is_empty() && !bytes.is_empty() {
+            return Err(MailboxError::WrongFormat("mbox"));
+        }
+        Ok(messages
+            .iter()
+            .filter_map(|message| Email::from_bytes(message))
+            .collect())
+    }
+}
+
+impl IntoDocuments for Mbox {
+    type Error = MailboxError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let emails = self.emails().await?;
+        emails.into_documents().await.map_err(|err| match err {})
+    }
+}
+
+/// A Maildir folder. Every message in the `cur` and `new` sub folders is converted into a [`Document`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let maildir = Maildir::new("./Mail/INBOX").unwrap();
+///     let documents = maildir.into_documents().await.unwrap();
+///     println!("{} messages", documents.len());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Maildir {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for Maildir {
+    type Error = MailboxError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.join("cur").is_dir() && !path.join("new").is_dir() {
+            return Err(MailboxError::WrongFormat("Maildir"));
+        }
+        Ok(Self { path })
+    }
+}
+
+impl Maildir {
+    /// Try to create a new Maildir source from a path.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, MailboxError> {
+        Self::try_from(path.into())
+    }
+
+    /// Read and parse every message in the Maildir in the order they were delivered. Messages that cannot be parsed
+    /// are skipped.
+    pub async fn emails(&self) -> Result<Vec<Email>, MailboxError> {
+        let mut files = Vec::new();
+        for folder in ["cur", "new"] {
+            let folder = self.path.join(folder);
+            if !folder.is_dir() {
+                continue;
+            }
+            let mut read_dir = tokio::fs::read_dir(folder).await?;
+            while let Some(entry) = read_dir.next_entry().await? {
+                if entry.file_type().await?.is_file() {
+                    files.push(entry.path());
+                }
+            }
+        }
+        // Maildir file names start with the delivery time, so sorting by name keeps the order the messages arrived in
+        // instead of the order the file system lists them in
+        files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        let mut emails = Vec::new();
+        for file in files {
+            let bytes = tokio::fs::read(file).await?;
+            emails.extend(Email::from_bytes(&bytes));
+        }
+        Ok(emails)
+    }
+}
+
+impl IntoDocuments for Maildir {
+    type Error = MailboxError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let emails = self.emails().await?;
+        emails.into_documents().await.map_err(|err| match err {})
+    }
+}
+
+#[test]
+fn splits_mbox_messages() {
+    let mbox = b"From alice@example.com Mon Jan  1 00:00:00 2024\nSubject: Hello\n\nFirst\n>From the start\n\nFrom bob@example.com Mon Jan  1 00:00:00 2024\nSubject: Re: Hello\n\nSecond\n";
+    let messages = split_mbox(mbox);
+    assert_eq!(messages.len(), 2);
+    assert_eq!(
+        String::from_utf8_lossy(&messages[0]),
+        "Subject: Hello\n\nFirst\nFrom the start\n\n"
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&messages[1]),
+        "Subject: Re: Hello\n\nSecond\n"
+    );
+}
+
+#[test]
+fn parses_email_threads() {
+    let message = b"From: Alice <alice@example.com>\r\nTo: bob@example.com\r\nCc: Carol <carol@example.com>\r\nSubject: Re: Lunch\r\nDate: Mon, 1 Jan 2024 12:00:00 +0000\r\nMessage-ID: <2@example.com>\r\nIn-Reply-To: <1@example.com>\r\nReferences: <1@example.com>\r\n\r\nSounds good!\r\n";
+    let email = Email::from_bytes(message).unwrap();
+    assert_eq!(email.subject, "Re: Lunch");
+    assert_eq!(email.from.as_deref(), Some("Alice <alice@example.com>"));
+    assert_eq!(
+        email.to,
+        vec![
+            "bob@example.com".to_string(),
+            "Carol <carol@example.com>".to_string()
+        ]
+    );
+    assert_eq!(email.message_id.as_deref(), Some("2@example.com"));
+    assert_eq!(email.thread_id.as_deref(), Some("1@example.com"));
+    assert_eq!(email.body.trim(), "Sounds good!");
+}
+
+#[tokio::test]
+async fn emails_store_headers_as_document_metadata() {
+    let message = b"From: Alice <alice@example.com>\r\nTo: bob@example.com\r\nSubject: Re: Lunch\r\nDate: Mon, 1 Jan 2024 12:00:00 +0000\r\nMessage-ID: <2@example.com>\r\nIn-Reply-To: <1@example.com>\r\nReferences: <1@example.com>\r\n\r\nSounds good!\r\n";
+    let document = Email::from_bytes(message)
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(
+        document.metadata("thread_id"),
+        Some(&serde_json::json!("1@example.com"))
+    );
+    assert_eq!(
+        document.metadata("message_id"),
+        Some(&serde_json::json!("2@example.com"))
+    );
+    assert_eq!(
+        document.metadata("sender"),
+        Some(&serde_json::json!("Alice <alice@example.com>"))
+    );
+    assert_eq!(
+        document.metadata("recipients"),
+        Some(&serde_json::json!(["bob@example.com"]))
+    );
+    assert_eq!(
+        document.metadata("date"),
+        Some(&serde_json::json!("2024-01-01T12:00:00+00:00"))
+    );
+}
+
+#[tokio::test]
+async fn maildir_messages_are_read_in_delivery_order() {
+    let path = std::env::temp_dir().join(format!("kalosm-maildir-{}", std::process::id()));
+    for (folder, name, subject) in [
+        ("cur", "1704110400.M2P1.host:2,S", "Second"),
+        ("new", "1704106800.M1P1.host", "First"),
+        ("cur", "1704114000.M3P1.host:2,S", "Third"),
+    ] {
+        let folder = path.join(folder);
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(
+            folder.join(name),
+            format!("Subject: {subject}\r\n\r\nBody\r\n"),
+        )
+        .unwrap();
+    }
+    let emails = Maildir::new(&path).unwrap().emails().await;
+    std::fs::remove_dir_all(&path).unwrap();
+    let subjects: Vec<_> = emails
+        .unwrap()
+        .into_iter()
+        .map(|email| email.subject)
+        .collect();
+    assert_eq!(subjects, ["First", "Second", "Third"]);
+}
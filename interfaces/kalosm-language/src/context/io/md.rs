@@ -45,3 +45,22 @@ impl IntoDocument for MdDocument {
         extract_article(&html_output).map_err(FsDocumentError::Decode)
     }
 }
+
+/// Convert markdown into plain text, keeping paragraph and line breaks.
+pub(crate) fn markdown_to_text(md: &str) -> String {
+    use pulldown_cmark::{Event, Tag};
+
+    let mut text = String::new();
+    for event in pulldown_cmark::Parser::new(md) {
+        match event {
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::CodeBlock(_)) => {
+                text.push_str("\n\n")
+            }
+            Event::End(Tag::Item) => text.push('\n'),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
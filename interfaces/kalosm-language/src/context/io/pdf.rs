@@ -51,13 +51,7 @@ impl IntoDocument for PdfDocument {
             );
         }
 
-        let mut all_text = String::new();
-        for paragraph in text.text.values().flatten() {
-            all_text.push_str(paragraph);
-            all_text.push('\n');
-        }
-
-        Ok(Document::from_parts(title, all_text))
+        Ok(Document::from_parts(title, text.into_text()))
     }
 }
 
@@ -87,11 +81,22 @@ static IGNORE: &[&[u8]] = &[
 ];
 
 #[derive(Debug, Deserialize, Serialize)]
-struct PdfText {
+pub(crate) struct PdfText {
     text: BTreeMap<u32, Vec<String>>, // Key is page number
     errors: Vec<String>,
 }
 
+impl PdfText {
+    pub(crate) fn into_text(self) -> String {
+        let mut all_text = String::new();
+        for paragraph in self.text.values().flatten() {
+            all_text.push_str(paragraph);
+            all_text.push('\n');
+        }
+        all_text
+    }
+}
+
 fn filter_func(object_id: (u32, u16), object: &mut Object) -> Option<((u32, u16), Object)> {
     if IGNORE.contains(&object.type_name().unwrap_or_default()) {
         return None;
@@ -118,7 +123,7 @@ async fn load_pdf<P: AsRef<Path>>(path: P) -> Result<PdfDoc, Error> {
         .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))
 }
 
-fn get_pdf_text(doc: &PdfDoc) -> Result<PdfText, Error> {
+pub(crate) fn get_pdf_text(doc: &PdfDoc) -> Result<PdfText, Error> {
     let mut pdf_text: PdfText = PdfText {
         text: BTreeMap::new(),
         errors: Vec::new(),
@@ -1,9 +1,17 @@
 //! Context for language models to consume.
 
+mod chat_export;
+pub use chat_export::*;
 mod document;
 pub use document::*;
+mod email;
+pub use email::*;
 mod io;
 pub use io::*;
+mod notes;
+pub use notes::*;
+mod prompt_injection;
+pub use prompt_injection::*;
 #[cfg(feature = "scrape")]
 mod page;
 #[cfg(feature = "scrape")]
@@ -12,5 +20,7 @@ mod rss;
 pub use self::rss::*;
 mod search;
 pub use search::*;
+mod source;
+pub use source::*;
 
 pub use url::Url;
@@ -0,0 +1,52 @@
+//! Sources for personal knowledge bases that are stored as folders of markdown files or read from the notion API.
+
+use std::path::{Path, PathBuf};
+
+mod notion;
+pub use notion::*;
+mod notion_api;
+pub use notion_api::*;
+mod obsidian;
+pub use obsidian::*;
+
+/// An error that can occur when reading notes from a knowledge base.
+#[derive(Debug, thiserror::Error)]
+pub enum NotesError {
+    /// An error reading the notes
+    #[error("Failed to read notes: {0}")]
+    Read(#[from] std::io::Error),
+    /// The path to the knowledge base was not a directory
+    #[error("The path to the knowledge base was not a directory")]
+    NotDirectory,
+    /// An error requesting pages from the notion API
+    #[error("Failed to request notes: {0}")]
+    Request(#[from] reqwest::Error),
+}
+
+/// Find every markdown file in a folder, skipping hidden folders like `.obsidian` or `.git`.
+async fn markdown_files(root: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files = Vec::new();
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let mut read_dir = tokio::fs::read_dir(&folder).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if hidden {
+                continue;
+            }
+            if entry.file_type().await?.is_dir() {
+                folders.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "md") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+async fn modified_at(path: &Path) -> Option<chrono::DateTime<chrono::Utc>> {
+    let metadata = tokio::fs::metadata(path).await.ok()?;
+    metadata.modified().ok().map(Into::into)
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use super::{markdown_files, modified_at, NotesError};
+use crate::context::document::{Document, IntoDocument, IntoDocuments};
+use crate::context::markdown_to_text;
+
+/// A page from a [`NotionExport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct NotionPage {
+    /// The path of the page relative to the root of the export. This is empty for pages read with the notion API.
+    pub path: PathBuf,
+    /// The id notion appends to the name of every exported file.
+    pub id: Option<String>,
+    /// The title of the page.
+    pub title: String,
+    /// The properties of the page. Pages in a database export their properties as `Key: Value` lines under the title.
+    pub properties: BTreeMap<String, String>,
+    /// The markdown content of the page without the title and properties.
+    pub content: String,
+    /// The titles of the pages this page links to.
+    pub links: Vec<String>,
+    /// The time the page was exported or last edited.
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Split the 32 character hex id notion appends to exported file names from the name.
+fn split_notion_id(name: &str) -> (&str, Option<&str>) {
+    if let Some((title, id)) = name.rsplit_once(' ') {
+        if id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return (title, Some(id));
+        }
+    }
+    (name, None)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Find the links to other exported pages in the markdown
+fn page_links(content: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("](") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find(')') else {
+            break;
+        };
+        let target = &rest[..end];
+        rest = &rest[end + 1..];
+        if target.contains("://") || !target.ends_with(".md") {
+            continue;
+        }
+        let target = percent_decode(target);
+        let file_name = Path::new(&target)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (title, _) = split_notion_id(&file_name);
+        let title = title.to_string();
+        if !title.is_empty() && !links.contains(&title) {
+            links.push(title);
+        }
+    }
+    links
+}
+
+impl NotionPage {
+    /// Parse a page from the markdown notion exported.
+    pub fn parse(path: impl Into<PathBuf>, source: &str) -> Self {
+        let path = path.into();
+        let file_name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (file_title, id) = split_notion_id(&file_name);
+        let id = id.map(|id| id.to_string());
+
+        // The first heading is the title of the page
+        let mut lines = source.lines().peekable();
+        let mut title = file_title.to_string();
+        while let Some(line) = lines.peek() {
+            if line.trim().is_empty() {
+                lines.next();
+                continue;
+            }
+            if let Some(heading) = line.strip_prefix("# ") {
+                title = heading.trim().to_string();
+                lines.next();
+            }
+            break;
+        }
+
+        // Database pages list their properties right after the title
+        let mut properties = BTreeMap::new();
+        while lines.peek().is_some_and(|line| line.trim().is_empty()) {
+            lines.next();
+        }
+        while let Some(line) = lines.peek() {
+            let Some((key, value)) = line.split_once(": ") else {
+                break;
+            };
+            if key.is_empty() || key.starts_with(['#', '-', '*', '>', '|']) || key.len() > 64 {
+                break;
+            }
+            properties.insert(key.trim().to_string(), value.trim().to_string());
+            lines.next();
+        }
+
+        let content = lines.collect::<Vec<_>>().join("\n");
+        let links = page_links(&content);
+
+        Self {
+            path,
+            id,
+            title,
+            properties,
+            content,
+            links,
+            updated_at: None,
+        }
+    }
+
+    /// Get the text of the page.
+    pub fn text(&self) -> String {
+        markdown_to_text(&self.content)
+    }
+}
+
+impl IntoDocument for NotionPage {
+    type Error = std::convert::Infallible;
+
+    async fn into_document(self) -> Result<Document, Self::Error> {
+        let mut document = Document::from_parts(&self.title, self.text());
+        if let Some(updated_at) = self.updated_at {
+            document.set_updated_at(updated_at);
+        }
+        if !self.path.as_os_str().is_empty() {
+            document.set_metadata("path", self.path.to_string_lossy().to_string());
+        }
+        if let Some(id) = self.id {
+            document.set_metadata("notion_id", id);
+        }
+        document.set_metadata("links", self.links);
+        document.set_metadata("properties", serde_json::json!(self.properties));
+        Ok(document)
+    }
+}
+
+/// An unzipped [Notion](https://notion.so) workspace export in the "Markdown & CSV" format. Every page is
+/// converted into a [`Document`] with the notion ids stripped from the titles and links. The path, notion id, links
+/// and properties of each page are stored as the `path`, `notion_id`, `links` and `properties`
+/// [metadata](Document::metadata) of its document.
+///
+/// To read the pages of a workspace with an integration token instead of an export, use [`NotionWorkspace`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let export = NotionExport::new("./notion-export").unwrap();
+///     for page in export.pages().await.unwrap() {
+///         println!("{} links to {:?}", page.title, page.links);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NotionExport {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for NotionExport {
+    type Error = NotesError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_dir() {
+            return Err(NotesError::NotDirectory);
+        }
+        Ok(Self { path })
+    }
+}
+
+impl NotionExport {
+    /// Try to create a new notion export source from the path to the unzipped export.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, NotesError> {
+        Self::try_from(path.into())
+    }
+
+    /// Read every page in the export.
+    pub async fn pages(&self) -> Result<Vec<NotionPage>, NotesError> {
+        let mut pages = Vec::new();
+        for path in markdown_files(&self.path).await? {
+            let source = tokio::fs::read_to_string(&path).await?;
+            let relative = path.strip_prefix(&self.path).unwrap_or(&path);
+            let mut page = NotionPage::parse(relative, &source);
+            page.updated_at = modified_at(&path).await;
+            pages.push(page);
+        }
+        Ok(pages)
+    }
+}
+
+impl IntoDocuments for NotionExport {
+    type Error = NotesError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let pages = self.pages().await?;
+        pages.into_documents().await.map_err(|err| match err {})
+    }
+}
+
+#[test]
+fn parses_notion_page() {
+    let source = "# Launch plan\n\nStatus: In progress\nOwner: Evan\n\nSee the [roadmap](Roadmap%2001234567890abcdef01234567890abcd.md) and [docs](https://docs.rs).\n";
+    let page = NotionPage::parse("Launch plan 0123456789abcdef0123456789abcdef.md", source);
+    assert_eq!(page.title, "Launch plan");
+    assert_eq!(page.id.as_deref(), Some("0123456789abcdef0123456789abcdef"));
+    assert_eq!(page.properties["Status"], "In progress");
+    assert_eq!(page.properties["Owner"], "Evan");
+    assert_eq!(page.links, vec!["Roadmap"]);
+    assert_eq!(page.text(), "See the roadmap and docs.");
+}
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+
+use kalosm_language_model::{RetryPolicy, RetryReason};
+use reqwest::header::RETRY_AFTER;
+use serde_json::{json, Value};
+
+use super::{NotesError, NotionPage};
+use crate::context::document::{Document, IntoDocuments};
+
+const NOTION_API: &str = "https://api.notion.com/v1";
+const NOTION_VERSION: &str = "2022-06-28";
+/// Nested blocks are only read this deep so deeply nested pages don't need an unbounded number of requests.
+const MAX_BLOCK_DEPTH: usize = 8;
+
+/// The pages a [Notion](https://notion.so) integration can access, read with the notion API. Every page is
+/// converted into a [`Document`] the same way as the pages of a [`NotionExport`](super::NotionExport).
+///
+/// Create an internal integration in the notion settings and share the pages you want to read with it. The
+/// integration only needs the "Read content" capability.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let workspace = NotionWorkspace::new(std::env::var("NOTION_TOKEN").unwrap());
+///     for page in workspace.pages().await.unwrap() {
+///         println!("{} links to {:?}", page.title, page.links);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NotionWorkspace {
+    token: String,
+    client: reqwest::Client,
+    retry_policy: RetryPolicy,
+}
+
+impl NotionWorkspace {
+    /// Create a new notion workspace source from the token of an integration.
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            client: reqwest::Client::new(),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Set the policy for retrying requests that fail with transient errors or are rate limited. (Defaults to
+    /// [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Send a request to the notion API. Failed requests are retried with the retry policy. If notion sends a
+    /// `Retry-After` header, the retry waits for the time notion asks for instead of the backoff.
+    async fn request(
+        &self,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<Value, NotesError> {
+        let mut attempt = 1;
+        loop {
+            let response = request()
+                .bearer_auth(&self.token)
+                .header("Notion-Version", NOTION_VERSION)
+                .send()
+                .await;
+            let (err, retry_after) = match response {
+                Ok(response) => {
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse().ok())
+                        .map(Duration::from_secs);
+                    match response.error_for_status() {
+                        Ok(response) => return Ok(response.json().await?),
+                        Err(err) => (err, retry_after),
+                    }
+                }
+                Err(err) => (err, None),
+            };
+            let retry = attempt < self.retry_policy.max_attempts()
+                && RetryReason::from_reqwest_error(&err)
+                    .is_some_and(|reason| self.retry_policy.should_retry(reason));
+            if !retry {
+                return Err(err.into());
+            }
+            let delay = retry_after.unwrap_or_else(|| self.retry_policy.backoff(attempt - 1));
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Read every page the integration can access.
+    pub async fn pages(&self) -> Result<Vec<NotionPage>, NotesError> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut body = json!({
+                "filter": { "property": "object", "value": "page" },
+                "page_size": 100,
+            });
+            if let Some(cursor) = cursor.take() {
+                body["start_cursor"] = Value::String(cursor);
+            }
+            let response = self
+                .request(|| self.client.post(format!("{NOTION_API}/search")).json(&body))
+                .await?;
+            for page in results(&response) {
+                pages.push(self.page(page).await?);
+            }
+            cursor = next_cursor(&response);
+            if cursor.is_none() {
+                break;
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Read the content of a page from the search results.
+    async fn page(&self, page: &Value) -> Result<NotionPage, NotesError> {
+        let id = page["id"].as_str().unwrap_or_default();
+        let (title, properties) = page_properties(page);
+        let mut content = String::new();
+        let mut links = Vec::new();
+        self.read_blocks(id, 0, &mut content, &mut links).await?;
+        Ok(NotionPage {
+            path: PathBuf::new(),
+            id: Some(id.replace('-', "")),
+            title,
+            properties,
+            content: content.trim_end().to_string(),
+            links,
+            updated_at: page["last_edited_time"]
+                .as_str()
+                .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+                .map(Into::into),
+        })
+    }
+
+    /// Read the blocks inside a page or block as markdown.
+    fn read_blocks<'a>(
+        &'a self,
+        id: &'a str,
+        depth: usize,
+        content: &'a mut String,
+        links: &'a mut Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), NotesError>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut cursor = None;
+            loop {
+                let url = format!("{NOTION_API}/blocks/{id}/children");
+                let mut query = vec![("page_size", "100".to_string())];
+                if let Some(cursor) = cursor.take() {
+                    query.push(("start_cursor", cursor));
+                }
+                let response = self.request(|| self.client.get(&url).query(&query)).await?;
+                for block in results(&response) {
+                    if let Some(markdown) = block_markdown(block, depth, links) {
+                        content.push_str(&markdown);
+                        content.push_str("\n\n");
+                    }
+                    let has_children = block["has_children"].as_bool().unwrap_or_default();
+                    // Child pages are separate pages in the search results
+                    let child_page = block["type"] == "child_page";
+                    if has_children && !child_page && depth + 1 < MAX_BLOCK_DEPTH {
+                        if let Some(child) = block["id"].as_str() {
+                            self.read_blocks(child, depth + 1, content, links).await?;
+                        }
+                    }
+                }
+                cursor = next_cursor(&response);
+                if cursor.is_none() {
+                    return Ok(());
+                }
+            }
+        })
+    }
+}
+
+impl IntoDocuments for NotionWorkspace {
+    type Error = NotesError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let pages = self.pages().await?;
+        pages.into_documents().await.map_err(|err| match err {})
+    }
+}
+
+fn results(response: &Value) -> impl Iterator<Item = &Value> {
+    response["results"].as_array().into_iter().flatten()
+}
+
+fn next_cursor(response: &Value) -> Option<String> {
+    if response["has_more"].as_bool() != Some(true) {
+        return None;
+    }
+    response["next_cursor"].as_str().map(ToString::to_string)
+}
+
+/// Get the plain text of a notion rich text array. Mentions of other pages are added to `links`.
+fn rich_text(value: &Value, links: &mut Vec<String>) -> String {
+    let mut text = String::new();
+    for part in value.as_array().into_iter().flatten() {
+        let plain_text = part["plain_text"].as_str().unwrap_or_default();
+        if part["type"] == "mention"
+            && part["mention"]["type"] == "page"
+            && !plain_text.is_empty()
+            && !links.iter().any(|link| link == plain_text)
+        {
+            links.push(plain_text.to_string());
+        }
+        text.push_str(plain_text);
+    }
+    text
+}
+
+/// Get the title and the text of the other properties of a page.
+fn page_properties(page: &Value) -> (String, BTreeMap<String, String>) {
+    let mut title = String::new();
+    let mut properties = BTreeMap::new();
+    for (key, property) in page["properties"].as_object().into_iter().flatten() {
+        if property["type"] == "title" {
+            title = rich_text(&property["title"], &mut Vec::new());
+        } else if let Some(value) = property_text(property) {
+            if !value.is_empty() {
+                properties.insert(key.clone(), value);
+            }
+        }
+    }
+    (title, properties)
+}
+
+/// Get the text of a page property. Returns `None` for property types that don't have a text value.
+fn property_text(property: &Value) -> Option<String> {
+    let kind = property["type"].as_str()?;
+    let value = &property[kind];
+    let names = |value: &Value| {
+        value
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item["name"].as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    Some(match kind {
+        "rich_text" => rich_text(value, &mut Vec::new()),
+        "select" | "status" => value["name"].as_str()?.to_string(),
+        "multi_select" | "people" => names(value),
+        "number" => value.as_number()?.to_string(),
+        "checkbox" => value.as_bool()?.to_string(),
+        "url" | "email" | "phone_number" => value.as_str()?.to_string(),
+        "date" => {
+            let start = value["start"].as_str()?;
+            match value["end"].as_str() {
+                Some(end) => format!("{start} - {end}"),
+                None => start.to_string(),
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// Convert a notion block into markdown. Returns `None` for blocks without text. Titles of child pages are added to
+/// `links`.
+fn block_markdown(block: &Value, depth: usize, links: &mut Vec<String>) -> Option<String> {
+    let kind = block["type"].as_str()?;
+    let data = &block[kind];
+    let text = rich_text(&data["rich_text"], links);
+    let markdown = match kind {
+        "paragraph" if !text.is_empty() => text,
+        "heading_1" => format!("# {text}"),
+        "heading_2" => format!("## {text}"),
+        "heading_3" => format!("### {text}"),
+        "bulleted_list_item" | "toggle" => format!("- {text}"),
+        "numbered_list_item" => format!("1. {text}"),
+        "to_do" => {
+            let checked = if data["checked"] == true { "x" } else { " " };
+            format!("- [{checked}] {text}")
+        }
+        "quote" | "callout" => format!("> {text}"),
+        "code" => {
+            let language = data["language"].as_str().unwrap_or_default();
+            format!("```{language}\n{text}\n```")
+        }
+        "equation" => data["expression"].as_str()?.to_string(),
+        "child_page" => {
+            let title = data["title"].as_str()?.to_string();
+            if !links.contains(&title) {
+                links.push(title.clone());
+            }
+            title
+        }
+        _ => return None,
+    };
+    Some(format!("{}{markdown}", "  ".repeat(depth)))
+}
+
+#[test]
+fn converts_notion_api_pages() {
+    let page = json!({
+        "id": "01234567-89ab-cdef-0123-456789abcdef",
+        "properties": {
+            "Name": { "type": "title", "title": [{ "type": "text", "plain_text": "Launch plan" }] },
+            "Status": { "type": "status", "status": { "name": "In progress" } },
+            "Tags": { "type": "multi_select", "multi_select": [{ "name": "rust" }, { "name": "ai" }] },
+            "Estimate": { "type": "number", "number": 3 },
+            "Notes": { "type": "rich_text", "rich_text": [] },
+            "Files": { "type": "files", "files": [] },
+        }
+    });
+    let (title, properties) = page_properties(&page);
+    assert_eq!(title, "Launch plan");
+    assert_eq!(properties["Status"], "In progress");
+    assert_eq!(properties["Tags"], "rust, ai");
+    assert_eq!(properties["Estimate"], "3");
+    assert!(!properties.contains_key("Notes"));
+    assert!(!properties.contains_key("Files"));
+
+    let mut links = Vec::new();
+    let paragraph = json!({
+        "type": "paragraph",
+        "paragraph": { "rich_text": [
+            { "type": "text", "plain_text": "See the " },
+            { "type": "mention", "mention": { "type": "page" }, "plain_text": "Roadmap" },
+        ] }
+    });
+    assert_eq!(
+        block_markdown(&paragraph, 0, &mut links).as_deref(),
+        Some("See the Roadmap")
+    );
+    let to_do = json!({
+        "type": "to_do",
+        "to_do": { "checked": true, "rich_text": [{ "type": "text", "plain_text": "Ship it" }] }
+    });
+    assert_eq!(
+        block_markdown(&to_do, 1, &mut links).as_deref(),
+        Some("  - [x] Ship it")
+    );
+    let child_page = json!({ "type": "child_page", "child_page": { "title": "Retro" } });
+    assert_eq!(
+        block_markdown(&child_page, 0, &mut links).as_deref(),
+        Some("Retro")
+    );
+    let divider = json!({ "type": "divider", "divider": {} });
+    assert_eq!(block_markdown(&divider, 0, &mut links), None);
+    assert_eq!(links, vec!["Roadmap", "Retro"]);
+}
+
+#[tokio::test]
+async fn rate_limited_requests_stop_after_the_retry_policy_runs_out() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1/search", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    tokio::spawn({
+        let requests = requests.clone();
+        async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                requests.fetch_add(1, Ordering::SeqCst);
+                let _ = stream.read(&mut [0; 4096]).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                    .await;
+            }
+        }
+    });
+
+    let workspace = NotionWorkspace::new("token")
+        .with_retry_policy(RetryPolicy::default().with_max_attempts(3));
+    let result = workspace.request(|| workspace.client.post(&url)).await;
+    let Err(NotesError::Request(err)) = result else {
+        panic!("expected the request to fail, got {result:?}");
+    };
+    assert_eq!(err.status().map(|status| status.as_u16()), Some(429));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,333 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use super::{markdown_files, modified_at, NotesError};
+use crate::context::document::{Document, IntoDocument, IntoDocuments};
+use crate::context::markdown_to_text;
+
+/// A note in an [`ObsidianVault`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObsidianNote {
+    /// The path of the note relative to the root of the vault.
+    pub path: PathBuf,
+    /// The title of the note. This is the file name without the extension.
+    pub title: String,
+    /// The markdown content of the note without the frontmatter.
+    pub content: String,
+    /// The properties from the yaml frontmatter of the note.
+    pub properties: BTreeMap<String, Vec<String>>,
+    /// The tags of the note from the frontmatter and inline `#tags`.
+    pub tags: Vec<String>,
+    /// The notes this note links to with `[[wikilinks]]` or embeds.
+    pub links: Vec<String>,
+    /// The time the note was last modified.
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ObsidianNote {
+    /// Parse a note from the markdown source.
+    pub fn parse(path: impl Into<PathBuf>, source: &str) -> Self {
+        let path = path.into();
+        let title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let (properties, content) = split_frontmatter(source);
+        let mut tags = properties.get("tags").cloned().unwrap_or_default();
+        for tag in inline_tags(content) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let mut links = Vec::new();
+        for link in wikilinks(content) {
+            if !links.contains(&link.target) {
+                links.push(link.target);
+            }
+        }
+        Self {
+            path,
+            title,
+            content: content.to_string(),
+            properties,
+            tags,
+            links,
+            updated_at: None,
+        }
+    }
+
+    /// Get the text of the note with wikilinks replaced by their display text.
+    pub fn text(&self) -> String {
+        let mut content = String::new();
+        let mut rest = self.content.as_str();
+        while let Some((before, link, after)) = next_wikilink(rest) {
+            content.push_str(before.strip_suffix('!').unwrap_or(before));
+            content.push_str(link.display());
+            rest = after;
+        }
+        content.push_str(rest);
+        markdown_to_text(&content)
+    }
+}
+
+impl IntoDocument for ObsidianNote {
+    type Error = std::convert::Infallible;
+
+    async fn into_document(self) -> Result<Document, Self::Error> {
+        let mut document = Document::from_parts(&self.title, self.text());
+        if let Some(updated_at) = self.updated_at {
+            document.set_updated_at(updated_at);
+        }
+        document.set_metadata("path", self.path.to_string_lossy().to_string());
+        document.set_metadata("tags", self.tags);
+        document.set_metadata("links", self.links);
+        document.set_metadata("properties", serde_json::json!(self.properties));
+        Ok(document)
+    }
+}
+
+/// An [Obsidian](https://obsidian.md) vault. Every markdown note in the vault is converted into a [`Document`]
+/// and the `[[wikilinks]]` between notes are kept as [`ObsidianNote::links`]. The path, tags, links and frontmatter
+/// properties of each note are stored as the `path`, `tags`, `links` and `properties`
+/// [metadata](Document::metadata) of its document.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language::prelude::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let vault = ObsidianVault::new("./vault").unwrap();
+///     let notes = vault.notes().await.unwrap();
+///     let backlinks = ObsidianVault::backlinks(&notes);
+///     for note in &notes {
+///         println!("{} is linked from {:?}", note.title, backlinks.get(&note.title));
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObsidianVault {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for ObsidianVault {
+    type Error = NotesError;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_dir() {
+            return Err(NotesError::NotDirectory);
+        }
+        Ok(Self { path })
+    }
+}
+
+impl ObsidianVault {
+    /// Try to create a new vault source from the path to the root of the vault.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, NotesError> {
+        Self::try_from(path.into())
+    }
+
+    /// Read every note in the vault.
+    pub async fn notes(&self) -> Result<Vec<ObsidianNote>, NotesError> {
+        let mut notes = Vec::new();
+        for path in markdown_files(&self.path).await? {
+            let source = tokio::fs::read_to_string(&path).await?;
+            let relative = path.strip_prefix(&self.path).unwrap_or(&path);
+            let mut note = ObsidianNote::parse(relative, &source);
+            note.updated_at = modified_at(&path).await;
+            notes.push(note);
+        }
+        Ok(notes)
+    }
+
+    /// Find the notes that link to each note. The map is keyed by the title of the linked note.
+    pub fn backlinks(notes: &[ObsidianNote]) -> HashMap<String, Vec<String>> {
+        let mut backlinks: HashMap<String, Vec<String>> = HashMap::new();
+        for note in notes {
+            for link in &note.links {
+                backlinks
+                    .entry(link.clone())
+                    .or_default()
+                    .push(note.title.clone());
+            }
+        }
+        backlinks
+    }
+}
+
+impl IntoDocuments for ObsidianVault {
+    type Error = NotesError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        let notes = self.notes().await?;
+        notes.into_documents().await.map_err(|err| match err {})
+    }
+}
+
+/// Split the yaml frontmatter from the rest of the note. Only simple `key: value` pairs and lists are supported.
+fn split_frontmatter(source: &str) -> (BTreeMap<String, Vec<String>>, &str) {
+    let mut properties = BTreeMap::new();
+    let Some(rest) = source.strip_prefix("---\n") else {
+        return (properties, source);
+    };
+    let Some(end) = rest.find("\n---") else {
+        return (properties, source);
+    };
+    let frontmatter = &rest[..end];
+    let content = rest[end + 4..].trim_start_matches(['\r', '\n']);
+
+    let mut current_key: Option<String> = None;
+    for line in frontmatter.lines() {
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            if let Some(key) = &current_key {
+                properties
+                    .entry(key.clone())
+                    .or_insert_with(Vec::new)
+                    .push(unquote(item).to_string());
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let key = key.trim().to_string();
+        let value = value.trim();
+        let values = if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            list.split(',')
+                .map(|item| unquote(item.trim()).to_string())
+                .filter(|item| !item.is_empty())
+                .collect()
+        } else if value.is_empty() {
+            Vec::new()
+        } else {
+            vec![unquote(value).to_string()]
+        };
+        properties.insert(key.clone(), values);
+        current_key = Some(key);
+    }
+    if let Some(tags) = properties.get_mut("tags") {
+        for tag in tags.iter_mut() {
+            *tag = tag.trim_start_matches('#').to_string();
+        }
+    }
+
+    (properties, content)
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|c| c == '"' || c == '\'')
+}
+
+struct Wikilink<'a> {
+    target: String,
+    alias: Option<&'a str>,
+    raw_target: &'a str,
+}
+
+impl Wikilink<'_> {
+    fn display(&self) -> &str {
+        self.alias.unwrap_or(self.raw_target)
+    }
+}
+
+/// Find the next `[[wikilink]]` in the text and return the text before it, the link and the text after it.
+fn next_wikilink(text: &str) -> Option<(&str, Wikilink<'_>, &str)> {
+    let start = text.find("[[")?;
+    let end = start + 2 + text[start + 2..].find("]]")?;
+    let inner = &text[start + 2..end];
+    let (raw_target, alias) = match inner.split_once('|') {
+        Some((target, alias)) => (target, Some(alias.trim())),
+        None => (inner, None),
+    };
+    // Links can point to a heading or block in the note
+    let target = raw_target
+        .split(['#', '^'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .trim_end_matches(".md")
+        .to_string();
+    Some((
+        &text[..start],
+        Wikilink {
+            target,
+            alias,
+            raw_target: raw_target.trim(),
+        },
+        &text[end + 2..],
+    ))
+}
+
+fn wikilinks(text: &str) -> Vec<Wikilink<'_>> {
+    let mut links = Vec::new();
+    let mut rest = text;
+    while let Some((_, link, after)) = next_wikilink(rest) {
+        if !link.target.is_empty() {
+            links.push(link);
+        }
+        rest = after;
+    }
+    links
+}
+
+fn inline_tags(text: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut in_code_block = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            continue;
+        }
+        for word in line.split_whitespace() {
+            let Some(tag) = word.strip_prefix('#') else {
+                continue;
+            };
+            let tag = tag
+                .split(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '/')))
+                .next()
+                .unwrap_or_default();
+            // Tags must contain at least one non numeric character
+            if !tag.is_empty() && !tag.chars().all(|c| c.is_ascii_digit()) {
+                let tag = tag.to_string();
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    tags
+}
+
+#[test]
+fn parses_obsidian_note() {
+    let source = "---\naliases: [Kalosm]\ntags:\n  - rust\n  - '#ai'\n---\n# Notes\nKalosm uses [[Candle|the candle framework]] and ![[diagram.png]].\nSee [[Llama#Presets]] #models #2024\n";
+    let note = ObsidianNote::parse("kalosm.md", source);
+    assert_eq!(note.title, "kalosm");
+    assert_eq!(note.properties["aliases"], vec!["Kalosm".to_string()]);
+    assert_eq!(note.tags, vec!["rust", "ai", "models"]);
+    assert_eq!(note.links, vec!["Candle", "diagram.png", "Llama"]);
+    assert_eq!(
+        note.text(),
+        "Notes\n\nKalosm uses the candle framework and diagram.png.\nSee Llama#Presets #models #2024"
+    );
+
+    let document = futures_util::FutureExt::now_or_never(note.into_document())
+        .unwrap()
+        .unwrap();
+    assert!(!document.body().contains("Tags:"));
+    assert_eq!(
+        document.metadata("tags"),
+        Some(&serde_json::json!(["rust", "ai", "models"]))
+    );
+    assert_eq!(
+        document.metadata("links"),
+        Some(&serde_json::json!(["Candle", "diagram.png", "Llama"]))
+    );
+    assert_eq!(
+        document.metadata("properties"),
+        Some(&serde_json::json!({ "aliases": ["Kalosm"], "tags": ["rust", "ai"] }))
+    );
+}
@@ -3,10 +3,12 @@ use std::sync::OnceLock;
 use super::browse::Tab;
 use super::AnyNode;
 use super::{super::document::Document, NodeRef};
+use crate::context::document::fetch_text;
 use crate::context::page::crawl::Crawler;
 pub use crate::context::page::crawl::CrawlingCallback;
 use crate::context::{extract_article, ExtractDocumentError};
 use image::DynamicImage;
+use kalosm_language_model::RetryPolicy;
 use scraper::{Html, Selector};
 use tokio::time::Instant;
 use url::Url;
@@ -185,6 +187,7 @@ pub struct StaticPage {
     wait_until: Instant,
     url: Url,
     html: OnceLock<Html>,
+    retry_policy: RetryPolicy,
 }
 
 impl StaticPage {
@@ -198,9 +201,16 @@ impl StaticPage {
             wait_until,
             url: url.clone(),
             html: OnceLock::new(),
+            retry_policy: RetryPolicy::default(),
         })
     }
 
+    /// Set the policy for retrying the request for the page if it fails with a transient error. (Defaults to [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// Get the URL of the page.
     pub fn url(&self) -> Url {
         self.url.clone()
@@ -212,7 +222,7 @@ impl StaticPage {
             Some(html) => Ok(html),
             None => {
                 tokio::time::sleep_until(self.wait_until).await;
-                let html = reqwest::get(self.url.clone()).await?.text().await?;
+                let html = fetch_text(self.url.clone(), &self.retry_policy).await?;
                 let html = Html::parse_document(&html);
                 self.html.set(html).unwrap();
                 Ok(self.html.get().unwrap())
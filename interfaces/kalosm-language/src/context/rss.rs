@@ -1,7 +1,8 @@
+use kalosm_language_model::RetryPolicy;
 use rss::Channel;
 use url::Url;
 
-use super::document::{Document, IntoDocuments};
+use super::document::{fetch_text, Document, IntoDocuments};
 
 /// An error that can occur when interacting with an RSS feed.
 #[derive(Debug, thiserror::Error)]
@@ -30,7 +31,10 @@ pub enum RssFeedError {
 /// }
 /// ```
 #[derive(Debug, Clone, PartialEq)]
-pub struct RssFeed(Url);
+pub struct RssFeed {
+    url: Url,
+    retry_policy: RetryPolicy,
+}
 
 impl From<Url> for RssFeed {
     fn from(url: Url) -> Self {
@@ -49,17 +53,26 @@ impl IntoDocuments for RssFeed {
 impl RssFeed {
     /// Create a new RSS feed from the given URL.
     pub fn new(url: Url) -> Self {
-        Self(url)
+        Self {
+            url,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Set the policy for retrying requests for the feed and its articles if they fail with a transient error. (Defaults to [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
     /// Get the URL of the RSS feed.
     pub fn url(&self) -> &Url {
-        &self.0
+        &self.url
     }
 
     /// Read the top N documents from the RSS feed.
     pub async fn read_top_n(&self, top_n: usize) -> Result<Vec<Document>, RssFeedError> {
-        let xml = reqwest::get(self.0.clone()).await?.text().await?;
+        let xml = fetch_text(self.url.clone(), &self.retry_policy).await?;
         let channel = Channel::read_from(xml.as_bytes())?;
         let mut documents = Vec::new();
         for item in channel.items().iter().take(top_n) {
@@ -70,9 +83,12 @@ impl RssFeed {
             let (source_url, content) = if let Some(content) = item.content() {
                 (None, content.to_string())
             } else if let Some(source_url) = item.link() {
+                let Ok(parsed) = Url::parse(source_url) else {
+                    continue;
+                };
                 (
                     Some(source_url),
-                    reqwest::get(source_url).await?.text().await?,
+                    fetch_text(parsed, &self.retry_policy).await?,
                 )
             } else {
                 (None, String::new())
@@ -80,7 +96,7 @@ impl RssFeed {
 
             let url = match source_url {
                 Some(url) => Url::parse(url).unwrap(),
-                None => self.0.clone(),
+                None => self.url.clone(),
             };
 
             if let Ok(article) =
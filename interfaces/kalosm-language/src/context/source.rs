@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use url::Url;
+
+use super::document::{Document, IntoDocument, IntoDocuments};
+use super::{DocumentFolder, FsDocument};
+
+/// An error that can occur while loading documents from a [`DocumentSourceRegistry`].
+#[derive(Debug, thiserror::Error)]
+pub enum DocumentSourceError {
+    /// The URI could not be parsed.
+    #[error("Failed to parse URI: {0}")]
+    ParseUri(#[from] url::ParseError),
+    /// No source is registered for the scheme of the URI.
+    #[error("No document source is registered for the scheme {0:?}")]
+    UnknownScheme(String),
+    /// The source failed to load the documents.
+    #[error("Failed to load documents: {0}")]
+    Load(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl DocumentSourceError {
+    /// Create an error for a source that failed to load documents.
+    pub fn load(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Self {
+        Self::Load(error.into())
+    }
+}
+
+/// The future returned by [`DocumentSource::load`].
+pub type DocumentSourceFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<Document>, DocumentSourceError>> + Send + 'a>>;
+
+/// A system documents can be loaded from by URI. Sources are registered for a URI scheme in a
+/// [`DocumentSourceRegistry`].
+///
+/// Any `Fn(Url) -> impl Future<Output = Result<impl IntoDocuments, DocumentSourceError>>` is a document source, so
+/// existing [`IntoDocuments`] implementations can be registered with a closure that builds them from the URI.
+pub trait DocumentSource: Send + Sync + 'static {
+    /// Load the documents the URI points to.
+    fn load(&self, uri: Url) -> DocumentSourceFuture<'_>;
+}
+
+impl<F, Fut, D> DocumentSource for F
+where
+    F: Fn(Url) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<D, DocumentSourceError>> + Send + 'static,
+    D: IntoDocuments + Send + 'static,
+    D::Error: std::error::Error,
+{
+    fn load(&self, uri: Url) -> DocumentSourceFuture<'_> {
+        let documents = self(uri);
+        Box::pin(async move {
+            documents
+                .await?
+                .into_documents()
+                .await
+                .map_err(DocumentSourceError::load)
+        })
+    }
+}
+
+/// A registry of [`DocumentSource`]s by URI scheme. Applications can register their own sources (for example
+/// `notion://` or `jira://`) and then load documents from any registered system with a URI.
+///
+/// Clones of the registry share the same sources. The [`DocumentSourceRegistry::global`] registry is used by
+/// [`SourceUri`] and the high level indexing APIs.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), anyhow::Error> {
+///     DocumentSourceRegistry::global().register("notes", |uri: Url| async move {
+///         let title = uri.host_str().unwrap_or_default().to_string();
+///         Ok::<_, DocumentSourceError>([Document::from_parts(title, "Notes loaded from my app")])
+///     });
+///
+///     let documents = DocumentSourceRegistry::global()
+///         .load("notes://meeting")
+///         .await?;
+///     println!("{documents:?}");
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DocumentSourceRegistry {
+    sources: Arc<RwLock<HashMap<String, Arc<dyn DocumentSource>>>>,
+}
+
+impl std::fmt::Debug for DocumentSourceRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DocumentSourceRegistry")
+            .field("schemes", &self.schemes())
+            .finish()
+    }
+}
+
+impl Default for DocumentSourceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DocumentSourceRegistry {
+    /// Create a new registry with the built in sources:
+    /// - `http` and `https` extract the article at the URL
+    /// - `file` reads a document, or every document in a folder
+    pub fn new() -> Self {
+        let registry = Self::empty();
+        registry.register("http", load_url);
+        registry.register("https", load_url);
+        registry.register("file", load_file);
+        registry
+    }
+
+    /// Create a new registry without any sources.
+    pub fn empty() -> Self {
+        Self {
+            sources: Default::default(),
+        }
+    }
+
+    /// Get the registry that is shared by the whole process.
+    pub fn global() -> &'static Self {
+        static GLOBAL: OnceLock<DocumentSourceRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(DocumentSourceRegistry::new)
+    }
+
+    /// Register a source for a URI scheme. If a source was already registered for the scheme, it is replaced.
+    pub fn register(&self, scheme: impl Into<String>, source: impl DocumentSource) {
+        let scheme = scheme.into().to_lowercase();
+        self.sources
+            .write()
+            .unwrap()
+            .insert(scheme, Arc::new(source));
+    }
+
+    /// Remove the source for a URI scheme. Returns true if a source was registered.
+    pub fn unregister(&self, scheme: &str) -> bool {
+        self.sources
+            .write()
+            .unwrap()
+            .remove(&scheme.to_lowercase())
+            .is_some()
+    }
+
+    /// Get the schemes that have a registered source.
+    pub fn schemes(&self) -> Vec<String> {
+        let mut schemes: Vec<_> = self.sources.read().unwrap().keys().cloned().collect();
+        schemes.sort();
+        schemes
+    }
+
+    /// Load the documents a URI points to with the source registered for its scheme.
+    pub async fn load(&self, uri: &str) -> Result<Vec<Document>, DocumentSourceError> {
+        let uri = Url::parse(uri)?;
+        let source = self
+            .sources
+            .read()
+            .unwrap()
+            .get(uri.scheme())
+            .cloned()
+            .ok_or_else(|| DocumentSourceError::UnknownScheme(uri.scheme().to_string()))?;
+        source.load(uri).await
+    }
+
+    /// Get a URI that loads its documents from this registry when it is used as [`IntoDocuments`].
+    pub fn uri(&self, uri: impl Into<String>) -> SourceUri {
+        SourceUri {
+            uri: uri.into(),
+            registry: self.clone(),
+        }
+    }
+}
+
+/// A URI that is loaded with a [`DocumentSourceRegistry`]. It can be passed to anything that accepts
+/// [`IntoDocuments`].
+#[derive(Debug, Clone)]
+pub struct SourceUri {
+    uri: String,
+    registry: DocumentSourceRegistry,
+}
+
+impl SourceUri {
+    /// Create a URI that is loaded with the [`DocumentSourceRegistry::global`] registry.
+    pub fn new(uri: impl Into<String>) -> Self {
+        DocumentSourceRegistry::global().uri(uri)
+    }
+
+    /// Get the URI.
+    pub fn as_str(&self) -> &str {
+        &self.uri
+    }
+}
+
+impl IntoDocuments for SourceUri {
+    type Error = DocumentSourceError;
+
+    async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+        self.registry.load(&self.uri).await
+    }
+}
+
+async fn load_url(uri: Url) -> Result<[Url; 1], DocumentSourceError> {
+    Ok([uri])
+}
+
+async fn load_file(uri: Url) -> Result<Vec<Document>, DocumentSourceError> {
+    let path: PathBuf = uri
+        .to_file_path()
+        .map_err(|_| DocumentSourceError::load(format!("{uri} is not a valid file path")))?;
+    if path.is_dir() {
+        let folder = DocumentFolder::try_from(path).map_err(DocumentSourceError::load)?;
+        folder
+            .into_documents()
+            .await
+            .map_err(DocumentSourceError::load)
+    } else {
+        let document = FsDocument::try_from(path).map_err(DocumentSourceError::load)?;
+        let document = document
+            .into_document()
+            .await
+            .map_err(DocumentSourceError::load)?;
+        Ok(vec![document])
+    }
+}
+
+#[tokio::test]
+async fn registered_sources_load_by_scheme() {
+    let registry = DocumentSourceRegistry::empty();
+    registry.register("memo", |uri: Url| async move {
+        Ok::<_, DocumentSourceError>([Document::from_parts(uri.path().to_string(), "body")])
+    });
+    let documents = registry.load("memo:/first").await.unwrap();
+    assert_eq!(documents[0].title(), "/first");
+    assert!(matches!(
+        registry.load("jira://ticket").await,
+        Err(DocumentSourceError::UnknownScheme(scheme)) if scheme == "jira"
+    ));
+}
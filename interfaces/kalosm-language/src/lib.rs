@@ -21,7 +21,9 @@ pub mod prelude {
     pub use futures_util::StreamExt as _;
     pub use kalosm_language_model::*;
     #[cfg(feature = "llama")]
-    pub use kalosm_llama::{Llama, LlamaBuilder, LlamaSession, LlamaSource};
+    pub use kalosm_llama::{
+        GgufQuantization, Llama, LlamaBuilder, LlamaSession, LlamaSource, Quantization,
+    };
     pub use kalosm_sample::*;
     pub use kalosm_streams::text_stream::*;
     #[cfg(feature = "bert")]
@@ -0,0 +1,335 @@
+use std::ops::Range;
+
+use kalosm_language_model::{ChatModel, CreateChatSession, StructuredChatModel};
+use kalosm_sample::{IndexParser, LiteralParser, ParserExt, StopOn};
+
+use crate::prelude::{Document, Task};
+
+use super::ChunkStrategy;
+
+const TASK_DESCRIPTION: &str = "You answer questions about a long document one section at a time. You only use information from the sections you are given. After each answer, you rate your confidence that the answer is complete and correct as low, medium or high.";
+
+const CONFIDENCE_LEVELS: [(&str, f32); 3] = [("low", 0.2), ("medium", 0.6), ("high", 0.9)];
+
+type Constraints = kalosm_sample::SequenceParser<
+    kalosm_sample::SequenceParser<
+        kalosm_sample::SequenceParser<LiteralParser, StopOn<&'static str>>,
+        LiteralParser,
+    >,
+    IndexParser<LiteralParser>,
+>;
+
+fn create_constraints() -> Constraints {
+    LiteralParser::new("Answer: ")
+        .then(StopOn::new("\n"))
+        .then(LiteralParser::new("Confidence: "))
+        .then(IndexParser::new(
+            CONFIDENCE_LEVELS
+                .iter()
+                .map(|(level, _)| LiteralParser::new(*level))
+                .collect(),
+        ))
+}
+
+/// How [`DocumentQa`] combines the sections of a document into an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentQaStrategy {
+    /// Read the sections in order and refine one running answer with each section. This works well for questions
+    /// that need information from many parts of the document.
+    #[default]
+    Refine,
+    /// Answer the question with each section on its own and keep the answer with the highest confidence. This works
+    /// well for questions that are answered in one place in the document.
+    MapRerank,
+}
+
+/// One step of answering a question with [`DocumentQa`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentQaStep {
+    /// The byte range of the section of the document that was read in this step.
+    pub byte_range: Range<usize>,
+    /// The answer after reading the section.
+    pub answer: String,
+    /// The confidence of the model in the answer between 0 and 1.
+    pub confidence: f32,
+}
+
+/// The answer to a question about a document from [`DocumentQa`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocumentAnswer {
+    /// The final answer.
+    pub answer: String,
+    /// The confidence of the model in the final answer between 0 and 1.
+    pub confidence: f32,
+    /// Every step that was run to find the answer. The byte ranges of the steps can be used as citations.
+    pub steps: Vec<DocumentQaStep>,
+    /// The strategy that was used to combine the steps.
+    pub strategy: DocumentQaStrategy,
+    /// The index of the step the final answer was taken from, or `None` if no section was read.
+    pub best_step: Option<usize>,
+}
+
+impl DocumentAnswer {
+    /// Get the byte ranges of the sections the final answer is based on. For [`DocumentQaStrategy::Refine`] this is
+    /// every section that was read. For [`DocumentQaStrategy::MapRerank`] this is the section of the best answer.
+    pub fn citations(&self) -> impl Iterator<Item = &Range<usize>> {
+        let steps = match (self.strategy, self.best_step) {
+            (DocumentQaStrategy::Refine, _) => &self.steps[..],
+            (DocumentQaStrategy::MapRerank, Some(best)) => &self.steps[best..=best],
+            (DocumentQaStrategy::MapRerank, None) => &[],
+        };
+        steps.iter().map(|step| &step.byte_range)
+    }
+}
+
+/// Answers questions about documents that are too large to fit in the context window of the model by reading the
+/// document one section at a time.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let model = Llama::new_chat().await.unwrap();
+///     let document = Url::parse("https://en.wikipedia.org/wiki/Rust_(programming_language)")
+///         .unwrap()
+///         .into_document()
+///         .await
+///         .unwrap();
+///     let qa = DocumentQa::new(model)
+///         .with_strategy(DocumentQaStrategy::MapRerank)
+///         .with_early_exit(0.9);
+///     let answer = qa
+///         .answer(&document, "Who originally designed Rust?")
+///         .await
+///         .unwrap();
+///     println!("{} ({})", answer.answer, answer.confidence);
+///     for citation in answer.citations() {
+///         println!("- {}", &document.body()[citation.clone()]);
+///     }
+/// }
+/// ```
+pub struct DocumentQa<M: CreateChatSession> {
+    task: Task<M>,
+    strategy: DocumentQaStrategy,
+    chunking: ChunkStrategy,
+    early_exit: Option<f32>,
+}
+
+impl<M: CreateChatSession> DocumentQa<M> {
+    /// Create a new document question answerer.
+    pub fn new(model: M) -> Self
+    where
+        M: ChatModel,
+    {
+        Self {
+            task: Task::new(model, TASK_DESCRIPTION),
+            strategy: DocumentQaStrategy::default(),
+            chunking: ChunkStrategy::Paragraph {
+                paragraph_count: 8,
+                overlap: 1,
+            },
+            early_exit: None,
+        }
+    }
+
+    /// Set the strategy used to combine the sections of the document. (Defaults to [`DocumentQaStrategy::Refine`])
+    pub fn with_strategy(mut self, strategy: DocumentQaStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Set how the document is split into sections. Each section and the current answer must fit in the context
+    /// window of the model. (Defaults to 8 paragraphs with an overlap of 1)
+    pub fn with_chunking(mut self, chunking: ChunkStrategy) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Stop reading the document once an answer reaches this confidence between 0 and 1. (Defaults to reading the
+    /// whole document)
+    pub fn with_early_exit(mut self, confidence: f32) -> Self {
+        self.early_exit = Some(confidence);
+        self
+    }
+
+    /// Answer a question about a document.
+    pub async fn answer(
+        &self,
+        document: &Document,
+        question: &str,
+    ) -> Result<DocumentAnswer, M::Error>
+    where
+        M: StructuredChatModel<Constraints> + Send + Sync + Clone + Unpin + 'static,
+        M::ChatSession: Clone + Send + Sync + Unpin + 'static,
+        M::Error: Send + Sync + Unpin,
+    {
+        let body = document.body();
+        let mut steps: Vec<DocumentQaStep> = Vec::new();
+        for byte_range in self.chunking.chunk_str(body) {
+            let section = &body[byte_range.clone()];
+            let prompt = match (self.strategy, steps.last()) {
+                (DocumentQaStrategy::Refine, Some(previous)) => format!(
+                    "Question: {question}\nCurrent answer: {}\n\nSection:\n{section}\n\nRefine the current answer with the information in this section. If the section is not relevant, repeat the current answer.",
+                    previous.answer
+                ),
+                _ => format!(
+                    "Question: {question}\n\nSection:\n{section}\n\nAnswer the question with the information in this section. If the section does not answer the question, say that it is not mentioned with low confidence."
+                ),
+            };
+            let (((_, answer), _), (confidence, _)) = self
+                .task
+                .run(prompt)
+                .with_constraints(create_constraints())
+                .await?;
+            let step = DocumentQaStep {
+                byte_range,
+                answer: answer.trim().to_string(),
+                confidence: CONFIDENCE_LEVELS[confidence].1,
+            };
+            let done = self
+                .early_exit
+                .is_some_and(|early_exit| step.confidence >= early_exit);
+            steps.push(step);
+            if done {
+                break;
+            }
+        }
+
+        let best_step = match self.strategy {
+            DocumentQaStrategy::Refine => steps.len().checked_sub(1),
+            // Ties go to the earliest section
+            DocumentQaStrategy::MapRerank => steps
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by(|(_, a), (_, b)| a.confidence.total_cmp(&b.confidence))
+                .map(|(index, _)| index),
+        };
+        let (answer, confidence) = best_step
+            .map(|index| (steps[index].answer.clone(), steps[index].confidence))
+            .unwrap_or_default();
+        Ok(DocumentAnswer {
+            answer,
+            confidence,
+            steps,
+            strategy: self.strategy,
+            best_step,
+        })
+    }
+}
+
+#[tokio::test]
+async fn citations_follow_the_strategy() {
+    use kalosm_language_model::{ChatMessage, ChatSession, GenerationParameters};
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::future::Future;
+    use std::sync::{Arc, Mutex};
+
+    /// A chat model that answers each section with the next scripted answer and confidence level.
+    #[derive(Clone)]
+    struct ScriptedModel {
+        answers: Arc<Mutex<VecDeque<(&'static str, usize)>>>,
+    }
+
+    #[derive(Clone)]
+    struct ScriptedSession;
+
+    impl ChatSession for ScriptedSession {
+        type Error = Infallible;
+
+        fn write_to(&self, _: &mut Vec<u8>) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn from_bytes(_: &[u8]) -> Result<Self, Self::Error> {
+            Ok(Self)
+        }
+
+        fn history(&self) -> Vec<ChatMessage> {
+            Vec::new()
+        }
+
+        fn try_clone(&self) -> Result<Self, Self::Error> {
+            Ok(Self)
+        }
+    }
+
+    impl CreateChatSession for ScriptedModel {
+        type Error = Infallible;
+        type ChatSession = ScriptedSession;
+
+        fn new_chat_session(&self) -> Result<Self::ChatSession, Self::Error> {
+            Ok(ScriptedSession)
+        }
+    }
+
+    impl ChatModel for ScriptedModel {
+        fn add_messages_with_callback<'a>(
+            &'a self,
+            _: &'a mut Self::ChatSession,
+            _: &[ChatMessage],
+            _: GenerationParameters,
+            _: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+            std::future::ready(Ok(()))
+        }
+    }
+
+    impl StructuredChatModel<Constraints> for ScriptedModel {
+        fn add_message_with_callback_and_constraints<'a>(
+            &'a self,
+            _: &'a mut Self::ChatSession,
+            _: &[ChatMessage],
+            _: GenerationParameters,
+            _: Constraints,
+            _: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+        ) -> impl Future<Output = Result<((((), String), ()), (usize, ())), Self::Error>> + Send + 'a
+        {
+            let (answer, confidence) = self.answers.lock().unwrap().pop_front().unwrap();
+            std::future::ready(Ok(((((), answer.to_string()), ()), (confidence, ()))))
+        }
+    }
+
+    let document = Document::from_parts("Rust", "one\ntwo\nthree");
+    let answer = |strategy, answers: [(&'static str, usize); 3]| {
+        let model = ScriptedModel {
+            answers: Arc::new(Mutex::new(answers.into())),
+        };
+        let qa = DocumentQa::new(model)
+            .with_strategy(strategy)
+            .with_chunking(ChunkStrategy::Paragraph {
+                paragraph_count: 1,
+                overlap: 0,
+            });
+        let document = &document;
+        async move { qa.answer(document, "Who made Rust?").await.unwrap() }
+    };
+
+    // Every section that was read refines the final answer
+    let refined = answer(
+        DocumentQaStrategy::Refine,
+        [("Graydon", 0), ("Graydon Hoare", 1), ("Graydon Hoare", 2)],
+    )
+    .await;
+    assert_eq!(refined.answer, "Graydon Hoare");
+    assert_eq!(
+        refined.citations().cloned().collect::<Vec<_>>(),
+        [0..3, 4..7, 8..13]
+    );
+
+    // Only the section of the chosen answer is cited, even if another section gave the same answer
+    let reranked = answer(
+        DocumentQaStrategy::MapRerank,
+        [
+            ("Graydon Hoare", 0),
+            ("Graydon Hoare", 2),
+            ("Not mentioned", 0),
+        ],
+    )
+    .await;
+    assert_eq!(reranked.answer, "Graydon Hoare");
+    assert_eq!(reranked.best_step, Some(1));
+    assert_eq!(reranked.citations().cloned().collect::<Vec<_>>(), vec![4..7]);
+}
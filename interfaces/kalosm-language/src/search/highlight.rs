@@ -0,0 +1,105 @@
+use std::ops::Range;
+
+use kalosm_language_model::{Embedder, Embedding};
+
+use super::SentenceChunker;
+
+/// A span of text that supports a search query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Highlight {
+    /// The byte range of the span in the text that was highlighted.
+    pub byte_range: Range<usize>,
+    /// The cosine similarity between the span and the query. Higher scores are more similar.
+    pub score: f32,
+}
+
+/// Find the sentences in a passage that are most similar to a query so they can be highlighted. Returns every
+/// sentence in the passage sorted from most to least similar.
+///
+/// The query should be embedded with [`EmbedderExt::embed_query`](kalosm_language_model::EmbedderExt::embed_query)
+/// with the same embedder.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let bert = Bert::new().await.unwrap();
+///     let passage = "The Eiffel Tower is in Paris. It was built in 1889. Paris is the capital of France.";
+///     let query = bert.embed_query("When was the Eiffel Tower built?").await.unwrap();
+///     let highlights = highlight_passage(passage, &query, &bert).await.unwrap();
+///     let best = &highlights[0];
+///     println!("{}", &passage[best.byte_range.clone()]);
+/// }
+/// ```
+pub async fn highlight_passage<E: Embedder>(
+    passage: &str,
+    query: &Embedding,
+    embedder: &E,
+) -> Result<Vec<Highlight>, E::Error> {
+    let ranges: Vec<_> = SentenceChunker::default()
+        .split_sentences(passage)
+        .into_iter()
+        .filter(|range| !passage[range.clone()].trim().is_empty())
+        .collect();
+    let sentences = ranges
+        .iter()
+        .map(|range| passage[range.clone()].to_string())
+        .collect();
+    let embeddings = embedder.embed_vec(sentences).await?;
+
+    let mut highlights: Vec<_> = ranges
+        .into_iter()
+        .zip(embeddings)
+        .map(|(byte_range, embedding)| Highlight {
+            byte_range,
+            score: embedding.cosine_similarity(query),
+        })
+        .collect();
+    highlights.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(highlights)
+}
+
+#[tokio::test]
+async fn highlights_are_byte_ranges_of_the_most_similar_sentences() {
+    use kalosm_language_model::EmbeddingInput;
+
+    /// Embeds text as the number of times each keyword appears in it.
+    struct KeywordEmbedder;
+
+    impl Embedder for KeywordEmbedder {
+        type Error = std::convert::Infallible;
+
+        async fn embed_for(&self, input: EmbeddingInput) -> Result<Embedding, Self::Error> {
+            let count = |keyword| input.text.matches(keyword).count() as f32;
+            Ok(Embedding::from([
+                count("crème"),
+                count("1889"),
+                count("nickname"),
+                0.1,
+            ]))
+        }
+    }
+
+    // The sentences before the best match contain multi-byte characters, so byte and character offsets differ
+    let passage = "The café sells crème brûlée for 5€. The Eiffel Tower was finished in 1889. Its nickname is « la Dame de fer ».";
+    let query = Embedding::from([0.0, 1.0, 0.5, 0.0]);
+    let highlights = highlight_passage(passage, &query, &KeywordEmbedder)
+        .await
+        .unwrap();
+
+    let sentences = [
+        "The Eiffel Tower was finished in 1889.",
+        "Its nickname is « la Dame de fer ».",
+        "The café sells crème brûlée for 5€.",
+    ];
+    assert_eq!(highlights.len(), sentences.len());
+    for (highlight, sentence) in highlights.iter().zip(sentences) {
+        let start = passage.find(sentence).unwrap();
+        assert_eq!(highlight.byte_range.start, start);
+        assert_eq!(passage[highlight.byte_range.clone()].trim(), sentence);
+    }
+    assert!(highlights
+        .windows(2)
+        .all(|pair| pair[0].score >= pair[1].score));
+}
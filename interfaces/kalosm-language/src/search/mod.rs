@@ -1,5 +1,9 @@
 //! The index module contains different types of search indexes that can be used to search for [`crate::context::Document`]s created from [`crate::context::IntoDocument`] or [`crate::context::IntoDocuments`]
 
+mod document_qa;
+pub use document_qa::*;
+mod highlight;
+pub use highlight::*;
 mod postprocessing;
 mod preprocessing;
 pub use preprocessing::*;
@@ -2,6 +2,7 @@
 
 use arroy::distances::DotProduct;
 use heed::{types::*, RwTxn};
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::atomic::AtomicUsize;
 
@@ -25,6 +26,35 @@ pub enum VectorDbError {
     /// An error from querying an embedding id that does not exist.
     #[error("Embedding {0:?} not found")]
     EmbeddingNotFound(EmbeddingId),
+    /// An error from restoring or deleting a snapshot that does not exist.
+    #[error("Snapshot {0:?} not found")]
+    SnapshotNotFound(String),
+    /// An error from creating a snapshot with a name that is already used.
+    #[error("Snapshot {0:?} already exists")]
+    SnapshotExists(String),
+}
+
+// Snapshots are tracked with string keys in the metadata database. Arroy keys start with the big endian index of
+// the arroy tree, so string keys never overlap with the keys of index 0.
+
+/// The version of a live embedding.
+fn version_key(id: u32) -> String {
+    format!("version:{id}")
+}
+
+/// The number of snapshots that reference a version of an embedding.
+fn refs_key(version: u64) -> String {
+    format!("refs:{version}")
+}
+
+/// The vector of a version of an embedding that was removed from the live index while a snapshot referenced it.
+fn page_key(version: u64) -> String {
+    format!("page:{version}")
+}
+
+/// The id and version of every embedding in a snapshot.
+fn snapshot_key(name: &str) -> String {
+    format!("snapshot:{name}")
 }
 
 impl From<heed::Error> for VectorDbError {
@@ -181,6 +211,9 @@ impl VectorDB {
         let mut wtxn = self.env.write_txn()?;
         let dims = self.get_dim()?;
         let writer = Writer::<DotProduct>::new(self.database, 0, dims);
+        for id in &self.live_ids(&wtxn)? {
+            self.release(&mut wtxn, &writer, id)?;
+        }
         writer.clear(&mut wtxn)?;
 
         // Reset the ids
@@ -211,6 +244,7 @@ impl VectorDB {
 
         let mut writer = Writer::<DotProduct>::new(self.database, 0, dims);
 
+        self.release(&mut wtxn, &writer, embedding_id.0)?;
         writer.del_item(&mut wtxn, embedding_id.0)?;
         self.recycle_id(embedding_id, &mut wtxn)?;
 
@@ -236,6 +270,7 @@ impl VectorDB {
         let id = self.take_id(&mut wtxn)?;
 
         writer.add_item(&mut wtxn, id.0, embedding)?;
+        self.assign_version(&mut wtxn, id)?;
 
         self.rebuild(&mut writer, &mut wtxn)?;
 
@@ -265,12 +300,14 @@ impl VectorDB {
         {
             let first_id = self.take_id(&mut wtxn)?;
             writer.add_item(&mut wtxn, first_id.0, &first_embedding)?;
+            self.assign_version(&mut wtxn, first_id)?;
             ids.push(first_id);
         }
 
         for embedding in embeddings {
             let id = self.take_id(&mut wtxn)?;
             writer.add_item(&mut wtxn, id.0, &embedding)?;
+            self.assign_version(&mut wtxn, id)?;
             ids.push(id);
         }
 
@@ -293,6 +330,264 @@ impl VectorDB {
         Ok(Embedding::from(embedding))
     }
 
+    fn snapshot_records(&self, rtxn: &heed::RoTxn) -> Result<Vec<SnapshotRecord>, heed::Error> {
+        Ok(self
+            .metadata
+            .remap_data_type::<SerdeJson<Vec<SnapshotRecord>>>()
+            .get(rtxn, "snapshots")?
+            .unwrap_or_default())
+    }
+
+    fn put_snapshot_records(
+        &self,
+        wtxn: &mut RwTxn,
+        records: Vec<SnapshotRecord>,
+    ) -> Result<(), heed::Error> {
+        self.metadata
+            .remap_data_type::<SerdeJson<Vec<SnapshotRecord>>>()
+            .put(wtxn, "snapshots", &records)
+    }
+
+    /// Give a newly added embedding a version that snapshots use to tell whether it changed.
+    fn assign_version(&self, wtxn: &mut RwTxn, id: EmbeddingId) -> Result<u64, heed::Error> {
+        let versions = self.metadata.remap_data_type::<SerdeJson<u64>>();
+        let version = versions.get(wtxn, "next-version")?.unwrap_or_default();
+        versions.put(wtxn, "next-version", &(version + 1))?;
+        versions.put(wtxn, &version_key(id.0), &version)?;
+        Ok(version)
+    }
+
+    /// Get the ids of every embedding in the live index.
+    fn live_ids(&self, rtxn: &heed::RoTxn) -> Result<Candidates, arroy::Error> {
+        match Reader::<DotProduct>::open(rtxn, 0, self.database) {
+            Ok(reader) => Ok(reader.item_ids().clone()),
+            // Indexes that have never been built have no metadata
+            Err(arroy::Error::MissingMetadata { .. }) => Ok(Candidates::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the version of every embedding in the live index. Embeddings that were added before the database tracked
+    /// versions get a new version.
+    fn live_versions(&self, wtxn: &mut RwTxn) -> Result<Vec<(u32, u64)>, arroy::Error> {
+        let versions = self.metadata.remap_data_type::<SerdeJson<u64>>();
+        let mut items = Vec::new();
+        for id in &self.live_ids(wtxn)? {
+            let version = match versions.get(wtxn, &version_key(id))? {
+                Some(version) => version,
+                None => self.assign_version(wtxn, EmbeddingId(id))?,
+            };
+            items.push((id, version));
+        }
+        Ok(items)
+    }
+
+    /// Forget the version of an embedding that is about to be removed from the live index. If a snapshot still
+    /// references that version, the vector is copied out of the index first so the snapshot can be restored later.
+    fn release(
+        &self,
+        wtxn: &mut RwTxn,
+        writer: &Writer<DotProduct>,
+        id: u32,
+    ) -> Result<(), arroy::Error> {
+        let versions = self.metadata.remap_data_type::<SerdeJson<u64>>();
+        let Some(version) = versions.get(wtxn, &version_key(id))? else {
+            return Ok(());
+        };
+        versions.delete(wtxn, &version_key(id))?;
+
+        let exists = self.metadata.remap_data_type::<DecodeIgnore>();
+        let referenced = exists.get(wtxn, &refs_key(version))?.is_some();
+        if referenced && exists.get(wtxn, &page_key(version))?.is_none() {
+            if let Some(vector) = writer.item_vector(wtxn, id)? {
+                self.metadata.remap_data_type::<SerdeJson<Vec<f32>>>().put(
+                    wtxn,
+                    &page_key(version),
+                    &vector,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create a snapshot of every embedding currently in the database. The database can be rolled back to the
+    /// snapshot later with [`VectorDB::restore_snapshot`].
+    ///
+    /// Snapshots are copy-on-write. Creating a snapshot only records the version of each embedding, and the
+    /// vectors are shared with the live database. A vector is only copied when it is removed from the live
+    /// database while a snapshot still references it. The snapshot is created in a single transaction, so it never
+    /// contains part of a batch of embeddings.
+    ///
+    /// If the database backs an `EmbeddingIndexedTable` or `DocumentTable`, snapshot the table instead so the
+    /// records and embeddings are rolled back together.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # fn main() -> Result<(), VectorDbError> {
+    /// let db = VectorDB::new()?;
+    /// db.add_embeddings([Embedding::from([1.0, 0.0]), Embedding::from([0.0, 1.0])])?;
+    /// db.create_snapshot("before-reindex")?;
+    /// // Ingest a batch of embeddings that turns out to be bad
+    /// db.add_embeddings([Embedding::from([0.5, 0.5])])?;
+    /// // Roll back to the state before the batch
+    /// db.restore_snapshot("before-reindex")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn create_snapshot(&self, name: impl ToString) -> Result<VectorDBSnapshot, VectorDbError> {
+        let name = name.to_string();
+        let mut wtxn = self.env.write_txn()?;
+        let mut records = self.snapshot_records(&wtxn)?;
+        if records.iter().any(|record| record.snapshot.name == name) {
+            return Err(VectorDbError::SnapshotExists(name));
+        }
+
+        let items = self.live_versions(&mut wtxn)?;
+        let refs = self.metadata.remap_data_type::<SerdeJson<u32>>();
+        for (_, version) in &items {
+            let count = refs.get(&wtxn, &refs_key(*version))?.unwrap_or_default();
+            refs.put(&mut wtxn, &refs_key(*version), &(count + 1))?;
+        }
+        self.metadata
+            .remap_data_type::<SerdeJson<Vec<(u32, u64)>>>()
+            .put(&mut wtxn, &snapshot_key(&name), &items)?;
+
+        let snapshot = VectorDBSnapshot {
+            name,
+            created_at: chrono::Utc::now(),
+            embeddings: items.len() as u64,
+        };
+        records.push(SnapshotRecord {
+            snapshot: snapshot.clone(),
+            max: self.metadata.get(&wtxn, "max")?,
+            free: self.metadata.get(&wtxn, "free")?,
+        });
+        self.put_snapshot_records(&mut wtxn, records)?;
+        wtxn.commit()?;
+
+        Ok(snapshot)
+    }
+
+    /// List the snapshots of the database from oldest to newest.
+    pub fn snapshots(&self) -> Result<Vec<VectorDBSnapshot>, VectorDbError> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self
+            .snapshot_records(&rtxn)?
+            .into_iter()
+            .map(|record| record.snapshot)
+            .collect())
+    }
+
+    /// Roll the database back to a snapshot. Every embedding added after the snapshot was created is removed and
+    /// every embedding removed after the snapshot was created is restored with the same [`EmbeddingId`]. Only the
+    /// embeddings that changed since the snapshot are touched.
+    ///
+    /// The snapshot is kept, so the database can be restored to it again later.
+    pub fn restore_snapshot(&self, name: &str) -> Result<(), VectorDbError> {
+        let mut wtxn = self.env.write_txn()?;
+        let records = self.snapshot_records(&wtxn)?;
+        let record = records
+            .iter()
+            .find(|record| record.snapshot.name == name)
+            .ok_or_else(|| VectorDbError::SnapshotNotFound(name.to_string()))?;
+        let items: HashMap<u32, u64> = self
+            .metadata
+            .remap_data_type::<SerdeJson<Vec<(u32, u64)>>>()
+            .get(&wtxn, &snapshot_key(name))?
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        let live: HashMap<u32, u64> = self.live_versions(&mut wtxn)?.into_iter().collect();
+
+        let pages = self.metadata.remap_data_type::<SerdeJson<Vec<f32>>>();
+        let mut added = Vec::new();
+        for (&id, &version) in &items {
+            if live.get(&id) != Some(&version) {
+                let vector = pages
+                    .get(&wtxn, &page_key(version))?
+                    .ok_or(VectorDbError::EmbeddingNotFound(EmbeddingId(id)))?;
+                added.push((id, version, vector));
+            }
+        }
+        let removed: Vec<u32> = live
+            .iter()
+            .filter(|(id, version)| items.get(id) != Some(version))
+            .map(|(id, _)| *id)
+            .collect();
+
+        if !added.is_empty() || !removed.is_empty() {
+            let dims = match added.first() {
+                Some((_, _, vector)) => vector.len(),
+                None => self.get_dim()?,
+            };
+            let mut writer = Writer::<DotProduct>::new(self.database, 0, dims);
+            for id in removed {
+                self.release(&mut wtxn, &writer, id)?;
+                writer.del_item(&mut wtxn, id)?;
+            }
+            let versions = self.metadata.remap_data_type::<SerdeJson<u64>>();
+            for (id, version, vector) in added {
+                writer.add_item(&mut wtxn, id, &vector)?;
+                versions.put(&mut wtxn, &version_key(id), &version)?;
+            }
+            self.rebuild(&mut writer, &mut wtxn)?;
+            self.set_dim(dims);
+        }
+
+        match &record.max {
+            Some(max) => self.metadata.put(&mut wtxn, "max", max)?,
+            None => {
+                self.metadata.delete(&mut wtxn, "max")?;
+            }
+        }
+        match &record.free {
+            Some(free) => self.metadata.put(&mut wtxn, "free", free)?,
+            None => {
+                self.metadata.delete(&mut wtxn, "free")?;
+            }
+        }
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
+    /// Delete a snapshot. This does not change the embeddings currently in the database. Vectors that were only
+    /// kept for this snapshot are removed.
+    pub fn delete_snapshot(&self, name: &str) -> Result<(), VectorDbError> {
+        let mut wtxn = self.env.write_txn()?;
+        let mut records = self.snapshot_records(&wtxn)?;
+        let position = records
+            .iter()
+            .position(|record| record.snapshot.name == name)
+            .ok_or_else(|| VectorDbError::SnapshotNotFound(name.to_string()))?;
+        records.remove(position);
+
+        let snapshots = self
+            .metadata
+            .remap_data_type::<SerdeJson<Vec<(u32, u64)>>>();
+        let items = snapshots
+            .get(&wtxn, &snapshot_key(name))?
+            .unwrap_or_default();
+        let refs = self.metadata.remap_data_type::<SerdeJson<u32>>();
+        for (_, version) in items {
+            match refs.get(&wtxn, &refs_key(version))? {
+                Some(count) if count > 1 => {
+                    refs.put(&mut wtxn, &refs_key(version), &(count - 1))?
+                }
+                _ => {
+                    refs.delete(&mut wtxn, &refs_key(version))?;
+                    self.metadata.delete(&mut wtxn, &page_key(version))?;
+                }
+            }
+        }
+        snapshots.delete(&mut wtxn, &snapshot_key(name))?;
+        self.put_snapshot_records(&mut wtxn, records)?;
+        wtxn.commit()?;
+
+        Ok(())
+    }
+
     /// Get the closest N embeddings to the given embedding.
     pub fn search<'a>(&'a self, embedding: &'a Embedding) -> VectorDBSearchBuilder<'a> {
         VectorDBSearchBuilder {
@@ -420,6 +715,25 @@ pub struct VectorDBSearchResult {
     pub value: EmbeddingId,
 }
 
+/// A snapshot of a [`VectorDB`] created with [`VectorDB::create_snapshot`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VectorDBSnapshot {
+    /// The name of the snapshot.
+    pub name: String,
+    /// The time the snapshot was created.
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// The number of embeddings in the snapshot.
+    pub embeddings: u64,
+}
+
+/// A snapshot along with the id allocation state at the time it was created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SnapshotRecord {
+    snapshot: VectorDBSnapshot,
+    max: Option<Vec<u32>>,
+    free: Option<Vec<u32>>,
+}
+
 /// A unique identifier for an embedding. If you delete an embedding, the id will be recycled.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct EmbeddingId(pub u32);
@@ -473,3 +787,97 @@ async fn test_vector_db_get_closest() {
         vec![id2]
     );
 }
+
+#[tokio::test]
+async fn test_vector_db_snapshots() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let first_vector = Embedding::from([1.0, 2.0, 3.0]);
+    let second_vector = Embedding::from([-1.0, 2.0, 3.0]);
+    let id1 = db.add_embedding(first_vector.clone()).unwrap();
+    let snapshot = db.create_snapshot("one").unwrap();
+    assert_eq!(snapshot.embeddings, 1);
+    assert!(matches!(
+        db.create_snapshot("one"),
+        Err(VectorDbError::SnapshotExists(_))
+    ));
+
+    let id2 = db.add_embedding(second_vector.clone()).unwrap();
+    db.remove_embedding(id1).unwrap();
+    assert!(db.get_embedding(id1).is_err());
+
+    db.restore_snapshot("one").unwrap();
+    assert_eq!(
+        db.get_embedding(id1).unwrap().vector(),
+        first_vector.vector()
+    );
+    assert!(db.get_embedding(id2).is_err());
+    assert_eq!(
+        db.search(&second_vector)
+            .run()
+            .unwrap()
+            .iter()
+            .map(|r| r.value)
+            .collect::<Vec<_>>(),
+        vec![id1]
+    );
+    // Ids are allocated the same way they were when the snapshot was created
+    assert_eq!(db.add_embedding(second_vector).unwrap(), id2);
+
+    assert_eq!(
+        db.snapshots()
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.name)
+            .collect::<Vec<_>>(),
+        vec!["one".to_string()]
+    );
+    db.delete_snapshot("one").unwrap();
+    assert!(db.snapshots().unwrap().is_empty());
+    assert!(matches!(
+        db.restore_snapshot("one"),
+        Err(VectorDbError::SnapshotNotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_vector_db_snapshots_share_vectors() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let first_vector = Embedding::from([1.0, 0.0]);
+    let second_vector = Embedding::from([0.0, 1.0]);
+    let id1 = db.add_embedding(first_vector.clone()).unwrap();
+    db.create_snapshot("one").unwrap();
+    db.create_snapshot("two").unwrap();
+
+    // Nothing is copied until an embedding a snapshot references leaves the live index
+    let page = page_key(0);
+    let has_page = |db: &VectorDB| {
+        let rtxn = db.env.read_txn().unwrap();
+        db.metadata
+            .remap_data_type::<DecodeIgnore>()
+            .get(&rtxn, &page)
+            .unwrap()
+            .is_some()
+    };
+    assert!(!has_page(&db));
+    db.remove_embedding(id1).unwrap();
+    assert!(has_page(&db));
+
+    // Restoring only adds back the embedding that changed
+    let id2 = db.add_embedding(second_vector.clone()).unwrap();
+    assert_eq!(id2, id1);
+    db.restore_snapshot("two").unwrap();
+    assert_eq!(
+        db.get_embedding(id1).unwrap().vector(),
+        first_vector.vector()
+    );
+
+    // The copied vector is kept until every snapshot that references it is deleted
+    db.delete_snapshot("one").unwrap();
+    assert!(has_page(&db));
+    db.delete_snapshot("two").unwrap();
+    assert!(!has_page(&db));
+    assert_eq!(
+        db.get_embedding(id1).unwrap().vector(),
+        first_vector.vector()
+    );
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::{FileLoadingProgress, ModelLoadingProgress};
+
+/// A handle to the loading of a model that combines the progress of every file the model downloads and can cancel
+/// the load.
+///
+/// Clones of the handle share the same progress, so one clone can be passed to the builder while another is used to
+/// show progress or cancel the load from another task.
+///
+/// # Example
+/// ```rust
+/// use kalosm_model_types::*;
+///
+/// let handle = LoadingHandle::new();
+/// // Pass the handler to a model builder. Every file the model downloads is recorded in the handle
+/// let mut handler = handle.handler();
+/// handler(ModelLoadingProgress::downloading(
+///     "Model (llama-3.gguf)".to_string(),
+///     FileLoadingProgress {
+///         start_time: std::time::Instant::now(),
+///         cached_size: 0,
+///         size: 8_000_000_000,
+///         progress: 2_000_000_000,
+///     },
+/// ));
+///
+/// // Another clone of the handle can watch the progress and give up on models that are too large
+/// let status = handle.clone().status();
+/// assert_eq!(status.progress(), 0.25);
+/// if status.total > 4_000_000_000 {
+///     handle.cancel();
+/// }
+/// assert!(handle.is_cancelled());
+/// ```
+#[derive(Clone, Default)]
+pub struct LoadingHandle {
+    inner: Arc<HandleInner>,
+}
+
+#[derive(Default)]
+struct HandleInner {
+    state: Mutex<LoadingState>,
+    cancelled: AtomicBool,
+    /// The latest waker of each task waiting in [`LoadingHandle::cancelled`], by waiter id
+    wakers: Mutex<HashMap<u64, Waker>>,
+    next_waiter: AtomicU64,
+}
+
+#[derive(Default)]
+struct LoadingState {
+    start_time: Option<Instant>,
+    files: HashMap<String, FileLoadingProgress>,
+    loading: Option<f32>,
+}
+
+impl std::fmt::Debug for LoadingHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadingHandle")
+            .field("status", &self.status())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl LoadingHandle {
+    /// Create a new handle for a load that hasn't started.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a loading handler that records progress in this handle. Pass it to a `build_with_loading_handler` method.
+    pub fn handler(&self) -> impl FnMut(ModelLoadingProgress) + Send + Sync + 'static {
+        self.handler_with(|_| {})
+    }
+
+    /// Get a loading handler that records progress in this handle and then calls another handler.
+    pub fn handler_with(
+        &self,
+        mut handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> impl FnMut(ModelLoadingProgress) + Send + Sync + 'static {
+        let handle = self.clone();
+        move |progress| {
+            handle.record(&progress);
+            handler(progress)
+        }
+    }
+
+    fn record(&self, progress: &ModelLoadingProgress) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.start_time.get_or_insert_with(Instant::now);
+        match progress {
+            ModelLoadingProgress::Downloading { source, progress } => {
+                state.files.insert(source.clone(), progress.clone());
+            }
+            ModelLoadingProgress::Loading { progress } => state.loading = Some(*progress),
+        }
+    }
+
+    /// Get the combined progress of every file.
+    pub fn status(&self) -> LoadingStatus {
+        let state = self.inner.state.lock().unwrap();
+        let mut status = LoadingStatus {
+            files: state.files.len(),
+            elapsed: state
+                .start_time
+                .map(|start| start.elapsed())
+                .unwrap_or_default(),
+            loading: state.loading,
+            ..Default::default()
+        };
+        for file in state.files.values() {
+            status.total += file.size;
+            status.downloaded += file.progress.min(file.size);
+            status.cached += file.cached_size.min(file.size);
+            if file.progress >= file.size {
+                status.finished_files += 1;
+            }
+        }
+        status
+    }
+
+    /// Cancel the load. A load that is waiting on the handle stops as soon as possible with a cancelled error.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        for (_, waker) in self.inner.wakers.lock().unwrap().drain() {
+            waker.wake();
+        }
+    }
+
+    /// Check if the load was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the load is cancelled.
+    pub async fn cancelled(&self) {
+        let waiter = Waiter {
+            handle: self,
+            id: self.inner.next_waiter.fetch_add(1, Ordering::Relaxed),
+        };
+        std::future::poll_fn(|cx| waiter.poll(cx)).await
+    }
+
+    /// Run a future until it finishes or the handle is cancelled. Downloads stop when the future is dropped, but work
+    /// that already moved to a blocking thread finishes in the background.
+    pub async fn run<F: Future>(&self, future: F) -> Result<F::Output, LoadingCancelled> {
+        let mut future = pin!(future);
+        let mut cancelled = pin!(self.cancelled());
+        std::future::poll_fn(|cx| {
+            if cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(LoadingCancelled));
+            }
+            future.as_mut().poll(cx).map(Ok)
+        })
+        .await
+    }
+}
+
+/// A task waiting in [`LoadingHandle::cancelled`]. Each waiter keeps only the waker it was polled with last, and
+/// removes it from the handle when it is dropped.
+struct Waiter<'a> {
+    handle: &'a LoadingHandle,
+    id: u64,
+}
+
+impl Waiter<'_> {
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.handle.is_cancelled() {
+            return Poll::Ready(());
+        }
+        {
+            let mut wakers = self.handle.inner.wakers.lock().unwrap();
+            match wakers.get_mut(&self.id) {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => {
+                    wakers.insert(self.id, cx.waker().clone());
+                }
+            }
+        }
+        // Check again in case the handle was cancelled before the waker was registered
+        if self.handle.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.handle.inner.wakers.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// The error returned by [`LoadingHandle::run`] when the load is cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadingCancelled;
+
+impl std::fmt::Display for LoadingCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The model load was cancelled")
+    }
+}
+
+impl std::error::Error for LoadingCancelled {}
+
+/// The combined progress of every file of a model from [`LoadingHandle::status`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LoadingStatus {
+    /// The number of bytes of every file that are available, including bytes that were already cached.
+    pub downloaded: u64,
+    /// The number of bytes that were already cached when the download started.
+    pub cached: u64,
+    /// The size of every file in bytes. Files that haven't started downloading are not included.
+    pub total: u64,
+    /// The number of files that started downloading.
+    pub files: usize,
+    /// The number of files that finished downloading.
+    pub finished_files: usize,
+    /// The progress of loading the model into memory once every file is downloaded, from 0 to 1.
+    pub loading: Option<f32>,
+    /// The time since the load started.
+    pub elapsed: Duration,
+}
+
+impl LoadingStatus {
+    /// Get the fraction of the bytes that are downloaded, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 {
+            return 0.;
+        }
+        self.downloaded as f32 / self.total as f32
+    }
+
+    /// Estimate the time until every file that started downloading is finished from the download speed so far.
+    pub fn estimate_time_remaining(&self) -> Option<Duration> {
+        let downloaded = self.downloaded.saturating_sub(self.cached);
+        if downloaded == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.downloaded);
+        let seconds_per_byte = self.elapsed.as_secs_f64() / downloaded as f64;
+        Some(Duration::from_secs_f64(remaining as f64 * seconds_per_byte))
+    }
+}
+
+#[test]
+fn loading_handle_combines_files() {
+    let handle = LoadingHandle::new();
+    let mut handler = handle.handler();
+    let file = |size, progress| FileLoadingProgress {
+        start_time: Instant::now(),
+        cached_size: 0,
+        size,
+        progress,
+    };
+    handler(ModelLoadingProgress::downloading(
+        "Model".to_string(),
+        file(300, 100),
+    ));
+    handler(ModelLoadingProgress::downloading(
+        "Tokenizer".to_string(),
+        file(100, 100),
+    ));
+    let status = handle.status();
+    assert_eq!((status.downloaded, status.total), (200, 400));
+    assert_eq!((status.files, status.finished_files), (2, 1));
+    assert_eq!(status.progress(), 0.5);
+
+    assert!(!handle.is_cancelled());
+    handle.clone().cancel();
+    assert!(handle.is_cancelled());
+}
+
+#[test]
+fn polling_a_load_keeps_one_waker_per_waiter() {
+    use std::sync::atomic::AtomicUsize;
+    use std::task::Wake;
+
+    struct CountWakes(AtomicUsize);
+
+    impl Wake for CountWakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let handle = LoadingHandle::new();
+    // A load that is woken many times before it finishes, like a long download
+    let load = std::future::poll_fn(|cx| {
+        cx.waker().wake_by_ref();
+        Poll::<()>::Pending
+    });
+    let mut run = pin!(handle.run(load));
+    let wakes = Arc::new(CountWakes(AtomicUsize::new(0)));
+    for i in 0..1000 {
+        // Every other poll comes from a different waker
+        let waker = if i % 2 == 0 {
+            Waker::from(wakes.clone())
+        } else {
+            Waker::from(Arc::new(CountWakes(AtomicUsize::new(0))))
+        };
+        assert!(run
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(handle.inner.wakers.lock().unwrap().len(), 1);
+    }
+
+    // Cancelling wakes the waker of the last poll and the load stops
+    let before = wakes.0.load(Ordering::SeqCst);
+    let waker = Waker::from(wakes.clone());
+    assert!(run
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+    handle.cancel();
+    assert_eq!(wakes.0.load(Ordering::SeqCst), before + 2);
+    assert_eq!(
+        run.as_mut().poll(&mut Context::from_waker(&waker)),
+        Poll::Ready(Err(LoadingCancelled))
+    );
+
+    // A waiter that stops waiting removes its waker
+    let handle = LoadingHandle::new();
+    let mut cancelled = Box::pin(handle.cancelled());
+    assert!(cancelled
+        .as_mut()
+        .poll(&mut Context::from_waker(&waker))
+        .is_pending());
+    assert_eq!(handle.inner.wakers.lock().unwrap().len(), 1);
+    drop(cancelled);
+    assert!(handle.inner.wakers.lock().unwrap().is_empty());
+}
@@ -1,6 +1,11 @@
 //! Common types for Kalosm models
 
-use std::{fmt::Display, path::PathBuf};
+use std::{fmt::Display, path::PathBuf, sync::Arc};
+
+mod handle;
+pub use handle::*;
+mod retry;
+pub use retry::*;
 
 /// The progress starting a model
 #[derive(Clone, Debug)]
@@ -131,8 +136,8 @@ impl ModelLoadingProgress {
     }
 }
 
-/// A source for a file, either from Hugging Face or a local path
-#[derive(Clone, Debug)]
+/// A source for a file, either from Hugging Face, Ollama, a local path or memory
+#[derive(Clone)]
 pub enum FileSource {
     /// A file from Hugging Face
     HuggingFace {
@@ -145,6 +150,50 @@ pub enum FileSource {
     },
     /// A local file
     Local(PathBuf),
+    /// The model file of a model in the [Ollama library](https://ollama.com/library). If the model was already pulled
+    /// with Ollama, the local copy is used instead of downloading it again.
+    Ollama {
+        /// The name of the model like `llama3.2` or `namespace/model` for models outside of the official library
+        model: String,
+        /// The tag to use like `3b` or `latest`
+        tag: String,
+    },
+    /// A file held in memory, like a model embedded in the binary with `include_bytes!` or received over the network
+    Bytes {
+        /// The name of the file. The extension is used to detect the format of the file
+        name: String,
+        /// The contents of the file
+        bytes: Arc<[u8]>,
+    },
+}
+
+impl std::fmt::Debug for FileSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileSource::HuggingFace {
+                model_id,
+                revision,
+                file,
+            } => f
+                .debug_struct("HuggingFace")
+                .field("model_id", model_id)
+                .field("revision", revision)
+                .field("file", file)
+                .finish(),
+            FileSource::Local(path) => f.debug_tuple("Local").field(path).finish(),
+            FileSource::Ollama { model, tag } => f
+                .debug_struct("Ollama")
+                .field("model", model)
+                .field("tag", tag)
+                .finish(),
+            // Don't print the contents of the file which may be gigabytes long
+            FileSource::Bytes { name, bytes } => f
+                .debug_struct("Bytes")
+                .field("name", name)
+                .field("len", &bytes.len())
+                .finish(),
+        }
+    }
 }
 
 impl Display for FileSource {
@@ -156,6 +205,8 @@ impl Display for FileSource {
                 file,
             } => write!(f, "hf://{}/{}/{}", model_id, revision, file),
             FileSource::Local(path) => write!(f, "{}", path.display()),
+            FileSource::Ollama { model, tag } => write!(f, "ollama://{}:{}", model, tag),
+            FileSource::Bytes { name, .. } => write!(f, "memory://{}", name),
         }
     }
 }
@@ -178,4 +229,76 @@ impl FileSource {
     pub fn local(path: PathBuf) -> Self {
         Self::Local(path)
     }
+
+    /// Create a new source for a file held in memory. The name is used to detect the format of the file from its
+    /// extension, so it should match the name the file would have on disk like `model.gguf` or `tokenizer.json`.
+    ///
+    /// This makes it possible to embed small models in a single binary with `include_bytes!` or load models in unit
+    /// tests without touching the filesystem.
+    ///
+    /// ```rust, no_run
+    /// use kalosm_model_types::FileSource;
+    ///
+    /// // Files embedded with `include_bytes!("tokenizer.json")` work the same way
+    /// let config = FileSource::bytes("config.json", br#"{"hidden_size": 384}"#.as_slice());
+    /// ```
+    pub fn bytes(name: impl ToString, bytes: impl Into<Arc<[u8]>>) -> Self {
+        Self::Bytes {
+            name: name.to_string(),
+            bytes: bytes.into(),
+        }
+    }
+
+    /// Create a new source for a model in the [Ollama library](https://ollama.com/library). The name can include a
+    /// tag like `llama3.2:3b`. If there is no tag, the `latest` tag is used.
+    pub fn ollama(name: impl AsRef<str>) -> Self {
+        let name = name.as_ref().trim();
+        let (model, tag) = match name.split_once(':') {
+            Some((model, tag)) if !tag.is_empty() => (model, tag),
+            Some((model, _)) => (model, "latest"),
+            None => (name, "latest"),
+        };
+        Self::Ollama {
+            model: model.to_string(),
+            tag: tag.to_string(),
+        }
+    }
+}
+
+#[test]
+fn ollama_names_are_split_into_model_and_tag() {
+    let parts = |name: &str| match FileSource::ollama(name) {
+        FileSource::Ollama { model, tag } => (model, tag),
+        source => panic!("expected an Ollama source, found {source:?}"),
+    };
+    let expected = |model: &str, tag: &str| (model.to_string(), tag.to_string());
+    assert_eq!(parts("llama3.2"), expected("llama3.2", "latest"));
+    assert_eq!(parts("llama3.2:3b"), expected("llama3.2", "3b"));
+    assert_eq!(parts("llama3.2:"), expected("llama3.2", "latest"));
+    assert_eq!(
+        parts(" namespace/model:q4_K_M "),
+        expected("namespace/model", "q4_K_M")
+    );
+    assert_eq!(
+        FileSource::ollama("qwen2.5:0.5b").to_string(),
+        "ollama://qwen2.5:0.5b"
+    );
+}
+
+#[test]
+fn byte_sources_keep_their_contents_in_memory() {
+    let contents = b"GGUF".to_vec();
+    let source = FileSource::bytes("model.gguf", contents.clone());
+    assert_eq!(source.to_string(), "memory://model.gguf");
+    assert_eq!(
+        format!("{source:?}"),
+        r#"Bytes { name: "model.gguf", len: 4 }"#
+    );
+    match source.clone() {
+        FileSource::Bytes { name, bytes } => {
+            assert_eq!(name, "model.gguf");
+            assert_eq!(&*bytes, contents.as_slice());
+        }
+        source => panic!("expected a bytes source, found {source:?}"),
+    }
 }
@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// The reason a network request failed, used by a [`RetryPolicy`] to decide if the request should be retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The connection failed or was interrupted before a response was received.
+    Connection,
+    /// The request timed out.
+    Timeout,
+    /// The server responded with a status code.
+    Status(u16),
+}
+
+#[cfg(feature = "reqwest")]
+impl RetryReason {
+    /// Get the reason a reqwest request failed if it may succeed when retried. This can be passed directly as the
+    /// `classify` function of [`RetryPolicy::retry`].
+    pub fn from_reqwest_error(err: &reqwest::Error) -> Option<Self> {
+        if err.is_timeout() {
+            Some(Self::Timeout)
+        } else if let Some(status) = err.status() {
+            Some(Self::Status(status.as_u16()))
+        } else if err.is_connect() || err.is_request() || err.is_body() {
+            Some(Self::Connection)
+        } else {
+            None
+        }
+    }
+}
+
+/// A policy for retrying network requests that fail with transient errors. The policy is shared by the model
+/// download cache, web document sources and remote model backends.
+///
+/// Failed requests are retried with exponential backoff and random jitter so many clients that fail at the same
+/// time don't retry at the same time.
+///
+/// # Example
+/// ```rust
+/// use kalosm_model_types::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::default()
+///     .with_max_attempts(5)
+///     .with_initial_backoff(Duration::from_secs(1))
+///     .with_retry_statuses([429, 503]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    retry_connection_errors: bool,
+    retry_timeouts: bool,
+    retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retry_connection_errors: true,
+            retry_timeouts: true,
+            retry_statuses: vec![408, 425, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a new retry policy with the default settings. Requests are attempted up to 3 times.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a policy that never retries requests.
+    pub fn none() -> Self {
+        Self::default().with_max_attempts(1)
+    }
+
+    /// Set the maximum number of times a request is attempted, including the first attempt. (Defaults to 3)
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry. (Defaults to 500 milliseconds)
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Set the maximum delay between retries. (Defaults to 30 seconds)
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after each retry. (Defaults to 2)
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Set the fraction of the delay that is randomized. A jitter of 0.5 means the delay is between 50% and 100%
+    /// of the exponential backoff. (Defaults to 0.5)
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set if requests that fail to connect are retried. (Defaults to true)
+    pub fn with_retry_connection_errors(mut self, retry: bool) -> Self {
+        self.retry_connection_errors = retry;
+        self
+    }
+
+    /// Set if requests that time out are retried. (Defaults to true)
+    pub fn with_retry_timeouts(mut self, retry: bool) -> Self {
+        self.retry_timeouts = retry;
+        self
+    }
+
+    /// Set the HTTP status codes that are retried. (Defaults to 408, 425, 429, 500, 502, 503 and 504)
+    pub fn with_retry_statuses(mut self, statuses: impl IntoIterator<Item = u16>) -> Self {
+        self.retry_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Get the maximum number of times a request is attempted.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Check if a request that failed for the given reason should be retried.
+    pub fn should_retry(&self, reason: RetryReason) -> bool {
+        match reason {
+            RetryReason::Connection => self.retry_connection_errors,
+            RetryReason::Timeout => self.retry_timeouts,
+            RetryReason::Status(status) => self.retry_statuses.contains(&status),
+        }
+    }
+
+    /// Get the delay before the given retry. The first retry is retry 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponential = self.initial_backoff.as_secs_f64()
+            * self.multiplier.powi(retry.min(i32::MAX as u32) as i32);
+        let capped = exponential.min(self.max_backoff.as_secs_f64());
+        let jittered = capped * (1.0 - self.jitter * random_fraction());
+        Duration::from_secs_f64(jittered)
+    }
+
+    /// Run an operation until it succeeds, fails with an error that should not be retried, or runs out of
+    /// attempts. `classify` returns the [`RetryReason`] for an error, or `None` if the error is not transient.
+    /// `sleep` waits for a duration in your async runtime, like `tokio::time::sleep`.
+    pub async fn retry<T, E, Fut, SleepFut>(
+        &self,
+        mut operation: impl FnMut() -> Fut,
+        classify: impl Fn(&E) -> Option<RetryReason>,
+        sleep: impl Fn(Duration) -> SleepFut,
+    ) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+        SleepFut: Future<Output = ()>,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let retry = attempt < self.max_attempts
+                        && classify(&err).is_some_and(|reason| self.should_retry(reason));
+                    if !retry {
+                        return Err(err);
+                    }
+                    sleep(self.backoff(attempt - 1)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// A random value between 0 and 1. This doesn't need to be high quality randomness, it just needs to be
+/// different for different clients.
+fn random_fraction() -> f64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[test]
+fn backoff_grows_and_is_capped() {
+    let policy = RetryPolicy::default()
+        .with_initial_backoff(Duration::from_secs(1))
+        .with_max_backoff(Duration::from_secs(5))
+        .with_jitter(0.5);
+    for retry in 0..10 {
+        let expected = 2f64.powi(retry).min(5.0);
+        let backoff = policy.backoff(retry as u32).as_secs_f64();
+        assert!(backoff <= expected && backoff >= expected * 0.5);
+    }
+    assert!(policy.should_retry(RetryReason::Status(503)));
+    assert!(!policy.should_retry(RetryReason::Status(404)));
+}
@@ -28,6 +28,12 @@ impl<T> ArcLinkedList<T> {
         self.len
     }
 
+    /// Iterate over the values from the newest to the oldest.
+    pub(crate) fn iter_rev(&self) -> impl Iterator<Item = &T> + Clone {
+        std::iter::successors(self.tail.as_ref(), |node| node.prev.as_deref())
+            .map(|node| &*node.value)
+    }
+
     pub(crate) fn vec(&self) -> Vec<T>
     where
         T: Clone,
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use crate::{
+    CreateParserState, LiteralParser, LiteralParserOffset, ParseStatus, Parser, SeparatedParser,
+    SeparatedParserState,
+};
+
+/// A parser for a JSON array of items like `["a", "b", "c"]`. Unlike the parser for [`Vec`], the number of items can
+/// be limited and duplicate items can be rejected while the array is generated. The parser state exposes the items
+/// that are already finished with [`ArrayParserState::items`], so they can be used before the whole array is parsed.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = ArrayParser::new(String::new_parser())
+///     .with_max_items(2)
+///     .with_unique_items();
+/// let state = parser.create_parser_state();
+/// assert!(parser.parse(&state, br#"["a", "b"]"#).is_ok());
+/// assert!(parser.parse(&state, br#"["a", "a"]"#).is_err());
+/// assert!(parser.parse(&state, br#"["a", "b", "c"]"#).is_err());
+/// ```
+#[derive(Debug)]
+pub struct ArrayParser<P: Parser> {
+    items: SeparatedParser<Arc<P>, LiteralParser>,
+    open: LiteralParser,
+    close: LiteralParser,
+    is_duplicate: Option<fn(&P::Output, &P::Output) -> bool>,
+}
+
+impl<P: Parser> Clone for ArrayParser<P> {
+    fn clone(&self) -> Self {
+        Self {
+            items: self.items.clone(),
+            open: self.open.clone(),
+            close: self.close.clone(),
+            is_duplicate: self.is_duplicate,
+        }
+    }
+}
+
+impl<P: Parser> ArrayParser<P> {
+    /// Create a new parser for an array of any number of items.
+    pub fn new(item: P) -> Self {
+        Self {
+            items: SeparatedParser::new(Arc::new(item), LiteralParser::new(", "), 0..=usize::MAX),
+            open: LiteralParser::new("["),
+            close: LiteralParser::new("]"),
+            is_duplicate: None,
+        }
+    }
+
+    /// Require at least `min_items` items in the array.
+    pub fn with_min_items(mut self, min_items: usize) -> Self {
+        let max_items = *self.items.length_range.end();
+        self.items.length_range = min_items..=max_items;
+        self
+    }
+
+    /// Stop the array after at most `max_items` items.
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        let min_items = *self.items.length_range.start();
+        self.items.length_range = min_items..=max_items;
+        self
+    }
+
+    /// Reject any item that is equal to an item that is already in the array.
+    pub fn with_unique_items(mut self) -> Self
+    where
+        P::Output: PartialEq,
+    {
+        self.is_duplicate = Some(|first, second| first == second);
+        self
+    }
+
+    /// Check the newest `new_items` items against every item before them.
+    fn check_unique<'b>(
+        &self,
+        new_items: usize,
+        mut newest_first: impl Iterator<Item = &'b P::Output> + Clone,
+    ) -> crate::ParseResult<()>
+    where
+        P::Output: 'b,
+    {
+        let Some(is_duplicate) = self.is_duplicate else {
+            return Ok(());
+        };
+        for _ in 0..new_items {
+            let Some(item) = newest_first.next() else {
+                break;
+            };
+            if newest_first.clone().any(|other| is_duplicate(item, other)) {
+                crate::bail!(DuplicateItemError);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error an [`ArrayParser`] returns when an item is a duplicate of an earlier item.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct DuplicateItemError;
+
+impl std::fmt::Display for DuplicateItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Duplicate item in array")
+    }
+}
+
+impl std::error::Error for DuplicateItemError {}
+
+/// The state of an [`ArrayParser`].
+pub enum ArrayParserState<P: Parser> {
+    /// The opening bracket is in progress.
+    Open(LiteralParserOffset),
+    /// The items are in progress.
+    Items(SeparatedParserState<Arc<P>, LiteralParser>),
+    /// Every item is finished and the closing bracket is in progress.
+    Close(LiteralParserOffset, Vec<P::Output>),
+}
+
+impl<P: Parser + std::fmt::Debug> std::fmt::Debug for ArrayParserState<P>
+where
+    P::PartialState: std::fmt::Debug,
+    P::Output: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open(offset) => f.debug_tuple("Open").field(offset).finish(),
+            Self::Items(items) => f.debug_tuple("Items").field(items).finish(),
+            Self::Close(offset, items) => {
+                f.debug_tuple("Close").field(offset).field(items).finish()
+            }
+        }
+    }
+}
+
+impl<P: Parser + PartialEq> PartialEq for ArrayParserState<P>
+where
+    P::PartialState: PartialEq,
+    P::Output: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Open(a), Self::Open(b)) => a == b,
+            (Self::Items(a), Self::Items(b)) => a == b,
+            (Self::Close(a, a_items), Self::Close(b, b_items)) => a == b && a_items == b_items,
+            _ => false,
+        }
+    }
+}
+
+impl<P: Parser> Clone for ArrayParserState<P> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Open(offset) => Self::Open(*offset),
+            Self::Items(items) => Self::Items(items.clone()),
+            Self::Close(offset, items) => Self::Close(*offset, items.clone()),
+        }
+    }
+}
+
+impl<P: Parser> ArrayParserState<P> {
+    /// Get the items that are finished.
+    pub fn items(&self) -> Vec<P::Output> {
+        match self {
+            Self::Open(_) => Vec::new(),
+            Self::Items(items) => items.outputs.vec(),
+            Self::Close(_, items) => items.clone(),
+        }
+    }
+
+    /// Get the number of items that are finished.
+    pub fn item_count(&self) -> usize {
+        match self {
+            Self::Open(_) => 0,
+            Self::Items(items) => items.outputs.len(),
+            Self::Close(_, items) => items.len(),
+        }
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for ArrayParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        ArrayParserState::Open(self.open.create_parser_state())
+    }
+}
+
+impl<P: CreateParserState> Parser for ArrayParser<P> {
+    type Output = Vec<P::Output>;
+    type PartialState = ArrayParserState<P>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        let mut remaining = input;
+        loop {
+            match state {
+                ArrayParserState::Open(offset) => match self.open.parse(&offset, remaining)? {
+                    ParseStatus::Finished {
+                        remaining: new_remaining,
+                        ..
+                    } => {
+                        state = ArrayParserState::Items(self.items.create_parser_state());
+                        remaining = new_remaining;
+                    }
+                    ParseStatus::Incomplete {
+                        new_state,
+                        required_next,
+                    } => {
+                        return Ok(ParseStatus::Incomplete {
+                            new_state: ArrayParserState::Open(new_state),
+                            required_next,
+                        })
+                    }
+                },
+                ArrayParserState::Items(items) => {
+                    let previous_len = items.outputs.len();
+                    match self.items.parse(&items, remaining)? {
+                        ParseStatus::Finished {
+                            result,
+                            remaining: new_remaining,
+                        } => {
+                            self.check_unique(
+                                result.len().saturating_sub(previous_len),
+                                result.iter().rev(),
+                            )?;
+                            state =
+                                ArrayParserState::Close(self.close.create_parser_state(), result);
+                            remaining = new_remaining;
+                        }
+                        ParseStatus::Incomplete {
+                            new_state,
+                            required_next,
+                        } => {
+                            self.check_unique(
+                                new_state.outputs.len().saturating_sub(previous_len),
+                                new_state.outputs.iter_rev(),
+                            )?;
+                            return Ok(ParseStatus::Incomplete {
+                                new_state: ArrayParserState::Items(new_state),
+                                required_next,
+                            });
+                        }
+                    }
+                }
+                ArrayParserState::Close(offset, items) => {
+                    return match self.close.parse(&offset, remaining)? {
+                        ParseStatus::Finished { remaining, .. } => Ok(ParseStatus::Finished {
+                            result: items,
+                            remaining,
+                        }),
+                        ParseStatus::Incomplete {
+                            new_state,
+                            required_next,
+                        } => Ok(ParseStatus::Incomplete {
+                            new_state: ArrayParserState::Close(new_state, items),
+                            required_next,
+                        }),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn array_parser() {
+    use crate::{IntegerParser, Parse};
+
+    let parser = ArrayParser::new(IntegerParser::new(0..=9)).with_max_items(3);
+    let state = parser.create_parser_state();
+    assert_eq!(
+        parser.parse(&state, b"[1, 2, 3]"),
+        Ok(ParseStatus::Finished {
+            result: vec![1, 2, 3],
+            remaining: b"",
+        })
+    );
+    assert!(parser.parse(&state, b"[1, 2, 3, 4]").is_err());
+
+    // Finished items are available before the array is closed
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, b"[4, 5, ").unwrap()
+    else {
+        panic!("expected incomplete");
+    };
+    assert_eq!(new_state.items(), vec![4, 5]);
+
+    let parser = ArrayParser::new(String::new_parser()).with_unique_items();
+    let state = parser.create_parser_state();
+    assert!(parser.parse(&state, br#"["a", "b"]"#).is_ok());
+    let ParseStatus::Incomplete { new_state, .. } = parser.parse(&state, br#"["a", "#).unwrap()
+    else {
+        panic!("expected incomplete");
+    };
+    assert!(parser.parse(&new_state, br#""a""#).is_err());
+    assert!(parser.parse(&new_state, br#""ab""#).is_ok());
+}
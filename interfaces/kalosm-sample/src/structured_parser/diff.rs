@@ -0,0 +1,659 @@
+use std::{borrow::Cow, fmt::Display, sync::Arc};
+
+use crate::{CreateParserState, ParseResult, ParseStatus, Parser};
+
+/// Split a file into lines without the line endings.
+fn split_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = text.split('\n').map(String::from).collect();
+    // A trailing newline ends the last line instead of starting a new one
+    if text.is_empty() || text.ends_with('\n') {
+        lines.pop();
+    }
+    lines
+}
+
+/// A line in a [`DiffHunk`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    /// A line that is the same in the original and the edited file.
+    Context(String),
+    /// A line that was removed from the original file.
+    Removed(String),
+    /// A line that was added to the edited file.
+    Added(String),
+}
+
+impl DiffLine {
+    /// Get the text of the line without the diff prefix.
+    pub fn text(&self) -> &str {
+        match self {
+            DiffLine::Context(text) | DiffLine::Removed(text) | DiffLine::Added(text) => text,
+        }
+    }
+}
+
+impl Display for DiffLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffLine::Context(text) => write!(f, " {text}"),
+            DiffLine::Removed(text) => write!(f, "-{text}"),
+            DiffLine::Added(text) => write!(f, "+{text}"),
+        }
+    }
+}
+
+/// A hunk in a [`UnifiedDiff`]. The line numbers follow the unified diff format: they start at one, and if the hunk
+/// is empty on one side the start is the line before the hunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    old_start: usize,
+    old_len: usize,
+    new_start: usize,
+    new_len: usize,
+    lines: Vec<DiffLine>,
+}
+
+impl DiffHunk {
+    /// Get the start of the hunk in the original file.
+    pub fn old_start(&self) -> usize {
+        self.old_start
+    }
+
+    /// Get the number of lines of the original file the hunk covers.
+    pub fn old_len(&self) -> usize {
+        self.old_len
+    }
+
+    /// Get the start of the hunk in the edited file.
+    pub fn new_start(&self) -> usize {
+        self.new_start
+    }
+
+    /// Get the number of lines of the edited file the hunk covers.
+    pub fn new_len(&self) -> usize {
+        self.new_len
+    }
+
+    /// Get the lines in the hunk.
+    pub fn lines(&self) -> &[DiffLine] {
+        &self.lines
+    }
+
+    /// The index of the first line of the original file the hunk covers.
+    fn old_index(&self) -> usize {
+        if self.old_len == 0 {
+            self.old_start
+        } else {
+            self.old_start - 1
+        }
+    }
+}
+
+impl Display for DiffHunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )?;
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A unified diff parsed by [`UnifiedDiffParser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnifiedDiff {
+    path: Option<String>,
+    hunks: Vec<DiffHunk>,
+}
+
+impl UnifiedDiff {
+    /// Get the path of the file the diff edits if the diff has a file header.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Get the hunks in the diff.
+    pub fn hunks(&self) -> &[DiffHunk] {
+        &self.hunks
+    }
+
+    /// Apply the diff to the original file and return the edited file.
+    pub fn apply(&self, original: &str) -> Result<String, DiffApplyError> {
+        let lines = split_lines(original);
+        let mut output: Vec<&str> = Vec::new();
+        let mut position = 0;
+        for hunk in &self.hunks {
+            let start = hunk.old_index();
+            if start < position || start > lines.len() {
+                return Err(DiffApplyError { line: start + 1 });
+            }
+            output.extend(lines[position..start].iter().map(String::as_str));
+            position = start;
+            for line in &hunk.lines {
+                match line {
+                    DiffLine::Context(text) | DiffLine::Removed(text) => {
+                        if lines.get(position) != Some(text) {
+                            return Err(DiffApplyError { line: position + 1 });
+                        }
+                        if let DiffLine::Context(text) = line {
+                            output.push(text);
+                        }
+                        position += 1;
+                    }
+                    DiffLine::Added(text) => output.push(text),
+                }
+            }
+        }
+        output.extend(lines[position..].iter().map(String::as_str));
+
+        let mut edited = output.join("\n");
+        if !output.is_empty() && (original.is_empty() || original.ends_with('\n')) {
+            edited.push('\n');
+        }
+        Ok(edited)
+    }
+}
+
+impl Display for UnifiedDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            writeln!(f, "--- a/{path}")?;
+            writeln!(f, "+++ b/{path}")?;
+        }
+        for hunk in &self.hunks {
+            write!(f, "{hunk}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An error that can occur when applying a [`UnifiedDiff`] to a file it was not generated for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffApplyError {
+    line: usize,
+}
+
+impl DiffApplyError {
+    /// Get the line of the original file that does not match the diff.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl Display for DiffApplyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The diff does not match line {} of the original file",
+            self.line
+        )
+    }
+}
+
+impl std::error::Error for DiffApplyError {}
+
+/// An error that can occur when parsing a [`UnifiedDiff`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnifiedDiffError {
+    /// The file header did not match the path of the file.
+    FileHeader,
+    /// A hunk header was malformed or its line numbers do not fit the original file.
+    HunkHeader,
+    /// A context or removed line does not match the original file.
+    Mismatch {
+        /// The line of the original file that was expected.
+        line: usize,
+    },
+    /// A line was not valid at the current position in the diff.
+    UnexpectedLine,
+    /// The diff ended without any hunks.
+    Empty,
+}
+
+impl Display for UnifiedDiffError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnifiedDiffError::FileHeader => write!(f, "Invalid file header"),
+            UnifiedDiffError::HunkHeader => write!(f, "Invalid hunk header"),
+            UnifiedDiffError::Mismatch { line } => {
+                write!(f, "Line {line} does not match the original file")
+            }
+            UnifiedDiffError::UnexpectedLine => write!(f, "Unexpected line in diff"),
+            UnifiedDiffError::Empty => write!(f, "The diff has no hunks"),
+        }
+    }
+}
+
+impl std::error::Error for UnifiedDiffError {}
+
+/// A parser for a unified diff of a single file. The parser is created from the original file and only accepts hunks
+/// that fit it: hunk headers must be well formed with line counts that match the hunk body, hunks must be in order
+/// without overlapping, and every context and removed line must match the original file exactly. The diff ends with
+/// a blank line.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let original = "fn main() {\n    println!(\"hi\");\n}\n";
+/// let parser = UnifiedDiffParser::new(original);
+/// let state = parser.create_parser_state();
+/// let diff = parser
+///     .parse(
+///         &state,
+///         b"@@ -1,3 +1,3 @@\n fn main() {\n-    println!(\"hi\");\n+    println!(\"hello\");\n }\n\n",
+///     )
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(
+///     diff.apply(original).unwrap(),
+///     "fn main() {\n    println!(\"hello\");\n}\n"
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct UnifiedDiffParser {
+    original: Arc<[String]>,
+    path: Option<String>,
+    header: String,
+}
+
+impl UnifiedDiffParser {
+    /// Create a new parser for diffs of the original file.
+    pub fn new(original: impl AsRef<str>) -> Self {
+        Self {
+            original: split_lines(original.as_ref()).into(),
+            path: None,
+            header: String::new(),
+        }
+    }
+
+    /// Require the diff to start with a `--- a/path` and `+++ b/path` file header for the path.
+    pub fn with_path(mut self, path: impl ToString) -> Self {
+        let path = path.to_string();
+        self.header = format!("--- a/{path}\n+++ b/{path}\n");
+        self.path = Some(path);
+        self
+    }
+
+    /// Get the lines of the original file.
+    pub fn original(&self) -> &[String] {
+        &self.original
+    }
+
+    /// Check a hunk header. Returns the hunk if the header is complete and valid, or `None` if the header is a valid
+    /// prefix of a header.
+    fn parse_hunk_header(
+        &self,
+        state: &UnifiedDiffParserState,
+        line: &[u8],
+        complete: bool,
+    ) -> Result<Option<DiffHunk>, UnifiedDiffError> {
+        let mut rest = line;
+        let mut numbers = [0; 4];
+        for (i, separator) in ["@@ -", ",", " +", ",", " @@"].iter().enumerate() {
+            let separator = separator.as_bytes();
+            let matched = rest.len().min(separator.len());
+            if rest[..matched] != separator[..matched] {
+                return Err(UnifiedDiffError::HunkHeader);
+            }
+            if matched < separator.len() {
+                return match complete {
+                    true => Err(UnifiedDiffError::HunkHeader),
+                    false => Ok(None),
+                };
+            }
+            rest = &rest[separator.len()..];
+            if i == numbers.len() {
+                break;
+            }
+
+            let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+            let (number, after) = rest.split_at(digits);
+            // The number is finished once anything follows it
+            let finished = complete || !after.is_empty();
+            if (finished && digits == 0) || (digits > 1 && number[0] == b'0') || digits > 9 {
+                return Err(UnifiedDiffError::HunkHeader);
+            }
+            numbers[i] = std::str::from_utf8(number)
+                .ok()
+                .and_then(|number| number.parse().ok())
+                .unwrap_or(0);
+            self.check_hunk_number(state, &numbers, i, number, finished)?;
+            if !finished {
+                return Ok(None);
+            }
+            rest = after;
+        }
+        if !rest.is_empty() {
+            return Err(UnifiedDiffError::HunkHeader);
+        }
+        if !complete {
+            return Ok(None);
+        }
+
+        let [old_start, old_len, new_start, new_len] = numbers;
+        Ok(Some(DiffHunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+            lines: Vec::new(),
+        }))
+    }
+
+    /// Check the number at `index` in a hunk header. If the number is not finished, only check that some number
+    /// starting with the digits could be valid.
+    fn check_hunk_number(
+        &self,
+        state: &UnifiedDiffParserState,
+        numbers: &[usize; 4],
+        index: usize,
+        digits: &[u8],
+        finished: bool,
+    ) -> Result<(), UnifiedDiffError> {
+        let len = self.original.len();
+        let position = state.position;
+        let value = numbers[index];
+        let valid = match index {
+            // The old start
+            0 => value <= len && (!finished || value >= position),
+            // The old length
+            1 => {
+                let old_start = numbers[0];
+                let max = if old_start == 0 {
+                    0
+                } else {
+                    len + 1 - old_start
+                };
+                let start_valid = value == 0 || old_start > position;
+                value <= max && (!finished || start_valid)
+            }
+            // The new start
+            2 => {
+                let new_start = state.new_start(numbers[0], numbers[1]);
+                let candidates = [new_start.to_string(), (new_start + 1).to_string()];
+                candidates.iter().any(|candidate| match finished {
+                    true => candidate.as_bytes() == digits,
+                    false => candidate.as_bytes().starts_with(digits),
+                })
+            }
+            // The new length
+            _ => {
+                let new_start = state.new_start(numbers[0], numbers[1]);
+                if numbers[2] == new_start {
+                    // A hunk that starts at the line before must be empty in the new file, but not in the old file
+                    value == 0 && numbers[1] > 0
+                } else {
+                    !finished || value > 0
+                }
+            }
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(UnifiedDiffError::HunkHeader),
+        }
+    }
+
+    /// Check a partial line in a hunk body.
+    fn check_hunk_line(
+        &self,
+        state: &UnifiedDiffParserState,
+        hunk: &HunkProgress,
+        line: &[u8],
+        complete: bool,
+    ) -> Result<(), UnifiedDiffError> {
+        let Some((&prefix, text)) = line.split_first() else {
+            return Err(UnifiedDiffError::UnexpectedLine);
+        };
+        let (uses_old, uses_new) = match prefix {
+            b' ' => (true, true),
+            b'-' => (true, false),
+            b'+' => (false, true),
+            _ => return Err(UnifiedDiffError::UnexpectedLine),
+        };
+        if (uses_old && hunk.old_remaining == 0) || (uses_new && hunk.new_remaining == 0) {
+            return Err(UnifiedDiffError::UnexpectedLine);
+        }
+        if uses_old {
+            let expected = self.original[state.position].as_bytes();
+            let matches = match complete {
+                true => expected == text,
+                false => expected.starts_with(text),
+            };
+            if !matches {
+                return Err(UnifiedDiffError::Mismatch {
+                    line: state.position + 1,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Handle a complete line. Returns true if the line ends the diff.
+    fn finish_line(
+        &self,
+        state: &mut UnifiedDiffParserState,
+        line: &[u8],
+    ) -> Result<bool, UnifiedDiffError> {
+        let Some(mut hunk) = state.hunk.take() else {
+            if line.is_empty() {
+                return match state.hunks.is_empty() {
+                    true => Err(UnifiedDiffError::Empty),
+                    false => Ok(true),
+                };
+            }
+            let hunk = self
+                .parse_hunk_header(state, line, true)?
+                .ok_or(UnifiedDiffError::HunkHeader)?;
+            state.position = hunk.old_index();
+            state.hunk = Some(HunkProgress {
+                old_remaining: hunk.old_len,
+                new_remaining: hunk.new_len,
+                hunk,
+            });
+            return Ok(false);
+        };
+
+        self.check_hunk_line(state, &hunk, line, true)?;
+        let text = String::from_utf8_lossy(&line[1..]).to_string();
+        let line = match line[0] {
+            b' ' => DiffLine::Context(text),
+            b'-' => DiffLine::Removed(text),
+            _ => DiffLine::Added(text),
+        };
+        if !matches!(line, DiffLine::Added(_)) {
+            hunk.old_remaining -= 1;
+            state.position += 1;
+        }
+        if !matches!(line, DiffLine::Removed(_)) {
+            hunk.new_remaining -= 1;
+        }
+        hunk.hunk.lines.push(line);
+
+        if hunk.old_remaining == 0 && hunk.new_remaining == 0 {
+            state.offset += hunk.hunk.new_len as isize - hunk.hunk.old_len as isize;
+            state.hunks.push(hunk.hunk);
+        } else {
+            state.hunk = Some(hunk);
+        }
+        Ok(false)
+    }
+
+    /// Check the line that is currently being parsed.
+    fn check_partial_line(&self, state: &UnifiedDiffParserState) -> Result<(), UnifiedDiffError> {
+        match &state.hunk {
+            Some(hunk) => self.check_hunk_line(state, hunk, &state.line, false),
+            None => self
+                .parse_hunk_header(state, &state.line, false)
+                .map(|_| ()),
+        }
+    }
+
+    /// The text that must come next in the current line if there is only one option.
+    fn required_next(&self, state: &UnifiedDiffParserState) -> Cow<'static, str> {
+        let line = &state.line;
+        match &state.hunk {
+            Some(_) if matches!(line.first(), Some(b' ' | b'-')) => {
+                let expected = self.original[state.position].as_bytes();
+                match std::str::from_utf8(&expected[line.len() - 1..]) {
+                    Ok(rest) => Cow::Owned(format!("{rest}\n")),
+                    Err(_) => Cow::Borrowed(""),
+                }
+            }
+            None if !line.is_empty() && line.len() < 4 => Cow::Borrowed(&"@@ -"[line.len()..]),
+            _ => Cow::Borrowed(""),
+        }
+    }
+}
+
+/// A hunk that is being parsed.
+#[derive(Debug, Clone)]
+struct HunkProgress {
+    hunk: DiffHunk,
+    old_remaining: usize,
+    new_remaining: usize,
+}
+
+/// The state of the [`UnifiedDiffParser`]
+#[derive(Debug, Clone)]
+pub struct UnifiedDiffParserState {
+    header_matched: usize,
+    line: Vec<u8>,
+    /// The index of the next line of the original file
+    position: usize,
+    /// The number of lines added minus the number of lines removed by the finished hunks
+    offset: isize,
+    hunk: Option<HunkProgress>,
+    hunks: Vec<DiffHunk>,
+}
+
+impl UnifiedDiffParserState {
+    /// The start of a hunk in the new file if the hunk is empty in the new file.
+    fn new_start(&self, old_start: usize, old_len: usize) -> usize {
+        let index = if old_len == 0 {
+            old_start
+        } else {
+            old_start.saturating_sub(1)
+        };
+        (index as isize + self.offset).max(0) as usize
+    }
+}
+
+impl CreateParserState for UnifiedDiffParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        UnifiedDiffParserState {
+            header_matched: 0,
+            line: Vec::new(),
+            position: 0,
+            offset: 0,
+            hunk: None,
+            hunks: Vec::new(),
+        }
+    }
+}
+
+impl Parser for UnifiedDiffParser {
+    type Output = UnifiedDiff;
+    type PartialState = UnifiedDiffParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        let mut iter = input.iter();
+
+        let header = self.header.as_bytes();
+        while state.header_matched < header.len() {
+            let Some(&byte) = iter.next() else {
+                return Ok(ParseStatus::Incomplete {
+                    required_next: Cow::Owned(self.header[state.header_matched..].to_string()),
+                    new_state: state,
+                });
+            };
+            if byte != header[state.header_matched] {
+                crate::bail!(UnifiedDiffError::FileHeader);
+            }
+            state.header_matched += 1;
+        }
+
+        while let Some(&byte) = iter.next() {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut state.line);
+                if self.finish_line(&mut state, &line)? {
+                    return Ok(ParseStatus::Finished {
+                        result: UnifiedDiff {
+                            path: self.path.clone(),
+                            hunks: state.hunks,
+                        },
+                        remaining: iter.as_slice(),
+                    });
+                }
+            } else {
+                state.line.push(byte);
+                self.check_partial_line(&state)?;
+            }
+        }
+
+        Ok(ParseStatus::Incomplete {
+            required_next: self.required_next(&state),
+            new_state: state,
+        })
+    }
+}
+
+#[test]
+fn unified_diff_parser() {
+    let original = "fn main() {\n    println!(\"hi\");\n}\n";
+    let parser = UnifiedDiffParser::new(original).with_path("src/main.rs");
+    let state = parser.create_parser_state();
+
+    let result = parser
+        .parse(
+            &state,
+            b"--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,4 @@\n ",
+        )
+        .unwrap();
+    let (state_in_hunk, required_next) = result.unwrap_incomplete();
+    assert_eq!(required_next, "fn main() {\n");
+
+    let diff = parser
+        .parse(
+            &state_in_hunk,
+            b"fn main() {\n-    println!(\"hi\");\n+    println!(\"hello\");\n+    println!(\"world\");\n }\n\n",
+        )
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(diff.hunks().len(), 1);
+    assert_eq!(
+        diff.apply(original).unwrap(),
+        "fn main() {\n    println!(\"hello\");\n    println!(\"world\");\n}\n"
+    );
+    assert_eq!(
+        diff.to_string(),
+        "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,4 @@\n fn main() {\n-    println!(\"hi\");\n+    println!(\"hello\");\n+    println!(\"world\");\n }\n"
+    );
+
+    let header = b"--- a/src/main.rs\n+++ b/src/main.rs\n";
+    let state = parser.parse(&state, header).unwrap().unwrap_incomplete().0;
+    // The new start does not line up with the old start
+    assert!(parser.parse(&state, b"@@ -1,3 +2,3 @@\n").is_err());
+    // The hunk runs past the end of the file
+    assert!(parser.parse(&state, b"@@ -2,3").is_err());
+    // The context line does not match the original file
+    assert!(parser.parse(&state, b"@@ -1,1 +1,1 @@\n fn mian").is_err());
+    // Insert a line after the first line
+    let diff = parser
+        .parse(&state, b"@@ -1,0 +2,1 @@\n+    // greet\n\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(
+        diff.apply(original).unwrap(),
+        "fn main() {\n    // greet\n    println!(\"hi\");\n}\n"
+    );
+}
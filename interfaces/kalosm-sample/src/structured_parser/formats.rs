@@ -0,0 +1,448 @@
+use std::sync::Arc;
+
+use crate::{CreateParserState, ParseResult, ParseStatus, Parser, RegexParser, RegexParserState};
+
+/// Escape a string so it matches literally in a regex.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        if r"\.+*?()|[]{}^$#&-~".contains(char) {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// A regex that matches any of the names literally.
+fn any_of<'a>(names: impl IntoIterator<Item = &'a String>) -> String {
+    let names: Vec<_> = names.into_iter().map(|name| escape(name)).collect();
+    format!("(?:{})", names.join("|"))
+}
+
+/// Implement the parser traits for a format parser that wraps a regex.
+macro_rules! format_parser {
+    ($name:ident, $finish:expr) => {
+        impl CreateParserState for $name {
+            fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+                self.regex.create_parser_state()
+            }
+        }
+
+        impl Parser for $name {
+            type Output = String;
+            type PartialState = RegexParserState;
+
+            fn parse<'a>(
+                &self,
+                state: &Self::PartialState,
+                input: &'a [u8],
+            ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+                let finish: fn(String) -> String = $finish;
+                self.regex
+                    .parse(state, input)
+                    .map(|status| status.map(finish))
+            }
+        }
+    };
+}
+
+/// Remove the blank line that ends a multi-line document.
+fn trim_document(document: String) -> String {
+    let mut document = document.trim_end().to_string();
+    document.push('\n');
+    document
+}
+
+/// A parser for a subset of SQL. The parser accepts a single `SELECT`, `INSERT`, `UPDATE` or `DELETE` statement
+/// that ends with a semicolon. Keywords are case insensitive.
+///
+/// Table and column names can be restricted with [`SqlParser::with_tables`] and [`SqlParser::with_columns`] so
+/// the model can only reference parts of your schema that exist.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = SqlParser::new()
+///     .with_tables(["users", "orders"])
+///     .with_columns(["id", "name", "user_id", "total"]);
+/// let state = parser.create_parser_state();
+/// let query = parser
+///     .parse(&state, b"SELECT name FROM users WHERE id = 1;")
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(query, "SELECT name FROM users WHERE id = 1;");
+/// assert!(parser.parse(&state, b"SELECT name FROM accounts;").is_err());
+/// ```
+#[derive(Clone)]
+pub struct SqlParser {
+    regex: Arc<RegexParser>,
+    tables: Option<Vec<String>>,
+    columns: Option<Vec<String>>,
+}
+
+impl Default for SqlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SqlParser {
+    /// Create a parser that accepts any table or column name.
+    pub fn new() -> Self {
+        Self::build(None, None)
+    }
+
+    /// Only allow the given table names.
+    pub fn with_tables<S: ToString>(self, tables: impl IntoIterator<Item = S>) -> Self {
+        let tables = tables.into_iter().map(|table| table.to_string()).collect();
+        Self::build(Some(tables), self.columns)
+    }
+
+    /// Only allow the given column names.
+    pub fn with_columns<S: ToString>(self, columns: impl IntoIterator<Item = S>) -> Self {
+        let columns = columns
+            .into_iter()
+            .map(|column| column.to_string())
+            .collect();
+        Self::build(self.tables, Some(columns))
+    }
+
+    fn build(tables: Option<Vec<String>>, columns: Option<Vec<String>>) -> Self {
+        let regex = Self::regex(tables.as_deref(), columns.as_deref());
+        Self {
+            regex: Arc::new(RegexParser::new(&regex).expect("the sql grammar is a valid regex")),
+            tables,
+            columns,
+        }
+    }
+
+    fn regex(tables: Option<&[String]>, columns: Option<&[String]>) -> String {
+        let ident = r#"(?:[A-Za-z_][A-Za-z0-9_]*|"[A-Za-z_][A-Za-z0-9_ ]*")"#;
+        let table = tables.map(any_of).unwrap_or_else(|| ident.to_string());
+        let column = columns.map(any_of).unwrap_or_else(|| ident.to_string());
+        let ws = r"[ \n]+";
+        let column = format!(r"(?:{ident}\.)?{column}");
+        let literal = r"(?:-?[0-9]+(?:\.[0-9]+)?|'(?:[^'\n]|'')*'|NULL|TRUE|FALSE)";
+        let aggregate = format!(r"(?:COUNT|SUM|AVG|MIN|MAX)\((?:\*|(?:DISTINCT )?{column})\)");
+        let operand = format!("(?:{column}|{literal}|{aggregate})");
+        let comparison = format!(
+            r"(?:{operand} ?(?:=|!=|<>|<=|>=|<|>) ?{operand}|{operand}{ws}(?:NOT )?LIKE{ws}'(?:[^'\n]|'')*'|{operand}{ws}IS(?: NOT)? NULL|{operand}{ws}(?:NOT )?IN ?\({literal}(?:, ?{literal})*\))"
+        );
+        let condition = format!("{comparison}(?:{ws}(?:AND|OR){ws}{comparison})*");
+        let alias = format!("(?:{ws}(?:AS{ws})?{ident})?");
+        let selected = format!(r"(?:{operand}|\*)(?:{ws}AS{ws}{ident})?");
+        let columns = format!("{column}(?:, ?{column})*");
+        let join = format!(
+            "(?:{ws}(?:(?:INNER|LEFT|RIGHT)(?: OUTER)?{ws})?JOIN{ws}{table}{alias}{ws}ON{ws}{condition})"
+        );
+        let order = format!("{operand}(?:{ws}(?:ASC|DESC))?");
+        let select = format!(
+            "SELECT{ws}(?:DISTINCT{ws})?{selected}(?:, ?{selected})*{ws}FROM{ws}{table}{alias}{join}*\
+            (?:{ws}WHERE{ws}{condition})?\
+            (?:{ws}GROUP BY{ws}{columns}(?:{ws}HAVING{ws}{condition})?)?\
+            (?:{ws}ORDER BY{ws}{order}(?:, ?{order})*)?\
+            (?:{ws}LIMIT{ws}[0-9]+(?:{ws}OFFSET{ws}[0-9]+)?)?"
+        );
+        let values = format!(r"\({literal}(?:, ?{literal})*\)");
+        let insert =
+            format!(r"INSERT INTO{ws}{table} ?\({columns}\){ws}VALUES ?{values}(?:, ?{values})*");
+        let update = format!(
+            "UPDATE{ws}{table}{ws}SET{ws}{column} ?= ?{operand}(?:, ?{column} ?= ?{operand})*(?:{ws}WHERE{ws}{condition})?"
+        );
+        let delete = format!("DELETE FROM{ws}{table}(?:{ws}WHERE{ws}{condition})?");
+        format!("(?i)(?:{select}|{insert}|{update}|{delete});")
+    }
+}
+
+format_parser!(SqlParser, |statement| statement);
+
+/// A parser for TOML documents. Documents contain key/value pairs, `[table]` and `[[array]]` headers and
+/// comments. Values can be strings, numbers, booleans, dates, arrays of values, or inline tables.
+///
+/// The document ends with a blank line, so tables in the document are not separated by blank lines.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = TomlParser::cargo_manifest();
+/// let state = parser.create_parser_state();
+/// let manifest = parser
+///     .parse(
+///         &state,
+///         b"[package]\nname = \"fetch\"\nversion = \"0.1.0\"\nedition = \"2021\"\n[dependencies]\nreqwest = \"0.12\"\n\n",
+///     )
+///     .unwrap()
+///     .unwrap_finished();
+/// assert!(manifest.ends_with("[dependencies]\nreqwest = \"0.12\"\n"));
+/// ```
+#[derive(Clone)]
+pub struct TomlParser {
+    regex: Arc<RegexParser>,
+}
+
+impl Default for TomlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TomlParser {
+    /// Create a parser for any TOML document.
+    pub fn new() -> Self {
+        Self::with_prefix("")
+    }
+
+    /// Create a parser for a `Cargo.toml` manifest. The manifest starts with a `[package]` table with a name,
+    /// version and edition followed by any other tables.
+    pub fn cargo_manifest() -> Self {
+        Self::with_prefix(
+            r#"\[package\]\nname = "[a-z][a-z0-9_-]*"\nversion = "[0-9]+\.[0-9]+\.[0-9]+"\nedition = "20(?:15|18|21|24)"\n"#,
+        )
+    }
+
+    fn with_prefix(prefix: &str) -> Self {
+        let string = r#"(?:"(?:[^"\\\n]|\\.)*"|'[^'\n]*')"#;
+        let key_part = format!("(?:[A-Za-z0-9_-]+|{string})");
+        let key = format!(r"{key_part}(?:\.{key_part})*");
+        let number = r"[+-]?(?:0|[1-9][0-9_]*)(?:\.[0-9]+)?(?:[eE][+-]?[0-9]+)?";
+        let date = r"[0-9]{4}-[0-9]{2}-[0-9]{2}(?:[T ][0-9]{2}:[0-9]{2}:[0-9]{2}(?:\.[0-9]+)?(?:Z|[+-][0-9]{2}:[0-9]{2})?)?";
+        let scalar = format!("(?:{string}|{number}|true|false|{date})");
+        let array = format!(r"\[ ?(?:{scalar}(?:, ?{scalar})*,?)? ?\]");
+        let inline_table = format!(r"\{{ ?(?:{key} ?= ?{scalar}(?:, ?{key} ?= ?{scalar})*)? ?\}}");
+        let value = format!("(?:{scalar}|{array}|{inline_table})");
+        let line =
+            format!(r"(?:{key} ?= ?{value}(?: +#[^\n]*)?|\[{key}\]|\[\[{key}\]\]|#[^\n]*)\n");
+        let regex = format!(r"{prefix}(?:{line})+\n");
+        Self {
+            regex: Arc::new(RegexParser::new(&regex).expect("the toml grammar is a valid regex")),
+        }
+    }
+}
+
+format_parser!(TomlParser, trim_document);
+
+/// A parser for YAML documents made of block mappings and sequences. Values can be plain or quoted scalars, or
+/// flow sequences like `[a, b]`. Each level of nesting is indented with two spaces.
+///
+/// The document ends with a blank line.
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = YamlParser::new().with_max_depth(2);
+/// let state = parser.create_parser_state();
+/// let config = parser
+///     .parse(&state, b"db:\n  image: postgres\n  ports: [5432]\n\n")
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(config, "db:\n  image: postgres\n  ports: [5432]\n");
+/// ```
+#[derive(Clone)]
+pub struct YamlParser {
+    regex: Arc<RegexParser>,
+    max_depth: usize,
+}
+
+impl Default for YamlParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl YamlParser {
+    /// Create a parser for YAML documents with up to 3 levels of nesting.
+    pub fn new() -> Self {
+        Self::build(3)
+    }
+
+    /// Set the maximum number of nested mappings or sequences. Each level makes the grammar larger, so keep this
+    /// as small as your documents allow. (Defaults to 3)
+    pub fn with_max_depth(self, max_depth: usize) -> Self {
+        Self::build(max_depth)
+    }
+
+    /// Get the maximum number of nested mappings or sequences.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    fn build(max_depth: usize) -> Self {
+        let max_depth = max_depth.max(1);
+        let regex = format!(r"(?:---\n)?{}\n", Self::block(0, max_depth));
+        Self {
+            regex: Arc::new(RegexParser::new(&regex).expect("the yaml grammar is a valid regex")),
+            max_depth,
+        }
+    }
+
+    /// A mapping or sequence where every line is indented by `indent` spaces.
+    fn block(indent: usize, depth: usize) -> String {
+        let spaces = " ".repeat(indent);
+        let quoted = r#"(?:"(?:[^"\\\n]|\\.)*"|'(?:[^'\n]|'')*')"#;
+        let plain = r#"[^\s"'#\[\]{},&*!|>%@`-][^\n#:]*"#;
+        let flow_item =
+            r#"(?:"(?:[^"\\\n]|\\.)*"|'(?:[^'\n]|'')*'|[^\s"'#\[\]{},:-][^\n#:,\[\]{}]*)"#;
+        let flow = format!(r"\[(?:{flow_item}(?:, ?{flow_item})*)?\]");
+        let value = format!("(?:{quoted}|{plain}|{flow})");
+        let key = format!("(?:[A-Za-z_][A-Za-z0-9_.-]*|{quoted})");
+        let inline_entry = format!("{key}: {value}\n");
+
+        let entry = if depth > 1 {
+            let child = Self::block(indent + 2, depth - 1);
+            format!("{spaces}{key}:(?: {value}\n|\n{child})")
+        } else {
+            format!("{spaces}{inline_entry}")
+        };
+        // Sequence items can be a value or a flat mapping
+        let item = format!("{spaces}- (?:{value}\n|{inline_entry}(?:{spaces}  {inline_entry})*)");
+        format!("(?:(?:{entry})+|(?:{item})+)")
+    }
+}
+
+format_parser!(YamlParser, trim_document);
+
+/// A parser for CSV rows with a fixed header. The header is always the first line of the output and every row
+/// has one field for each column. Fields can be unquoted, or quoted with `"` if they contain commas or quotes.
+///
+/// The rows end with a blank line unless a fixed number of rows is set with [`CsvParser::with_rows`].
+///
+/// # Example
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// let parser = CsvParser::new(["country", "area_km2"]).with_rows(2);
+/// let state = parser.create_parser_state();
+/// let csv = parser
+///     .parse(&state, b"country,area_km2\nRussia,17098246\nCanada,9984670\n")
+///     .unwrap()
+///     .unwrap_finished();
+/// assert_eq!(csv, "country,area_km2\nRussia,17098246\nCanada,9984670\n");
+/// ```
+#[derive(Clone)]
+pub struct CsvParser {
+    regex: Arc<RegexParser>,
+    header: Vec<String>,
+    rows: Option<usize>,
+}
+
+impl CsvParser {
+    /// Create a parser for rows with the given column names.
+    pub fn new<S: ToString>(header: impl IntoIterator<Item = S>) -> Self {
+        let header = header
+            .into_iter()
+            .map(|column| column.to_string())
+            .collect();
+        Self::build(header, None)
+    }
+
+    /// Require exactly this many rows after the header.
+    pub fn with_rows(self, rows: usize) -> Self {
+        Self::build(self.header, Some(rows))
+    }
+
+    /// Get the column names of the header.
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    /// Get the number of rows required after the header, if it is fixed.
+    pub fn rows(&self) -> Option<usize> {
+        self.rows
+    }
+
+    fn build(header: Vec<String>, rows: Option<usize>) -> Self {
+        let header_line = header
+            .iter()
+            .map(|column| {
+                if column.contains([',', '"', '\n']) {
+                    format!("\"{}\"", column.replace('"', "\"\""))
+                } else {
+                    column.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let quoted = r#""(?:[^"]|"")*""#;
+        // A row with a single column can't be empty or it would look like the end of the rows
+        let field = if header.len() > 1 {
+            format!(r#"(?:[^,"\n]*|{quoted})"#)
+        } else {
+            format!(r#"(?:[^,"\n]+|{quoted})"#)
+        };
+        let row = vec![field; header.len().max(1)].join(",") + r"\n";
+        let rows_regex = match rows {
+            Some(rows) => format!("(?:{row}){{{rows}}}"),
+            None => format!(r"(?:{row})+\n"),
+        };
+        let regex = format!(r"{}\n{rows_regex}", escape(&header_line));
+        Self {
+            regex: Arc::new(RegexParser::new(&regex).expect("the csv grammar is a valid regex")),
+            header,
+            rows,
+        }
+    }
+}
+
+format_parser!(CsvParser, trim_document);
+
+#[test]
+fn sql_parser() {
+    let parser = SqlParser::new().with_tables(["users"]);
+    let state = parser.create_parser_state();
+    let result = parser
+        .parse(
+            &state,
+            b"select name, COUNT(*) from users u where u.age >= 18 AND name LIKE 'A%' group by name limit 5;",
+        )
+        .unwrap();
+    assert!(matches!(result, ParseStatus::Finished { .. }));
+    assert!(parser.parse(&state, b"SELECT * FROM accounts;").is_err());
+    let result = parser
+        .parse(&state, b"INSERT INTO users (id, name) VALUES (1, 'Ann');")
+        .unwrap();
+    assert!(matches!(result, ParseStatus::Finished { .. }));
+}
+
+#[test]
+fn csv_parser() {
+    let parser = CsvParser::new(["name", "age"]);
+    let state = parser.create_parser_state();
+    let result = parser.parse(&state, b"").unwrap();
+    match result {
+        ParseStatus::Incomplete { required_next, .. } => assert_eq!(required_next, "name,age\n"),
+        _ => panic!("expected the header to be required"),
+    }
+    let result = parser
+        .parse(&state, b"name,age\nAnn,31\n\"Smith, Bob\",\n\nmore")
+        .unwrap();
+    assert_eq!(
+        result,
+        ParseStatus::Finished {
+            result: "name,age\nAnn,31\n\"Smith, Bob\",\n".to_string(),
+            remaining: b"more"
+        }
+    );
+    assert!(parser.parse(&state, b"name,age\nAnn,31,extra\n").is_err());
+}
+
+#[test]
+fn toml_and_yaml_parsers() {
+    let parser = TomlParser::cargo_manifest();
+    let state = parser.create_parser_state();
+    let manifest = b"[package]\nname = \"fetch\"\nversion = \"0.1.0\"\nedition = \"2021\"\n[dependencies]\nreqwest = { version = \"0.12\", default-features = false }\nfeatures = [\"a\", \"b\"]\n\n";
+    let result = parser.parse(&state, manifest).unwrap();
+    assert!(matches!(result, ParseStatus::Finished { .. }));
+
+    let parser = YamlParser::new();
+    let state = parser.create_parser_state();
+    let document = b"services:\n  db:\n    image: postgres\n    ports: [5432]\n  env:\n    - name: USER\n      value: admin\n\n";
+    let result = parser.parse(&state, document).unwrap();
+    assert!(matches!(result, ParseStatus::Finished { .. }));
+    assert!(parser
+        .parse(&state, b"services:\n   bad: indent\n")
+        .is_err());
+}
@@ -0,0 +1,680 @@
+use serde_json::{Map, Number, Value};
+
+use crate::{
+    AnyOfSchema, ArcParser, ArraySchema, BooleanSchema, ConstSchema, EnumSchema, FloatParser,
+    IndexParser, IntegerParser, IntegerSchema, JsonObjectSchema, JsonPropertySchema, LiteralParser,
+    NumberSchema, OneOfSchema, ParserExt, SchemaLiteral, SchemaType, SeparatedParser, StringParser,
+    StringSchema,
+};
+
+/// An error that can occur when creating a parser from a [`SchemaType`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum FromSchemaError {
+    /// A string pattern in the schema is not a valid regex
+    InvalidPattern(String),
+    /// An enum, anyOf or oneOf schema has no options
+    EmptyChoice,
+    /// A JSON Schema is not valid
+    InvalidSchema(String),
+    /// A JSON Schema uses a feature that constrained generation doesn't support
+    Unsupported(String),
+    /// A `$ref` in a JSON Schema doesn't point to a schema in the same document
+    UnresolvedReference(String),
+    /// A `$ref` in a JSON Schema refers to itself
+    RecursiveReference(String),
+}
+
+impl std::fmt::Display for FromSchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FromSchemaError::InvalidPattern(error) => write!(f, "Invalid pattern: {error}"),
+            FromSchemaError::EmptyChoice => write!(f, "Schema choice has no options"),
+            FromSchemaError::InvalidSchema(error) => write!(f, "Invalid JSON Schema: {error}"),
+            FromSchemaError::Unsupported(feature) => {
+                write!(f, "Unsupported JSON Schema feature: {feature}")
+            }
+            FromSchemaError::UnresolvedReference(reference) => {
+                write!(f, "Failed to resolve JSON Schema reference {reference}")
+            }
+            FromSchemaError::RecursiveReference(reference) => {
+                write!(
+                    f,
+                    "Recursive JSON Schema reference {reference} is not supported"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FromSchemaError {}
+
+impl ArcParser<Value> {
+    /// Create a parser from a schema that is only known at runtime. The parser generates JSON in the same format as
+    /// the [`Parse`](crate::Parse) derive macro and outputs the parsed [`Value`].
+    ///
+    /// Every property of an object is generated, even if it is not required.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// let schema = SchemaType::Object(JsonObjectSchema::new([
+    ///     JsonPropertySchema::new("name", SchemaType::String(StringSchema::new())).with_required(true),
+    ///     JsonPropertySchema::new("tags", SchemaType::Array(ArraySchema::new(SchemaType::String(StringSchema::new())))),
+    /// ]));
+    /// let parser = ArcParser::from_schema(&schema).unwrap();
+    /// let state = parser.create_parser_state();
+    /// let result = parser.parse(&state, br#"{ "name": "kalosm", "tags": ["rust"] }"#).unwrap();
+    /// assert_eq!(result.unwrap_finished()["tags"][0], "rust");
+    /// ```
+    pub fn from_schema(schema: &SchemaType) -> Result<Self, FromSchemaError> {
+        let parser = match schema {
+            SchemaType::String(schema) => match &schema.pattern {
+                Some(pattern) => {
+                    let pattern = pattern.trim_start_matches('^').trim_end_matches('$');
+                    crate::RegexParser::new(&format!("\"(?:{pattern})\""))
+                        .map_err(|err| FromSchemaError::InvalidPattern(err.to_string()))?
+                        .map_output(|string| Value::String(string[1..string.len() - 1].to_string()))
+                        .boxed()
+                }
+                None => StringParser::new(schema.length.clone().unwrap_or(0..=usize::MAX))
+                    .map_output(Value::String)
+                    .boxed(),
+            },
+            SchemaType::Number(schema) => {
+                FloatParser::new(schema.range.clone().unwrap_or(f64::MIN..=f64::MAX))
+                    .map_output(|number| {
+                        Number::from_f64(number)
+                            .map(Value::Number)
+                            .unwrap_or(Value::Null)
+                    })
+                    .boxed()
+            }
+            SchemaType::Integer(schema) => {
+                let range = schema.range.clone().unwrap_or(i64::MIN..=i64::MAX);
+                IntegerParser::new(*range.start() as i128..=*range.end() as i128)
+                    .map_output(|number| Value::from(number as i64))
+                    .boxed()
+            }
+            SchemaType::Boolean(_) => LiteralParser::new("true")
+                .map_output(|_| Value::Bool(true))
+                .or(LiteralParser::new("false").map_output(|_| Value::Bool(false)))
+                .boxed(),
+            SchemaType::Null => literal_parser(&SchemaLiteral::Null),
+            SchemaType::Const(schema) => literal_parser(&schema.value),
+            SchemaType::Enum(schema) => {
+                choice_parser(schema.variants.iter().map(literal_parser).collect())?
+            }
+            SchemaType::AnyOf(schema) => choice_parser(
+                schema
+                    .any_of
+                    .iter()
+                    .map(Self::from_schema)
+                    .collect::<Result<_, _>>()?,
+            )?,
+            SchemaType::OneOf(schema) => choice_parser(
+                schema
+                    .one_of
+                    .iter()
+                    .map(Self::from_schema)
+                    .collect::<Result<_, _>>()?,
+            )?,
+            // Any value that matches the then schema is valid whether or not it matches the if schema
+            SchemaType::IfThen(schema) => Self::from_schema(&schema.then_schema)?,
+            SchemaType::Array(schema) => {
+                let items = Self::from_schema(&schema.items)?;
+                LiteralParser::new("[")
+                    .ignore_output_then(SeparatedParser::new(
+                        items,
+                        LiteralParser::new(", "),
+                        schema.length.clone().unwrap_or(0..=usize::MAX),
+                    ))
+                    .then_literal("]")
+                    .map_output(Value::Array)
+                    .boxed()
+            }
+            SchemaType::Object(schema) => {
+                if schema.properties.is_empty() {
+                    return Ok(LiteralParser::new("{}")
+                        .map_output(|_| Value::Object(Map::new()))
+                        .boxed());
+                }
+                let mut parser: Option<ArcParser<Map<String, Value>>> = None;
+                for (i, property) in schema.properties.iter().enumerate() {
+                    let separator = if i == 0 { "{ " } else { ", " };
+                    let key = Value::String(property.name.clone()).to_string();
+                    let name = property.name.clone();
+                    let field = LiteralParser::new(format!("{separator}{key}: "))
+                        .ignore_output_then(Self::from_schema(&property.ty)?);
+                    parser = Some(match parser {
+                        None => field
+                            .map_output(move |value| {
+                                let mut object = Map::new();
+                                object.insert(name.clone(), value);
+                                object
+                            })
+                            .boxed(),
+                        Some(parser) => parser
+                            .then(field)
+                            .map_output(move |(mut object, value)| {
+                                object.insert(name.clone(), value);
+                                object
+                            })
+                            .boxed(),
+                    });
+                }
+                let parser = parser.expect("objects with properties always create a parser");
+                parser.then_literal(" }").map_output(Value::Object).boxed()
+            }
+        };
+        Ok(parser)
+    }
+
+    /// Create a parser from a JSON Schema that is only known at runtime, like a user supplied `json_schema` response
+    /// format. See [`SchemaType::from_json_schema`] for the parts of JSON Schema that are supported.
+    ///
+    /// # Example
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// let schema = serde_json::json!({
+    ///     "type": "object",
+    ///     "properties": {
+    ///         "city": { "type": "string" },
+    ///         "population": { "type": "integer" }
+    ///     },
+    ///     "required": ["city", "population"]
+    /// });
+    /// let parser = ArcParser::from_json_schema(&schema).unwrap();
+    /// let state = parser.create_parser_state();
+    /// let result = parser.parse(&state, br#"{ "city": "Paris", "population": 2102650 }"#).unwrap();
+    /// assert_eq!(result.unwrap_finished()["city"], "Paris");
+    /// ```
+    pub fn from_json_schema(schema: &Value) -> Result<Self, FromSchemaError> {
+        Self::from_schema(&SchemaType::from_json_schema(schema)?)
+    }
+}
+
+impl SchemaType {
+    /// Read a JSON Schema that is only known at runtime.
+    ///
+    /// The `type`, `enum`, `const`, `anyOf`, `oneOf` and single schema `allOf` keywords are supported along with
+    /// the length and range limits of strings, numbers and arrays. `$ref`s to other parts of the same document, like
+    /// `#/$defs/Address`, are inlined, but recursive references are an error. Keywords that don't change the shape of
+    /// the JSON, like `description`, are ignored.
+    ///
+    /// The properties of an object are generated in the order they are listed in `required`, followed by the optional
+    /// properties in the order of the `properties` map.
+    pub fn from_json_schema(schema: &Value) -> Result<Self, FromSchemaError> {
+        JsonSchemaReader {
+            root: schema,
+            resolving: Vec::new(),
+        }
+        .read(schema)
+    }
+}
+
+/// Reads a JSON Schema document into a [`SchemaType`], keeping track of the references that are being inlined.
+struct JsonSchemaReader<'a> {
+    root: &'a Value,
+    resolving: Vec<&'a str>,
+}
+
+impl<'a> JsonSchemaReader<'a> {
+    fn read(&mut self, schema: &'a Value) -> Result<SchemaType, FromSchemaError> {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(_) => return Err(FromSchemaError::Unsupported("boolean schemas".into())),
+            other => {
+                return Err(FromSchemaError::InvalidSchema(format!(
+                    "expected a schema object, found {other}"
+                )))
+            }
+        };
+
+        if let Some(reference) = schema.get("$ref") {
+            let reference = reference
+                .as_str()
+                .ok_or_else(|| invalid_keyword("$ref", reference))?;
+            return self.resolve(reference);
+        }
+        if let Some(value) = schema.get("const") {
+            return Ok(SchemaType::Const(ConstSchema::new(schema_literal(value)?)));
+        }
+        if let Some(variants) = schema.get("enum") {
+            let variants = variants
+                .as_array()
+                .ok_or_else(|| invalid_keyword("enum", variants))?
+                .iter()
+                .map(schema_literal)
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(SchemaType::Enum(EnumSchema::new(variants)));
+        }
+        if let Some(options) = schema.get("anyOf") {
+            let options = self.read_all("anyOf", options)?;
+            return Ok(SchemaType::AnyOf(AnyOfSchema::new(options)));
+        }
+        if let Some(options) = schema.get("oneOf") {
+            let options = self.read_all("oneOf", options)?;
+            return Ok(SchemaType::OneOf(OneOfSchema::new(options)));
+        }
+        if let Some(all_of) = schema.get("allOf") {
+            let mut all_of = self.read_all("allOf", all_of)?;
+            if all_of.len() != 1 {
+                return Err(FromSchemaError::Unsupported(
+                    "allOf with more than one schema".into(),
+                ));
+            }
+            return Ok(all_of.remove(0));
+        }
+
+        match schema.get("type") {
+            Some(Value::String(ty)) => self.read_type(ty, schema),
+            Some(Value::Array(types)) => {
+                let options = types
+                    .iter()
+                    .map(|ty| match ty {
+                        Value::String(ty) => self.read_type(ty, schema),
+                        other => Err(invalid_keyword("type", other)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(SchemaType::AnyOf(AnyOfSchema::new(options)))
+            }
+            Some(other) => Err(invalid_keyword("type", other)),
+            None if schema.contains_key("properties") => self.read_type("object", schema),
+            None if schema.contains_key("items") => self.read_type("array", schema),
+            None => Err(FromSchemaError::Unsupported(
+                "schemas without a type".into(),
+            )),
+        }
+    }
+
+    fn read_all(
+        &mut self,
+        keyword: &str,
+        schemas: &'a Value,
+    ) -> Result<Vec<SchemaType>, FromSchemaError> {
+        schemas
+            .as_array()
+            .ok_or_else(|| invalid_keyword(keyword, schemas))?
+            .iter()
+            .map(|schema| self.read(schema))
+            .collect()
+    }
+
+    fn read_type(
+        &mut self,
+        ty: &str,
+        schema: &'a Map<String, Value>,
+    ) -> Result<SchemaType, FromSchemaError> {
+        Ok(match ty {
+            "string" => {
+                let mut string = StringSchema::new().with_length(length_range(
+                    schema,
+                    "minLength",
+                    "maxLength",
+                )?);
+                if let Some(pattern) = schema.get("pattern") {
+                    let pattern = pattern
+                        .as_str()
+                        .ok_or_else(|| invalid_keyword("pattern", pattern))?;
+                    string = string.with_pattern(pattern);
+                }
+                SchemaType::String(string)
+            }
+            "number" => {
+                let minimum = number_bound(schema, "minimum")?;
+                let maximum = number_bound(schema, "maximum")?;
+                let range = (minimum.is_some() || maximum.is_some())
+                    .then(|| minimum.unwrap_or(f64::MIN)..=maximum.unwrap_or(f64::MAX));
+                SchemaType::Number(NumberSchema::new().with_range(range))
+            }
+            "integer" => {
+                // Fractional bounds are rounded to the nearest integer inside the range
+                let minimum = [
+                    number_bound(schema, "minimum")?.map(f64::ceil),
+                    number_bound(schema, "exclusiveMinimum")?.map(|bound| bound.floor() + 1.),
+                ]
+                .into_iter()
+                .flatten()
+                .reduce(f64::max);
+                let maximum = [
+                    number_bound(schema, "maximum")?.map(f64::floor),
+                    number_bound(schema, "exclusiveMaximum")?.map(|bound| bound.ceil() - 1.),
+                ]
+                .into_iter()
+                .flatten()
+                .reduce(f64::min);
+                let range = (minimum.is_some() || maximum.is_some()).then(|| {
+                    minimum.map_or(i64::MIN, |minimum| minimum as i64)
+                        ..=maximum.map_or(i64::MAX, |maximum| maximum as i64)
+                });
+                if let Some(range) = &range {
+                    if range.is_empty() {
+                        return Err(FromSchemaError::InvalidSchema(format!(
+                            "no integer is between {} and {}",
+                            range.start(),
+                            range.end()
+                        )));
+                    }
+                }
+                SchemaType::Integer(IntegerSchema::new().with_range(range))
+            }
+            "boolean" => SchemaType::Boolean(BooleanSchema),
+            "null" => SchemaType::Null,
+            "array" => {
+                let items = schema.get("items").ok_or_else(|| {
+                    FromSchemaError::Unsupported("arrays without an items schema".into())
+                })?;
+                SchemaType::Array(
+                    ArraySchema::new(self.read(items)?)
+                        .with_length(length_range(schema, "minItems", "maxItems")?),
+                )
+            }
+            "object" => {
+                let required = match schema.get("required") {
+                    Some(required) => required
+                        .as_array()
+                        .ok_or_else(|| invalid_keyword("required", required))?
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let properties = match schema.get("properties") {
+                    Some(Value::Object(properties)) => {
+                        // Maps don't keep the order their keys were written in, but the required list does
+                        let mut names: Vec<&str> = Vec::new();
+                        for name in required
+                            .iter()
+                            .copied()
+                            .chain(properties.keys().map(String::as_str))
+                        {
+                            if properties.contains_key(name) && !names.contains(&name) {
+                                names.push(name);
+                            }
+                        }
+                        names
+                            .into_iter()
+                            .map(|name| {
+                                Ok(JsonPropertySchema::new(name, self.read(&properties[name])?)
+                                    .with_required(required.contains(&name)))
+                            })
+                            .collect::<Result<Vec<_>, FromSchemaError>>()?
+                    }
+                    Some(other) => return Err(invalid_keyword("properties", other)),
+                    None => Vec::new(),
+                };
+                let mut object = JsonObjectSchema::new(properties);
+                if let Some(title) = schema.get("title").and_then(Value::as_str) {
+                    object = object.with_title(title);
+                }
+                SchemaType::Object(object)
+            }
+            other => {
+                return Err(FromSchemaError::InvalidSchema(format!(
+                    "unknown type {other:?}"
+                )))
+            }
+        })
+    }
+
+    /// Inline the schema a `$ref` points to.
+    fn resolve(&mut self, reference: &'a str) -> Result<SchemaType, FromSchemaError> {
+        if self.resolving.contains(&reference) {
+            return Err(FromSchemaError::RecursiveReference(reference.to_string()));
+        }
+        let target = reference
+            .strip_prefix('#')
+            .and_then(|pointer| self.root.pointer(pointer))
+            .ok_or_else(|| FromSchemaError::UnresolvedReference(reference.to_string()))?;
+        self.resolving.push(reference);
+        let schema = self.read(target);
+        self.resolving.pop();
+        schema
+    }
+}
+
+fn invalid_keyword(keyword: &str, value: &Value) -> FromSchemaError {
+    FromSchemaError::InvalidSchema(format!("invalid value for {keyword}: {value}"))
+}
+
+fn schema_literal(value: &Value) -> Result<SchemaLiteral, FromSchemaError> {
+    match value {
+        Value::String(string) => Ok(SchemaLiteral::String(string.clone())),
+        Value::Number(number) => Ok(SchemaLiteral::Number(number.clone())),
+        Value::Bool(boolean) => Ok(SchemaLiteral::Boolean(*boolean)),
+        Value::Null => Ok(SchemaLiteral::Null),
+        _ => Err(FromSchemaError::Unsupported(
+            "enum and const values that are arrays or objects".into(),
+        )),
+    }
+}
+
+fn length_range(
+    schema: &Map<String, Value>,
+    min_key: &str,
+    max_key: &str,
+) -> Result<Option<std::ops::RangeInclusive<usize>>, FromSchemaError> {
+    let bound = |key: &str| {
+        schema
+            .get(key)
+            .map(|value| {
+                value
+                    .as_u64()
+                    .map(|value| value as usize)
+                    .ok_or_else(|| invalid_keyword(key, value))
+            })
+            .transpose()
+    };
+    let min = bound(min_key)?;
+    let max = bound(max_key)?;
+    Ok((min.is_some() || max.is_some()).then(|| min.unwrap_or(0)..=max.unwrap_or(usize::MAX)))
+}
+
+fn number_bound(schema: &Map<String, Value>, key: &str) -> Result<Option<f64>, FromSchemaError> {
+    schema
+        .get(key)
+        .map(|value| value.as_f64().ok_or_else(|| invalid_keyword(key, value)))
+        .transpose()
+}
+
+fn literal_parser(literal: &SchemaLiteral) -> ArcParser<Value> {
+    let value = match literal {
+        SchemaLiteral::String(string) => Value::String(string.clone()),
+        SchemaLiteral::Number(number) => Value::Number(number.clone()),
+        SchemaLiteral::Boolean(boolean) => Value::Bool(*boolean),
+        SchemaLiteral::Null => Value::Null,
+    };
+    LiteralParser::new(value.to_string())
+        .map_output(move |_| value.clone())
+        .boxed()
+}
+
+fn choice_parser(options: Vec<ArcParser<Value>>) -> Result<ArcParser<Value>, FromSchemaError> {
+    if options.is_empty() {
+        return Err(FromSchemaError::EmptyChoice);
+    }
+    Ok(IndexParser::new(options)
+        .map_output(|(_, value)| value)
+        .boxed())
+}
+
+#[test]
+fn parse_runtime_schema() {
+    use crate::{
+        ArraySchema, CreateParserState, EnumSchema, JsonObjectSchema, JsonPropertySchema,
+        NumberSchema, ParseStatus, Parser, StringSchema,
+    };
+
+    let schema = SchemaType::Object(JsonObjectSchema::new([
+        JsonPropertySchema::new("name", SchemaType::String(StringSchema::new())),
+        JsonPropertySchema::new(
+            "age",
+            SchemaType::Number(NumberSchema::new().with_range(0.0..=150.0)),
+        ),
+        JsonPropertySchema::new(
+            "color",
+            SchemaType::Enum(EnumSchema::new([
+                SchemaLiteral::String("red".to_string()),
+                SchemaLiteral::String("blue".to_string()),
+            ])),
+        ),
+        JsonPropertySchema::new(
+            "scores",
+            SchemaType::Array(ArraySchema::new(SchemaType::Boolean(Default::default()))),
+        ),
+    ]));
+    let parser = ArcParser::from_schema(&schema).unwrap();
+    let state = parser.create_parser_state();
+
+    let input = br#"{ "name": "Alice", "age": 32, "color": "blue", "scores": [true, false] }"#;
+    let result = parser.parse(&state, input).unwrap();
+    let ParseStatus::Finished { result, remaining } = result else {
+        panic!("Parser did not finish");
+    };
+    assert!(remaining.is_empty());
+    assert_eq!(
+        result,
+        serde_json::json!({
+            "name": "Alice",
+            "age": 32.0,
+            "color": "blue",
+            "scores": [true, false]
+        })
+    );
+
+    let input = br#"{ "name": "Alice", "age": 32, "color": "green""#;
+    assert!(parser.parse(&state, input).is_err());
+}
+
+#[test]
+fn parse_runtime_json_schema() {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "address": { "$ref": "#/$defs/address" },
+            "tags": {
+                "type": "array",
+                "items": { "enum": ["home", "work"] },
+                "maxItems": 2
+            }
+        },
+        "required": ["address"],
+        "$defs": {
+            "address": {
+                "type": "object",
+                "properties": { "zip": { "type": "string", "pattern": "^[0-9]{5}$" } }
+            }
+        }
+    });
+    let parser = ArcParser::from_json_schema(&schema).unwrap();
+    let state = parser.create_parser_state();
+
+    let input = br#"{ "address": { "zip": "12345" }, "tags": ["work"] }"#;
+    let ParseStatus::Finished { result, remaining } = parser.parse(&state, input).unwrap() else {
+        panic!("Parser did not finish");
+    };
+    assert!(remaining.is_empty());
+    assert_eq!(
+        result,
+        serde_json::json!({ "address": { "zip": "12345" }, "tags": ["work"] })
+    );
+
+    let input = br#"{ "address": { "zip": "1234a"#;
+    assert!(parser.parse(&state, input).is_err());
+
+    let recursive = serde_json::json!({
+        "type": "object",
+        "properties": { "child": { "$ref": "#" } }
+    });
+    assert_eq!(
+        SchemaType::from_json_schema(&recursive).unwrap_err(),
+        FromSchemaError::RecursiveReference("#".to_string())
+    );
+}
+
+#[test]
+fn runtime_json_schema_properties_follow_the_required_order() {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    let schema = serde_json::json!({
+        "type": "object",
+        "properties": {
+            "zip": { "type": "string" },
+            "note": { "type": "string" },
+            "city": { "type": "string" },
+            "address": { "type": "string" }
+        },
+        "required": ["zip", "city", "address"]
+    });
+    let parser = ArcParser::from_json_schema(&schema).unwrap();
+    let state = parser.create_parser_state();
+
+    let input = br#"{ "zip": "12345", "city": "Paris", "address": "1 Rue", "note": "" }"#;
+    let ParseStatus::Finished { result, .. } = parser.parse(&state, input).unwrap() else {
+        panic!("Parser did not finish");
+    };
+    assert_eq!(
+        result,
+        serde_json::json!({ "zip": "12345", "city": "Paris", "address": "1 Rue", "note": "" })
+    );
+
+    let input = br#"{ "address": "1 Rue""#;
+    assert!(parser.parse(&state, input).is_err());
+}
+
+#[test]
+fn runtime_json_schema_integer_bounds() {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    let schema = serde_json::json!({ "type": "integer", "minimum": 1, "maximum": 5 });
+    let parser = ArcParser::from_json_schema(&schema).unwrap();
+    let state = parser.create_parser_state();
+    let ParseStatus::Finished { result, .. } = parser.parse(&state, b"3").unwrap() else {
+        panic!("Parser did not finish");
+    };
+    assert_eq!(result, serde_json::json!(3));
+    assert!(parser.parse(&state, b"6").is_err());
+    assert!(parser.parse(&state, b"0").is_err());
+
+    let exclusive =
+        serde_json::json!({ "type": "integer", "exclusiveMinimum": 1, "exclusiveMaximum": 5 });
+    let SchemaType::Integer(integer) = SchemaType::from_json_schema(&exclusive).unwrap() else {
+        panic!("expected an integer schema");
+    };
+    assert_eq!(integer.range, Some(2..=4));
+
+    let empty = serde_json::json!({ "type": "integer", "minimum": 5, "maximum": 1 });
+    assert!(SchemaType::from_json_schema(&empty).is_err());
+}
+
+#[test]
+fn runtime_json_schema_integer_literals() {
+    use crate::{CreateParserState, ParseStatus, Parser};
+
+    for (schema, input, expected) in [
+        (
+            serde_json::json!({ "enum": [1, 2] }),
+            "2",
+            serde_json::json!(2),
+        ),
+        (serde_json::json!({ "const": 3 }), "3", serde_json::json!(3)),
+        (
+            serde_json::json!({ "enum": [1.5, "a"] }),
+            "1.5",
+            serde_json::json!(1.5),
+        ),
+    ] {
+        let parser = ArcParser::from_json_schema(&schema).unwrap();
+        let state = parser.create_parser_state();
+        let ParseStatus::Finished { result, .. } = parser.parse(&state, input.as_bytes()).unwrap()
+        else {
+            panic!("Parser did not finish on {input}");
+        };
+        assert_eq!(result, expected);
+        assert_eq!(result.to_string(), input);
+    }
+}
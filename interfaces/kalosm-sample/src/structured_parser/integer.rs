@@ -212,10 +212,14 @@ impl Parser for IntegerParser {
             let signed_value = value as i128 * if positive { 1 } else { -1 };
 
             if self.should_stop(signed_value) {
-                return Ok(ParseStatus::Finished {
-                    result: signed_value,
-                    remaining: &input[index + 1..],
-                });
+                // No more digits can be added, so the number is finished if it is in the range
+                if self.is_number_valid(signed_value) {
+                    return Ok(ParseStatus::Finished {
+                        result: signed_value,
+                        remaining: &input[index + 1..],
+                    });
+                }
+                bail!(OutOfRangeError)
             }
 
             if !self.could_number_become_valid(signed_value) {
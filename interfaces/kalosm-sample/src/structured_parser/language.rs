@@ -0,0 +1,276 @@
+use crate::{CreateParserState, ParseStatus, Parser};
+
+/// Common English words that are not words in other languages written in the Latin script. Used by
+/// [`Language::without_english`].
+const ENGLISH_FUNCTION_WORDS: &[&str] = &[
+    "the", "and", "with", "this", "that", "which", "would", "should", "could", "there", "their",
+    "they", "have", "been", "from", "what", "when", "where", "because", "however", "about", "your",
+    "these", "those", "other", "through",
+];
+
+/// A writing system that a [`Language`] can allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Script {
+    /// The Latin script used by English, French, Spanish, German and many other languages.
+    Latin,
+    /// The Cyrillic script used by Russian, Ukrainian, Bulgarian and other languages.
+    Cyrillic,
+    /// The Greek script.
+    Greek,
+    /// The Arabic script used by Arabic, Persian and Urdu.
+    Arabic,
+    /// The Hebrew script.
+    Hebrew,
+    /// The Devanagari script used by Hindi, Marathi and Nepali.
+    Devanagari,
+    /// Chinese characters.
+    Han,
+    /// Hiragana, katakana and the Chinese characters used in Japanese.
+    Japanese,
+    /// The Korean Hangul script.
+    Hangul,
+    /// The Thai script.
+    Thai,
+}
+
+impl Script {
+    /// Check if a letter belongs to the script.
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            Script::Latin => {
+                c.is_ascii_alphabetic()
+                    || matches!(c, '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}')
+            }
+            Script::Cyrillic => matches!(c, '\u{0400}'..='\u{052F}'),
+            Script::Greek => matches!(c, '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}'),
+            Script::Arabic => matches!(c, '\u{0600}'..='\u{06FF}' | '\u{0750}'..='\u{077F}'),
+            Script::Hebrew => matches!(c, '\u{0590}'..='\u{05FF}'),
+            Script::Devanagari => matches!(c, '\u{0900}'..='\u{097F}'),
+            Script::Han => matches!(c, '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}'),
+            Script::Japanese => {
+                Script::Han.contains(c)
+                    || matches!(c, '\u{3040}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}')
+            }
+            Script::Hangul => {
+                matches!(c, '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}')
+            }
+            Script::Thai => matches!(c, '\u{0E00}'..='\u{0E7F}'),
+        }
+    }
+}
+
+/// The target language for a [`LanguageParser`]. Letters must belong to one of the scripts of the language, and
+/// forbidden words are rejected. Numbers, punctuation, whitespace and symbols are always allowed.
+///
+/// ```rust
+/// use kalosm_sample::*;
+///
+/// // Russian text may only use Cyrillic letters
+/// let russian = Language::new(Script::Cyrillic);
+/// // French text uses the Latin script, so English function words are rejected instead
+/// let french = Language::new(Script::Latin).without_english();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Language {
+    scripts: Vec<Script>,
+    forbidden_words: Vec<String>,
+}
+
+impl Language {
+    /// Create a new language written in a script.
+    pub fn new(script: Script) -> Self {
+        Self {
+            scripts: vec![script],
+            forbidden_words: Vec::new(),
+        }
+    }
+
+    /// Allow letters from another script. This is useful for languages that mix scripts or text that quotes names in
+    /// the Latin script.
+    pub fn with_script(mut self, script: Script) -> Self {
+        self.scripts.push(script);
+        self
+    }
+
+    /// Reject words from a list. Words are compared without case.
+    pub fn with_forbidden_words(mut self, words: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.forbidden_words.extend(
+            words
+                .into_iter()
+                .map(|word| word.to_string().to_lowercase()),
+        );
+        self
+    }
+
+    /// Reject common English words. Small models often drift into English when they answer in another language
+    /// that uses the Latin script. This catches that drift.
+    pub fn without_english(self) -> Self {
+        self.with_forbidden_words(ENGLISH_FUNCTION_WORDS.iter().copied())
+    }
+
+    fn allows_letter(&self, c: char) -> bool {
+        self.scripts.iter().any(|script| script.contains(c))
+    }
+
+    fn check_word(&self, word: &str) -> Result<(), LanguageParseError> {
+        if word.is_empty() {
+            return Ok(());
+        }
+        let lowercase = word.to_lowercase();
+        if self.forbidden_words.contains(&lowercase) {
+            return Err(LanguageParseError::ForbiddenWord(word.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// An error that can occur while parsing text with a [`LanguageParser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LanguageParseError {
+    /// The text contains a letter that is not in any script of the language.
+    Script(char),
+    /// The text contains a forbidden word.
+    ForbiddenWord(String),
+    /// The text is not valid UTF-8.
+    InvalidUtf8,
+}
+
+impl std::fmt::Display for LanguageParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LanguageParseError::Script(c) => {
+                write!(f, "The letter {c:?} is not in the target script")
+            }
+            LanguageParseError::ForbiddenWord(word) => write!(f, "The word {word:?} is forbidden"),
+            LanguageParseError::InvalidUtf8 => write!(f, "The text is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for LanguageParseError {}
+
+/// The state of a [`LanguageParser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageParserState<S> {
+    inner: S,
+    /// The bytes of a character that was split between tokens
+    incomplete_char: Vec<u8>,
+    /// The letters of the current word
+    word: String,
+}
+
+/// A parser that restricts the text another parser accepts to a target [`Language`].
+///
+/// Tokens are checked as they are generated. A letter from another script is rejected as soon as it appears. A
+/// forbidden word is rejected when the token that would end it is generated, so the model has to continue with
+/// something else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageParser<P> {
+    parser: P,
+    language: Language,
+}
+
+impl<P> LanguageParser<P> {
+    /// Create a new parser that restricts another parser to a language.
+    pub fn new(parser: P, language: Language) -> Self {
+        Self { parser, language }
+    }
+}
+
+impl<P: CreateParserState> CreateParserState for LanguageParser<P> {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        LanguageParserState {
+            inner: self.parser.create_parser_state(),
+            incomplete_char: Vec::new(),
+            word: String::new(),
+        }
+    }
+}
+
+impl<P: Parser> Parser for LanguageParser<P> {
+    type Output = P::Output;
+    type PartialState = LanguageParserState<P::PartialState>;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> crate::ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let status = self.parser.parse(&state.inner, input)?;
+        // Only check the bytes the inner parser consumed
+        let consumed = match &status {
+            ParseStatus::Finished { remaining, .. } => &input[..input.len() - remaining.len()],
+            ParseStatus::Incomplete { .. } => input,
+        };
+
+        let mut bytes = state.incomplete_char.clone();
+        bytes.extend_from_slice(consumed);
+        let valid_up_to = match std::str::from_utf8(&bytes) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => crate::bail!(LanguageParseError::InvalidUtf8),
+        };
+        let incomplete_char = bytes.split_off(valid_up_to);
+        let text = std::str::from_utf8(&bytes).expect("the bytes were validated above");
+
+        let mut word = state.word.clone();
+        for c in text.chars() {
+            if c.is_alphabetic() {
+                if !self.language.allows_letter(c) {
+                    crate::bail!(LanguageParseError::Script(c));
+                }
+                word.push(c);
+            } else {
+                self.language.check_word(&word)?;
+                word.clear();
+            }
+        }
+
+        Ok(match status {
+            ParseStatus::Finished { result, remaining } => {
+                self.language.check_word(&word)?;
+                ParseStatus::Finished { result, remaining }
+            }
+            ParseStatus::Incomplete {
+                new_state,
+                required_next,
+            } => ParseStatus::Incomplete {
+                new_state: LanguageParserState {
+                    inner: new_state,
+                    incomplete_char,
+                    word,
+                },
+                required_next,
+            },
+        })
+    }
+}
+
+#[test]
+fn language_parser_rejects_other_languages() {
+    use crate::StopOn;
+
+    let russian = LanguageParser::new(StopOn::new("\n"), Language::new(Script::Cyrillic));
+    let state = russian.create_parser_state();
+    assert!(russian
+        .parse(&state, "Привет, мир! 42\n".as_bytes())
+        .is_ok());
+    assert!(russian.parse(&state, "Привет world".as_bytes()).is_err());
+
+    let french = LanguageParser::new(
+        StopOn::new("\n"),
+        Language::new(Script::Latin).without_english(),
+    );
+    let state = french.create_parser_state();
+    assert!(french
+        .parse(&state, "Bonjour, le théâtre est fermé.".as_bytes())
+        .is_ok());
+    let (state, _) = french
+        .parse(&state, "Bonjour, the".as_bytes())
+        .unwrap()
+        .unwrap_incomplete();
+    // The word can still become a french word, but it can't end as "the"
+    assert!(french.parse(&state, "ière".as_bytes()).is_ok());
+    assert!(french.parse(&state, " ".as_bytes()).is_err());
+}
@@ -26,6 +26,8 @@ mod repeat;
 pub use repeat::*;
 mod separated;
 pub use separated::*;
+mod array;
+pub use array::*;
 mod parse;
 pub use parse::*;
 mod word;
@@ -42,10 +44,18 @@ mod arc_linked_list;
 pub(crate) use arc_linked_list::*;
 mod schema;
 pub use schema::*;
+mod from_schema;
+pub use from_schema::*;
 mod index;
 pub use index::*;
 mod one_line;
 pub use one_line::*;
+mod formats;
+pub use formats::*;
+mod diff;
+pub use diff::*;
+mod language;
+pub use language::*;
 
 /// An error that occurred while parsing.
 #[derive(Debug, Clone)]
@@ -436,6 +446,23 @@ pub trait ParserExt: Parser {
         ArcParser::new(AnyParser(self))
     }
 
+    /// Restrict the text this parser accepts to a [`Language`]. This keeps the model from drifting into another
+    /// language or script.
+    ///
+    /// ```rust
+    /// use kalosm_sample::*;
+    ///
+    /// let parser = StopOn::new("\n").restrict_language(Language::new(Script::Cyrillic));
+    /// let state = parser.create_parser_state();
+    /// assert!(parser.parse(&state, "Привет world".as_bytes()).is_err());
+    /// ```
+    fn restrict_language(self, language: Language) -> LanguageParser<Self>
+    where
+        Self: Sized,
+    {
+        LanguageParser::new(self, language)
+    }
+
     /// Create a new parser with a different initial state
     fn with_initial_state<F: Fn() -> Self::PartialState + Clone>(
         self,
@@ -2,7 +2,7 @@ use std::{collections::HashMap, sync::RwLock};
 
 use crate::{CreateParserState, Parser};
 use regex_automata::{
-    dfa::{dense, Automaton},
+    dfa::{dense, Automaton, StartKind},
     util::primitives::StateID,
 };
 
@@ -18,7 +18,11 @@ impl RegexParser {
     /// Create a new `RegexParser` from a regex pattern.
     #[allow(clippy::result_large_err)]
     pub fn new(regex: &str) -> std::result::Result<Self, regex_automata::dfa::dense::BuildError> {
-        let dfa = dense::DFA::new(regex)?;
+        // The parser always starts at the beginning of the input, so only the anchored start states are built. Large
+        // grammars can take gigabytes to determinize with unanchored start states.
+        let dfa = dense::Builder::new()
+            .configure(dense::Config::new().start_kind(StartKind::Anchored))
+            .build(regex)?;
 
         let config =
             regex_automata::util::start::Config::new().anchored(regex_automata::Anchored::Yes);
@@ -43,8 +43,8 @@ impl std::fmt::Write for IndentationWriter<'_> {
 pub enum SchemaLiteral {
     /// A string
     String(String),
-    /// A number
-    Number(f64),
+    /// A number. Integers are kept as integers so they are written without a fractional part
+    Number(serde_json::Number),
     /// A boolean
     Boolean(bool),
     /// The null value
@@ -128,8 +128,8 @@ impl Display for SchemaType {
 /// A schema for an conditional schema
 #[derive(Debug, Clone)]
 pub struct IfThenSchema {
-    if_schema: Box<SchemaType>,
-    then_schema: Box<SchemaType>,
+    pub(crate) if_schema: Box<SchemaType>,
+    pub(crate) then_schema: Box<SchemaType>,
 }
 
 impl IfThenSchema {
@@ -170,7 +170,7 @@ impl Display for IfThenSchema {
 /// A schema that matches any of the composite schemas
 #[derive(Debug, Clone)]
 pub struct AnyOfSchema {
-    any_of: Vec<SchemaType>,
+    pub(crate) any_of: Vec<SchemaType>,
 }
 
 impl AnyOfSchema {
@@ -220,7 +220,7 @@ impl Display for AnyOfSchema {
 /// A schema that matches one of the composite schemas
 #[derive(Debug, Clone)]
 pub struct OneOfSchema {
-    one_of: Vec<SchemaType>,
+    pub(crate) one_of: Vec<SchemaType>,
 }
 
 impl OneOfSchema {
@@ -270,7 +270,7 @@ impl Display for OneOfSchema {
 /// A schema for a constant
 #[derive(Debug, Clone)]
 pub struct ConstSchema {
-    value: SchemaLiteral,
+    pub(crate) value: SchemaLiteral,
 }
 
 impl ConstSchema {
@@ -314,7 +314,7 @@ fn test_const_schema() {
 /// A schema for an enum
 #[derive(Debug, Clone)]
 pub struct EnumSchema {
-    variants: Vec<SchemaLiteral>,
+    pub(crate) variants: Vec<SchemaLiteral>,
 }
 
 impl EnumSchema {
@@ -361,9 +361,9 @@ impl Display for EnumSchema {
 #[derive(Debug, Clone)]
 pub struct StringSchema {
     /// The length that is valid for the string
-    length: Option<std::ops::RangeInclusive<usize>>,
+    pub(crate) length: Option<std::ops::RangeInclusive<usize>>,
     /// The regex pattern that the string must match
-    pattern: Option<String>,
+    pub(crate) pattern: Option<String>,
 }
 
 impl Schema for String {
@@ -440,7 +440,7 @@ impl Display for StringSchema {
 #[derive(Debug, Clone)]
 pub struct NumberSchema {
     /// The range that the number must be in
-    range: Option<std::ops::RangeInclusive<f64>>,
+    pub(crate) range: Option<std::ops::RangeInclusive<f64>>,
 }
 
 macro_rules! impl_schema_for_number {
@@ -522,13 +522,21 @@ fn test_number_schema() {
 
 /// A schema for an integer
 #[derive(Debug, Clone, Default)]
-
-pub struct IntegerSchema;
+pub struct IntegerSchema {
+    /// The range that the integer must be in
+    pub(crate) range: Option<std::ops::RangeInclusive<i64>>,
+}
 
 impl IntegerSchema {
     /// Create a new integer schema
     pub fn new() -> Self {
-        Self
+        Self { range: None }
+    }
+
+    /// Set the range of the integer
+    pub fn with_range(mut self, range: impl Into<Option<std::ops::RangeInclusive<i64>>>) -> Self {
+        self.range = range.into();
+        self
     }
 }
 
@@ -562,13 +570,25 @@ impl IntegerSchema {
         f: &mut std::fmt::Formatter<'_>,
         description: Option<&str>,
     ) -> std::fmt::Result {
-        if let Some(description) = description {
-            write!(
+        match (&self.range, description) {
+            (None, Some(description)) => write!(
                 f,
                 "{{\n\t\"description\": \"{description}\",\n\t\"type\": \"integer\"\n}}"
-            )
-        } else {
-            f.write_str("{ \"type\": \"integer\" }")
+            ),
+            (None, None) => f.write_str("{ \"type\": \"integer\" }"),
+            (Some(range), description) => {
+                f.write_char('{')?;
+                {
+                    let mut writer = IndentationWriter::new(1, f);
+                    if let Some(description) = description {
+                        write!(&mut writer, "\n\"description\": \"{description}\",")?;
+                    }
+                    writer.write_str("\n\"type\": \"integer\",")?;
+                    writer.write_fmt(format_args!("\n\"minimum\": {},", range.start()))?;
+                    writer.write_fmt(format_args!("\n\"maximum\": {}", range.end()))?;
+                }
+                f.write_str("\n}")
+            }
         }
     }
 }
@@ -581,9 +601,16 @@ impl Display for IntegerSchema {
 
 #[test]
 fn test_integer_schema() {
-    let schema = IntegerSchema;
+    let schema = IntegerSchema::new();
 
     assert_eq!(schema.to_string(), "{ \"type\": \"integer\" }");
+
+    let schema = IntegerSchema::new().with_range(1..=5);
+
+    assert_eq!(
+        schema.to_string(),
+        "{\n\t\"type\": \"integer\",\n\t\"minimum\": 1,\n\t\"maximum\": 5\n}"
+    );
 }
 
 /// A schema for a boolean
@@ -623,8 +650,8 @@ fn test_boolean_schema() {
 /// A schema for an array
 #[derive(Debug, Clone)]
 pub struct ArraySchema {
-    items: Box<SchemaType>,
-    length: Option<std::ops::RangeInclusive<usize>>,
+    pub(crate) items: Box<SchemaType>,
+    pub(crate) length: Option<std::ops::RangeInclusive<usize>>,
 }
 
 impl<T: Schema> Schema for Vec<T> {
@@ -730,7 +757,7 @@ fn test_array_schema() {
 pub struct JsonObjectSchema {
     title: Option<String>,
     description: Option<&'static str>,
-    properties: Vec<JsonPropertySchema>,
+    pub(crate) properties: Vec<JsonPropertySchema>,
 }
 
 impl JsonObjectSchema {
@@ -859,10 +886,10 @@ fn test_object_schema() {
 /// A schema for a property of an object
 #[derive(Debug, Clone)]
 pub struct JsonPropertySchema {
-    name: String,
+    pub(crate) name: String,
     description: Option<&'static str>,
     required: bool,
-    ty: SchemaType,
+    pub(crate) ty: SchemaType,
 }
 
 impl JsonPropertySchema {
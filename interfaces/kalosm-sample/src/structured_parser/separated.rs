@@ -71,7 +71,7 @@ where
 pub struct SeparatedParser<P, S> {
     pub(crate) parser: P,
     pub(crate) separator: S,
-    length_range: std::ops::RangeInclusive<usize>,
+    pub(crate) length_range: std::ops::RangeInclusive<usize>,
 }
 
 impl<P, S> Default for SeparatedParser<P, S>
@@ -211,6 +211,8 @@ impl<P: CreateParserState, S: CreateParserState> Parser for SeparatedParser<P, S
                                     }) => required_next = Some(new_required_next),
                                     _ => required_next = None,
                                 }
+                                // The next input starts the next item
+                                state.last_state = SeparatedItemState::Item(item_state);
                                 break;
                             }
                             state.last_state = SeparatedItemState::Item(item_state);
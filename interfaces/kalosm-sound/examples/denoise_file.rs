@@ -14,6 +14,7 @@ async fn main() -> Result<(), anyhow::Error> {
     while let Some(VoiceActivityDetectorOutput {
         probability,
         samples,
+        ..
     }) = vad.next().await
     {
         if probability > 0.1 {
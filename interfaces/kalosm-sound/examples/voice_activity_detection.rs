@@ -12,6 +12,7 @@ async fn main() -> Result<(), anyhow::Error> {
     while let Some(VoiceActivityDetectorOutput {
         probability,
         samples,
+        ..
     }) = vad.next().await
     {
         println!("probability: {probability}");
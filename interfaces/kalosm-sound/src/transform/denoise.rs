@@ -33,6 +33,7 @@ pub struct DenoisedStream<S: AsyncSource + Unpin> {
     source: ResampledAsyncSource<S>,
     denoiser: Box<nnnoiseless::DenoiseState<'static>>,
     fill_index: usize,
+    samples_read: usize,
     input_buffer: [f32; DenoiseState::FRAME_SIZE],
     output: [f32; DenoiseState::FRAME_SIZE],
 }
@@ -43,6 +44,7 @@ impl<S: AsyncSource + Unpin> DenoisedStream<S> {
             source: source.resample(SAMPLE_RATE),
             denoiser: DenoiseState::new(),
             fill_index: 0,
+            samples_read: 0,
             input_buffer: [0f32; DenoiseState::FRAME_SIZE],
             output: [0f32; DenoiseState::FRAME_SIZE],
         }
@@ -84,9 +86,12 @@ impl<S: AsyncSource + Unpin> Stream for DenoisedStream<S> {
             debug_assert!(*output <= 1.0);
         }
         let samples = SamplesBuffer::new(1, sample_rate, this.output);
+        let sample_offset = this.samples_read;
+        this.samples_read += DenoiseState::FRAME_SIZE;
         Poll::Ready(Some(VoiceActivityDetectorOutput {
             probability: vad,
             samples,
+            sample_offset,
         }))
     }
 }
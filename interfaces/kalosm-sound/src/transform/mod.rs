@@ -17,3 +17,8 @@ pub use voice_audio_detector_ext::*;
 mod transcribe;
 #[cfg(feature = "voice_detection")]
 pub use transcribe::*;
+
+#[cfg(feature = "voice_detection")]
+mod telephone;
+#[cfg(feature = "voice_detection")]
+pub use telephone::*;
@@ -0,0 +1,281 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_core::Stream;
+use futures_util::StreamExt;
+use rodio::buffer::SamplesBuffer;
+use rwhisper::{ChunkedTranscriptionTask, Segment, Whisper};
+
+use super::voice_audio_detector::*;
+use super::voice_audio_detector_ext::*;
+use crate::{AsyncSourceTranscribeExt, ResampledAsyncSource};
+
+/// Settings for resampling and chunking audio by voice activity before it is transcribed. Use
+/// [`TranscriptionProfile::telephone`] for narrowband call recordings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TranscriptionProfile {
+    sample_rate: u32,
+    start_threshold: f32,
+    start_window: Duration,
+    end_threshold: f32,
+    end_window: Duration,
+    time_before_speech: Duration,
+}
+
+impl Default for TranscriptionProfile {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            start_threshold: 0.6,
+            start_window: Duration::from_millis(100),
+            end_threshold: 0.2,
+            end_window: Duration::from_millis(2000),
+            time_before_speech: Duration::from_millis(500),
+        }
+    }
+}
+
+impl TranscriptionProfile {
+    /// A profile for 8 kHz telephone audio. The audio is upsampled to 16 kHz before voice activity detection, and
+    /// the voice activity thresholds are lowered because speech codecs flatten the signal the detector relies on.
+    /// Speech runs end after a shorter pause so quick turns in a conversation are split into separate segments.
+    pub fn telephone() -> Self {
+        Self {
+            sample_rate: 16000,
+            start_threshold: 0.45,
+            start_window: Duration::from_millis(200),
+            end_threshold: 0.15,
+            end_window: Duration::from_millis(800),
+            time_before_speech: Duration::from_millis(300),
+        }
+    }
+
+    /// Set the sample rate the audio is resampled to before voice activity detection. (Defaults to 16 kHz)
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Set the threshold for the start of a voice activity run
+    pub fn with_start_threshold(mut self, start_threshold: f32) -> Self {
+        self.start_threshold = start_threshold;
+        self
+    }
+
+    /// Set the window for the start of a voice activity run
+    pub fn with_start_window(mut self, start_window: Duration) -> Self {
+        self.start_window = start_window;
+        self
+    }
+
+    /// Set the threshold for the end of a voice activity run
+    pub fn with_end_threshold(mut self, end_threshold: f32) -> Self {
+        self.end_threshold = end_threshold;
+        self
+    }
+
+    /// Set the window for the end of a voice activity run
+    pub fn with_end_window(mut self, end_window: Duration) -> Self {
+        self.end_window = end_window;
+        self
+    }
+
+    /// Set the time before the speech run starts to include in the output
+    pub fn with_time_before_speech(mut self, time_before_speech: Duration) -> Self {
+        self.time_before_speech = time_before_speech;
+        self
+    }
+
+    /// Get the sample rate the audio is resampled to before voice activity detection.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Apply the voice activity settings of the profile to a rechunker stream.
+    pub(crate) fn apply<S>(
+        &self,
+        stream: VoiceActivityRechunkerStream<S>,
+    ) -> VoiceActivityRechunkerStream<S> {
+        stream
+            .with_start_threshold(self.start_threshold)
+            .with_start_window(self.start_window)
+            .with_end_threshold(self.end_threshold)
+            .with_end_window(self.end_window)
+            .with_time_before_speech(self.time_before_speech)
+    }
+}
+
+type ChannelTranscription = ChunkedTranscriptionTask<
+    VoiceActivityOffsetRechunkerStream<
+        VoiceActivityDetectorStream<ResampledAsyncSource<SamplesBuffer<f32>>>,
+    >,
+>;
+
+/// A recording of a call with the caller and the agent on separate channels. Each channel is transcribed on its own
+/// and the segments are merged in order and labeled with the speaker.
+///
+/// ```rust, no_run
+/// use kalosm::sound::*;
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), anyhow::Error> {
+///     let model = Whisper::new().await?;
+///     let file = BufReader::new(File::open("./call.wav")?);
+///     let audio = rodio::Decoder::new(file)?;
+///
+///     let mut transcript = DualChannelCall::new(audio)
+///         .with_labels("Customer", "Support")
+///         .transcribe(model);
+///     while let Some(segment) = transcript.next().await {
+///         println!("{}: {}", segment.speaker(), segment.text());
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct DualChannelCall {
+    channels: [SamplesBuffer<f32>; 2],
+    labels: [String; 2],
+    profile: TranscriptionProfile,
+}
+
+impl DualChannelCall {
+    /// Split a recording into the caller (the first channel) and the agent (the second channel). A mono recording is
+    /// transcribed as the caller only.
+    pub fn new<S>(source: S) -> Self
+    where
+        S: rodio::Source,
+        <S as Iterator>::Item: rodio::Sample + dasp::sample::ToSample<f32>,
+    {
+        let channels = source.channels().max(1) as usize;
+        let sample_rate = source.sample_rate();
+        let mut caller = Vec::new();
+        let mut agent = Vec::new();
+        for (i, sample) in source.enumerate() {
+            let sample: f32 = dasp::Sample::to_sample(sample);
+            match i % channels {
+                0 => caller.push(sample),
+                1 => agent.push(sample),
+                _ => {}
+            }
+        }
+        Self {
+            channels: [
+                SamplesBuffer::new(1, sample_rate, caller),
+                SamplesBuffer::new(1, sample_rate, agent),
+            ],
+            labels: ["Caller".to_string(), "Agent".to_string()],
+            profile: TranscriptionProfile::telephone(),
+        }
+    }
+
+    /// Set the speaker labels for the first and second channel. (Defaults to "Caller" and "Agent")
+    pub fn with_labels(mut self, caller: impl ToString, agent: impl ToString) -> Self {
+        self.labels = [caller.to_string(), agent.to_string()];
+        self
+    }
+
+    /// Set the profile used to transcribe each channel. (Defaults to [`TranscriptionProfile::telephone`])
+    pub fn with_profile(mut self, profile: TranscriptionProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Transcribe both channels and stream the segments in the order they were spoken.
+    pub fn transcribe(self, model: Whisper) -> CallTranscriptionStream {
+        let [caller, agent] = self.channels;
+        let profile = self.profile;
+        let transcribe = |channel: SamplesBuffer<f32>| {
+            Some(channel.transcribe_with_profile(model.clone(), profile))
+        };
+        CallTranscriptionStream {
+            channels: [transcribe(caller), transcribe(agent)],
+            pending: [None, None],
+            labels: self.labels,
+        }
+    }
+}
+
+/// A transcribed segment of a call labeled with the speaker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeakerSegment {
+    speaker: String,
+    channel: usize,
+    segment: Segment,
+}
+
+impl SpeakerSegment {
+    /// Get the label of the speaker.
+    pub fn speaker(&self) -> &str {
+        &self.speaker
+    }
+
+    /// Get the index of the channel the segment was transcribed from.
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    /// Get the text of the segment.
+    pub fn text(&self) -> &str {
+        self.segment.text()
+    }
+
+    /// Get the transcribed segment.
+    pub fn segment(&self) -> &Segment {
+        &self.segment
+    }
+
+    /// Convert this into the transcribed segment.
+    pub fn into_segment(self) -> Segment {
+        self.segment
+    }
+}
+
+/// A stream of [`SpeakerSegment`]s from a [`DualChannelCall`].
+pub struct CallTranscriptionStream {
+    channels: [Option<ChannelTranscription>; 2],
+    pending: [Option<Segment>; 2],
+    labels: [String; 2],
+}
+
+impl Stream for CallTranscriptionStream {
+    type Item = SpeakerSegment;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut waiting = false;
+        for (channel, pending) in this.channels.iter_mut().zip(&mut this.pending) {
+            if pending.is_some() {
+                continue;
+            }
+            if let Some(stream) = channel {
+                match stream.poll_next_unpin(cx) {
+                    Poll::Ready(Some(segment)) => *pending = Some(segment),
+                    Poll::Ready(None) => *channel = None,
+                    Poll::Pending => waiting = true,
+                }
+            }
+        }
+        // Wait for the next segment of every channel that is still running so segments are returned in order
+        if waiting {
+            return Poll::Pending;
+        }
+        let next = this
+            .pending
+            .iter()
+            .enumerate()
+            .filter_map(|(i, segment)| segment.as_ref().map(|segment| (i, segment.start())))
+            .min_by(|(_, first), (_, second)| first.total_cmp(second))
+            .map(|(i, _)| i);
+        Poll::Ready(next.map(|channel| SpeakerSegment {
+            speaker: this.labels[channel].clone(),
+            channel,
+            segment: this.pending[channel].take().unwrap(),
+        }))
+    }
+}
@@ -2,7 +2,7 @@ use rwhisper::ChunkedTranscriptionTask;
 
 use super::voice_audio_detector::*;
 use super::voice_audio_detector_ext::*;
-use crate::AsyncSource;
+use crate::{AsyncSource, ResampledAsyncSource, TranscriptionProfile};
 
 /// An extension trait for [`AsyncSource`] that integrates with [`crate::Whisper`].
 pub trait AsyncSourceTranscribeExt: AsyncSource + Unpin + Send + Sized + 'static {
@@ -32,10 +32,50 @@ pub trait AsyncSourceTranscribeExt: AsyncSource + Unpin + Send + Sized + 'static
     fn transcribe(
         self,
         model: rwhisper::Whisper,
-    ) -> ChunkedTranscriptionTask<VoiceActivityRechunkerStream<VoiceActivityDetectorStream<Self>>>
-    {
+    ) -> ChunkedTranscriptionTask<
+        VoiceActivityOffsetRechunkerStream<VoiceActivityDetectorStream<Self>>,
+    > {
         rwhisper::TranscribeChunkedAudioStreamExt::transcribe(
-            self.voice_activity_stream().rechunk_voice_activity(),
+            self.voice_activity_stream()
+                .rechunk_voice_activity_with_offsets(),
+            model,
+        )
+    }
+
+    /// Transcribe the audio stream with the resampling and voice activity settings of a [`TranscriptionProfile`].
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), anyhow::Error> {
+    ///     let model = Whisper::new().await?;
+    ///
+    ///     // Read a narrowband (8 kHz) call recording
+    ///     let file = BufReader::new(File::open("./call.wav")?);
+    ///     let audio = rodio::Decoder::new(file)?;
+    ///
+    ///     let mut text_stream = audio.transcribe_with_profile(model, TranscriptionProfile::telephone());
+    ///     text_stream.to_std_out().await.unwrap();
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    fn transcribe_with_profile(
+        self,
+        model: rwhisper::Whisper,
+        profile: TranscriptionProfile,
+    ) -> ChunkedTranscriptionTask<
+        VoiceActivityOffsetRechunkerStream<VoiceActivityDetectorStream<ResampledAsyncSource<Self>>>,
+    > {
+        let chunks = self
+            .resample(profile.sample_rate())
+            .voice_activity_stream()
+            .rechunk_voice_activity();
+        rwhisper::TranscribeChunkedAudioStreamExt::transcribe(
+            profile.apply(chunks).with_offsets(),
             model,
         )
     }
@@ -33,6 +33,7 @@ pub struct VoiceActivityDetectorStream<S: AsyncSource + Unpin> {
     source: ResampledAsyncSource<S>,
     buffer: Vec<f32>,
     chunk_size: usize,
+    samples_read: usize,
     vad: Arc<RwLock<VoiceActivityDetector>>,
     task: Option<tokio::task::JoinHandle<VoiceActivityDetectorOutput>>,
 }
@@ -43,6 +44,7 @@ impl<S: AsyncSource + Unpin> VoiceActivityDetectorStream<S> {
             source,
             buffer: Vec::with_capacity(chunk_size),
             chunk_size,
+            samples_read: 0,
             vad: Arc::new(RwLock::new(vad)),
             task: None,
         }
@@ -77,6 +79,8 @@ impl<S: AsyncSource + Unpin> Stream for VoiceActivityDetectorStream<S> {
                 }
             }
             let data = this.buffer.drain(..).collect::<Vec<_>>();
+            let sample_offset = this.samples_read;
+            this.samples_read += data.len();
             let model = this.vad.clone();
             let vad = tokio::task::spawn_blocking(move || {
                 let mut locked = model.write().unwrap();
@@ -84,6 +88,7 @@ impl<S: AsyncSource + Unpin> Stream for VoiceActivityDetectorStream<S> {
                 VoiceActivityDetectorOutput {
                     probability: vad,
                     samples: SamplesBuffer::new(1, sample_rate, data),
+                    sample_offset,
                 }
             });
             this.task = Some(vad);
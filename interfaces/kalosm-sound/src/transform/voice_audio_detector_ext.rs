@@ -1,5 +1,6 @@
 use futures_core::ready;
 use rodio::buffer::SamplesBuffer;
+use rwhisper::OffsetSamplesBuffer;
 use std::{collections::VecDeque, task::Poll, time::Duration};
 
 /// The output of a [`crate::VoiceActivityDetectorStream`]
@@ -8,6 +9,14 @@ pub struct VoiceActivityDetectorOutput {
     pub probability: f32,
     /// The audio sample associated with the voice activity probability
     pub samples: rodio::buffer::SamplesBuffer<f32>,
+    pub(crate) sample_offset: usize,
+}
+
+impl VoiceActivityDetectorOutput {
+    /// Get the offset of the first sample in the chunk from the start of the stream, at the sample rate of the chunk
+    pub fn sample_offset(&self) -> usize {
+        self.sample_offset
+    }
 }
 
 /// An extension trait for audio streams with voice activity detection information
@@ -39,6 +48,16 @@ pub trait VoiceActivityStreamExt: futures_core::Stream<Item = VoiceActivityDetec
             time_before_speech,
         )
     }
+
+    /// Rechunk the audio like [`VoiceActivityStreamExt::rechunk_voice_activity`], but yield each chunk as an
+    /// [`OffsetSamplesBuffer`] that records where it starts in the input stream. Transcribing these chunks gives
+    /// segments that are timed from the start of the whole stream instead of the start of each chunk.
+    fn rechunk_voice_activity_with_offsets(self) -> VoiceActivityOffsetRechunkerStream<Self>
+    where
+        Self: Sized + Unpin,
+    {
+        self.rechunk_voice_activity().with_offsets()
+    }
 }
 
 impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput>> VoiceActivityStreamExt for S {}
@@ -90,6 +109,7 @@ pub struct VoiceActivityRechunkerStream<S> {
     duration_before_window: Duration,
     in_voice_run: bool,
     buffer: VecDeque<SamplesBuffer<f32>>,
+    buffer_offset: usize,
     channels: u16,
     sample_rate: u32,
     voice_probabilities_window: VecDeque<(f32, Duration)>,
@@ -127,6 +147,11 @@ impl<S> VoiceActivityRechunkerStream<S> {
         self.include_duration_before = time_before_speech;
         self
     }
+
+    /// Yield each chunk as an [`OffsetSamplesBuffer`] that records where it starts in the input stream
+    pub fn with_offsets(self) -> VoiceActivityOffsetRechunkerStream<S> {
+        VoiceActivityOffsetRechunkerStream { inner: self }
+    }
 }
 
 impl<S> VoiceActivityRechunkerStream<S> {
@@ -148,6 +173,7 @@ impl<S> VoiceActivityRechunkerStream<S> {
             duration_before_window: Duration::ZERO,
             in_voice_run: false,
             buffer: VecDeque::new(),
+            buffer_offset: 0,
             channels: 1,
             sample_rate: 0,
             voice_probabilities_window: VecDeque::new(),
@@ -178,7 +204,8 @@ impl<S> VoiceActivityRechunkerStream<S> {
         self.sum / self.voice_probabilities_window.len() as f32
     }
 
-    fn finish_voice_run(&mut self) -> SamplesBuffer<f32> {
+    fn finish_voice_run(&mut self) -> OffsetSamplesBuffer {
+        let sample_offset = self.buffer_offset;
         let samples = SamplesBuffer::new(
             self.channels,
             self.sample_rate,
@@ -193,23 +220,21 @@ impl<S> VoiceActivityRechunkerStream<S> {
         self.in_voice_run = false;
         self.duration_before_window = Duration::ZERO;
         self.buffer.clear();
-        samples
+        OffsetSamplesBuffer::new(samples, sample_offset)
     }
 }
 
-impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> futures_core::Stream
-    for VoiceActivityRechunkerStream<S>
+impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin>
+    VoiceActivityRechunkerStream<S>
 {
-    type Item = SamplesBuffer<f32>;
-
-    fn poll_next(
-        self: std::pin::Pin<&mut Self>,
+    fn poll_next_chunk(
+        &mut self,
         cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        let this = self.get_mut();
+    ) -> std::task::Poll<Option<OffsetSamplesBuffer>> {
+        let this = self;
         loop {
-            let source = std::pin::pin!(&mut this.source);
-            let next = ready!(source.poll_next(cx));
+            let source = std::pin::Pin::new(&mut this.source);
+            let next = ready!(futures_core::Stream::poll_next(source, cx));
             if let Some(next) = next {
                 // Set the sample rate from the stream
                 this.sample_rate = rodio::Source::sample_rate(&next.samples);
@@ -226,6 +251,9 @@ impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> future
                     this.in_voice_run = true;
                 }
                 // Add the samples to the buffer
+                if this.buffer.is_empty() {
+                    this.buffer_offset = next.sample_offset;
+                }
                 this.buffer.push_back(next.samples);
                 // If this is inside a voice run, add the sample to the buffer
                 if this.in_voice_run {
@@ -240,6 +268,7 @@ impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> future
                     // If the pre-voice buffer is full, remove the first sample from it
                     while this.duration_before_window >= this.include_duration_before {
                         let sample = this.buffer.pop_front().unwrap();
+                        this.buffer_offset += sample.size_hint().0;
                         this.duration_before_window -= rodio::Source::total_duration(&sample)
                             .expect("samples must have a duration");
                     }
@@ -256,3 +285,72 @@ impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> future
         }
     }
 }
+
+impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> futures_core::Stream
+    for VoiceActivityRechunkerStream<S>
+{
+    type Item = SamplesBuffer<f32>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_next_chunk(cx)
+            .map(|chunk| chunk.map(OffsetSamplesBuffer::into_inner))
+    }
+}
+
+/// A [`VoiceActivityRechunkerStream`] that yields each chunk as an [`OffsetSamplesBuffer`] that records where it
+/// starts in the input stream
+pub struct VoiceActivityOffsetRechunkerStream<S> {
+    inner: VoiceActivityRechunkerStream<S>,
+}
+
+impl<S: futures_core::Stream<Item = VoiceActivityDetectorOutput> + Unpin> futures_core::Stream
+    for VoiceActivityOffsetRechunkerStream<S>
+{
+    type Item = OffsetSamplesBuffer;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().inner.poll_next_chunk(cx)
+    }
+}
+
+#[test]
+fn rechunked_voice_keeps_its_offset_in_the_stream() {
+    use futures_util::{FutureExt, StreamExt};
+
+    // One second of silence followed by half a second of speech in chunks of 100ms
+    let chunks = || {
+        let probabilities = [0.0; 10].into_iter().chain([1.0; 5]);
+        futures_util::stream::iter(probabilities.enumerate().map(|(i, probability)| {
+            VoiceActivityDetectorOutput {
+                probability,
+                samples: SamplesBuffer::new(1, 16000, vec![0.0; 1600]),
+                sample_offset: i * 1600,
+            }
+        }))
+    };
+
+    let with_offsets: Vec<_> = chunks()
+        .rechunk_voice_activity_with_offsets()
+        .collect()
+        .now_or_never()
+        .unwrap();
+    // The chunk includes the 400ms of silence before the speech
+    assert_eq!(with_offsets.len(), 1);
+    assert_eq!(with_offsets[0].sample_offset(), 6 * 1600);
+    assert_eq!(with_offsets[0].start(), Duration::from_millis(600));
+
+    let plain: Vec<SamplesBuffer<f32>> = chunks()
+        .rechunk_voice_activity()
+        .collect()
+        .now_or_never()
+        .unwrap();
+    assert_eq!(plain.len(), 1);
+    assert_eq!(plain[0].clone().count(), 9 * 1600);
+}
@@ -10,6 +10,9 @@ use std::{
 pub use crate::sender::*;
 use futures_util::{Stream, StreamExt};
 
+mod graphemes;
+pub use graphemes::{LineStream, TypewriterStream, UnicodeSentenceStream};
+
 /// A stream of text. This is automatically implemented for all streams of something that acts like a string (String, &str).
 pub trait TextStream<I: AsRef<str> = String>: Stream<Item = I> {
     /// Split the stream into words.
@@ -36,6 +39,46 @@ pub trait TextStream<I: AsRef<str> = String>: Stream<Item = I> {
         ParagraphStream::new(self)
     }
 
+    /// Split the stream into lines. Unlike [`TextStream::paragraphs`], the line endings (`\n` or `\r\n`) are
+    /// removed from each line.
+    fn lines(self) -> LineStream<Self, I>
+    where
+        Self: Sized,
+    {
+        LineStream::new(self)
+    }
+
+    /// Split the stream into sentences with the unicode sentence boundary rules. This handles numbers, quotes and
+    /// non-latin punctuation that [`TextStream::sentences`] splits incorrectly.
+    fn unicode_sentences(self) -> UnicodeSentenceStream<Self, I>
+    where
+        Self: Sized,
+    {
+        UnicodeSentenceStream::new(self)
+    }
+
+    /// Output one grapheme at a time with a delay between each grapheme. Graphemes are never split, so emoji and
+    /// accented characters that arrive over multiple tokens are written at once.
+    ///
+    /// ```rust
+    /// use futures_util::stream;
+    /// use kalosm_streams::text_stream::TextStream;
+    /// use std::time::Duration;
+    ///
+    /// async fn write_slowly() -> std::io::Result<()> {
+    ///     // The emoji arrives over two chunks, but it is written as one grapheme
+    ///     let mut stream =
+    ///         stream::iter(["Hello ", "👩\u{200d}", "🔬!"]).typewriter(Duration::from_millis(20));
+    ///     stream.to_std_out().await
+    /// }
+    /// ```
+    fn typewriter(self, delay: std::time::Duration) -> TypewriterStream<Self, I>
+    where
+        Self: Sized,
+    {
+        TypewriterStream::new(self, delay)
+    }
+
     /// Write the stream to a writer.
     fn write_to<'a, W: std::io::Write + Send + 'a>(
         &'a mut self,
@@ -67,6 +110,28 @@ pub trait TextStream<I: AsRef<str> = String>: Stream<Item = I> {
         }
     }
 
+    /// Write the stream to a writer, wrapping lines at unicode word boundaries so no line is wider than `width`
+    /// columns. Wide characters like emoji and CJK text count as two columns.
+    fn write_wrapped_to<'a, W: std::io::Write + Send + 'a>(
+        &'a mut self,
+        mut writer: W,
+        width: usize,
+    ) -> impl std::future::Future<Output = std::io::Result<()>> + Send + 'a
+    where
+        Self: Sized + Unpin + Send,
+    {
+        async move {
+            let mut wrapper = graphemes::WordWrapper::new(width);
+            while let Some(text) = self.next().await {
+                writer.write_all(wrapper.push(text.as_ref()).as_bytes())?;
+                writer.flush()?;
+            }
+            writer.write_all(wrapper.finish().as_bytes())?;
+            writer.flush()?;
+            Ok(())
+        }
+    }
+
     /// Write the stream to standard output.
     fn to_std_out(&mut self) -> impl std::future::Future<Output = std::io::Result<()>> + Send + '_
     where
@@ -74,6 +139,30 @@ pub trait TextStream<I: AsRef<str> = String>: Stream<Item = I> {
     {
         self.write_to(std::io::stdout())
     }
+
+    /// Write the stream to standard output, wrapping lines at unicode word boundaries so no line is wider than
+    /// `width` columns.
+    ///
+    /// ```rust
+    /// use futures_util::{stream, FutureExt};
+    /// use kalosm_streams::text_stream::TextStream;
+    ///
+    /// let mut stream = stream::iter(["Once upon a ti", "me there was a cat ", "named Tom."]);
+    /// stream
+    ///     .to_std_out_wrapped(20)
+    ///     .now_or_never()
+    ///     .unwrap()
+    ///     .unwrap();
+    /// ```
+    fn to_std_out_wrapped(
+        &mut self,
+        width: usize,
+    ) -> impl std::future::Future<Output = std::io::Result<()>> + Send + '_
+    where
+        Self: Sized + Unpin + Send,
+    {
+        self.write_wrapped_to(std::io::stdout(), width)
+    }
 }
 
 impl<S: Stream<Item = I>, I: AsRef<str>> TextStream<I> for S {}
@@ -0,0 +1,330 @@
+use pin_project_lite::pin_project;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_util::Stream;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A buffer that only releases whole grapheme clusters. Tokens often end in the middle of a cluster (like an emoji
+/// with a skin tone modifier or a letter with a combining accent), so the last cluster is held back until more text
+/// arrives.
+#[derive(Default)]
+struct GraphemeBuffer {
+    incomplete: String,
+}
+
+impl GraphemeBuffer {
+    /// Add text to the buffer and return the graphemes that are complete.
+    fn push(&mut self, text: &str) -> String {
+        self.incomplete.push_str(text);
+        let last_start = self
+            .incomplete
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(index, _)| index)
+            .unwrap_or_default();
+        let last = self.incomplete.split_off(last_start);
+        std::mem::replace(&mut self.incomplete, last)
+    }
+
+    /// Take the rest of the text once the stream has ended.
+    fn finish(&mut self) -> String {
+        std::mem::take(&mut self.incomplete)
+    }
+}
+
+/// Wraps streamed text to a fixed column width at unicode word boundaries.
+pub(crate) struct WordWrapper {
+    width: usize,
+    column: usize,
+    /// The last word segment which may continue in the next chunk of text
+    incomplete: String,
+}
+
+impl WordWrapper {
+    pub(crate) fn new(width: usize) -> Self {
+        Self {
+            width: width.max(1),
+            column: 0,
+            incomplete: String::new(),
+        }
+    }
+
+    /// Add text to the wrapper and return the wrapped text that is ready to be written.
+    pub(crate) fn push(&mut self, text: &str) -> String {
+        self.incomplete.push_str(text);
+        let incomplete = std::mem::take(&mut self.incomplete);
+        let mut segments = incomplete.split_word_bounds().peekable();
+        let mut output = String::new();
+        while let Some(segment) = segments.next() {
+            if segments.peek().is_none() {
+                self.incomplete = segment.to_string();
+                break;
+            }
+            self.write_segment(segment, &mut output);
+        }
+        output
+    }
+
+    /// Take the rest of the wrapped text once the stream has ended.
+    pub(crate) fn finish(&mut self) -> String {
+        let incomplete = std::mem::take(&mut self.incomplete);
+        let mut output = String::new();
+        self.write_segment(&incomplete, &mut output);
+        output
+    }
+
+    fn write_segment(&mut self, segment: &str, output: &mut String) {
+        if let Some((_, after_newline)) = segment.rsplit_once('\n') {
+            output.push_str(segment);
+            self.column = after_newline.width();
+            return;
+        }
+        let width = segment.width();
+        if segment.chars().all(char::is_whitespace) {
+            // Drop spaces that would run past the end of the line. The next word starts a new line
+            if self.column + width <= self.width {
+                output.push_str(segment);
+                self.column += width;
+            }
+            return;
+        }
+        if self.column > 0 && self.column + width > self.width {
+            output.push('\n');
+            self.column = 0;
+        }
+        if width <= self.width {
+            output.push_str(segment);
+            self.column += width;
+            return;
+        }
+        // Words longer than a line are broken between graphemes
+        for grapheme in segment.graphemes(true) {
+            let width = grapheme.width();
+            if self.column > 0 && self.column + width > self.width {
+                output.push('\n');
+                self.column = 0;
+            }
+            output.push_str(grapheme);
+            self.column += width;
+        }
+    }
+}
+
+pin_project! {
+    /// A stream that outputs one grapheme at a time with a fixed delay between each grapheme.
+    pub struct TypewriterStream<S: Stream<Item = I>, I: AsRef<str>> {
+        #[pin]
+        backing: S,
+        buffer: GraphemeBuffer,
+        queue: VecDeque<String>,
+        delay: Duration,
+        timer: Option<futures_timer::Delay>,
+        finished: bool,
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> TypewriterStream<S, I> {
+    /// Create a new typewriter stream from a stream of text
+    pub(crate) fn new(backing: S, delay: Duration) -> Self {
+        Self {
+            backing,
+            buffer: GraphemeBuffer::default(),
+            queue: VecDeque::new(),
+            delay,
+            timer: None,
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> Stream for TypewriterStream<S, I> {
+    type Item = String;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut projected = self.project();
+        // Keep reading the backing stream so the queue is full when the timer fires
+        while !*projected.finished {
+            match projected.backing.as_mut().poll_next(cx) {
+                Poll::Ready(Some(text)) => {
+                    let complete = projected.buffer.push(text.as_ref());
+                    projected
+                        .queue
+                        .extend(complete.graphemes(true).map(str::to_string));
+                }
+                Poll::Ready(None) => {
+                    *projected.finished = true;
+                    let rest = projected.buffer.finish();
+                    projected
+                        .queue
+                        .extend(rest.graphemes(true).map(str::to_string));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        if let Some(timer) = projected.timer.as_mut() {
+            if Pin::new(timer).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            *projected.timer = None;
+        }
+        match projected.queue.pop_front() {
+            Some(grapheme) => {
+                *projected.timer = Some(futures_timer::Delay::new(*projected.delay));
+                Poll::Ready(Some(grapheme))
+            }
+            None if *projected.finished => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+pin_project! {
+    /// A stream that outputs lines of text at a time without the line ending.
+    pub struct LineStream<S: Stream<Item = I>, I: AsRef<str>> {
+        #[pin]
+        backing: S,
+        queue: VecDeque<String>,
+        incomplete: String,
+        finished: bool,
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> LineStream<S, I> {
+    /// Create a new line stream from a stream of text
+    pub(crate) fn new(backing: S) -> Self {
+        Self {
+            backing,
+            queue: VecDeque::new(),
+            incomplete: String::new(),
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> Stream for LineStream<S, I> {
+    type Item = String;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut projected = self.project();
+        loop {
+            if let Some(line) = projected.queue.pop_front() {
+                return Poll::Ready(Some(line));
+            }
+            if *projected.finished {
+                return Poll::Ready(None);
+            }
+            match projected.backing.as_mut().poll_next(cx) {
+                Poll::Ready(Some(text)) => {
+                    projected.incomplete.push_str(text.as_ref());
+                    while let Some(end) = projected.incomplete.find('\n') {
+                        let mut line: String = projected.incomplete.drain(..=end).collect();
+                        line.pop();
+                        if line.ends_with('\r') {
+                            line.pop();
+                        }
+                        projected.queue.push_back(line);
+                    }
+                }
+                Poll::Ready(None) => {
+                    *projected.finished = true;
+                    if !projected.incomplete.is_empty() {
+                        projected
+                            .queue
+                            .push_back(std::mem::take(projected.incomplete));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// A stream that outputs sentences of text at a time. Sentences are split with the unicode sentence boundary
+    /// rules, so numbers like `3.14` and text in scripts that don't use `.` to end sentences are handled correctly.
+    pub struct UnicodeSentenceStream<S: Stream<Item = I>, I: AsRef<str>> {
+        #[pin]
+        backing: S,
+        queue: VecDeque<String>,
+        incomplete: String,
+        finished: bool,
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> UnicodeSentenceStream<S, I> {
+    /// Create a new sentence stream from a stream of text
+    pub(crate) fn new(backing: S) -> Self {
+        Self {
+            backing,
+            queue: VecDeque::new(),
+            incomplete: String::new(),
+            finished: false,
+        }
+    }
+}
+
+impl<S: Stream<Item = I>, I: AsRef<str>> Stream for UnicodeSentenceStream<S, I> {
+    type Item = String;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut projected = self.project();
+        loop {
+            if let Some(sentence) = projected.queue.pop_front() {
+                return Poll::Ready(Some(sentence));
+            }
+            if *projected.finished {
+                return Poll::Ready(None);
+            }
+            match projected.backing.as_mut().poll_next(cx) {
+                Poll::Ready(Some(text)) => {
+                    projected.incomplete.push_str(text.as_ref());
+                    // The last sentence may continue in the next chunk of text
+                    let last_start = projected
+                        .incomplete
+                        .split_sentence_bound_indices()
+                        .last()
+                        .map(|(index, _)| index)
+                        .unwrap_or_default();
+                    let last = projected.incomplete.split_off(last_start);
+                    let complete = std::mem::replace(projected.incomplete, last);
+                    projected
+                        .queue
+                        .extend(complete.split_sentence_bounds().map(str::to_string));
+                }
+                Poll::Ready(None) => {
+                    *projected.finished = true;
+                    if !projected.incomplete.is_empty() {
+                        projected
+                            .queue
+                            .push_back(std::mem::take(projected.incomplete));
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[test]
+fn word_wrapper_wraps_streamed_words() {
+    let mut wrapper = WordWrapper::new(10);
+    let mut output = String::new();
+    for chunk in ["The qu", "ick brown ", "fox 👩‍", "🔬 jumps\nover ", "it"] {
+        output += &wrapper.push(chunk);
+    }
+    output += &wrapper.finish();
+    assert_eq!(output, "The quick \nbrown fox \n👩‍🔬 jumps\nover it");
+
+    let mut buffer = GraphemeBuffer::default();
+    assert_eq!(buffer.push("ok 👩‍"), "ok ");
+    assert_eq!(buffer.push("🔬!"), "👩‍🔬");
+    assert_eq!(buffer.finish(), "!");
+}
@@ -0,0 +1,104 @@
+//! The kalosm command line tool.
+//!
+//! `kalosm serve-ui` starts an OpenAI compatible chat completions server with a small web chat frontend:
+//!
+//! ```text
+//! kalosm serve-ui [--host 127.0.0.1] [--port 8080] [--preset llama-3.1-8b-instruct] [--max-models 1]
+//! ```
+//!
+//! Any chat preset from [`LlamaSource::presets`](kalosm::language::LlamaSource::presets) can be selected with the
+//! `model` field of a request. At most `--max-models` models are kept in memory; the least recently used model is
+//! unloaded when another model is requested.
+//!
+//! Pass `--api-key <KEY>[:<QUOTA>]` to require API keys with an optional token quota for each key, and
+//! `--session-quota <TOKENS>` to limit the tokens each session (the `user` field of a request) can use. The tokens
+//! used by a key are reported at `/v1/usage`.
+
+mod server;
+mod usage;
+
+const USAGE: &str = "Usage: kalosm serve-ui [--host <HOST>] [--port <PORT>] [--preset <PRESET>] [--max-models <COUNT>]
+
+Commands:
+  serve-ui  Start an OpenAI compatible server with a web chat frontend
+
+Options:
+  --host <HOST>      The address to listen on [default: 127.0.0.1]
+  --port <PORT>      The port to listen on [default: 8080]
+  --preset <PRESET>  The model preset to load when the server starts [default: llama-3.1-8b-instruct]
+  --max-models <COUNT>
+                     The maximum number of models to keep loaded at once [default: 1]
+  --api-key <KEY>[:<QUOTA>]
+                     Require an API key with an optional token quota. Can be repeated
+  --session-quota <TOKENS>
+                     The maximum number of tokens each session can use";
+
+#[tokio::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("serve-ui") => {}
+        Some("--help" | "-h") => {
+            println!("{USAGE}");
+            return;
+        }
+        _ => exit_with_usage("expected a command"),
+    }
+
+    let mut config = server::ServerConfig::default();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| exit_with_usage(&format!("missing value for {arg}")))
+        };
+        match arg.as_str() {
+            "--host" => config.host = value(),
+            "--port" => {
+                config.port = value()
+                    .parse()
+                    .unwrap_or_else(|_| exit_with_usage("the port must be a number"))
+            }
+            "--preset" => config.preset = value(),
+            "--max-models" => {
+                config.max_models = value()
+                    .parse()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .unwrap_or_else(|| exit_with_usage("the model count must be a positive number"))
+            }
+            "--api-key" => {
+                let value = value();
+                let (key, quota) = match value.rsplit_once(':') {
+                    Some((key, quota)) => match quota.parse() {
+                        Ok(quota) => (key.to_string(), Some(quota)),
+                        Err(_) => (value.clone(), None),
+                    },
+                    None => (value, None),
+                };
+                config.quotas.api_keys.insert(key, quota);
+            }
+            "--session-quota" => {
+                config.quotas.session_quota = Some(
+                    value()
+                        .parse()
+                        .unwrap_or_else(|_| exit_with_usage("the session quota must be a number")),
+                )
+            }
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => exit_with_usage(&format!("unknown argument {arg}")),
+        }
+    }
+
+    if let Err(err) = server::serve(config).await {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn exit_with_usage(error: &str) -> ! {
+    eprintln!("error: {error}\n\n{USAGE}");
+    std::process::exit(2);
+}
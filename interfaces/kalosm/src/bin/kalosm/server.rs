@@ -0,0 +1,473 @@
+use crate::usage::{QuotaConfig, QuotaError, Usage, UsageTracker};
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        Html, IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use kalosm::language::*;
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The bundled web chat frontend.
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// The preset that is loaded when the server starts if no other preset is selected.
+const DEFAULT_PRESET: &str = "llama-3.1-8b-instruct";
+
+/// Get the chat presets that can be selected in the frontend or with the `model` field of a request.
+fn chat_presets() -> impl Iterator<Item = &'static LlamaPreset> {
+    LlamaSource::presets()
+        .iter()
+        .filter(|preset| preset.is_chat())
+}
+
+/// Find a chat preset by name. The name is case insensitive.
+fn find_preset(name: &str) -> Option<&'static LlamaPreset> {
+    chat_presets().find(|preset| preset.name().eq_ignore_ascii_case(name))
+}
+
+/// The configuration for `kalosm serve-ui`.
+pub(crate) struct ServerConfig {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) preset: String,
+    /// The maximum number of models kept in memory at once. The least recently used model is unloaded when another
+    /// model is requested.
+    pub(crate) max_models: usize,
+    pub(crate) quotas: QuotaConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            preset: DEFAULT_PRESET.to_string(),
+            max_models: 1,
+            quotas: QuotaConfig::default(),
+        }
+    }
+}
+
+/// A model that is loaded the first time it is requested. Requests for the same model wait for the same load.
+type LoadingModel = Arc<tokio::sync::OnceCell<Llama>>;
+
+/// The models that have been loaded so far. Models are loaded the first time they are requested.
+struct AppState {
+    default_preset: &'static str,
+    max_models: usize,
+    /// The loaded and loading models from least to most recently used
+    models: Mutex<VecDeque<(&'static str, LoadingModel)>>,
+    usage: Arc<UsageTracker>,
+}
+
+impl AppState {
+    async fn model(&self, id: Option<&str>) -> Result<(&'static str, Llama), ApiError> {
+        let id = id.unwrap_or(self.default_preset);
+        let preset = find_preset(id)
+            .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Unknown model {id}")))?;
+        let model = {
+            let mut models = self.models.lock().unwrap();
+            match models.iter().position(|(id, _)| *id == preset.name()) {
+                Some(index) => {
+                    let entry = models.remove(index).unwrap();
+                    models.push_back(entry.clone());
+                    entry.1
+                }
+                None => {
+                    // Forget the least recently used models before loading a new one. Requests that are still
+                    // running or loading keep their clone of the model alive until they finish, so an unloaded
+                    // model may still be in memory while the new model loads.
+                    while models.len() >= self.max_models {
+                        if let Some((id, _)) = models.pop_front() {
+                            println!("Unloading {id}...");
+                        }
+                    }
+                    let model = LoadingModel::default();
+                    models.push_back((preset.name(), model.clone()));
+                    model
+                }
+            }
+        };
+        // Load the model without holding the lock, so requests for models that are already loaded don't wait
+        let model = model
+            .get_or_try_init(|| async {
+                println!("Loading {}...", preset.name());
+                Llama::builder().with_source(preset.source()).build().await
+            })
+            .await
+            .map_err(|err| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+        Ok((preset.name(), model.clone()))
+    }
+}
+
+/// Start the server and load the default preset.
+pub(crate) async fn serve(config: ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let default_preset = find_preset(&config.preset)
+        .ok_or_else(|| {
+            let presets: Vec<_> = chat_presets().map(|preset| preset.name()).collect();
+            format!(
+                "unknown preset {}, expected one of: {}",
+                config.preset,
+                presets.join(", ")
+            )
+        })?
+        .name();
+    let state = Arc::new(AppState {
+        default_preset,
+        max_models: config.max_models,
+        models: Default::default(),
+        usage: Arc::new(UsageTracker::new(config.quotas)),
+    });
+    state.model(None).await.map_err(|err| err.message)?;
+
+    let app = Router::new()
+        .route("/", get(|| async { Html(INDEX_HTML) }))
+        .route("/v1/models", get(list_models))
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/usage", get(usage_report))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind((config.host.as_str(), config.port)).await?;
+    println!("Chat UI running at http://{}:{}", config.host, config.port);
+    println!(
+        "OpenAI compatible API running at http://{}:{}/v1",
+        config.host, config.port
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// An error response in the OpenAI error format.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<QuotaError> for ApiError {
+    fn from(err: QuotaError) -> Self {
+        match err {
+            QuotaError::Unauthorized => {
+                ApiError::new(StatusCode::UNAUTHORIZED, "Missing or invalid API key")
+            }
+            QuotaError::Exceeded => ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "The token quota was exceeded",
+            ),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = json!({ "error": { "message": self.message, "type": "invalid_request_error" } });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Get the API key from the authorization header of a request.
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Report the tokens used by the API key of the request and each of its sessions.
+async fn usage_report(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let key = state.usage.authorize(api_key(&headers))?;
+    Ok(Json(state.usage.report(&key)))
+}
+
+async fn list_models(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let data: Vec<_> = chat_presets()
+        .map(|preset| {
+            json!({
+                "id": preset.name(),
+                "object": "model",
+                "owned_by": "kalosm",
+                "description": preset.description(),
+                "parameters": preset.parameters(),
+                "default": preset.name() == state.default_preset,
+            })
+        })
+        .collect();
+    Json(json!({ "object": "list", "data": data }))
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionRequest {
+    model: Option<String>,
+    messages: Vec<RequestMessage>,
+    #[serde(default)]
+    stream: bool,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    max_completion_tokens: Option<u32>,
+    seed: Option<u64>,
+    /// The session the request is part of. Session quotas are tracked per user
+    user: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RequestMessage {
+    role: String,
+    content: String,
+}
+
+async fn chat_completions(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<ChatCompletionRequest>,
+) -> Result<Response, ApiError> {
+    let key = state.usage.authorize(api_key(&headers))?;
+
+    let messages = request
+        .messages
+        .iter()
+        .map(|message| {
+            let role = match message.role.as_str() {
+                "system" | "developer" => MessageType::SystemPrompt,
+                "user" => MessageType::UserMessage,
+                "assistant" => MessageType::ModelAnswer,
+                role => {
+                    return Err(ApiError::new(
+                        StatusCode::BAD_REQUEST,
+                        format!("Unsupported message role {role}"),
+                    ))
+                }
+            };
+            Ok(ChatMessage::new(role, &message.content))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if messages.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "At least one message is required",
+        ));
+    }
+
+    let (model_id, model) = state.model(request.model.as_deref()).await?;
+    // Reserve the tokens of the request before generating so concurrent requests can't exceed the quota together.
+    // The model never generates more tokens than the reservation allows
+    let prompt_tokens = count_prompt_tokens(&model, &request.messages);
+    let reservation = state.usage.reserve(
+        &key,
+        request.user.as_deref(),
+        prompt_tokens,
+        request
+            .max_completion_tokens
+            .or(request.max_tokens)
+            .map(u64::from),
+    )?;
+    let mut parameters = GenerationParameters::default().with_seed(request.seed);
+    if let Some(temperature) = request.temperature {
+        parameters = parameters.with_temperature(temperature);
+    }
+    if let Some(max_tokens) = reservation.limit() {
+        parameters = parameters.with_max_length(max_tokens.min(u32::MAX as u64) as u32);
+    }
+    let finish_reason = Arc::new(Mutex::new(None));
+    let usage = Arc::new(Mutex::new(None));
+    parameters = parameters
+        .with_finish_handler({
+            let finish_reason = finish_reason.clone();
+            move |reason| *finish_reason.lock().unwrap() = Some(reason)
+        })
+        .with_usage_handler({
+            let usage = usage.clone();
+            move |report| *usage.lock().unwrap() = Some(report)
+        });
+
+    // Run the model in the background and forward tokens to the response as they are generated
+    let (tokens_tx, mut tokens_rx) = tokio::sync::mpsc::unbounded_channel();
+    let generation = tokio::spawn(async move {
+        let mut session = model.new_chat_session()?;
+        model
+            .add_messages_with_callback(&mut session, &messages, parameters, move |token| {
+                _ = tokens_tx.send(token);
+                Ok(())
+            })
+            .await
+    });
+    // Record the tokens used by the request once it finishes and get the OpenAI finish reason. The model only
+    // reports usage for generations that succeed, so failed requests are charged for the prompt and the tokens
+    // streamed before the failure
+    let settle = move |streamed_tokens: u64| {
+        let usage = usage
+            .lock()
+            .unwrap()
+            .take()
+            .map(|usage| Usage {
+                prompt_tokens: usage.prompt_tokens() as u64,
+                completion_tokens: usage.completion_tokens() as u64,
+            })
+            .unwrap_or(Usage {
+                prompt_tokens,
+                completion_tokens: streamed_tokens,
+            });
+        reservation.finish(usage);
+        let finish_reason = match *finish_reason.lock().unwrap() {
+            Some(FinishReason::MaxTokens | FinishReason::TimedOut) => "length",
+            _ => "stop",
+        };
+        (usage, finish_reason)
+    };
+
+    let id = format!("chatcmpl-{}", rand_id());
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+
+    if !request.stream {
+        let mut content = String::new();
+        let mut streamed_tokens = 0;
+        while let Some(token) = tokens_rx.recv().await {
+            content += &token;
+            streamed_tokens += 1;
+        }
+        let result = finish(generation).await;
+        let (usage, finish_reason) = settle(streamed_tokens);
+        result?;
+        // Reasoning models return the text inside <think> tags separately like the DeepSeek API
+        let response = ReasoningResponse::from_text(&content);
+        let mut message = json!({ "role": "assistant", "content": response.answer });
+        if !response.reasoning.is_empty() {
+            message["reasoning_content"] = json!(response.reasoning);
+        }
+        let body = json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": model_id,
+            "choices": [{
+                "index": 0,
+                "message": message,
+                "finish_reason": finish_reason,
+            }],
+            "usage": usage.to_json(),
+        });
+        return Ok(Json(body).into_response());
+    }
+
+    let chunk = move |delta: serde_json::Value, finish_reason: Option<&str>| {
+        json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": model_id,
+            "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+        })
+        .to_string()
+    };
+    let first = chunk(json!({ "role": "assistant", "content": "" }), None);
+    let stream = async_stream(move |send| async move {
+        send(Event::default().data(first));
+        let mut splitter = ThinkTagSplitter::new();
+        let send_chunks = |chunks: Vec<ReasoningChunk>| {
+            for reasoning_chunk in chunks {
+                let delta = match reasoning_chunk {
+                    ReasoningChunk::Reasoning(text) => json!({ "reasoning_content": text }),
+                    ReasoningChunk::Answer(text) => json!({ "content": text }),
+                };
+                send(Event::default().data(chunk(delta, None)));
+            }
+        };
+        let mut streamed_tokens = 0;
+        while let Some(token) = tokens_rx.recv().await {
+            send_chunks(splitter.push(&token));
+            streamed_tokens += 1;
+        }
+        send_chunks(splitter.finish());
+        let result = finish(generation).await;
+        let (_, finish_reason) = settle(streamed_tokens);
+        match result {
+            Ok(()) => send(Event::default().data(chunk(json!({}), Some(finish_reason)))),
+            Err(err) => send(
+                Event::default().data(json!({ "error": { "message": err.message } }).to_string()),
+            ),
+        }
+        send(Event::default().data("[DONE]"));
+    });
+    Ok(Sse::new(stream).into_response())
+}
+
+/// Count the tokens of the messages of a request. The count does not include the tokens the chat template adds around
+/// each message.
+fn count_prompt_tokens(model: &Llama, messages: &[RequestMessage]) -> u64 {
+    let tokenizer = model.tokenizer();
+    messages
+        .iter()
+        .map(|message| {
+            tokenizer
+                .encode_fast(message.content.as_str(), false)
+                .map_or(0, |encoding| encoding.len() as u64)
+        })
+        .sum()
+}
+
+/// Wait for the model to finish generating.
+async fn finish<T, E: std::fmt::Display>(
+    generation: tokio::task::JoinHandle<Result<T, E>>,
+) -> Result<T, ApiError> {
+    match generation.await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(err)) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err)),
+        Err(err) => Err(ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err)),
+    }
+}
+
+/// Create a stream of server sent events from an async function that sends events.
+fn async_stream<F, Fut>(
+    run: F,
+) -> impl futures_util::Stream<Item = Result<Event, std::convert::Infallible>>
+where
+    F: FnOnce(Box<dyn Fn(Event) + Send + Sync>) -> Fut,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(run(Box::new(move |event| {
+        _ = tx.send(event);
+    })));
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (Ok(event), rx))
+    })
+}
+
+/// Create a random id for a completion.
+fn rand_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default(),
+    );
+    format!("{:016x}", hasher.finish())
+}
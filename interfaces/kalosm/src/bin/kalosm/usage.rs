@@ -0,0 +1,288 @@
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The key usage is recorded under when the server does not require API keys.
+const ANONYMOUS: &str = "anonymous";
+
+/// The number of tokens used by an API key or session.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Usage {
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+}
+
+impl Usage {
+    pub(crate) fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+
+    pub(crate) fn to_json(self) -> serde_json::Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.total_tokens(),
+        })
+    }
+}
+
+/// The API keys and token quotas of the server.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct QuotaConfig {
+    /// The API keys that may use the server with an optional token quota for each key. If there are no keys, the
+    /// server does not require a key.
+    pub(crate) api_keys: HashMap<String, Option<u64>>,
+    /// The token quota for each session. Sessions are identified by the `user` field of a request.
+    pub(crate) session_quota: Option<u64>,
+}
+
+/// Why a request was refused.
+#[derive(Debug)]
+pub(crate) enum QuotaError {
+    /// The API key is missing or unknown.
+    Unauthorized,
+    /// The API key or session has used its whole quota.
+    Exceeded,
+}
+
+/// The tokens used by an API key or session and the tokens reserved by its running requests.
+#[derive(Default)]
+struct Ledger {
+    usage: Usage,
+    reserved: u64,
+}
+
+impl Ledger {
+    fn remaining(&self, quota: u64) -> u64 {
+        quota.saturating_sub(self.usage.total_tokens() + self.reserved)
+    }
+}
+
+#[derive(Default)]
+struct KeyUsage {
+    ledger: Ledger,
+    sessions: HashMap<String, Ledger>,
+}
+
+/// Tracks the tokens used by each API key and session and enforces the quotas.
+pub(crate) struct UsageTracker {
+    config: QuotaConfig,
+    usage: Mutex<HashMap<String, KeyUsage>>,
+}
+
+impl UsageTracker {
+    pub(crate) fn new(config: QuotaConfig) -> Self {
+        Self {
+            config,
+            usage: Default::default(),
+        }
+    }
+
+    /// Get the key usage is recorded under for the API key of a request.
+    pub(crate) fn authorize(&self, api_key: Option<&str>) -> Result<String, QuotaError> {
+        if self.config.api_keys.is_empty() {
+            return Ok(ANONYMOUS.to_string());
+        }
+        match api_key {
+            Some(api_key) if self.config.api_keys.contains_key(api_key) => Ok(api_key.to_string()),
+            _ => Err(QuotaError::Unauthorized),
+        }
+    }
+
+    /// Reserve the prompt and up to `max_tokens` completion tokens of the quota of an API key and session for a
+    /// request. Requests whose prompt leaves no room for a completion are refused. The check and the reservation
+    /// happen under one lock, so concurrent requests can never spend the same tokens. If there is no quota, nothing is
+    /// reserved.
+    pub(crate) fn reserve(
+        self: &Arc<Self>,
+        key: &str,
+        session: Option<&str>,
+        prompt_tokens: u64,
+        max_tokens: Option<u64>,
+    ) -> Result<Reservation, QuotaError> {
+        let mut usage = self.usage.lock().unwrap();
+        let key_usage = usage.entry(key.to_string()).or_default();
+        let key_remaining = self
+            .config
+            .api_keys
+            .get(key)
+            .copied()
+            .flatten()
+            .map(|quota| key_usage.ledger.remaining(quota));
+        let session_remaining = session.and_then(|session| {
+            let quota = self.config.session_quota?;
+            Some(
+                key_usage
+                    .sessions
+                    .get(session)
+                    .map_or(quota, |ledger| ledger.remaining(quota)),
+            )
+        });
+        let remaining = match (key_remaining, session_remaining) {
+            (Some(key), Some(session)) => Some(key.min(session)),
+            (key, session) => key.or(session),
+        };
+        if remaining.is_some_and(|remaining| remaining <= prompt_tokens) {
+            return Err(QuotaError::Exceeded);
+        }
+
+        let completion_remaining = remaining.map(|remaining| remaining - prompt_tokens);
+        let limit = max_tokens.into_iter().chain(completion_remaining).min();
+        let reserved = completion_remaining
+            .and(limit)
+            .map_or(0, |limit| prompt_tokens + limit);
+        key_usage.ledger.reserved += reserved;
+        if let Some(session) = session {
+            key_usage
+                .sessions
+                .entry(session.to_string())
+                .or_default()
+                .reserved += reserved;
+        }
+        Ok(Reservation {
+            tracker: self.clone(),
+            key: key.to_string(),
+            session: session.map(ToString::to_string),
+            reserved,
+            limit,
+            used: Usage::default(),
+        })
+    }
+
+    /// Release the tokens reserved for a request and record the tokens it used.
+    fn settle(&self, reservation: &Reservation) {
+        let tokens = reservation.used;
+        let mut usage = self.usage.lock().unwrap();
+        let key_usage = usage.entry(reservation.key.clone()).or_default();
+        key_usage.ledger.reserved -= reservation.reserved;
+        key_usage.ledger.usage.add(tokens);
+        if let Some(session) = &reservation.session {
+            let ledger = key_usage.sessions.entry(session.clone()).or_default();
+            ledger.reserved -= reservation.reserved;
+            ledger.usage.add(tokens);
+        }
+    }
+
+    /// Get the usage and quotas of an API key and each of its sessions.
+    pub(crate) fn report(&self, key: &str) -> serde_json::Value {
+        let usage = self.usage.lock().unwrap();
+        let key_usage = usage.get(key);
+        let sessions: serde_json::Map<_, _> = key_usage
+            .into_iter()
+            .flat_map(|usage| &usage.sessions)
+            .map(|(session, ledger)| {
+                let mut report = ledger.usage.to_json();
+                report["quota"] = json!(self.config.session_quota);
+                (session.clone(), report)
+            })
+            .collect();
+        let mut report = key_usage
+            .map(|usage| usage.ledger.usage)
+            .unwrap_or_default()
+            .to_json();
+        report["object"] = json!("usage");
+        report["quota"] = json!(self.config.api_keys.get(key).copied().flatten());
+        report["sessions"] = json!(sessions);
+        report
+    }
+}
+
+/// Tokens of a quota reserved for a running request. The reservation is released and the tokens the request used
+/// are recorded when it is dropped, so the tokens of a failed request go back to the quota.
+pub(crate) struct Reservation {
+    tracker: Arc<UsageTracker>,
+    key: String,
+    session: Option<String>,
+    reserved: u64,
+    limit: Option<u64>,
+    used: Usage,
+}
+
+impl Reservation {
+    /// Get the maximum number of completion tokens the request may generate, or `None` if there is no limit.
+    pub(crate) fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    /// Record the tokens the request used and release the rest of the reservation.
+    pub(crate) fn finish(mut self, tokens: Usage) {
+        self.used = tokens;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.tracker.settle(self);
+    }
+}
+
+#[cfg(test)]
+fn tracker_with_quotas(key_quota: Option<u64>, session_quota: Option<u64>) -> Arc<UsageTracker> {
+    Arc::new(UsageTracker::new(QuotaConfig {
+        api_keys: HashMap::from([("key".to_string(), key_quota)]),
+        session_quota,
+    }))
+}
+
+#[test]
+fn reserving_counts_the_prompt_against_the_quota() {
+    let tracker = tracker_with_quotas(Some(100), None);
+    let first = tracker.reserve("key", None, 30, Some(50)).unwrap();
+    assert_eq!(first.limit(), Some(50));
+
+    // The first request holds 80 tokens, so the second request may only generate 10 tokens after its prompt
+    let second = tracker.reserve("key", None, 10, None).unwrap();
+    assert_eq!(second.limit(), Some(10));
+    assert!(matches!(
+        tracker.reserve("key", None, 1, None),
+        Err(QuotaError::Exceeded)
+    ));
+}
+
+#[test]
+fn settling_records_usage_and_releases_the_rest_of_the_reservation() {
+    let tracker = tracker_with_quotas(Some(100), Some(60));
+    let reservation = tracker.reserve("key", Some("session"), 20, None).unwrap();
+    assert_eq!(reservation.limit(), Some(40));
+    reservation.finish(Usage {
+        prompt_tokens: 20,
+        completion_tokens: 5,
+    });
+
+    let report = tracker.report("key");
+    assert_eq!(report["total_tokens"], 25);
+    assert_eq!(report["sessions"]["session"]["total_tokens"], 25);
+    let reservation = tracker.reserve("key", Some("session"), 5, None).unwrap();
+    assert_eq!(reservation.limit(), Some(30));
+    drop(reservation);
+
+    // A request that is dropped without finishing only releases its reservation
+    assert_eq!(tracker.report("key")["total_tokens"], 25);
+    let reservation = tracker.reserve("key", None, 5, None).unwrap();
+    assert_eq!(reservation.limit(), Some(70));
+}
+
+#[test]
+fn prompts_that_exceed_the_quota_are_rejected() {
+    let tracker = tracker_with_quotas(Some(100), Some(50));
+    assert!(matches!(
+        tracker.reserve("key", None, 100, None),
+        Err(QuotaError::Exceeded)
+    ));
+    assert!(matches!(
+        tracker.reserve("key", Some("session"), 60, Some(1)),
+        Err(QuotaError::Exceeded)
+    ));
+    // Rejected requests don't reserve anything
+    assert!(tracker.reserve("key", Some("session"), 49, None).is_ok());
+
+    // Without a quota, only the maximum number of tokens of the request limits it
+    let unlimited = tracker_with_quotas(None, None);
+    let reservation = unlimited.reserve("key", None, 1_000_000, Some(10)).unwrap();
+    assert_eq!(reservation.limit(), Some(10));
+}
@@ -0,0 +1,161 @@
+//! Hooks that bridge kalosm streams into [Dioxus](https://dioxuslabs.com) signals.
+//!
+//! Every hook spawns its task in the scope of the component that calls it, so the stream is
+//! dropped and the model stops generating when the component unmounts.
+
+use ::dioxus::prelude::*;
+use futures_util::{Stream, StreamExt};
+use kalosm_model_types::ModelLoadingProgress;
+
+/// The state of a stream that is being collected into a signal.
+pub struct StreamSignal<T: 'static> {
+    value: Signal<T>,
+    finished: Signal<bool>,
+    task: Task,
+}
+
+impl<T: 'static> Clone for StreamSignal<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: 'static> Copy for StreamSignal<T> {}
+
+impl<T: 'static> StreamSignal<T> {
+    /// Get the signal that holds the value collected from the stream so far.
+    pub fn value(&self) -> Signal<T> {
+        self.value
+    }
+
+    /// Check if the stream has finished.
+    pub fn is_finished(&self) -> bool {
+        (self.finished)()
+    }
+
+    /// Stop reading from the stream. The value collected so far is kept.
+    pub fn cancel(&self) {
+        self.task.cancel();
+    }
+}
+
+/// Fold every item in a stream into a signal. The stream is created once when the component first renders.
+pub fn use_stream_fold<S, T>(
+    stream: impl FnOnce() -> S + 'static,
+    initial: impl FnOnce() -> T,
+    mut fold: impl FnMut(&mut T, S::Item) + 'static,
+) -> StreamSignal<T>
+where
+    S: Stream + 'static,
+    T: 'static,
+{
+    let mut value = use_signal(initial);
+    let mut finished = use_signal(|| false);
+    let task = use_hook(move || {
+        let stream = stream();
+        spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(item) = stream.next().await {
+                fold(&mut value.write(), item);
+            }
+            finished.set(true);
+        })
+    });
+    StreamSignal {
+        value,
+        finished,
+        task,
+    }
+}
+
+/// Collect a stream of text (like the tokens from a model) into a string signal.
+///
+/// # Example
+/// ```rust, ignore
+/// use dioxus::prelude::*;
+/// use kalosm::language::*;
+/// use kalosm::use_text_stream;
+///
+/// #[component]
+/// fn Story(model: ReadOnlySignal<Llama>) -> Element {
+///     let text = use_text_stream(move || model.read().complete("Once upon a time"));
+///     rsx! { p { "{text.value()}" } }
+/// }
+/// ```
+pub fn use_text_stream<S>(stream: impl FnOnce() -> S + 'static) -> StreamSignal<String>
+where
+    S: Stream + 'static,
+    S::Item: AsRef<str>,
+{
+    use_stream_fold(stream, String::new, |text, token| {
+        text.push_str(token.as_ref())
+    })
+}
+
+/// Collect every item in a stream (like the segments from a transcription) into a vec signal.
+pub fn use_stream_items<S>(stream: impl FnOnce() -> S + 'static) -> StreamSignal<Vec<S::Item>>
+where
+    S: Stream + 'static,
+{
+    use_stream_fold(stream, Vec::new, |items, item| items.push(item))
+}
+
+/// Keep only the latest item from a stream in a signal.
+pub fn use_stream_latest<S>(stream: impl FnOnce() -> S + 'static) -> StreamSignal<Option<S::Item>>
+where
+    S: Stream + 'static,
+{
+    use_stream_fold(stream, || None, |latest, item| *latest = Some(item))
+}
+
+/// A signal that tracks the progress of a model while it loads.
+#[derive(Clone, Copy)]
+pub struct LoadingProgressSignal {
+    progress: SyncSignal<Option<ModelLoadingProgress>>,
+}
+
+impl LoadingProgressSignal {
+    /// Get the latest progress reported by the model.
+    pub fn progress(&self) -> Option<ModelLoadingProgress> {
+        self.progress.cloned()
+    }
+
+    /// Get the progress as a fraction from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        self.progress
+            .read()
+            .as_ref()
+            .map(|progress| progress.progress())
+            .unwrap_or_default()
+    }
+
+    /// Create a loading handler that can be passed to a model builder like `LlamaBuilder::build_with_loading_handler`.
+    pub fn handler(&self) -> impl FnMut(ModelLoadingProgress) + Send + Sync + 'static {
+        let mut progress = self.progress;
+        move |update| progress.set(Some(update))
+    }
+}
+
+/// Create a signal that tracks the download and loading progress of a model.
+///
+/// # Example
+/// ```rust, ignore
+/// use dioxus::prelude::*;
+/// use kalosm::language::*;
+/// use kalosm::use_loading_progress;
+///
+/// #[component]
+/// fn LoadModel() -> Element {
+///     let progress = use_loading_progress();
+///     let model = use_resource(move || async move {
+///         Llama::builder()
+///             .build_with_loading_handler(progress.handler())
+///             .await
+///     });
+///     rsx! { progress { value: "{progress.fraction()}" } }
+/// }
+/// ```
+pub fn use_loading_progress() -> LoadingProgressSignal {
+    let progress = use_signal_sync(|| None);
+    LoadingProgressSignal { progress }
+}
@@ -9,6 +9,8 @@ pub mod language {
     #![doc = include_str!("../docs/language.md")]
     #[cfg(any(feature = "bert", feature = "llama"))]
     pub use kalosm_common::accelerated_device_if_available;
+    #[cfg(any(feature = "bert", feature = "llama"))]
+    pub use kalosm_common::{ConfigError, DevicePreference, KalosmConfig};
     pub use kalosm_language::context::*;
     pub use kalosm_language::kalosm_language_model::{
         ChatModel as _, ChatModelExt as _, ChatSession as _, CreateChatSession as _,
@@ -20,7 +22,11 @@ pub mod language {
     };
     #[cfg(feature = "llama")]
     pub use kalosm_language::kalosm_llama::{
-        Llama, LlamaBuilder, LlamaChatSession, LlamaSession, LlamaSource,
+        ActivationCapture, AttentionSinks, BackgroundLlama, CapturedActivations, FinetuneError,
+        FinetuneProgress, FinetunedLora, GgufQuantization, InstructionDataset, InstructionExample,
+        KvCacheQuantization, LayerActivations, Llama, LlamaBuilder, LlamaChatSession, LlamaPooling,
+        LlamaPreset, LlamaSession, LlamaSource, LoraFinetune, MemoryEstimate, OomPolicy,
+        Quantization, RopeScaling,
     };
     pub use kalosm_language::kalosm_sample::{self, *};
     pub use kalosm_language::prelude::Html;
@@ -28,7 +34,10 @@ pub mod language {
     pub use kalosm_language::rbert::{Bert, BertBuilder, BertSource};
     pub use kalosm_language::search::*;
     pub use kalosm_language::vector_db::*;
-    pub use kalosm_model_types::{FileLoadingProgress, FileSource, ModelLoadingProgress};
+    pub use kalosm_model_types::{
+        FileLoadingProgress, FileSource, LoadingCancelled, LoadingHandle, LoadingStatus,
+        ModelLoadingProgress,
+    };
     pub use kalosm_streams::text_stream::*;
 
     #[cfg(feature = "surrealdb")]
@@ -54,6 +63,11 @@ mod evaluate;
 #[cfg(feature = "language")]
 pub use evaluate::*;
 
+#[cfg(all(feature = "language", feature = "sound"))]
+mod realtime_translation;
+#[cfg(all(feature = "language", feature = "sound"))]
+pub use realtime_translation::*;
+
 #[cfg(feature = "prompt_annealing")]
 mod prompt_annealing;
 #[cfg(feature = "prompt_annealing")]
@@ -65,3 +79,8 @@ mod surrealdb_integration;
 pub use ::surrealdb;
 #[cfg(feature = "surrealdb")]
 pub use surrealdb_integration::*;
+
+#[cfg(feature = "dioxus")]
+mod dioxus_integration;
+#[cfg(feature = "dioxus")]
+pub use dioxus_integration::*;
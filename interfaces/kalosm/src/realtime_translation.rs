@@ -0,0 +1,345 @@
+use futures_util::{Stream, StreamExt};
+use kalosm_language::kalosm_language_model::{ChatModel, GenerationParameters, Task};
+use kalosm_language::search::SentenceChunker;
+use kalosm_sound::{AsyncSource, AsyncSourceTranscribeExt, Segment, Whisper};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A pipeline that transcribes speech with [`Whisper`], splits the transcript into sentences, and translates each
+/// sentence with a chat model as soon as it is complete. This is useful for live caption translation.
+///
+/// If a speaker doesn't finish a sentence within the [maximum latency](RealtimeTranslator::with_max_latency), the
+/// partial sentence is translated anyway so captions never fall too far behind the audio.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use kalosm::sound::*;
+/// use kalosm::RealtimeTranslator;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), anyhow::Error> {
+///     let whisper = Whisper::new().await?;
+///     let llm = Llama::new_chat().await?;
+///     let translator = RealtimeTranslator::new(whisper, llm, "Spanish")
+///         .with_max_latency(Duration::from_secs(4));
+///
+///     let mic = MicInput::default();
+///     let mut captions = translator.translate(mic.stream());
+///     while let Some(sentence) = captions.next().await {
+///         let sentence = sentence?;
+///         println!("{} -> {}", sentence.source(), sentence.translation());
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RealtimeTranslator<M: ChatModel> {
+    whisper: Whisper,
+    task: Task<M>,
+    max_latency: Duration,
+    no_speech_threshold: f64,
+}
+
+impl<M: ChatModel> RealtimeTranslator<M> {
+    /// Create a new translator that transcribes audio with the whisper model and translates it into the target language with the chat model.
+    pub fn new(whisper: Whisper, model: M, target_language: impl std::fmt::Display) -> Self {
+        let task = Task::new(
+            model,
+            format!(
+                "You are a live caption translator. Translate each message into {target_language}. \
+                Messages may be partial sentences from a speech transcript. Respond with only the translation."
+            ),
+        );
+        Self {
+            whisper,
+            task,
+            max_latency: Duration::from_secs(3),
+            no_speech_threshold: 0.5,
+        }
+    }
+
+    /// Set the latency target for each sentence. If the audio of an unfinished sentence is longer than this, the
+    /// partial sentence is translated without waiting for the speaker to finish it. (Defaults to 3 seconds)
+    pub fn with_max_latency(mut self, max_latency: Duration) -> Self {
+        self.max_latency = max_latency;
+        self
+    }
+
+    /// Set the probability of no speech above which transcribed segments are ignored. (Defaults to 0.5)
+    pub fn with_no_speech_threshold(mut self, threshold: f64) -> Self {
+        self.no_speech_threshold = threshold;
+        self
+    }
+
+    /// Get the latency target for each sentence.
+    pub fn max_latency(&self) -> Duration {
+        self.max_latency
+    }
+}
+
+impl<M> RealtimeTranslator<M>
+where
+    M: ChatModel<GenerationParameters> + Send + Sync + Unpin + Clone + 'static,
+    M::ChatSession: Clone + Send + Sync + Unpin + 'static,
+{
+    /// Transcribe and translate a stream of audio. Speech is split into chunks with voice activity detection before it is transcribed.
+    pub fn translate<S>(
+        &self,
+        audio: S,
+    ) -> impl Stream<Item = Result<TranslatedSentence, M::Error>> + Send + 'static
+    where
+        S: AsyncSource + Unpin + Send + 'static,
+    {
+        self.translate_segments(audio.transcribe(self.whisper.clone()))
+    }
+
+    /// Translate a stream of segments that have already been transcribed.
+    pub fn translate_segments<S>(
+        &self,
+        segments: S,
+    ) -> impl Stream<Item = Result<TranslatedSentence, M::Error>> + Send + 'static
+    where
+        S: Stream<Item = Segment> + Unpin + Send + 'static,
+    {
+        let state = TranslationState {
+            segments: Some(segments),
+            buffer: SentenceBuffer::new(self.max_latency),
+            ready: VecDeque::new(),
+            task: self.task.clone(),
+            no_speech_threshold: self.no_speech_threshold,
+        };
+        futures_util::stream::unfold(state, |mut state| async move {
+            let sentence = state.next_sentence().await?;
+            let started = sentence.received;
+            let translation =
+                state
+                    .task
+                    .run(&sentence.text)
+                    .await
+                    .map(|translation| TranslatedSentence {
+                        source: sentence.text,
+                        translation: translation.trim().to_string(),
+                        start: sentence.start,
+                        end: sentence.end,
+                        latency: started.elapsed(),
+                    });
+            Some((translation, state))
+        })
+    }
+}
+
+struct TranslationState<S, M: ChatModel> {
+    segments: Option<S>,
+    buffer: SentenceBuffer,
+    ready: VecDeque<PendingSentence>,
+    task: Task<M>,
+    no_speech_threshold: f64,
+}
+
+impl<S: Stream<Item = Segment> + Unpin, M: ChatModel> TranslationState<S, M> {
+    /// Wait for the next sentence that is ready to translate.
+    async fn next_sentence(&mut self) -> Option<PendingSentence> {
+        loop {
+            if let Some(sentence) = self.ready.pop_front() {
+                return Some(sentence);
+            }
+            let segments = self.segments.as_mut()?;
+            match segments.next().await {
+                Some(segment) => {
+                    if segment.probability_of_no_speech() >= self.no_speech_threshold {
+                        continue;
+                    }
+                    let end = segment.start() + segment.duration();
+                    self.ready
+                        .extend(self.buffer.push(segment.text(), segment.start(), end));
+                }
+                None => {
+                    self.segments = None;
+                    self.ready.extend(self.buffer.flush());
+                }
+            }
+        }
+    }
+}
+
+/// A sentence that was transcribed and translated by a [`RealtimeTranslator`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslatedSentence {
+    source: String,
+    translation: String,
+    start: f64,
+    end: f64,
+    latency: Duration,
+}
+
+impl TranslatedSentence {
+    /// Get the transcribed text in the original language.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Get the translated text.
+    pub fn translation(&self) -> &str {
+        &self.translation
+    }
+
+    /// Get the start time of the sentence in the audio stream in seconds.
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+
+    /// Get the end time of the sentence in the audio stream in seconds.
+    pub fn end(&self) -> f64 {
+        self.end
+    }
+
+    /// Get the time between the transcription of the end of the sentence and the translation being ready.
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+}
+
+/// A sentence that is ready to translate.
+#[derive(Debug)]
+struct PendingSentence {
+    text: String,
+    start: f64,
+    end: f64,
+    received: Instant,
+}
+
+/// Collects transcribed text until full sentences are available.
+struct SentenceBuffer {
+    text: String,
+    /// The byte offset in the text, start and end time of each buffered segment
+    spans: Vec<(usize, f64, f64)>,
+    max_latency: Duration,
+}
+
+impl SentenceBuffer {
+    fn new(max_latency: Duration) -> Self {
+        Self {
+            text: String::new(),
+            spans: Vec::new(),
+            max_latency,
+        }
+    }
+
+    /// Add a segment of text and return any sentences that are complete.
+    fn push(&mut self, text: &str, start: f64, end: f64) -> Vec<PendingSentence> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Vec::new();
+        }
+        if !self.text.is_empty() {
+            self.text.push(' ');
+        }
+        self.spans.push((self.text.len(), start, end));
+        self.text.push_str(text);
+
+        let mut sentences = Vec::new();
+        let ranges = SentenceChunker::default().split_sentences(&self.text);
+        let mut complete = ranges.len().saturating_sub(1);
+        // The last sentence is only complete if it ends with punctuation
+        if let Some(last) = ranges.last() {
+            if self.text[last.clone()]
+                .trim_end()
+                .ends_with(['.', '!', '?', '。', '！', '？'])
+            {
+                complete = ranges.len();
+            }
+        }
+        let mut consumed = 0;
+        for range in &ranges[..complete] {
+            sentences.extend(self.sentence(consumed..range.end));
+            consumed = range.end;
+        }
+        self.consume(consumed);
+
+        // Translate the partial sentence if the speaker has been talking for too long
+        if let Some(&(_, first_start, _)) = self.spans.first() {
+            if Duration::from_secs_f64((end - first_start).max(0.0)) >= self.max_latency {
+                sentences.extend(self.flush());
+            }
+        }
+        sentences
+    }
+
+    /// Return any remaining text as a sentence.
+    fn flush(&mut self) -> Option<PendingSentence> {
+        let sentence = self.sentence(0..self.text.len());
+        self.consume(self.text.len());
+        sentence
+    }
+
+    fn sentence(&self, range: std::ops::Range<usize>) -> Option<PendingSentence> {
+        let text = self.text[range.clone()].trim();
+        if text.is_empty() {
+            return None;
+        }
+        let overlapping = self
+            .spans
+            .iter()
+            .enumerate()
+            .filter(|(i, (offset, _, _))| {
+                let span_end = self
+                    .spans
+                    .get(i + 1)
+                    .map(|(next, _, _)| *next)
+                    .unwrap_or(self.text.len());
+                *offset < range.end && span_end > range.start
+            })
+            .map(|(_, span)| span);
+        let (start, end) = overlapping.fold((f64::MAX, f64::MIN), |(start, end), span| {
+            (start.min(span.1), end.max(span.2))
+        });
+        Some(PendingSentence {
+            text: text.to_string(),
+            start,
+            end,
+            received: Instant::now(),
+        })
+    }
+
+    /// Remove the first `len` bytes of text and any segments that are no longer needed.
+    fn consume(&mut self, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let len = len + (self.text[len..].len() - self.text[len..].trim_start().len());
+        // Drop segments that end before the cut
+        while self.spans.len() > 1 && self.spans[1].0 <= len {
+            self.spans.remove(0);
+        }
+        if len >= self.text.len() {
+            self.spans.clear();
+        }
+        self.text.drain(..len);
+        for (offset, _, _) in &mut self.spans {
+            *offset = offset.saturating_sub(len);
+        }
+    }
+}
+
+#[test]
+fn sentence_buffer_splits_sentences() {
+    let mut buffer = SentenceBuffer::new(Duration::from_secs(10));
+    assert!(buffer.push("Hello there", 0.0, 1.0).is_empty());
+    let sentences = buffer.push("friend. How are", 1.0, 2.0);
+    assert_eq!(sentences.len(), 1);
+    assert_eq!(sentences[0].text, "Hello there friend.");
+    assert_eq!((sentences[0].start, sentences[0].end), (0.0, 2.0));
+    let sentence = buffer.flush().unwrap();
+    assert_eq!(sentence.text, "How are");
+    assert_eq!((sentence.start, sentence.end), (1.0, 2.0));
+    assert!(buffer.flush().is_none());
+
+    let mut buffer = SentenceBuffer::new(Duration::from_secs(2));
+    assert!(buffer.push("this keeps going", 0.0, 1.5).is_empty());
+    let sentences = buffer.push("and going", 1.5, 2.5);
+    assert_eq!(sentences.len(), 1);
+    assert_eq!(sentences[0].text, "this keeps going and going");
+}
@@ -16,6 +16,20 @@ use surrealdb::Connection;
 use surrealdb::RecordIdKey;
 use surrealdb::Surreal;
 
+mod extract;
+pub use extract::*;
+mod refresh;
+pub use refresh::*;
+mod stats;
+pub use stats::*;
+mod backup;
+pub use backup::*;
+mod indexer;
+pub use indexer::*;
+mod snapshot;
+#[cfg(test)]
+mod test_utils;
+
 /// An error that can occur when adding items to a [`DocumentTable`].
 #[derive(Debug, thiserror::Error)]
 pub enum DocumentTableModifyError<E> {
@@ -90,6 +104,7 @@ pub struct DocumentTable<
     embedding_model: M,
     chunker: K,
     table: EmbeddingIndexedTable<C, R>,
+    refresh: RefreshRegistry,
 }
 
 impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
@@ -99,6 +114,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             embedding_model,
             table,
             chunker,
+            refresh: RefreshRegistry::default(),
         }
     }
 
@@ -259,6 +275,38 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .await
             .map_err(DocumentTableAddContextError::ModifyTable)
     }
+
+    /// Extend the table with the documents a URI points to. The URI is loaded with the source registered for its
+    /// scheme in the [`DocumentSourceRegistry::global`] registry.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), anyhow::Error> {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db.document_table_builder("documents").build::<Document>().await?;
+    ///
+    ///     document_table.add_source("https://floneum.com/kalosm/docs").await?;
+    ///     document_table.add_source("file:///home/me/notes").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn add_source(
+        &self,
+        uri: &str,
+    ) -> Result<
+        Vec<RecordIdKey>,
+        DocumentTableAddContextError<DocumentSourceError, K::Error<M::Error>>,
+    >
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned + 'static,
+        K: Sync,
+    {
+        self.add_context(SourceUri::new(uri)).await
+    }
 }
 
 /// A builder for searching for embeddings in a vector database.
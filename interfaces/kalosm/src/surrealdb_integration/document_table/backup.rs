@@ -0,0 +1,407 @@
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::RngCore;
+
+use super::DocumentTable;
+use crate::surrealdb_integration::EmbeddedIndexedTableError;
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use surrealdb::{Connection, RecordId, RecordIdKey};
+
+/// The bytes every backup archive starts with.
+const MAGIC: &[u8; 8] = b"KALOSMDB";
+/// The version of the archive format. Archives with any other version are rejected.
+const FORMAT_VERSION: u8 = 2;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// An error that can occur while backing up or restoring a [`DocumentTable`].
+#[derive(Debug, thiserror::Error)]
+pub enum DocumentTableBackupError {
+    /// An error reading or writing the archive.
+    #[error("Failed to read or write the backup: {0}")]
+    Io(#[from] std::io::Error),
+    /// An error reading or writing the table.
+    #[error("Failed to access the table: {0}")]
+    Table(Box<EmbeddedIndexedTableError>),
+    /// An error serializing or deserializing the contents of the table.
+    #[error("Failed to serialize the backup: {0}")]
+    Serialization(#[from] serde_json::Error),
+    /// The file is not a backup archive.
+    #[error("The file is not a document table backup")]
+    NotABackup,
+    /// The archive was written with a format version this version of kalosm can't read.
+    #[error("Unsupported backup format version {0}")]
+    UnsupportedVersion(u8),
+    /// The archive is encrypted but no passphrase was provided.
+    #[error("The backup is encrypted and requires a passphrase")]
+    PassphraseRequired,
+    /// The passphrase was wrong or the archive was modified.
+    #[error("Failed to decrypt the backup. The passphrase is wrong or the backup is corrupted")]
+    Decryption,
+    /// Deriving the encryption key from the passphrase failed.
+    #[error("Failed to derive the encryption key: {0}")]
+    KeyDerivation(String),
+}
+
+impl From<EmbeddedIndexedTableError> for DocumentTableBackupError {
+    fn from(value: EmbeddedIndexedTableError) -> Self {
+        Self::Table(Box::new(value))
+    }
+}
+
+/// Options for [`DocumentTable::backup_with_options`] and [`DocumentTable::restore_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct BackupOptions {
+    passphrase: Option<String>,
+}
+
+impl BackupOptions {
+    /// Create new backup options without encryption.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encrypt the backup with AES-256-GCM using a key derived from the passphrase with Argon2. The same passphrase
+    /// is required to restore the backup.
+    pub fn with_passphrase(mut self, passphrase: impl ToString) -> Self {
+        self.passphrase = Some(passphrase.to_string());
+        self
+    }
+}
+
+/// Information about a backup archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    /// The name of the table the backup was created from.
+    pub table: String,
+    /// The number of seconds since the unix epoch when the backup was created.
+    pub created_at: u64,
+    /// The number of documents in the backup.
+    pub documents: usize,
+    /// The number of dimensions of the embeddings in the backup.
+    pub embedding_dimensions: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupChunk {
+    byte_range: Range<usize>,
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupRecord<R> {
+    /// The id of the record in the table the backup was created from. Restoring the backup replaces the record
+    /// with the same id.
+    #[serde(default)]
+    id: Option<RecordIdKey>,
+    object: R,
+    chunks: Vec<BackupChunk>,
+}
+
+/// A record read from the table with its id. This matches the layout of
+/// [`ObjectWithEmbeddingIds`](crate::surrealdb_integration::ObjectWithEmbeddingIds).
+#[derive(Deserialize)]
+struct StoredRecord<R> {
+    id: RecordId,
+    object: R,
+    chunks: Vec<(Range<usize>, Vec<EmbeddingId>)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Backup<R> {
+    metadata: BackupMetadata,
+    records: Vec<BackupRecord<R>>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm, DocumentTableBackupError> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| DocumentTableBackupError::KeyDerivation(err.to_string()))?;
+    Ok(Aes256Gcm::new(&key.into()))
+}
+
+/// Compress and optionally encrypt the contents of a backup into an archive. The header of encrypted archives is
+/// authenticated along with the contents, so it can't be changed without failing decryption.
+fn seal(contents: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, DocumentTableBackupError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(contents)?;
+    let compressed = encoder.finish()?;
+
+    let mut archive = MAGIC.to_vec();
+    archive.push(FORMAT_VERSION);
+    match passphrase {
+        Some(passphrase) => {
+            let mut salt = [0u8; SALT_LEN];
+            let mut nonce = [0u8; NONCE_LEN];
+            rand::thread_rng().fill_bytes(&mut salt);
+            rand::thread_rng().fill_bytes(&mut nonce);
+            archive.push(1);
+            archive.extend_from_slice(&salt);
+            archive.extend_from_slice(&nonce);
+            let payload = Payload {
+                msg: &compressed,
+                aad: &archive,
+            };
+            let encrypted = derive_key(passphrase, &salt)?
+                .encrypt(Nonce::from_slice(&nonce), payload)
+                .map_err(|_| DocumentTableBackupError::Decryption)?;
+            archive.extend_from_slice(&encrypted);
+        }
+        None => {
+            archive.push(0);
+            archive.extend_from_slice(&compressed);
+        }
+    }
+    Ok(archive)
+}
+
+/// Decrypt and decompress an archive created with [`seal`].
+fn open(archive: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>, DocumentTableBackupError> {
+    let rest = archive
+        .strip_prefix(MAGIC.as_slice())
+        .ok_or(DocumentTableBackupError::NotABackup)?;
+    let [version, encrypted, rest @ ..] = rest else {
+        return Err(DocumentTableBackupError::NotABackup);
+    };
+    if *version != FORMAT_VERSION {
+        return Err(DocumentTableBackupError::UnsupportedVersion(*version));
+    }
+    let compressed = match encrypted {
+        0 => rest.to_vec(),
+        _ => {
+            let passphrase = passphrase.ok_or(DocumentTableBackupError::PassphraseRequired)?;
+            if rest.len() < SALT_LEN + NONCE_LEN {
+                return Err(DocumentTableBackupError::NotABackup);
+            }
+            let (salt, rest) = rest.split_at(SALT_LEN);
+            let (nonce, encrypted) = rest.split_at(NONCE_LEN);
+            let header_len = archive.len() - encrypted.len();
+            let payload = Payload {
+                msg: encrypted,
+                aad: &archive[..header_len],
+            };
+            derive_key(passphrase, salt)?
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .map_err(|_| DocumentTableBackupError::Decryption)?
+        }
+    };
+
+    let mut contents = Vec::new();
+    GzDecoder::new(compressed.as_slice()).read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Write every document in the table with its chunks and embeddings to a single compressed archive. The
+    /// archive can be loaded into another table with [`DocumentTable::restore`], so apps can export user data or
+    /// sync it between machines without re-embedding anything.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await.unwrap();
+    ///     db.use_ns("rag").use_db("rag").await.unwrap();
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     let options = BackupOptions::new().with_passphrase("correct horse battery staple");
+    ///     let metadata = document_table
+    ///         .backup_with_options("./documents.kalosm", options)
+    ///         .await
+    ///         .unwrap();
+    ///     println!("Exported {} documents", metadata.documents);
+    /// }
+    /// ```
+    pub async fn backup(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<BackupMetadata, DocumentTableBackupError>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        self.backup_with_options(path, BackupOptions::default())
+            .await
+    }
+
+    /// Write every document in the table to a single compressed archive with the given options.
+    pub async fn backup_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: BackupOptions,
+    ) -> Result<BackupMetadata, DocumentTableBackupError>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        let records: Vec<StoredRecord<R>> = self
+            .table
+            .db()
+            .select(self.table.table())
+            .await
+            .map_err(EmbeddedIndexedTableError::from)?;
+        let vector_db = self.table.vector_db();
+
+        let mut embedding_dimensions = 0;
+        let mut backup_records = Vec::with_capacity(records.len());
+        for record in records {
+            let mut chunks = Vec::with_capacity(record.chunks.len());
+            for (byte_range, ids) in record.chunks {
+                let mut embeddings = Vec::with_capacity(ids.len());
+                for id in ids {
+                    let embedding = vector_db
+                        .get_embedding(id)
+                        .map_err(EmbeddedIndexedTableError::from)?
+                        .vector()
+                        .to_vec();
+                    embedding_dimensions = embedding.len();
+                    embeddings.push(embedding);
+                }
+                chunks.push(BackupChunk {
+                    byte_range,
+                    embeddings,
+                });
+            }
+            backup_records.push(BackupRecord {
+                id: Some(record.id.key().clone()),
+                object: record.object,
+                chunks,
+            });
+        }
+
+        let metadata = BackupMetadata {
+            table: self.table.table().to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            documents: backup_records.len(),
+            embedding_dimensions,
+        };
+        let backup = Backup {
+            metadata: metadata.clone(),
+            records: backup_records,
+        };
+        let contents = serde_json::to_vec(&backup)?;
+        let archive = seal(&contents, options.passphrase.as_deref())?;
+        tokio::fs::write(path, archive).await?;
+
+        Ok(metadata)
+    }
+
+    /// Add every document from an archive created with [`DocumentTable::backup`] to the table. The stored
+    /// embeddings are reused, so the documents are not embedded again. Documents keep the id they had in the table
+    /// the backup was created from, and documents that are already in the table are replaced, so restoring the same
+    /// backup twice does not duplicate any documents. Returns the ids of the restored records.
+    pub async fn restore(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<Vec<RecordIdKey>, DocumentTableBackupError>
+    where
+        R: Serialize + DeserializeOwned + 'static,
+    {
+        self.restore_with_options(path, BackupOptions::default())
+            .await
+    }
+
+    /// Add every document from an archive to the table with the given options. The passphrase must match the
+    /// passphrase the archive was created with.
+    pub async fn restore_with_options(
+        &self,
+        path: impl AsRef<Path>,
+        options: BackupOptions,
+    ) -> Result<Vec<RecordIdKey>, DocumentTableBackupError>
+    where
+        R: Serialize + DeserializeOwned + 'static,
+    {
+        let archive = tokio::fs::read(path).await?;
+        let contents = open(&archive, options.passphrase.as_deref())?;
+        let backup: Backup<R> = serde_json::from_slice(&contents)?;
+
+        let mut ids = Vec::with_capacity(backup.records.len());
+        for record in backup.records {
+            let chunks = record.chunks.into_iter().map(|chunk| Chunk {
+                byte_range: chunk.byte_range,
+                embeddings: chunk.embeddings.into_iter().map(Embedding::from).collect(),
+            });
+            let id = match record.id {
+                Some(id) => {
+                    // Remove the old version of the record and its embeddings before inserting the backup
+                    self.table.delete(id.clone()).await?;
+                    self.table.insert_with_id(id, chunks, record.object).await?
+                }
+                None => self.table.insert(chunks, record.object).await?,
+            };
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+}
+
+#[test]
+fn backup_archive_round_trip() {
+    let contents = br#"{"metadata":{},"records":[]}"#;
+
+    let plain = seal(contents, None).unwrap();
+    assert_eq!(open(&plain, None).unwrap(), contents);
+
+    let encrypted = seal(contents, Some("passphrase")).unwrap();
+    assert_eq!(open(&encrypted, Some("passphrase")).unwrap(), contents);
+    assert!(matches!(
+        open(&encrypted, None),
+        Err(DocumentTableBackupError::PassphraseRequired)
+    ));
+    assert!(matches!(
+        open(&encrypted, Some("wrong")),
+        Err(DocumentTableBackupError::Decryption)
+    ));
+    assert!(matches!(
+        open(b"not a backup", None),
+        Err(DocumentTableBackupError::NotABackup)
+    ));
+
+    // The header is authenticated, so changing the salt or nonce fails to decrypt
+    let mut tampered = encrypted.clone();
+    tampered[MAGIC.len() + 2] ^= 1;
+    assert!(matches!(
+        open(&tampered, Some("passphrase")),
+        Err(DocumentTableBackupError::Decryption)
+    ));
+
+    // Archives with a different format version are rejected instead of being decrypted another way
+    for version in [1, FORMAT_VERSION + 1] {
+        let mut other_version = encrypted.clone();
+        other_version[MAGIC.len()] = version;
+        assert!(matches!(
+            open(&other_version, Some("passphrase")),
+            Err(DocumentTableBackupError::UnsupportedVersion(v)) if v == version
+        ));
+    }
+
+    // Records keep their id through the archive
+    let record = BackupRecord {
+        id: Some(RecordIdKey::from("document")),
+        object: "text".to_string(),
+        chunks: Vec::new(),
+    };
+    let json = serde_json::to_vec(&record).unwrap();
+    let record: BackupRecord<String> = serde_json::from_slice(&json).unwrap();
+    assert_eq!(record.id, Some(RecordIdKey::from("document")));
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{DocumentTable, DocumentTableSearchError};
+use crate::surrealdb_integration::EmbeddingIndexedTableSearchResult;
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use surrealdb::Connection;
+
+const EXTRACT_TASK: &str = "You extract structured information from documents. You are given numbered excerpts from documents and a request. Fill in the requested information using only facts found in the excerpts.";
+
+/// The number of chunks that are retrieved by [`DocumentTable::extract`].
+const EXTRACT_RESULTS: usize = 5;
+
+/// An error that can occur while extracting typed information from a [`DocumentTable`].
+#[derive(Debug, thiserror::Error)]
+pub enum DocumentTableExtractError<E, L> {
+    /// An error occurred while searching for chunks relevant to the query.
+    #[error("Failed to search the table: {0}")]
+    Search(#[from] DocumentTableSearchError<E>),
+    /// An error occurred while generating the value.
+    #[error("Failed to generate the value: {0}")]
+    Generate(L),
+}
+
+/// A value that was extracted from the chunks of a [`DocumentTable`] along with the chunks that were shown to the
+/// model.
+///
+/// The model does not report which chunk each field came from. [`Extracted::sources_containing`] and
+/// [`Extracted::field_matches`] are a best-effort match of the generated text against the chunks after generation:
+/// values the model reformatted (like dates or numbers) may not match any chunk, and short or common values may match
+/// chunks they were not taken from.
+#[derive(Debug, Clone)]
+pub struct Extracted<T, R> {
+    /// The extracted value.
+    pub value: T,
+    /// The chunks that were shown to the model while extracting the value.
+    pub sources: Vec<EmbeddingIndexedTableSearchResult<R>>,
+}
+
+impl<T, R: AsRef<Document> + DeserializeOwned> Extracted<T, R> {
+    /// Get the sources that contain the given text, ignoring case. This is a text match, not a citation from the
+    /// model.
+    pub fn sources_containing(
+        &self,
+        text: impl ToString,
+    ) -> Vec<&EmbeddingIndexedTableSearchResult<R>> {
+        let text = text.to_string().to_lowercase();
+        if text.is_empty() {
+            return Vec::new();
+        }
+        self.sources
+            .iter()
+            .filter(|source| source.text().to_lowercase().contains(&text))
+            .collect()
+    }
+
+    /// Get the sources that contain the value of each top level field of the extracted value with
+    /// [`Extracted::sources_containing`]. Fields that can't be found verbatim in any source map to an empty list.
+    pub fn field_matches(&self) -> HashMap<String, Vec<&EmbeddingIndexedTableSearchResult<R>>>
+    where
+        T: Serialize,
+    {
+        let mut matches = HashMap::new();
+        let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(&self.value) else {
+            return matches;
+        };
+        for (field, value) in fields {
+            let text = match value {
+                serde_json::Value::String(text) => text,
+                serde_json::Value::Null => String::new(),
+                other => other.to_string(),
+            };
+            matches.insert(field, self.sources_containing(text));
+        }
+        matches
+    }
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Extract a typed value from the table. The chunks most relevant to the query are retrieved and the model
+    /// is constrained to generate the type `T`. The chunks are returned with the value and can be matched against its
+    /// fields with [`Extracted::field_matches`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[derive(Parse, Schema, Clone, Debug, serde::Serialize)]
+    /// struct Invoice {
+    ///     number: String,
+    ///     date: String,
+    ///     total: f64,
+    /// }
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await.unwrap();
+    ///     db.use_ns("invoices").use_db("invoices").await.unwrap();
+    ///     let table = db
+    ///         .document_table_builder("invoices")
+    ///         .build::<Document>()
+    ///         .await
+    ///         .unwrap();
+    ///     table
+    ///         .add_context(DocumentFolder::try_from(std::path::PathBuf::from("./invoices")).unwrap())
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     let model = Llama::new_chat().await.unwrap();
+    ///     let invoice = table
+    ///         .extract::<Invoice, _>(&model, "the invoice number, date and total")
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{:?}", invoice.value);
+    ///     println!("{:?}", invoice.field_matches());
+    /// }
+    /// ```
+    pub async fn extract<T, L>(
+        &self,
+        model: &L,
+        query: impl ToString,
+    ) -> Result<Extracted<T, R>, DocumentTableExtractError<M::Error, L::Error>>
+    where
+        T: Send + 'static,
+        L: CreateDefaultChatConstraintsForType<T> + Clone + Send + Sync + Unpin + 'static,
+        L::ChatSession: Clone + Send + Sync + Unpin + 'static,
+        L::DefaultConstraints: Clone + Send + Sync + Unpin + 'static,
+        R: AsRef<Document> + DeserializeOwned + Send + Sync,
+    {
+        let query = query.to_string();
+        let sources = self
+            .search(query.clone())
+            .with_results(EXTRACT_RESULTS)
+            .run()
+            .await?;
+
+        let mut message = String::new();
+        for (i, source) in sources.iter().enumerate() {
+            let title = source.record.as_ref().title();
+            _ = writeln!(message, "[{}] {}\n{}\n", i + 1, title, source.text());
+        }
+        _ = write!(message, "Request: {query}");
+
+        let value = model
+            .task(EXTRACT_TASK)
+            .typed::<T>()
+            .run(message)
+            .await
+            .map_err(DocumentTableExtractError::Generate)?;
+
+        Ok(Extracted { value, sources })
+    }
+}
+
+#[test]
+fn field_matches_are_found_by_text() {
+    #[derive(Serialize)]
+    struct Invoice {
+        number: String,
+        total: f64,
+        note: Option<String>,
+    }
+
+    let source = |id: u32, body: &str| EmbeddingIndexedTableSearchResult {
+        distance: 0.0,
+        id: EmbeddingId(id),
+        record_id: surrealdb::RecordIdKey::from(id.to_string()),
+        byte_range: 0..body.len(),
+        record: Document::from_parts("Invoice", body),
+    };
+    let extracted = Extracted {
+        value: Invoice {
+            number: "inv-42".to_string(),
+            total: 99.5,
+            note: None,
+        },
+        sources: vec![
+            source(0, "Invoice INV-42 has a total of $99.5"),
+            source(1, "Invoice INV-7 was paid"),
+        ],
+    };
+
+    let matches = extracted.field_matches();
+    let ids = |field: &str| {
+        matches[field]
+            .iter()
+            .map(|source| source.id.0)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids("number"), [0]);
+    assert_eq!(ids("total"), [0]);
+    assert!(ids("note").is_empty());
+    // Common text matches every source that contains it
+    assert_eq!(extracted.sources_containing("invoice").len(), 2);
+    assert!(extracted.sources_containing("").is_empty());
+}
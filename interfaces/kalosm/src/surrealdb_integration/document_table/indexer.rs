@@ -0,0 +1,354 @@
+use std::collections::BTreeSet;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures_util::{FutureExt, Stream, StreamExt};
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use surrealdb::Connection;
+
+use super::DocumentTable;
+
+/// Options for [`DocumentTable::index`] and [`DocumentTable::index_with_progress`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// let options = IndexingOptions::new()
+///     .with_concurrency(4)
+///     .with_batch_size(32)
+///     .with_max_documents_per_second(50.0)
+///     .with_checkpoint("./index-checkpoint.json");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexingOptions {
+    concurrency: usize,
+    batch_size: usize,
+    max_documents_per_second: Option<f64>,
+    checkpoint: Option<PathBuf>,
+    checkpoint_every: usize,
+}
+
+impl Default for IndexingOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexingOptions {
+    /// Create the default options which embed two batches of 16 documents at a time without a rate limit or a
+    /// checkpoint.
+    pub fn new() -> Self {
+        Self {
+            concurrency: 2,
+            batch_size: 16,
+            max_documents_per_second: None,
+            checkpoint: None,
+            checkpoint_every: 256,
+        }
+    }
+
+    /// Set the number of batches that are embedded and inserted at the same time. (Defaults to 2)
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the number of documents that are chunked and embedded together. (Defaults to 16)
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Limit how many documents are started per second. This is useful for remote embedding models with a rate
+    /// limit. A rate that is zero, negative or NaN removes the limit. (Defaults to no limit)
+    pub fn with_max_documents_per_second(mut self, documents_per_second: f64) -> Self {
+        self.max_documents_per_second = Some(documents_per_second).filter(|rate| *rate > 0.);
+        self
+    }
+
+    /// Record which documents were indexed in a checkpoint file. If the file already exists, the documents it
+    /// records are skipped, so indexing the same source again resumes where the last run stopped. The source must
+    /// yield documents in the same order every time.
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Set how many documents are indexed between writes of the checkpoint file. (Defaults to 256)
+    pub fn with_checkpoint_every(mut self, documents: usize) -> Self {
+        self.checkpoint_every = documents.max(1);
+        self
+    }
+}
+
+/// The progress of [`DocumentTable::index_with_progress`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexingProgress {
+    /// The number of documents indexed in this run.
+    pub indexed: usize,
+    /// The number of documents skipped because the checkpoint recorded them as indexed.
+    pub skipped: usize,
+    /// The number of documents that failed to index in this run.
+    pub failed: usize,
+    /// The time since indexing started.
+    pub elapsed: Duration,
+}
+
+impl IndexingProgress {
+    /// Get the number of documents indexed per second in this run.
+    pub fn documents_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0. {
+            return 0.;
+        }
+        self.indexed as f64 / seconds
+    }
+}
+
+/// A summary of a run of [`DocumentTable::index`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexingSummary {
+    /// The number of documents indexed in this run.
+    pub indexed: usize,
+    /// The number of documents skipped because the checkpoint recorded them as indexed.
+    pub skipped: usize,
+    /// The position in the source of each document that failed to index. Failed documents are not recorded in the
+    /// checkpoint, so they are retried the next time the source is indexed.
+    pub failed: Vec<usize>,
+}
+
+/// An error that can occur while indexing documents into a [`DocumentTable`]. Errors from individual documents are
+/// recorded in [`IndexingSummary::failed`] instead.
+#[derive(Debug, thiserror::Error)]
+pub enum DocumentTableIndexError {
+    /// An error reading or writing the checkpoint file.
+    #[error("Failed to read or write the indexing checkpoint: {0}")]
+    Io(#[from] std::io::Error),
+    /// The checkpoint file is not a valid checkpoint.
+    #[error("Failed to parse the indexing checkpoint: {0}")]
+    Checkpoint(#[from] serde_json::Error),
+}
+
+/// The documents that have been indexed. Documents are identified by their position in the source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct IndexingCheckpoint {
+    /// Every document before this position was indexed.
+    indexed_before: usize,
+    /// Documents after `indexed_before` that were indexed.
+    indexed: BTreeSet<usize>,
+}
+
+impl IndexingCheckpoint {
+    fn load(path: &Path) -> Result<Self, DocumentTableIndexError> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), DocumentTableIndexError> {
+        // Write to a temporary file first so a crash while writing doesn't corrupt the checkpoint
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_vec(self)?)?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        index < self.indexed_before || self.indexed.contains(&index)
+    }
+
+    fn insert(&mut self, index: usize) {
+        self.indexed.insert(index);
+        while self.indexed.remove(&self.indexed_before) {
+            self.indexed_before += 1;
+        }
+    }
+}
+
+/// Spaces out the start of batches to stay under a rate limit.
+struct RateLimiter {
+    seconds_per_document: f64,
+    next_start: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(documents_per_second: f64) -> Self {
+        Self {
+            seconds_per_document: 1. / documents_per_second,
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self, documents: usize) {
+        let start = {
+            let mut next_start = self.next_start.lock().unwrap();
+            let start = (*next_start).max(Instant::now());
+            *next_start =
+                start + Duration::from_secs_f64(self.seconds_per_document * documents as f64);
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Index a large source of documents with bounded concurrency. Unlike [`DocumentTable::extend`], a document
+    /// that fails to index (or panics while chunking or embedding) is recorded in the summary instead of stopping
+    /// the whole run.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await?;
+    ///     db.use_ns("test").use_db("test").await?;
+    ///     let table = db
+    ///         .document_table_builder("documents")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     let documents = DocumentFolder::new("./documents")?.into_documents().await?;
+    ///     let summary = table
+    ///         .index(
+    ///             futures_util::stream::iter(documents),
+    ///             IndexingOptions::new().with_checkpoint("./documents-checkpoint.json"),
+    ///         )
+    ///         .await?;
+    ///     println!("Indexed {} documents", summary.indexed);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn index(
+        &self,
+        documents: impl Stream<Item = R>,
+        options: IndexingOptions,
+    ) -> Result<IndexingSummary, DocumentTableIndexError>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned + Send + 'static,
+        K: Sync,
+        K::Error<M::Error>: std::fmt::Display,
+    {
+        self.index_with_progress(documents, options, |_| {}).await
+    }
+
+    /// Index a large source of documents like [`DocumentTable::index`] and call the handler with the progress after
+    /// every batch.
+    pub async fn index_with_progress(
+        &self,
+        documents: impl Stream<Item = R>,
+        options: IndexingOptions,
+        mut handler: impl FnMut(IndexingProgress),
+    ) -> Result<IndexingSummary, DocumentTableIndexError>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned + Send + 'static,
+        K: Sync,
+        K::Error<M::Error>: std::fmt::Display,
+    {
+        let start = Instant::now();
+        let resumed = match &options.checkpoint {
+            Some(path) => IndexingCheckpoint::load(path)?,
+            None => IndexingCheckpoint::default(),
+        };
+        let mut checkpoint = resumed.clone();
+        let rate_limiter = options.max_documents_per_second.map(RateLimiter::new);
+        let rate_limiter = rate_limiter.as_ref();
+
+        let mut summary = IndexingSummary::default();
+        let skipped = std::cell::Cell::new(0);
+        let batches = documents
+            .enumerate()
+            .filter(|(index, _)| {
+                let done = resumed.contains(*index);
+                if done {
+                    skipped.set(skipped.get() + 1);
+                }
+                std::future::ready(!done)
+            })
+            .chunks(options.batch_size)
+            .map(|batch| async move {
+                if let Some(rate_limiter) = rate_limiter {
+                    rate_limiter.wait(batch.len()).await;
+                }
+                let (indices, values): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+                let result = AssertUnwindSafe(self.extend(values)).catch_unwind().await;
+                match &result {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => tracing::error!("Failed to index documents: {err}"),
+                    Err(_) => tracing::error!("Indexing documents panicked"),
+                }
+                (indices, matches!(result, Ok(Ok(_))))
+            })
+            .buffer_unordered(options.concurrency);
+        let mut batches = std::pin::pin!(batches);
+
+        let mut since_checkpoint = 0;
+        while let Some((indices, indexed)) = batches.next().await {
+            if indexed {
+                summary.indexed += indices.len();
+                since_checkpoint += indices.len();
+                for index in indices {
+                    checkpoint.insert(index);
+                }
+            } else {
+                summary.failed.extend(indices);
+            }
+            if let Some(path) = &options.checkpoint {
+                if since_checkpoint >= options.checkpoint_every {
+                    checkpoint.save(path)?;
+                    since_checkpoint = 0;
+                }
+            }
+            handler(IndexingProgress {
+                indexed: summary.indexed,
+                skipped: skipped.get(),
+                failed: summary.failed.len(),
+                elapsed: start.elapsed(),
+            });
+        }
+
+        if let Some(path) = &options.checkpoint {
+            checkpoint.save(path)?;
+        }
+        summary.failed.sort_unstable();
+        summary.skipped = skipped.get();
+
+        Ok(summary)
+    }
+}
+
+#[test]
+fn checkpoint_tracks_indexed_documents() {
+    let mut checkpoint = IndexingCheckpoint::default();
+    checkpoint.insert(1);
+    checkpoint.insert(3);
+    assert_eq!(checkpoint.indexed_before, 0);
+    checkpoint.insert(0);
+    assert_eq!(checkpoint.indexed_before, 2);
+    assert!(checkpoint.contains(1));
+    assert!(!checkpoint.contains(2));
+    assert!(checkpoint.contains(3));
+    checkpoint.insert(2);
+    assert_eq!(checkpoint.indexed_before, 4);
+    assert!(checkpoint.indexed.is_empty());
+}
+
+#[test]
+fn rates_that_are_not_positive_remove_the_limit() {
+    for rate in [0., -1., f64::NAN] {
+        let options = IndexingOptions::new().with_max_documents_per_second(rate);
+        assert_eq!(options.max_documents_per_second, None);
+    }
+    let options = IndexingOptions::new().with_max_documents_per_second(f64::INFINITY);
+    let limiter = RateLimiter::new(options.max_documents_per_second.unwrap());
+    assert_eq!(limiter.seconds_per_document, 0.);
+}
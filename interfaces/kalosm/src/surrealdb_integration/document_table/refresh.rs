@@ -0,0 +1,622 @@
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{
+    DocumentTable, DocumentTableAddContextError, DocumentTableModifyError, SourceFreshness,
+};
+use crate::surrealdb_integration::EmbeddedIndexedTableError;
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use surrealdb::{Connection, RecordId, RecordIdKey};
+
+/// A policy that controls how often documents from a source are re-fetched and how long they stay in a [`DocumentTable`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// // Re-fetch the source every hour
+/// let rss = RefreshPolicy::refresh_every(Duration::from_secs(60 * 60));
+/// // Drop documents after 90 days
+/// let memories = RefreshPolicy::expire_after(Duration::from_secs(60 * 60 * 24 * 90));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefreshPolicy {
+    refresh_every: Option<Duration>,
+    expire_after: Option<Duration>,
+}
+
+impl RefreshPolicy {
+    /// Create a new policy that never refreshes or expires documents.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new policy that re-fetches the source after the given interval.
+    pub fn refresh_every(interval: Duration) -> Self {
+        Self::new().with_refresh_every(interval)
+    }
+
+    /// Create a new policy that removes documents after the given time to live.
+    pub fn expire_after(ttl: Duration) -> Self {
+        Self::new().with_expire_after(ttl)
+    }
+
+    /// Set the interval the source is re-fetched at.
+    pub fn with_refresh_every(mut self, interval: Duration) -> Self {
+        self.refresh_every = Some(interval);
+        self
+    }
+
+    /// Set the time to live of documents from the source.
+    pub fn with_expire_after(mut self, ttl: Duration) -> Self {
+        self.expire_after = Some(ttl);
+        self
+    }
+
+    /// Get the interval the source is re-fetched at.
+    pub fn refresh_interval(&self) -> Option<Duration> {
+        self.refresh_every
+    }
+
+    /// Get the time to live of documents from the source.
+    pub fn time_to_live(&self) -> Option<Duration> {
+        self.expire_after
+    }
+}
+
+/// A summary of a single refresh pass over a [`DocumentTable`].
+#[derive(Debug)]
+pub struct RefreshSummary<M> {
+    /// The number of documents that were removed because they expired.
+    pub expired: usize,
+    /// The number of sources that were re-fetched.
+    pub refreshed_sources: usize,
+    /// The documents and sources that failed to refresh. A failure does not stop the rest of the pass.
+    pub failures: Vec<RefreshFailure<M>>,
+}
+
+impl<M> Default for RefreshSummary<M> {
+    fn default() -> Self {
+        Self {
+            expired: 0,
+            refreshed_sources: 0,
+            failures: Vec::new(),
+        }
+    }
+}
+
+/// A document or source that failed to refresh in a [`DocumentTable::refresh`] pass.
+#[derive(Debug)]
+pub enum RefreshFailure<M> {
+    /// An expired document could not be removed. The document is removed on the next pass instead.
+    ExpireDocument {
+        /// The id of the expired document.
+        document: RecordIdKey,
+        /// The error that occurred while removing the document.
+        error: DocumentTableRefreshError<M>,
+    },
+    /// A source could not be re-fetched. The old documents from the source stay in the table and the source is retried
+    /// on the next pass.
+    RefreshSource {
+        /// The name of the source.
+        source: String,
+        /// The error that occurred while re-fetching the source.
+        error: DocumentTableRefreshError<M>,
+    },
+}
+
+/// An error that can occur while refreshing a [`DocumentTable`].
+#[derive(Debug, thiserror::Error)]
+pub enum DocumentTableRefreshError<M> {
+    /// An error occurred while re-fetching a source.
+    #[error("Failed to fetch source: {0}")]
+    FetchSource(Box<dyn Error + Send + Sync>),
+    /// An error occurred while modifying the table.
+    #[error("Failed to modify table: {0}")]
+    ModifyTable(#[from] DocumentTableModifyError<M>),
+}
+
+impl<M> From<EmbeddedIndexedTableError> for DocumentTableRefreshError<M> {
+    fn from(value: EmbeddedIndexedTableError) -> Self {
+        Self::ModifyTable(DocumentTableModifyError::AddItem(value))
+    }
+}
+
+/// The time a document expires at. This type is stored in the `{table}-expiry` table.
+#[derive(Serialize, Deserialize)]
+struct DocumentExpiry {
+    document_id: RecordIdKey,
+    expires_at: u64,
+}
+
+/// The documents a source was last fetched as. This type is stored in the `{table}-sources` table with the name
+/// of the source as the key, so registering a source again after the table is reopened updates the documents from
+/// the source instead of adding them a second time.
+#[derive(Serialize, Deserialize)]
+struct SourceRecord {
+    documents: Vec<RecordIdKey>,
+    hash: String,
+    fetched_at: u64,
+}
+
+type FetchDocuments = Arc<
+    dyn Fn() -> Pin<
+            Box<dyn Future<Output = Result<Vec<Document>, Box<dyn Error + Send + Sync>>> + Send>,
+        > + Send
+        + Sync,
+>;
+
+struct TrackedSource {
+    name: String,
+    fetch: FetchDocuments,
+    policy: RefreshPolicy,
+    last_fetched: Instant,
+    documents: Vec<RecordIdKey>,
+}
+
+/// The sources registered with a refresh interval. The documents from each source are stored in the table, but
+/// the sources themselves cannot be serialized, so they need to be registered again when the table is reopened.
+#[derive(Default)]
+pub(crate) struct RefreshRegistry {
+    sources: Mutex<Vec<TrackedSource>>,
+}
+
+impl RefreshRegistry {
+    /// Get the freshness of every registered source.
+    pub(crate) fn freshness(&self) -> Vec<SourceFreshness> {
+        let sources = self.sources.lock().unwrap();
+        sources
+            .iter()
+            .map(|source| {
+                let elapsed = source.last_fetched.elapsed();
+                let interval = source.policy.refresh_interval();
+                SourceFreshness {
+                    source: source.name.clone(),
+                    documents: source.documents.len(),
+                    seconds_since_fetch: elapsed.as_secs(),
+                    refresh_interval_seconds: interval.map(|interval| interval.as_secs()),
+                    stale: interval.is_some_and(|interval| elapsed > interval),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Hash the documents fetched from a source so unchanged sources are not embedded again.
+fn hash_documents(documents: &[Document]) -> String {
+    let mut hasher = Sha256::new();
+    for document in documents {
+        for part in [document.title(), document.body()] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part.as_bytes());
+        }
+        let metadata = serde_json::to_vec(document.all_metadata()).unwrap_or_default();
+        hasher.update((metadata.len() as u64).to_le_bytes());
+        hasher.update(metadata);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    pub(super) fn table_expiry(&self) -> String {
+        format!("{}-expiry", self.table.table())
+    }
+
+    pub(super) fn table_sources(&self) -> String {
+        format!("{}-sources", self.table.table())
+    }
+
+    async fn set_expiry(
+        &self,
+        ids: &[RecordIdKey],
+        ttl: Duration,
+    ) -> Result<(), EmbeddedIndexedTableError> {
+        let expires_at = unix_time().saturating_add(ttl.as_secs());
+        for id in ids {
+            let thing = RecordId::from_table_key(self.table_expiry(), id.clone());
+            self.table
+                .db()
+                .upsert::<Option<DocumentExpiry>>(thing)
+                .content(DocumentExpiry {
+                    document_id: id.clone(),
+                    expires_at,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_with_expiry(
+        &self,
+        id: RecordIdKey,
+    ) -> Result<Option<R>, EmbeddedIndexedTableError>
+    where
+        R: Serialize + DeserializeOwned + 'static,
+    {
+        let thing = RecordId::from_table_key(self.table_expiry(), id.clone());
+        self.table
+            .db()
+            .delete::<Option<DocumentExpiry>>(thing)
+            .await?;
+        self.table.delete(id).await
+    }
+
+    /// Insert a new record into the table that will be removed after the given time to live.
+    ///
+    /// Expired records are removed the next time [`DocumentTable::refresh`] runs.
+    pub async fn insert_with_expiry(
+        &self,
+        value: R,
+        ttl: Duration,
+    ) -> Result<RecordIdKey, DocumentTableModifyError<K::Error<M::Error>>>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned + 'static,
+    {
+        let id = self.insert(value).await?;
+        self.set_expiry(std::slice::from_ref(&id), ttl).await?;
+        Ok(id)
+    }
+
+    /// Replace the documents stored for a source with newly fetched documents. If the documents did not change since
+    /// the source was last fetched, the documents already in the table are kept instead of being embedded again.
+    ///
+    /// The new documents are inserted before the old documents are removed, so a failed insert leaves the old
+    /// documents searchable.
+    async fn upsert_source(
+        &self,
+        name: &str,
+        documents: Vec<Document>,
+        policy: RefreshPolicy,
+    ) -> Result<Vec<RecordIdKey>, DocumentTableModifyError<K::Error<M::Error>>>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned + 'static,
+        K: Sync,
+    {
+        let thing = RecordId::from_table_key(self.table_sources(), name);
+        let previous: Option<SourceRecord> = self
+            .table
+            .db()
+            .select(thing.clone())
+            .await
+            .map_err(EmbeddedIndexedTableError::from)?;
+        let hash = hash_documents(&documents);
+
+        let mut unchanged = None;
+        if let Some(previous) = previous.as_ref().filter(|previous| previous.hash == hash) {
+            // The documents may have expired since the source was fetched
+            let mut all_present = true;
+            for id in &previous.documents {
+                match self.table.select(id.clone()).await {
+                    Ok(_) => {}
+                    Err(EmbeddedIndexedTableError::RecordNotFound) => {
+                        all_present = false;
+                        break;
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            if all_present {
+                unchanged = Some(previous.documents.clone());
+            }
+        }
+
+        let ids = match unchanged {
+            Some(ids) => ids,
+            None => self.extend(documents.into_iter().map(R::from)).await?,
+        };
+        if let Some(ttl) = policy.time_to_live() {
+            self.set_expiry(&ids, ttl).await?;
+        }
+        self.table
+            .db()
+            .upsert::<Option<SourceRecord>>(thing)
+            .content(SourceRecord {
+                documents: ids.clone(),
+                hash,
+                fetched_at: unix_time(),
+            })
+            .await
+            .map_err(EmbeddedIndexedTableError::from)?;
+        if let Some(previous) = previous {
+            for id in previous.documents {
+                if !ids.contains(&id) {
+                    self.delete_with_expiry(id).await?;
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Extend the table from [`IntoDocuments`] and keep the documents up to date with the given [`RefreshPolicy`].
+    ///
+    /// The documents are stored under the name of the source. Adding a source with the same name again replaces
+    /// the documents from the earlier fetch, even if the table was reopened in between, so sources can be
+    /// registered again every time the table is opened without adding duplicate documents.
+    ///
+    /// The source is re-fetched by [`DocumentTable::refresh`] or the task started with [`DocumentTable::spawn_refresh_task`].
+    pub async fn add_context_with_policy<D>(
+        &self,
+        name: impl Into<String>,
+        context: D,
+        policy: RefreshPolicy,
+    ) -> Result<Vec<RecordIdKey>, DocumentTableAddContextError<D::Error, K::Error<M::Error>>>
+    where
+        D: IntoDocuments + Clone + Send + Sync + 'static,
+        D::Error: Error,
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned + 'static,
+        K: Sync,
+    {
+        let name = name.into();
+        let documents = context
+            .clone()
+            .into_documents()
+            .await
+            .map_err(DocumentTableAddContextError::ConvertItem)?;
+        let ids = self
+            .upsert_source(&name, documents, policy)
+            .await
+            .map_err(DocumentTableAddContextError::ModifyTable)?;
+
+        let mut sources = self.refresh.sources.lock().unwrap();
+        sources.retain(|source| source.name != name);
+        if policy.refresh_interval().is_some() {
+            let fetch: FetchDocuments = Arc::new(move || {
+                let context = context.clone();
+                Box::pin(async move {
+                    context
+                        .into_documents()
+                        .await
+                        .map_err(|err| Box::new(err) as Box<dyn Error + Send + Sync>)
+                })
+            });
+            sources.push(TrackedSource {
+                name,
+                fetch,
+                policy,
+                last_fetched: Instant::now(),
+                documents: ids.clone(),
+            });
+        }
+        Ok(ids)
+    }
+
+    /// Run a single refresh pass over the table. This removes any expired records and re-fetches any sources
+    /// whose [`RefreshPolicy`] interval has elapsed.
+    ///
+    /// The documents from a re-fetched source are inserted before the old documents are removed. If fetching or
+    /// inserting fails, the old documents stay in the table and the source is retried on the next pass. Failures are
+    /// logged and returned in [`RefreshSummary::failures`] without stopping the rest of the pass.
+    pub async fn refresh(
+        &self,
+    ) -> Result<RefreshSummary<K::Error<M::Error>>, DocumentTableRefreshError<K::Error<M::Error>>>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned + 'static,
+        K: Sync,
+        K::Error<M::Error>: std::fmt::Display,
+    {
+        let mut summary = RefreshSummary::default();
+
+        // First remove any records that have expired
+        let now = unix_time();
+        let expiry: Vec<DocumentExpiry> = self
+            .table
+            .db()
+            .select(self.table_expiry())
+            .await
+            .map_err(EmbeddedIndexedTableError::from)?;
+        for entry in expiry {
+            if entry.expires_at <= now {
+                match self.delete_with_expiry(entry.document_id.clone()).await {
+                    Ok(_) => summary.expired += 1,
+                    Err(err) => {
+                        let error = DocumentTableRefreshError::from(err);
+                        tracing::error!(
+                            "Failed to remove expired document {:?}: {error}",
+                            entry.document_id
+                        );
+                        summary.failures.push(RefreshFailure::ExpireDocument {
+                            document: entry.document_id,
+                            error,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Then re-fetch any sources that are due
+        let due = {
+            let sources = self.refresh.sources.lock().unwrap();
+            sources
+                .iter()
+                .filter(|source| {
+                    source
+                        .policy
+                        .refresh_interval()
+                        .is_some_and(|interval| source.last_fetched.elapsed() >= interval)
+                })
+                .map(|source| (source.name.clone(), source.fetch.clone(), source.policy))
+                .collect::<Vec<_>>()
+        };
+        for (name, fetch, policy) in due {
+            let ids = match fetch().await {
+                Ok(documents) => self
+                    .upsert_source(&name, documents, policy)
+                    .await
+                    .map_err(DocumentTableRefreshError::from),
+                Err(err) => Err(DocumentTableRefreshError::FetchSource(err)),
+            };
+            let ids = match ids {
+                Ok(ids) => ids,
+                Err(error) => {
+                    tracing::error!("Failed to refresh source {name}: {error}");
+                    summary.failures.push(RefreshFailure::RefreshSource {
+                        source: name,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            let mut sources = self.refresh.sources.lock().unwrap();
+            if let Some(source) = sources.iter_mut().find(|source| source.name == name) {
+                source.last_fetched = Instant::now();
+                source.documents = ids;
+            }
+            summary.refreshed_sources += 1;
+        }
+
+        Ok(summary)
+    }
+
+    /// Spawn a background task that calls [`DocumentTable::refresh`] every `check_every`. The task stops when the
+    /// returned handle is aborted.
+    pub fn spawn_refresh_task(
+        self: &Arc<Self>,
+        check_every: Duration,
+    ) -> tokio::task::JoinHandle<()>
+    where
+        Self: Send + Sync + 'static,
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned + Send + Sync + 'static,
+        K: Sync,
+        K::Error<M::Error>: std::fmt::Display + Send,
+    {
+        let table = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(check_every);
+            loop {
+                interval.tick().await;
+                if let Err(err) = table.refresh().await {
+                    tracing::error!("Failed to refresh document table: {err}");
+                }
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn registering_a_source_again_does_not_duplicate_documents() {
+    let db = super::test_utils::TestDatabase::new("refresh").await;
+    let open = || db.document_table::<Document>();
+    let source = vec![
+        Document::from_parts("First", "The first document."),
+        Document::from_parts("Second", "The second document."),
+    ];
+    let policy = RefreshPolicy::refresh_every(Duration::from_secs(60 * 60));
+
+    let table = open().await;
+    let first_ids = table
+        .add_context_with_policy("notes", source.clone(), policy)
+        .await
+        .unwrap();
+    drop(table);
+
+    // Reopening the table forgets the registered sources, but the documents from the source are still stored
+    let table = open().await;
+    let ids = table
+        .add_context_with_policy("notes", source.clone(), policy)
+        .await
+        .unwrap();
+    assert_eq!(ids, first_ids);
+    assert_eq!(table.select_all().await.unwrap().len(), 2);
+
+    // Changed documents replace the old documents from the source
+    let changed = vec![Document::from_parts("First", "The first document, edited.")];
+    table
+        .add_context_with_policy("notes", changed, policy)
+        .await
+        .unwrap();
+    let documents = table.select_all().await.unwrap();
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].body(), "The first document, edited.");
+    assert_eq!(table.refresh.freshness().len(), 1);
+
+    drop(table);
+    db.remove();
+}
+
+#[tokio::test]
+async fn a_failing_source_does_not_stop_the_refresh() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// A source that returns different documents every fetch, or fails once `fail` is set.
+    #[derive(Clone)]
+    struct FlakySource {
+        fetches: Arc<Mutex<usize>>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl IntoDocuments for FlakySource {
+        type Error = std::io::Error;
+
+        async fn into_documents(self) -> Result<Vec<Document>, Self::Error> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(std::io::Error::other("the source is offline"));
+            }
+            let mut fetches = self.fetches.lock().unwrap();
+            *fetches += 1;
+            Ok(vec![Document::from_parts(
+                "Fetch",
+                format!("Fetch number {fetches}."),
+            )])
+        }
+    }
+
+    let db = super::test_utils::TestDatabase::new("refresh-failure").await;
+    let table = db.document_table::<Document>().await;
+
+    let policy = RefreshPolicy::refresh_every(Duration::ZERO);
+    let failing = FlakySource {
+        fetches: Default::default(),
+        fail: Default::default(),
+    };
+    let working = FlakySource {
+        fetches: Default::default(),
+        fail: Default::default(),
+    };
+    table
+        .add_context_with_policy("failing", failing.clone(), policy)
+        .await
+        .unwrap();
+    table
+        .add_context_with_policy("working", working.clone(), policy)
+        .await
+        .unwrap();
+
+    failing.fail.store(true, Ordering::SeqCst);
+    let summary = table.refresh().await.unwrap();
+    assert_eq!(summary.refreshed_sources, 1);
+    assert_eq!(summary.failures.len(), 1);
+    assert!(matches!(
+        &summary.failures[0],
+        RefreshFailure::RefreshSource { source, error: DocumentTableRefreshError::FetchSource(_) }
+            if source == "failing"
+    ));
+
+    // The working source was re-fetched and the failing source kept its old documents
+    let mut bodies: Vec<_> = table
+        .select_all()
+        .await
+        .unwrap()
+        .iter()
+        .map(|document| document.body().to_string())
+        .collect();
+    bodies.sort();
+    assert_eq!(bodies, ["Fetch number 1.", "Fetch number 2."]);
+    assert_eq!(*working.fetches.lock().unwrap(), 2);
+
+    drop(table);
+    db.remove();
+}
@@ -0,0 +1,65 @@
+use super::DocumentTable;
+use crate::surrealdb_integration::EmbeddedIndexedTableError;
+use kalosm_language::prelude::*;
+use surrealdb::Connection;
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// The tables the document table keeps next to the records that need to be rolled back with them.
+    fn companion_tables(&self) -> Vec<String> {
+        vec![self.table_expiry(), self.table_sources()]
+    }
+
+    /// Create a snapshot of every document, embedding, expiry time and refreshed source in the table. The table can be rolled back
+    /// to the snapshot later with [`DocumentTable::restore_snapshot`], so a bad ingestion batch or an experimental
+    /// re-indexing run can be undone without embedding the documents again.
+    ///
+    /// The embeddings are shared with the live table until they are removed, so snapshots are cheap to create.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), anyhow::Error> {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     document_table.create_snapshot("before-import").await?;
+    ///     document_table.add_source("file:///home/me/notes").await?;
+    ///     // The import added documents we don't want. Roll it back
+    ///     document_table.restore_snapshot("before-import").await?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn create_snapshot(
+        &self,
+        name: impl ToString,
+    ) -> Result<VectorDBSnapshot, EmbeddedIndexedTableError> {
+        self.table
+            .create_snapshot_with_tables(name, self.companion_tables())
+            .await
+    }
+
+    /// List the snapshots of the table from oldest to newest.
+    pub fn snapshots(&self) -> Result<Vec<VectorDBSnapshot>, VectorDbError> {
+        self.table.snapshots()
+    }
+
+    /// Roll the table back to a snapshot. The snapshot is kept, so the table can be restored to it again later.
+    pub async fn restore_snapshot(&self, name: &str) -> Result<(), EmbeddedIndexedTableError> {
+        self.table
+            .restore_snapshot_with_tables(name, self.companion_tables())
+            .await
+    }
+
+    /// Delete a snapshot. This does not change the documents currently in the table.
+    pub async fn delete_snapshot(&self, name: &str) -> Result<(), EmbeddedIndexedTableError> {
+        self.table.delete_snapshot(name).await
+    }
+}
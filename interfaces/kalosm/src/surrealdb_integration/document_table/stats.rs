@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+
+use super::DocumentTable;
+use crate::surrealdb_integration::{EmbeddedIndexedTableError, ObjectWithEmbeddingIds};
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use surrealdb::Connection;
+
+/// The cosine similarity above which two chunks are considered duplicates.
+const DUPLICATE_SIMILARITY: f32 = 0.98;
+
+/// Statistics about the contents of a [`DocumentTable`] returned by [`DocumentTable::stats`]. The statistics can be
+/// exported as JSON with [`CorpusStats::to_json`] to monitor the health of an index over time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CorpusStats {
+    /// The number of documents in the table.
+    pub documents: usize,
+    /// The number of chunks in the table.
+    pub chunks: usize,
+    /// The number of embeddings in the vector database.
+    pub embeddings: usize,
+    /// The distribution of the length of chunks in tokens.
+    pub chunk_tokens: TokenDistribution,
+    /// Statistics about the embeddings of the chunks.
+    pub embedding_space: EmbeddingSpaceStats,
+    /// The freshness of each source added with [`DocumentTable::add_context_with_policy`].
+    pub sources: Vec<SourceFreshness>,
+}
+
+impl CorpusStats {
+    /// Serialize the statistics as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("corpus stats are always valid json")
+    }
+
+    /// Get the cosine distance between the centroid of these statistics and the centroid of earlier statistics
+    /// for the same table. A large value means the contents of the table have shifted since the earlier snapshot.
+    pub fn centroid_drift_from(&self, previous: &CorpusStats) -> f32 {
+        cosine_distance(
+            &self.embedding_space.centroid,
+            &previous.embedding_space.centroid,
+        )
+    }
+}
+
+/// The distribution of chunk lengths. Tokens are approximated by whitespace separated words so the statistics do
+/// not depend on the tokenizer of the embedding model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TokenDistribution {
+    /// The total number of tokens in all chunks.
+    pub total: usize,
+    /// The length of the shortest chunk.
+    pub min: usize,
+    /// The length of the longest chunk.
+    pub max: usize,
+    /// The mean chunk length.
+    pub mean: f64,
+    /// The median chunk length.
+    pub median: usize,
+    /// The 90th percentile chunk length.
+    pub p90: usize,
+}
+
+impl TokenDistribution {
+    fn from_lengths(mut lengths: Vec<usize>) -> Self {
+        if lengths.is_empty() {
+            return Self::default();
+        }
+        lengths.sort_unstable();
+        let percentile = |p: f64| lengths[((lengths.len() - 1) as f64 * p).round() as usize];
+        let total = lengths.iter().sum();
+        Self {
+            total,
+            min: lengths[0],
+            max: lengths[lengths.len() - 1],
+            mean: total as f64 / lengths.len() as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+        }
+    }
+}
+
+/// Statistics about the embeddings in a [`DocumentTable`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct EmbeddingSpaceStats {
+    /// The number of dimensions of the embeddings.
+    pub dimensions: usize,
+    /// The mean of all embeddings.
+    pub centroid: Vec<f32>,
+    /// The mean cosine similarity between each embedding and the centroid. Values close to 1 mean the corpus is
+    /// very uniform.
+    pub mean_similarity_to_centroid: f32,
+    /// The cosine distance between the centroid of the older half of the documents and the centroid of the newer
+    /// half. A large value means newly added documents are about different topics than older documents.
+    pub centroid_drift: f32,
+    /// The fraction of embeddings that have a near duplicate in the table.
+    pub duplicate_ratio: f32,
+}
+
+/// The freshness of a source that is kept up to date with a [`RefreshPolicy`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceFreshness {
+    /// The name the source was added with.
+    pub source: String,
+    /// The number of documents from the source in the table.
+    pub documents: usize,
+    /// The number of seconds since the source was last fetched.
+    pub seconds_since_fetch: u64,
+    /// The refresh interval of the source in seconds.
+    pub refresh_interval_seconds: Option<u64>,
+    /// If the source is overdue for a refresh.
+    pub stale: bool,
+}
+
+fn cosine_distance(first: &[f32], second: &[f32]) -> f32 {
+    if first.is_empty() || second.is_empty() {
+        return 0.0;
+    }
+    let first = Embedding::from(first.iter().copied());
+    let second = Embedding::from(second.iter().copied());
+    let similarity = first.cosine_similarity(&second);
+    if similarity.is_nan() {
+        0.0
+    } else {
+        1.0 - similarity
+    }
+}
+
+fn mean(sum: &[f32], count: usize) -> Vec<f32> {
+    sum.iter()
+        .map(|value| value / count.max(1) as f32)
+        .collect()
+}
+
+fn add_to(sum: &mut Vec<f32>, vector: &[f32]) {
+    if sum.is_empty() {
+        sum.resize(vector.len(), 0.0);
+    }
+    for (total, value) in sum.iter_mut().zip(vector) {
+        *total += value;
+    }
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Collect statistics about the chunks, embeddings and sources in the table.
+    ///
+    /// This reads every embedding in the table, so it can take a while for large tables.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::SurrealKv, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let db = Surreal::new::<SurrealKv>("./db/temp.db").await.unwrap();
+    ///     db.use_ns("rag").use_db("rag").await.unwrap();
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await
+    ///         .unwrap();
+    ///
+    ///     let stats = document_table.stats().await.unwrap();
+    ///     if stats.embedding_space.duplicate_ratio > 0.1 {
+    ///         println!("More than 10% of the chunks are duplicates");
+    ///     }
+    ///     println!("{}", stats.to_json());
+    /// }
+    /// ```
+    pub async fn stats(&self) -> Result<CorpusStats, EmbeddedIndexedTableError>
+    where
+        R: AsRef<Document> + DeserializeOwned,
+    {
+        // Record ids are time ordered UUIDs, so records are returned in the order they were inserted
+        let records: Vec<ObjectWithEmbeddingIds<R>> =
+            self.table.db().select(self.table.table()).await?;
+        let vector_db = self.table.vector_db();
+
+        let mut lengths = Vec::new();
+        let mut embedding_ids = Vec::new();
+        for record in &records {
+            let body = record.object.as_ref().body();
+            for (byte_range, ids) in &record.chunks {
+                let text = body.get(byte_range.clone()).unwrap_or_default();
+                lengths.push(text.split_whitespace().count());
+                embedding_ids.extend(ids.iter().copied());
+            }
+        }
+
+        // Sum the older and newer halves separately to measure drift
+        let half = embedding_ids.len() / 2;
+        let mut older = Vec::new();
+        let mut newer = Vec::new();
+        for (index, id) in embedding_ids.iter().enumerate() {
+            let embedding = vector_db.get_embedding(*id)?;
+            add_to(
+                if index < half { &mut older } else { &mut newer },
+                embedding.vector(),
+            );
+        }
+        let mut total = older.clone();
+        add_to(&mut total, &newer);
+        let centroid = mean(&total, embedding_ids.len());
+        let centroid_drift = if half == 0 {
+            0.0
+        } else {
+            cosine_distance(
+                &mean(&older, half),
+                &mean(&newer, embedding_ids.len() - half),
+            )
+        };
+
+        // Find near duplicates with the nearest neighbor of each embedding
+        let centroid_embedding = Embedding::from(centroid.iter().copied());
+        let mut similarity_sum = 0.0;
+        let mut duplicates = HashSet::new();
+        for id in &embedding_ids {
+            let embedding = vector_db.get_embedding(*id)?;
+            let similarity = embedding.cosine_similarity(&centroid_embedding);
+            if !similarity.is_nan() {
+                similarity_sum += similarity;
+            }
+            if duplicates.contains(id) {
+                continue;
+            }
+            let neighbors = vector_db.search(&embedding).with_results(2).run()?;
+            for neighbor in neighbors {
+                if neighbor.value == *id {
+                    continue;
+                }
+                let other = vector_db.get_embedding(neighbor.value)?;
+                if embedding.cosine_similarity(&other) >= DUPLICATE_SIMILARITY {
+                    duplicates.insert(*id);
+                    duplicates.insert(neighbor.value);
+                }
+            }
+        }
+        let embedding_count = embedding_ids.len();
+        let embedding_space = EmbeddingSpaceStats {
+            dimensions: centroid.len(),
+            mean_similarity_to_centroid: similarity_sum / embedding_count.max(1) as f32,
+            centroid,
+            centroid_drift,
+            duplicate_ratio: duplicates.len() as f32 / embedding_count.max(1) as f32,
+        };
+
+        Ok(CorpusStats {
+            documents: records.len(),
+            chunks: lengths.len(),
+            embeddings: embedding_count,
+            chunk_tokens: TokenDistribution::from_lengths(lengths),
+            embedding_space,
+            sources: self.refresh.freshness(),
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use super::{DocumentTable, DocumentTableSurrealExt};
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use surrealdb::engine::local::{Db, SurrealKv};
+use surrealdb::Surreal;
+
+/// An embedder that embeds text by its length, so the tests don't need to download a model.
+pub(super) struct LengthEmbedder;
+
+impl Embedder for LengthEmbedder {
+    type Error = std::convert::Infallible;
+
+    async fn embed_for(&self, input: EmbeddingInput) -> Result<Embedding, Self::Error> {
+        Ok(Embedding::from([input.text.len() as f32, 1.0]))
+    }
+}
+
+/// A database stored in a fresh temporary directory.
+pub(super) struct TestDatabase {
+    pub(super) db: Surreal<Db>,
+    dir: PathBuf,
+}
+
+impl TestDatabase {
+    /// Open a database in a temporary directory named after the test. Anything left from an earlier run is removed.
+    pub(super) async fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("kalosm-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let db = Surreal::new::<SurrealKv>(dir.join("db")).await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        Self { db, dir }
+    }
+
+    /// Open the `documents` table with the [`LengthEmbedder`]. Opening it again reuses the stored documents.
+    pub(super) async fn document_table<R: Serialize + DeserializeOwned>(
+        &self,
+    ) -> DocumentTable<Db, R, LengthEmbedder, ChunkStrategy> {
+        self.db
+            .document_table_builder("documents")
+            .with_embedding_model(LengthEmbedder)
+            .at(self.dir.join("embeddings"))
+            .build()
+            .await
+            .unwrap()
+    }
+
+    /// Close the database and remove its directory. Drop any tables opened from it first.
+    pub(super) fn remove(self) {
+        let Self { db, dir } = self;
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -24,6 +24,9 @@ pub enum EmbeddedIndexedTableError {
     /// An error from querying an embedding id that does not exist.
     #[error("Embedding {0:?} not found")]
     EmbeddingNotFound(EmbeddingId),
+    /// Any other error from the vector database.
+    #[error("Vector database error: {0}")]
+    VectorDb(VectorDbError),
 }
 
 impl From<heed::Error> for EmbeddedIndexedTableError {
@@ -37,6 +40,7 @@ impl From<VectorDbError> for EmbeddedIndexedTableError {
         match value {
             VectorDbError::Arroy(err) => Self::Arroy(err),
             VectorDbError::EmbeddingNotFound(id) => Self::EmbeddingNotFound(id),
+            err => Self::VectorDb(err),
         }
     }
 }
@@ -78,6 +82,11 @@ impl<C: Connection, R> EmbeddingIndexedTable<C, R> {
         format!("{}-links", &self.table)
     }
 
+    /// Get the name of the table that holds the records of every snapshot.
+    fn table_snapshots(&self) -> String {
+        format!("{}-snapshots", &self.table)
+    }
+
     /// Get the raw vector database.
     pub fn vector_db(&self) -> &VectorDB {
         &self.vector_db
@@ -128,8 +137,21 @@ impl<C: Connection, R> EmbeddingIndexedTable<C, R> {
         R: Serialize + DeserializeOwned + 'static,
     {
         let id_uuid = surrealdb::sql::Uuid::new_v7().0;
-        let id = RecordIdKey::from(id_uuid);
+        self.insert_with_id(RecordIdKey::from(id_uuid), chunks, value)
+            .await
+    }
 
+    /// Insert a new record into the table with the given id and embedding. The id must not be used by another
+    /// record.
+    pub(crate) async fn insert_with_id(
+        &self,
+        id: RecordIdKey,
+        chunks: impl IntoIterator<Item = Chunk>,
+        value: R,
+    ) -> Result<RecordIdKey, EmbeddedIndexedTableError>
+    where
+        R: Serialize + DeserializeOwned + 'static,
+    {
         let mut embedding_ids = Vec::new();
         let thing = RecordId::from_table_key(self.table.clone(), id.clone());
 
@@ -246,6 +268,111 @@ impl<C: Connection, R> EmbeddingIndexedTable<C, R> {
         Ok(records.into_iter().map(|v| v.object).collect())
     }
 
+    /// Create a snapshot of the records in the table and the embeddings in the vector database. The table can be
+    /// rolled back to the snapshot later with [`EmbeddingIndexedTable::restore_snapshot`].
+    ///
+    /// The records are copied into the `{table}-snapshots` table. The embeddings use the copy-on-write snapshots of
+    /// the [`VectorDB`], so they are only copied when they are removed while the snapshot still references them.
+    pub async fn create_snapshot(
+        &self,
+        name: impl ToString,
+    ) -> Result<VectorDBSnapshot, EmbeddedIndexedTableError> {
+        self.create_snapshot_with_tables(name, Vec::new()).await
+    }
+
+    /// Create a snapshot that also includes every record in the given companion tables.
+    pub(crate) async fn create_snapshot_with_tables(
+        &self,
+        name: impl ToString,
+        tables: Vec<String>,
+    ) -> Result<VectorDBSnapshot, EmbeddedIndexedTableError> {
+        let name = name.to_string();
+        let snapshot = self.vector_db.create_snapshot(&name)?;
+        let copied = self
+            .db
+            .query(
+                "FOR $table IN $tables {
+                    FOR $row IN (SELECT * FROM type::table($table)) {
+                        CREATE type::table($snapshots) CONTENT { snapshot: $name, row: $row };
+                    };
+                };",
+            )
+            .bind(("tables", self.snapshot_tables(tables)))
+            .bind(("snapshots", self.table_snapshots()))
+            .bind(("name", name.clone()))
+            .await;
+        if let Err(err) = copied.and_then(surrealdb::Response::check) {
+            // Don't leave a snapshot of the embeddings without the records that point to them
+            self.delete_snapshot_rows(&name).await?;
+            self.vector_db.delete_snapshot(&name)?;
+            return Err(err.into());
+        }
+        Ok(snapshot)
+    }
+
+    /// List the snapshots of the table from oldest to newest.
+    pub fn snapshots(&self) -> Result<Vec<VectorDBSnapshot>, VectorDbError> {
+        self.vector_db.snapshots()
+    }
+
+    /// Roll the table back to a snapshot. Every record and embedding is returned to the state it was in when the
+    /// snapshot was created, so the records keep pointing at the right embeddings.
+    ///
+    /// The snapshot is kept, so the table can be restored to it again later.
+    pub async fn restore_snapshot(&self, name: &str) -> Result<(), EmbeddedIndexedTableError> {
+        self.restore_snapshot_with_tables(name, Vec::new()).await
+    }
+
+    /// Restore a snapshot that also includes every record in the given companion tables.
+    pub(crate) async fn restore_snapshot_with_tables(
+        &self,
+        name: &str,
+        tables: Vec<String>,
+    ) -> Result<(), EmbeddedIndexedTableError> {
+        self.vector_db.restore_snapshot(name)?;
+        self.db
+            .query(
+                "BEGIN TRANSACTION;
+                FOR $table IN $tables {
+                    DELETE type::table($table);
+                };
+                FOR $row IN (SELECT VALUE row FROM type::table($snapshots) WHERE snapshot = $name) {
+                    CREATE $row.id CONTENT $row;
+                };
+                COMMIT TRANSACTION;",
+            )
+            .bind(("tables", self.snapshot_tables(tables)))
+            .bind(("snapshots", self.table_snapshots()))
+            .bind(("name", name.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Delete a snapshot. This does not change the records or embeddings currently in the table.
+    pub async fn delete_snapshot(&self, name: &str) -> Result<(), EmbeddedIndexedTableError> {
+        self.vector_db.delete_snapshot(name)?;
+        self.delete_snapshot_rows(name).await
+    }
+
+    async fn delete_snapshot_rows(&self, name: &str) -> Result<(), EmbeddedIndexedTableError> {
+        self.db
+            .query("DELETE type::table($snapshots) WHERE snapshot = $name")
+            .bind(("snapshots", self.table_snapshots()))
+            .bind(("name", name.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// The tables a snapshot includes: the table, the links to the embeddings and any companion tables.
+    fn snapshot_tables(&self, tables: Vec<String>) -> Vec<String> {
+        [self.table.clone(), self.table_links()]
+            .into_iter()
+            .chain(tables)
+            .collect()
+    }
+
     /// Search for records that are close to the given embedding.
     pub fn search<'a>(
         &'a self,
@@ -430,6 +557,26 @@ where
     {
         self.record.as_ref().body()[self.byte_range.clone()].to_string()
     }
+
+    /// Find the sentences in the search result that are most similar to the query so they can be highlighted instead
+    /// of showing the whole chunk. The byte ranges of the highlights are relative to the body of the document and are
+    /// sorted from most to least similar.
+    pub async fn highlights<E: Embedder>(
+        &self,
+        query: &Embedding,
+        embedder: &E,
+    ) -> Result<Vec<Highlight>, E::Error>
+    where
+        R: AsRef<Document>,
+    {
+        let text = self.text();
+        let mut highlights = highlight_passage(&text, query, embedder).await?;
+        for highlight in &mut highlights {
+            highlight.byte_range = highlight.byte_range.start + self.byte_range.start
+                ..highlight.byte_range.end + self.byte_range.start;
+        }
+        Ok(highlights)
+    }
 }
 
 /// A builder for creating a new document table.
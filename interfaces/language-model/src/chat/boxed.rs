@@ -9,7 +9,21 @@ use std::{error::Error, future::Future, pin::Pin, sync::Arc};
 /// A boxed [`ChatModel`].
 #[derive(Clone)]
 pub struct BoxedChatModel {
-    model: Arc<dyn DynChatModel + Send + Sync>,
+    model: Arc<dyn AnyChatModel>,
+}
+
+impl From<Box<dyn AnyChatModel>> for BoxedChatModel {
+    fn from(model: Box<dyn AnyChatModel>) -> Self {
+        Self {
+            model: Arc::from(model),
+        }
+    }
+}
+
+impl From<Arc<dyn AnyChatModel>> for BoxedChatModel {
+    fn from(model: Arc<dyn AnyChatModel>) -> Self {
+        Self { model }
+    }
 }
 
 impl BoxedChatModel {
@@ -36,7 +50,7 @@ impl CreateChatSession for BoxedChatModel {
     type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
     fn new_chat_session(&self) -> Result<Self::ChatSession, Self::Error> {
-        self.model.new_chat_session_boxed()
+        self.model.new_boxed_chat_session()
     }
 }
 
@@ -49,7 +63,71 @@ impl ChatModel for BoxedChatModel {
         on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
         self.model
-            .add_messages_with_callback_boxed(session, messages, sampler, Box::new(on_token))
+            .add_messages_boxed(session, messages, sampler, Box::new(on_token))
+    }
+}
+
+/// An object safe version of [`ChatModel`]. This trait is implemented for every [`ChatModel`] with a cloneable
+/// session and can be used to choose between local and remote models at runtime with `Box<dyn AnyChatModel>`.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let model: Box<dyn AnyChatModel> = match std::env::var("MODEL").as_deref() {
+///         Ok("openai") => Box::new(OpenAICompatibleChatModel::builder().with_gpt_4o_mini().build()),
+///         _ => Box::new(Llama::new_chat().await.unwrap()),
+///     };
+///     let model = BoxedChatModel::from(model);
+///     let mut chat = model.chat();
+///     chat.add_message("Hello!").to_std_out().await.unwrap();
+/// }
+/// ```
+pub trait AnyChatModel: Send + Sync + 'static {
+    /// Create a new type erased chat session for the model.
+    fn new_boxed_chat_session(
+        &self,
+    ) -> Result<BoxedChatSession, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Add messages to a type erased session and stream the response into the callback.
+    fn add_messages_boxed<'a>(
+        &'a self,
+        session: &'a mut BoxedChatSession,
+        messages: &[ChatMessage],
+        sampler: crate::GenerationParameters,
+        on_token: BoxedTokenClosure,
+    ) -> BoxedMaybeFuture<'a>;
+}
+
+impl<S> AnyChatModel for S
+where
+    S: ChatModel<
+            Error: Send + Sync + Error + 'static,
+            ChatSession: ChatSession<Error: Error + Send + Sync + 'static>
+                             + Clone
+                             + Send
+                             + Sync
+                             + 'static,
+        > + Send
+        + Sync
+        + 'static,
+{
+    fn new_boxed_chat_session(
+        &self,
+    ) -> Result<BoxedChatSession, Box<dyn std::error::Error + Send + Sync>> {
+        DynCreateChatSession::new_chat_session_boxed(self)
+    }
+
+    fn add_messages_boxed<'a>(
+        &'a self,
+        session: &'a mut BoxedChatSession,
+        messages: &[ChatMessage],
+        sampler: crate::GenerationParameters,
+        on_token: BoxedTokenClosure,
+    ) -> BoxedMaybeFuture<'a> {
+        DynChatModel::add_messages_with_callback_boxed(self, session, messages, sampler, on_token)
     }
 }
 
@@ -1,6 +1,10 @@
+use crate::ArrayItemStream;
+use crate::FinishReason;
+use crate::GenerationHandle;
 use crate::GenerationParameters;
 use crate::ModelConstraints;
 use crate::NoConstraints;
+use crate::Usage;
 use async_lock::Mutex as AsyncMutex;
 use futures_channel::mpsc::UnboundedReceiver;
 use futures_channel::oneshot::Receiver;
@@ -8,6 +12,7 @@ use futures_util::Future;
 use futures_util::FutureExt;
 use futures_util::Stream;
 use futures_util::StreamExt;
+use kalosm_sample::{ArrayParser, CreateParserState};
 use std::any::Any;
 use std::fmt::Debug;
 use std::future::IntoFuture;
@@ -27,6 +32,7 @@ use super::ChatSession;
 use super::CreateChatSession;
 use super::CreateDefaultChatConstraintsForType;
 use super::IntoChatMessage;
+use super::MessageImage;
 use super::MessageType;
 use super::StructuredChatModel;
 
@@ -37,6 +43,8 @@ pub struct Chat<M: CreateChatSession> {
     #[allow(clippy::type_complexity)]
     session: OnceLock<Result<Arc<AsyncMutex<M::ChatSession>>, M::Error>>,
     queued_messages: Vec<ChatMessage>,
+    /// Images that are attached to the next message
+    queued_images: Vec<MessageImage>,
 }
 
 impl<M: CreateChatSession + Debug> Debug for Chat<M> {
@@ -44,6 +52,7 @@ impl<M: CreateChatSession + Debug> Debug for Chat<M> {
         f.debug_struct("Chat")
             .field("model", &self.model)
             .field("queued_messages", &self.queued_messages)
+            .field("queued_images", &self.queued_images)
             .finish()
     }
 }
@@ -68,6 +77,7 @@ impl<M: CreateChatSession> Clone for Chat<M> {
             session,
             model,
             queued_messages,
+            queued_images: self.queued_images.clone(),
         }
     }
 }
@@ -91,9 +101,35 @@ impl<M: CreateChatSession> Chat<M> {
             model: Arc::new(model),
             session: OnceLock::new(),
             queued_messages: Vec::new(),
+            queued_images: Vec::new(),
         }
     }
 
+    /// Create a chat from a transcript exported from another chat application. The transcript can be an OpenAI
+    /// messages array or a ShareGPT conversation. See [`parse_transcript`](super::parse_transcript) for how the
+    /// messages are mapped into the history.
+    ///
+    /// The imported messages are processed by the model along with the next message you add, so later responses
+    /// reuse the cached history.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::new_chat().await.unwrap();
+    /// let transcript = std::fs::read_to_string("conversation.json").unwrap();
+    /// let mut chat = Chat::from_transcript(model, &transcript).unwrap();
+    /// chat("Can you summarize what we talked about?").to_std_out().await.unwrap();
+    /// # }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_transcript(model: M, transcript: &str) -> Result<Self, super::TranscriptError> {
+        let mut chat = Self::new(model);
+        chat.queued_messages = super::parse_transcript(transcript)?;
+        Ok(chat)
+    }
+
     /// Adds a system prompt to the chat. The system prompt guides the model to respond in a certain way.
     /// If no system prompt is added, the model will use a default system prompt that instructs the model to respond in a way that is safe and respectful.
     ///
@@ -177,7 +213,8 @@ impl<M: CreateChatSession> Chat<M> {
     /// ```
     pub fn add_message(&mut self, message: impl IntoChatMessage) -> ChatResponseBuilder<'_, M> {
         // First push the message to the queue
-        self.queued_messages.push(message.into_chat_message());
+        let message = self.attach_queued_images(message.into_chat_message());
+        self.queued_messages.push(message);
 
         // Then create the builder that will respond to the message if it is awaited
         ChatResponseBuilder {
@@ -187,6 +224,7 @@ impl<M: CreateChatSession> Chat<M> {
             task: OnceLock::new(),
             queued_tokens: None,
             result: None,
+            handle: GenerationHandle::new(),
         }
     }
 
@@ -212,7 +250,8 @@ impl<M: CreateChatSession> Chat<M> {
         message: impl IntoChatMessage,
     ) -> ChatResponseBuilder<'static, M> {
         // First push the message to the queue
-        self.queued_messages.push(message.into_chat_message());
+        let message = self.attach_queued_images(message.into_chat_message());
+        self.queued_messages.push(message);
 
         // Then create the builder that will respond to the message if it is awaited
         ChatResponseBuilder {
@@ -222,7 +261,41 @@ impl<M: CreateChatSession> Chat<M> {
             task: OnceLock::new(),
             queued_tokens: None,
             result: None,
+            handle: GenerationHandle::new(),
+        }
+    }
+
+    /// Adds an image to the next message in the chat. The model needs vision support to read the image.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llava_1_5_7b())
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// let mut chat = model.chat();
+    /// let image = MessageImage::from_path("cat.png").unwrap();
+    /// chat.add_image(image)
+    ///     .add_message("What is in this picture?")
+    ///     .to_std_out()
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_image(&mut self, image: impl Into<MessageImage>) -> &mut Self {
+        self.queued_images.push(image.into());
+        self
+    }
+
+    fn attach_queued_images(&mut self, mut message: ChatMessage) -> ChatMessage {
+        for image in self.queued_images.drain(..) {
+            message = message.with_image(image);
         }
+        message
     }
 
     fn session_clone(&mut self) -> Result<Arc<AsyncMutex<M::ChatSession>>, M::Error> {
@@ -289,6 +362,34 @@ impl<M: CreateChatSession> Chat<M> {
             Err(err) => Err(err),
         }
     }
+
+    /// Create a new empty chat that shares the model with this chat.
+    pub(crate) fn with_same_model(&self) -> Self {
+        Self {
+            model: self.model.clone(),
+            session: OnceLock::new(),
+            queued_messages: Vec::new(),
+            queued_images: Vec::new(),
+        }
+    }
+
+    /// Get the messages in the chat, including messages that have not been sent to the model yet. If the session is
+    /// currently generating a response, only the queued messages are returned.
+    pub(crate) fn messages(&self) -> Vec<ChatMessage> {
+        let mut history = match self.session.get() {
+            Some(Ok(session)) => session
+                .try_lock()
+                .map(|session| session.history())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        // Messages from a session added with `with_session` are already in the queue
+        if self.queued_messages.starts_with(&history) {
+            history.clear();
+        }
+        history.extend_from_slice(&self.queued_messages);
+        history
+    }
 }
 
 impl<M: CreateChatSession + Clone + 'static> Deref for Chat<M> {
@@ -434,6 +535,7 @@ pub struct ChatResponseBuilder<
     #[allow(clippy::type_complexity)]
     result: Option<Receiver<Result<Box<dyn Any + Send>, M::Error>>>,
     queued_tokens: Option<UnboundedReceiver<String>>,
+    handle: GenerationHandle,
 }
 
 impl<'a, M: CreateChatSession, Constraints, Sampler>
@@ -472,6 +574,7 @@ impl<'a, M: CreateChatSession, Constraints, Sampler>
             queued_tokens: None,
             result: None,
             task: OnceLock::new(),
+            handle: self.handle,
         }
     }
 
@@ -552,8 +655,29 @@ impl<'a, M: CreateChatSession, Constraints, Sampler>
             queued_tokens: None,
             result: None,
             task: OnceLock::new(),
+            handle: self.handle,
         }
     }
+
+    /// Get a [`GenerationHandle`] that can abort the response while it is generated.
+    pub fn handle(&self) -> GenerationHandle {
+        self.handle.clone()
+    }
+
+    /// Stop the response at the next token. See [`GenerationHandle::abort`].
+    pub fn abort(&self) {
+        self.handle.abort();
+    }
+
+    /// Get why the response stopped once it finishes. See [`GenerationHandle::finish_reason`].
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.handle.finish_reason()
+    }
+
+    /// Get the tokens and time the response used once it finishes. See [`GenerationHandle::usage`].
+    pub fn usage(&self) -> Option<Usage> {
+        self.handle.usage()
+    }
 }
 
 impl<M, Sampler> ChatResponseBuilder<'_, M, NoConstraints, Sampler>
@@ -565,10 +689,11 @@ where
     fn ensure_unstructured_task_started(&mut self) {
         if self.task.get().is_none() {
             let messages = std::mem::take(&mut self.chat_session.queued_messages);
-            let sampler = self
+            let mut sampler = self
                 .sampler
                 .take()
                 .expect("ChatResponseBuilder cannot be turned into a future twice");
+            self.handle.attach(&mut sampler);
             let (mut tx, rx) = futures_channel::mpsc::unbounded();
             let (result_tx, result_rx) = futures_channel::oneshot::channel();
             self.queued_tokens = Some(rx);
@@ -670,10 +795,11 @@ where
     fn ensure_structured_task_started(&mut self) {
         if self.task.get().is_none() {
             let messages = std::mem::take(&mut self.chat_session.queued_messages);
-            let sampler = self
+            let mut sampler = self
                 .sampler
                 .take()
                 .expect("ChatResponseBuilder cannot be turned into a future twice");
+            self.handle.attach(&mut sampler);
             let constraints = self
                 .constraints
                 .take()
@@ -749,6 +875,42 @@ where
     }
 }
 
+impl<M, P, Sampler> ChatResponseBuilder<'_, M, ArrayParser<P>, Sampler>
+where
+    M: CreateChatSession,
+    P: CreateParserState,
+{
+    /// Stream each item of the array as soon as the model finishes generating it, instead of waiting for the whole
+    /// array. See [`TextCompletionBuilder::stream_items`](crate::TextCompletionBuilder::stream_items).
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::new_chat().await.unwrap();
+    /// let mut chat = model.chat();
+    /// let parser = ArrayParser::new(String::new_parser()).with_unique_items();
+    /// let mut ideas = chat("Brainstorm names for a bakery")
+    ///     .with_constraints(parser)
+    ///     .stream_items();
+    /// while let Some(idea) = ideas.next().await {
+    ///     println!("{idea}");
+    /// }
+    /// # }
+    /// ```
+    pub fn stream_items(self) -> ArrayItemStream<Self, P>
+    where
+        Self: Stream<Item = String> + Unpin,
+    {
+        let parser = self
+            .constraints
+            .clone()
+            .expect("stream_items must be called before the response is started");
+        ArrayItemStream::new(self, parser)
+    }
+}
+
 impl<'a, M, Constraints, Sampler> IntoFuture for ChatResponseBuilder<'a, M, Constraints, Sampler>
 where
     Constraints: ModelConstraints + Send + Sync + Unpin + 'static,
@@ -0,0 +1,94 @@
+use std::fmt::Write;
+use std::sync::Arc;
+
+use kalosm_sample::UnifiedDiffParser;
+
+use super::ChatResponseBuilder;
+use super::CreateChatSession;
+use super::Task;
+
+const CODE_EDIT_DESCRIPTION: &str = "You are a code editing assistant. You are given a file with line numbers and an instruction. Respond with a unified diff of the file that follows the instruction. Each hunk starts with a header like `@@ -3,4 +3,5 @@` followed by context lines starting with a space, removed lines starting with `-` and added lines starting with `+`. Copy context and removed lines exactly from the file without the line numbers. End the diff with a blank line.";
+
+/// A task that edits a file by generating a unified diff instead of rewriting the whole file. The diff is constrained
+/// with a [`UnifiedDiffParser`] built from the file, so hunk headers are always valid and every context and removed
+/// line matches the file. The resulting [`UnifiedDiff`](kalosm_sample::UnifiedDiff) can be applied with
+/// [`UnifiedDiff::apply`](kalosm_sample::UnifiedDiff::apply).
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let model = Llama::new_chat().await.unwrap();
+///     let task = CodeEditTask::new(model);
+///     let file = std::fs::read_to_string("src/main.rs").unwrap();
+///     let diff = task
+///         .run("src/main.rs", &file, "Print \"Hello, world!\" instead of \"hi\"")
+///         .await
+///         .unwrap();
+///     println!("{diff}");
+///     std::fs::write("src/main.rs", diff.apply(&file).unwrap()).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct CodeEditTask<M: CreateChatSession> {
+    task: Task<M>,
+}
+
+impl<M: CreateChatSession> Clone for CodeEditTask<M> {
+    fn clone(&self) -> Self {
+        Self {
+            task: self.task.clone(),
+        }
+    }
+}
+
+impl<M: CreateChatSession> CodeEditTask<M> {
+    /// Create a new code edit task.
+    pub fn new(model: M) -> Self {
+        Self {
+            task: Task::new(model, CODE_EDIT_DESCRIPTION),
+        }
+    }
+
+    /// Add an example edit to the task. The example output should be a unified diff of the file that ends with a
+    /// blank line.
+    pub fn with_example(
+        mut self,
+        path: impl AsRef<str>,
+        file: impl AsRef<str>,
+        instruction: impl AsRef<str>,
+        diff: impl ToString,
+    ) -> Self {
+        let message = edit_message(path.as_ref(), file.as_ref(), instruction.as_ref());
+        self.task = self.task.with_example(message, diff);
+        self
+    }
+
+    /// Edit a file with an instruction. The response is a diff of the file with a `--- a/path` and `+++ b/path`
+    /// header.
+    pub fn run(
+        &self,
+        path: impl AsRef<str>,
+        file: impl AsRef<str>,
+        instruction: impl AsRef<str>,
+    ) -> ChatResponseBuilder<'static, M, Arc<UnifiedDiffParser>> {
+        let (path, file) = (path.as_ref(), file.as_ref());
+        let parser = UnifiedDiffParser::new(file).with_path(path);
+        self.task
+            .clone()
+            .with_constraints(Arc::new(parser))
+            .run(edit_message(path, file, instruction.as_ref()))
+    }
+}
+
+/// Format the file with line numbers so the model can write hunk headers.
+fn edit_message(path: &str, file: &str, instruction: &str) -> String {
+    let mut message = format!("File: {path}\n");
+    for (i, line) in file.lines().enumerate() {
+        let _ = writeln!(message, "{:>4} | {line}", i + 1);
+    }
+    let _ = write!(message, "\nInstruction: {instruction}");
+    message
+}
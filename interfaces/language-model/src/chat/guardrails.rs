@@ -0,0 +1,524 @@
+use futures_util::Future;
+use regex::Regex;
+use std::sync::{Arc, Mutex};
+
+use super::{ChatMessage, ChatModel, CreateChatSession};
+
+/// The result of running an [`OutputFilter`] on a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterVerdict {
+    /// The response is allowed without changes.
+    Allow,
+    /// The response is allowed with the given text instead.
+    Rewrite(String),
+    /// The response is not allowed. The reason is included in the [`GuardrailEvent`].
+    Block(String),
+}
+
+/// A check that runs on the complete text of a response before it is surfaced to the caller. Filters are used
+/// with [`Guardrails`].
+pub trait OutputFilter: Send + Sync + 'static {
+    /// The name of the filter that is included in each [`GuardrailEvent`].
+    fn name(&self) -> &str;
+
+    /// Check the text of a response.
+    fn check(&self, text: &str) -> FilterVerdict;
+}
+
+/// What a filter did to a response in a [`GuardrailEvent`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GuardrailAction {
+    /// The filter changed the text of the response.
+    Rewritten,
+    /// The filter blocked the response, and it was replaced with the blocked message of the [`Guardrails`].
+    Blocked {
+        /// Why the response was blocked.
+        reason: String,
+    },
+}
+
+/// An event that is emitted when a filter alters a response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardrailEvent {
+    /// The name of the filter that altered the response.
+    pub filter: String,
+    /// What the filter did.
+    pub action: GuardrailAction,
+    /// The text before the filter ran.
+    pub original: String,
+    /// The text after the filter ran.
+    pub output: String,
+}
+
+type EventHandler = Arc<dyn Fn(&GuardrailEvent) + Send + Sync>;
+
+/// A [`ChatModel`] wrapper that runs a chain of [`OutputFilter`]s on every response before it is surfaced to the
+/// caller. Filters run in the order they are added, and each filter sees the output of the previous filter. If a
+/// filter blocks the response, the rest of the chain is skipped and the response is replaced with the blocked
+/// message.
+///
+/// The response is held back until generation finishes so the filters can see the complete text, so streaming
+/// responses arrive in one piece. The session history keeps the unfiltered response the model generated.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new_chat().await.unwrap();
+///     let llm = Guardrails::new(llm)
+///         .with_filter(DenyListFilter::new().with_word("password"))
+///         .with_filter(PiiFilter::new())
+///         .with_filter(MaxLinksFilter::new(2))
+///         .with_event_handler(|event| println!("{} altered a response: {:?}", event.filter, event.action));
+///     let mut chat = llm.chat();
+///     chat("What is the support email address?").to_std_out().await.unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Guardrails<M> {
+    model: M,
+    filters: Vec<Arc<dyn OutputFilter>>,
+    event_handler: Option<EventHandler>,
+    blocked_message: String,
+}
+
+impl<M> Guardrails<M> {
+    /// Wrap a model without any filters.
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            filters: Vec::new(),
+            event_handler: None,
+            blocked_message: "Sorry, I can't help with that.".to_string(),
+        }
+    }
+
+    /// Add a filter to the end of the chain.
+    pub fn with_filter(mut self, filter: impl OutputFilter) -> Self {
+        self.filters.push(Arc::new(filter));
+        self
+    }
+
+    /// Call the handler every time a filter alters a response.
+    pub fn with_event_handler(
+        mut self,
+        handler: impl Fn(&GuardrailEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.event_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Set the text that replaces blocked responses. (Defaults to "Sorry, I can't help with that.")
+    pub fn with_blocked_message(mut self, message: impl ToString) -> Self {
+        self.blocked_message = message.to_string();
+        self
+    }
+
+    /// Get the wrapped model.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Run the filter chain on some text and get the filtered text along with an event for each filter that
+    /// altered it. The event handler is not called.
+    pub fn filter_text(&self, text: &str) -> (String, Vec<GuardrailEvent>) {
+        let mut text = text.to_string();
+        let mut events = Vec::new();
+        for filter in &self.filters {
+            let (output, action) = match filter.check(&text) {
+                FilterVerdict::Allow => continue,
+                FilterVerdict::Rewrite(output) if output == text => continue,
+                FilterVerdict::Rewrite(output) => (output, GuardrailAction::Rewritten),
+                FilterVerdict::Block(reason) => (
+                    self.blocked_message.clone(),
+                    GuardrailAction::Blocked { reason },
+                ),
+            };
+            let blocked = matches!(action, GuardrailAction::Blocked { .. });
+            events.push(GuardrailEvent {
+                filter: filter.name().to_string(),
+                action,
+                original: std::mem::replace(&mut text, output.clone()),
+                output,
+            });
+            if blocked {
+                break;
+            }
+        }
+        (text, events)
+    }
+}
+
+impl<M: CreateChatSession> CreateChatSession for Guardrails<M> {
+    type Error = M::Error;
+    type ChatSession = M::ChatSession;
+
+    fn new_chat_session(&self) -> Result<Self::ChatSession, Self::Error> {
+        self.model.new_chat_session()
+    }
+}
+
+impl<M, Sampler> ChatModel<Sampler> for Guardrails<M>
+where
+    Sampler: Send,
+    M: ChatModel<Sampler> + Send + Sync,
+    M::ChatSession: Send,
+{
+    fn add_messages_with_callback<'a>(
+        &'a self,
+        session: &'a mut Self::ChatSession,
+        messages: &[ChatMessage],
+        sampler: Sampler,
+        mut on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+        let response = Arc::new(Mutex::new(String::new()));
+        let collect = {
+            let response = response.clone();
+            move |token: String| {
+                response.lock().unwrap().push_str(&token);
+                Ok(())
+            }
+        };
+        let generation = self
+            .model
+            .add_messages_with_callback(session, messages, sampler, collect);
+        async move {
+            generation.await?;
+            let response = std::mem::take(&mut *response.lock().unwrap());
+            let (output, events) = self.filter_text(&response);
+            if let Some(handler) = &self.event_handler {
+                for event in &events {
+                    handler(event);
+                }
+            }
+            on_token(output)
+        }
+    }
+}
+
+/// An [`OutputFilter`] that redacts or blocks words and regex patterns from a deny list.
+///
+/// # Example
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let filter = DenyListFilter::new()
+///     .with_word("secret")
+///     .with_pattern(r"sk-[a-zA-Z0-9]{20,}")
+///     .unwrap();
+/// assert_eq!(
+///     filter.check("The Secret is out"),
+///     FilterVerdict::Rewrite("The [redacted] is out".to_string())
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct DenyListFilter {
+    patterns: Vec<Regex>,
+    replacement: String,
+    block: bool,
+}
+
+impl Default for DenyListFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DenyListFilter {
+    /// Create an empty deny list that replaces matches with `[redacted]`.
+    pub fn new() -> Self {
+        Self {
+            patterns: Vec::new(),
+            replacement: "[redacted]".to_string(),
+            block: false,
+        }
+    }
+
+    /// Deny a whole word, ignoring case.
+    pub fn with_word(mut self, word: &str) -> Self {
+        let pattern = format!(r"(?i)\b{}\b", regex::escape(word));
+        self.patterns
+            .push(Regex::new(&pattern).expect("escaped words are valid patterns"));
+        self
+    }
+
+    /// Deny every match of a regex pattern.
+    pub fn with_pattern(self, pattern: &str) -> Result<Self, regex::Error> {
+        Ok(self.with_regex(Regex::new(pattern)?))
+    }
+
+    /// Deny every match of a compiled regex.
+    pub fn with_regex(mut self, regex: Regex) -> Self {
+        self.patterns.push(regex);
+        self
+    }
+
+    /// Set the text that replaces each match. (Defaults to `[redacted]`)
+    pub fn with_replacement(mut self, replacement: impl ToString) -> Self {
+        self.replacement = replacement.to_string();
+        self
+    }
+
+    /// Block the whole response if anything matches instead of redacting the matches.
+    pub fn blocking(mut self) -> Self {
+        self.block = true;
+        self
+    }
+}
+
+impl OutputFilter for DenyListFilter {
+    fn name(&self) -> &str {
+        "deny list"
+    }
+
+    fn check(&self, text: &str) -> FilterVerdict {
+        if self.block {
+            return match self.patterns.iter().find(|pattern| pattern.is_match(text)) {
+                Some(pattern) => FilterVerdict::Block(format!("matched {}", pattern.as_str())),
+                None => FilterVerdict::Allow,
+            };
+        }
+        replace_all(text, &self.patterns, &self.replacement)
+    }
+}
+
+/// A kind of personally identifiable information detected by [`PiiFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PiiKind {
+    /// Email addresses
+    Email,
+    /// Phone numbers with at least 10 digits
+    PhoneNumber,
+    /// Credit card numbers that pass the Luhn checksum
+    CreditCard,
+    /// US social security numbers in the `123-45-6789` format
+    SocialSecurityNumber,
+    /// IPv4 addresses
+    IpAddress,
+}
+
+impl PiiKind {
+    /// Every kind of personally identifiable information.
+    pub const ALL: [PiiKind; 5] = [
+        PiiKind::Email,
+        PiiKind::PhoneNumber,
+        PiiKind::CreditCard,
+        PiiKind::SocialSecurityNumber,
+        PiiKind::IpAddress,
+    ];
+
+    fn pattern(&self) -> &'static str {
+        match self {
+            PiiKind::Email => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}",
+            PiiKind::PhoneNumber => r"(?:\+?\d{1,3}[\s.-]?)?\(?\d{3}\)?[\s.-]?\d{3}[\s.-]?\d{4}\b",
+            PiiKind::CreditCard => r"\b(?:\d[ -]?){12,18}\d\b",
+            PiiKind::SocialSecurityNumber => r"\b\d{3}-\d{2}-\d{4}\b",
+            PiiKind::IpAddress => {
+                r"\b(?:(?:25[0-5]|2[0-4]\d|1?\d?\d)\.){3}(?:25[0-5]|2[0-4]\d|1?\d?\d)\b"
+            }
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            PiiKind::Email => "[email]",
+            PiiKind::PhoneNumber => "[phone number]",
+            PiiKind::CreditCard => "[credit card]",
+            PiiKind::SocialSecurityNumber => "[ssn]",
+            PiiKind::IpAddress => "[ip address]",
+        }
+    }
+}
+
+/// An [`OutputFilter`] that redacts personally identifiable information like email addresses and phone numbers.
+/// Each match is replaced with a label for its kind like `[email]`.
+///
+/// # Example
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let filter = PiiFilter::new();
+/// assert_eq!(
+///     filter.check("Email jane@example.com for access"),
+///     FilterVerdict::Rewrite("Email [email] for access".to_string())
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct PiiFilter {
+    detectors: Vec<(PiiKind, Regex)>,
+}
+
+impl Default for PiiFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PiiFilter {
+    /// Create a filter that redacts every [`PiiKind`].
+    pub fn new() -> Self {
+        Self::only(PiiKind::ALL)
+    }
+
+    /// Create a filter that only redacts the given kinds of information.
+    pub fn only(kinds: impl IntoIterator<Item = PiiKind>) -> Self {
+        Self {
+            detectors: kinds
+                .into_iter()
+                .map(|kind| {
+                    let regex = Regex::new(kind.pattern()).expect("PII patterns are valid");
+                    (kind, regex)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl OutputFilter for PiiFilter {
+    fn name(&self) -> &str {
+        "pii"
+    }
+
+    fn check(&self, text: &str) -> FilterVerdict {
+        let mut output = text.to_string();
+        // Credit cards are checked before phone numbers so long digit runs get the more specific label
+        let mut detectors: Vec<_> = self.detectors.iter().collect();
+        detectors.sort_by_key(|(kind, _)| *kind != PiiKind::CreditCard);
+        for (kind, regex) in detectors {
+            output = regex
+                .replace_all(&output, |captures: &regex::Captures| {
+                    let matched = &captures[0];
+                    if *kind == PiiKind::CreditCard && !luhn_valid(matched) {
+                        matched.to_string()
+                    } else {
+                        kind.label().to_string()
+                    }
+                })
+                .into_owned();
+        }
+        if output == text {
+            FilterVerdict::Allow
+        } else {
+            FilterVerdict::Rewrite(output)
+        }
+    }
+}
+
+fn luhn_valid(number: &str) -> bool {
+    let digits: Vec<u32> = number.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match (i % 2 == 1, digit * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => *digit,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// An [`OutputFilter`] that limits the number of links in a response. Links past the limit are replaced with
+/// `[link removed]`.
+#[derive(Debug, Clone)]
+pub struct MaxLinksFilter {
+    max_links: usize,
+    link: Regex,
+    block: bool,
+}
+
+impl MaxLinksFilter {
+    /// Create a filter that allows up to `max_links` links in each response.
+    pub fn new(max_links: usize) -> Self {
+        Self {
+            max_links,
+            link: Regex::new(r"(?i)\b(?:https?://|www\.)[^\s<>()\[\]]+").expect("valid pattern"),
+            block: false,
+        }
+    }
+
+    /// Block the whole response if it has too many links instead of removing the extra links.
+    pub fn blocking(mut self) -> Self {
+        self.block = true;
+        self
+    }
+}
+
+impl OutputFilter for MaxLinksFilter {
+    fn name(&self) -> &str {
+        "max links"
+    }
+
+    fn check(&self, text: &str) -> FilterVerdict {
+        let links = self.link.find_iter(text).count();
+        if links <= self.max_links {
+            return FilterVerdict::Allow;
+        }
+        if self.block {
+            return FilterVerdict::Block(format!(
+                "{links} links is more than the limit of {}",
+                self.max_links
+            ));
+        }
+        let mut seen = 0;
+        let output = self.link.replace_all(text, |captures: &regex::Captures| {
+            seen += 1;
+            if seen > self.max_links {
+                "[link removed]".to_string()
+            } else {
+                captures[0].to_string()
+            }
+        });
+        FilterVerdict::Rewrite(output.into_owned())
+    }
+}
+
+fn replace_all(text: &str, patterns: &[Regex], replacement: &str) -> FilterVerdict {
+    let mut output = text.to_string();
+    for pattern in patterns {
+        output = pattern
+            .replace_all(&output, regex::NoExpand(replacement))
+            .into_owned();
+    }
+    if output == text {
+        FilterVerdict::Allow
+    } else {
+        FilterVerdict::Rewrite(output)
+    }
+}
+
+#[test]
+fn guardrails_filter_chain() {
+    let guardrails = Guardrails::new(())
+        .with_filter(PiiFilter::new())
+        .with_filter(MaxLinksFilter::new(1))
+        .with_filter(DenyListFilter::new().with_word("forbidden").blocking());
+
+    let (output, events) = guardrails.filter_text(
+        "Call 555-123-4567 or pay with 4111 1111 1111 1111. See https://a.com and https://b.com",
+    );
+    assert_eq!(
+        output,
+        "Call [phone number] or pay with [credit card]. See https://a.com and [link removed]"
+    );
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].filter, "pii");
+    assert_eq!(events[1].original, events[0].output);
+
+    let (output, events) = guardrails.filter_text("The forbidden answer");
+    assert_eq!(output, "Sorry, I can't help with that.");
+    assert!(matches!(
+        events.as_slice(),
+        [GuardrailEvent {
+            action: GuardrailAction::Blocked { .. },
+            ..
+        }]
+    ));
+
+    let (output, events) = guardrails.filter_text("Nothing to change");
+    assert_eq!(output, "Nothing to change");
+    assert!(events.is_empty());
+}
@@ -3,15 +3,36 @@ use crate::ModelConstraints;
 use futures_util::Future;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::path::Path;
 
 mod ext;
 pub use ext::*;
 mod task;
 pub use task::*;
+mod code_edit;
+pub use code_edit::*;
+mod title;
+pub use title::*;
 mod chat_builder;
 pub use chat_builder::*;
 mod boxed;
 pub use boxed::*;
+mod reasoning;
+pub use reasoning::*;
+mod guardrails;
+pub use guardrails::*;
+#[cfg(feature = "serde")]
+mod recorder;
+#[cfg(feature = "serde")]
+pub use recorder::*;
+#[cfg(feature = "serde")]
+mod transcript;
+#[cfg(feature = "serde")]
+pub use transcript::*;
+#[cfg(feature = "serde")]
+mod tools;
+#[cfg(feature = "serde")]
+pub use tools::*;
 
 /// A trait for creating a chat session. While it the core trait
 /// every chat session implementation implements, most methods to use models that implement
@@ -297,6 +318,8 @@ pub enum MessageType {
 pub struct ChatMessage {
     role: MessageType,
     content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<MessageImage>,
 }
 
 impl ChatMessage {
@@ -316,9 +339,32 @@ impl ChatMessage {
         Self {
             role,
             content: contents.to_string(),
+            images: Vec::new(),
         }
     }
 
+    /// Attach an image to the message. Only models with vision support can read images.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # fn main() -> std::io::Result<()> {
+    /// let message = ChatMessage::new(MessageType::UserMessage, "What is in this picture?")
+    ///     .with_image(MessageImage::from_path("cat.png")?);
+    /// assert_eq!(message.images().len(), 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_image(mut self, image: impl Into<MessageImage>) -> Self {
+        self.images.push(image.into());
+        self
+    }
+
+    /// Returns the images attached to the message.
+    pub fn images(&self) -> &[MessageImage] {
+        &self.images
+    }
+
     /// Returns the type of the chat message.
     ///
     /// # Example
@@ -350,6 +396,50 @@ impl ChatMessage {
     }
 }
 
+/// An image attached to a [`ChatMessage`]. The image is kept in its encoded format (like PNG or JPEG) and decoded by
+/// the model.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageImage {
+    bytes: Vec<u8>,
+}
+
+impl MessageImage {
+    /// Create an image from the bytes of an encoded image file.
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            bytes: bytes.into(),
+        }
+    }
+
+    /// Read an image file.
+    pub fn from_path(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self::new(std::fs::read(path)?))
+    }
+
+    /// Get the bytes of the encoded image.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl std::fmt::Debug for MessageImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MessageImage({} bytes)", self.bytes.len())
+    }
+}
+
+impl From<Vec<u8>> for MessageImage {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<&[u8]> for MessageImage {
+    fn from(bytes: &[u8]) -> Self {
+        Self::new(bytes)
+    }
+}
+
 /// A trait for types that can be converted into a chat message.
 ///
 /// # Example
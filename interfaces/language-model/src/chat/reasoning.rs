@@ -0,0 +1,282 @@
+use futures_util::{Future, Stream, StreamExt};
+use std::collections::VecDeque;
+use std::future::IntoFuture;
+use std::pin::Pin;
+use std::task::Poll;
+
+use super::{ChatModel, ChatResponseBuilder, CreateChatSession};
+use crate::{GenerationParameters, NoConstraints};
+
+const OPEN_TAG: &str = "<think>";
+const CLOSE_TAG: &str = "</think>";
+
+/// A piece of the response of a reasoning model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReasoningChunk {
+    /// Text from inside the `<think>...</think>` block.
+    Reasoning(String),
+    /// Text from the final answer after the reasoning.
+    Answer(String),
+}
+
+/// The response of a reasoning model split into the reasoning trace and the final answer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReasoningResponse {
+    /// The text the model generated inside the `<think>...</think>` block.
+    pub reasoning: String,
+    /// The final answer after the reasoning.
+    pub answer: String,
+}
+
+impl ReasoningResponse {
+    /// Split the complete text of a response into the reasoning and the answer.
+    pub fn from_text(text: &str) -> Self {
+        let mut splitter = ThinkTagSplitter::new();
+        let mut chunks = splitter.push(text);
+        chunks.extend(splitter.finish());
+        let mut response = Self::default();
+        for chunk in chunks {
+            match chunk {
+                ReasoningChunk::Reasoning(text) => response.reasoning += &text,
+                ReasoningChunk::Answer(text) => response.answer += &text,
+            }
+        }
+        response
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SplitterState {
+    /// Waiting to see if the response starts with an open tag
+    Start,
+    Reasoning,
+    /// Skipping the whitespace between the close tag and the answer
+    AfterReasoning,
+    Answer,
+}
+
+/// Splits text streamed from a reasoning model like DeepSeek R1 into [`ReasoningChunk`]s. A response that does not
+/// start with `<think>` is treated as an answer without reasoning.
+#[derive(Debug, Clone)]
+pub struct ThinkTagSplitter {
+    state: SplitterState,
+    /// Text that may be the start of a tag
+    buffer: String,
+}
+
+impl Default for ThinkTagSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThinkTagSplitter {
+    /// Create a new splitter for the start of a response.
+    pub fn new() -> Self {
+        Self {
+            state: SplitterState::Start,
+            buffer: String::new(),
+        }
+    }
+
+    /// Add the next text of the response and get the chunks that are complete.
+    pub fn push(&mut self, text: &str) -> Vec<ReasoningChunk> {
+        self.buffer.push_str(text);
+        let mut chunks = Vec::new();
+        loop {
+            match self.state {
+                SplitterState::Start => {
+                    let trimmed = self.buffer.trim_start();
+                    if let Some(reasoning) = trimmed.strip_prefix(OPEN_TAG) {
+                        self.buffer = reasoning.to_string();
+                        self.state = SplitterState::Reasoning;
+                    } else if OPEN_TAG.starts_with(trimmed) {
+                        break;
+                    } else {
+                        self.state = SplitterState::Answer;
+                    }
+                }
+                SplitterState::Reasoning => {
+                    if let Some(end) = self.buffer.find(CLOSE_TAG) {
+                        let reasoning = self.buffer[..end].to_string();
+                        if !reasoning.is_empty() {
+                            chunks.push(ReasoningChunk::Reasoning(reasoning));
+                        }
+                        self.buffer.drain(..end + CLOSE_TAG.len());
+                        self.state = SplitterState::AfterReasoning;
+                    } else {
+                        // Hold back anything that could be the start of the close tag
+                        let held = partial_tag_len(&self.buffer, CLOSE_TAG);
+                        let reasoning: String =
+                            self.buffer.drain(..self.buffer.len() - held).collect();
+                        if !reasoning.is_empty() {
+                            chunks.push(ReasoningChunk::Reasoning(reasoning));
+                        }
+                        break;
+                    }
+                }
+                SplitterState::AfterReasoning => {
+                    let trimmed = self.buffer.trim_start();
+                    if trimmed.is_empty() {
+                        self.buffer.clear();
+                        break;
+                    }
+                    self.buffer = trimmed.to_string();
+                    self.state = SplitterState::Answer;
+                }
+                SplitterState::Answer => {
+                    if !self.buffer.is_empty() {
+                        chunks.push(ReasoningChunk::Answer(std::mem::take(&mut self.buffer)));
+                    }
+                    break;
+                }
+            }
+        }
+        chunks
+    }
+
+    /// Get the text that was held back once the response is complete.
+    pub fn finish(&mut self) -> Vec<ReasoningChunk> {
+        let remaining = std::mem::take(&mut self.buffer);
+        let chunk = match self.state {
+            SplitterState::Start if !remaining.trim().is_empty() => {
+                Some(ReasoningChunk::Answer(remaining))
+            }
+            // The response ended before the reasoning was closed
+            SplitterState::Reasoning if !remaining.is_empty() => {
+                Some(ReasoningChunk::Reasoning(remaining))
+            }
+            _ => None,
+        };
+        self.state = SplitterState::Answer;
+        chunk.into_iter().collect()
+    }
+}
+
+/// Get the length of the longest suffix of the text that is a prefix of the tag.
+fn partial_tag_len(text: &str, tag: &str) -> usize {
+    (1..tag.len())
+        .rev()
+        .find(|&len| text.ends_with(&tag[..len]))
+        .unwrap_or(0)
+}
+
+/// A chat response from a reasoning model that separates the reasoning from the answer. This is returned by
+/// [`ChatResponseBuilder::split_reasoning`].
+///
+/// The response can be streamed as [`ReasoningChunk`]s or awaited to get a [`ReasoningResponse`].
+pub struct ReasoningResponseBuilder<'a, M: CreateChatSession, Sampler = GenerationParameters> {
+    response: ChatResponseBuilder<'a, M, NoConstraints, Sampler>,
+    splitter: ThinkTagSplitter,
+    queued: VecDeque<ReasoningChunk>,
+    finished: bool,
+}
+
+impl<'a, M: CreateChatSession, Sampler> ChatResponseBuilder<'a, M, NoConstraints, Sampler> {
+    /// Split the response into the reasoning inside the `<think>...</think>` block and the final answer. This is
+    /// useful for reasoning models like the DeepSeek R1 distills.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::deepseek_r1_distill_qwen_7b())
+    ///     .build()
+    ///     .await
+    ///     .unwrap();
+    /// let mut chat = model.chat();
+    /// let response = chat("What is the 10th prime number?")
+    ///     .split_reasoning()
+    ///     .await
+    ///     .unwrap();
+    /// println!("Reasoning: {}", response.reasoning);
+    /// println!("Answer: {}", response.answer);
+    /// # }
+    /// ```
+    pub fn split_reasoning(self) -> ReasoningResponseBuilder<'a, M, Sampler> {
+        ReasoningResponseBuilder {
+            response: self,
+            splitter: ThinkTagSplitter::new(),
+            queued: VecDeque::new(),
+            finished: false,
+        }
+    }
+}
+
+impl<M, Sampler> Stream for ReasoningResponseBuilder<'_, M, Sampler>
+where
+    Sampler: Send + Unpin + 'static,
+    M: ChatModel<Sampler> + Send + Sync + Clone + Unpin + 'static,
+    M::ChatSession: Send + Sync + Unpin + 'static,
+    M::Error: Send + Sync + Unpin,
+{
+    type Item = ReasoningChunk;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let myself = Pin::get_mut(self);
+        loop {
+            if let Some(chunk) = myself.queued.pop_front() {
+                return Poll::Ready(Some(chunk));
+            }
+            if myself.finished {
+                return Poll::Ready(None);
+            }
+            match myself.response.poll_next_unpin(cx) {
+                Poll::Ready(Some(text)) => myself.queued.extend(myself.splitter.push(&text)),
+                Poll::Ready(None) => {
+                    myself.queued.extend(myself.splitter.finish());
+                    myself.finished = true;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl<'a, M, Sampler> IntoFuture for ReasoningResponseBuilder<'a, M, Sampler>
+where
+    Sampler: Send + Unpin + 'static,
+    M: ChatModel<Sampler> + Send + Sync + Unpin + Clone + 'static,
+    M::ChatSession: Clone + Send + Sync + Unpin + 'static,
+{
+    type Output = Result<ReasoningResponse, M::Error>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            let text = self.response.await?;
+            Ok(ReasoningResponse::from_text(&text))
+        })
+    }
+}
+
+#[test]
+fn split_think_tags_across_tokens() {
+    let mut splitter = ThinkTagSplitter::new();
+    let mut chunks = Vec::new();
+    for token in ["<th", "ink>\nThe", " answer is 4</", "think>", "\n\n", "4"] {
+        chunks.extend(splitter.push(token));
+    }
+    chunks.extend(splitter.finish());
+    assert_eq!(
+        chunks,
+        [
+            ReasoningChunk::Reasoning("\nThe".to_string()),
+            ReasoningChunk::Reasoning(" answer is 4".to_string()),
+            ReasoningChunk::Answer("4".to_string()),
+        ]
+    );
+
+    assert_eq!(
+        ReasoningResponse::from_text("Just an answer"),
+        ReasoningResponse {
+            reasoning: String::new(),
+            answer: "Just an answer".to_string(),
+        }
+    );
+}
@@ -0,0 +1,445 @@
+use super::{ChatMessage, ChatModel, ChatSession, CreateChatSession};
+use crate::{GenerationParameters, SamplingMethod, StopSequences};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A [`ChatModel`] wrapper that records every request and response to an append-only file. Each line of the file is a
+/// JSON [`ChatRecord`] with the prompt, sampling config, output, the model id and a hash of the session state.
+///
+/// The records can be re-run against another model with [`ChatReplayer`] to catch prompt regressions when you
+/// upgrade models.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new_chat().await.unwrap();
+///     let llm = ChatRecorder::new(llm, "chat-log.jsonl")
+///         .unwrap()
+///         .with_model_id("llama-3.1-8b-chat-q4");
+///     let mut chat = llm.chat();
+///     chat("What is the capital of France?").await.unwrap();
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ChatRecorder<M> {
+    model: M,
+    model_id: String,
+    file: Arc<Mutex<File>>,
+}
+
+impl<M> ChatRecorder<M> {
+    /// Wrap a model and append records to the file at the given path. The file is created if it doesn't exist.
+    pub fn new(model: M, path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            model,
+            model_id: "unknown".to_string(),
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Set the id of the model that is stored in each record. The id should change when the weights, quantization or
+    /// chat template of the model change.
+    pub fn with_model_id(mut self, model_id: impl ToString) -> Self {
+        self.model_id = model_id.to_string();
+        self
+    }
+
+    /// Get the wrapped model.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    fn record(&self, record: &ChatRecord) {
+        let result = serde_json::to_string(record).map(|mut line| {
+            line.push('\n');
+            let mut file = self.file.lock().unwrap();
+            file.write_all(line.as_bytes())
+        });
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => tracing::error!("Failed to write chat record: {err}"),
+            Err(err) => tracing::error!("Failed to serialize chat record: {err}"),
+        }
+    }
+}
+
+impl<M: CreateChatSession> CreateChatSession for ChatRecorder<M> {
+    type Error = M::Error;
+    type ChatSession = M::ChatSession;
+
+    fn new_chat_session(&self) -> Result<Self::ChatSession, Self::Error> {
+        self.model.new_chat_session()
+    }
+}
+
+impl<M> ChatModel<GenerationParameters> for ChatRecorder<M>
+where
+    M: ChatModel<GenerationParameters> + Send + Sync,
+    M::ChatSession: Send,
+{
+    fn add_messages_with_callback<'a>(
+        &'a self,
+        session: &'a mut Self::ChatSession,
+        messages: &[ChatMessage],
+        sampler: GenerationParameters,
+        mut on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+        let messages = messages.to_vec();
+        let sampling = RecordedSampling::from(&sampler);
+        let history = session.history();
+        let session_hash = session.to_bytes().ok().map(|bytes| stable_hash(&bytes));
+        let output = Arc::new(Mutex::new(String::new()));
+        async move {
+            let on_token = {
+                let output = output.clone();
+                move |token: String| {
+                    output.lock().unwrap().push_str(&token);
+                    on_token(token)
+                }
+            };
+            self.model
+                .add_messages_with_callback(session, &messages, sampler, on_token)
+                .await?;
+            let output = std::mem::take(&mut *output.lock().unwrap());
+            self.record(&ChatRecord {
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs())
+                    .unwrap_or_default(),
+                model_id: self.model_id.clone(),
+                model_id_hash: stable_hash(self.model_id.as_bytes()),
+                session_hash,
+                history,
+                messages,
+                sampling,
+                output,
+            });
+            Ok(())
+        }
+    }
+}
+
+/// A single request and response recorded by a [`ChatRecorder`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatRecord {
+    /// The unix time in seconds the response finished.
+    pub timestamp: u64,
+    /// The id of the model that generated the response.
+    pub model_id: String,
+    /// A stable hash of the model id set with [`ChatRecorder::with_model_id`]. This only identifies the model as
+    /// well as the id does; it is not a hash of the weights.
+    #[serde(alias = "model_hash")]
+    pub model_id_hash: u64,
+    /// A stable hash of the serialized session before the messages were added, or `None` if the session could not
+    /// be serialized.
+    pub session_hash: Option<u64>,
+    /// The messages that were already in the session.
+    pub history: Vec<ChatMessage>,
+    /// The new messages that were added to the session.
+    pub messages: Vec<ChatMessage>,
+    /// The sampling config used to generate the response.
+    pub sampling: RecordedSampling,
+    /// The response of the model.
+    pub output: String,
+}
+
+/// The sampling config of a [`ChatRecord`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedSampling {
+    /// The temperature.
+    pub temperature: f32,
+    /// The tau value for mirostat sampling.
+    pub tau: f32,
+    /// The eta value for mirostat sampling.
+    pub eta: f32,
+    /// The mu value for mirostat sampling.
+    pub mu: f32,
+    /// The top p value.
+    pub top_p: f64,
+    /// The top k value.
+    pub top_k: u32,
+    /// The method used to pick each token.
+    #[serde(default)]
+    pub sampling_method: SamplingMethod,
+    /// The repetition penalty.
+    pub repetition_penalty: f32,
+    /// The number of tokens the repetition penalty is applied to.
+    pub repetition_penalty_range: u32,
+    /// The frequency penalty.
+    #[serde(default)]
+    pub frequency_penalty: f32,
+    /// The presence penalty.
+    #[serde(default)]
+    pub presence_penalty: f32,
+    /// The maximum number of tokens to generate.
+    pub max_length: u32,
+    /// The string generation stops on.
+    pub stop_on: Option<String>,
+    /// The stop sequences generation stops on.
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    /// Whether the stop sequence that fired was removed from the output.
+    #[serde(default = "default_trim_stop_sequences")]
+    pub trim_stop_sequences: bool,
+    /// Whether the boundary between the prompt and the generated text was healed.
+    #[serde(default)]
+    pub token_healing: bool,
+    /// The seed of the sampler.
+    pub seed: Option<u64>,
+}
+
+impl From<&GenerationParameters> for RecordedSampling {
+    fn from(parameters: &GenerationParameters) -> Self {
+        Self {
+            temperature: parameters.temperature,
+            tau: parameters.tau,
+            eta: parameters.eta,
+            mu: parameters.mu,
+            top_p: parameters.top_p,
+            top_k: parameters.top_k,
+            sampling_method: parameters.sampling_method,
+            repetition_penalty: parameters.repetition_penalty,
+            repetition_penalty_range: parameters.repetition_penalty_range,
+            frequency_penalty: parameters.frequency_penalty,
+            presence_penalty: parameters.presence_penalty,
+            max_length: parameters.max_length,
+            stop_on: parameters.stop_on.clone(),
+            stop_sequences: parameters
+                .stop_sequences
+                .as_ref()
+                .map(|stops| stops.sequences().to_vec())
+                .unwrap_or_default(),
+            trim_stop_sequences: parameters
+                .stop_sequences
+                .as_ref()
+                .is_none_or(|stops| stops.trim()),
+            token_healing: parameters.token_healing,
+            seed: parameters.seed,
+        }
+    }
+}
+
+impl From<&RecordedSampling> for GenerationParameters {
+    fn from(sampling: &RecordedSampling) -> Self {
+        GenerationParameters::new()
+            .with_temperature(sampling.temperature)
+            .with_tau(sampling.tau)
+            .with_eta(sampling.eta)
+            .with_mu(sampling.mu)
+            .with_top_p(sampling.top_p)
+            .with_top_k(sampling.top_k)
+            .with_sampling_method(sampling.sampling_method)
+            .with_repetition_penalty(sampling.repetition_penalty)
+            .with_repetition_penalty_range(sampling.repetition_penalty_range)
+            .with_frequency_penalty(sampling.frequency_penalty)
+            .with_presence_penalty(sampling.presence_penalty)
+            .with_max_length(sampling.max_length)
+            .with_stop_on(sampling.stop_on.clone())
+            .with_stop_sequences((!sampling.stop_sequences.is_empty()).then(|| {
+                StopSequences::new(sampling.stop_sequences.iter().cloned())
+                    .with_trim(sampling.trim_stop_sequences)
+            }))
+            .with_token_healing(sampling.token_healing)
+            .with_seed(sampling.seed)
+    }
+}
+
+fn default_trim_stop_sequences() -> bool {
+    true
+}
+
+/// An error that can occur when loading records with [`ChatReplayer::open`].
+#[derive(Debug, thiserror::Error)]
+pub enum ChatReplayError {
+    /// An error reading the file.
+    #[error("Failed to read chat records: {0}")]
+    Io(#[from] std::io::Error),
+    /// A line in the file was not a valid record.
+    #[error("Invalid chat record on line {line}: {error}")]
+    InvalidRecord {
+        /// The line number of the invalid record, starting at 1.
+        line: usize,
+        /// The error from parsing the record.
+        error: serde_json::Error,
+    },
+}
+
+/// Re-runs records from a [`ChatRecorder`] file against a model and compares the outputs.
+///
+/// Outputs will only match exactly if the records were generated with a fixed seed or greedy sampling.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let replayer = ChatReplayer::open("chat-log.jsonl").unwrap();
+///     let new_model = Llama::builder()
+///         .with_source(LlamaSource::llama_3_2_3b_chat())
+///         .build()
+///         .await
+///         .unwrap();
+///     let results = replayer.replay(&new_model).await.unwrap();
+///     for result in results.iter().filter(|result| !result.matches()) {
+///         println!("expected: {}", result.record().output);
+///         println!("found:    {}", result.output());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ChatReplayer {
+    records: Vec<ChatRecord>,
+}
+
+impl ChatReplayer {
+    /// Load the records from a file written by a [`ChatRecorder`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ChatReplayError> {
+        let file = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+        for (index, line) in file.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record =
+                serde_json::from_str(&line).map_err(|error| ChatReplayError::InvalidRecord {
+                    line: index + 1,
+                    error,
+                })?;
+            records.push(record);
+        }
+        Ok(Self { records })
+    }
+
+    /// Create a replayer from records that are already loaded.
+    pub fn from_records(records: impl IntoIterator<Item = ChatRecord>) -> Self {
+        Self {
+            records: records.into_iter().collect(),
+        }
+    }
+
+    /// Get the loaded records.
+    pub fn records(&self) -> &[ChatRecord] {
+        &self.records
+    }
+
+    /// Re-run every record against the model in order. Each record is run in a new session with the recorded
+    /// history and sampling config.
+    pub async fn replay<M>(&self, model: &M) -> Result<Vec<ReplayResult>, M::Error>
+    where
+        M: ChatModel<GenerationParameters>,
+    {
+        let mut results = Vec::with_capacity(self.records.len());
+        for record in &self.records {
+            let mut session = model.new_chat_session()?;
+            let messages: Vec<_> = record
+                .history
+                .iter()
+                .chain(&record.messages)
+                .cloned()
+                .collect();
+            let output = Arc::new(Mutex::new(String::new()));
+            model
+                .add_messages_with_callback(
+                    &mut session,
+                    &messages,
+                    GenerationParameters::from(&record.sampling),
+                    {
+                        let output = output.clone();
+                        move |token| {
+                            output.lock().unwrap().push_str(&token);
+                            Ok(())
+                        }
+                    },
+                )
+                .await?;
+            let output = std::mem::take(&mut *output.lock().unwrap());
+            results.push(ReplayResult {
+                record: record.clone(),
+                output,
+            });
+        }
+        Ok(results)
+    }
+}
+
+/// The result of replaying a single [`ChatRecord`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayResult {
+    record: ChatRecord,
+    output: String,
+}
+
+impl ReplayResult {
+    /// Get the record that was replayed.
+    pub fn record(&self) -> &ChatRecord {
+        &self.record
+    }
+
+    /// Get the output of the new model.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Check if the new output is exactly the same as the recorded output.
+    pub fn matches(&self) -> bool {
+        self.output == self.record.output
+    }
+}
+
+/// A 64 bit FNV-1a hash. This is stable across versions of Rust, unlike the standard library hasher, so records
+/// from different builds can be compared.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[test]
+fn chat_records_round_trip() {
+    let path =
+        std::env::temp_dir().join(format!("kalosm-chat-records-{}.jsonl", std::process::id()));
+    let record = ChatRecord {
+        timestamp: 0,
+        model_id: "test".to_string(),
+        model_id_hash: stable_hash(b"test"),
+        session_hash: None,
+        history: vec![ChatMessage::new(
+            super::MessageType::SystemPrompt,
+            "Be brief",
+        )],
+        messages: vec![ChatMessage::new(super::MessageType::UserMessage, "Hi")],
+        sampling: RecordedSampling::from(&GenerationParameters::new().with_seed(42)),
+        output: "Hello!".to_string(),
+    };
+    {
+        let mut file = File::create(&path).unwrap();
+        for _ in 0..2 {
+            writeln!(file, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+        }
+    }
+    let replayer = ChatReplayer::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(replayer.records(), &[record.clone(), record.clone()]);
+
+    // Records written before the field was renamed still load
+    let old_record = serde_json::to_string(&record)
+        .unwrap()
+        .replace("model_id_hash", "model_hash");
+    let old_record: ChatRecord = serde_json::from_str(&old_record).unwrap();
+    assert_eq!(old_record, record);
+    assert_eq!(
+        GenerationParameters::from(&record.sampling).seed(),
+        Some(42)
+    );
+}
@@ -0,0 +1,378 @@
+use std::borrow::Cow;
+
+use kalosm_sample::{CreateParserState, ParseResult, ParseStatus, Parser};
+
+use super::Chat;
+use super::ChatResponseBuilder;
+use super::CreateChatSession;
+use super::MessageType;
+
+/// The maximum number of characters of each message that is included when generating a title.
+const MAX_MESSAGE_CHARACTERS: usize = 1000;
+
+/// A short title and topic tags for a conversation. Created with [`Chat::generate_title`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatTitle {
+    title: String,
+    tags: Vec<String>,
+}
+
+impl ChatTitle {
+    /// Get the title of the conversation.
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Get the lowercase topic tags of the conversation.
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+}
+
+/// A parser for a [`ChatTitle`] in the format `Title: <title>\nTags: <tag>, <tag>\n`. Tags are lowercase ascii
+/// letters, numbers and dashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleParser {
+    max_length: usize,
+    max_tags: usize,
+    max_tag_length: usize,
+    allow_quotes: bool,
+    allow_emoji: bool,
+}
+
+impl Default for TitleParser {
+    fn default() -> Self {
+        Self {
+            max_length: 50,
+            max_tags: 3,
+            max_tag_length: 20,
+            allow_quotes: false,
+            allow_emoji: false,
+        }
+    }
+}
+
+impl TitleParser {
+    /// Create a new title parser with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of characters in the title. (Defaults to 50)
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length.max(1);
+        self
+    }
+
+    /// Set the maximum number of topic tags. Setting this to zero skips the tags. (Defaults to 3)
+    pub fn with_max_tags(mut self, max_tags: usize) -> Self {
+        self.max_tags = max_tags;
+        self
+    }
+
+    /// Set the maximum number of characters in each tag. (Defaults to 20)
+    pub fn with_max_tag_length(mut self, max_tag_length: usize) -> Self {
+        self.max_tag_length = max_tag_length.max(1);
+        self
+    }
+
+    /// Allow quotation marks in the title. (Defaults to false)
+    pub fn with_quotes(mut self, allow_quotes: bool) -> Self {
+        self.allow_quotes = allow_quotes;
+        self
+    }
+
+    /// Allow emoji in the title. (Defaults to false)
+    pub fn with_emoji(mut self, allow_emoji: bool) -> Self {
+        self.allow_emoji = allow_emoji;
+        self
+    }
+
+    fn allowed_in_title(&self, title: &str, char: char) -> bool {
+        if char.is_control() || (title.is_empty() && char.is_whitespace()) {
+            return false;
+        }
+        let quote = matches!(char, '"' | '`' | '“' | '”' | '„' | '«' | '»')
+            || (title.is_empty() && matches!(char, '\'' | '‘' | '’'));
+        if quote && !self.allow_quotes {
+            return false;
+        }
+        if is_emoji(char) && !self.allow_emoji {
+            return false;
+        }
+        true
+    }
+}
+
+/// Check if a character is in one of the common emoji blocks.
+fn is_emoji(char: char) -> bool {
+    matches!(
+        char as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0xFE00..=0xFE0F | 0x200D
+    )
+}
+
+/// An error that can occur when parsing a [`ChatTitle`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleParseError;
+
+impl std::fmt::Display for TitleParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TitleParseError")
+    }
+}
+
+impl std::error::Error for TitleParseError {}
+
+const TITLE_PREFIX: &str = "Title: ";
+const TAGS_PREFIX: &str = "Tags: ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TitleProgress {
+    TitlePrefix(usize),
+    Title,
+    TagsPrefix(usize),
+    Tag,
+    TagSeparator,
+}
+
+/// The state of the [`TitleParser`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleParserState {
+    progress: TitleProgress,
+    /// The bytes of a character that is not complete yet
+    partial_char: Vec<u8>,
+    title: String,
+    tag: String,
+    tags: Vec<String>,
+}
+
+impl CreateParserState for TitleParser {
+    fn create_parser_state(&self) -> <Self as Parser>::PartialState {
+        TitleParserState {
+            progress: TitleProgress::TitlePrefix(0),
+            partial_char: Vec::new(),
+            title: String::new(),
+            tag: String::new(),
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl Parser for TitleParser {
+    type Output = ChatTitle;
+    type PartialState = TitleParserState;
+
+    fn parse<'a>(
+        &self,
+        state: &Self::PartialState,
+        input: &'a [u8],
+    ) -> ParseResult<ParseStatus<'a, Self::PartialState, Self::Output>> {
+        let mut state = state.clone();
+        let mut iter = input.iter();
+        while let Some(&byte) = iter.next() {
+            match state.progress {
+                TitleProgress::TitlePrefix(matched) | TitleProgress::TagsPrefix(matched) => {
+                    let prefix = match state.progress {
+                        TitleProgress::TitlePrefix(_) => TITLE_PREFIX,
+                        _ => TAGS_PREFIX,
+                    };
+                    if prefix.as_bytes()[matched] != byte {
+                        kalosm_sample::bail!(TitleParseError);
+                    }
+                    state.progress = match (&state.progress, matched + 1 == prefix.len()) {
+                        (TitleProgress::TitlePrefix(_), true) => TitleProgress::Title,
+                        (TitleProgress::TitlePrefix(_), false) => {
+                            TitleProgress::TitlePrefix(matched + 1)
+                        }
+                        (_, true) => TitleProgress::Tag,
+                        (_, false) => TitleProgress::TagsPrefix(matched + 1),
+                    };
+                }
+                TitleProgress::Title => {
+                    if byte == b'\n' {
+                        if state.title.trim().is_empty() || !state.partial_char.is_empty() {
+                            kalosm_sample::bail!(TitleParseError);
+                        }
+                        state.title = state.title.trim_end().to_string();
+                        if self.max_tags == 0 {
+                            return Ok(ParseStatus::Finished {
+                                result: ChatTitle {
+                                    title: state.title,
+                                    tags: state.tags,
+                                },
+                                remaining: iter.as_slice(),
+                            });
+                        }
+                        state.progress = TitleProgress::TagsPrefix(0);
+                        continue;
+                    }
+                    state.partial_char.push(byte);
+                    let char = match std::str::from_utf8(&state.partial_char) {
+                        Ok(char) => char.chars().next().unwrap(),
+                        // Wait for the rest of the character
+                        Err(err) if err.error_len().is_none() => continue,
+                        Err(_) => kalosm_sample::bail!(TitleParseError),
+                    };
+                    state.partial_char.clear();
+                    if state.title.chars().count() >= self.max_length
+                        || !self.allowed_in_title(&state.title, char)
+                    {
+                        kalosm_sample::bail!(TitleParseError);
+                    }
+                    state.title.push(char);
+                }
+                TitleProgress::Tag => match byte {
+                    b'\n' | b',' if state.tag.is_empty() => kalosm_sample::bail!(TitleParseError),
+                    b'\n' => {
+                        state.tags.push(std::mem::take(&mut state.tag));
+                        return Ok(ParseStatus::Finished {
+                            result: ChatTitle {
+                                title: state.title,
+                                tags: state.tags,
+                            },
+                            remaining: iter.as_slice(),
+                        });
+                    }
+                    b',' => {
+                        if state.tags.len() + 1 >= self.max_tags {
+                            kalosm_sample::bail!(TitleParseError);
+                        }
+                        state.tags.push(std::mem::take(&mut state.tag));
+                        state.progress = TitleProgress::TagSeparator;
+                    }
+                    b'a'..=b'z' | b'0'..=b'9' | b'-' if state.tag.len() < self.max_tag_length => {
+                        state.tag.push(byte as char);
+                    }
+                    _ => kalosm_sample::bail!(TitleParseError),
+                },
+                TitleProgress::TagSeparator => {
+                    if byte != b' ' {
+                        kalosm_sample::bail!(TitleParseError);
+                    }
+                    state.progress = TitleProgress::Tag;
+                }
+            }
+        }
+
+        let required_next = match state.progress {
+            TitleProgress::TitlePrefix(matched) => Cow::Borrowed(&TITLE_PREFIX[matched..]),
+            TitleProgress::TagsPrefix(matched) => Cow::Borrowed(&TAGS_PREFIX[matched..]),
+            TitleProgress::TagSeparator => Cow::Borrowed(" "),
+            _ => Cow::Borrowed(""),
+        };
+        Ok(ParseStatus::Incomplete {
+            new_state: state,
+            required_next,
+        })
+    }
+}
+
+impl<M: CreateChatSession> Chat<M> {
+    /// Generate a short title and topic tags for the conversation. The title is generated in a separate chat with
+    /// the same model, so the conversation is not changed. The title is limited to 50 characters without quotes or
+    /// emoji, with up to 3 tags. Use [`Chat::generate_title_with`] to change the limits.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let model = Llama::new_chat().await.unwrap();
+    ///     let mut chat = model.chat();
+    ///     chat("How do I make sourdough bread?").await.unwrap();
+    ///     let title = chat.generate_title().await.unwrap();
+    ///     println!("{} {:?}", title.title(), title.tags());
+    /// }
+    /// ```
+    pub fn generate_title(&self) -> ChatResponseBuilder<'static, M, TitleParser> {
+        self.generate_title_with(TitleParser::default())
+    }
+
+    /// Generate a title and topic tags for the conversation with a custom [`TitleParser`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let model = Llama::new_chat().await.unwrap();
+    ///     let mut chat = model.chat();
+    ///     chat("How do I make sourdough bread?").await.unwrap();
+    ///     let title = chat
+    ///         .generate_title_with(TitleParser::new().with_max_length(30).with_max_tags(0))
+    ///         .await
+    ///         .unwrap();
+    ///     println!("{}", title.title());
+    /// }
+    /// ```
+    pub fn generate_title_with(
+        &self,
+        parser: TitleParser,
+    ) -> ChatResponseBuilder<'static, M, TitleParser> {
+        let mut prompt = format!(
+            "Write a short title of at most {} characters for the conversation below.",
+            parser.max_length
+        );
+        if parser.max_tags > 0 {
+            prompt += &format!(
+                " Then list up to {} lowercase topic tags separated by commas.",
+                parser.max_tags
+            );
+        }
+        prompt += " Respond in the format:\nTitle: <title>";
+        if parser.max_tags > 0 {
+            prompt += "\nTags: <tag>, <tag>";
+        }
+
+        let mut conversation = String::new();
+        for message in self.messages() {
+            let speaker = match message.role() {
+                MessageType::SystemPrompt => continue,
+                MessageType::UserMessage => "User",
+                MessageType::ModelAnswer => "Assistant",
+            };
+            let content: String = message
+                .content()
+                .chars()
+                .take(MAX_MESSAGE_CHARACTERS)
+                .collect();
+            conversation += &format!("{speaker}: {content}\n");
+        }
+
+        self.with_same_model()
+            .with_system_prompt(prompt)
+            .into_add_message(conversation)
+            .with_constraints(parser)
+    }
+}
+
+#[test]
+fn title_parser() {
+    let parser = TitleParser::new().with_max_length(20).with_max_tags(2);
+    let state = parser.create_parser_state();
+    assert!(parser
+        .parse(&state, "Title: Baking bread 🍞".as_bytes())
+        .is_err());
+    assert!(parser.parse(&state, b"Title: \"Baking\"").is_err());
+    assert!(parser
+        .parse(&state, b"Title: Sourdough basics for beginners")
+        .is_err());
+
+    let (state, required_next) = parser
+        .parse(&state, "Title: Ann's sourdough ".as_bytes())
+        .unwrap()
+        .unwrap_incomplete();
+    assert_eq!(required_next, "");
+    let (state, required_next) = parser.parse(&state, b"\n").unwrap().unwrap_incomplete();
+    assert_eq!(required_next, "Tags: ");
+    assert!(parser.parse(&state, b"Tags: baking, bread, food").is_err());
+    let result = parser
+        .parse(&state, b"Tags: baking, bread\n")
+        .unwrap()
+        .unwrap_finished();
+    assert_eq!(result.title(), "Ann's sourdough");
+    assert_eq!(result.tags(), ["baking", "bread"]);
+}
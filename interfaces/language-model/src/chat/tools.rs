@@ -0,0 +1,495 @@
+use futures_util::stream::FuturesUnordered;
+use futures_util::{Future, FutureExt, StreamExt};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Chat, ChatMessage, ChatModel, IntoChatMessage, MessageType};
+
+/// The future returned by [`Tool::call`].
+pub type ToolFuture<'a> = Pin<
+    Box<dyn Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>,
+>;
+
+/// A tool the model can call with a `<tool_call>` block.
+pub trait Tool: Send + Sync + 'static {
+    /// The name the model uses to call the tool.
+    fn name(&self) -> &str;
+
+    /// Call the tool with the arguments the model generated.
+    fn call(&self, arguments: Value) -> ToolFuture<'_>;
+}
+
+/// A [`Tool`] created from an async function with [`tool_fn`].
+pub struct ToolFn<F> {
+    name: String,
+    function: F,
+}
+
+/// Create a [`Tool`] from an async function that takes the JSON arguments of the call.
+///
+/// # Example
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let weather = tool_fn("weather", |arguments| async move {
+///     let city = arguments["city"].as_str().unwrap_or("Paris");
+///     Ok(format!("It is sunny in {city}"))
+/// });
+/// ```
+pub fn tool_fn<F, Fut>(name: impl ToString, function: F) -> ToolFn<F>
+where
+    F: Fn(Value) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send + 'static,
+{
+    ToolFn {
+        name: name.to_string(),
+        function,
+    }
+}
+
+impl<F, Fut> Tool for ToolFn<F>
+where
+    F: Fn(Value) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send + 'static,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn call(&self, arguments: Value) -> ToolFuture<'_> {
+        Box::pin((self.function)(arguments))
+    }
+}
+
+/// A call to a tool parsed from a `<tool_call>` block in the response of a model.
+///
+/// A call can depend on the result of earlier calls in the same response. Dependencies are listed by their position
+/// in the response in the `depends_on` field, or by replacing an argument with `{"$result": <position>}`, which is
+/// substituted with the text the other call returned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    /// The name of the tool to call.
+    pub name: String,
+    /// The arguments of the call.
+    pub arguments: Value,
+    /// The positions of the calls that must finish before this call starts.
+    pub depends_on: Vec<usize>,
+}
+
+impl ToolCall {
+    /// Create a call to a tool without dependencies.
+    pub fn new(name: impl ToString, arguments: Value) -> Self {
+        Self {
+            name: name.to_string(),
+            arguments,
+            depends_on: Vec::new(),
+        }
+    }
+
+    /// Parse every `<tool_call>` block in the response of a model. Each block holds a JSON object like
+    /// `{"name": "weather", "arguments": {"city": "Paris"}}`.
+    pub fn parse_all(response: &str) -> Result<Vec<Self>, serde_json::Error> {
+        let mut calls = Vec::new();
+        let mut rest = response;
+        while let Some(start) = rest.find("<tool_call>") {
+            rest = &rest[start + "<tool_call>".len()..];
+            let end = rest.find("</tool_call>").unwrap_or(rest.len());
+            let call: Value = serde_json::from_str(rest[..end].trim())?;
+            let depends_on = call["depends_on"]
+                .as_array()
+                .map(|dependencies| {
+                    dependencies
+                        .iter()
+                        .filter_map(|index| index.as_u64())
+                        .map(|index| index as usize)
+                        .collect()
+                })
+                .unwrap_or_default();
+            calls.push(Self {
+                name: call["name"].as_str().unwrap_or_default().to_string(),
+                arguments: call.get("arguments").cloned().unwrap_or(Value::Null),
+                depends_on,
+            });
+            rest = &rest[end..];
+        }
+        Ok(calls)
+    }
+
+    /// Get every call this call depends on, including calls referenced with `{"$result": <position>}`.
+    fn dependencies(&self) -> BTreeSet<usize> {
+        fn references(value: &Value, dependencies: &mut BTreeSet<usize>) {
+            match value {
+                Value::Object(object) => match result_reference(object) {
+                    Some(index) => {
+                        dependencies.insert(index);
+                    }
+                    None => object
+                        .values()
+                        .for_each(|value| references(value, dependencies)),
+                },
+                Value::Array(array) => array
+                    .iter()
+                    .for_each(|value| references(value, dependencies)),
+                _ => {}
+            }
+        }
+        let mut dependencies: BTreeSet<_> = self.depends_on.iter().copied().collect();
+        references(&self.arguments, &mut dependencies);
+        dependencies
+    }
+}
+
+fn result_reference(object: &serde_json::Map<String, Value>) -> Option<usize> {
+    if object.len() != 1 {
+        return None;
+    }
+    object.get("$result")?.as_u64().map(|index| index as usize)
+}
+
+/// Replace every `{"$result": <position>}` in the arguments with the output of that call.
+fn substitute_results(value: &mut Value, outputs: &[Option<String>]) {
+    match value {
+        Value::Object(object) => match result_reference(object) {
+            Some(index) => {
+                *value = Value::String(outputs[index].clone().unwrap_or_default());
+            }
+            None => object
+                .values_mut()
+                .for_each(|value| substitute_results(value, outputs)),
+        },
+        Value::Array(array) => array
+            .iter_mut()
+            .for_each(|value| substitute_results(value, outputs)),
+        _ => {}
+    }
+}
+
+/// An error from one call in [`ToolExecutor::execute`]. Other calls still run when one call fails.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ToolError {
+    /// The model called a tool that is not registered with the executor.
+    #[error("There is no tool named {0}")]
+    UnknownTool(String),
+    /// The tool didn't finish within its timeout.
+    #[error("The tool timed out after {0:?}")]
+    Timeout(Duration),
+    /// The tool returned an error or panicked.
+    #[error("The tool failed: {0}")]
+    Failed(String),
+    /// A call this call depends on failed, so this call was skipped.
+    #[error("The call depends on call {0} which failed")]
+    DependencyFailed(usize),
+    /// The call depends on a call that doesn't exist, on itself or on a cycle of calls.
+    #[error("The call has an invalid dependency on call {0}")]
+    InvalidDependency(usize),
+}
+
+/// The result of one call in [`ToolExecutor::execute`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolResult {
+    /// The name of the tool that was called.
+    pub name: String,
+    /// The text the tool returned or the reason the call failed.
+    pub output: Result<String, ToolError>,
+    /// How long the call took to run.
+    pub duration: Duration,
+}
+
+impl ToolResult {
+    /// Format the results of calls as a user message with a `<tool_response>` block for each call. The blocks are
+    /// in the same order as the calls regardless of which call finished first.
+    pub fn into_message(results: &[ToolResult]) -> ChatMessage {
+        let responses = results
+            .iter()
+            .map(|result| {
+                let content = match &result.output {
+                    Ok(output) => output.clone(),
+                    Err(err) => format!("Error: {err}"),
+                };
+                format!("<tool_response>\n{content}\n</tool_response>")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        ChatMessage::new(MessageType::UserMessage, responses)
+    }
+}
+
+/// Runs the tool calls a model makes. Independent calls run concurrently, calls that depend on other calls wait for
+/// them, and each call has a timeout. A call that fails doesn't stop the other calls.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new_chat().await.unwrap();
+///     let mut chat = llm.chat().with_system_prompt(
+///         "You can call the weather tool with <tool_call>{\"name\": \"weather\", \"arguments\": {\"city\": \"Paris\"}}</tool_call>",
+///     );
+///     let tools = ToolExecutor::new()
+///         .with_tool(tool_fn("weather", |arguments| async move {
+///             Ok(format!("It is sunny in {}", arguments["city"]))
+///         }))
+///         .with_timeout(Duration::from_secs(10));
+///     let answer = tools
+///         .respond(&mut chat, "What is the weather in Paris and Tokyo?")
+///         .await
+///         .unwrap();
+///     println!("{answer}");
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ToolExecutor {
+    tools: HashMap<String, Arc<dyn Tool>>,
+    timeout: Duration,
+    tool_timeouts: HashMap<String, Duration>,
+    max_concurrency: usize,
+    max_rounds: usize,
+}
+
+impl Default for ToolExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ToolExecutor {
+    /// Create an executor without any tools that runs up to 8 calls at a time with a 30 second timeout.
+    pub fn new() -> Self {
+        Self {
+            tools: HashMap::new(),
+            timeout: Duration::from_secs(30),
+            tool_timeouts: HashMap::new(),
+            max_concurrency: 8,
+            max_rounds: 8,
+        }
+    }
+
+    /// Register a tool. A tool with the same name replaces the existing tool.
+    pub fn with_tool(mut self, tool: impl Tool) -> Self {
+        self.tools.insert(tool.name().to_string(), Arc::new(tool));
+        self
+    }
+
+    /// Set the timeout for calls to tools without their own timeout. (Defaults to 30 seconds)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the timeout for calls to one tool.
+    pub fn with_tool_timeout(mut self, name: impl ToString, timeout: Duration) -> Self {
+        self.tool_timeouts.insert(name.to_string(), timeout);
+        self
+    }
+
+    /// Set the maximum number of calls that run at the same time. (Defaults to 8)
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Set the maximum number of times [`ToolExecutor::respond`] feeds tool results back to the model before
+    /// returning the last response. (Defaults to 8)
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    /// Run a set of calls and get a result for each call in the same order as the calls.
+    pub async fn execute(&self, calls: &[ToolCall]) -> Vec<ToolResult> {
+        let mut results: Vec<Option<ToolResult>> = vec![None; calls.len()];
+        let mut outputs: Vec<Option<String>> = vec![None; calls.len()];
+        let mut pending: Vec<(usize, BTreeSet<usize>)> = Vec::new();
+        for (index, call) in calls.iter().enumerate() {
+            let dependencies = call.dependencies();
+            match dependencies
+                .iter()
+                .find(|dependency| **dependency >= calls.len() || **dependency == index)
+            {
+                Some(invalid) => {
+                    results[index] = Some(failed(call, ToolError::InvalidDependency(*invalid)))
+                }
+                None => pending.push((index, dependencies)),
+            }
+        }
+
+        let mut running = FuturesUnordered::new();
+        loop {
+            // Skip calls whose dependencies failed and start calls whose dependencies finished
+            let mut waiting = Vec::new();
+            for (index, dependencies) in pending.drain(..) {
+                let failed_dependency = dependencies.iter().find(|dependency| {
+                    matches!(
+                        &results[**dependency],
+                        Some(ToolResult { output: Err(_), .. })
+                    )
+                });
+                if let Some(dependency) = failed_dependency {
+                    results[index] = Some(failed(
+                        &calls[index],
+                        ToolError::DependencyFailed(*dependency),
+                    ));
+                } else if running.len() < self.max_concurrency
+                    && dependencies
+                        .iter()
+                        .all(|dependency| outputs[*dependency].is_some())
+                {
+                    let mut arguments = calls[index].arguments.clone();
+                    substitute_results(&mut arguments, &outputs);
+                    running.push(self.call(index, &calls[index].name, arguments));
+                } else {
+                    waiting.push((index, dependencies));
+                }
+            }
+            pending = waiting;
+
+            let Some((index, result)) = running.next().await else {
+                break;
+            };
+            if let Ok(output) = &result.output {
+                outputs[index] = Some(output.clone());
+            }
+            results[index] = Some(result);
+        }
+
+        // Anything still pending waits on a cycle of calls
+        for (index, dependencies) in pending {
+            let dependency = dependencies
+                .into_iter()
+                .find(|dependency| results[*dependency].is_none())
+                .unwrap_or(index);
+            results[index] = Some(failed(
+                &calls[index],
+                ToolError::InvalidDependency(dependency),
+            ));
+        }
+
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    async fn call(&self, index: usize, name: &str, arguments: Value) -> (usize, ToolResult) {
+        let start = Instant::now();
+        let output = match self.tools.get(name) {
+            Some(tool) => {
+                let timeout = self
+                    .tool_timeouts
+                    .get(name)
+                    .copied()
+                    .unwrap_or(self.timeout);
+                let call = AssertUnwindSafe(tool.call(arguments)).catch_unwind();
+                match tokio::time::timeout(timeout, call).await {
+                    Ok(Ok(Ok(output))) => Ok(output),
+                    Ok(Ok(Err(err))) => Err(ToolError::Failed(err.to_string())),
+                    Ok(Err(_)) => Err(ToolError::Failed("the tool panicked".to_string())),
+                    Err(_) => Err(ToolError::Timeout(timeout)),
+                }
+            }
+            None => Err(ToolError::UnknownTool(name.to_string())),
+        };
+        if let Err(err) = &output {
+            tracing::warn!("Tool call {index} to {name} failed: {err}");
+        }
+        (
+            index,
+            ToolResult {
+                name: name.to_string(),
+                output,
+                duration: start.elapsed(),
+            },
+        )
+    }
+
+    /// Add a message to the chat and run the tool calls in each response, feeding the results back to the model until
+    /// it responds without calling a tool. Returns the final response.
+    pub async fn respond<M>(
+        &self,
+        chat: &mut Chat<M>,
+        message: impl IntoChatMessage,
+    ) -> Result<String, M::Error>
+    where
+        M: ChatModel + Send + Sync + Unpin + Clone + 'static,
+        M::ChatSession: Clone + Send + Sync + Unpin + 'static,
+    {
+        let mut message = message.into_chat_message();
+        let mut response = String::new();
+        for _ in 0..=self.max_rounds {
+            response = chat.add_message(message).await?;
+            let calls = match ToolCall::parse_all(&response) {
+                Ok(calls) if calls.is_empty() => return Ok(response),
+                Ok(calls) => calls,
+                Err(err) => {
+                    message = ChatMessage::new(
+                        MessageType::UserMessage,
+                        format!(
+                            "<tool_response>\nError: invalid tool call: {err}\n</tool_response>"
+                        ),
+                    );
+                    continue;
+                }
+            };
+            message = ToolResult::into_message(&self.execute(&calls).await);
+        }
+        Ok(response)
+    }
+}
+
+fn failed(call: &ToolCall, error: ToolError) -> ToolResult {
+    ToolResult {
+        name: call.name.clone(),
+        output: Err(error),
+        duration: Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn tool_calls_run_concurrently_in_dependency_order() {
+        let executor = ToolExecutor::new()
+            .with_tool(tool_fn("sleep", |arguments| async move {
+                let millis = arguments["millis"].as_u64().unwrap_or_default();
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                Ok(format!("slept {millis}"))
+            }))
+            .with_tool(tool_fn("echo", |arguments| async move {
+                Ok(arguments["text"].as_str().unwrap_or_default().to_string())
+            }))
+            .with_tool_timeout("sleep", Duration::from_millis(500));
+
+        let calls = ToolCall::parse_all(
+            r#"<tool_call>{"name": "sleep", "arguments": {"millis": 200}}</tool_call>
+    <tool_call>{"name": "sleep", "arguments": {"millis": 200}}</tool_call>
+    <tool_call>{"name": "echo", "arguments": {"text": {"$result": 0}}}</tool_call>
+    <tool_call>{"name": "sleep", "arguments": {"millis": 1000}}</tool_call>
+    <tool_call>{"name": "echo", "arguments": {"text": "skipped"}, "depends_on": [3]}</tool_call>
+    <tool_call>{"name": "search", "arguments": {}}</tool_call>"#,
+        )
+        .unwrap();
+        assert_eq!(calls.len(), 6);
+
+        let start = Instant::now();
+        let results = executor.execute(&calls).await;
+        // The two 200ms calls run at the same time, and the slow call is cut off by its timeout
+        assert!(start.elapsed() < Duration::from_millis(900));
+        assert_eq!(results[0].output, Ok("slept 200".to_string()));
+        assert_eq!(results[2].output, Ok("slept 200".to_string()));
+        assert_eq!(
+            results[3].output,
+            Err(ToolError::Timeout(Duration::from_millis(500)))
+        );
+        assert_eq!(results[4].output, Err(ToolError::DependencyFailed(3)));
+        assert_eq!(
+            results[5].output,
+            Err(ToolError::UnknownTool("search".to_string()))
+        );
+    }
+}
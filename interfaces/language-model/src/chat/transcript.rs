@@ -0,0 +1,199 @@
+use super::{ChatMessage, MessageType};
+use serde_json::Value;
+
+/// An error that can occur when importing a transcript with [`parse_transcript`] or
+/// [`Chat::from_transcript`](super::Chat::from_transcript).
+#[derive(Debug, thiserror::Error)]
+pub enum TranscriptError {
+    /// The transcript is not valid JSON.
+    #[error("Invalid transcript JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The JSON is not an OpenAI messages array or a ShareGPT conversation.
+    #[error(
+        "Unknown transcript format: expected an OpenAI messages array or a ShareGPT conversation"
+    )]
+    UnknownFormat,
+    /// A message has a role that can't be mapped to the chat history.
+    #[error("Unknown role {role:?} in message {index}")]
+    UnknownRole {
+        /// The role of the message.
+        role: String,
+        /// The index of the message in the transcript.
+        index: usize,
+    },
+}
+
+/// Parse a transcript from another chat application into chat messages. Two formats are supported:
+/// - OpenAI chat completion messages: an array of `{"role", "content"}` objects or an object with a `messages` array
+/// - ShareGPT exports: an object with a `conversations` array of `{"from", "value"}` objects, or the array itself
+///
+/// The history only has system, user and assistant messages, so tool calls are written into the assistant message
+/// as `<tool_call>` blocks and tool results become user messages with `<tool_response>` blocks. Consecutive tool
+/// results are merged into one message.
+///
+/// # Example
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let messages = parse_transcript(
+///     r#"[{"role": "user", "content": "Hi"}, {"role": "assistant", "content": "Hello!"}]"#,
+/// )
+/// .unwrap();
+/// assert_eq!(messages[1].role(), MessageType::ModelAnswer);
+/// ```
+pub fn parse_transcript(json: &str) -> Result<Vec<ChatMessage>, TranscriptError> {
+    let value: Value = serde_json::from_str(json)?;
+    let messages = match &value {
+        Value::Array(messages) => messages,
+        Value::Object(object) => match object
+            .get("messages")
+            .or_else(|| object.get("conversations"))
+        {
+            Some(Value::Array(messages)) => messages,
+            _ => return Err(TranscriptError::UnknownFormat),
+        },
+        _ => return Err(TranscriptError::UnknownFormat),
+    };
+
+    let mut history: Vec<ChatMessage> = Vec::with_capacity(messages.len());
+    let mut last_was_tool_response = false;
+    for (index, message) in messages.iter().enumerate() {
+        let (role, content, tool_calls) = match (message.get("role"), message.get("from")) {
+            (Some(role), _) => (
+                role.as_str().unwrap_or_default(),
+                openai_content(message.get("content")),
+                message
+                    .get("tool_calls")
+                    .and_then(Value::as_array)
+                    .map(|calls| calls.iter().map(openai_tool_call).collect::<Vec<_>>())
+                    .unwrap_or_default(),
+            ),
+            (None, Some(from)) => (
+                from.as_str().unwrap_or_default(),
+                message
+                    .get("value")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_string(),
+                Vec::new(),
+            ),
+            (None, None) => return Err(TranscriptError::UnknownFormat),
+        };
+
+        let (role, content) = match role {
+            "system" | "developer" => (MessageType::SystemPrompt, content),
+            "user" | "human" => (MessageType::UserMessage, content),
+            "assistant" | "gpt" | "bot" | "model" => {
+                let mut content = content;
+                for call in tool_calls {
+                    if !content.is_empty() {
+                        content.push('\n');
+                    }
+                    content += &format!("<tool_call>\n{call}\n</tool_call>");
+                }
+                (MessageType::ModelAnswer, content)
+            }
+            "function_call" => (
+                MessageType::ModelAnswer,
+                format!("<tool_call>\n{content}\n</tool_call>"),
+            ),
+            "tool" | "function" | "observation" => {
+                let response = format!("<tool_response>\n{content}\n</tool_response>");
+                match history.last_mut() {
+                    Some(last) if last_was_tool_response => {
+                        last.content = format!("{}\n{response}", last.content);
+                    }
+                    _ => history.push(ChatMessage::new(MessageType::UserMessage, response)),
+                }
+                last_was_tool_response = true;
+                continue;
+            }
+            role => {
+                return Err(TranscriptError::UnknownRole {
+                    role: role.to_string(),
+                    index,
+                })
+            }
+        };
+        last_was_tool_response = false;
+        history.push(ChatMessage::new(role, content));
+    }
+
+    Ok(history)
+}
+
+/// Get the text of OpenAI message content, which is either a string or an array of parts.
+fn openai_content(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Format an OpenAI tool call as `{"name": ..., "arguments": ...}`.
+fn openai_tool_call(call: &Value) -> String {
+    let function = call.get("function").unwrap_or(call);
+    let name = function.get("name").cloned().unwrap_or(Value::Null);
+    // OpenAI encodes the arguments as a JSON string
+    let arguments = match function.get("arguments") {
+        Some(Value::String(arguments)) => {
+            serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.clone()))
+        }
+        Some(arguments) => arguments.clone(),
+        None => Value::Object(Default::default()),
+    };
+    format!(r#"{{"name": {name}, "arguments": {arguments}}}"#)
+}
+
+#[test]
+fn parse_openai_and_sharegpt_transcripts() {
+    let openai = r#"{"messages": [
+        {"role": "system", "content": "Be brief"},
+        {"role": "user", "content": [{"type": "text", "text": "Weather in Paris?"}]},
+        {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "1", "type": "function", "function": {"name": "weather", "arguments": "{\"city\":\"Paris\"}"}}
+        ]},
+        {"role": "tool", "tool_call_id": "1", "content": "Sunny"},
+        {"role": "assistant", "content": "It is sunny."}
+    ]}"#;
+    let messages = parse_transcript(openai).unwrap();
+    assert_eq!(
+        messages,
+        [
+            ChatMessage::new(MessageType::SystemPrompt, "Be brief"),
+            ChatMessage::new(MessageType::UserMessage, "Weather in Paris?"),
+            ChatMessage::new(
+                MessageType::ModelAnswer,
+                "<tool_call>\n{\"name\": \"weather\", \"arguments\": {\"city\":\"Paris\"}}\n</tool_call>"
+            ),
+            ChatMessage::new(
+                MessageType::UserMessage,
+                "<tool_response>\nSunny\n</tool_response>"
+            ),
+            ChatMessage::new(MessageType::ModelAnswer, "It is sunny."),
+        ]
+    );
+
+    let sharegpt = r#"{"conversations": [
+        {"from": "human", "value": "Hi"},
+        {"from": "gpt", "value": "Hello!"}
+    ]}"#;
+    let messages = parse_transcript(sharegpt).unwrap();
+    assert_eq!(
+        messages,
+        [
+            ChatMessage::new(MessageType::UserMessage, "Hi"),
+            ChatMessage::new(MessageType::ModelAnswer, "Hello!"),
+        ]
+    );
+
+    assert!(matches!(
+        parse_transcript(r#"[{"role": "narrator", "content": "..."}]"#),
+        Err(TranscriptError::UnknownRole { index: 0, .. })
+    ));
+}
@@ -1,10 +1,10 @@
 use super::{AnthropicCompatibleClient, NoAnthropicAPIKeyError};
+use crate::remote_retry::{next_event_before, open_event_source, StreamInterrupted, UsageTimer};
 use crate::{
     ChatMessage, ChatModel, ChatSession, CreateChatSession, GenerationParameters, ModelBuilder,
 };
-use futures_util::StreamExt;
 use kalosm_model_types::ModelLoadingProgress;
-use reqwest_eventsource::{Event, RequestBuilderExt};
+use reqwest_eventsource::Event;
 use serde::{Deserialize, Serialize};
 use std::{future::Future, sync::Arc};
 use thiserror::Error;
@@ -146,6 +146,9 @@ pub enum AnthropicCompatibleChatModelError {
     /// An error occurred while streaming the response from the Anthropic API.
     #[error("Error streaming response from Anthropic API: {0}")]
     StreamError(#[from] AnthropicCompatibleChatResponseError),
+    /// The generation was cancelled with a [`CancellationToken`](crate::CancellationToken).
+    #[error("The generation was cancelled")]
+    Cancelled,
 }
 
 /// A chat session for the Anthropic compatible chat model.
@@ -203,10 +206,20 @@ impl CreateChatSession for AnthropicCompatibleChatModel {
 #[derive(Serialize, Deserialize)]
 #[serde(tag = "type")]
 enum AnthropicCompatibleChatResponse {
+    #[serde(rename = "message_start")]
+    MessageStart {
+        message: AnthropicCompatibleChatResponseMessageStart,
+    },
     #[serde(rename = "content_block_delta")]
     ContentBlockDelta(AnthropicCompatibleChatResponseContentBlockDelta),
     #[serde(rename = "content_block_stop")]
     ContentBlockStop,
+    #[serde(rename = "message_delta")]
+    MessageDelta {
+        #[serde(default)]
+        delta: AnthropicCompatibleChatResponseMessageDelta,
+        usage: AnthropicCompatibleChatResponseUsage,
+    },
     #[serde(rename = "error")]
     Error(AnthropicCompatibleChatResponseError),
     #[serde(other)]
@@ -280,6 +293,18 @@ pub enum AnthropicCompatibleChatResponseError {
     Unknown,
 }
 
+#[derive(Serialize, Deserialize)]
+struct AnthropicCompatibleChatResponseMessageStart {
+    usage: AnthropicCompatibleChatResponseUsage,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AnthropicCompatibleChatResponseUsage {
+    #[serde(default)]
+    input_tokens: usize,
+    output_tokens: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 struct AnthropicCompatibleChatResponseContentBlockDelta {
     index: u32,
@@ -295,22 +320,32 @@ enum AnthropicCompatibleChatResponseContentBlockDeltaMessage {
     Unknown,
 }
 
+#[derive(Serialize, Deserialize, Default)]
+struct AnthropicCompatibleChatResponseMessageDelta {
+    stop_reason: Option<StopReason>,
+}
+
 #[derive(Serialize, Deserialize)]
-enum FinishReason {
-    #[serde(rename = "content_filter")]
-    ContentFilter,
-    #[serde(rename = "function_call")]
-    FunctionCall,
-    #[serde(rename = "length")]
+enum StopReason {
+    #[serde(rename = "end_turn")]
+    EndTurn,
+    #[serde(rename = "max_tokens")]
     MaxTokens,
-    #[serde(rename = "stop")]
-    Stop,
+    #[serde(rename = "stop_sequence")]
+    StopSequence,
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Serialize, Deserialize)]
-struct AnthropicCompatibleChatResponseChoiceMessage {
-    content: Option<String>,
-    refusal: Option<String>,
+impl From<StopReason> for crate::FinishReason {
+    fn from(reason: StopReason) -> Self {
+        match reason {
+            StopReason::MaxTokens => crate::FinishReason::MaxTokens,
+            StopReason::EndTurn | StopReason::StopSequence | StopReason::Unknown => {
+                crate::FinishReason::Stop
+            }
+        }
+    }
 }
 
 impl ChatModel<GenerationParameters> for AnthropicCompatibleChatModel {
@@ -344,28 +379,66 @@ impl ChatModel<GenerationParameters> for AnthropicCompatibleChatModel {
             "max_tokens": sampler.max_length.min(myself.max_tokens),
         });
 
+        let cancellation_token = sampler.cancellation_token().cloned();
+        let deadline = sampler
+            .timeout()
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let finish_handler = sampler.finish_handler();
+        let usage_handler = sampler.usage_handler();
         async move {
             let api_key = myself.client.resolve_api_key()?;
-            if let Some(stop_on) = sampler.stop_on.as_ref() {
-                json["stop"] = vec![stop_on.clone()].into();
+            let stop = sampler.stop_strings();
+            if !stop.is_empty() {
+                json["stop"] = stop.into();
             }
             if let Some(system) = system_prompt {
                 json["system"] = system.into();
             }
-            let mut event_source = myself
-                .client
-                .reqwest_client
-                .post(format!("{}/messages", myself.client.base_url()))
-                .header("Content-Type", "application/json")
-                .header("x-api-key", api_key)
-                .header("anthropic-version", myself.client.version())
-                .json(&json)
-                .eventsource()
-                .unwrap();
+            let mut timer = UsageTimer::start();
+            let mut event_source = open_event_source(myself.client.retry_policy(), || {
+                myself
+                    .client
+                    .reqwest_client
+                    .post(format!("{}/messages", myself.client.base_url()))
+                    .header("Content-Type", "application/json")
+                    .header("x-api-key", &api_key)
+                    .header("anthropic-version", myself.client.version())
+                    .json(&json)
+            })
+            .await?;
 
             let mut new_message_text = String::new();
 
-            while let Some(event) = event_source.next().await {
+            let finish = |reason: crate::FinishReason| {
+                if let Some(finish_handler) = &finish_handler {
+                    (finish_handler.lock().unwrap())(reason);
+                }
+            };
+            let mut finish_reason = crate::FinishReason::Stop;
+            let mut prompt_tokens = 0;
+            let mut completion_tokens = 0;
+            loop {
+                let event = match next_event_before(
+                    &mut event_source,
+                    deadline,
+                    cancellation_token.as_ref(),
+                )
+                .await
+                {
+                    Ok(event) => event,
+                    Err(StreamInterrupted::TimedOut) => {
+                        event_source.close();
+                        finish_reason = crate::FinishReason::TimedOut;
+                        break;
+                    }
+                    Err(StreamInterrupted::Cancelled) => {
+                        event_source.close();
+                        return Err(AnthropicCompatibleChatModelError::Cancelled);
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
                 match event? {
                     Event::Open => {}
                     Event::Message(message) => {
@@ -377,13 +450,28 @@ impl ChatModel<GenerationParameters> for AnthropicCompatibleChatModel {
                             ) => {
                                 match anthropic_compatible_chat_response_content_block_delta.delta {
                                 AnthropicCompatibleChatResponseContentBlockDeltaMessage::TextDelta { text } => {
+                                        timer.token();
                                         new_message_text += &text;
                                         on_token(text)?;
                                 },
                                 AnthropicCompatibleChatResponseContentBlockDeltaMessage::Unknown => tracing::trace!("Unknown delta from Anthropic API: {:?}", message.data),
                             }
                             }
+                            AnthropicCompatibleChatResponse::MessageStart { message } => {
+                                prompt_tokens = message.usage.input_tokens;
+                            }
+                            // The stop reason and output token count are sent after the content block stops
                             AnthropicCompatibleChatResponse::ContentBlockStop => {
+                                if usage_handler.is_none() && finish_handler.is_none() {
+                                    break;
+                                }
+                            }
+                            AnthropicCompatibleChatResponse::MessageDelta { delta, usage } => {
+                                if let Some(stop_reason) = delta.stop_reason {
+                                    finish_reason = stop_reason.into();
+                                }
+                                completion_tokens = usage.output_tokens;
+                                event_source.close();
                                 break;
                             }
                             AnthropicCompatibleChatResponse::Error(
@@ -402,6 +490,11 @@ impl ChatModel<GenerationParameters> for AnthropicCompatibleChatModel {
                 }
             }
 
+            finish(finish_reason);
+            if let Some(usage_handler) = &usage_handler {
+                (usage_handler.lock().unwrap())(timer.finish(prompt_tokens, completion_tokens));
+            }
+
             let new_message =
                 crate::ChatMessage::new(crate::MessageType::UserMessage, new_message_text);
 
@@ -413,12 +506,86 @@ impl ChatModel<GenerationParameters> for AnthropicCompatibleChatModel {
 }
 
 #[cfg(test)]
+// The token callbacks have to return the model error, which is large
+#[allow(clippy::result_large_err)]
 mod tests {
     use std::sync::{Arc, RwLock};
 
     use super::{
-        AnthropicCompatibleChatModelBuilder, ChatModel, CreateChatSession, GenerationParameters,
+        AnthropicCompatibleChatModelBuilder, AnthropicCompatibleChatModelError, ChatModel,
+        CreateChatSession, GenerationParameters,
     };
+    use crate::{AnthropicCompatibleClient, CancellationToken, FinishReason};
+
+    /// Serve one recorded message stream from a local server and return the base url to reach it. If `stall` is set,
+    /// the connection is left open after the events are sent.
+    async fn serve_recorded_stream(events: &'static str, stall: bool) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0; 4096]).await;
+            let _ = stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n{events}"
+                    )
+                    .as_bytes(),
+                )
+                .await;
+            if stall {
+                std::future::pending::<()>().await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn max_tokens_stop_reason_is_reported_to_the_finish_handler() {
+        let url = serve_recorded_stream(concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Ahoy\"}}\n\n",
+            "event: content_block_stop\n",
+            "data: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"max_tokens\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":1}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n",
+        ), false)
+        .await;
+        let model = AnthropicCompatibleChatModelBuilder::new()
+            .with_claude_3_5_haiku()
+            .with_client(
+                AnthropicCompatibleClient::new()
+                    .with_api_key("key")
+                    .with_base_url(url),
+            )
+            .build();
+        let mut session = model.new_chat_session().unwrap();
+
+        let finish_reason = Arc::new(RwLock::new(None));
+        let sampler = GenerationParameters::default().with_finish_handler({
+            let finish_reason = finish_reason.clone();
+            move |reason| *finish_reason.write().unwrap() = Some(reason)
+        });
+        let messages = [crate::ChatMessage::new(
+            crate::MessageType::UserMessage,
+            "Hello, world!".to_string(),
+        )];
+        model
+            .add_messages_with_callback(&mut session, &messages, sampler, |_| Ok(()))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *finish_reason.read().unwrap(),
+            Some(FinishReason::MaxTokens)
+        );
+    }
 
     #[tokio::test]
     async fn test_claude_3_5_haiku() {
@@ -455,4 +622,47 @@ mod tests {
 
         assert!(!all_text.is_empty());
     }
+
+    #[tokio::test]
+    async fn cancelling_a_stalled_stream_stops_the_generation() {
+        let url = serve_recorded_stream(
+            concat!(
+                "event: message_start\n",
+                "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":12,\"output_tokens\":1}}}\n\n",
+            ),
+            true,
+        )
+        .await;
+        let model = AnthropicCompatibleChatModelBuilder::new()
+            .with_claude_3_5_haiku()
+            .with_client(
+                AnthropicCompatibleClient::new()
+                    .with_api_key("key")
+                    .with_base_url(url),
+            )
+            .build();
+        let mut session = model.new_chat_session().unwrap();
+
+        let token = CancellationToken::new();
+        let sampler = GenerationParameters::default().with_cancellation_token(token.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            token.cancel();
+        });
+        let messages = [crate::ChatMessage::new(
+            crate::MessageType::UserMessage,
+            "Hello, world!".to_string(),
+        )];
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            model.add_messages_with_callback(&mut session, &messages, sampler, |_| Ok(())),
+        )
+        .await
+        .expect("the generation should stop when the token is cancelled");
+
+        assert!(matches!(
+            result,
+            Err(AnthropicCompatibleChatModelError::Cancelled)
+        ));
+    }
 }
@@ -1,5 +1,7 @@
 use std::sync::OnceLock;
 
+use kalosm_model_types::RetryPolicy;
+
 use thiserror::Error;
 
 mod chat;
@@ -13,6 +15,7 @@ pub struct AnthropicCompatibleClient {
     api_key: Option<String>,
     resolved_api_key: OnceLock<String>,
     version: String,
+    retry_policy: RetryPolicy,
 }
 
 impl Default for AnthropicCompatibleClient {
@@ -28,6 +31,7 @@ impl AnthropicCompatibleClient {
             reqwest_client: reqwest::Client::new(),
             base_url: "https://api.anthropic.com/v1/".to_string(),
             resolved_api_key: OnceLock::new(),
+            retry_policy: RetryPolicy::default(),
             api_key: None,
             version: "2023-06-01".to_string(),
         }
@@ -61,6 +65,13 @@ impl AnthropicCompatibleClient {
         self
     }
 
+    /// Set the policy for retrying requests that fail with transient errors like rate limits or dropped
+    /// connections. Streaming responses are only retried before the first token is received. (Defaults to [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// Resolve the anthropic API key from the environment variable `ANTHROPIC_API_KEY` or the provided api key.
     pub fn resolve_api_key(&self) -> Result<String, NoAnthropicAPIKeyError> {
         if let Some(api_key) = self.resolved_api_key.get() {
@@ -84,6 +95,11 @@ impl AnthropicCompatibleClient {
         self.base_url.trim_end_matches('/')
     }
 
+    /// Get the policy for retrying failed requests.
+    pub(crate) fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
     /// Get the version of the Anthropic API.
     pub(crate) fn version(&self) -> &str {
         &self.version
@@ -11,6 +11,10 @@ mod model;
 pub use model::*;
 mod into_embedding;
 pub use into_embedding::*;
+mod router;
+pub use router::*;
+mod similarity;
+pub use similarity::*;
 
 #[doc = include_str!("../../docs/embedding.md")]
 pub struct Embedding {
@@ -157,7 +157,7 @@ pub trait EmbedderExt: Embedder {
         Self::Error: std::error::Error,
     {
         DynEmbedder {
-            embedder: Box::new(AnyEmbedder::<Self>(self)),
+            embedder: Box::new(self),
         }
     }
 
@@ -205,7 +205,13 @@ impl<E: Embedder> EmbedderExt for E {}
 
 /// A trait object for an embedder.
 pub struct DynEmbedder {
-    embedder: Box<dyn BoxedEmbedder + Send + Sync>,
+    embedder: Box<dyn AnyEmbedder>,
+}
+
+impl From<Box<dyn AnyEmbedder>> for DynEmbedder {
+    fn from(embedder: Box<dyn AnyEmbedder>) -> Self {
+        Self { embedder }
+    }
 }
 
 impl Embedder for DynEmbedder {
@@ -240,32 +246,56 @@ impl Embedder for DynEmbedder {
     }
 }
 
-struct AnyEmbedder<E: Embedder + Send + Sync + 'static>(E);
-
+/// An object safe version of [`Embedder`]. This trait is implemented for every [`Embedder`] and can be used to
+/// choose an embedding model at runtime with `Box<dyn AnyEmbedder>`.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let embedder: Box<dyn AnyEmbedder> = match std::env::var("EMBEDDER").as_deref() {
+///         Ok("openai") => Box::new(
+///             OpenAICompatibleEmbeddingModel::builder()
+///                 .with_text_embedding_3_small()
+///                 .build(),
+///         ),
+///         _ => Box::new(Bert::new().await.unwrap()),
+///     };
+///     let embedder = DynEmbedder::from(embedder);
+///     let embedding = embedder.embed("Cats are cool").await.unwrap();
+///     println!("{:?}", embedding);
+/// }
+/// ```
 #[allow(clippy::type_complexity)]
-trait BoxedEmbedder {
+pub trait AnyEmbedder: Send + Sync + 'static {
+    /// Embed some text into a vector space.
     fn embed_string_boxed(
         &self,
         input: String,
     ) -> BoxedFuture<'_, Result<Embedding, Box<dyn std::error::Error + Send + Sync>>>;
 
+    /// Embed a batch of text into a vector space.
     fn embed_vec_boxed(
         &self,
         inputs: Vec<String>,
     ) -> BoxedFuture<'_, Result<Vec<Embedding>, Box<dyn std::error::Error + Send + Sync>>>;
 
+    /// Embed a [`EmbeddingInput`] into a vector space.
     fn embed_for_boxed(
         &self,
         input: EmbeddingInput,
     ) -> BoxedFuture<'_, Result<Embedding, Box<dyn std::error::Error + Send + Sync>>>;
 
+    /// Embed a batch of [`EmbeddingInput`] into a vector space.
     fn embed_vec_for_boxed(
         &self,
         inputs: Vec<EmbeddingInput>,
     ) -> BoxedFuture<'_, Result<Vec<Embedding>, Box<dyn std::error::Error + Send + Sync>>>;
 }
 
-impl<E: Embedder + Send + Sync + 'static> BoxedEmbedder for AnyEmbedder<E>
+impl<E: Embedder> AnyEmbedder for E
 where
     E::Error: std::error::Error,
 {
@@ -273,7 +303,7 @@ where
         &self,
         input: String,
     ) -> BoxedFuture<'_, Result<Embedding, Box<dyn std::error::Error + Send + Sync>>> {
-        let future = self.0.embed_string(input);
+        let future = self.embed_string(input);
         Box::pin(async move { future.await.map_err(|e| e.into()) })
     }
 
@@ -281,7 +311,7 @@ where
         &self,
         inputs: Vec<String>,
     ) -> BoxedFuture<'_, Result<Vec<Embedding>, Box<dyn std::error::Error + Send + Sync>>> {
-        let future = self.0.embed_vec(inputs);
+        let future = self.embed_vec(inputs);
         Box::pin(async move {
             future
                 .await
@@ -294,7 +324,7 @@ where
         &self,
         input: EmbeddingInput,
     ) -> BoxedFuture<'_, Result<Embedding, Box<dyn std::error::Error + Send + Sync>>> {
-        let future = self.0.embed_for(input);
+        let future = self.embed_for(input);
         Box::pin(async move { future.await.map_err(|e| e.into()) })
     }
 
@@ -302,7 +332,7 @@ where
         &self,
         inputs: Vec<EmbeddingInput>,
     ) -> BoxedFuture<'_, Result<Vec<Embedding>, Box<dyn std::error::Error + Send + Sync>>> {
-        let future = self.0.embed_vec_for(inputs);
+        let future = self.embed_vec_for(inputs);
         Box::pin(async move {
             future
                 .await
@@ -0,0 +1,208 @@
+use super::{Embedder, EmbedderExt, Embedding, EmbeddingInput, EmbeddingVariant};
+
+/// A route registered with a [`SemanticRouter`].
+struct Route {
+    name: String,
+    examples: Vec<Embedding>,
+}
+
+/// The route a message was classified as by a [`SemanticRouter`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteMatch {
+    name: String,
+    confidence: f32,
+    fallback: bool,
+}
+
+impl RouteMatch {
+    /// Get the name of the route.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the cosine similarity between the message and the closest example of the best route. If the message
+    /// fell back to the fallback route, this is the similarity of the best route that was below the threshold.
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+
+    /// Check if no route was confident enough and the message was sent to the fallback route.
+    pub fn is_fallback(&self) -> bool {
+        self.fallback
+    }
+}
+
+/// A router that classifies messages into named routes by comparing the embedding of the message to embeddings of
+/// example utterances for each route. The examples are embedded once when the route is added, so classifying a
+/// message only takes one embedding call. This is much cheaper than asking a language model to classify the message.
+///
+/// A message is sent to the route with the most similar example if the similarity is above the threshold. Otherwise
+/// it is sent to the fallback route if one is set.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let bert = Bert::new().await.unwrap();
+///     let mut router = SemanticRouter::new(bert)
+///         .with_threshold(0.6)
+///         .with_fallback("chat");
+///     router
+///         .add_route("weather", ["What is the weather like?", "Will it rain tomorrow?"])
+///         .await
+///         .unwrap();
+///     router
+///         .add_route("calendar", ["What meetings do I have today?", "Schedule a call with Ann"])
+///         .await
+///         .unwrap();
+///
+///     let route = router.route("Do I need an umbrella?").await.unwrap().unwrap();
+///     println!("{} ({})", route.name(), route.confidence());
+/// }
+/// ```
+pub struct SemanticRouter<E: Embedder> {
+    embedder: E,
+    routes: Vec<Route>,
+    threshold: f32,
+    fallback: Option<String>,
+}
+
+impl<E: Embedder> SemanticRouter<E> {
+    /// Create a new router with no routes that embeds messages with the embedder.
+    pub fn new(embedder: E) -> Self {
+        Self {
+            embedder,
+            routes: Vec::new(),
+            threshold: 0.5,
+            fallback: None,
+        }
+    }
+
+    /// Set the minimum similarity a message needs to be sent to a route. (Defaults to 0.5)
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Set the route messages are sent to when no route is above the threshold. Without a fallback,
+    /// [`SemanticRouter::route`] returns `None` for those messages.
+    pub fn with_fallback(mut self, fallback: impl ToString) -> Self {
+        self.fallback = Some(fallback.to_string());
+        self
+    }
+
+    /// Get the embedder the router uses.
+    pub fn embedder(&self) -> &E {
+        &self.embedder
+    }
+
+    /// Get the names of the registered routes.
+    pub fn routes(&self) -> impl Iterator<Item = &str> {
+        self.routes.iter().map(|route| route.name.as_str())
+    }
+
+    /// Add a route with example utterances. If a route with the same name already exists, the examples are added to
+    /// that route.
+    pub async fn add_route(
+        &mut self,
+        name: impl ToString,
+        examples: impl IntoIterator<Item = impl ToString>,
+    ) -> Result<(), E::Error> {
+        let inputs = examples
+            .into_iter()
+            .map(|example| EmbeddingInput::new(example, EmbeddingVariant::Query));
+        let embeddings = self.embedder.embed_batch_for(inputs).await?;
+        self.add_route_embeddings(name.to_string(), embeddings);
+        Ok(())
+    }
+
+    /// Remove a route. Returns true if the route existed.
+    pub fn remove_route(&mut self, name: &str) -> bool {
+        let len = self.routes.len();
+        self.routes.retain(|route| route.name != name);
+        self.routes.len() != len
+    }
+
+    /// Classify a message into a route. Returns `None` if no route is above the threshold and there is no fallback
+    /// route.
+    pub async fn route(&self, message: impl ToString) -> Result<Option<RouteMatch>, E::Error> {
+        let embedding = self.embedder.embed_query(message).await?;
+        Ok(self.route_embedding(&embedding))
+    }
+
+    /// Classify an embedding of a message into a route.
+    pub fn route_embedding(&self, embedding: &Embedding) -> Option<RouteMatch> {
+        let best = self
+            .routes
+            .iter()
+            .filter_map(|route| {
+                let similarity = route
+                    .examples
+                    .iter()
+                    .map(|example| example.cosine_similarity(embedding))
+                    .filter(|similarity| !similarity.is_nan())
+                    .max_by(f32::total_cmp)?;
+                Some((route, similarity))
+            })
+            .max_by(|(_, first), (_, second)| first.total_cmp(second));
+
+        match best {
+            Some((route, confidence)) if confidence >= self.threshold => Some(RouteMatch {
+                name: route.name.clone(),
+                confidence,
+                fallback: false,
+            }),
+            best => self.fallback.as_ref().map(|fallback| RouteMatch {
+                name: fallback.clone(),
+                confidence: best.map(|(_, confidence)| confidence).unwrap_or(0.0),
+                fallback: true,
+            }),
+        }
+    }
+
+    fn add_route_embeddings(&mut self, name: String, embeddings: Vec<Embedding>) {
+        match self.routes.iter_mut().find(|route| route.name == name) {
+            Some(route) => route.examples.extend(embeddings),
+            None => self.routes.push(Route {
+                name,
+                examples: embeddings,
+            }),
+        }
+    }
+}
+
+#[test]
+fn semantic_router_uses_threshold_and_fallback() {
+    struct NoEmbedder;
+
+    impl Embedder for NoEmbedder {
+        type Error = std::convert::Infallible;
+
+        async fn embed_for(&self, _: EmbeddingInput) -> Result<Embedding, Self::Error> {
+            Ok(Embedding::from([0.0, 0.0]))
+        }
+    }
+
+    let mut router = SemanticRouter::new(NoEmbedder).with_threshold(0.9);
+    router.add_route_embeddings(
+        "weather".to_string(),
+        vec![Embedding::from([1.0, 0.0]), Embedding::from([0.8, 0.6])],
+    );
+    router.add_route_embeddings("calendar".to_string(), vec![Embedding::from([0.0, 1.0])]);
+
+    let route = router
+        .route_embedding(&Embedding::from([0.6, 0.8]))
+        .unwrap();
+    assert_eq!(route.name(), "weather");
+    assert!(!route.is_fallback());
+    assert!((route.confidence() - 0.96).abs() < 1e-5);
+
+    let unsure = Embedding::from([-1.0, 0.0]);
+    assert_eq!(router.route_embedding(&unsure), None);
+    let router = router.with_fallback("chat");
+    let route = router.route_embedding(&unsure).unwrap();
+    assert_eq!(route.name(), "chat");
+    assert!(route.is_fallback());
+}
@@ -0,0 +1,380 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::Embedding;
+
+impl Embedding {
+    /// Compute the dot product between this embedding and another embedding.
+    pub fn dot(&self, other: &Self) -> f32 {
+        dot(self.vector(), other.vector())
+    }
+
+    /// Get the euclidean length of this embedding.
+    pub fn norm(&self) -> f32 {
+        dot(self.vector(), self.vector()).sqrt()
+    }
+
+    /// Scale this embedding to have a length of one. Embeddings with a length of zero are returned unchanged.
+    pub fn normalized(&self) -> Self {
+        let norm = self.norm();
+        if norm == 0. {
+            return self.clone();
+        }
+        Embedding::from(self.vector().iter().map(|x| x / norm))
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Compute the dot product between every pair of embeddings in `rows` and `columns`. `matrix[i][j]` is the dot
+/// product of `rows[i]` and `columns[j]`.
+pub fn dot_similarity_matrix(rows: &[Embedding], columns: &[Embedding]) -> Vec<Vec<f32>> {
+    rows.iter()
+        .map(|row| columns.iter().map(|column| row.dot(column)).collect())
+        .collect()
+}
+
+/// Compute the cosine similarity between every pair of embeddings in `rows` and `columns`. `matrix[i][j]` is the
+/// cosine similarity of `rows[i]` and `columns[j]`.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let bert = Bert::new().await?;
+/// let questions = bert.embed_batch(["What is the capital of France?", "How tall is Everest?"]).await?;
+/// let answers = bert.embed_batch(["Paris", "8,849 meters", "Berlin"]).await?;
+/// let matrix = cosine_similarity_matrix(&questions, &answers);
+/// println!("{matrix:?}");
+/// # Ok(())
+/// # }
+/// ```
+pub fn cosine_similarity_matrix(rows: &[Embedding], columns: &[Embedding]) -> Vec<Vec<f32>> {
+    let rows: Vec<_> = rows.iter().map(Embedding::normalized).collect();
+    let columns: Vec<_> = columns.iter().map(Embedding::normalized).collect();
+    dot_similarity_matrix(&rows, &columns)
+}
+
+/// Find the `k` candidates with the highest cosine similarity to the query. Returns the index of each candidate and
+/// its similarity, sorted from most to least similar.
+pub fn top_k_similar(query: &Embedding, candidates: &[Embedding], k: usize) -> Vec<(usize, f32)> {
+    let query = query.normalized();
+    let mut scores: Vec<_> = candidates
+        .iter()
+        .map(|candidate| query.dot(&candidate.normalized()))
+        .enumerate()
+        .collect();
+    let k = k.min(scores.len());
+    if k == 0 {
+        return Vec::new();
+    }
+    let by_score = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1);
+    scores.select_nth_unstable_by(k - 1, by_score);
+    scores.truncate(k);
+    scores.sort_by(by_score);
+    scores
+}
+
+/// Groups embeddings into clusters with k-means. Centroids are initialized with k-means++.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let bert = Bert::new().await?;
+/// let sentences = ["I like cats", "Dogs are great", "The stock market fell", "Shares rallied today"];
+/// let embeddings = bert.embed_batch(sentences).await?;
+/// let clusters = KMeans::new(2).with_seed(42).fit(&embeddings);
+/// for (sentence, cluster) in sentences.iter().zip(clusters.assignments()) {
+///     println!("{cluster}: {sentence}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct KMeans {
+    k: usize,
+    max_iterations: usize,
+    seed: Option<u64>,
+}
+
+impl KMeans {
+    /// Create a new k-means clusterer that groups embeddings into `k` clusters.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is zero.
+    pub fn new(k: usize) -> Self {
+        assert!(k > 0, "k-means needs at least one cluster");
+        Self {
+            k,
+            max_iterations: 100,
+            seed: None,
+        }
+    }
+
+    /// Set the maximum number of iterations to run before stopping. (Defaults to 100)
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Set the seed used to pick the initial centroids. (Defaults to a random seed)
+    pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
+        self.seed = seed.into();
+        self
+    }
+
+    /// Cluster the embeddings. If there are fewer embeddings than clusters, every embedding gets its own cluster.
+    pub fn fit(&self, embeddings: &[Embedding]) -> Clusters {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let k = self.k.min(embeddings.len());
+        let mut centroids = initial_centroids(embeddings, k, &mut rng);
+        let mut assignments = vec![0; embeddings.len()];
+
+        for _ in 0..self.max_iterations {
+            let mut changed = false;
+            for (embedding, assignment) in embeddings.iter().zip(&mut assignments) {
+                let nearest = nearest_centroid(embedding.vector(), &centroids);
+                changed |= nearest != *assignment;
+                *assignment = nearest;
+            }
+
+            let dimensions = centroids.first().map_or(0, Vec::len);
+            let mut sums = vec![vec![0.; dimensions]; k];
+            let mut counts = vec![0usize; k];
+            for (embedding, &assignment) in embeddings.iter().zip(&assignments) {
+                counts[assignment] += 1;
+                for (sum, x) in sums[assignment].iter_mut().zip(embedding.vector()) {
+                    *sum += x;
+                }
+            }
+            for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+                // Keep the old centroid if the cluster is empty
+                if count > 0 {
+                    *centroid = sum.into_iter().map(|x| x / count as f32).collect();
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        Clusters {
+            centroids: centroids.into_iter().map(Embedding::from).collect(),
+            assignments,
+        }
+    }
+}
+
+/// Pick centroids with k-means++: each new centroid is chosen with a probability proportional to its squared distance
+/// from the nearest existing centroid.
+fn initial_centroids(embeddings: &[Embedding], k: usize, rng: &mut StdRng) -> Vec<Vec<f32>> {
+    let mut centroids: Vec<Vec<f32>> = Vec::with_capacity(k);
+    if k == 0 {
+        return centroids;
+    }
+    centroids.push(
+        embeddings[rng.gen_range(0..embeddings.len())]
+            .vector()
+            .to_vec(),
+    );
+    let mut distances: Vec<f32> = embeddings
+        .iter()
+        .map(|embedding| squared_distance(embedding.vector(), &centroids[0]))
+        .collect();
+    while centroids.len() < k {
+        let total: f32 = distances.iter().sum();
+        let next = if total > 0. {
+            let mut target = rng.gen_range(0. ..total);
+            distances
+                .iter()
+                .position(|distance| {
+                    target -= distance;
+                    target < 0.
+                })
+                .unwrap_or(distances.len() - 1)
+        } else {
+            rng.gen_range(0..embeddings.len())
+        };
+        let centroid = embeddings[next].vector().to_vec();
+        for (distance, embedding) in distances.iter_mut().zip(embeddings) {
+            *distance = distance.min(squared_distance(embedding.vector(), &centroid));
+        }
+        centroids.push(centroid);
+    }
+    centroids
+}
+
+fn nearest_centroid(embedding: &[f32], centroids: &[Vec<f32>]) -> usize {
+    centroids
+        .iter()
+        .map(|centroid| squared_distance(embedding, centroid))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(i, _)| i)
+}
+
+/// The result of clustering embeddings with [`KMeans`].
+#[derive(Debug, Clone)]
+pub struct Clusters {
+    centroids: Vec<Embedding>,
+    assignments: Vec<usize>,
+}
+
+impl Clusters {
+    /// Get the center of each cluster.
+    pub fn centroids(&self) -> &[Embedding] {
+        &self.centroids
+    }
+
+    /// Get the index of the cluster each embedding was assigned to, in the same order as the embeddings.
+    pub fn assignments(&self) -> &[usize] {
+        &self.assignments
+    }
+
+    /// Get the indexes of the embeddings in a cluster.
+    pub fn members(&self, cluster: usize) -> impl Iterator<Item = usize> + '_ {
+        self.assignments
+            .iter()
+            .enumerate()
+            .filter(move |(_, assignment)| **assignment == cluster)
+            .map(|(i, _)| i)
+    }
+}
+
+/// Project embeddings onto their first two principal components, for example to plot them. The components are found
+/// with power iteration, so this works on large embeddings without building the covariance matrix.
+pub fn project_2d(embeddings: &[Embedding]) -> Vec<[f32; 2]> {
+    let Some(first) = embeddings.first() else {
+        return Vec::new();
+    };
+    let dimensions = first.vector().len();
+    let mut mean = vec![0.; dimensions];
+    for embedding in embeddings {
+        for (mean, x) in mean.iter_mut().zip(embedding.vector()) {
+            *mean += x / embeddings.len() as f32;
+        }
+    }
+    let centered: Vec<Vec<f32>> = embeddings
+        .iter()
+        .map(|embedding| {
+            embedding
+                .vector()
+                .iter()
+                .zip(&mean)
+                .map(|(x, mean)| x - mean)
+                .collect()
+        })
+        .collect();
+
+    let first_component = principal_component(&centered, None);
+    let second_component = principal_component(&centered, Some(&first_component));
+    centered
+        .iter()
+        .map(|row| [dot(row, &first_component), dot(row, &second_component)])
+        .collect()
+}
+
+/// Find the direction of largest variance in the centered rows with power iteration, ignoring the variance along
+/// `orthogonal_to`.
+fn principal_component(centered: &[Vec<f32>], orthogonal_to: Option<&[f32]>) -> Vec<f32> {
+    const ITERATIONS: usize = 100;
+    let dimensions = centered[0].len();
+    // A fixed start vector keeps the projection deterministic
+    let mut component: Vec<f32> = (0..dimensions)
+        .map(|i| 1. + i as f32 / dimensions as f32)
+        .collect();
+    for _ in 0..ITERATIONS {
+        if let Some(orthogonal_to) = orthogonal_to {
+            let overlap = dot(&component, orthogonal_to);
+            for (x, o) in component.iter_mut().zip(orthogonal_to) {
+                *x -= overlap * o;
+            }
+        }
+        let norm = dot(&component, &component).sqrt();
+        if norm == 0. {
+            return vec![0.; dimensions];
+        }
+        component.iter_mut().for_each(|x| *x /= norm);
+
+        // covariance * component = centered^T * (centered * component)
+        let mut next = vec![0.; dimensions];
+        for row in centered {
+            let projection = dot(row, &component);
+            for (next, x) in next.iter_mut().zip(row) {
+                *next += projection * x;
+            }
+        }
+        component = next;
+    }
+    if let Some(orthogonal_to) = orthogonal_to {
+        let overlap = dot(&component, orthogonal_to);
+        for (x, o) in component.iter_mut().zip(orthogonal_to) {
+            *x -= overlap * o;
+        }
+    }
+    let norm = dot(&component, &component).sqrt();
+    if norm == 0. {
+        return vec![0.; dimensions];
+    }
+    component.into_iter().map(|x| x / norm).collect()
+}
+
+#[test]
+fn top_k_is_sorted_by_similarity() {
+    let query = Embedding::from([1., 0.]);
+    let candidates = [
+        Embedding::from([0., 1.]),
+        Embedding::from([1., 0.1]),
+        Embedding::from([-1., 0.]),
+        Embedding::from([1., 1.]),
+    ];
+    let top: Vec<_> = top_k_similar(&query, &candidates, 2)
+        .into_iter()
+        .map(|(i, _)| i)
+        .collect();
+    assert_eq!(top, [1, 3]);
+}
+
+#[test]
+fn kmeans_separates_clusters() {
+    let embeddings = [
+        Embedding::from([0., 0.]),
+        Embedding::from([0.1, 0.]),
+        Embedding::from([10., 10.]),
+        Embedding::from([10., 10.1]),
+    ];
+    let clusters = KMeans::new(2).with_seed(0).fit(&embeddings);
+    let assignments = clusters.assignments();
+    assert_eq!(assignments[0], assignments[1]);
+    assert_eq!(assignments[2], assignments[3]);
+    assert_ne!(assignments[0], assignments[2]);
+}
+
+#[test]
+#[should_panic(expected = "at least one cluster")]
+fn kmeans_rejects_zero_clusters() {
+    KMeans::new(0);
+}
+
+#[test]
+fn projection_keeps_the_main_axis() {
+    let embeddings = [
+        Embedding::from([-2., 0., 0.]),
+        Embedding::from([0., 0.1, 0.]),
+        Embedding::from([2., 0., 0.]),
+    ];
+    let points = project_2d(&embeddings);
+    assert!((points[0][0] - points[2][0]).abs() > 3.9);
+}
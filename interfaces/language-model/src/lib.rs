@@ -23,6 +23,7 @@
 #![warn(missing_docs)]
 
 pub use futures_util::StreamExt;
+pub use kalosm_model_types::{RetryPolicy, RetryReason};
 pub use kalosm_sample;
 
 #[cfg(feature = "openai")]
@@ -33,6 +34,8 @@ pub use openai::*;
 mod claude;
 #[cfg(feature = "anthropic")]
 pub use claude::*;
+#[cfg(any(feature = "openai", feature = "anthropic"))]
+mod remote_retry;
 
 mod embedding;
 pub use embedding::*;
@@ -42,3 +45,5 @@ mod builder;
 pub use builder::*;
 mod chat;
 pub use chat::*;
+mod pipeline;
+pub use pipeline::*;
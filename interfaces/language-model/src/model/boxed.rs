@@ -7,7 +7,21 @@ use std::{error::Error, future::Future, pin::Pin, sync::Arc};
 /// A boxed [`TextCompletionModel`].
 #[derive(Clone)]
 pub struct BoxedTextCompletionModel {
-    model: Arc<dyn DynTextCompletionModel + Send + Sync>,
+    model: Arc<dyn AnyTextModel>,
+}
+
+impl From<Box<dyn AnyTextModel>> for BoxedTextCompletionModel {
+    fn from(model: Box<dyn AnyTextModel>) -> Self {
+        Self {
+            model: Arc::from(model),
+        }
+    }
+}
+
+impl From<Arc<dyn AnyTextModel>> for BoxedTextCompletionModel {
+    fn from(model: Arc<dyn AnyTextModel>) -> Self {
+        Self { model }
+    }
 }
 
 impl BoxedTextCompletionModel {
@@ -34,7 +48,7 @@ impl CreateTextCompletionSession for BoxedTextCompletionModel {
     type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 
     fn new_session(&self) -> Result<Self::Session, Self::Error> {
-        self.model.new_session_boxed()
+        self.model.new_boxed_session()
     }
 }
 
@@ -47,7 +61,73 @@ impl TextCompletionModel for BoxedTextCompletionModel {
         on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
         self.model
-            .add_messages_with_callback_boxed(session, text, sampler, Box::new(on_token))
+            .stream_text_boxed(session, text, sampler, Box::new(on_token))
+    }
+}
+
+/// An object safe version of [`TextCompletionModel`]. This trait is implemented for every [`TextCompletionModel`]
+/// with a cloneable session and can be used to choose a model at runtime with `Box<dyn AnyTextModel>`.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let model: Box<dyn AnyTextModel> = match std::env::var("MODEL").as_deref() {
+///         Ok("phi") => Box::new(Llama::phi_3().await.unwrap()),
+///         _ => Box::new(Llama::new().await.unwrap()),
+///     };
+///     let model = BoxedTextCompletionModel::from(model);
+///     let mut stream = model.complete("The capital of France is ");
+///     stream.to_std_out().await.unwrap();
+/// }
+/// ```
+pub trait AnyTextModel: Send + Sync + 'static {
+    /// Create a new type erased session for the model.
+    fn new_boxed_session(
+        &self,
+    ) -> Result<BoxedTextCompletionSession, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Add text to a type erased session and stream the response into the callback.
+    fn stream_text_boxed<'a>(
+        &'a self,
+        session: &'a mut BoxedTextCompletionSession,
+        text: &str,
+        sampler: crate::GenerationParameters,
+        on_token: BoxedTokenClosure,
+    ) -> BoxedMaybeFuture<'a>;
+}
+
+impl<S> AnyTextModel for S
+where
+    S: TextCompletionModel<
+            Error: Send + Sync + Error + 'static,
+            Session: TextCompletionSession<Error: Error + Send + Sync + 'static>
+                         + Clone
+                         + Send
+                         + Sync
+                         + 'static,
+        > + Send
+        + Sync
+        + 'static,
+{
+    fn new_boxed_session(
+        &self,
+    ) -> Result<BoxedTextCompletionSession, Box<dyn std::error::Error + Send + Sync>> {
+        DynCreateTextCompletionSession::new_session_boxed(self)
+    }
+
+    fn stream_text_boxed<'a>(
+        &'a self,
+        session: &'a mut BoxedTextCompletionSession,
+        text: &str,
+        sampler: crate::GenerationParameters,
+        on_token: BoxedTokenClosure,
+    ) -> BoxedMaybeFuture<'a> {
+        DynTextCompletionModel::add_messages_with_callback_boxed(
+            self, session, text, sampler, on_token,
+        )
     }
 }
 
@@ -264,14 +344,16 @@ impl<S: TextCompletionSession<Error: Error> + Clone + Send + Sync + 'static>
     }
 }
 
-pub(crate) type BoxedMaybeFuture<'a, T = ()> = Pin<
+/// A boxed future returned by the object safe model traits.
+pub type BoxedMaybeFuture<'a, T = ()> = Pin<
     Box<
         dyn Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>>
             + Send
             + 'a,
     >,
 >;
-pub(crate) type BoxedTokenClosure = Box<
+/// A boxed callback that is called with each token a type erased model generates.
+pub type BoxedTokenClosure = Box<
     dyn FnMut(String) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>>
         + Send
         + Sync
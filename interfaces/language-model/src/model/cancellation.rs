@@ -0,0 +1,149 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// A handle that stops a generation that is in progress. Add the token to a generation with
+/// [`GenerationParameters::with_cancellation_token`](crate::GenerationParameters::with_cancellation_token) and call
+/// [`CancellationToken::cancel`] from anywhere to stop the generation at the next token. Clones of the token share
+/// the same state. To stop one response, [`GenerationHandle::abort`](crate::GenerationHandle::abort) is a shortcut.
+///
+/// Cancelled generations return an error instead of the text generated so far, and local models clear the cache of
+/// the session so the memory is freed right away.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let token = CancellationToken::new();
+///     let mut stream = llm
+///         .complete("A long story about a cat: ")
+///         .with_sampler(GenerationParameters::default().with_cancellation_token(token.clone()));
+///     tokio::spawn(async move {
+///         tokio::time::sleep(Duration::from_secs(5)).await;
+///         token.cancel();
+///     });
+///     while let Some(text) = stream.next().await {
+///         print!("{text}");
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<CancellationState>,
+    /// Another token that also cancels anything this token is attached to
+    linked: Option<Arc<CancellationToken>>,
+}
+
+#[derive(Debug, Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    /// Wakes the tasks waiting in [`CancellationToken::cancelled`]
+    notify: Notify,
+}
+
+impl CancellationToken {
+    /// Create a new token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every generation that uses this token.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.state.notify.notify_waiters();
+    }
+
+    /// Check if the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+            || self
+                .linked
+                .as_ref()
+                .is_some_and(|linked| linked.is_cancelled())
+    }
+
+    /// Wait until the token is cancelled. This returns right away if the token was already cancelled.
+    pub async fn cancelled(&self) {
+        let own = async {
+            loop {
+                // Register for the wakeup before checking the flag so a cancel in between is not missed
+                let notified = self.state.notify.notified();
+                if self.state.cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        match &self.linked {
+            Some(linked) => {
+                tokio::select! {
+                    _ = own => {}
+                    _ = Box::pin(linked.cancelled()) => {}
+                }
+            }
+            None => own.await,
+        }
+    }
+
+    /// Get a token that shares the state of this token, but is also cancelled if `other` is cancelled.
+    pub(crate) fn linked_with(&self, other: &CancellationToken) -> Self {
+        Self {
+            state: self.state.clone(),
+            linked: Some(Arc::new(other.clone())),
+        }
+    }
+}
+
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+#[test]
+fn clones_share_cancellation() {
+    let token = CancellationToken::new();
+    let clone = token.clone();
+    assert!(!clone.is_cancelled());
+    token.cancel();
+    assert!(clone.is_cancelled());
+    assert_eq!(token, clone);
+    assert_ne!(token, CancellationToken::new());
+}
+
+#[test]
+fn linked_tokens_cancel_together() {
+    let token = CancellationToken::new();
+    let other = CancellationToken::new();
+    let linked = token.linked_with(&other);
+    other.cancel();
+    assert!(linked.is_cancelled());
+    assert!(!token.is_cancelled());
+
+    let linked = token.linked_with(&CancellationToken::new());
+    token.cancel();
+    assert!(linked.is_cancelled());
+}
+
+#[tokio::test]
+async fn waiting_tasks_wake_up_when_cancelled() {
+    let token = CancellationToken::new();
+    let other = CancellationToken::new();
+    let linked = token.linked_with(&other);
+    let waiting = tokio::spawn(async move { linked.cancelled().await });
+    tokio::task::yield_now().await;
+    assert!(!waiting.is_finished());
+    other.cancel();
+    tokio::time::timeout(std::time::Duration::from_secs(1), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // A token that is already cancelled doesn't wait
+    token.cancel();
+    token.cancelled().await;
+}
@@ -0,0 +1,22 @@
+/// A way a generation was degraded to recover from running out of device memory. Local model backends that recover
+/// from running out of memory report every fallback they used in
+/// [`Usage::degradations`](crate::Usage::degradations).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Degradation {
+    /// The prompt was processed in chunks of `chunk_size` tokens instead of all at once.
+    ChunkedPrefill {
+        /// The number of tokens processed at once.
+        chunk_size: usize,
+    },
+    /// The oldest tokens in the context were dropped so the rest of the context fits in memory.
+    ReducedContext {
+        /// The number of tokens that were dropped from the start of the context.
+        dropped_tokens: usize,
+    },
+    /// The tokens were processed on the CPU instead of the accelerator the model runs on.
+    CpuOffload {
+        /// The number of tokens processed on the CPU.
+        tokens: usize,
+    },
+}
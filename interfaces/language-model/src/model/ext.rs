@@ -4,6 +4,7 @@ use futures_util::Future;
 use futures_util::FutureExt;
 use futures_util::Stream;
 use futures_util::StreamExt;
+use kalosm_sample::{ArrayParser, CreateParserState};
 use std::any::Any;
 use std::error::Error;
 use std::future::IntoFuture;
@@ -14,9 +15,13 @@ use std::sync::OnceLock;
 use std::sync::RwLock;
 use std::task::Poll;
 
+use crate::ArrayItemStream;
+use crate::FinishReason;
+use crate::GenerationHandle;
 use crate::GenerationParameters;
 use crate::ModelConstraints;
 use crate::NoConstraints;
+use crate::Usage;
 
 use super::BoxedStructuredTextCompletionModel;
 use super::BoxedTextCompletionModel;
@@ -42,6 +47,7 @@ pub trait TextCompletionModelExt: CreateTextCompletionSession {
             task: OnceLock::new(),
             queued_tokens: None,
             result: None,
+            handle: GenerationHandle::new(),
         }
     }
 
@@ -139,6 +145,7 @@ pub struct TextCompletionBuilder<
     #[allow(clippy::type_complexity)]
     result: Option<Receiver<Result<Box<dyn Any + Send>, M::Error>>>,
     queued_tokens: Option<UnboundedReceiver<String>>,
+    handle: GenerationHandle,
 }
 
 impl<M: CreateTextCompletionSession, Constraints, Sampler>
@@ -182,6 +189,7 @@ impl<M: CreateTextCompletionSession, Constraints, Sampler>
             queued_tokens: None,
             result: None,
             task: OnceLock::new(),
+            handle: self.handle,
         }
     }
 
@@ -253,8 +261,29 @@ impl<M: CreateTextCompletionSession, Constraints, Sampler>
             queued_tokens: None,
             result: None,
             task: OnceLock::new(),
+            handle: self.handle,
         }
     }
+
+    /// Get a [`GenerationHandle`] that can abort the generation while it runs.
+    pub fn handle(&self) -> GenerationHandle {
+        self.handle.clone()
+    }
+
+    /// Stop the generation at the next token. See [`GenerationHandle::abort`].
+    pub fn abort(&self) {
+        self.handle.abort();
+    }
+
+    /// Get why the generation stopped once it finishes. See [`GenerationHandle::finish_reason`].
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        self.handle.finish_reason()
+    }
+
+    /// Get the tokens and time the generation used once it finishes. See [`GenerationHandle::usage`].
+    pub fn usage(&self) -> Option<Usage> {
+        self.handle.usage()
+    }
 }
 
 impl<M, Sampler> TextCompletionBuilder<M, NoConstraints, Sampler>
@@ -270,10 +299,11 @@ where
                 .model
                 .take()
                 .expect("TextCompletionBuilder cannot be turned into a future twice");
-            let sampler = self
+            let mut sampler = self
                 .sampler
                 .take()
                 .expect("TextCompletionBuilder cannot be turned into a future twice");
+            self.handle.attach(&mut sampler);
             let (mut tx, rx) = futures_channel::mpsc::unbounded();
             let (result_tx, result_rx) = futures_channel::oneshot::channel();
             self.queued_tokens = Some(rx);
@@ -371,10 +401,11 @@ where
                 .model
                 .take()
                 .expect("TextCompletionBuilder cannot be turned into a future twice");
-            let sampler = self
+            let mut sampler = self
                 .sampler
                 .take()
                 .expect("TextCompletionBuilder cannot be turned into a future twice");
+            self.handle.attach(&mut sampler);
             let constraints = self
                 .constraints
                 .take()
@@ -440,6 +471,45 @@ where
     }
 }
 
+impl<M, P, Sampler> TextCompletionBuilder<M, ArrayParser<P>, Sampler>
+where
+    M: CreateTextCompletionSession,
+    P: CreateParserState,
+{
+    /// Stream each item of the array as soon as the model finishes generating it, instead of waiting for the whole
+    /// array. Combine with [`ArrayParser::with_max_items`] and [`ArrayParser::with_unique_items`] to bound the list
+    /// and skip repeated items.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let model = Llama::new().await.unwrap();
+    /// let parser = ArrayParser::new(String::new_parser())
+    ///     .with_max_items(50)
+    ///     .with_unique_items();
+    /// let mut names = model
+    ///     .complete("A list of fantasy character names: ")
+    ///     .with_constraints(parser)
+    ///     .stream_items();
+    /// while let Some(name) = names.next().await {
+    ///     println!("{name}");
+    /// }
+    /// # }
+    /// ```
+    pub fn stream_items(self) -> ArrayItemStream<Self, P>
+    where
+        Self: Stream<Item = String> + Unpin,
+    {
+        let parser = self
+            .constraints
+            .clone()
+            .expect("stream_items must be called before the response is started");
+        ArrayItemStream::new(self, parser)
+    }
+}
+
 impl<M, Constraints, Sampler> IntoFuture for TextCompletionBuilder<M, Constraints, Sampler>
 where
     Constraints: ModelConstraints + Send + Sync + Unpin + 'static,
@@ -0,0 +1,21 @@
+/// Why a generation stopped. Model backends that support it call the handler set with
+/// [`GenerationParameters::with_finish_handler`](crate::GenerationParameters::with_finish_handler) once the
+/// generation finishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FinishReason {
+    /// The model generated its stop token, or the constraints were satisfied.
+    Stop,
+    /// The maximum number of tokens was generated.
+    MaxTokens,
+    /// The model generated the stop_on string or one of the stop sequences.
+    StopSequence,
+    /// A [`StopCriteria`](crate::StopCriteria) stopped generation.
+    StopCriteria,
+    /// The timeout set with [`GenerationParameters::with_timeout`](crate::GenerationParameters::with_timeout)
+    /// passed. The text generated before the timeout is still returned.
+    TimedOut,
+}
+
+/// A finish handler that is shared between clones of [`GenerationParameters`](crate::GenerationParameters).
+pub(crate) type SharedFinishHandler = crate::Shared<dyn FnMut(FinishReason) + Send>;
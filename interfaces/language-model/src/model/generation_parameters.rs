@@ -8,6 +8,58 @@ use llm_samplers::configure::SamplerChainBuilder;
 #[cfg(feature = "sample")]
 use llm_samplers::prelude::*;
 
+/// The priority of a generation request. Models that share one device between many requests run interactive
+/// requests before background requests. Background requests are paused between tokens while interactive requests
+/// run, so a long running background job like indexing or summarization doesn't slow down a chat on the same model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// A request a user is waiting on. This is the default.
+    #[default]
+    Interactive,
+    /// A request that can be paused while interactive requests run.
+    Background,
+}
+
+/// The method local models use to pick a token after the repetition penalties and temperature are applied. Select
+/// it per request with [`GenerationParameters::with_sampling_method`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SamplingMethod {
+    /// Mirostat v2 sampling, which keeps the surprise of each token near the target set with
+    /// [`GenerationParameters::with_tau`]. This is the default.
+    #[default]
+    Mirostat2,
+    /// Min-p sampling, which removes every token that is less likely than `min_p` times the probability of the most
+    /// likely token and samples from the rest.
+    MinP {
+        /// The fraction of the probability of the most likely token a token needs to be kept, between 0 and 1.
+        min_p: f32,
+    },
+    /// Locally typical sampling, which keeps the tokens with a surprise closest to the expected surprise of the
+    /// distribution until their probabilities add up to `p` and samples from them.
+    Typical {
+        /// The total probability of the tokens that are kept, between 0 and 1.
+        p: f32,
+    },
+}
+
+#[cfg(feature = "sample")]
+impl SamplingMethod {
+    fn hash_parameters(&self, hasher: &mut impl Hasher) {
+        match self {
+            SamplingMethod::Mirostat2 => 0u8.hash(hasher),
+            SamplingMethod::MinP { min_p } => {
+                1u8.hash(hasher);
+                min_p.to_le_bytes().hash(hasher);
+            }
+            SamplingMethod::Typical { p } => {
+                2u8.hash(hasher);
+                p.to_le_bytes().hash(hasher);
+            }
+        }
+    }
+}
+
 /// Parameters to use when generating text.
 #[derive(Debug)]
 pub struct GenerationParameters {
@@ -17,11 +69,26 @@ pub struct GenerationParameters {
     pub(crate) mu: f32,
     pub(crate) top_p: f64,
     pub(crate) top_k: u32,
+    pub(crate) sampling_method: SamplingMethod,
     pub(crate) repetition_penalty: f32,
     pub(crate) repetition_penalty_range: u32,
+    pub(crate) frequency_penalty: f32,
+    pub(crate) presence_penalty: f32,
     pub(crate) max_length: u32,
     pub(crate) stop_on: Option<String>,
+    pub(crate) stop_sequences: Option<crate::StopSequences>,
+    pub(crate) token_healing: bool,
     pub(crate) seed: Option<u64>,
+    pub(crate) watermark: Option<crate::Watermark>,
+    pub(crate) stop_criteria: Option<crate::SharedStopCriteria>,
+    pub(crate) priority: Priority,
+    pub(crate) prefill_progress: Option<crate::SharedPrefillHandler>,
+    pub(crate) prompt_lookup: Option<crate::PromptLookup>,
+    pub(crate) logprobs: Option<crate::SharedLogprobsHandler>,
+    pub(crate) cancellation_token: Option<crate::CancellationToken>,
+    pub(crate) timeout: Option<std::time::Duration>,
+    pub(crate) finish_handler: Option<crate::SharedFinishHandler>,
+    pub(crate) usage_handler: Option<crate::SharedUsageHandler>,
     #[cfg(feature = "sample")]
     sampler: Option<(u64, SamplerChain)>,
 }
@@ -33,10 +100,25 @@ impl PartialEq for GenerationParameters {
             && self.tau == other.tau
             && self.mu == other.mu
             && self.top_p == other.top_p
+            && self.sampling_method == other.sampling_method
             && self.repetition_penalty == other.repetition_penalty
             && self.repetition_penalty_range == other.repetition_penalty_range
+            && self.frequency_penalty == other.frequency_penalty
+            && self.presence_penalty == other.presence_penalty
             && self.max_length == other.max_length
             && self.stop_on == other.stop_on
+            && self.stop_sequences == other.stop_sequences
+            && self.token_healing == other.token_healing
+            && self.watermark == other.watermark
+            && self.stop_criteria == other.stop_criteria
+            && self.priority == other.priority
+            && self.prefill_progress == other.prefill_progress
+            && self.prompt_lookup == other.prompt_lookup
+            && self.logprobs == other.logprobs
+            && self.cancellation_token == other.cancellation_token
+            && self.timeout == other.timeout
+            && self.finish_handler == other.finish_handler
+            && self.usage_handler == other.usage_handler
     }
 }
 
@@ -49,11 +131,26 @@ impl Clone for GenerationParameters {
             mu: self.mu,
             top_p: self.top_p,
             top_k: self.top_k,
+            sampling_method: self.sampling_method,
             repetition_penalty: self.repetition_penalty,
             repetition_penalty_range: self.repetition_penalty_range,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
             max_length: self.max_length,
             stop_on: self.stop_on.clone(),
-            seed: None,
+            stop_sequences: self.stop_sequences.clone(),
+            token_healing: self.token_healing,
+            seed: self.seed,
+            watermark: self.watermark,
+            stop_criteria: self.stop_criteria.clone(),
+            priority: self.priority,
+            prefill_progress: self.prefill_progress.clone(),
+            prompt_lookup: self.prompt_lookup,
+            logprobs: self.logprobs.clone(),
+            cancellation_token: self.cancellation_token.clone(),
+            timeout: self.timeout,
+            finish_handler: self.finish_handler.clone(),
+            usage_handler: self.usage_handler.clone(),
             #[cfg(feature = "sample")]
             sampler: None,
         }
@@ -73,6 +170,9 @@ impl Sampler for GenerationParameters {
         res: &mut dyn HasSamplerResources,
         logits: &'a mut Logits,
     ) -> anyhow::Result<&'a mut Logits> {
+        if let Some(mut watermark) = self.watermark {
+            watermark.sample(res, logits)?;
+        }
         self.with_sampler(|sampler| sampler.sample(res, logits))
     }
 
@@ -81,6 +181,9 @@ impl Sampler for GenerationParameters {
         res: &mut dyn HasSamplerResources,
         logits: &mut Logits,
     ) -> anyhow::Result<Option<TID>> {
+        if let Some(mut watermark) = self.watermark {
+            watermark.sample(res, logits)?;
+        }
         self.with_sampler(|sampler| sampler.sample_token(res, logits))
     }
 
@@ -99,11 +202,26 @@ impl GenerationParameters {
             mu: 10.,
             top_p: 1.0,
             top_k: 1,
+            sampling_method: SamplingMethod::Mirostat2,
             repetition_penalty: 1.3,
             repetition_penalty_range: 64,
+            frequency_penalty: 0.,
+            presence_penalty: 0.,
             max_length: u32::MAX,
             stop_on: None,
+            stop_sequences: None,
+            token_healing: false,
             seed: None,
+            watermark: None,
+            stop_criteria: None,
+            priority: Priority::Interactive,
+            prefill_progress: None,
+            prompt_lookup: None,
+            logprobs: None,
+            cancellation_token: None,
+            timeout: None,
+            finish_handler: None,
+            usage_handler: None,
             #[cfg(feature = "sample")]
             sampler: None,
         }
@@ -116,9 +234,12 @@ impl GenerationParameters {
         self.mu.to_le_bytes().hash(&mut hash);
         self.repetition_penalty.to_le_bytes().hash(&mut hash);
         self.repetition_penalty_range.hash(&mut hash);
+        self.frequency_penalty.to_le_bytes().hash(&mut hash);
+        self.presence_penalty.to_le_bytes().hash(&mut hash);
         self.tau.to_le_bytes().hash(&mut hash);
         self.top_p.to_le_bytes().hash(&mut hash);
         self.temperature.to_le_bytes().hash(&mut hash);
+        self.sampling_method.hash_parameters(&mut hash);
         self.max_length.hash(&mut hash);
         let hash = hash.finish();
         if let Some((old_hash, sampler)) = &mut self.sampler {
@@ -143,6 +264,9 @@ impl GenerationParameters {
             mu,
             repetition_penalty,
             repetition_penalty_range,
+            frequency_penalty,
+            presence_penalty,
+            sampling_method,
             top_p: _,
             max_length: _,
             stop_on: _,
@@ -154,7 +278,9 @@ impl GenerationParameters {
         let mu = *mu;
         let repetition_penalty = *repetition_penalty;
         let repetition_penalty_range = *repetition_penalty_range;
-        SamplerChainBuilder::from([
+        let frequency_penalty = *frequency_penalty;
+        let presence_penalty = *presence_penalty;
+        let mut slots = vec![
             (
                 "repetition",
                 SamplerSlot::new_static(move || {
@@ -167,7 +293,14 @@ impl GenerationParameters {
             ),
             (
                 "freqpresence",
-                SamplerSlot::new_static(move || Box::new(SampleFreqPresence::default().last_n(64))),
+                SamplerSlot::new_static(move || {
+                    Box::new(
+                        SampleFreqPresence::default()
+                            .frequency(frequency_penalty)
+                            .presence(presence_penalty)
+                            .last_n(repetition_penalty_range as usize),
+                    )
+                }),
             ),
             (
                 "seqrepetition",
@@ -179,14 +312,36 @@ impl GenerationParameters {
                     Box::new(SampleTemperature::default().temperature(temperature))
                 }),
             ),
-            (
+        ];
+        match *sampling_method {
+            SamplingMethod::Mirostat2 => slots.push((
                 "mirostat2",
                 SamplerSlot::new_static(move || {
                     Box::new(SampleMirostat2::default().tau(tau).eta(eta).mu(mu))
                 }),
-            ),
-        ])
-        .into_chain()
+            )),
+            SamplingMethod::MinP { min_p } => {
+                slots.push((
+                    "minp",
+                    SamplerSlot::new_static(move || Box::new(SampleMinP::default().p(min_p))),
+                ));
+                slots.push((
+                    "randdistrib",
+                    SamplerSlot::new_static(move || Box::<SampleRandDistrib>::default()),
+                ));
+            }
+            SamplingMethod::Typical { p } => {
+                slots.push((
+                    "locallytypical",
+                    SamplerSlot::new_static(move || Box::new(SampleLocallyTypical::default().p(p))),
+                ));
+                slots.push((
+                    "randdistrib",
+                    SamplerSlot::new_static(move || Box::<SampleRandDistrib>::default()),
+                ));
+            }
+        }
+        SamplerChainBuilder::from(slots).into_chain()
     }
 
     /// Set the top_p parameter to the generation parameters (only used by the OpenAI API).
@@ -218,6 +373,8 @@ impl GenerationParameters {
             temperature,
             repetition_penalty,
             repetition_penalty_range,
+            frequency_penalty,
+            presence_penalty,
             ..
         } = self;
         SamplerChainBuilder::from([
@@ -233,7 +390,14 @@ impl GenerationParameters {
             ),
             (
                 "freqpresence",
-                SamplerSlot::new_static(move || Box::new(SampleFreqPresence::default().last_n(64))),
+                SamplerSlot::new_static(move || {
+                    Box::new(
+                        SampleFreqPresence::default()
+                            .frequency(frequency_penalty)
+                            .presence(presence_penalty)
+                            .last_n(repetition_penalty_range as usize),
+                    )
+                }),
             ),
             (
                 "seqrepetition",
@@ -249,6 +413,29 @@ impl GenerationParameters {
         .into_chain()
     }
 
+    /// Set the [`SamplingMethod`] local models use to pick each token. (Defaults to [`SamplingMethod::Mirostat2`])
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let parameters = GenerationParameters::default()
+    ///     .with_temperature(1.0)
+    ///     .with_sampling_method(SamplingMethod::MinP { min_p: 0.05 });
+    /// model
+    ///     .complete("Once upon a time")
+    ///     .with_sampler(parameters)
+    ///     .to_std_out()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_sampling_method(mut self, sampling_method: SamplingMethod) -> Self {
+        self.sampling_method = sampling_method;
+        self
+    }
+
     /// Set the temperature to use when generating text.
     pub fn with_temperature(mut self, temperature: f32) -> Self {
         self.temperature = temperature;
@@ -273,18 +460,54 @@ impl GenerationParameters {
         self
     }
 
-    /// Set the repetition penalty to use when generating text.
+    /// Set the repetition penalty to use when generating text. Like llama.cpp, the logits of tokens in the penalty
+    /// window are divided by the penalty if they are positive and multiplied by it if they are negative. A penalty of
+    /// 1.0 disables it. (Defaults to 1.3)
     pub fn with_repetition_penalty(mut self, repetition_penalty: f32) -> Self {
         self.repetition_penalty = repetition_penalty;
         self
     }
 
-    /// Set the repetition penalty range to use when generating text.
+    /// Set the number of most recent tokens the repetition, frequency and presence penalties look at.
+    /// (Defaults to 64)
     pub fn with_repetition_penalty_range(mut self, repetition_penalty_range: u32) -> Self {
         self.repetition_penalty_range = repetition_penalty_range;
         self
     }
 
+    /// Set the OpenAI style frequency penalty. The penalty is subtracted from the logit of a token once for every
+    /// time it appears in the penalty window, so tokens that repeat a lot become less likely. (Defaults to 0.0)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let parameters = GenerationParameters::default()
+    ///     .with_repetition_penalty(1.1)
+    ///     .with_frequency_penalty(0.5)
+    ///     .with_presence_penalty(0.5)
+    ///     .with_repetition_penalty_range(256);
+    /// model
+    ///     .complete("Write a long story about a dragon:")
+    ///     .with_sampler(parameters)
+    ///     .to_std_out()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.frequency_penalty = frequency_penalty;
+        self
+    }
+
+    /// Set the OpenAI style presence penalty. The penalty is subtracted from the logit of every token that appears
+    /// in the penalty window, no matter how many times it appears. (Defaults to 0.0)
+    pub fn with_presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.presence_penalty = presence_penalty;
+        self
+    }
+
     /// Set the maximum length to use when generating text.
     pub fn with_max_length(mut self, max_length: u32) -> Self {
         self.max_length = max_length;
@@ -297,12 +520,278 @@ impl GenerationParameters {
         self
     }
 
-    /// Set the seed to use when generating text.
+    /// Stop generating text when any of the [`StopSequences`](crate::StopSequences) is generated. Stop sequences are
+    /// matched even if they are split between several tokens. This is checked along with
+    /// [`GenerationParameters::with_stop_on`].
+    pub fn with_stop_sequences(
+        mut self,
+        stop_sequences: impl Into<Option<crate::StopSequences>>,
+    ) -> Self {
+        self.stop_sequences = stop_sequences.into();
+        self
+    }
+
+    /// Heal the boundary between the prompt and the generated text. When a prompt ends in the middle of a token (for
+    /// example with a trailing space or part of a word), the last prompt token is removed and the first generated
+    /// token must start with its text. The text of the removed token is not repeated in the output. Model backends
+    /// that don't support token healing ignore it. (Defaults to false)
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let llm = Llama::new().await.unwrap();
+    ///     // Without token healing, the model rarely continues a url that was cut off at `http:`
+    ///     llm.complete("The link to the kalosm repository is http:")
+    ///         .with_sampler(GenerationParameters::default().with_token_healing(true))
+    ///         .to_std_out()
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn with_token_healing(mut self, token_healing: bool) -> Self {
+        self.token_healing = token_healing;
+        self
+    }
+
+    /// Set the seed to use when generating text. With a seed, the same model and prompt always generate the same text,
+    /// which makes snapshot tests possible. The seed is kept when the parameters are cloned, so every turn of a chat
+    /// with these parameters uses it.
+    ///
+    /// Local models respect the seed with every [`SamplingMethod`] and with structured generation. OpenAI compatible
+    /// models send the seed to the API, which only makes a best effort to be deterministic. Anthropic models ignore
+    /// the seed.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let parameters = GenerationParameters::default().with_seed(42);
+    /// let first = model
+    ///     .complete("Once upon a time")
+    ///     .with_sampler(parameters.clone())
+    ///     .await?;
+    /// let second = model
+    ///     .complete("Once upon a time")
+    ///     .with_sampler(parameters)
+    ///     .await?;
+    /// assert_eq!(first, second);
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn with_seed(mut self, seed: impl Into<Option<u64>>) -> Self {
         self.seed = seed.into();
         self
     }
 
+    /// Bias generation towards the green list of a [`Watermark`](crate::Watermark) so the text can be identified later.
+    pub fn with_watermark(mut self, watermark: impl Into<Option<crate::Watermark>>) -> Self {
+        self.watermark = watermark.into();
+        self
+    }
+
+    /// Add a [`StopCriteria`](crate::StopCriteria) that is checked after every generated token. If stop criteria
+    /// were already added, generation stops when any of them stop.
+    ///
+    /// The criteria is shared between clones of the generation parameters and is reset before each generation.
+    pub fn with_stop_criteria(mut self, stop_criteria: impl crate::StopCriteria + 'static) -> Self {
+        use crate::StopCriteria;
+        let stop_criteria: std::sync::Arc<std::sync::Mutex<dyn crate::StopCriteria>> =
+            match self.stop_criteria.take() {
+                Some(existing) => {
+                    std::sync::Arc::new(std::sync::Mutex::new(existing.0.or(stop_criteria)))
+                }
+                None => std::sync::Arc::new(std::sync::Mutex::new(stop_criteria)),
+            };
+        self.stop_criteria = Some(crate::Shared(stop_criteria));
+        self
+    }
+
+    /// Stop the generation at the next token when the [`CancellationToken`](crate::CancellationToken) is cancelled.
+    pub fn with_cancellation_token(
+        mut self,
+        token: impl Into<Option<crate::CancellationToken>>,
+    ) -> Self {
+        self.cancellation_token = token.into();
+        self
+    }
+
+    /// Stop generating once the request has run for the duration, including the time spent waiting for the model
+    /// and processing the prompt. The text generated before the timeout is returned and the finish handler is
+    /// called with [`FinishReason::TimedOut`](crate::FinishReason::TimedOut). Constrained generations can't return
+    /// incomplete output, so they return an error instead.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use std::time::Duration;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let parameters = GenerationParameters::default()
+    ///     .with_timeout(Duration::from_secs(2))
+    ///     .with_finish_handler(|reason| {
+    ///         if reason == FinishReason::TimedOut {
+    ///             println!("\n(timed out)");
+    ///         }
+    ///     });
+    /// model
+    ///     .complete("A long story about a cat: ")
+    ///     .with_sampler(parameters)
+    ///     .to_std_out()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_timeout(mut self, timeout: impl Into<Option<std::time::Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// Set a handler that is called with the [`FinishReason`](crate::FinishReason) once generation stops. The
+    /// finish reason is also available from
+    /// [`TextCompletionBuilder::finish_reason`](crate::TextCompletionBuilder::finish_reason) and
+    /// [`ChatResponseBuilder::finish_reason`](crate::ChatResponseBuilder::finish_reason) once the response is
+    /// complete.
+    pub fn with_finish_handler(
+        mut self,
+        handler: impl FnMut(crate::FinishReason) + Send + 'static,
+    ) -> Self {
+        self.finish_handler = Some(crate::Shared(std::sync::Arc::new(std::sync::Mutex::new(
+            handler,
+        ))));
+        self
+    }
+
+    /// Set a handler that is called with the [`Usage`](crate::Usage) of the generation once it finishes. Chat
+    /// responses report the usage of each turn. Remote models report the token counts returned by the API, or zero
+    /// if the API doesn't return them. The usage is also available from
+    /// [`TextCompletionBuilder::usage`](crate::TextCompletionBuilder::usage) and
+    /// [`ChatResponseBuilder::usage`](crate::ChatResponseBuilder::usage) once the response is complete.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new_chat().await?;
+    /// let mut chat = model.chat();
+    /// let parameters = GenerationParameters::default().with_usage_handler(|usage| {
+    ///     println!(
+    ///         "{} prompt tokens, {} completion tokens at {:.1} tokens/s",
+    ///         usage.prompt_tokens(),
+    ///         usage.completion_tokens(),
+    ///         usage.tokens_per_second()
+    ///     );
+    /// });
+    /// chat("Hello!").with_sampler(parameters).to_std_out().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_usage_handler(
+        mut self,
+        handler: impl FnMut(crate::Usage) + Send + 'static,
+    ) -> Self {
+        self.usage_handler = Some(crate::Shared(std::sync::Arc::new(std::sync::Mutex::new(
+            handler,
+        ))));
+        self
+    }
+
+    /// Set the [`Priority`] of the request. Background requests are paused between tokens while interactive requests
+    /// on the same model run. (Defaults to [`Priority::Interactive`])
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Call a handler with the [`PrefillProgress`](crate::PrefillProgress) while a long prompt is fed into the model,
+    /// before the first token is generated. Model backends that don't support prefill progress ignore the handler.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let long_document = std::fs::read_to_string("document.txt")?;
+    /// let parameters = GenerationParameters::default().with_prefill_progress(|progress| {
+    ///     println!(
+    ///         "Read {}/{} prompt tokens, about {:?} left",
+    ///         progress.tokens_processed(),
+    ///         progress.total_tokens(),
+    ///         progress.estimate_time_remaining()
+    ///     );
+    /// });
+    /// model
+    ///     .complete(format!("{long_document}\n\nSummary:"))
+    ///     .with_sampler(parameters)
+    ///     .to_std_out()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_prefill_progress(
+        mut self,
+        handler: impl FnMut(crate::PrefillProgress) + Send + 'static,
+    ) -> Self {
+        self.prefill_progress = Some(crate::Shared(std::sync::Arc::new(std::sync::Mutex::new(
+            handler,
+        ))));
+        self
+    }
+
+    /// Speed up generation with [`PromptLookup`](crate::PromptLookup) decoding, which drafts the next tokens from
+    /// earlier in the context. Model backends that don't support prompt lookup ignore it.
+    pub fn with_prompt_lookup(
+        mut self,
+        prompt_lookup: impl Into<Option<crate::PromptLookup>>,
+    ) -> Self {
+        self.prompt_lookup = prompt_lookup.into();
+        self
+    }
+
+    /// Call a handler with the [`TokenLogprobs`](crate::TokenLogprobs) of each generated token and the
+    /// `top_alternatives` most likely tokens at its position. The log probabilities are from the model before
+    /// sampling. Model backends that don't support log probabilities ignore the handler.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use std::sync::{Arc, Mutex};
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let logprobs = Arc::new(Mutex::new(Vec::new()));
+    /// let parameters = GenerationParameters::default().with_logprobs(5, {
+    ///     let logprobs = logprobs.clone();
+    ///     move |token: TokenLogprobs| logprobs.lock().unwrap().push(token)
+    /// });
+    /// let answer = model
+    ///     .complete("The capital of France is")
+    ///     .with_sampler(parameters)
+    ///     .await?;
+    /// let confidence: f32 = logprobs
+    ///     .lock()
+    ///     .unwrap()
+    ///     .iter()
+    ///     .map(|token| token.sampled().logprob())
+    ///     .sum();
+    /// println!("{answer} (log probability {confidence})");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_logprobs(
+        mut self,
+        top_alternatives: usize,
+        handler: impl FnMut(crate::TokenLogprobs) + Send + 'static,
+    ) -> Self {
+        self.logprobs = Some(crate::SharedLogprobsHandler {
+            top_alternatives,
+            handler: crate::Shared(std::sync::Arc::new(std::sync::Mutex::new(handler))),
+        });
+        self
+    }
+
     /// Get the temperature to use when generating text.
     pub fn temperature(&self) -> f32 {
         self.temperature
@@ -323,6 +812,11 @@ impl GenerationParameters {
         self.mu
     }
 
+    /// Get the method used to pick each token.
+    pub fn sampling_method(&self) -> SamplingMethod {
+        self.sampling_method
+    }
+
     /// Get the repetition penalty to use when generating text.
     pub fn repetition_penalty(&self) -> f32 {
         self.repetition_penalty
@@ -333,6 +827,16 @@ impl GenerationParameters {
         self.repetition_penalty_range
     }
 
+    /// Get the frequency penalty to use when generating text.
+    pub fn frequency_penalty(&self) -> f32 {
+        self.frequency_penalty
+    }
+
+    /// Get the presence penalty to use when generating text.
+    pub fn presence_penalty(&self) -> f32 {
+        self.presence_penalty
+    }
+
     /// Get the maximum length to use when generating text.
     pub fn max_length(&self) -> u32 {
         self.max_length
@@ -343,8 +847,205 @@ impl GenerationParameters {
         self.stop_on.as_deref()
     }
 
+    /// Get the stop sequences to stop on when generating text.
+    pub fn stop_sequences(&self) -> Option<&crate::StopSequences> {
+        self.stop_sequences.as_ref()
+    }
+
+    /// Get every string that stops generation: the [`GenerationParameters::stop_on`] string followed by the
+    /// [`GenerationParameters::stop_sequences`]. This is used by model backends that send the stop strings to a
+    /// remote API.
+    pub fn stop_strings(&self) -> Vec<String> {
+        self.stop_on
+            .iter()
+            .chain(
+                self.stop_sequences
+                    .iter()
+                    .flat_map(|stops| stops.sequences()),
+            )
+            .cloned()
+            .collect()
+    }
+
+    /// Check if the boundary between the prompt and the generated text is healed.
+    pub fn token_healing(&self) -> bool {
+        self.token_healing
+    }
+
     /// Get the seed to use when generating text.
     pub fn seed(&self) -> Option<u64> {
         self.seed
     }
+
+    /// Get the watermark to use when generating text.
+    pub fn watermark(&self) -> Option<crate::Watermark> {
+        self.watermark
+    }
+
+    /// Get the stop criteria that is checked after every generated token.
+    pub fn stop_criteria(
+        &self,
+    ) -> Option<std::sync::Arc<std::sync::Mutex<dyn crate::StopCriteria>>> {
+        self.stop_criteria
+            .as_ref()
+            .map(|criteria| criteria.0.clone())
+    }
+
+    /// Get the token that cancels the generation.
+    pub fn cancellation_token(&self) -> Option<&crate::CancellationToken> {
+        self.cancellation_token.as_ref()
+    }
+
+    /// Get the longest the request can run before generation stops.
+    pub fn timeout(&self) -> Option<std::time::Duration> {
+        self.timeout
+    }
+
+    /// Get the handler that is called with the reason generation stopped.
+    #[allow(clippy::type_complexity)]
+    pub fn finish_handler(
+        &self,
+    ) -> Option<std::sync::Arc<std::sync::Mutex<dyn FnMut(crate::FinishReason) + Send>>> {
+        self.finish_handler
+            .as_ref()
+            .map(|handler| handler.0.clone())
+    }
+
+    /// Get the handler that is called with the usage of the generation.
+    #[allow(clippy::type_complexity)]
+    pub fn usage_handler(
+        &self,
+    ) -> Option<std::sync::Arc<std::sync::Mutex<dyn FnMut(crate::Usage) + Send>>> {
+        self.usage_handler.as_ref().map(|handler| handler.0.clone())
+    }
+
+    /// Get the priority of the request.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Get the handler that is called with the progress of the prompt prefill.
+    #[allow(clippy::type_complexity)]
+    pub fn prefill_progress(
+        &self,
+    ) -> Option<std::sync::Arc<std::sync::Mutex<dyn FnMut(crate::PrefillProgress) + Send>>> {
+        self.prefill_progress
+            .as_ref()
+            .map(|handler| handler.0.clone())
+    }
+
+    /// Get the prompt lookup settings to use when generating text.
+    pub fn prompt_lookup(&self) -> Option<crate::PromptLookup> {
+        self.prompt_lookup
+    }
+
+    /// Get the number of alternative tokens to report with the log probability of each generated token.
+    pub fn top_logprobs(&self) -> usize {
+        self.logprobs
+            .as_ref()
+            .map_or(0, |logprobs| logprobs.top_alternatives)
+    }
+
+    /// Get the handler that is called with the log probabilities of each generated token.
+    #[allow(clippy::type_complexity)]
+    pub fn logprobs_handler(
+        &self,
+    ) -> Option<std::sync::Arc<std::sync::Mutex<dyn FnMut(crate::TokenLogprobs) + Send>>> {
+        self.logprobs
+            .as_ref()
+            .map(|logprobs| logprobs.handler.0.clone())
+    }
+}
+
+#[test]
+fn clone_keeps_seed() {
+    let parameters = GenerationParameters::new().with_seed(42);
+    assert_eq!(parameters.clone().seed(), Some(42));
+}
+
+#[cfg(all(feature = "sample", feature = "serde"))]
+#[test]
+fn min_p_and_typical_sample_from_the_tokens_they_keep() {
+    use crate::RecordedSampling;
+    use rand::SeedableRng;
+
+    // The softmax of the logits is about [0.535, 0.438, 0.027, 0.0002]
+    let logits = [2.0f32, 1.8, -1.0, -6.0];
+    let sample = |parameters: &GenerationParameters| {
+        let mut sampler = parameters.sampler();
+        let mut resources = SimpleSamplerResources::new(
+            Some(Box::new(rand::rngs::StdRng::seed_from_u64(0))),
+            Some(Vec::new()),
+        );
+        (0..200)
+            .map(|_| {
+                let mut logits = Logits::try_from_iter(logits.iter().copied()).unwrap();
+                sampler
+                    .sample_token(&mut resources, &mut logits)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for (method, kept) in [
+        // Min-p keeps the tokens with at least a fifth of the probability of the most likely token
+        (SamplingMethod::MinP { min_p: 0.2 }, &[0, 1][..]),
+        // Token 1 has the surprise closest to the entropy and has more than 0.4 of the probability on its own
+        (SamplingMethod::Typical { p: 0.4 }, &[1][..]),
+    ] {
+        let parameters = GenerationParameters::new()
+            .with_temperature(1.0)
+            .with_sampling_method(method);
+        let tokens = sample(&parameters);
+        for token in kept {
+            assert!(tokens.contains(token), "{method:?} never sampled {token}");
+        }
+        assert!(
+            tokens.iter().all(|token| kept.contains(token)),
+            "{method:?} sampled a token it should remove: {tokens:?}"
+        );
+
+        // The method survives a round trip through a chat record and builds the same sampler
+        let recorded = RecordedSampling::from(&parameters);
+        let json = serde_json::to_string(&recorded).unwrap();
+        let recorded: RecordedSampling = serde_json::from_str(&json).unwrap();
+        let restored = GenerationParameters::from(&recorded);
+        assert_eq!(restored.sampling_method(), method);
+        assert_eq!(sample(&restored), tokens);
+    }
+}
+
+#[cfg(feature = "sample")]
+#[test]
+fn frequency_and_presence_penalties_only_apply_inside_the_repetition_range() {
+    use rand::SeedableRng;
+
+    let parameters = GenerationParameters::new()
+        .with_temperature(1.0)
+        .with_repetition_penalty(1.0)
+        .with_repetition_penalty_range(3)
+        .with_frequency_penalty(0.5)
+        .with_presence_penalty(0.25);
+    let mut sampler = parameters.bias_only_sampler();
+    // Only the last three tokens are in the range: token 1 once and token 3 twice. Token 0 was repeated before the
+    // range, and token 2 was never generated.
+    let mut resources = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(0))),
+        Some(vec![0, 0, 0, 1, 3, 3]),
+    );
+    let mut logits = Logits::try_from_iter([1.0f32, 1.0, 1.0, 1.0]).unwrap();
+    sampler.sample(&mut resources, &mut logits).unwrap();
+
+    let logit = |token: u32| {
+        logits
+            .iter()
+            .find(|logit| logit.token_id == token)
+            .unwrap()
+            .logit
+    };
+    assert_eq!(logit(0), 1.0);
+    assert_eq!(logit(1), 1.0 - 0.5 - 0.25);
+    assert_eq!(logit(2), 1.0);
+    assert_eq!(logit(3), 1.0 - 2.0 * 0.5 - 0.25);
 }
@@ -0,0 +1,157 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+use crate::{CancellationToken, FinishReason, GenerationParameters, Shared, Usage};
+
+/// A handle to a generation that is shared with the response builder. Get it from
+/// [`TextCompletionBuilder::handle`](crate::TextCompletionBuilder::handle) or
+/// [`ChatResponseBuilder::handle`](crate::ChatResponseBuilder::handle) and call [`GenerationHandle::abort`] from
+/// anywhere to stop the generation at the next token. Clones of the handle refer to the same generation.
+///
+/// Once the generation finishes, [`GenerationHandle::finish_reason`] tells you why it stopped and
+/// [`GenerationHandle::usage`] how many tokens and how much time it used.
+///
+/// Only generations that use [`GenerationParameters`] as the sampler can be aborted or report a finish reason and
+/// usage.
+/// Dropping the response stops any generation without clearing the session.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let mut stream = llm.complete("A long story about a cat: ");
+///     let handle = stream.handle();
+///     tokio::spawn(async move {
+///         tokio::time::sleep(Duration::from_secs(5)).await;
+///         handle.abort();
+///     });
+///     while let Some(text) = stream.next().await {
+///         print!("{text}");
+///     }
+///     println!("\n{:?} {:?}", stream.finish_reason(), stream.usage());
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GenerationHandle {
+    cancellation_token: CancellationToken,
+    finish_reason: Arc<Mutex<Option<FinishReason>>>,
+    usage: Arc<Mutex<Option<Usage>>>,
+}
+
+impl GenerationHandle {
+    /// Create a handle for a new generation.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the generation at the next token. This cancels the generation like
+    /// [`CancellationToken::cancel`].
+    pub fn abort(&self) {
+        self.cancellation_token.cancel();
+    }
+
+    /// Check if the generation was aborted.
+    pub fn is_aborted(&self) -> bool {
+        self.cancellation_token.is_cancelled()
+    }
+
+    /// Get why the generation stopped, or `None` if it is still running, failed, or the model doesn't report finish
+    /// reasons.
+    pub fn finish_reason(&self) -> Option<FinishReason> {
+        *self.finish_reason.lock().unwrap()
+    }
+
+    /// Get the tokens and time the generation used, or `None` if it is still running, failed, or the model doesn't
+    /// report usage.
+    pub fn usage(&self) -> Option<Usage> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// Get the cancellation token that aborts this generation. It is cancelled along with any token set with
+    /// [`GenerationParameters::with_cancellation_token`].
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation_token
+    }
+
+    /// Attach the handle to the sampler of the generation before it starts. The handle records the finish reason and
+    /// usage before any handlers already set on the parameters are called.
+    pub(crate) fn attach(&self, sampler: &mut dyn Any) {
+        if let Some(parameters) = sampler.downcast_mut::<GenerationParameters>() {
+            parameters.cancellation_token = Some(match &parameters.cancellation_token {
+                Some(token) => self.cancellation_token.linked_with(token),
+                None => self.cancellation_token.clone(),
+            });
+            parameters.finish_handler = Some(record(
+                self.finish_reason.clone(),
+                parameters.finish_handler.take(),
+            ));
+            parameters.usage_handler =
+                Some(record(self.usage.clone(), parameters.usage_handler.take()));
+        }
+    }
+}
+
+/// Create a handler that stores the value in `slot` and then calls `handler`.
+fn record<T: Clone + Send + 'static>(
+    slot: Arc<Mutex<Option<T>>>,
+    handler: Option<Shared<dyn FnMut(T) + Send>>,
+) -> Shared<dyn FnMut(T) + Send> {
+    Shared(Arc::new(Mutex::new(move |value: T| {
+        *slot.lock().unwrap() = Some(value.clone());
+        if let Some(handler) = &handler {
+            (handler.0.lock().unwrap())(value);
+        }
+    })))
+}
+
+#[test]
+fn abort_cancels_attached_parameters() {
+    let user_token = CancellationToken::new();
+    let mut parameters = GenerationParameters::new().with_cancellation_token(user_token.clone());
+    let handle = GenerationHandle::new();
+    handle.attach(&mut parameters);
+    let token = parameters.cancellation_token().unwrap().clone();
+    assert!(!token.is_cancelled());
+    handle.abort();
+    assert!(token.is_cancelled());
+    // The token set on the parameters still cancels the generation
+    let mut parameters = GenerationParameters::new().with_cancellation_token(user_token.clone());
+    GenerationHandle::new().attach(&mut parameters);
+    user_token.cancel();
+    assert!(parameters.cancellation_token().unwrap().is_cancelled());
+}
+
+#[test]
+fn finish_reason_is_recorded_before_the_handler_runs() {
+    let handled = Arc::new(Mutex::new(None));
+    let mut parameters = GenerationParameters::new().with_finish_handler({
+        let handled = handled.clone();
+        move |reason| *handled.lock().unwrap() = Some(reason)
+    });
+    let handle = GenerationHandle::new();
+    handle.attach(&mut parameters);
+    assert_eq!(handle.finish_reason(), None);
+    let handler = parameters.finish_handler().unwrap();
+    (handler.lock().unwrap())(FinishReason::TimedOut);
+    assert_eq!(handle.finish_reason(), Some(FinishReason::TimedOut));
+    assert_eq!(*handled.lock().unwrap(), Some(FinishReason::TimedOut));
+}
+
+#[test]
+fn usage_is_recorded_without_a_handler() {
+    let mut parameters = GenerationParameters::new();
+    let handle = GenerationHandle::new();
+    handle.attach(&mut parameters);
+    let usage = Usage::new(
+        3,
+        5,
+        std::time::Duration::from_millis(10),
+        std::time::Duration::from_millis(50),
+    );
+    (parameters.usage_handler().unwrap().lock().unwrap())(usage.clone());
+    assert_eq!(handle.usage(), Some(usage));
+}
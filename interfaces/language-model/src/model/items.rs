@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{Stream, StreamExt};
+use kalosm_sample::{ArrayParser, ArrayParserState, CreateParserState, ParseStatus, Parser};
+
+/// A stream of the items of a JSON array as the model generates them. Created with
+/// [`TextCompletionBuilder::stream_items`](crate::TextCompletionBuilder::stream_items) or
+/// [`ChatResponseBuilder::stream_items`](crate::ChatResponseBuilder::stream_items).
+///
+/// Each item is yielded as soon as the separator or closing bracket after it is generated, while the model keeps
+/// generating the rest of the array.
+pub struct ArrayItemStream<S, P: Parser> {
+    tokens: S,
+    parser: ArrayParser<P>,
+    state: Option<ArrayParserState<P>>,
+    emitted: usize,
+    queued: VecDeque<P::Output>,
+}
+
+impl<S, P: Parser> Unpin for ArrayItemStream<S, P> where S: Unpin {}
+
+impl<S, P: CreateParserState> ArrayItemStream<S, P> {
+    /// Create a stream of items from a stream of tokens that are constrained by the parser.
+    pub fn new(tokens: S, parser: ArrayParser<P>) -> Self {
+        let state = parser.create_parser_state();
+        Self {
+            tokens,
+            parser,
+            state: Some(state),
+            emitted: 0,
+            queued: VecDeque::new(),
+        }
+    }
+
+    fn queue_new_items(&mut self, items: Vec<P::Output>) {
+        self.queued.extend(items.into_iter().skip(self.emitted));
+        self.emitted += self.queued.len();
+    }
+
+    fn parse_token(&mut self, token: &str) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        match self.parser.parse(&state, token.as_bytes()) {
+            Ok(ParseStatus::Incomplete { new_state, .. }) => {
+                if new_state.item_count() > self.emitted {
+                    self.queue_new_items(new_state.items());
+                }
+                self.state = Some(new_state);
+            }
+            Ok(ParseStatus::Finished { result, .. }) => self.queue_new_items(result),
+            Err(err) => tracing::error!("Failed to parse the generated array: {err:?}"),
+        }
+    }
+}
+
+impl<S, P> Stream for ArrayItemStream<S, P>
+where
+    S: Stream<Item = String> + Unpin,
+    P: CreateParserState,
+{
+    type Item = P::Output;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let myself = Pin::get_mut(self);
+        loop {
+            if let Some(item) = myself.queued.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+            match myself.tokens.poll_next_unpin(cx) {
+                Poll::Ready(Some(token)) => myself.parse_token(&token),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+/// A token and its log probability under the model before sampling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprob {
+    token: u32,
+    text: String,
+    logprob: f32,
+}
+
+impl TokenLogprob {
+    /// Create a new token log probability. This is used by model backends that report log probabilities.
+    pub fn new(token: u32, text: impl Into<String>, logprob: f32) -> Self {
+        Self {
+            token,
+            text: text.into(),
+            logprob,
+        }
+    }
+
+    /// Get the id of the token.
+    pub fn token(&self) -> u32 {
+        self.token
+    }
+
+    /// Get the text of the token on its own.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the natural log of the probability of the token.
+    pub fn logprob(&self) -> f32 {
+        self.logprob
+    }
+
+    /// Get the probability of the token between 0 and 1.
+    pub fn probability(&self) -> f32 {
+        self.logprob.exp()
+    }
+}
+
+/// The log probability of a generated token and the most likely tokens the model could have generated in its place.
+/// A handler for token log probabilities can be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_logprobs`](crate::GenerationParameters::with_logprobs).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenLogprobs {
+    sampled: TokenLogprob,
+    top_alternatives: Vec<TokenLogprob>,
+}
+
+impl TokenLogprobs {
+    /// Create the log probabilities for a generated token. This is used by model backends that report log
+    /// probabilities.
+    pub fn new(sampled: TokenLogprob, top_alternatives: Vec<TokenLogprob>) -> Self {
+        Self {
+            sampled,
+            top_alternatives,
+        }
+    }
+
+    /// Get the token that was generated.
+    pub fn sampled(&self) -> &TokenLogprob {
+        &self.sampled
+    }
+
+    /// Get the most likely tokens at the position of the generated token, sorted from most to least likely. The
+    /// generated token is included if it is one of them.
+    pub fn top_alternatives(&self) -> &[TokenLogprob] {
+        &self.top_alternatives
+    }
+}
+
+/// A token log probability handler that is shared between clones of
+/// [`GenerationParameters`](crate::GenerationParameters).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SharedLogprobsHandler {
+    pub(crate) top_alternatives: usize,
+    pub(crate) handler: crate::Shared<dyn FnMut(TokenLogprobs) + Send>,
+}
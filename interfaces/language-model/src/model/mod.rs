@@ -7,6 +7,32 @@ mod ext;
 pub use ext::*;
 mod boxed;
 pub use boxed::*;
+mod watermark;
+pub use watermark::*;
+mod stop_criteria;
+pub use stop_criteria::*;
+mod items;
+pub use items::*;
+mod prefill;
+pub use prefill::*;
+mod prompt_lookup;
+pub use prompt_lookup::*;
+mod logprobs;
+pub use logprobs::*;
+mod stop_sequences;
+pub use stop_sequences::*;
+mod cancellation;
+pub use cancellation::*;
+mod handle;
+pub use handle::*;
+mod finish_reason;
+pub use finish_reason::*;
+mod usage;
+pub use usage::*;
+mod degradation;
+pub use degradation::*;
+mod shared;
+pub(crate) use shared::*;
 
 #[doc = include_str!("../../docs/completion_session.md")]
 pub trait TextCompletionSession {
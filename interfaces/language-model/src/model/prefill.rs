@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+/// The progress of feeding a prompt into the model before the first token is generated. A handler for prefill
+/// progress can be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_prefill_progress`](crate::GenerationParameters::with_prefill_progress).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrefillProgress {
+    tokens_processed: usize,
+    total_tokens: usize,
+    elapsed: Duration,
+}
+
+impl PrefillProgress {
+    /// Create a new prefill progress event. This is used by model backends that report prefill progress.
+    pub fn new(tokens_processed: usize, total_tokens: usize, elapsed: Duration) -> Self {
+        Self {
+            tokens_processed,
+            total_tokens,
+            elapsed,
+        }
+    }
+
+    /// Get the number of prompt tokens that have been fed into the model so far.
+    pub fn tokens_processed(&self) -> usize {
+        self.tokens_processed
+    }
+
+    /// Get the number of prompt tokens that need to be fed into the model.
+    pub fn total_tokens(&self) -> usize {
+        self.total_tokens
+    }
+
+    /// Get the time since the prefill started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Get the fraction of the prompt that has been processed between 0 and 1.
+    pub fn progress(&self) -> f32 {
+        if self.total_tokens == 0 {
+            return 1.;
+        }
+        self.tokens_processed as f32 / self.total_tokens as f32
+    }
+
+    /// Estimate the time until the prefill is finished based on the speed so far. Returns `None` if no tokens have
+    /// been processed yet.
+    pub fn estimate_time_remaining(&self) -> Option<Duration> {
+        if self.tokens_processed == 0 {
+            return None;
+        }
+        let remaining = self.total_tokens.saturating_sub(self.tokens_processed);
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.tokens_processed as f64),
+        )
+    }
+}
+
+/// A prefill progress handler that is shared between clones of [`GenerationParameters`](crate::GenerationParameters).
+pub(crate) type SharedPrefillHandler = crate::Shared<dyn FnMut(PrefillProgress) + Send>;
+
+#[test]
+fn estimates_remaining_time_from_speed() {
+    let progress = PrefillProgress::new(512, 2048, Duration::from_secs(1));
+    assert_eq!(progress.progress(), 0.25);
+    assert_eq!(
+        progress.estimate_time_remaining(),
+        Some(Duration::from_secs(3))
+    );
+    let progress = PrefillProgress::new(0, 2048, Duration::ZERO);
+    assert_eq!(progress.estimate_time_remaining(), None);
+}
@@ -0,0 +1,127 @@
+/// Settings for prompt lookup decoding. While generating, the last few tokens of the context are looked up earlier in
+/// the context and the tokens that followed them are proposed as a draft of the next tokens. The model checks the
+/// whole draft in one forward pass and keeps the tokens it would have generated anyway, so the output is the same as
+/// without prompt lookup.
+///
+/// Prompt lookup doesn't need a draft model. It speeds up tasks where the output copies long chunks of the prompt,
+/// like answering questions about retrieved documents or editing code.
+///
+/// The settings can be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_prompt_lookup`](crate::GenerationParameters::with_prompt_lookup). Model backends that
+/// don't support prompt lookup ignore it.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let code = std::fs::read_to_string("src/main.rs").unwrap();
+///     let parameters = GenerationParameters::default().with_prompt_lookup(PromptLookup::new());
+///     llm.complete(format!("{code}\n\nRename `x` to `count` in the code above:\n"))
+///         .with_sampler(parameters)
+///         .to_std_out()
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptLookup {
+    min_ngram: usize,
+    max_ngram: usize,
+    draft_tokens: usize,
+}
+
+impl Default for PromptLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PromptLookup {
+    /// Create new prompt lookup settings that match the last 2 to 3 tokens and draft up to 8 tokens.
+    pub const fn new() -> Self {
+        Self {
+            min_ngram: 2,
+            max_ngram: 3,
+            draft_tokens: 8,
+        }
+    }
+
+    /// Set the shortest run of tokens at the end of the context that is looked up. Shorter runs find more drafts, but
+    /// more of them are rejected. (Defaults to 2)
+    pub fn with_min_ngram(mut self, min_ngram: usize) -> Self {
+        self.min_ngram = min_ngram.max(1);
+        self.max_ngram = self.max_ngram.max(self.min_ngram);
+        self
+    }
+
+    /// Set the longest run of tokens at the end of the context that is looked up. Longer runs are tried first.
+    /// (Defaults to 3)
+    pub fn with_max_ngram(mut self, max_ngram: usize) -> Self {
+        self.max_ngram = max_ngram.max(1);
+        self.min_ngram = self.min_ngram.min(self.max_ngram);
+        self
+    }
+
+    /// Set the maximum number of tokens in a draft. (Defaults to 8)
+    pub fn with_draft_tokens(mut self, draft_tokens: usize) -> Self {
+        self.draft_tokens = draft_tokens;
+        self
+    }
+
+    /// Get the shortest run of tokens that is looked up.
+    pub fn min_ngram(&self) -> usize {
+        self.min_ngram
+    }
+
+    /// Get the longest run of tokens that is looked up.
+    pub fn max_ngram(&self) -> usize {
+        self.max_ngram
+    }
+
+    /// Get the maximum number of tokens in a draft.
+    pub fn draft_tokens(&self) -> usize {
+        self.draft_tokens
+    }
+
+    /// Propose a draft of the tokens that follow the context. The longest run of tokens at the end of the context
+    /// that appears earlier in the context is found, and the tokens after the most recent earlier match are returned.
+    /// Returns an empty draft if the end of the context doesn't appear earlier.
+    pub fn draft<'a>(&self, tokens: &'a [u32]) -> &'a [u32] {
+        for ngram in (self.min_ngram..=self.max_ngram).rev() {
+            if ngram >= tokens.len() {
+                continue;
+            }
+            let suffix = &tokens[tokens.len() - ngram..];
+            let found = (0..tokens.len() - ngram)
+                .rev()
+                .find(|&start| &tokens[start..start + ngram] == suffix);
+            if let Some(start) = found {
+                let draft_start = start + ngram;
+                let draft_end = (draft_start + self.draft_tokens).min(tokens.len());
+                return &tokens[draft_start..draft_end];
+            }
+        }
+        &[]
+    }
+}
+
+#[test]
+fn prompt_lookup_drafts_from_the_longest_match() {
+    let lookup = PromptLookup::new().with_draft_tokens(3);
+    // `2 3` appears twice, but only the first occurrence continues the 3-gram `1 2 3`
+    let tokens = [1, 2, 3, 4, 5, 6, 2, 3, 9, 1, 2, 3];
+    assert_eq!(lookup.draft(&tokens), [4, 5, 6]);
+
+    // Without a 3-gram match, the most recent 2-gram match is used
+    let tokens = [1, 2, 3, 4, 5, 6, 7, 2, 3, 9, 8, 2, 3];
+    assert_eq!(lookup.draft(&tokens), [9, 8, 2]);
+
+    // The draft stops at the end of the context
+    let tokens = [7, 2, 3, 4, 2, 3];
+    assert_eq!(PromptLookup::new().draft(&tokens), [4, 2, 3]);
+
+    assert!(lookup.draft(&[1, 2, 3, 4]).is_empty());
+}
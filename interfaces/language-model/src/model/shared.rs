@@ -0,0 +1,34 @@
+use std::sync::{Arc, Mutex};
+
+/// A callback or [`StopCriteria`](crate::StopCriteria) that is shared between clones of
+/// [`GenerationParameters`](crate::GenerationParameters). Two shared values are equal if they point to the same
+/// value.
+pub(crate) struct Shared<T: ?Sized>(pub(crate) Arc<Mutex<T>>);
+
+impl<T: ?Sized> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> std::fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Shared")
+            .field(&std::any::type_name::<T>())
+            .finish()
+    }
+}
+
+impl<T: ?Sized> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[test]
+fn shared_values_are_equal_to_their_clones() {
+    let first: Shared<dyn FnMut(u32) + Send> = Shared(Arc::new(Mutex::new(|_: u32| {})));
+    let second: Shared<dyn FnMut(u32) + Send> = Shared(Arc::new(Mutex::new(|_: u32| {})));
+    assert_eq!(first, first.clone());
+    assert_ne!(first, second);
+}
@@ -0,0 +1,307 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The state of generation passed to a [`StopCriteria`] after each token is generated.
+#[derive(Debug, Clone, Copy)]
+pub struct StopContext<'a> {
+    token: u32,
+    text: &'a str,
+    tokens_generated: usize,
+    elapsed: Duration,
+    logprob: Option<f32>,
+}
+
+impl<'a> StopContext<'a> {
+    /// Create a new stop context. This is used by model backends that support [`StopCriteria`].
+    pub fn new(
+        token: u32,
+        text: &'a str,
+        tokens_generated: usize,
+        elapsed: Duration,
+        logprob: Option<f32>,
+    ) -> Self {
+        Self {
+            token,
+            text,
+            tokens_generated,
+            elapsed,
+            logprob,
+        }
+    }
+
+    /// Get the id of the token that was just generated.
+    pub fn token(&self) -> u32 {
+        self.token
+    }
+
+    /// Get all of the text generated so far, including the text of the latest token.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Get the number of tokens generated so far, including the latest token.
+    pub fn tokens_generated(&self) -> usize {
+        self.tokens_generated
+    }
+
+    /// Get the time since generation started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Get the log probability of the latest token if the model backend provides it.
+    pub fn logprob(&self) -> Option<f32> {
+        self.logprob
+    }
+}
+
+/// A condition that is checked after every generated token to decide if generation should stop. Stop criteria can
+/// be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_stop_criteria`](crate::GenerationParameters::with_stop_criteria).
+///
+/// Any closure that takes a [`StopContext`] and returns a `bool` is a stop criteria. Criteria can be combined with
+/// [`StopCriteria::or`] and [`StopCriteria::and`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let stop = BalancedJson::new()
+///         .or(MaxDuration::new(Duration::from_secs(10)))
+///         .or(|context: &StopContext| context.logprob().is_some_and(|logprob| logprob < -10.0));
+///     let text = llm
+///         .complete("A JSON object describing a cat: ")
+///         .with_sampler(GenerationParameters::default().with_stop_criteria(stop))
+///         .await
+///         .unwrap();
+///     println!("{text}");
+/// }
+/// ```
+pub trait StopCriteria: Send + Sync {
+    /// Check if generation should stop after the latest token. The text of the latest token is still returned.
+    fn should_stop(&mut self, context: &StopContext) -> bool;
+
+    /// Reset any state before a new generation starts.
+    fn reset(&mut self) {}
+
+    /// Stop when either this criteria or the other criteria stops.
+    fn or<O: StopCriteria>(self, other: O) -> StopWhenAny<Self, O>
+    where
+        Self: Sized,
+    {
+        StopWhenAny {
+            first: self,
+            second: other,
+        }
+    }
+
+    /// Stop only when both this criteria and the other criteria stop after the same token.
+    fn and<O: StopCriteria>(self, other: O) -> StopWhenAll<Self, O>
+    where
+        Self: Sized,
+    {
+        StopWhenAll {
+            first: self,
+            second: other,
+        }
+    }
+}
+
+impl<F: FnMut(&StopContext) -> bool + Send + Sync> StopCriteria for F {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        self(context)
+    }
+}
+
+impl StopCriteria for Box<dyn StopCriteria> {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        (**self).should_stop(context)
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+}
+
+impl StopCriteria for Arc<Mutex<dyn StopCriteria>> {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        self.lock().unwrap().should_stop(context)
+    }
+
+    fn reset(&mut self) {
+        self.lock().unwrap().reset()
+    }
+}
+
+impl StopCriteria for Vec<Box<dyn StopCriteria>> {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        // Check every criteria so stateful criteria see every token
+        let mut stop = false;
+        for criteria in self.iter_mut() {
+            stop |= criteria.should_stop(context);
+        }
+        stop
+    }
+
+    fn reset(&mut self) {
+        self.iter_mut().for_each(|criteria| criteria.reset());
+    }
+}
+
+/// A [`StopCriteria`] that stops when either of two criteria stop. Created with [`StopCriteria::or`].
+pub struct StopWhenAny<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: StopCriteria, B: StopCriteria> StopCriteria for StopWhenAny<A, B> {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        let first = self.first.should_stop(context);
+        let second = self.second.should_stop(context);
+        first || second
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+}
+
+/// A [`StopCriteria`] that stops when both of two criteria stop. Created with [`StopCriteria::and`].
+pub struct StopWhenAll<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: StopCriteria, B: StopCriteria> StopCriteria for StopWhenAll<A, B> {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        let first = self.first.should_stop(context);
+        let second = self.second.should_stop(context);
+        first && second
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+    }
+}
+
+/// A [`StopCriteria`] that stops once generation has run for longer than a duration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxDuration {
+    duration: Duration,
+}
+
+impl MaxDuration {
+    /// Create a new criteria that stops after the duration.
+    pub const fn new(duration: Duration) -> Self {
+        Self { duration }
+    }
+}
+
+impl StopCriteria for MaxDuration {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        context.elapsed() >= self.duration
+    }
+}
+
+/// A [`StopCriteria`] that stops once the first JSON object or array in the generated text is closed. Brackets
+/// inside of strings are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalancedJson {
+    /// The number of bytes of the text that have already been checked
+    checked: usize,
+    depth: usize,
+    opened: bool,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl BalancedJson {
+    /// Create a new balanced JSON criteria.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, char: char) -> bool {
+        if self.in_string {
+            match char {
+                _ if self.escaped => self.escaped = false,
+                '\\' => self.escaped = true,
+                '"' => self.in_string = false,
+                _ => {}
+            }
+            return false;
+        }
+        match char {
+            '"' if self.opened => self.in_string = true,
+            '{' | '[' => {
+                self.opened = true;
+                self.depth += 1;
+            }
+            '}' | ']' if self.opened => {
+                self.depth = self.depth.saturating_sub(1);
+                return self.depth == 0;
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+impl StopCriteria for BalancedJson {
+    fn should_stop(&mut self, context: &StopContext) -> bool {
+        let text = context.text();
+        let new_text = text.get(self.checked..).unwrap_or_default();
+        self.checked = text.len();
+        let mut stop = false;
+        for char in new_text.chars() {
+            stop |= self.push(char);
+        }
+        stop
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// A [`StopCriteria`] that can be shared between clones of [`GenerationParameters`](crate::GenerationParameters).
+pub(crate) type SharedStopCriteria = crate::Shared<dyn StopCriteria>;
+
+#[test]
+fn balanced_json_stops_when_object_closes() {
+    let mut criteria = BalancedJson::new();
+    let mut text = String::new();
+    let mut stopped_at = None;
+    for (i, token) in [
+        "Here: ",
+        "{\"a\": ",
+        "\"}{\" ",
+        ", \"b\": [1",
+        ", 2]}",
+        " more",
+    ]
+    .iter()
+    .enumerate()
+    {
+        text += token;
+        let context = StopContext::new(0, &text, i + 1, Duration::ZERO, None);
+        if criteria.should_stop(&context) {
+            stopped_at = Some(i);
+            break;
+        }
+    }
+    assert_eq!(stopped_at, Some(4));
+
+    criteria.reset();
+    let mut max_tokens = (|context: &StopContext| context.tokens_generated() >= 2).or(criteria);
+    let context = StopContext::new(0, "[", 1, Duration::ZERO, None);
+    assert!(!max_tokens.should_stop(&context));
+    let context = StopContext::new(0, "[1", 2, Duration::ZERO, None);
+    assert!(max_tokens.should_stop(&context));
+}
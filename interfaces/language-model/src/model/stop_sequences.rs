@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+
+/// A list of strings that stop generation when the model generates any of them. Stop sequences are matched against
+/// the generated text, so they work even when a stop sequence is split between several tokens.
+///
+/// Stop sequences can be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_stop_sequences`](crate::GenerationParameters::with_stop_sequences). Clones of the stop
+/// sequences share the sequence that stopped the last generation, so you can keep a clone to check which one fired.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let stops = StopSequences::new(["\nQ:", "\n\n"]);
+///     let text = llm
+///         .complete("Q: What is the capital of France?\nA:")
+///         .with_sampler(GenerationParameters::default().with_stop_sequences(stops.clone()))
+///         .await
+///         .unwrap();
+///     println!("{text} (stopped on {:?})", stops.fired());
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StopSequences {
+    sequences: Vec<String>,
+    trim: bool,
+    fired: Arc<Mutex<Option<String>>>,
+}
+
+impl Default for StopSequences {
+    fn default() -> Self {
+        Self::new(Vec::<String>::new())
+    }
+}
+
+impl PartialEq for StopSequences {
+    fn eq(&self, other: &Self) -> bool {
+        self.sequences == other.sequences && self.trim == other.trim
+    }
+}
+
+impl StopSequences {
+    /// Create a new list of stop sequences. The stop sequence that fires is removed from the output by default.
+    pub fn new(sequences: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            sequences: sequences
+                .into_iter()
+                .map(Into::into)
+                .filter(|sequence: &String| !sequence.is_empty())
+                .collect(),
+            trim: true,
+            fired: Default::default(),
+        }
+    }
+
+    /// Set whether the stop sequence that fires is removed from the output. (Defaults to true)
+    pub fn with_trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Get the stop sequences.
+    pub fn sequences(&self) -> &[String] {
+        &self.sequences
+    }
+
+    /// Check if the stop sequence that fires is removed from the output.
+    pub fn trim(&self) -> bool {
+        self.trim
+    }
+
+    /// Get the stop sequence that stopped the last generation, or `None` if the last generation didn't stop on one
+    /// of these sequences.
+    pub fn fired(&self) -> Option<String> {
+        self.fired.lock().unwrap().clone()
+    }
+}
+
+struct StopSequence {
+    text: String,
+    ignore_ascii_case: bool,
+    trim: bool,
+}
+
+impl StopSequence {
+    fn matches(&self, text: &[u8]) -> bool {
+        let sequence = self.text.as_bytes();
+        if self.ignore_ascii_case {
+            sequence.eq_ignore_ascii_case(text)
+        } else {
+            sequence == text
+        }
+    }
+
+    fn matches_prefix(&self, text: &[u8]) -> bool {
+        let Some(prefix) = self.text.as_bytes().get(..text.len()) else {
+            return false;
+        };
+        if self.ignore_ascii_case {
+            prefix.eq_ignore_ascii_case(text)
+        } else {
+            prefix == text
+        }
+    }
+}
+
+/// Matches stop sequences in text that is streamed one token at a time. Text that could be the start of a stop
+/// sequence is held back until the next token shows if the stop sequence is complete. This is used by model
+/// backends that support [`StopSequences`].
+pub struct StopSequenceMatcher {
+    sequences: Vec<StopSequence>,
+    pending: String,
+    fired: Option<Arc<Mutex<Option<String>>>>,
+}
+
+impl StopSequenceMatcher {
+    /// Create a matcher for the stop sequences and the `stop_on` string of
+    /// [`GenerationParameters`](crate::GenerationParameters). The `stop_on` string is matched ignoring ASCII case and
+    /// is always removed from the output. Creating a matcher resets the stop sequence that fired.
+    pub fn new(stop_sequences: Option<&StopSequences>, stop_on: Option<&str>) -> Self {
+        let mut sequences: Vec<_> = stop_on
+            .filter(|stop_on| !stop_on.is_empty())
+            .map(|stop_on| StopSequence {
+                text: stop_on.to_string(),
+                ignore_ascii_case: true,
+                trim: true,
+            })
+            .into_iter()
+            .collect();
+        if let Some(stop_sequences) = stop_sequences {
+            *stop_sequences.fired.lock().unwrap() = None;
+            sequences.extend(
+                stop_sequences
+                    .sequences
+                    .iter()
+                    .map(|sequence| StopSequence {
+                        text: sequence.clone(),
+                        ignore_ascii_case: false,
+                        trim: stop_sequences.trim,
+                    }),
+            );
+        }
+        Self {
+            sequences,
+            pending: String::new(),
+            fired: stop_sequences.map(|stop_sequences| stop_sequences.fired.clone()),
+        }
+    }
+
+    /// Check if there are any stop sequences to match.
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// Add the text of a new token. Returns the text that is safe to send to the user and the stop sequence that
+    /// fired, if any. Generation should stop once a stop sequence fires.
+    pub fn push(&mut self, text: &str) -> (String, Option<String>) {
+        self.pending.push_str(text);
+        let bytes = self.pending.as_bytes();
+
+        // Find the stop sequence that ends first in the text
+        let stop = self
+            .sequences
+            .iter()
+            .filter_map(|sequence| {
+                let len = sequence.text.len();
+                let start = (0..=bytes.len().checked_sub(len)?)
+                    .find(|&start| sequence.matches(&bytes[start..start + len]))?;
+                Some((start + len, start, sequence))
+            })
+            .min_by_key(|(end, start, _)| (*end, *start));
+        if let Some((end, start, sequence)) = stop {
+            let output_end = if sequence.trim { start } else { end };
+            let output = self.pending[..output_end].to_string();
+            let fired = self.pending[start..end].to_string();
+            if !sequence.ignore_ascii_case {
+                if let Some(shared) = &self.fired {
+                    *shared.lock().unwrap() = Some(sequence.text.clone());
+                }
+            }
+            self.pending.clear();
+            return (output, Some(fired));
+        }
+
+        // Hold back the longest end of the text that could be the start of a stop sequence
+        let held = self
+            .sequences
+            .iter()
+            .filter_map(|sequence| {
+                (1..sequence.text.len().min(bytes.len() + 1))
+                    .rev()
+                    .find(|&len| sequence.matches_prefix(&bytes[bytes.len() - len..]))
+            })
+            .max()
+            .unwrap_or(0);
+        let output = self.pending[..self.pending.len() - held].to_string();
+        self.pending.drain(..self.pending.len() - held);
+        (output, None)
+    }
+
+    /// Take the text that was held back because it could have been the start of a stop sequence. Call this when
+    /// generation ends without a stop sequence.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[test]
+fn stop_sequences_span_tokens() {
+    let stops = StopSequences::new(["\nQ:", "END"]);
+    let mut matcher = StopSequenceMatcher::new(Some(&stops), None);
+
+    assert_eq!(matcher.push("Paris"), ("Paris".to_string(), None));
+    // The newline could be the start of `\nQ:`, so it is held back
+    assert_eq!(matcher.push(".\n"), (".".to_string(), None));
+    assert_eq!(matcher.push("A"), ("\nA".to_string(), None));
+    assert_eq!(matcher.push("\nQ"), (String::new(), None));
+    assert_eq!(
+        matcher.push(": next"),
+        (String::new(), Some("\nQ:".to_string()))
+    );
+    assert_eq!(stops.fired(), Some("\nQ:".to_string()));
+
+    // stop_on is matched ignoring case, but it is not reported as a stop sequence
+    let mut matcher = StopSequenceMatcher::new(Some(&stops), Some("stop"));
+    assert_eq!(stops.fired(), None);
+    assert_eq!(matcher.push("one STO"), ("one ".to_string(), None));
+    assert_eq!(
+        matcher.push("P two"),
+        (String::new(), Some("STOP".to_string()))
+    );
+    assert_eq!(stops.fired(), None);
+
+    // Stop sequences can be kept in the output
+    let stops = StopSequences::new(["END"]).with_trim(false);
+    let mut matcher = StopSequenceMatcher::new(Some(&stops), None);
+    assert_eq!(
+        matcher.push("the END of it"),
+        ("the END".to_string(), Some("END".to_string()))
+    );
+
+    let mut matcher = StopSequenceMatcher::new(Some(&stops), None);
+    assert_eq!(matcher.push("EN"), (String::new(), None));
+    assert_eq!(matcher.finish(), "EN");
+}
@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use crate::Degradation;
+
+/// The tokens and time a generation used. Model backends that support it call the handler set with
+/// [`GenerationParameters::with_usage_handler`](crate::GenerationParameters::with_usage_handler) once the
+/// generation finishes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Usage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    prefill_time: Duration,
+    decode_time: Duration,
+    degradations: Vec<Degradation>,
+}
+
+impl Usage {
+    /// Create a new usage report. This is used by model backends that support usage reports.
+    pub const fn new(
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        prefill_time: Duration,
+        decode_time: Duration,
+    ) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            prefill_time,
+            decode_time,
+            degradations: Vec::new(),
+        }
+    }
+
+    /// Set the ways the generation was degraded to recover from running out of memory.
+    pub fn with_degradations(mut self, degradations: Vec<Degradation>) -> Self {
+        self.degradations = degradations;
+        self
+    }
+
+    /// Get the number of prompt tokens fed into the model for this generation. Chat sessions with a local model
+    /// only feed the new messages of each turn.
+    pub fn prompt_tokens(&self) -> usize {
+        self.prompt_tokens
+    }
+
+    /// Get the number of tokens the model generated.
+    pub fn completion_tokens(&self) -> usize {
+        self.completion_tokens
+    }
+
+    /// Get the total number of tokens in the prompt and completion.
+    pub fn total_tokens(&self) -> usize {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Get the time spent processing the prompt before the first token was generated. For remote models, this is
+    /// the time until the first token arrived.
+    pub fn prefill_time(&self) -> Duration {
+        self.prefill_time
+    }
+
+    /// Get the time spent generating tokens after the prompt was processed.
+    pub fn decode_time(&self) -> Duration {
+        self.decode_time
+    }
+
+    /// Get the ways the generation was degraded to recover from running out of device memory. This is empty if the
+    /// generation ran normally.
+    pub fn degradations(&self) -> &[Degradation] {
+        &self.degradations
+    }
+
+    /// Get the number of tokens generated per second while decoding.
+    pub fn tokens_per_second(&self) -> f64 {
+        let seconds = self.decode_time.as_secs_f64();
+        if seconds == 0.0 {
+            0.0
+        } else {
+            self.completion_tokens as f64 / seconds
+        }
+    }
+}
+
+/// A usage handler that is shared between clones of [`GenerationParameters`](crate::GenerationParameters).
+pub(crate) type SharedUsageHandler = crate::Shared<dyn FnMut(Usage) + Send>;
+
+#[test]
+fn tokens_per_second_uses_decode_time() {
+    let usage = Usage::new(100, 50, Duration::from_secs(1), Duration::from_secs(2));
+    assert_eq!(usage.total_tokens(), 150);
+    assert_eq!(usage.tokens_per_second(), 25.0);
+    assert_eq!(Usage::default().tokens_per_second(), 0.0);
+}
@@ -0,0 +1,184 @@
+#[cfg(feature = "sample")]
+use llm_samplers::prelude::*;
+
+/// A keyed watermark that biases generation towards a "green list" of tokens. The green list changes with every
+/// token based on the previous token and a secret key. Text generated with the watermark contains many more green
+/// tokens than normal text which can be detected later with [`Watermark::detect`] if you know the key.
+///
+/// The watermark can be added to [`GenerationParameters`](crate::GenerationParameters) with
+/// [`GenerationParameters::with_watermark`](crate::GenerationParameters::with_watermark) or used as a sampler in a
+/// custom sampler chain.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new().await.unwrap();
+///     let watermark = Watermark::new(0x5eed_cafe);
+///     let text = llm
+///         .complete("Write a short story about a robot: ")
+///         .with_sampler(GenerationParameters::default().with_watermark(watermark))
+///         .await
+///         .unwrap();
+///
+///     let tokens = llm.tokenizer().encode(text, false).unwrap();
+///     let detection = watermark.detect(tokens.get_ids());
+///     println!("z-score: {}", detection.z_score());
+///     assert!(detection.is_watermarked(4.0));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watermark {
+    key: u64,
+    green_fraction: f32,
+    bias: f32,
+}
+
+impl Watermark {
+    /// Create a new watermark with a secret key. Only someone with the same key can detect the watermark.
+    pub const fn new(key: u64) -> Self {
+        Self {
+            key,
+            green_fraction: 0.25,
+            bias: 2.0,
+        }
+    }
+
+    /// Set the fraction of the vocabulary that is in the green list for each token. (Defaults to 0.25)
+    pub fn with_green_fraction(mut self, green_fraction: f32) -> Self {
+        self.green_fraction = green_fraction.clamp(f32::EPSILON, 1.0 - f32::EPSILON);
+        self
+    }
+
+    /// Set the amount added to the logits of green tokens. Larger values make the watermark easier to detect in
+    /// short texts, but change the output of the model more. (Defaults to 2.0)
+    pub fn with_bias(mut self, bias: f32) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Get the fraction of the vocabulary that is in the green list for each token.
+    pub fn green_fraction(&self) -> f32 {
+        self.green_fraction
+    }
+
+    /// Get the amount added to the logits of green tokens.
+    pub fn bias(&self) -> f32 {
+        self.bias
+    }
+
+    /// Check if a token is in the green list after the previous token.
+    pub fn is_green(&self, previous: u32, token: u32) -> bool {
+        let seed = split_mix(self.key ^ split_mix(previous as u64));
+        let hash = split_mix(seed ^ token as u64);
+        // Use the top 24 bits of the hash as a uniform value between 0 and 1
+        ((hash >> 40) as f32 / (1u64 << 24) as f32) < self.green_fraction
+    }
+
+    /// Check how many tokens in a sequence are in the green list. The first token is skipped because the token
+    /// before it is unknown.
+    pub fn detect(&self, tokens: &[u32]) -> WatermarkDetection {
+        let scored_tokens = tokens.len().saturating_sub(1);
+        let green_tokens = tokens
+            .windows(2)
+            .filter(|pair| self.is_green(pair[0], pair[1]))
+            .count();
+        WatermarkDetection {
+            scored_tokens,
+            green_tokens,
+            green_fraction: self.green_fraction,
+        }
+    }
+}
+
+/// A fast, stable 64 bit hash mixer. The watermark must not depend on the hasher of the standard library because
+/// detection needs to produce the same green lists as generation, possibly with a different version of Rust.
+fn split_mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(feature = "sample")]
+impl Sampler for Watermark {
+    fn sample<'a>(
+        &mut self,
+        res: &mut dyn HasSamplerResources,
+        logits: &'a mut Logits,
+    ) -> anyhow::Result<&'a mut Logits> {
+        let mut previous = None;
+        res.with_last_tokens(&mut |tokens| previous = tokens.last().copied())?;
+        let Some(previous) = previous else {
+            return Ok(logits);
+        };
+        for logit in logits.iter_mut() {
+            if self.is_green(previous, logit.token_id) {
+                logit.logit += self.bias;
+            }
+        }
+        logits.set_sorted(false);
+        Ok(logits)
+    }
+}
+
+/// The result of checking a sequence of tokens for a [`Watermark`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatermarkDetection {
+    scored_tokens: usize,
+    green_tokens: usize,
+    green_fraction: f32,
+}
+
+impl WatermarkDetection {
+    /// Get the number of tokens that were checked.
+    pub fn scored_tokens(&self) -> usize {
+        self.scored_tokens
+    }
+
+    /// Get the number of checked tokens that were in the green list.
+    pub fn green_tokens(&self) -> usize {
+        self.green_tokens
+    }
+
+    /// Get the z-score of the number of green tokens compared to text without a watermark. Text without the
+    /// watermark has a z-score around 0.
+    pub fn z_score(&self) -> f32 {
+        if self.scored_tokens == 0 {
+            return 0.0;
+        }
+        let total = self.scored_tokens as f32;
+        let expected = self.green_fraction * total;
+        let deviation = (total * self.green_fraction * (1.0 - self.green_fraction)).sqrt();
+        (self.green_tokens as f32 - expected) / deviation
+    }
+
+    /// Check if the z-score is above a threshold. A threshold of 4 makes false positives very unlikely.
+    pub fn is_watermarked(&self, z_threshold: f32) -> bool {
+        self.z_score() > z_threshold
+    }
+}
+
+#[test]
+fn detect_watermark() {
+    let watermark = Watermark::new(42);
+    let vocab = 1000;
+
+    // Greedily pick green tokens to simulate a strongly watermarked generation
+    let mut watermarked = vec![7];
+    let mut candidate = 0;
+    while watermarked.len() < 100 {
+        let previous = *watermarked.last().unwrap();
+        candidate = (candidate + 13) % vocab;
+        if watermark.is_green(previous, candidate) {
+            watermarked.push(candidate);
+        }
+    }
+    assert!(watermark.detect(&watermarked).is_watermarked(4.0));
+    assert!(!Watermark::new(43).detect(&watermarked).is_watermarked(4.0));
+
+    let unmarked: Vec<u32> = (0..100).map(|i| (i * 37 + 11) % vocab).collect();
+    assert!(!watermark.detect(&unmarked).is_watermarked(4.0));
+}
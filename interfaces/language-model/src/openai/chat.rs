@@ -1,12 +1,12 @@
 use super::{NoOpenAIAPIKeyError, OpenAICompatibleClient};
+use crate::remote_retry::{next_event_before, open_event_source, StreamInterrupted, UsageTimer};
 use crate::{
     ChatModel, ChatSession, CreateChatSession, CreateDefaultChatConstraintsForType,
     GenerationParameters, ModelBuilder, ModelConstraints, StructuredChatModel,
 };
-use futures_util::StreamExt;
 use kalosm_model_types::ModelLoadingProgress;
 use kalosm_sample::Schema;
-use reqwest_eventsource::{Event, RequestBuilderExt};
+use reqwest_eventsource::Event;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{future::Future, sync::Arc};
 use thiserror::Error;
@@ -130,6 +130,12 @@ pub enum OpenAICompatibleChatModelError {
     /// Function calls are not yet supported in kalosm with the OpenAI API.
     #[error("Function calls are not yet supported in kalosm with the OpenAI API")]
     FunctionCallsNotSupported,
+    /// The generation was cancelled with a [`CancellationToken`](crate::CancellationToken).
+    #[error("The generation was cancelled")]
+    Cancelled,
+    /// The timeout passed before the structured response was complete.
+    #[error("The generation timed out before the structured response was complete")]
+    TimedOut,
 }
 
 /// A chat session for the OpenAI compatible chat model.
@@ -187,6 +193,15 @@ impl CreateChatSession for OpenAICompatibleChatModel {
 #[derive(Serialize, Deserialize)]
 struct OpenAICompatibleChatResponse {
     choices: Vec<OpenAICompatibleChatResponseChoice>,
+    /// Only sent in the last chunk when `stream_options.include_usage` is set
+    #[serde(default)]
+    usage: Option<OpenAICompatibleChatResponseUsage>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAICompatibleChatResponseUsage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -228,35 +243,81 @@ impl ChatModel<GenerationParameters> for OpenAICompatibleChatModel {
             "stream": true,
             "top_p": sampler.top_p,
             "temperature": sampler.temperature,
-            "frequency_penalty": sampler.repetition_penalty,
+            "frequency_penalty": sampler.frequency_penalty,
+            "presence_penalty": sampler.presence_penalty,
             "max_completion_tokens": if sampler.max_length == u32::MAX { None } else { Some(sampler.max_length) },
-            "stop": sampler.stop_on.clone(),
+            "stop": Some(sampler.stop_strings()).filter(|stop| !stop.is_empty()),
+            "stream_options": sampler.usage_handler().map(|_| serde_json::json!({ "include_usage": true })),
+            "seed": sampler.seed(),
         });
+        let cancellation_token = sampler.cancellation_token().cloned();
+        let deadline = sampler
+            .timeout()
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let finish_handler = sampler.finish_handler();
+        let usage_handler = sampler.usage_handler();
         async move {
             let api_key = myself.client.resolve_api_key()?;
-            let mut event_source = myself
-                .client
-                .reqwest_client
-                .post(format!("{}/chat/completions", myself.client.base_url()))
-                .header("Content-Type", "application/json")
-                .header("Authorization", format!("Bearer {}", api_key))
-                .json(&json)
-                .eventsource()
-                .unwrap();
+            let mut timer = UsageTimer::start();
+            let mut event_source = open_event_source(myself.client.retry_policy(), || {
+                myself
+                    .client
+                    .reqwest_client
+                    .post(format!("{}/chat/completions", myself.client.base_url()))
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .json(&json)
+            })
+            .await?;
 
             let mut new_message_text = String::new();
 
-            while let Some(event) = event_source.next().await {
+            let finish = |reason: crate::FinishReason| {
+                if let Some(finish_handler) = &finish_handler {
+                    (finish_handler.lock().unwrap())(reason);
+                }
+            };
+            let mut timed_out = false;
+            let mut finish_reason = crate::FinishReason::Stop;
+            let mut usage = None;
+            loop {
+                let event = match next_event_before(
+                    &mut event_source,
+                    deadline,
+                    cancellation_token.as_ref(),
+                )
+                .await
+                {
+                    Ok(event) => event,
+                    Err(StreamInterrupted::TimedOut) => {
+                        event_source.close();
+                        timed_out = true;
+                        break;
+                    }
+                    Err(StreamInterrupted::Cancelled) => {
+                        event_source.close();
+                        return Err(OpenAICompatibleChatModelError::Cancelled);
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
                 match event? {
                     Event::Open => {}
                     Event::Message(message) => {
-                        let data =
-                            serde_json::from_str::<OpenAICompatibleChatResponse>(&message.data)?;
-                        let first_choice = data
-                            .choices
-                            .into_iter()
-                            .next()
-                            .ok_or(OpenAICompatibleChatModelError::NoMessageChoices)?;
+                        let OpenAICompatibleChatResponse {
+                            choices,
+                            usage: reported_usage,
+                        } = serde_json::from_str(&message.data)?;
+                        let Some(first_choice) = choices.into_iter().next() else {
+                            // The last chunk only holds the usage
+                            if reported_usage.is_some() {
+                                usage = reported_usage;
+                                event_source.close();
+                                break;
+                            }
+                            return Err(OpenAICompatibleChatModelError::NoMessageChoices);
+                        };
                         if let Some(content) = first_choice.delta.refusal {
                             return Err(OpenAICompatibleChatModelError::Refusal(content));
                         }
@@ -272,17 +333,38 @@ impl ChatModel<GenerationParameters> for OpenAICompatibleChatModel {
                                         OpenAICompatibleChatModelError::FunctionCallsNotSupported,
                                     )
                                 }
-                                _ => return Ok(()),
+                                FinishReason::MaxTokens => {
+                                    finish_reason = crate::FinishReason::MaxTokens
+                                }
+                                FinishReason::Stop => finish_reason = crate::FinishReason::Stop,
                             }
                         }
                         if let Some(content) = first_choice.delta.content {
+                            timer.token();
                             new_message_text += &content;
                             on_token(content)?;
                         }
+                        // Keep reading until the usage chunk if usage was requested
+                        if first_choice.finish_reason.is_some() && usage_handler.is_none() {
+                            event_source.close();
+                            break;
+                        }
                     }
                 }
             }
 
+            finish(if timed_out {
+                crate::FinishReason::TimedOut
+            } else {
+                finish_reason
+            });
+            if let Some(usage_handler) = &usage_handler {
+                let (prompt_tokens, completion_tokens) = usage
+                    .map(|usage| (usage.prompt_tokens, usage.completion_tokens))
+                    .unwrap_or_default();
+                (usage_handler.lock().unwrap())(timer.finish(prompt_tokens, completion_tokens));
+            }
+
             let new_message =
                 crate::ChatMessage::new(crate::MessageType::UserMessage, new_message_text);
 
@@ -398,9 +480,11 @@ where
             "stream": true,
             "top_p": sampler.top_p,
             "temperature": sampler.temperature,
-            "frequency_penalty": sampler.repetition_penalty,
+            "frequency_penalty": sampler.frequency_penalty,
+            "presence_penalty": sampler.presence_penalty,
             "max_completion_tokens": if sampler.max_length == u32::MAX { None } else { Some(sampler.max_length) },
-            "stop": sampler.stop_on.clone(),
+            "stop": Some(sampler.stop_strings()).filter(|stop| !stop.is_empty()),
+            "stream_options": sampler.usage_handler().map(|_| serde_json::json!({ "include_usage": true })),
             "seed": sampler.seed(),
             "response_format": {
                 "type": "json_schema",
@@ -411,31 +495,74 @@ where
                 }
             }
         }));
+        let cancellation_token = sampler.cancellation_token().cloned();
+        let deadline = sampler
+            .timeout()
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+        let finish_handler = sampler.finish_handler();
+        let usage_handler = sampler.usage_handler();
         async move {
             let json = json?;
             let api_key = myself.client.resolve_api_key()?;
-            let mut event_source = myself
-                .client
-                .reqwest_client
-                .post(format!("{}/chat/completions", myself.client.base_url()))
-                .header("Content-Type", "application/json")
-                .header("Authorization", format!("Bearer {}", api_key))
-                .json(&json)
-                .eventsource()
-                .unwrap();
+            let mut timer = UsageTimer::start();
+            let mut event_source = open_event_source(myself.client.retry_policy(), || {
+                myself
+                    .client
+                    .reqwest_client
+                    .post(format!("{}/chat/completions", myself.client.base_url()))
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", api_key))
+                    .json(&json)
+            })
+            .await?;
 
             let mut new_message_text = String::new();
 
-            while let Some(event) = event_source.next().await {
+            let finish = |reason: crate::FinishReason| {
+                if let Some(finish_handler) = &finish_handler {
+                    (finish_handler.lock().unwrap())(reason);
+                }
+            };
+            let mut timed_out = false;
+            let mut usage = None;
+            loop {
+                let event = match next_event_before(
+                    &mut event_source,
+                    deadline,
+                    cancellation_token.as_ref(),
+                )
+                .await
+                {
+                    Ok(event) => event,
+                    Err(StreamInterrupted::TimedOut) => {
+                        event_source.close();
+                        timed_out = true;
+                        break;
+                    }
+                    Err(StreamInterrupted::Cancelled) => {
+                        event_source.close();
+                        return Err(OpenAICompatibleChatModelError::Cancelled);
+                    }
+                };
+                let Some(event) = event else {
+                    break;
+                };
                 match event? {
                     Event::Open => {}
                     Event::Message(message) => {
-                        let data =
-                            serde_json::from_str::<OpenAICompatibleChatResponse>(&message.data)?;
-                        let first_choice = data
-                            .choices
-                            .first()
-                            .ok_or(OpenAICompatibleChatModelError::NoMessageChoices)?;
+                        let OpenAICompatibleChatResponse {
+                            choices,
+                            usage: reported_usage,
+                        } = serde_json::from_str(&message.data)?;
+                        let Some(first_choice) = choices.first() else {
+                            // The last chunk only holds the usage
+                            if reported_usage.is_some() {
+                                usage = reported_usage;
+                                event_source.close();
+                                break;
+                            }
+                            return Err(OpenAICompatibleChatModelError::NoMessageChoices);
+                        };
                         if let Some(content) = &first_choice.delta.refusal {
                             return Err(OpenAICompatibleChatModelError::Refusal(content.clone()));
                         }
@@ -451,10 +578,13 @@ where
                                         OpenAICompatibleChatModelError::FunctionCallsNotSupported,
                                     )
                                 }
+                                // Keep reading until the usage chunk if usage was requested
+                                _ if usage_handler.is_some() => {}
                                 _ => break,
                             }
                         }
                         if let Some(content) = &first_choice.delta.content {
+                            timer.token();
                             on_token(content.clone())?;
                             new_message_text += content;
                         }
@@ -462,7 +592,18 @@ where
                 }
             }
 
+            if timed_out {
+                finish(crate::FinishReason::TimedOut);
+                return Err(OpenAICompatibleChatModelError::TimedOut);
+            }
             let result = serde_json::from_str::<P>(&new_message_text)?;
+            finish(crate::FinishReason::Stop);
+            if let Some(usage_handler) = &usage_handler {
+                let (prompt_tokens, completion_tokens) = usage
+                    .map(|usage| (usage.prompt_tokens, usage.completion_tokens))
+                    .unwrap_or_default();
+                (usage_handler.lock().unwrap())(timer.finish(prompt_tokens, completion_tokens));
+            }
 
             let new_message =
                 crate::ChatMessage::new(crate::MessageType::UserMessage, new_message_text);
@@ -1,6 +1,6 @@
 use super::{NoOpenAIAPIKeyError, OpenAICompatibleClient};
 use crate::{Embedder, Embedding, ModelBuilder};
-use kalosm_model_types::ModelLoadingProgress;
+use kalosm_model_types::{ModelLoadingProgress, RetryReason};
 use serde::Deserialize;
 use std::future::Future;
 use thiserror::Error;
@@ -137,17 +137,27 @@ impl Embedder for OpenAICompatibleEmbeddingModel {
     /// Embed a single string.
     async fn embed_string(&self, input: String) -> Result<Embedding, Self::Error> {
         let api_key = self.client.resolve_api_key()?;
+        let body = serde_json::json!({
+            "input": input,
+            "model": self.model
+        });
         let request = self
             .client
-            .reqwest_client
-            .post(format!("{}/embeddings", self.client.base_url()))
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&serde_json::json!({
-                "input": input,
-                "model": self.model
-            }))
-            .send()
+            .retry_policy()
+            .retry(
+                || {
+                    let request = self
+                        .client
+                        .reqwest_client
+                        .post(format!("{}/embeddings", self.client.base_url()))
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", api_key))
+                        .json(&body);
+                    async move { request.send().await?.error_for_status() }
+                },
+                RetryReason::from_reqwest_error,
+                tokio::time::sleep,
+            )
             .await?;
         let response = request.json::<CreateEmbeddingResponse>().await?;
 
@@ -159,17 +169,27 @@ impl Embedder for OpenAICompatibleEmbeddingModel {
     /// Embed a single string.
     async fn embed_vec(&self, input: Vec<String>) -> Result<Vec<Embedding>, Self::Error> {
         let api_key = self.client.resolve_api_key()?;
+        let body = serde_json::json!({
+            "input": input,
+            "model": self.model
+        });
         let request = self
             .client
-            .reqwest_client
-            .post(format!("{}/embeddings", self.client.base_url()))
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .json(&serde_json::json!({
-                "input": input,
-                "model": self.model
-            }))
-            .send()
+            .retry_policy()
+            .retry(
+                || {
+                    let request = self
+                        .client
+                        .reqwest_client
+                        .post(format!("{}/embeddings", self.client.base_url()))
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", api_key))
+                        .json(&body);
+                    async move { request.send().await?.error_for_status() }
+                },
+                RetryReason::from_reqwest_error,
+                tokio::time::sleep,
+            )
             .await?;
         let mut response = request.json::<CreateEmbeddingResponse>().await?;
 
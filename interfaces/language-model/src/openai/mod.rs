@@ -1,5 +1,7 @@
 use std::sync::OnceLock;
 
+use kalosm_model_types::RetryPolicy;
+
 use thiserror::Error;
 
 mod embedding;
@@ -17,6 +19,7 @@ pub struct OpenAICompatibleClient {
     resolved_api_key: OnceLock<String>,
     organization_id: Option<String>,
     project_id: Option<String>,
+    retry_policy: RetryPolicy,
 }
 
 impl Default for OpenAICompatibleClient {
@@ -32,6 +35,7 @@ impl OpenAICompatibleClient {
             reqwest_client: reqwest::Client::new(),
             base_url: "https://api.openai.com/v1/".to_string(),
             resolved_api_key: OnceLock::new(),
+            retry_policy: RetryPolicy::default(),
             api_key: None,
             organization_id: None,
             project_id: None,
@@ -74,6 +78,13 @@ impl OpenAICompatibleClient {
         self
     }
 
+    /// Set the policy for retrying requests that fail with transient errors like rate limits or dropped
+    /// connections. Streaming responses are only retried before the first token is received. (Defaults to [`RetryPolicy::default`])
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
     /// Resolve the openai API key from the environment variable `OPENAI_API_KEY` or the provided api key.
     pub fn resolve_api_key(&self) -> Result<String, NoOpenAIAPIKeyError> {
         if let Some(api_key) = self.resolved_api_key.get() {
@@ -94,6 +105,11 @@ impl OpenAICompatibleClient {
     pub(crate) fn base_url(&self) -> &str {
         self.base_url.trim_end_matches('/')
     }
+
+    /// Get the policy for retrying failed requests.
+    pub(crate) fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
 }
 
 /// An error that can occur when building a remote OpenAI model without an API key.
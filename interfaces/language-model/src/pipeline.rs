@@ -0,0 +1,372 @@
+use futures_util::future::{AbortHandle, Abortable};
+use futures_util::{Future, FutureExt};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// A boxed error returned from a step in a [`Pipeline`].
+pub type BoxedStepError = Box<dyn std::error::Error + Send + Sync>;
+
+type PipelineFuture<O> = Pin<Box<dyn Future<Output = Result<O, PipelineError>> + Send>>;
+
+/// An error that can occur while running a [`Pipeline`].
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    /// A step in the pipeline failed.
+    #[error("Pipeline step {step:?} failed: {error}")]
+    Step {
+        /// The name of the step that failed.
+        step: String,
+        /// The error the step returned.
+        error: BoxedStepError,
+    },
+    /// A branch step classified the value into a branch that does not exist.
+    #[error("Pipeline step {step:?} has no branch for the classification result")]
+    MissingBranch {
+        /// The name of the branch step.
+        step: String,
+    },
+    /// The pipeline was cancelled before it finished.
+    #[error("Pipeline was cancelled")]
+    Cancelled,
+}
+
+/// Run a step of a pipeline inside of a tracing span named after the step.
+async fn run_step<O, E: Into<BoxedStepError>>(
+    name: &str,
+    step: impl Future<Output = Result<O, E>>,
+) -> Result<O, PipelineError> {
+    let span = tracing::info_span!("pipeline_step", step = name);
+    let start = Instant::now();
+    let result = step.instrument(span.clone()).await;
+    tracing::debug!(parent: &span, elapsed = ?start.elapsed(), success = result.is_ok(), "finished pipeline step");
+    result.map_err(|error| PipelineError::Step {
+        step: name.to_string(),
+        error: error.into(),
+    })
+}
+
+/// A chain of async steps with typed intermediate values. Steps can be tasks, other models or any async function.
+///
+/// Every step has a name that is used for the tracing span the step runs in and in the [`PipelineError`] if the
+/// step fails.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[derive(Parse, Clone, PartialEq, Eq, Hash)]
+/// enum Topic {
+///     Billing,
+///     Technical,
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let llm = Llama::new_chat().await.unwrap();
+///     let classify = llm
+///         .task("Classify the support ticket as Billing or Technical.")
+///         .typed::<Topic>();
+///     let billing = llm.task("Write a friendly reply to the billing question.");
+///     let technical = llm.task("Write step by step troubleshooting instructions for the question.");
+///
+///     let pipeline = Pipeline::<String>::new().branch(
+///         "classify",
+///         move |ticket: &String| {
+///             let topic = classify(ticket);
+///             async move { topic.await }
+///         },
+///         [
+///             (
+///                 Topic::Billing,
+///                 Pipeline::new().then("billing reply", move |ticket: String| {
+///                     let reply = billing(&ticket);
+///                     async move { reply.await }
+///                 }),
+///             ),
+///             (
+///                 Topic::Technical,
+///                 Pipeline::new().then("technical reply", move |ticket: String| {
+///                     let reply = technical(&ticket);
+///                     async move { reply.await }
+///                 }),
+///             ),
+///         ],
+///     );
+///
+///     let reply = pipeline.run("I was charged twice this month".to_string()).await.unwrap();
+///     println!("{reply}");
+/// }
+/// ```
+pub struct Pipeline<I, O = I> {
+    run: Arc<dyn Fn(I) -> PipelineFuture<O> + Send + Sync>,
+}
+
+impl<I, O> Clone for Pipeline<I, O> {
+    fn clone(&self) -> Self {
+        Self {
+            run: self.run.clone(),
+        }
+    }
+}
+
+impl<I: Send + 'static> Default for Pipeline<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Send + 'static> Pipeline<I> {
+    /// Create a new pipeline that returns its input unchanged.
+    pub fn new() -> Self {
+        Self::from_fn(|input| async move { Ok(input) }.boxed())
+    }
+}
+
+impl<I: Send + 'static, O: Send + 'static> Pipeline<I, O> {
+    fn from_fn(run: impl Fn(I) -> PipelineFuture<O> + Send + Sync + 'static) -> Self {
+        Self { run: Arc::new(run) }
+    }
+
+    /// Run an async step on the output of the pipeline.
+    pub fn then<O2, F, Fut, E>(self, name: impl ToString, step: F) -> Pipeline<I, O2>
+    where
+        O2: Send + 'static,
+        F: Fn(O) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<O2, E>> + Send + 'static,
+        E: Into<BoxedStepError>,
+    {
+        let name: Arc<str> = name.to_string().into();
+        let step = Arc::new(step);
+        let previous = self.run;
+        Pipeline::from_fn(move |input| {
+            let previous = previous.clone();
+            let step = step.clone();
+            let name = name.clone();
+            async move {
+                let value = previous(input).await?;
+                run_step(&name, step(value)).await
+            }
+            .boxed()
+        })
+    }
+
+    /// Transform the output of the pipeline with a function that cannot fail.
+    pub fn map<O2, F>(self, name: impl ToString, map: F) -> Pipeline<I, O2>
+    where
+        O2: Send + 'static,
+        F: Fn(O) -> O2 + Send + Sync + 'static,
+    {
+        self.then(name, move |value| {
+            let output = map(value);
+            async move { Ok::<_, std::convert::Infallible>(output) }
+        })
+    }
+
+    /// Classify the output of the pipeline and continue with the pipeline for that classification. If there is no
+    /// pipeline for the classification, the pipeline fails with [`PipelineError::MissingBranch`].
+    pub fn branch<K, O2, F, Fut, E>(
+        self,
+        name: impl ToString,
+        classify: F,
+        branches: impl IntoIterator<Item = (K, Pipeline<O, O2>)>,
+    ) -> Pipeline<I, O2>
+    where
+        K: Eq + Hash + Send + Sync + 'static,
+        O2: Send + 'static,
+        F: Fn(&O) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<K, E>> + Send + 'static,
+        E: Into<BoxedStepError>,
+    {
+        let name: Arc<str> = name.to_string().into();
+        let classify = Arc::new(classify);
+        let branches: Arc<HashMap<K, Pipeline<O, O2>>> = Arc::new(branches.into_iter().collect());
+        let previous = self.run;
+        Pipeline::from_fn(move |input| {
+            let previous = previous.clone();
+            let classify = classify.clone();
+            let branches = branches.clone();
+            let name = name.clone();
+            async move {
+                let value = previous(input).await?;
+                let key = run_step(&name, classify(&value)).await?;
+                let branch = branches
+                    .get(&key)
+                    .ok_or_else(|| PipelineError::MissingBranch {
+                        step: name.to_string(),
+                    })?;
+                (branch.run)(value).await
+            }
+            .boxed()
+        })
+    }
+
+    /// Run several pipelines on a copy of the output of the pipeline at the same time and collect their outputs
+    /// in the same order as the pipelines.
+    pub fn fan_out<O2>(
+        self,
+        name: impl ToString,
+        pipelines: impl IntoIterator<Item = Pipeline<O, O2>>,
+    ) -> Pipeline<I, Vec<O2>>
+    where
+        O: Clone,
+        O2: Send + 'static,
+    {
+        let name: Arc<str> = name.to_string().into();
+        let pipelines: Arc<[Pipeline<O, O2>]> = pipelines.into_iter().collect();
+        let previous = self.run;
+        Pipeline::from_fn(move |input| {
+            let previous = previous.clone();
+            let pipelines = pipelines.clone();
+            let name = name.clone();
+            async move {
+                let value = previous(input).await?;
+                let span = tracing::info_span!("pipeline_step", step = &*name);
+                futures_util::future::try_join_all(
+                    pipelines
+                        .iter()
+                        .map(|pipeline| (pipeline.run)(value.clone())),
+                )
+                .instrument(span)
+                .await
+            }
+            .boxed()
+        })
+    }
+
+    /// Run two pipelines with different output types on a copy of the output of the pipeline at the same time and
+    /// join their outputs.
+    pub fn join<A, B>(
+        self,
+        name: impl ToString,
+        first: Pipeline<O, A>,
+        second: Pipeline<O, B>,
+    ) -> Pipeline<I, (A, B)>
+    where
+        O: Clone,
+        A: Send + 'static,
+        B: Send + 'static,
+    {
+        let name: Arc<str> = name.to_string().into();
+        let previous = self.run;
+        Pipeline::from_fn(move |input| {
+            let previous = previous.clone();
+            let first = first.clone();
+            let second = second.clone();
+            let name = name.clone();
+            async move {
+                let value = previous(input).await?;
+                let span = tracing::info_span!("pipeline_step", step = &*name);
+                futures_util::future::try_join((first.run)(value.clone()), (second.run)(value))
+                    .instrument(span)
+                    .await
+            }
+            .boxed()
+        })
+    }
+
+    /// Run the pipeline with an input.
+    pub fn run(&self, input: I) -> impl Future<Output = Result<O, PipelineError>> + Send {
+        (self.run)(input)
+    }
+
+    /// Run the pipeline with an input and get a handle that can cancel the pipeline. Cancelling the pipeline stops
+    /// the step that is currently running and makes the pipeline return [`PipelineError::Cancelled`].
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let llm = Llama::new_chat().await.unwrap();
+    ///     let summarize = llm.task("Summarize the text in one sentence.");
+    ///     let pipeline = Pipeline::<String>::new()
+    ///         .then("summarize", move |text: String| {
+    ///             let summary = summarize(&text);
+    ///             async move { summary.await }
+    ///         });
+    ///     let (summary, cancel) = pipeline.run_cancellable("A very long document...".to_string());
+    ///     // Stop the pipeline if it takes too long
+    ///     tokio::spawn(async move {
+    ///         tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+    ///         cancel.abort();
+    ///     });
+    ///     match summary.await {
+    ///         Ok(summary) => println!("{summary}"),
+    ///         Err(PipelineError::Cancelled) => println!("timed out"),
+    ///         Err(err) => println!("{err}"),
+    ///     }
+    /// }
+    /// ```
+    pub fn run_cancellable(
+        &self,
+        input: I,
+    ) -> (
+        impl Future<Output = Result<O, PipelineError>> + Send,
+        AbortHandle,
+    ) {
+        let (handle, registration) = AbortHandle::new_pair();
+        let future = Abortable::new((self.run)(input), registration)
+            .map(|result| result.unwrap_or(Err(PipelineError::Cancelled)));
+        (future, handle)
+    }
+}
+
+#[test]
+fn pipeline_branches_and_fans_out() {
+    let classify = |value: &i32| {
+        let even = value % 2 == 0;
+        async move { Ok::<_, std::convert::Infallible>(even) }
+    };
+    let pipeline = Pipeline::<i32>::new()
+        .map("double", |value| value * 2)
+        .then("add one", |value| async move {
+            if value > 100 {
+                Err("too large")
+            } else {
+                Ok(value + 1)
+            }
+        })
+        .branch(
+            "parity",
+            classify,
+            [
+                (true, Pipeline::new().map("even", |value: i32| vec![value])),
+                (
+                    false,
+                    Pipeline::new().fan_out(
+                        "odd",
+                        [
+                            Pipeline::new().map("first", |value: i32| value),
+                            Pipeline::new().map("second", |value: i32| -value),
+                        ],
+                    ),
+                ),
+            ],
+        )
+        .join(
+            "summarize",
+            Pipeline::new().map("sum", |values: Vec<i32>| values.iter().sum::<i32>()),
+            Pipeline::new().map("count", |values: Vec<i32>| values.len()),
+        );
+
+    let result = futures_util::FutureExt::now_or_never(pipeline.run(5)).unwrap();
+    assert_eq!(result.unwrap(), (0, 2));
+
+    let result = futures_util::FutureExt::now_or_never(pipeline.run(60)).unwrap();
+    assert!(matches!(
+        result,
+        Err(PipelineError::Step { step, .. }) if step == "add one"
+    ));
+
+    let (future, handle) = pipeline.run_cancellable(5);
+    handle.abort();
+    assert!(matches!(
+        futures_util::FutureExt::now_or_never(future).unwrap(),
+        Err(PipelineError::Cancelled)
+    ));
+}
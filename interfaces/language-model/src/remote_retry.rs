@@ -0,0 +1,113 @@
+use crate::CancellationToken;
+use futures_util::StreamExt;
+use kalosm_model_types::{RetryPolicy, RetryReason};
+use reqwest_eventsource::{Event, EventSource, RequestBuilderExt};
+
+fn event_source_retry_reason(err: &reqwest_eventsource::Error) -> Option<RetryReason> {
+    match err {
+        reqwest_eventsource::Error::Transport(err) => RetryReason::from_reqwest_error(err),
+        reqwest_eventsource::Error::InvalidStatusCode(status, _) => {
+            Some(RetryReason::Status(status.as_u16()))
+        }
+        reqwest_eventsource::Error::StreamEnded => Some(RetryReason::Connection),
+        _ => None,
+    }
+}
+
+/// Open a server sent event stream. Failures before the stream opens are retried with the retry policy. Once the
+/// stream is open, tokens may have already been streamed to the user, so later errors are not retried.
+pub(crate) async fn open_event_source(
+    retry_policy: &RetryPolicy,
+    request: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<EventSource, reqwest_eventsource::Error> {
+    retry_policy
+        .retry(
+            || {
+                let mut event_source = request().eventsource().unwrap();
+                async move {
+                    match event_source.next().await {
+                        Some(Ok(Event::Open)) => Ok(event_source),
+                        Some(Ok(Event::Message(_))) => {
+                            unreachable!("the open event is always sent before messages")
+                        }
+                        Some(Err(err)) => {
+                            event_source.close();
+                            Err(err)
+                        }
+                        None => Err(reqwest_eventsource::Error::StreamEnded),
+                    }
+                }
+            },
+            event_source_retry_reason,
+            tokio::time::sleep,
+        )
+        .await
+}
+
+/// Why [`next_event_before`] stopped waiting before the next event arrived.
+pub(crate) enum StreamInterrupted {
+    /// The deadline passed.
+    TimedOut,
+    /// The cancellation token was cancelled.
+    Cancelled,
+}
+
+/// Wait for the next event from a server sent event stream. Returns an error if the deadline passes or the token is
+/// cancelled first, so a stalled stream can still be cancelled.
+pub(crate) async fn next_event_before(
+    event_source: &mut EventSource,
+    deadline: Option<tokio::time::Instant>,
+    cancellation_token: Option<&CancellationToken>,
+) -> Result<Option<Result<Event, reqwest_eventsource::Error>>, StreamInterrupted> {
+    let cancelled = async {
+        match cancellation_token {
+            Some(token) => token.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+    let deadline_passed = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        biased;
+        _ = cancelled => Err(StreamInterrupted::Cancelled),
+        event = event_source.next() => Ok(event),
+        _ = deadline_passed => Err(StreamInterrupted::TimedOut),
+    }
+}
+
+/// Measures the prefill and decode time of a streamed response. The prefill time is the time until the first token
+/// arrives.
+pub(crate) struct UsageTimer {
+    started: std::time::Instant,
+    first_token: Option<std::time::Instant>,
+}
+
+impl UsageTimer {
+    /// Start timing a request.
+    pub(crate) fn start() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            first_token: None,
+        }
+    }
+
+    /// Record that a token arrived.
+    pub(crate) fn token(&mut self) {
+        self.first_token.get_or_insert_with(std::time::Instant::now);
+    }
+
+    /// Get the usage of the request with the token counts the server reported.
+    pub(crate) fn finish(&self, prompt_tokens: usize, completion_tokens: usize) -> crate::Usage {
+        let first_token = self.first_token.unwrap_or_else(std::time::Instant::now);
+        crate::Usage::new(
+            prompt_tokens,
+            completion_tokens,
+            first_token - self.started,
+            first_token.elapsed(),
+        )
+    }
+}
@@ -0,0 +1,279 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use kalosm_language_model::{
+    ChatMessage, ChatModel, CreateChatSession, CreateDefaultChatConstraintsForType,
+    CreateDefaultCompletionConstraintsForType, CreateTextCompletionSession, ModelConstraints,
+    StructuredChatModel, StructuredTextCompletionModel, TextCompletionModel,
+};
+use kalosm_model_types::ModelLoadingProgress;
+use kalosm_sample::{ArcParser, CreateParserState, Parse, Parser, ParserExt};
+use llm_samplers::types::Sampler;
+
+use crate::model::{LlamaModel, LlamaModelError};
+use crate::raw::cache::LlamaCache;
+use crate::{
+    Llama, LlamaBuilder, LlamaChatSession, LlamaSession, LlamaSourceError,
+    StructuredGenerationTask, Task,
+};
+
+type LoadResult = Option<Result<Llama, Arc<LlamaSourceError>>>;
+
+/// A [`Llama`] model that is loading in the background, from [`LlamaBuilder::build_in_background`].
+///
+/// The handle can be used like a [`Llama`] model right away. Generation waits until the model is loaded, and sessions
+/// created before the model is loaded are sized for the model when they are first used.
+///
+/// Clones of the handle share the same model.
+#[derive(Clone)]
+pub struct BackgroundLlama {
+    receiver: tokio::sync::watch::Receiver<LoadResult>,
+}
+
+impl LlamaBuilder {
+    /// Start loading the model in the background and return a handle to it immediately. This must be called from
+    /// inside a tokio runtime.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder().build_in_background();
+    /// // Set up the rest of the app while the model loads
+    /// let mut chat = model.chat();
+    /// // The first message waits until the model is loaded
+    /// chat("Hello!").to_std_out().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn build_in_background(self) -> BackgroundLlama {
+        self.build_in_background_with_loading_handler(|_| {})
+    }
+
+    /// Start loading the model in the background with a handler for progress as the download and loading
+    /// progresses. See [`LlamaBuilder::build_in_background`].
+    pub fn build_in_background_with_loading_handler(
+        self,
+        handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> BackgroundLlama {
+        let (sender, receiver) = tokio::sync::watch::channel(None);
+        tokio::spawn(async move {
+            let result = self.build_with_loading_handler(handler).await;
+            _ = sender.send(Some(result.map_err(Arc::new)));
+        });
+        BackgroundLlama { receiver }
+    }
+}
+
+impl BackgroundLlama {
+    /// Check if the model finished loading (or failed to load).
+    pub fn is_ready(&self) -> bool {
+        self.receiver.borrow().is_some()
+    }
+
+    /// Wait until the model is loaded and get it.
+    pub async fn model(&self) -> Result<Llama, LlamaModelError> {
+        let mut receiver = self.receiver.clone();
+        let result = receiver
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        match result.as_ref().unwrap() {
+            Ok(model) => Ok(model.clone()),
+            Err(err) => Err(LlamaModelError::Loading(err.clone())),
+        }
+    }
+
+    /// Wait until the model is loaded and then run [`Llama::warm_up`].
+    pub async fn warm_up(&self) -> Result<(), LlamaModelError> {
+        self.model().await?.warm_up().await
+    }
+}
+
+impl Llama {
+    /// Run a short prompt through the model and throw away the result. The first forward pass on an accelerator
+    /// compiles and uploads kernels, so warming up the model ahead of time makes the first real request faster.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new_chat().await?;
+    /// model.warm_up().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn warm_up(&self) -> Result<(), LlamaModelError> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.task_sender
+            .send(Task::StructuredGeneration(StructuredGenerationTask {
+                priority: Default::default(),
+                session: None,
+                runner: Box::new(move |model| {
+                    _ = tx.send(warm_up(model));
+                }),
+            }))
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        rx.await.map_err(|_| LlamaModelError::ModelStopped)?
+    }
+}
+
+/// Run a prompt and then a single token so both the batched and the single token kernels are compiled.
+fn warm_up(model: &mut LlamaModel) -> Result<(), LlamaModelError> {
+    let mut tokens = model
+        .tokenizer
+        .encode_fast("Hello, world!", false)
+        .map_err(LlamaModelError::Tokenizer)?
+        .get_ids()
+        .to_vec();
+    if tokens.len() < 2 {
+        tokens = vec![0, 0];
+    }
+    let mut cache = LlamaCache::new(&model.model.config);
+    let mut logits = Vec::new();
+    let (prompt, last) = tokens.split_at(tokens.len() - 1);
+    LlamaModel::forward(
+        &model.model,
+        &model.device,
+        prompt,
+        Some(&mut cache),
+        &mut logits,
+    )?;
+    LlamaModel::forward(
+        &model.model,
+        &model.device,
+        last,
+        Some(&mut cache),
+        &mut logits,
+    )?;
+    Ok(())
+}
+
+impl CreateTextCompletionSession for BackgroundLlama {
+    type Session = LlamaSession;
+    type Error = LlamaModelError;
+
+    fn new_session(&self) -> Result<Self::Session, Self::Error> {
+        match &*self.receiver.borrow() {
+            Some(Ok(model)) => model.new_session(),
+            Some(Err(err)) => Err(LlamaModelError::Loading(err.clone())),
+            None => Ok(LlamaSession::unsized_session()),
+        }
+    }
+}
+
+impl<S: Sampler + 'static> TextCompletionModel<S> for BackgroundLlama {
+    fn stream_text_with_callback<'a>(
+        &'a self,
+        session: &'a mut Self::Session,
+        text: &str,
+        sampler: S,
+        on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+        let text = text.to_string();
+        async move {
+            let model = self.model().await?;
+            model
+                .stream_text_with_callback(session, &text, sampler, on_token)
+                .await
+        }
+    }
+}
+
+impl<S, Constraints> StructuredTextCompletionModel<Constraints, S> for BackgroundLlama
+where
+    <Constraints as Parser>::Output: Send,
+    Constraints: CreateParserState + Send + 'static,
+    S: Sampler + 'static,
+{
+    fn stream_text_with_callback_and_parser<'a>(
+        &'a self,
+        session: &'a mut Self::Session,
+        text: &str,
+        sampler: S,
+        parser: Constraints,
+        on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<Constraints::Output, Self::Error>> + Send + 'a {
+        let text = text.to_string();
+        async move {
+            let model = self.model().await?;
+            model
+                .stream_text_with_callback_and_parser(session, &text, sampler, parser, on_token)
+                .await
+        }
+    }
+}
+
+impl CreateChatSession for BackgroundLlama {
+    type Error = LlamaModelError;
+    type ChatSession = LlamaChatSession;
+
+    fn new_chat_session(&self) -> Result<Self::ChatSession, Self::Error> {
+        Ok(LlamaChatSession::new(self.new_session()?))
+    }
+}
+
+impl<S: Sampler + 'static> ChatModel<S> for BackgroundLlama {
+    fn add_messages_with_callback<'a>(
+        &'a self,
+        session: &'a mut Self::ChatSession,
+        messages: &[ChatMessage],
+        sampler: S,
+        on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+        let messages = messages.to_vec();
+        async move {
+            let model = self.model().await?;
+            model
+                .add_messages_with_callback(session, &messages, sampler, on_token)
+                .await
+        }
+    }
+}
+
+impl<S, Constraints> StructuredChatModel<Constraints, S> for BackgroundLlama
+where
+    <Constraints as Parser>::Output: Send,
+    Constraints: CreateParserState + Send + 'static,
+    S: Sampler + 'static,
+{
+    fn add_message_with_callback_and_constraints<'a>(
+        &'a self,
+        session: &'a mut Self::ChatSession,
+        messages: &[ChatMessage],
+        sampler: S,
+        constraints: Constraints,
+        on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
+    ) -> impl Future<Output = Result<<Constraints as ModelConstraints>::Output, Self::Error>> + Send + 'a
+    {
+        let messages = messages.to_vec();
+        async move {
+            let model = self.model().await?;
+            model
+                .add_message_with_callback_and_constraints(
+                    session,
+                    &messages,
+                    sampler,
+                    constraints,
+                    on_token,
+                )
+                .await
+        }
+    }
+}
+
+impl<T: Parse + 'static> CreateDefaultChatConstraintsForType<T> for BackgroundLlama {
+    type DefaultConstraints = ArcParser<T>;
+
+    fn create_default_constraints() -> Self::DefaultConstraints {
+        T::new_parser().boxed()
+    }
+}
+
+impl<T: Parse + 'static> CreateDefaultCompletionConstraintsForType<T> for BackgroundLlama {
+    type DefaultConstraints = ArcParser<T>;
+
+    fn create_default_constraints() -> Self::DefaultConstraints {
+        T::new_parser().boxed()
+    }
+}
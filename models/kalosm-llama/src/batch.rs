@@ -0,0 +1,688 @@
+use std::time::{Duration, Instant};
+
+use kalosm_common::copy_tensor_into_vec;
+use kalosm_language_model::{FinishReason, StopContext, StopSequenceMatcher, Usage};
+use llm_samplers::types::Logits;
+use rand::SeedableRng;
+
+use crate::logprobs::token_logprobs;
+use crate::model::{log_softmax, LlamaModel, LlamaModelError};
+use crate::raw::cache::LlamaCache;
+use crate::token_stream::TokenOutputStream;
+use crate::{InferenceSettings, LlamaSession, UnstructuredGenerationTask};
+
+/// The number of generations that are decoded together if [`crate::LlamaBuilder::with_max_batch_size`] is not set.
+pub(crate) const DEFAULT_MAX_BATCH_SIZE: usize = 8;
+
+/// An unstructured generation that is in progress. The prompt is already fed into the session, and each call to
+/// [`Generation::next_token`] samples one token from the logits of the last forward pass.
+pub(crate) struct Generation {
+    pub(crate) session: LlamaSession,
+    settings: InferenceSettings,
+    on_token: Box<dyn FnMut(String) -> Result<(), LlamaModelError> + Send + Sync>,
+    finished: tokio::sync::oneshot::Sender<Result<(), LlamaModelError>>,
+    text_stream: TokenOutputStream,
+    logit_probs: Vec<f32>,
+    stop_token: u32,
+    /// Matches the stop_on string and stop sequences. It holds back text that could be the start of a stop sequence.
+    stop_sequences: StopSequenceMatcher,
+    tokens_generated: u32,
+    /// The text generated so far for the stop criteria
+    generated_text: String,
+    started: Instant,
+    /// The random number generator for sampling. It is seeded once per generation so a seed reproduces the whole
+    /// output.
+    rng: rand::rngs::StdRng,
+    /// The number of prompt tokens in the text stream
+    prompt_tokens: usize,
+    /// The time it took to feed the prompt into the session
+    prefill_time: Duration,
+    /// The result of a token that was sampled while checking a prompt lookup draft. It is returned by the next call
+    /// to [`Generation::next_input`] in place of a new sample.
+    sampled_while_checking_draft: Option<Option<u32>>,
+    /// The prompt token that was removed for token healing, if it hasn't been replaced yet.
+    token_healing: Option<TokenHealing>,
+    /// Why generation stopped, once it has
+    finish_reason: Option<FinishReason>,
+    /// The number of tokens in the session before the prompt was fed. A cancelled generation truncates the session
+    /// back to this length.
+    prompt_start: usize,
+}
+
+/// The last prompt token, which is removed before the prompt is fed into the model when token healing is enabled. The
+/// generated tokens must continue its text until all of it has been generated again, and that text is removed from the
+/// output.
+struct TokenHealing {
+    token: u32,
+    /// The text of the removed token that has not been generated yet
+    remaining: String,
+    /// If any of the text has been generated yet
+    started: bool,
+}
+
+impl LlamaModel {
+    /// Feed the prompt of a task into its session and get the generation, ready to sample the first token. If the
+    /// prompt fails, the error is sent to the task and `None` is returned.
+    pub(crate) fn start_generation(&self, task: UnstructuredGenerationTask) -> Option<Generation> {
+        let UnstructuredGenerationTask {
+            settings,
+            on_token,
+            finished,
+        } = task;
+        let prompt_start = settings.session.cache.read().unwrap().tokens.len();
+        let prefill_started = Instant::now();
+        match self.feed_prompt(&settings) {
+            Ok((text_stream, logit_probs, token_healing)) => {
+                let prefill_time = prefill_started.elapsed();
+                let prompt_tokens = text_stream.tokens().len();
+                let rng = match settings.seed {
+                    Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+                    None => rand::rngs::StdRng::from_entropy(),
+                };
+                if let Some(stop_criteria) = &settings.stop_criteria {
+                    stop_criteria.lock().unwrap().reset();
+                }
+                let stop_sequences = StopSequenceMatcher::new(
+                    settings.stop_sequences.as_ref(),
+                    settings.stop_on.as_deref(),
+                );
+                Some(Generation {
+                    session: settings.session.clone(),
+                    settings,
+                    on_token,
+                    finished,
+                    text_stream,
+                    logit_probs,
+                    stop_token: self.model.config.stop_token,
+                    stop_sequences,
+                    tokens_generated: 0,
+                    generated_text: String::new(),
+                    started: Instant::now(),
+                    rng,
+                    prompt_tokens,
+                    prefill_time,
+                    sampled_while_checking_draft: None,
+                    token_healing,
+                    finish_reason: None,
+                    prompt_start,
+                })
+            }
+            // The deadline passed before the prompt was read, so finish without generating any text
+            Err(LlamaModelError::TimedOut) => {
+                if let Some(finish_handler) = &settings.finish_handler {
+                    (finish_handler.lock().unwrap())(FinishReason::TimedOut);
+                }
+                _ = finished.send(Ok(()));
+                None
+            }
+            Err(err) => {
+                tracing::error!("Error running model: {err}");
+                _ = finished.send(Err(err));
+                None
+            }
+        }
+    }
+
+    fn feed_prompt(
+        &self,
+        settings: &InferenceSettings,
+    ) -> Result<(TokenOutputStream, Vec<f32>, Option<TokenHealing>), LlamaModelError> {
+        let mut session = settings
+            .session
+            .cache
+            .write()
+            .map_err(|err| LlamaModelError::Session(err.to_string()))?;
+
+        let tokens = self
+            .tokenizer
+            .encode_fast(settings.prompt.as_str(), false)
+            .map_err(LlamaModelError::Tokenizer)?;
+        let mut tokens = tokens.get_ids();
+
+        // Remove the last token so the model can pick a token that continues it. Added tokens are never healed.
+        let mut healed_token = None;
+        if settings.token_healing {
+            if let [rest @ .., last] = tokens {
+                if !rest.is_empty() && !self.tokenizer.get_added_tokens_decoder().contains_key(last)
+                {
+                    tokens = rest;
+                    healed_token = Some(*last);
+                }
+            }
+        }
+
+        let mut text_stream = TokenOutputStream::new(self.tokenizer.clone());
+        for &token in tokens {
+            text_stream
+                .next_token(token)
+                .map_err(LlamaModelError::TokenOutputStreamError)?;
+        }
+        let token_healing = match healed_token {
+            Some(token) => text_stream
+                .peek_token(token)
+                .map_err(LlamaModelError::TokenOutputStreamError)?
+                .filter(|text| !text.is_empty())
+                .map(|text| TokenHealing {
+                    token,
+                    remaining: text,
+                    started: false,
+                }),
+            None => None,
+        };
+        // If the token has no text to continue, feed it with the rest of the prompt
+        let tokens = match (healed_token, &token_healing) {
+            (Some(token), None) => {
+                text_stream
+                    .next_token(token)
+                    .map_err(LlamaModelError::TokenOutputStreamError)?;
+                let mut tokens = tokens.to_vec();
+                tokens.push(token);
+                tokens
+            }
+            _ => tokens.to_vec(),
+        };
+
+        session.degradations.clear();
+        let mut logit_probs = Vec::new();
+        self.prefill(
+            &tokens,
+            &mut session,
+            &mut logit_probs,
+            settings.prefill_progress.as_ref(),
+            settings.deadline,
+        )?;
+        Ok((text_stream, logit_probs, token_healing))
+    }
+
+    /// Run a generation to the end on its own. Background generations yield to interactive tasks between tokens.
+    pub(crate) fn run_generation(&mut self, mut generation: Generation) {
+        loop {
+            self.yield_to_interactive(&generation.session);
+            let token = match generation.next_input() {
+                Ok(Some(token)) => token,
+                Ok(None) => return generation.finish(Ok(())),
+                Err(err) => return generation.finish(Err(err)),
+            };
+            let session = generation.session.clone();
+            let mut cache = session.cache.write().unwrap();
+            let draft = generation.draft(&cache, token, self.model.config.context_length);
+            let result = if draft.is_empty() {
+                self.forward_with_recovery(&[token], &mut cache, &mut generation.logit_probs)
+                    .map_err(LlamaModelError::from)
+            } else {
+                self.forward_draft(&mut generation, token, &draft, &mut cache)
+            };
+            drop(cache);
+            if let Err(err) = result {
+                return generation.finish(Err(err));
+            }
+        }
+    }
+
+    /// Decode one token for every active generation in a single batch. Generations that finish are removed from
+    /// the list and their result is sent.
+    pub(crate) fn step_generations(&self, active: &mut Vec<Generation>) {
+        let mut running = Vec::with_capacity(active.len());
+        let mut tokens = Vec::with_capacity(active.len());
+        for mut generation in active.drain(..) {
+            match generation.next_input() {
+                Ok(Some(token)) => {
+                    running.push(generation);
+                    tokens.push(token);
+                }
+                Ok(None) => generation.finish(Ok(())),
+                Err(err) => generation.finish(Err(err)),
+            }
+        }
+
+        let results = self.forward_generations(&mut running, &tokens);
+        for (generation, result) in running.into_iter().zip(results) {
+            match result {
+                Ok(()) => active.push(generation),
+                Err(err) => generation.finish(Err(err)),
+            }
+        }
+    }
+
+    /// Feed one token into each generation and store the new logits. Sequences that can't be batched (because their
+    /// context is full, activations are being captured or they have a prompt lookup draft) and every sequence of a
+    /// batch that fails are run one at a time instead.
+    fn forward_generations(
+        &self,
+        generations: &mut [Generation],
+        tokens: &[u32],
+    ) -> Vec<Result<(), LlamaModelError>> {
+        let sessions: Vec<LlamaSession> = generations
+            .iter()
+            .map(|generation| generation.session.clone())
+            .collect();
+        let mut caches: Vec<_> = sessions
+            .iter()
+            .map(|session| session.cache.write().unwrap())
+            .collect();
+
+        let context_length = self.model.config.context_length;
+        let drafts: Vec<Vec<u32>> = generations
+            .iter()
+            .zip(&caches)
+            .zip(tokens)
+            .map(|((generation, cache), &token)| generation.draft(cache, token, context_length))
+            .collect();
+        let mut batched: Vec<usize> = (0..caches.len())
+            .filter(|&i| {
+                let cache = &caches[i];
+                cache.tokens.len() < context_length
+                    && cache.activations.is_none()
+                    && drafts[i].is_empty()
+            })
+            .collect();
+        if batched.len() > 1 {
+            let lengths: Vec<usize> = batched.iter().map(|&i| caches[i].tokens.len()).collect();
+            let batch_tokens: Vec<u32> = batched.iter().map(|&i| tokens[i]).collect();
+            let mut batch_caches: Vec<&mut LlamaCache> = caches
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| batched.contains(i))
+                .map(|(_, cache)| &mut **cache)
+                .collect();
+            let result = self
+                .model
+                .forward_batch(&batch_tokens, &self.device, &mut batch_caches)
+                .and_then(|logits| logits.to_dtype(candle_core::DType::F32))
+                .and_then(|logits| {
+                    for (row, &i) in batched.iter().enumerate() {
+                        let logit_probs = &mut generations[i].logit_probs;
+                        copy_tensor_into_vec(&logits.get(row)?, logit_probs)?;
+                    }
+                    Ok(())
+                });
+            if let Err(err) = result {
+                tracing::warn!(
+                    "Failed to run the batch, running each generation separately: {err}"
+                );
+                for (&i, len) in batched.iter().zip(lengths) {
+                    caches[i].truncate(len);
+                }
+                batched.clear();
+            }
+        } else {
+            batched.clear();
+        }
+
+        (0..generations.len())
+            .map(|i| {
+                if batched.contains(&i) {
+                    return Ok(());
+                }
+                if !drafts[i].is_empty() {
+                    return self.forward_draft(
+                        &mut generations[i],
+                        tokens[i],
+                        &drafts[i],
+                        &mut caches[i],
+                    );
+                }
+                self.forward_with_recovery(
+                    &[tokens[i]],
+                    &mut caches[i],
+                    &mut generations[i].logit_probs,
+                )
+                .map_err(LlamaModelError::from)
+            })
+            .collect()
+    }
+
+    /// Feed a token and a prompt lookup draft of the tokens after it into the model in one pass. Tokens are sampled
+    /// from the logits of each position until a sample differs from the draft, and the cache keeps only the tokens
+    /// that were accepted. The last sample is fed into the model next.
+    fn forward_draft(
+        &self,
+        generation: &mut Generation,
+        token: u32,
+        draft: &[u32],
+        cache: &mut LlamaCache,
+    ) -> Result<(), LlamaModelError> {
+        let start = cache.tokens.len();
+        let snapshot = cache.clone();
+        let mut tokens = Vec::with_capacity(draft.len() + 1);
+        tokens.push(token);
+        tokens.extend_from_slice(draft);
+        let logits = match self
+            .model
+            .forward_all_positions(&tokens, &self.device, cache)
+            .and_then(|logits| logits.to_dtype(candle_core::DType::F32))
+        {
+            Ok(logits) => logits,
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to check the prompt lookup draft, running the token on its own: {err}"
+                );
+                *cache = snapshot;
+                return self
+                    .forward_with_recovery(&[token], cache, &mut generation.logit_probs)
+                    .map_err(LlamaModelError::from);
+            }
+        };
+
+        let mut accepted = 0;
+        for position in 0..tokens.len() {
+            copy_tensor_into_vec(&logits.get(position)?, &mut generation.logit_probs)?;
+            let sampled = generation.next_token()?;
+            if sampled.is_some() && sampled.as_ref() == draft.get(position) {
+                accepted += 1;
+                continue;
+            }
+            generation.sampled_while_checking_draft = Some(sampled);
+            break;
+        }
+        tracing::trace!(
+            "Accepted {accepted} of {} prompt lookup draft tokens",
+            draft.len()
+        );
+        cache.truncate(start + 1 + accepted);
+        Ok(())
+    }
+}
+
+impl Generation {
+    /// Get the next token to feed into the model. This is the token that was sampled while checking a draft if
+    /// there is one, or a new sample otherwise.
+    pub(crate) fn next_input(&mut self) -> Result<Option<u32>, LlamaModelError> {
+        match self.sampled_while_checking_draft.take() {
+            Some(sampled) => Ok(sampled),
+            None => self.next_token(),
+        }
+    }
+
+    /// Propose a prompt lookup draft of the tokens after the next input token. Returns an empty draft if prompt
+    /// lookup is disabled or the draft can't be checked without truncating the context.
+    fn draft(&self, cache: &LlamaCache, token: u32, context_length: usize) -> Vec<u32> {
+        let Some(prompt_lookup) = self.settings.prompt_lookup else {
+            return Vec::new();
+        };
+        // Captured activations need every token to run through the model once
+        let room = context_length.saturating_sub(cache.tokens.len() + 1);
+        if room == 0 || cache.activations.is_some() {
+            return Vec::new();
+        }
+        let mut context = Vec::with_capacity(cache.tokens.len() + 1);
+        context.extend_from_slice(&cache.tokens);
+        context.push(token);
+        let draft = prompt_lookup.draft(&context);
+        draft[..draft.len().min(room)].to_vec()
+    }
+
+    /// Sample the next token and send any new text. Returns the token to feed into the model, or `None` once the
+    /// generation is finished.
+    pub(crate) fn next_token(&mut self) -> Result<Option<u32>, LlamaModelError> {
+        // The caller dropped the stream, so stop without touching the session
+        if self.finished.is_closed() {
+            return Ok(None);
+        }
+        if self
+            .settings
+            .cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Err(LlamaModelError::Cancelled);
+        }
+        if self
+            .settings
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            tracing::trace!("Stopping generation that timed out");
+            return Ok(self.stop(FinishReason::TimedOut));
+        }
+        if self.tokens_generated >= self.settings.max_tokens {
+            return Ok(self.stop(FinishReason::MaxTokens));
+        }
+        let mut healed_token = None;
+        let logits = match &self.token_healing {
+            Some(healing) => {
+                // The tokens that continue the prompt may be unlikely, so check the whole vocabulary
+                let allowed = self
+                    .text_stream
+                    .tokens_continuing(self.logit_probs.len() as u32, &healing.remaining);
+                if allowed.is_empty() && !healing.started {
+                    // No token continues the prompt, so keep the original token
+                    healed_token = Some(healing.token);
+                } else if allowed.is_empty() {
+                    // The text generated so far can't be finished, so stop healing
+                    self.token_healing = None;
+                }
+                let mut logits = Logits::try_from_iter(self.logit_probs.iter().copied())
+                    .expect("model output should be valid logits");
+                if !allowed.is_empty() {
+                    logits.retain(|logit| allowed.binary_search(&logit.token_id).is_ok());
+                }
+                logits
+            }
+            None => Logits::try_from_iter_top_k(self.logit_probs.iter().copied(), 512)
+                .expect("model output should be valid logits"),
+        };
+        let new_token = match healed_token {
+            Some(token) => token,
+            None => self
+                .text_stream
+                .sample_token(
+                    &mut self.settings.sampler,
+                    logits,
+                    self.settings.stop_on.as_deref(),
+                    &mut self.rng,
+                )
+                .map_err(LlamaModelError::TokenOutputStreamError)?,
+        };
+        if new_token == self.stop_token {
+            tracing::trace!("Stopping on stop token");
+            return Ok(self.stop(FinishReason::Stop));
+        }
+        if let Some((top_alternatives, handler)) = &self.settings.logprobs {
+            let logprobs = token_logprobs(
+                &self.logit_probs,
+                new_token,
+                *top_alternatives,
+                self.text_stream.tokenizer(),
+            )?;
+            (handler.lock().unwrap())(logprobs);
+        }
+        let new_text = self
+            .text_stream
+            .next_token(new_token)
+            .map_err(LlamaModelError::TokenOutputStreamError)?;
+        if let Some(new_text) = &new_text {
+            if self.settings.stop_criteria.is_some() {
+                self.generated_text += new_text;
+            }
+        }
+        if let Some(mut new_text) = new_text {
+            self.tokens_generated += 1;
+            // The text of the healed token is already part of the prompt
+            if let Some(healing) = &mut self.token_healing {
+                healing.started = true;
+                if new_text.starts_with(&healing.remaining) {
+                    new_text.drain(..healing.remaining.len());
+                    self.token_healing = None;
+                } else if healing.remaining.starts_with(&new_text) {
+                    healing.remaining.drain(..new_text.len());
+                    new_text.clear();
+                } else {
+                    self.token_healing = None;
+                }
+            }
+            let (text, stopped_on) = self.stop_sequences.push(&new_text);
+            if !text.is_empty() {
+                (self.on_token)(text)?;
+            }
+            if let Some(stopped_on) = stopped_on {
+                tracing::trace!("Stopping on stop sequence {stopped_on:?}");
+                return Ok(self.stop(FinishReason::StopSequence));
+            }
+        }
+        if let Some(stop_criteria) = &self.settings.stop_criteria {
+            let context = StopContext::new(
+                new_token,
+                &self.generated_text,
+                self.tokens_generated as usize,
+                self.started.elapsed(),
+                Some(log_softmax(&self.logit_probs, new_token as usize)),
+            );
+            if stop_criteria.lock().unwrap().should_stop(&context) {
+                tracing::trace!("Stopping on stop criteria");
+                return Ok(self.stop(FinishReason::StopCriteria));
+            }
+        }
+        Ok(Some(new_token))
+    }
+
+    /// Record why generation stopped. Always returns `None` so it can be returned from [`Generation::next_token`].
+    fn stop(&mut self, reason: FinishReason) -> Option<u32> {
+        self.finish_reason = Some(reason);
+        None
+    }
+
+    /// Flush any queued text and send the result of the generation.
+    pub(crate) fn finish(mut self, result: Result<(), LlamaModelError>) {
+        let result = result.and_then(|()| {
+            let queued = self.stop_sequences.finish();
+            if !queued.is_empty() {
+                (self.on_token)(queued)?;
+            }
+            Ok(())
+        });
+        match &result {
+            Err(LlamaModelError::Cancelled) => {
+                tracing::trace!("Stopping cancelled generation");
+                // Drop the prompt and the tokens generated so far, but keep the context from earlier generations
+                self.session
+                    .cache
+                    .write()
+                    .unwrap()
+                    .truncate(self.prompt_start);
+            }
+            Err(err) => tracing::error!("Error running model: {err}"),
+            Ok(()) => {
+                if let Some(finish_handler) = &self.settings.finish_handler {
+                    let reason = self.finish_reason.unwrap_or(FinishReason::Stop);
+                    (finish_handler.lock().unwrap())(reason);
+                }
+                if let Some(usage_handler) = &self.settings.usage_handler {
+                    let degradations = self.session.cache.read().unwrap().degradations.clone();
+                    let usage = Usage::new(
+                        self.prompt_tokens,
+                        self.text_stream.tokens().len() - self.prompt_tokens,
+                        self.prefill_time,
+                        self.started.elapsed(),
+                    )
+                    .with_degradations(degradations);
+                    (usage_handler.lock().unwrap())(usage);
+                }
+            }
+        }
+        _ = self.finished.send(result);
+    }
+}
+
+#[test]
+fn generation_past_its_deadline_times_out() {
+    use std::sync::{Arc, Mutex};
+
+    let reasons = Arc::new(Mutex::new(Vec::new()));
+    let finish_handler: crate::FinishHandler = {
+        let reasons = reasons.clone();
+        Arc::new(Mutex::new(move |reason| {
+            reasons.lock().unwrap().push(reason)
+        }))
+    };
+    let sampler = Arc::new(Mutex::new(
+        kalosm_language_model::GenerationParameters::new(),
+    ));
+    let settings = InferenceSettings::new(
+        "",
+        LlamaSession::unsized_session(),
+        sampler,
+        u32::MAX,
+        None,
+        None,
+    )
+    .with_deadline(Some(Instant::now()))
+    .with_finish_handler(Some(finish_handler));
+    let (finished, mut result) = tokio::sync::oneshot::channel();
+    let tokenizer = tokenizers::Tokenizer::new(tokenizers::models::wordlevel::WordLevel::default());
+    let mut generation = Generation {
+        session: settings.session.clone(),
+        settings,
+        on_token: Box::new(|_| Ok(())),
+        finished,
+        text_stream: TokenOutputStream::new(Arc::new(tokenizer)),
+        logit_probs: vec![0., 1.],
+        stop_token: 0,
+        stop_sequences: StopSequenceMatcher::new(None, None),
+        tokens_generated: 0,
+        generated_text: String::new(),
+        started: Instant::now(),
+        rng: rand::rngs::StdRng::seed_from_u64(0),
+        prompt_tokens: 0,
+        prefill_time: Duration::ZERO,
+        sampled_while_checking_draft: None,
+        token_healing: None,
+        finish_reason: None,
+        prompt_start: 0,
+    };
+
+    assert_eq!(generation.next_input().unwrap(), None);
+    generation.finish(Ok(()));
+    assert!(result.try_recv().unwrap().is_ok());
+    assert_eq!(*reasons.lock().unwrap(), [FinishReason::TimedOut]);
+}
+
+#[tokio::test]
+async fn token_healing_regenerates_the_last_prompt_token() {
+    use kalosm_language_model::{
+        CreateTextCompletionSession, GenerationParameters, StopContext, TextCompletionModel,
+    };
+    use std::sync::{Arc, Mutex};
+
+    let model = crate::test_model::tiny_llama().await;
+    let tokenizer = model.tokenizer();
+    for seed in 0..8 {
+        let mut session = model.new_session().unwrap();
+        let sampled = Arc::new(Mutex::new(Vec::new()));
+        let streamed = Arc::new(Mutex::new(String::new()));
+        model
+            .stream_text_with_callback(
+                &mut session,
+                "o hel",
+                GenerationParameters::new()
+                    .with_token_healing(true)
+                    .with_seed(seed)
+                    .with_stop_criteria({
+                        let sampled = sampled.clone();
+                        move |context: &StopContext| {
+                            let mut sampled = sampled.lock().unwrap();
+                            sampled.push(context.token());
+                            sampled.len() >= 16
+                        }
+                    }),
+                {
+                    let streamed = streamed.clone();
+                    move |token| {
+                        streamed.lock().unwrap().push_str(&token);
+                        Ok(())
+                    }
+                },
+            )
+            .await
+            .unwrap();
+
+        // The sampled tokens spell out the removed prompt text again, but only the text after it is streamed
+        let sampled = tokenizer.decode(&sampled.lock().unwrap(), false).unwrap();
+        let streamed = streamed.lock().unwrap();
+        assert!(sampled.starts_with("hel"), "seed {seed}: {sampled:?}");
+        assert!(
+            !streamed.starts_with("hel") || sampled["hel".len()..].starts_with("hel"),
+            "seed {seed}: {sampled:?} {streamed:?}"
+        );
+    }
+}
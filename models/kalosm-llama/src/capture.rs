@@ -0,0 +1,204 @@
+use candle_core::{DType, Device, Tensor};
+
+/// The default limit on the memory used by captured activations (256 MiB).
+const DEFAULT_MAX_BYTES: usize = 256 * 1024 * 1024;
+
+/// Which activations to capture while the model runs. Start capturing with
+/// [`LlamaSession::capture_activations`](crate::LlamaSession::capture_activations).
+///
+/// Captured activations are copied to the CPU as `f32` tensors. Once the captured tensors would use more than the
+/// memory limit, capturing stops and the result is marked as [`CapturedActivations::truncated`].
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let model = Llama::new().await?;
+/// let session = model.new_session()?;
+/// session.capture_activations(ActivationCapture::new().with_attention_weights(true).with_layers([0, 1]));
+/// // Run the model with the session, then read what was captured
+/// let activations = session.take_activations();
+/// for layer in &activations.layers {
+///     println!("layer {}: {:?}", layer.layer, layer.attention_weights.as_ref().map(|t| t.shape().clone()));
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivationCapture {
+    attention_weights: bool,
+    hidden_states: bool,
+    layers: Option<Vec<usize>>,
+    max_bytes: usize,
+}
+
+impl Default for ActivationCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ActivationCapture {
+    /// Create a new capture configuration. Nothing is captured until attention weights or hidden states are enabled.
+    pub fn new() -> Self {
+        Self {
+            attention_weights: false,
+            hidden_states: false,
+            layers: None,
+            max_bytes: DEFAULT_MAX_BYTES,
+        }
+    }
+
+    /// Capture the attention weights after the softmax of each layer.
+    pub fn with_attention_weights(mut self, attention_weights: bool) -> Self {
+        self.attention_weights = attention_weights;
+        self
+    }
+
+    /// Capture the hidden states each layer outputs.
+    pub fn with_hidden_states(mut self, hidden_states: bool) -> Self {
+        self.hidden_states = hidden_states;
+        self
+    }
+
+    /// Only capture these layers. Every layer is captured by default.
+    pub fn with_layers(mut self, layers: impl IntoIterator<Item = usize>) -> Self {
+        self.layers = Some(layers.into_iter().collect());
+        self
+    }
+
+    /// Set the maximum number of bytes the captured activations may use. Defaults to 256 MiB.
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    fn captures_layer(&self, layer: usize) -> bool {
+        self.layers
+            .as_ref()
+            .is_none_or(|layers| layers.contains(&layer))
+    }
+}
+
+/// The activations of one layer during one forward pass of the model.
+#[derive(Debug, Clone)]
+pub struct LayerActivations {
+    /// The index of the layer.
+    pub layer: usize,
+    /// The position in the session of the first token processed in the forward pass.
+    pub start_position: usize,
+    /// The attention weights with the shape `(heads, new_tokens, attended_tokens)`. Layers with a sliding window
+    /// only attend to the most recent tokens in the window while generating one token at a time.
+    pub attention_weights: Option<Tensor>,
+    /// The hidden states the layer outputs with the shape `(new_tokens, hidden_size)`.
+    pub hidden_states: Option<Tensor>,
+}
+
+/// The activations captured from a session.
+#[derive(Debug, Clone, Default)]
+pub struct CapturedActivations {
+    /// The activations of each captured layer in the order the model ran them.
+    pub layers: Vec<LayerActivations>,
+    /// If the memory limit was reached and later activations were not captured.
+    pub truncated: bool,
+}
+
+/// Records activations into a session while capturing is enabled.
+#[derive(Debug, Clone)]
+pub(crate) struct ActivationRecorder {
+    config: ActivationCapture,
+    captured: CapturedActivations,
+    bytes: usize,
+}
+
+impl ActivationRecorder {
+    pub(crate) fn new(config: ActivationCapture) -> Self {
+        Self {
+            config,
+            captured: CapturedActivations::default(),
+            bytes: 0,
+        }
+    }
+
+    /// Check if the attention weights of a layer should be computed for the recorder.
+    pub(crate) fn wants_attention_weights(&self, layer: usize) -> bool {
+        !self.captured.truncated
+            && self.config.attention_weights
+            && self.config.captures_layer(layer)
+    }
+
+    /// Check if the hidden states of a layer should be recorded.
+    pub(crate) fn wants_hidden_states(&self, layer: usize) -> bool {
+        !self.captured.truncated && self.config.hidden_states && self.config.captures_layer(layer)
+    }
+
+    /// Record the activations of a layer. The tensors have a leading batch dimension of one.
+    pub(crate) fn record(
+        &mut self,
+        layer: usize,
+        start_position: usize,
+        attention_weights: Option<Tensor>,
+        hidden_states: Option<Tensor>,
+    ) -> candle_core::Result<()> {
+        if attention_weights.is_none() && hidden_states.is_none() {
+            return Ok(());
+        }
+        let bytes = [&attention_weights, &hidden_states]
+            .into_iter()
+            .flatten()
+            .map(|tensor| tensor.elem_count() * DType::F32.size_in_bytes())
+            .sum::<usize>();
+        if self.bytes + bytes > self.config.max_bytes {
+            self.captured.truncated = true;
+            return Ok(());
+        }
+        self.bytes += bytes;
+        let to_cpu = |tensor: Option<Tensor>| {
+            tensor
+                .map(|tensor| {
+                    tensor
+                        .squeeze(0)?
+                        .to_dtype(DType::F32)?
+                        .to_device(&Device::Cpu)
+                })
+                .transpose()
+        };
+        self.captured.layers.push(LayerActivations {
+            layer,
+            start_position,
+            attention_weights: to_cpu(attention_weights)?,
+            hidden_states: to_cpu(hidden_states)?,
+        });
+        Ok(())
+    }
+
+    /// Take the activations recorded so far.
+    pub(crate) fn take(&mut self) -> CapturedActivations {
+        self.bytes = 0;
+        std::mem::take(&mut self.captured)
+    }
+}
+
+#[test]
+fn recorder_stops_at_memory_limit() {
+    let config = ActivationCapture::new()
+        .with_hidden_states(true)
+        .with_layers([1])
+        .with_max_bytes(2 * 4 * 4);
+    let mut recorder = ActivationRecorder::new(config);
+    assert!(!recorder.wants_hidden_states(0));
+    assert!(recorder.wants_hidden_states(1));
+
+    let hidden = Tensor::zeros((1, 2, 4), DType::F32, &Device::Cpu).unwrap();
+    recorder.record(1, 0, None, Some(hidden.clone())).unwrap();
+    recorder.record(1, 2, None, Some(hidden)).unwrap();
+    assert!(!recorder.wants_hidden_states(1));
+
+    let captured = recorder.take();
+    assert!(captured.truncated);
+    assert_eq!(captured.layers.len(), 1);
+    assert_eq!(
+        captured.layers[0].hidden_states.as_ref().unwrap().dims(),
+        [2, 4]
+    );
+}
@@ -3,11 +3,16 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use crate::{model::LlamaModelError, session::LlamaSessionLoadingError, Llama, LlamaSession};
+use crate::chat_template::IMAGE_MARKER;
+use crate::{
+    model::LlamaModelError, session::LlamaSessionLoadingError, Llama, LlamaSession,
+    StructuredGenerationTask, Task,
+};
 use kalosm_common::accelerated_device_if_available;
 use kalosm_language_model::{
     ChatMessage, ChatModel, ChatSession, CreateChatSession, CreateTextCompletionSession,
-    MessageType, StructuredChatModel, StructuredTextCompletionModel, TextCompletionModel,
+    MessageImage, MessageType, Priority, StructuredChatModel, StructuredTextCompletionModel,
+    TextCompletionModel,
 };
 use kalosm_sample::{CreateParserState, Parser};
 use llm_samplers::types::Sampler;
@@ -21,13 +26,13 @@ fn get_new_tokens(
     session: &mut LlamaChatSession,
     model: &Llama,
 ) -> Result<String, LlamaModelError> {
-    let chat_template = model
-        .config
+    let config = model.config();
+    let chat_template = config
         .chat_template
         .as_ref()
         .ok_or(LlamaModelError::NoChatTemplate)?;
-    let bos_token = &model.config.start_token_string;
-    let eos_token = &model.config.stop_token_string;
+    let bos_token = &config.start_token_string;
+    let eos_token = &config.stop_token_string;
     let current_text = if session.history.is_empty() {
         String::new()
     } else {
@@ -51,6 +56,50 @@ fn get_new_tokens(
     Ok(new_text.to_string())
 }
 
+fn images_in(messages: &[ChatMessage]) -> Vec<MessageImage> {
+    messages
+        .iter()
+        .flat_map(|message| message.images().iter().cloned())
+        .collect()
+}
+
+impl Llama {
+    /// Feed the text before the last image and the images in the new chat text into the session. Returns the text
+    /// after the last image that still needs to be fed into the model.
+    async fn feed_images(
+        &self,
+        session: &LlamaSession,
+        text: &str,
+        images: Vec<MessageImage>,
+    ) -> Result<String, LlamaModelError> {
+        if images.is_empty() {
+            return Ok(text.to_string());
+        }
+        let mut segments: Vec<String> = text.split(IMAGE_MARKER).map(ToString::to_string).collect();
+        if segments.len() != images.len() + 1 {
+            return Err(LlamaModelError::ImageMarkerMismatch {
+                expected: images.len(),
+                found: segments.len() - 1,
+            });
+        }
+        let remaining = segments.pop().unwrap_or_default();
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let task_session = session.clone();
+        self.task_sender
+            .send(Task::StructuredGeneration(StructuredGenerationTask {
+                priority: Priority::Interactive,
+                session: Some(session.clone()),
+                runner: Box::new(move |model| {
+                    _ = tx.send(model.feed_images(&task_session, &segments, &images));
+                }),
+            }))
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        rx.await.map_err(|_| LlamaModelError::ModelStopped)??;
+        Ok(remaining)
+    }
+}
+
 impl CreateChatSession for Llama {
     type Error = LlamaModelError;
     type ChatSession = LlamaChatSession;
@@ -68,20 +117,31 @@ impl<S: Sampler + 'static> ChatModel<S> for Llama {
         sampler: S,
         mut on_token: impl FnMut(String) -> Result<(), Self::Error> + Send + Sync + 'static,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'a {
+        let checkpoint = session.checkpoint();
         let new_text = get_new_tokens(messages, session, self);
+        let images = images_in(messages);
         async move {
-            let new_text = new_text?;
             let model_response = Arc::new(RwLock::new(String::new()));
-            let on_token = {
-                let model_response = model_response.clone();
-                move |token: String| {
-                    let mut model_response = model_response.write().unwrap();
-                    *model_response += &token;
-                    on_token(token)
-                }
-            };
-            self.stream_text_with_callback(&mut session.session, &new_text, sampler, on_token)
-                .await?;
+            let result = async {
+                let new_text = self
+                    .feed_images(&session.session, &new_text?, images)
+                    .await?;
+                let on_token = {
+                    let model_response = model_response.clone();
+                    move |token: String| {
+                        let mut model_response = model_response.write().unwrap();
+                        *model_response += &token;
+                        on_token(token)
+                    }
+                };
+                self.stream_text_with_callback(&mut session.session, &new_text, sampler, on_token)
+                    .await
+            }
+            .await;
+            if let Err(err) = result {
+                session.restore(checkpoint);
+                return Err(err);
+            }
             session.history.push(ChatMessage::new(
                 MessageType::ModelAnswer,
                 model_response.read().unwrap().clone(),
@@ -111,27 +171,40 @@ where
         >,
     > + Send
            + 'a {
+        let checkpoint = session.checkpoint();
         let new_text = get_new_tokens(messages, session, self);
+        let images = images_in(messages);
         async move {
-            let new_text = new_text?;
             let model_response = Arc::new(RwLock::new(String::new()));
-            let on_token = {
-                let model_response = model_response.clone();
-                move |token: String| {
-                    let mut model_response = model_response.write().unwrap();
-                    *model_response += &token;
-                    on_token(token)
-                }
-            };
-            let result = self
-                .stream_text_with_callback_and_parser(
+            let result = async {
+                let new_text = self
+                    .feed_images(&session.session, &new_text?, images)
+                    .await?;
+                let on_token = {
+                    let model_response = model_response.clone();
+                    move |token: String| {
+                        let mut model_response = model_response.write().unwrap();
+                        *model_response += &token;
+                        on_token(token)
+                    }
+                };
+                self.stream_text_with_callback_and_parser(
                     &mut session.session,
                     &new_text,
                     sampler,
                     constraints,
                     on_token,
                 )
-                .await?;
+                .await
+            }
+            .await;
+            let result = match result {
+                Ok(result) => result,
+                Err(err) => {
+                    session.restore(checkpoint);
+                    return Err(err);
+                }
+            };
             session.history.push(ChatMessage::new(
                 MessageType::ModelAnswer,
                 model_response.read().unwrap().clone(),
@@ -268,13 +341,97 @@ fn test_serialize_deserialize_chat_session() {
     assert_eq!(session.history, session.history);
 }
 
+#[tokio::test]
+async fn cancelled_turns_can_be_continued() {
+    use kalosm_language_model::{CancellationToken, GenerationParameters};
+
+    let model = crate::test_model::tiny_llama().await;
+    let parameters = GenerationParameters::new().with_max_length(4).with_seed(0);
+    let first_turn = [
+        ChatMessage::new(MessageType::SystemPrompt, "Be brief".to_string()),
+        ChatMessage::new(MessageType::UserMessage, "hello".to_string()),
+    ];
+    let second_turn = [ChatMessage::new(
+        MessageType::UserMessage,
+        "hello world".to_string(),
+    )];
+    let tokens = |session: &LlamaChatSession| session.session.cache.read().unwrap().tokens.clone();
+
+    let mut session = model.new_chat_session().unwrap();
+    model
+        .add_messages_with_callback(&mut session, &first_turn, parameters.clone(), |_| Ok(()))
+        .await
+        .unwrap();
+    let history = session.history.clone();
+    let tokens_before = tokens(&session);
+    assert!(!tokens_before.is_empty());
+
+    // Cancelling a turn leaves the history and the cache the way they were before the turn
+    let cancellation_token = CancellationToken::new();
+    cancellation_token.cancel();
+    let result = model
+        .add_messages_with_callback(
+            &mut session,
+            &second_turn,
+            parameters
+                .clone()
+                .with_cancellation_token(cancellation_token),
+            |_| Ok(()),
+        )
+        .await;
+    assert!(matches!(result, Err(LlamaModelError::Cancelled)));
+    assert_eq!(session.history, history);
+    assert_eq!(tokens(&session), tokens_before);
+
+    // Continuing the chat feeds the same tokens as a chat that never saw the cancelled turn
+    model
+        .add_messages_with_callback(&mut session, &second_turn, parameters.clone(), |_| Ok(()))
+        .await
+        .unwrap();
+    let mut uncancelled = model.new_chat_session().unwrap();
+    for turn in [&first_turn[..], &second_turn[..]] {
+        model
+            .add_messages_with_callback(&mut uncancelled, turn, parameters.clone(), |_| Ok(()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(session.history, uncancelled.history);
+    assert_eq!(tokens(&session), tokens(&uncancelled));
+}
+
 impl LlamaChatSession {
     #[allow(clippy::too_many_arguments)]
     /// Creates a new chat history.
-    fn new(session: LlamaSession) -> Self {
+    pub(crate) fn new(session: LlamaSession) -> Self {
         Self {
             history: Vec::new(),
             session,
         }
     }
+
+    /// Remember the length of the history and the cache before a turn so the turn can be undone with
+    /// [`LlamaChatSession::restore`].
+    fn checkpoint(&self) -> (usize, usize) {
+        let cache_len = self.session.cache.read().unwrap().tokens.len();
+        (self.history.len(), cache_len)
+    }
+
+    /// Undo a turn that failed or was cancelled. The messages of the turn are removed from the history and the cache
+    /// is truncated to the tokens it had before the turn, so the next turn feeds the chat the same way as if the turn
+    /// never happened.
+    fn restore(&mut self, (history_len, cache_len): (usize, usize)) {
+        self.history.truncate(history_len);
+        self.session.cache.write().unwrap().truncate(cache_len);
+    }
+
+    /// Get the number of tokens in the session. This includes the formatted chat history and the generated tokens.
+    pub fn token_count(&self) -> usize {
+        self.session.token_count()
+    }
+
+    /// Get the underlying [`LlamaSession`]. This can be used to capture the activations of the model with
+    /// [`LlamaSession::capture_activations`].
+    pub fn session(&self) -> &LlamaSession {
+        &self.session
+    }
 }
@@ -1,11 +1,12 @@
 use std::fmt::Display;
 
-use kalosm_language_model::ChatMessage;
+use kalosm_language_model::{ChatMessage, MessageType};
 use minijinja::{context, Environment, ErrorKind};
 use minijinja_contrib::pycompat;
 
-#[cfg(test)]
-use kalosm_language_model::MessageType;
+/// The text that marks where the embeddings of each image go in the formatted chat.
+pub(crate) const IMAGE_MARKER: &str = "<image>";
+
 #[cfg(test)]
 use pretty_assertions::assert_eq;
 
@@ -44,6 +45,25 @@ impl HuggingFaceChatTemplate {
         add_generation_prompt: bool,
     ) -> Result<String, minijinja::Error> {
         let tools: Option<()> = None;
+        // Hugging Face templates use the system role instead of the developer role
+        let messages = messages
+            .iter()
+            .map(|message| {
+                let role = match message.role() {
+                    MessageType::SystemPrompt => "system",
+                    MessageType::UserMessage => "user",
+                    MessageType::ModelAnswer => "assistant",
+                };
+                // Images go before the text of the message
+                let mut content = String::new();
+                if !message.images().is_empty() {
+                    content += &IMAGE_MARKER.repeat(message.images().len());
+                    content.push('\n');
+                }
+                content += message.content();
+                context! { role, content }
+            })
+            .collect::<Vec<_>>();
         let ctx = context! { bos_token, eos_token, messages, add_generation_prompt, tools };
         let template = self.environment.get_template("main")?;
         let result = template.render(&ctx)?;
@@ -197,3 +217,47 @@ fn test_mistral_chat_template() {
         r#"<s> [INST] Hello, how are you? [/INST] I'm doing great. How can I help you today?</s> [INST] I'd like to show off how chat templating works! [/INST]"#
     )
 }
+
+#[test]
+fn test_mistral_nemo_chat_template() {
+    let template = "{%- if messages[0][\"role\"] == \"system\" %}\n    {%- set system_message = messages[0][\"content\"] %}\n    {%- set loop_messages = messages[1:] %}\n{%- else %}\n    {%- set loop_messages = messages %}\n{%- endif %}\n\n{{- bos_token }}\n{%- for message in loop_messages %}\n    {%- if (message['role'] == 'user') != (loop.index0 % 2 == 0) %}\n        {{- raise_exception('After the optional system message, conversation roles must alternate user/assistant/user/assistant/...') }}\n    {%- endif %}\n    {%- if message[\"role\"] == \"user\" %}\n        {%- if loop.last and system_message is defined %}\n            {{- \"[INST]\" + system_message + \"\\n\\n\" + message[\"content\"] + \"[/INST]\" }}\n        {%- else %}\n            {{- \"[INST]\" + message[\"content\"] + \"[/INST]\" }}\n        {%- endif %}\n    {%- elif message[\"role\"] == \"assistant\" %}\n        {{- message[\"content\"] + eos_token}}\n    {%- else %}\n        {{- raise_exception(\"Only user and assistant roles are supported, with the exception of an initial optional system message!\") }}\n    {%- endif %}\n{%- endfor %}\n";
+
+    let template = HuggingFaceChatTemplate::create(template).unwrap();
+
+    let inputs = [
+        ChatMessage::new(
+            MessageType::SystemPrompt,
+            "You are a helpful assistant.".to_string(),
+        ),
+        ChatMessage::new(MessageType::UserMessage, "Hello, how are you?".to_string()),
+        ChatMessage::new(
+            MessageType::ModelAnswer,
+            "I'm doing great. How can I help you today?".to_string(),
+        ),
+        ChatMessage::new(
+            MessageType::UserMessage,
+            "I'd like to show off how chat templating works!".to_string(),
+        ),
+    ];
+
+    let result = template.format("<s>", "</s>", &inputs, true).unwrap();
+    assert_eq!(
+        result,
+        "<s>[INST]Hello, how are you?[/INST]I'm doing great. How can I help you today?</s>[INST]You are a helpful assistant.\n\nI'd like to show off how chat templating works![/INST]"
+    )
+}
+
+#[test]
+fn test_image_markers_in_chat_template() {
+    let template =
+        "{% for message in messages %}{{ message['role'] }}: {{ message['content'] }}\n{% endfor %}";
+
+    let template = HuggingFaceChatTemplate::create(template).unwrap();
+
+    let inputs = [ChatMessage::new(MessageType::UserMessage, "What is this?")
+        .with_image(vec![1, 2, 3])
+        .with_image(vec![4, 5, 6])];
+
+    let result = template.format("<s>", "</s>", &inputs, true).unwrap();
+    assert_eq!(result, "user: <image><image>\nWhat is this?\n")
+}
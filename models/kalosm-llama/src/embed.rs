@@ -0,0 +1,129 @@
+use std::future::Future;
+
+use candle_core::{DType, Device, Tensor};
+use kalosm_language_model::{Embedder, Embedding, EmbeddingInput};
+
+use crate::model::{LlamaModel, LlamaModelError};
+use crate::raw::cache::LlamaCache;
+use crate::{Llama, StructuredGenerationTask, Task};
+
+/// How the hidden states of each token are combined into one embedding with [`Llama::embed_with_pooling`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LlamaPooling {
+    /// Take the mean of the hidden states of every token. This works well for most base and chat models.
+    #[default]
+    Mean,
+    /// Take the hidden state of the last token. Models trained as embedders with causal attention often use this.
+    LastToken,
+}
+
+impl Llama {
+    /// Embed text with the hidden states of the loaded model. The mean of the hidden states after the final norm is
+    /// used as the embedding. Text that doesn't fit in the context of the model is truncated.
+    ///
+    /// Llama also implements [`Embedder`], so it can be used for simple retrieval without loading a second
+    /// embedding model.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new().await?;
+    /// let cats = model.embed("Cats are cool").await?;
+    /// let pets = model.embed("Pets are great").await?;
+    /// println!("{}", cats.cosine_similarity(&pets));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn embed(&self, text: impl ToString) -> Result<Embedding, LlamaModelError> {
+        self.embed_with_pooling(text, LlamaPooling::Mean).await
+    }
+
+    /// Embed text with the hidden states of the loaded model, combining the hidden states of each token with a
+    /// [`LlamaPooling`] strategy.
+    pub async fn embed_with_pooling(
+        &self,
+        text: impl ToString,
+        pooling: LlamaPooling,
+    ) -> Result<Embedding, LlamaModelError> {
+        let text = text.to_string();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.task_sender
+            .send(Task::StructuredGeneration(StructuredGenerationTask {
+                priority: Default::default(),
+                session: None,
+                runner: Box::new(move |model| {
+                    _ = tx.send(model.embed(&text, pooling));
+                }),
+            }))
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        rx.await.map_err(|_| LlamaModelError::ModelStopped)?
+    }
+}
+
+impl Embedder for Llama {
+    type Error = LlamaModelError;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> impl Future<Output = Result<Embedding, Self::Error>> + Send {
+        self.embed_with_pooling(input.text, LlamaPooling::Mean)
+    }
+}
+
+impl LlamaModel {
+    /// Run text through the model in a new cache and pool the hidden states into an embedding.
+    pub(crate) fn embed(
+        &self,
+        text: &str,
+        pooling: LlamaPooling,
+    ) -> Result<Embedding, LlamaModelError> {
+        let tokens = self
+            .tokenizer
+            .encode_fast(text, false)
+            .map_err(LlamaModelError::Tokenizer)?;
+        let mut tokens = tokens.get_ids();
+        if tokens.is_empty() {
+            return Err(LlamaModelError::EmptyEmbeddingInput);
+        }
+        let context_length = self.model.config.context_length;
+        if tokens.len() > context_length {
+            tracing::trace!(
+                "Truncating {} tokens to the context length of {context_length} for embedding",
+                tokens.len()
+            );
+            tokens = &tokens[..context_length];
+        }
+        let mut cache = LlamaCache::new(&self.model.config);
+        let hidden_states =
+            self.model
+                .hidden_states_all_positions(tokens, &self.device, &mut cache)?;
+        Ok(Embedding::from(pool(&hidden_states, pooling)?))
+    }
+}
+
+/// Combine hidden states with the shape `(tokens, hidden_size)` into one vector.
+fn pool(hidden_states: &Tensor, pooling: LlamaPooling) -> candle_core::Result<Vec<f32>> {
+    let hidden_states = hidden_states
+        .to_dtype(DType::F32)?
+        .to_device(&Device::Cpu)?;
+    let pooled = match pooling {
+        LlamaPooling::Mean => hidden_states.mean(0)?,
+        LlamaPooling::LastToken => hidden_states.get(hidden_states.dim(0)? - 1)?,
+    };
+    pooled.flatten_all()?.to_vec1()
+}
+
+#[test]
+fn pooling_combines_tokens() {
+    let hidden_states = Tensor::new(&[[1f32, 2.0], [3.0, 6.0]], &Device::Cpu).unwrap();
+    assert_eq!(
+        pool(&hidden_states, LlamaPooling::Mean).unwrap(),
+        [2.0, 4.0]
+    );
+    assert_eq!(
+        pool(&hidden_states, LlamaPooling::LastToken).unwrap(),
+        [3.0, 6.0]
+    );
+}
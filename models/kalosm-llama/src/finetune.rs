@@ -0,0 +1,498 @@
+use std::fmt::Debug;
+use std::io::BufRead;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use candle_core::{Tensor, D};
+use candle_nn::{AdamW, Optimizer, ParamsAdamW};
+use kalosm_language_model::{ChatMessage, MessageType, Priority};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::model::LlamaModel;
+use crate::raw::{LoraTarget, TrainableLora};
+use crate::{Llama, StructuredGenerationTask, Task};
+
+/// An error that can occur while fine-tuning a LoRA adapter.
+#[derive(Debug, thiserror::Error)]
+pub enum FinetuneError {
+    /// An error reading the dataset or writing the adapter.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// A line in the dataset was not a valid example.
+    #[error("Invalid example on line {line}: {error}")]
+    InvalidExample {
+        /// The line number of the invalid example, starting at 1.
+        line: usize,
+        /// The error from parsing the example.
+        error: serde_json::Error,
+    },
+    /// The dataset has no examples with a response to learn from.
+    #[error("The dataset has no examples with response tokens to train on")]
+    EmptyDataset,
+    /// A target module is not a known projection name.
+    #[error("Unknown LoRA target module {0:?}")]
+    UnknownTarget(String),
+    /// An error from candle while training.
+    #[error("Candle error: {0}")]
+    Candle(#[from] candle_core::Error),
+    /// An error from tokenizers while preparing the examples.
+    #[error("Tokenizer error: {0}")]
+    Tokenizer(tokenizers::Error),
+    /// Error running the chat template
+    #[error("Error running the chat template: {0}")]
+    ChatTemplate(#[from] minijinja::Error),
+    /// The model has already stopped.
+    #[error("Model stopped")]
+    ModelStopped,
+}
+
+/// One instruction and the response the model should learn to give in an [`InstructionDataset`].
+///
+/// Examples are read from JSON objects with an `instruction` (or `prompt`), an optional `input` and an `output` (or
+/// `response`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstructionExample {
+    /// The instruction for the model.
+    #[serde(alias = "prompt")]
+    pub instruction: String,
+    /// Extra input for the instruction, like the text to summarize.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// The response the model should learn to give.
+    #[serde(alias = "response")]
+    pub output: String,
+}
+
+impl InstructionExample {
+    /// Create a new example from an instruction and the response to it.
+    pub fn new(instruction: impl ToString, output: impl ToString) -> Self {
+        Self {
+            instruction: instruction.to_string(),
+            input: None,
+            output: output.to_string(),
+        }
+    }
+
+    /// Set the extra input for the instruction.
+    pub fn with_input(mut self, input: impl ToString) -> Self {
+        self.input = Some(input.to_string());
+        self
+    }
+
+    /// Get the text of the user message with the input after the instruction.
+    fn prompt(&self) -> String {
+        match &self.input {
+            Some(input) if !input.is_empty() => format!("{}\n\n{}", self.instruction, input),
+            _ => self.instruction.clone(),
+        }
+    }
+}
+
+/// A dataset of instructions and responses to fine-tune a model on with [`Llama::finetune_lora`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstructionDataset {
+    examples: Vec<InstructionExample>,
+}
+
+impl InstructionDataset {
+    /// Create a new empty dataset.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a dataset from a JSONL file with one [`InstructionExample`] per line. Empty lines are skipped.
+    pub fn from_jsonl(path: impl AsRef<Path>) -> Result<Self, FinetuneError> {
+        let file = std::io::BufReader::new(std::fs::File::open(path)?);
+        let mut examples = Vec::new();
+        for (index, line) in file.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let example =
+                serde_json::from_str(&line).map_err(|error| FinetuneError::InvalidExample {
+                    line: index + 1,
+                    error,
+                })?;
+            examples.push(example);
+        }
+        Ok(Self { examples })
+    }
+
+    /// Add an example to the dataset.
+    pub fn push(&mut self, example: InstructionExample) {
+        self.examples.push(example);
+    }
+
+    /// Get the examples in the dataset.
+    pub fn examples(&self) -> &[InstructionExample] {
+        &self.examples
+    }
+
+    /// Get the number of examples in the dataset.
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    /// Check if the dataset is empty.
+    pub fn is_empty(&self) -> bool {
+        self.examples.is_empty()
+    }
+}
+
+impl FromIterator<InstructionExample> for InstructionDataset {
+    fn from_iter<T: IntoIterator<Item = InstructionExample>>(iter: T) -> Self {
+        Self {
+            examples: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// The progress of a [`Llama::finetune_lora`] run after a training step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FinetuneProgress {
+    epoch: usize,
+    step: usize,
+    total_steps: usize,
+    loss: f32,
+    elapsed: Duration,
+}
+
+impl FinetuneProgress {
+    /// Get the epoch of the step, starting at 1.
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// Get the number of steps that have finished.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Get the total number of steps in the run.
+    pub fn total_steps(&self) -> usize {
+        self.total_steps
+    }
+
+    /// Get the loss of the example in the step.
+    pub fn loss(&self) -> f32 {
+        self.loss
+    }
+
+    /// Get the time since training started.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Get the fraction of the steps that have finished between 0 and 1.
+    pub fn progress(&self) -> f32 {
+        self.step as f32 / self.total_steps.max(1) as f32
+    }
+}
+
+/// Settings for fine-tuning a LoRA adapter with [`Llama::finetune_lora`].
+///
+/// The weights of the model stay frozen and quantized. Each quantized weight is dequantized as it is used, so a
+/// quantized small model like SmolLM 2 or Qwen 2.5 0.5B can be fine-tuned with a few gigabytes of memory.
+pub struct LoraFinetune {
+    rank: usize,
+    alpha: f32,
+    learning_rate: f64,
+    epochs: usize,
+    max_sequence_length: usize,
+    targets: Vec<String>,
+    progress: Option<Box<dyn FnMut(FinetuneProgress) + Send>>,
+}
+
+impl Debug for LoraFinetune {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoraFinetune")
+            .field("rank", &self.rank)
+            .field("alpha", &self.alpha)
+            .field("learning_rate", &self.learning_rate)
+            .field("epochs", &self.epochs)
+            .field("max_sequence_length", &self.max_sequence_length)
+            .field("targets", &self.targets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for LoraFinetune {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LoraFinetune {
+    /// Create new fine-tuning settings with the defaults.
+    pub fn new() -> Self {
+        Self {
+            rank: 8,
+            alpha: 16.,
+            learning_rate: 1e-4,
+            epochs: 1,
+            max_sequence_length: 512,
+            targets: vec!["q_proj".to_string(), "v_proj".to_string()],
+            progress: None,
+        }
+    }
+
+    /// Set the rank of the adapter. (Defaults to 8)
+    pub fn with_rank(mut self, rank: usize) -> Self {
+        self.rank = rank.max(1);
+        self
+    }
+
+    /// Set the alpha of the adapter. The change from the adapter is scaled by `alpha / rank`. (Defaults to 16)
+    pub fn with_alpha(mut self, alpha: f32) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Set the learning rate of the optimizer. (Defaults to 1e-4)
+    pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+        self.learning_rate = learning_rate;
+        self
+    }
+
+    /// Set the number of passes over the dataset. (Defaults to 1)
+    pub fn with_epochs(mut self, epochs: usize) -> Self {
+        self.epochs = epochs;
+        self
+    }
+
+    /// Set the most tokens of each example that are trained on. Longer examples are truncated. (Defaults to 512)
+    pub fn with_max_sequence_length(mut self, max_sequence_length: usize) -> Self {
+        self.max_sequence_length = max_sequence_length;
+        self
+    }
+
+    /// Set the weights the adapter is trained for with Hugging Face PEFT names: `q_proj`, `k_proj`, `v_proj`,
+    /// `o_proj`, `gate_proj`, `up_proj` and `down_proj`. (Defaults to `q_proj` and `v_proj`)
+    pub fn with_targets(mut self, targets: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.targets = targets
+            .into_iter()
+            .map(|target| target.to_string())
+            .collect();
+        self
+    }
+
+    /// Set a handler that is called after each training step.
+    pub fn with_progress(
+        mut self,
+        progress: impl FnMut(FinetuneProgress) + Send + 'static,
+    ) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    fn train(
+        mut self,
+        model: &LlamaModel,
+        dataset: &InstructionDataset,
+    ) -> Result<FinetunedLora, FinetuneError> {
+        let targets = self
+            .targets
+            .iter()
+            .map(|target| {
+                LoraTarget::from_peft(target)
+                    .ok_or_else(|| FinetuneError::UnknownTarget(target.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut examples = Vec::new();
+        for example in dataset.examples() {
+            match tokenize_example(model, example, self.max_sequence_length)? {
+                Some(tokens) => examples.push(tokens),
+                None => tracing::warn!(
+                    "Skipping an example without any response tokens in the first {} tokens",
+                    self.max_sequence_length
+                ),
+            }
+        }
+        if examples.is_empty() {
+            return Err(FinetuneError::EmptyDataset);
+        }
+
+        let lora = TrainableLora::new(&model.model, &targets, self.rank, self.alpha)?;
+        let mut optimizer = AdamW::new(
+            lora.vars(),
+            ParamsAdamW {
+                lr: self.learning_rate,
+                ..Default::default()
+            },
+        )?;
+
+        let start = Instant::now();
+        let total_steps = examples.len() * self.epochs;
+        let mut losses = Vec::with_capacity(total_steps);
+        let mut rng = rand::thread_rng();
+        for epoch in 1..=self.epochs {
+            examples.shuffle(&mut rng);
+            for (tokens, prompt_len) in &examples {
+                let logits = model.model.forward_lora(tokens, &lora, &model.device)?;
+                // Only the response is trained on. The logits at each position predict the next token
+                let response_len = tokens.len() - prompt_len;
+                let logits = logits.narrow(0, prompt_len - 1, response_len)?;
+                let expected = Tensor::new(&tokens[*prompt_len..], logits.device())?;
+                let loss = candle_nn::loss::nll(
+                    &candle_nn::ops::log_softmax(&logits, D::Minus1)?,
+                    &expected,
+                )?;
+                optimizer.backward_step(&loss)?;
+
+                let loss = loss.to_scalar::<f32>()?;
+                losses.push(loss);
+                if let Some(progress) = &mut self.progress {
+                    progress(FinetuneProgress {
+                        epoch,
+                        step: losses.len(),
+                        total_steps,
+                        loss,
+                        elapsed: start.elapsed(),
+                    });
+                }
+            }
+        }
+
+        Ok(FinetunedLora { lora, losses })
+    }
+}
+
+/// Tokenize an example with the chat template of the model. Returns the tokens and the number of prompt tokens, or
+/// `None` if no response tokens fit in the maximum sequence length.
+fn tokenize_example(
+    model: &LlamaModel,
+    example: &InstructionExample,
+    max_sequence_length: usize,
+) -> Result<Option<(Vec<u32>, usize)>, FinetuneError> {
+    let config = &model.model.config;
+    let prompt = example.prompt();
+    let prompt = match &config.chat_template {
+        Some(chat_template) => chat_template.format(
+            &config.start_token_string,
+            &config.stop_token_string,
+            &[ChatMessage::new(MessageType::UserMessage, prompt)],
+            true,
+        )?,
+        None => format!("{prompt}\n"),
+    };
+    let text = format!("{prompt}{}{}", example.output, config.stop_token_string);
+
+    let encode = |text: &str| {
+        model
+            .tokenizer
+            .encode_fast(text, false)
+            .map(|encoding| encoding.get_ids().to_vec())
+            .map_err(FinetuneError::Tokenizer)
+    };
+    let prompt_len = encode(&prompt)?.len().max(1);
+    let mut tokens = encode(&text)?;
+    tokens.truncate(max_sequence_length);
+    Ok((tokens.len() > prompt_len).then_some((tokens, prompt_len)))
+}
+
+/// A LoRA adapter trained with [`Llama::finetune_lora`].
+pub struct FinetunedLora {
+    lora: TrainableLora,
+    losses: Vec<f32>,
+}
+
+impl Debug for FinetunedLora {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FinetunedLora")
+            .field("losses", &self.losses)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FinetunedLora {
+    /// Get the loss of each training step.
+    pub fn losses(&self) -> &[f32] {
+        &self.losses
+    }
+
+    /// Save the adapter as a gguf LoRA adapter. The file can be loaded on top of the same base model with
+    /// [`crate::LlamaSource::with_lora`].
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FinetuneError> {
+        Ok(self.lora.save(path.as_ref())?)
+    }
+}
+
+impl Llama {
+    /// Fine-tune a LoRA adapter on an instruction dataset. This is experimental and works best with small models like
+    /// SmolLM 2 or Qwen 2.5 0.5B. Training runs on the device of the model, and other requests to the model wait
+    /// until it finishes.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::qwen_2_5_0_5b_instruct())
+    ///     .build()
+    ///     .await?;
+    /// let dataset = InstructionDataset::from_jsonl("dataset.jsonl")?;
+    /// let adapter = model
+    ///     .finetune_lora(
+    ///         dataset,
+    ///         LoraFinetune::new()
+    ///             .with_epochs(2)
+    ///             .with_progress(|progress| println!("loss: {}", progress.loss())),
+    ///     )
+    ///     .await?;
+    /// adapter.save("adapter.gguf")?;
+    ///
+    /// // Load the base model with the adapter merged in
+    /// let model = Llama::builder()
+    ///     .with_source(
+    ///         LlamaSource::qwen_2_5_0_5b_instruct()
+    ///             .with_lora(FileSource::local("adapter.gguf".into())),
+    ///     )
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn finetune_lora(
+        &self,
+        dataset: InstructionDataset,
+        settings: LoraFinetune,
+    ) -> Result<FinetunedLora, FinetuneError> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.task_sender
+            .send(Task::StructuredGeneration(StructuredGenerationTask {
+                priority: Priority::Background,
+                session: None,
+                runner: Box::new(move |model| {
+                    _ = tx.send(settings.train(model, &dataset));
+                }),
+            }))
+            .map_err(|_| FinetuneError::ModelStopped)?;
+        rx.await.map_err(|_| FinetuneError::ModelStopped)?
+    }
+}
+
+#[test]
+fn instruction_dataset_reads_jsonl() {
+    let path = std::env::temp_dir().join("kalosm-instruction-dataset-test.jsonl");
+    std::fs::write(
+        &path,
+        r#"{"instruction": "Summarize", "input": "Some text", "output": "A summary"}
+
+{"prompt": "Say hi", "response": "Hi!"}
+"#,
+    )
+    .unwrap();
+    let dataset = InstructionDataset::from_jsonl(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        dataset.examples(),
+        [
+            InstructionExample::new("Summarize", "A summary").with_input("Some text"),
+            InstructionExample::new("Say hi", "Hi!"),
+        ]
+    );
+    assert_eq!(dataset.examples()[0].prompt(), "Summarize\n\nSome text");
+}
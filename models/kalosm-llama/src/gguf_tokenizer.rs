@@ -1,13 +1,223 @@
 use std::collections::HashMap;
 
+use candle_core::quantized::gguf_file;
 use tokenizers::{
-    decoders::byte_level::ByteLevel,
-    models::bpe::BpeBuilder,
+    decoders::{
+        byte_fallback::ByteFallback, byte_level::ByteLevel, fuse::Fuse, strip::Strip,
+        DecoderWrapper,
+    },
+    models::{bpe::BpeBuilder, unigram::Unigram},
+    normalizers::{NormalizerWrapper, Prepend, Replace},
     pre_tokenizers::split::SplitPattern,
     processors::template::{SpecialToken, TemplateProcessing},
     AddedToken, Tokenizer,
 };
 
+use crate::LlamaSourceError;
+
+/// Build a tokenizer from the vocabulary embedded in the metadata of a gguf file. Both byte level BPE (`gpt2`)
+/// and SentencePiece (`llama`) vocabularies are supported.
+pub(crate) fn tokenizer_from_gguf(
+    metadata: &HashMap<String, gguf_file::Value>,
+) -> Result<Tokenizer, LlamaSourceError> {
+    let tokenizer_model = metadata
+        .get("tokenizer.ggml.model")
+        .ok_or(LlamaSourceError::NoTokenizer)?
+        .to_string()
+        .map_err(|_| LlamaSourceError::NoTokenizer)?;
+    let add_bos_token = metadata
+        .get("tokenizer.ggml.add_bos_token")
+        .and_then(|v| v.to_bool().ok());
+
+    let tokens: Result<Vec<_>, _> = metadata
+        .get("tokenizer.ggml.tokens")
+        .ok_or(LlamaSourceError::NoTokenizer)?
+        .to_vec()
+        .map_err(|_| LlamaSourceError::NoTokenizer)?
+        .iter()
+        .map(|v| v.to_string().map(|s| s.to_string()))
+        .collect();
+    let tokens = tokens.map_err(|_| LlamaSourceError::NoTokenizer)?;
+    let types: Result<Vec<_>, _> = metadata
+        .get("tokenizer.ggml.token_type")
+        .ok_or(LlamaSourceError::NoTokenizer)?
+        .to_vec()
+        .map_err(|_| LlamaSourceError::NoTokenizer)?
+        .iter()
+        .map(|v| {
+            v.to_i32()
+                .map(|v| v as u8)
+                .or_else(|_| v.to_i64().map(|v| v as u8))
+                .or_else(|_| v.to_i16().map(|v| v as u8))
+                .or_else(|_| v.to_i8().map(|v| v as u8))
+                .or_else(|_| v.to_u64().map(|v| v as u8))
+                .or_else(|_| v.to_u32().map(|v| v as u8))
+                .or_else(|_| v.to_u16().map(|v| v as u8))
+                .or_else(|_| v.to_u8())
+        })
+        .collect();
+    let types = types.map_err(|_| LlamaSourceError::NoTokenizer)?;
+    if types.len() != tokens.len() {
+        return Err(LlamaSourceError::NoTokenizer);
+    }
+
+    let token_id = |key: &str| -> Result<u32, LlamaSourceError> {
+        let id = metadata
+            .get(key)
+            .ok_or(LlamaSourceError::NoTokenizer)?
+            .to_u32()
+            .map_err(|_| LlamaSourceError::NoTokenizer)?;
+        if id as usize >= tokens.len() {
+            return Err(LlamaSourceError::NoTokenizer);
+        }
+        Ok(id)
+    };
+    let eos = tokens[token_id("tokenizer.ggml.eos_token_id")? as usize].clone();
+    let bos = tokens[token_id("tokenizer.ggml.bos_token_id")? as usize].clone();
+
+    match tokenizer_model.as_str() {
+        "gpt2" => {
+            let pre = metadata
+                .get("tokenizer.ggml.pre")
+                .ok_or(LlamaSourceError::NoTokenizer)?
+                .to_string()
+                .map_err(|_| LlamaSourceError::NoTokenizer)?;
+            let config = get_pre_tokenizer(pre, add_bos_token);
+
+            let vocab: HashMap<_, _> = tokens
+                .iter()
+                .enumerate()
+                .map(|(id, v)| (v.clone(), id as u32))
+                .collect();
+            let merges: Result<Vec<_>, _> = metadata
+                .get("tokenizer.ggml.merges")
+                .ok_or(LlamaSourceError::NoTokenizer)?
+                .to_vec()
+                .map_err(|_| LlamaSourceError::NoTokenizer)?
+                .iter()
+                .map(|v| {
+                    v.to_string()
+                        .map_err(|_| LlamaSourceError::NoTokenizer)
+                        .and_then(|v| v.split_once(' ').ok_or(LlamaSourceError::NoTokenizer))
+                        .map(|(a, b)| (a.to_string(), b.to_string()))
+                })
+                .collect();
+            let merges = merges?;
+
+            config
+                .build(vocab, types, merges, &bos, &eos)
+                .map_err(LlamaSourceError::Tokenizer)
+        }
+        "llama" => {
+            let scores: Result<Vec<_>, _> = metadata
+                .get("tokenizer.ggml.scores")
+                .ok_or(LlamaSourceError::NoTokenizer)?
+                .to_vec()
+                .map_err(|_| LlamaSourceError::NoTokenizer)?
+                .iter()
+                .map(|v| v.to_f32().map(|v| v as f64))
+                .collect();
+            let scores = scores.map_err(|_| LlamaSourceError::NoTokenizer)?;
+            if scores.len() != tokens.len() {
+                return Err(LlamaSourceError::NoTokenizer);
+            }
+            let unknown = token_id("tokenizer.ggml.unknown_token_id").ok();
+            let add_space_prefix = metadata
+                .get("tokenizer.ggml.add_space_prefix")
+                .and_then(|v| v.to_bool().ok())
+                .unwrap_or(true);
+
+            build_sentencepiece(
+                tokens.into_iter().zip(scores).collect(),
+                &types,
+                unknown,
+                &bos,
+                &eos,
+                add_bos_token.unwrap_or(true),
+                add_space_prefix,
+            )
+            .map_err(LlamaSourceError::Tokenizer)
+        }
+        _ => Err(LlamaSourceError::NoTokenizer),
+    }
+}
+
+// Mirrors the tokenizer.json files Hugging Face generates for SentencePiece models with byte fallback
+fn build_sentencepiece(
+    vocab: Vec<(String, f64)>,
+    types: &[u8],
+    unknown: Option<u32>,
+    bos: &str,
+    eos: &str,
+    add_bos: bool,
+    add_space_prefix: bool,
+) -> Result<Tokenizer, tokenizers::Error> {
+    let bos_token = vocab
+        .iter()
+        .position(|(token, _)| token == bos)
+        .ok_or("The bos token is not in the vocabulary")? as u32;
+    // Byte tokens need to stay in the model for byte fallback, so only unknown, control and user defined
+    // tokens are added as special tokens
+    let mut special_tokens: Vec<_> = vocab
+        .iter()
+        .zip(types)
+        .filter(|(_, ty)| matches!(**ty, 2..=4))
+        .map(|((token, _), _)| AddedToken::from(token.clone(), true))
+        .collect();
+    let model = Unigram::from(vocab, unknown.map(|id| id as usize), true)?;
+
+    let mut tokenizer = Tokenizer::new(model);
+    let mut normalizers: Vec<NormalizerWrapper> = Vec::new();
+    if add_space_prefix {
+        normalizers.push(Prepend::new("▁".to_string()).into());
+    }
+    normalizers.push(Replace::new(" ", "▁")?.into());
+    tokenizer.with_normalizer(Some(tokenizers::normalizers::Sequence::new(normalizers)));
+    let mut decoders: Vec<DecoderWrapper> = vec![
+        Replace::new("▁", " ")?.into(),
+        ByteFallback::new().into(),
+        Fuse::new().into(),
+    ];
+    if add_space_prefix {
+        decoders.push(Strip::new(' ', 1, 0).into());
+    }
+    tokenizer.with_decoder(Some(tokenizers::decoders::sequence::Sequence::new(
+        decoders,
+    )));
+    if add_bos {
+        tokenizer.with_post_processor(Some(bos_template(bos, bos_token)));
+    }
+    special_tokens.push(AddedToken::from(bos.to_string(), true));
+    special_tokens.push(AddedToken::from(eos.to_string(), true));
+    tokenizer.add_special_tokens(&special_tokens);
+
+    Ok(tokenizer)
+}
+
+fn bos_template(bos: &str, bos_token: u32) -> TemplateProcessing {
+    let special_toks = vec![SpecialToken::from((bos_token, bos.to_string()))];
+    TemplateProcessing::builder()
+        .single(
+            tokenizers::processors::template::Template::try_from(vec![
+                format!("{bos}:0"),
+                "$A:0".to_string(),
+            ])
+            .unwrap(),
+        )
+        .pair(
+            tokenizers::processors::template::Template::try_from(vec![
+                format!("{bos}:0"),
+                "$A:0".to_string(),
+                format!("{bos}:1"),
+                "$B:1".to_string(),
+            ])
+            .unwrap(),
+        )
+        .special_tokens(special_toks)
+        .build()
+        .unwrap()
+}
+
 #[derive(Clone, Copy)]
 enum PreTokenizerType {
     Bloom,
@@ -181,30 +391,7 @@ impl GGUFPreTokenizerConfig {
         let mut post_processors = Vec::new();
         post_processors.push(byte_level_post.into());
         if self.add_bos {
-            let special_toks = vec![SpecialToken::from((bos_token, bos.to_string()))];
-            post_processors.push(
-                TemplateProcessing::builder()
-                    .single(
-                        tokenizers::processors::template::Template::try_from(vec![
-                            format!("{bos}:0"),
-                            "$A:0".to_string(),
-                        ])
-                        .unwrap(),
-                    )
-                    .pair(
-                        tokenizers::processors::template::Template::try_from(vec![
-                            format!("{bos}:0"),
-                            "$A:0".to_string(),
-                            format!("{bos}:1"),
-                            "$B:1".to_string(),
-                        ])
-                        .unwrap(),
-                    )
-                    .special_tokens(special_toks)
-                    .build()
-                    .unwrap()
-                    .into(),
-            );
+            post_processors.push(bos_template(bos, bos_token).into());
         }
         tokenizer.with_post_processor(Some(tokenizers::processors::sequence::Sequence::new(
             post_processors,
@@ -350,3 +537,43 @@ pub(crate) fn get_pre_tokenizer(
 
     tokenizer
 }
+
+#[test]
+fn sentencepiece_tokenizer_from_gguf() {
+    use gguf_file::Value;
+
+    let tokens = ["<unk>", "<s>", "</s>", "▁hello", "▁world", "▁", "<0x21>"];
+    let types = [2, 3, 3, 1, 1, 1, 6];
+    let metadata = HashMap::from([
+        (
+            "tokenizer.ggml.model".to_string(),
+            Value::String("llama".to_string()),
+        ),
+        (
+            "tokenizer.ggml.tokens".to_string(),
+            Value::Array(
+                tokens
+                    .iter()
+                    .map(|t| Value::String(t.to_string()))
+                    .collect(),
+            ),
+        ),
+        (
+            "tokenizer.ggml.token_type".to_string(),
+            Value::Array(types.iter().map(|t| Value::I32(*t)).collect()),
+        ),
+        (
+            "tokenizer.ggml.scores".to_string(),
+            Value::Array(tokens.iter().map(|_| Value::F32(-1.0)).collect()),
+        ),
+        ("tokenizer.ggml.unknown_token_id".to_string(), Value::U32(0)),
+        ("tokenizer.ggml.bos_token_id".to_string(), Value::U32(1)),
+        ("tokenizer.ggml.eos_token_id".to_string(), Value::U32(2)),
+    ]);
+
+    let tokenizer = tokenizer_from_gguf(&metadata).unwrap();
+    let encoding = tokenizer.encode("hello world!", true).unwrap();
+    assert_eq!(encoding.get_ids(), [1, 3, 4, 6]);
+    let decoded = tokenizer.decode(encoding.get_ids(), true).unwrap();
+    assert_eq!(decoded, "hello world!");
+}
@@ -1,13 +1,14 @@
 use kalosm_language_model::{
     CreateDefaultChatConstraintsForType, CreateDefaultCompletionConstraintsForType,
-    CreateTextCompletionSession, GenerationParameters, ModelBuilder, StructuredTextCompletionModel,
-    TextCompletionModel,
+    CreateTextCompletionSession, FinishReason, GenerationParameters, ModelBuilder, Priority,
+    StructuredTextCompletionModel, TextCompletionModel,
 };
 use kalosm_model_types::ModelLoadingProgress;
 use kalosm_sample::{ArcParser, CreateParserState, Parse, Parser, ParserExt};
 use llm_samplers::types::Sampler;
 use std::any::Any;
 use std::future::Future;
+use std::time::Instant;
 
 use crate::model::LlamaModelError;
 use crate::structured::generate_structured;
@@ -46,7 +47,7 @@ impl CreateTextCompletionSession for Llama {
     type Error = LlamaModelError;
 
     fn new_session(&self) -> Result<Self::Session, Self::Error> {
-        Ok(LlamaSession::new(&self.config))
+        Ok(LlamaSession::new(&self.config()))
     }
 }
 
@@ -61,30 +62,88 @@ impl<S: Sampler + 'static> TextCompletionModel<S> for Llama {
         let text = text.to_string();
         async move {
             let (tx, rx) = tokio::sync::oneshot::channel();
-            let (max_tokens, stop_on, seed) =
-                match (&sampler as &dyn Any).downcast_ref::<GenerationParameters>() {
-                    Some(sampler) => (
+            let (
+                max_tokens,
+                stop_on,
+                seed,
+                stop_criteria,
+                priority,
+                prefill_progress,
+                prompt_lookup,
+                logprobs,
+                stop_sequences,
+                token_healing,
+                cancellation_token,
+                timeout,
+                finish_handler,
+                usage_handler,
+            ) = match (&sampler as &dyn Any).downcast_ref::<GenerationParameters>() {
+                Some(sampler) => {
+                    session.cache.write().unwrap().sampler = Some(sampler.into());
+                    (
                         sampler.max_length(),
                         sampler.stop_on().map(|s| s.to_string()),
-                        sampler.seed(),
-                    ),
-                    None => (u32::MAX, None, None),
-                };
+                        sampler.seed().or(self.seed),
+                        sampler.stop_criteria(),
+                        sampler.priority(),
+                        sampler.prefill_progress(),
+                        sampler.prompt_lookup(),
+                        sampler
+                            .logprobs_handler()
+                            .map(|handler| (sampler.top_logprobs(), handler)),
+                        sampler.stop_sequences().cloned(),
+                        sampler.token_healing(),
+                        sampler.cancellation_token().cloned(),
+                        sampler.timeout(),
+                        sampler.finish_handler(),
+                        sampler.usage_handler(),
+                    )
+                }
+                None => (
+                    u32::MAX,
+                    None,
+                    self.seed,
+                    None,
+                    Priority::Interactive,
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
+                    None,
+                    None,
+                    None,
+                    None,
+                ),
+            };
             let sampler = std::sync::Arc::new(std::sync::Mutex::new(sampler));
             let on_token = Box::new(on_token);
             self.task_sender
-                .send(Task::UnstructuredGeneration(UnstructuredGenerationTask {
-                    settings: InferenceSettings::new(
-                        text,
-                        session.clone(),
-                        sampler,
-                        max_tokens,
-                        stop_on,
-                        seed,
-                    ),
-                    on_token,
-                    finished: tx,
-                }))
+                .send(Task::UnstructuredGeneration(Box::new(
+                    UnstructuredGenerationTask {
+                        settings: InferenceSettings::new(
+                            text,
+                            session.clone(),
+                            sampler,
+                            max_tokens,
+                            stop_on,
+                            seed,
+                        )
+                        .with_stop_criteria(stop_criteria)
+                        .with_priority(priority)
+                        .with_prefill_progress(prefill_progress)
+                        .with_prompt_lookup(prompt_lookup)
+                        .with_logprobs(logprobs)
+                        .with_stop_sequences(stop_sequences)
+                        .with_token_healing(token_healing)
+                        .with_cancellation_token(cancellation_token)
+                        .with_deadline(timeout.map(|timeout| Instant::now() + timeout))
+                        .with_finish_handler(finish_handler)
+                        .with_usage_handler(usage_handler),
+                        on_token,
+                        finished: tx,
+                    },
+                )))
                 .map_err(|_| LlamaModelError::ModelStopped)?;
 
             rx.await.map_err(|_| LlamaModelError::ModelStopped)??;
@@ -128,14 +187,49 @@ where
         let mut session = session.clone();
         async {
             let (tx, rx) = tokio::sync::oneshot::channel();
-            let seed = match (&sampler as &dyn Any).downcast_ref::<GenerationParameters>() {
-                Some(sampler) => sampler.seed(),
-                None => None,
+            let (
+                seed,
+                priority,
+                prefill_progress,
+                logprobs,
+                cancellation_token,
+                timeout,
+                finish_handler,
+                usage_handler,
+            ) = match (&sampler as &dyn Any).downcast_ref::<GenerationParameters>() {
+                Some(sampler) => {
+                    session.cache.write().unwrap().sampler = Some(sampler.into());
+                    (
+                        sampler.seed().or(self.seed),
+                        sampler.priority(),
+                        sampler.prefill_progress(),
+                        sampler
+                            .logprobs_handler()
+                            .map(|handler| (sampler.top_logprobs(), handler)),
+                        sampler.cancellation_token().cloned(),
+                        sampler.timeout(),
+                        sampler.finish_handler(),
+                        sampler.usage_handler(),
+                    )
+                }
+                None => (
+                    self.seed,
+                    Priority::Interactive,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                ),
             };
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
             let sampler = std::sync::Arc::new(std::sync::Mutex::new(sampler));
             let on_token = Box::new(on_token);
             self.task_sender
                 .send(Task::StructuredGeneration(StructuredGenerationTask {
+                    priority,
+                    session: Some(session.clone()),
                     runner: Box::new(move |model| {
                         let parser_state = parser.create_parser_state();
                         let result = generate_structured(
@@ -148,7 +242,22 @@ where
                             on_token,
                             Some(64),
                             seed,
+                            prefill_progress,
+                            logprobs,
+                            cancellation_token,
+                            || tx.is_closed(),
+                            deadline,
+                            usage_handler,
                         );
+                        if let Some(finish_handler) = &finish_handler {
+                            match &result {
+                                Ok(_) => (finish_handler.lock().unwrap())(FinishReason::Stop),
+                                Err(LlamaModelError::TimedOut) => {
+                                    (finish_handler.lock().unwrap())(FinishReason::TimedOut)
+                                }
+                                Err(_) => {}
+                            }
+                        }
                         _ = tx.send(result);
                     }),
                 }))
@@ -32,47 +32,88 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
+mod background;
+mod batch;
+mod capture;
 mod chat;
 mod chat_template;
+mod embed;
+mod finetune;
 mod gguf_tokenizer;
 mod language_model;
+mod logprobs;
+mod memory;
 mod model;
+mod oom;
+mod prefill;
+mod prefix_cache;
+mod preset;
+mod quantization;
 mod raw;
+mod rope_scaling;
+mod scheduler;
 mod session;
+mod shards;
+mod sinks;
 mod source;
 mod structured;
+mod swap;
+#[cfg(test)]
+mod test_model;
 mod token_stream;
 
+pub use crate::background::BackgroundLlama;
+pub use crate::capture::{ActivationCapture, CapturedActivations, LayerActivations};
 pub use crate::chat::LlamaChatSession;
+pub use crate::embed::LlamaPooling;
+pub use crate::finetune::{
+    FinetuneError, FinetuneProgress, FinetunedLora, InstructionDataset, InstructionExample,
+    LoraFinetune,
+};
+pub use crate::memory::MemoryEstimate;
 use crate::model::LlamaModel;
+pub use crate::oom::OomPolicy;
 pub use crate::raw::cache::*;
 pub use crate::session::LlamaSession;
+pub use crate::sinks::AttentionSinks;
 use candle_core::Device;
 pub use kalosm_common::*;
-use kalosm_language_model::{TextCompletionBuilder, TextCompletionModelExt};
-use kalosm_model_types::ModelLoadingProgress;
+pub use kalosm_language_model::Degradation;
+use kalosm_language_model::{
+    CancellationToken, FinishReason, Priority, PromptLookup, StopCriteria, StopSequences,
+    TextCompletionBuilder, TextCompletionModelExt, Usage,
+};
+use kalosm_model_types::{LoadingHandle, ModelLoadingProgress};
 use kalosm_sample::{LiteralParser, StopOn};
 use model::LlamaModelError;
+pub use preset::LlamaPreset;
+pub use quantization::{GgufQuantization, Quantization};
 use raw::LlamaConfig;
+pub use rope_scaling::RopeScaling;
 pub use source::*;
 use std::mem::MaybeUninit;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokenizers::Tokenizer;
 
 /// A prelude of commonly used items in kalosm-llama.
 pub mod prelude {
     pub use crate::session::LlamaSession;
-    pub use crate::{Llama, LlamaBuilder, LlamaSource};
+    pub use crate::{GgufQuantization, Llama, LlamaBuilder, LlamaSource, Quantization};
     pub use kalosm_language_model::*;
 }
 
 enum Task {
-    UnstructuredGeneration(UnstructuredGenerationTask),
+    UnstructuredGeneration(Box<UnstructuredGenerationTask>),
     StructuredGeneration(StructuredGenerationTask),
+    /// Replace the weights once no generation is running. See [`Llama::swap_source`].
+    SwapModel(swap::SwapModelTask),
 }
 
 struct StructuredGenerationTask {
+    priority: Priority,
+    /// The session the task writes to, if any
+    session: Option<LlamaSession>,
     runner: Box<dyn FnOnce(&mut LlamaModel) + Send>,
 }
 
@@ -85,9 +126,29 @@ struct UnstructuredGenerationTask {
 /// A quantized Llama language model with support for streaming generation.
 #[derive(Clone)]
 pub struct Llama {
+    /// The config and tokenizer of the model the thread is running. This is shared between clones so they all see
+    /// tokens added with [`Llama::add_special_tokens`] and models swapped in with [`Llama::swap_source`].
+    loaded: Arc<RwLock<LoadedModel>>,
+    task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
+    seed: Option<u64>,
+}
+
+/// The parts of the running model that handles read without sending a task to the model thread.
+struct LoadedModel {
     config: Arc<LlamaConfig>,
     tokenizer: Arc<Tokenizer>,
-    task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
+    /// The fingerprint of the loaded model that saved sessions are checked against
+    fingerprint: u64,
+}
+
+impl LoadedModel {
+    fn new(model: &LlamaModel) -> Self {
+        Self {
+            config: model.model.config.clone(),
+            tokenizer: model.tokenizer.clone(),
+            fingerprint: model.fingerprint,
+        }
+    }
 }
 
 impl Llama {
@@ -116,8 +177,57 @@ impl Llama {
     }
 
     /// Get the tokenizer for the model.
-    pub fn tokenizer(&self) -> &Arc<Tokenizer> {
-        &self.tokenizer
+    pub fn tokenizer(&self) -> Arc<Tokenizer> {
+        self.loaded.read().unwrap().tokenizer.clone()
+    }
+
+    /// Get the config of the model.
+    pub(crate) fn config(&self) -> Arc<LlamaConfig> {
+        self.loaded.read().unwrap().config.clone()
+    }
+
+    /// Get the fingerprint of the model that saved sessions are checked against.
+    pub(crate) fn fingerprint(&self) -> u64 {
+        self.loaded.read().unwrap().fingerprint
+    }
+
+    /// Register special tokens like tool call markers with the model and get their ids. Tokens that are not already in
+    /// the vocabulary are added to the tokenizer and the embedding table. The output head has no trained weights for new
+    /// tokens, so their logits are masked and they are never generated unless a constraint requires them.
+    ///
+    /// Every clone of the model uses the new tokenizer once this returns.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new_chat().await?;
+    /// let ids = model.add_special_tokens(["<tool_call>", "</tool_call>"]).await?;
+    /// println!("{ids:?}");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn add_special_tokens(
+        &self,
+        tokens: impl IntoIterator<Item = impl ToString>,
+    ) -> Result<Vec<u32>, LlamaModelError> {
+        let tokens: Vec<String> = tokens.into_iter().map(|token| token.to_string()).collect();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.task_sender
+            .send(Task::StructuredGeneration(StructuredGenerationTask {
+                priority: Priority::Interactive,
+                session: None,
+                runner: Box::new(move |model| {
+                    let result = model
+                        .add_special_tokens(&tokens)
+                        .map(|ids| (ids, LoadedModel::new(model)));
+                    _ = tx.send(result);
+                }),
+            }))
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        let (ids, loaded) = rx.await.map_err(|_| LlamaModelError::ModelStopped)??;
+        *self.loaded.write().unwrap() = loaded;
+        Ok(ids)
     }
 
     /// Create a new builder for a Llama model.
@@ -126,50 +236,37 @@ impl Llama {
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn from_build(mut model: LlamaModel) -> Self {
-        let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let config = model.model.config.clone();
-        let tokenizer = model.tokenizer.clone();
-
-        std::thread::spawn({
-            move || {
-                while let Some(task) = task_receiver.blocking_recv() {
-                    match task {
-                        Task::UnstructuredGeneration(UnstructuredGenerationTask {
-                            settings,
-                            on_token,
-                            finished,
-                        }) => {
-                            let result = model._infer(settings, on_token, &finished);
-                            if let Err(err) = &result {
-                                tracing::error!("Error running model: {err}");
-                            }
-                            _ = finished.send(result);
-                        }
-                        Task::StructuredGeneration(StructuredGenerationTask { runner }) => {
-                            runner(&mut model);
-                        }
-                    }
-                }
+    fn from_build(
+        model: LlamaModel,
+        thread_pool: Option<rayon::ThreadPool>,
+        seed: Option<u64>,
+    ) -> Self {
+        let (task_sender, task_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let loaded = Arc::new(RwLock::new(LoadedModel::new(&model)));
+
+        std::thread::spawn(move || match thread_pool {
+            Some(thread_pool) => {
+                thread_pool.install(move || scheduler::run_tasks(model, task_receiver))
             }
+            None => scheduler::run_tasks(model, task_receiver),
         });
         Self {
             task_sender,
-            config,
-            tokenizer,
+            loaded,
+            seed,
         }
     }
 
     /// Get the default constraints for an assistant response. It parses any text until the end of the assistant's response.
     pub fn default_assistant_constraints(&self) -> StopOn<String> {
-        let end_token = self.config.stop_token_string.clone();
+        let end_token = self.config().stop_token_string.clone();
 
         StopOn::from(end_token)
     }
 
     /// Get the constraints that end the assistant's response.
     pub fn end_assistant_marker_constraints(&self) -> LiteralParser {
-        let end_token = self.config.stop_token_string.clone();
+        let end_token = self.config().stop_token_string.clone();
 
         LiteralParser::from(end_token)
     }
@@ -215,11 +312,20 @@ impl Deref for Llama {
 }
 
 /// A builder with configuration for a Llama model.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct LlamaBuilder {
     source: source::LlamaSource,
     device: Option<Device>,
-    flash_attn: bool,
+    device_preference: Vec<DevicePreference>,
+    device_map: Option<Vec<usize>>,
+    flash_attn: Option<bool>,
+    gpu_layers: Option<usize>,
+    threads: Option<usize>,
+    pin_threads: bool,
+    oom_policy: OomPolicy,
+    max_batch_size: Option<usize>,
+    prefix_cache_size: Option<usize>,
+    seed: Option<u64>,
 }
 
 impl LlamaBuilder {
@@ -229,26 +335,250 @@ impl LlamaBuilder {
         self
     }
 
-    /// Set whether to use Flash Attention.
+    /// Set whether to use the flash attention kernel on CUDA devices. Flash attention makes prefill much faster for
+    /// long prompts. It requires the `flash-attn` feature. (Defaults to true if the `flash-attn` feature is enabled)
+    ///
+    /// Metal devices always use the fused attention kernel while generating tokens.
     pub fn with_flash_attn(mut self, use_flash_attn: bool) -> Self {
-        self.flash_attn = use_flash_attn;
+        self.flash_attn = Some(use_flash_attn);
         self
     }
 
-    /// Set the device to run the model with. (Defaults to an accelerator if available, otherwise the CPU)
+    /// Set the device to run the model with. This overrides [`LlamaBuilder::with_device_preference`]. (Defaults to
+    /// the device in the [`KalosmConfig`] or an accelerator if available, otherwise the CPU)
     pub fn with_device(mut self, device: Device) -> Self {
         self.device = Some(device);
         self
     }
 
+    /// Set the devices to try running the model with, in order of preference. The first device that is available is
+    /// used, so you can prefer a specific GPU and fall back to the CPU if it is missing.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// // Run on the second GPU, or the CPU if there is no second GPU
+    /// let model = Llama::builder()
+    ///     .with_device_preference([DevicePreference::Cuda(1), DevicePreference::Cpu])
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_device_preference(
+        mut self,
+        preference: impl IntoIterator<Item = DevicePreference>,
+    ) -> Self {
+        self.device_preference = preference.into_iter().collect();
+        self
+    }
+
+    /// Split the transformer layers of the model between multiple CUDA or Metal devices, so models larger than a
+    /// single GPU can still run. Each device runs an even, contiguous chunk of the layers in order. The embeddings run
+    /// on the first device and the output head on the last device. This overrides [`LlamaBuilder::with_device`].
+    ///
+    /// Device maps can be combined with [`LlamaBuilder::with_gpu_layers`] to split the first layers between the
+    /// devices and run the rest on the CPU.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_34b_code())
+    ///     .with_device_map([0, 1])
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_device_map(mut self, devices: impl IntoIterator<Item = usize>) -> Self {
+        self.device_map = Some(devices.into_iter().collect());
+        self
+    }
+
+    /// Keep only the first `layers` transformer layers on the accelerator and run the rest of the model on the CPU.
+    /// This lets models that are slightly too large for your VRAM still run most layers on the GPU. Works for GGUF and
+    /// safetensors models. (Defaults to all layers)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat())
+    ///     .with_gpu_layers(24)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_gpu_layers(mut self, layers: usize) -> Self {
+        self.gpu_layers = Some(layers);
+        self
+    }
+
+    /// Set the number of threads the model uses for inference on the CPU. By default candle uses every core, which
+    /// can starve the rest of your application while the model runs. This overrides the thread count in the
+    /// [`KalosmConfig`] for this model.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_device_preference([DevicePreference::Cpu])
+    ///     .with_threads(4)
+    ///     .with_thread_affinity(true)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Set whether each inference thread is pinned to its own core. This only applies if the thread count is set
+    /// with [`LlamaBuilder::with_threads`]. (Defaults to false)
+    pub fn with_thread_affinity(mut self, pin_threads: bool) -> Self {
+        self.pin_threads = pin_threads;
+        self
+    }
+
+    /// Set what the model tries when the device runs out of memory during generation instead of failing. Each
+    /// fallback is recorded in the [`Usage`] of the generation and in [`LlamaSession::degradations`]. Use
+    /// [`LlamaBuilder::with_gpu_layers`] if the model doesn't fit at all. (Defaults to [`OomPolicy::new`])
+    pub fn with_oom_policy(mut self, policy: OomPolicy) -> Self {
+        self.oom_policy = policy;
+        self
+    }
+
+    /// Set the most generations that are decoded together. Text generations that run at the same time (for example
+    /// from different requests to a server) share each forward pass through the model, which is much faster than
+    /// running them one after another. Set this to 1 to run each generation on its own. (Defaults to 8)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder().with_max_batch_size(32).build().await?;
+    /// let first = model.complete("The capital of France is ");
+    /// let second = model.complete("The capital of Spain is ");
+    /// // Both completions are generated in the same batches
+    /// let (first, second) = tokio::join!(first, second);
+    /// println!("{}\n{}", first?, second?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = Some(max_batch_size);
+        self
+    }
+
+    /// Set the most prompts whose kv cache is kept to share with new sessions. Sessions that start with the same
+    /// tokens as a cached prompt (like a shared system prompt) reuse the cache for those tokens instead of running
+    /// the model on them again. (Defaults to 0, which turns off prefix caching)
+    ///
+    /// Each cached prompt keeps the kv cache of every layer for its tokens alive until it is evicted: the keys and
+    /// values of every attention head, which is about 1MB per token for Llama 3 8B with the default f32 cache. A cache
+    /// of 32 prompts with 1000 tokens each can hold tens of gigabytes of memory. Quantizing the kv cache with
+    /// [`LlamaSource::with_kv_cache_quantization`] shrinks each entry by 3 to 6 times.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder().with_prefix_cache_size(4).build().await?;
+    /// // The second chat reuses the kv cache of the system prompt from the first chat
+    /// let mut first = model.chat().with_system_prompt("You are a helpful pirate.");
+    /// first("Hello!").await?;
+    /// let mut second = model.chat().with_system_prompt("You are a helpful pirate.");
+    /// second("Where is the treasure?").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_prefix_cache_size(mut self, prefix_cache_size: usize) -> Self {
+        self.prefix_cache_size = Some(prefix_cache_size);
+        self
+    }
+
+    /// Set the seed used by every generation that doesn't set its own seed with
+    /// [`GenerationParameters::with_seed`](kalosm_language_model::GenerationParameters::with_seed). With a seed, the
+    /// same prompt and model always generate the same text. (Defaults to a random seed for each generation)
+    ///
+    /// Every [`SamplingMethod`](kalosm_language_model::SamplingMethod) and structured generation respect the seed.
+    /// Prompt lookup, token healing and watermarks don't use randomness, so they don't change the output for a seed.
+    /// Generations that are decoded in the same batch can produce slightly different logits on some GPUs; set
+    /// [`LlamaBuilder::with_max_batch_size`] to 1 if the output must match exactly while other generations run.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder().with_seed(42).build().await?;
+    /// let first = model.complete("Once upon a time").await?;
+    /// let second = model.complete("Once upon a time").await?;
+    /// assert_eq!(first, second);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Set the context length of the model. This replaces the context length set on the source with
+    /// [`LlamaSource::with_context_length`].
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.source.context_length = Some(context_length);
+        self
+    }
+
+    /// Set how the rotary position embeddings are scaled to extend the context of the model. This replaces the
+    /// scaling set on the source with [`LlamaSource::with_rope_scaling`].
+    pub fn with_rope_scaling(mut self, rope_scaling: RopeScaling) -> Self {
+        self.source.rope_scaling = Some(rope_scaling);
+        self
+    }
+
     /// Get the device or the default device if not set.
     pub(crate) fn get_device(&self) -> Result<Device, LlamaSourceError> {
         match self.device.clone() {
             Some(device) => Ok(device),
-            None => Ok(accelerated_device_if_available()?),
+            None => Ok(first_available_device(
+                self.device_preference.iter().copied(),
+            )?),
         }
     }
 
+    /// Create the thread pool the model runs in if the thread count is set on the builder or in the [`KalosmConfig`].
+    pub(crate) fn get_thread_pool(&self) -> Result<Option<rayon::ThreadPool>, LlamaSourceError> {
+        self.threads
+            .or_else(|| KalosmConfig::global().threads())
+            .map(|threads| cpu_thread_pool(threads, self.pin_threads))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Open the devices in the device map. Returns an empty list if there is no device map.
+    pub(crate) fn get_device_map(&self) -> Result<Vec<Device>, LlamaSourceError> {
+        self.device_map
+            .iter()
+            .flatten()
+            .map(|&ordinal| {
+                let device = if cfg!(feature = "metal") {
+                    Device::new_metal(ordinal)?
+                } else {
+                    Device::new_cuda(ordinal)?
+                };
+                Ok(device)
+            })
+            .collect()
+    }
+
     /// Build the model with a handler for progress as the download and loading progresses.
     ///
     /// ```rust, no_run
@@ -276,9 +606,59 @@ impl LlamaBuilder {
         self,
         handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
     ) -> Result<Llama, LlamaSourceError> {
-        let model = LlamaModel::from_builder(self, handler).await?;
+        let thread_pool = self.get_thread_pool()?;
+        let seed = self.seed;
+        let model = self.load_model(handler).await?;
+
+        Ok(Llama::from_build(model, thread_pool, seed))
+    }
 
-        Ok(Llama::from_build(model))
+    /// Download and load the weights without starting a model thread.
+    pub(crate) async fn load_model(
+        self,
+        handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> Result<LlamaModel, LlamaSourceError> {
+        let handler: Arc<std::sync::Mutex<dyn FnMut(ModelLoadingProgress) + Send + Sync>> =
+            Arc::new(std::sync::Mutex::new(handler));
+        let mut builder = self;
+        if builder.source.quantization == Some(Quantization::Auto) {
+            builder.source =
+                quantization::resolve_auto_quantization(&builder, handler.clone()).await?;
+        }
+        LlamaModel::from_builder(builder, move |progress| {
+            (*handler.lock().unwrap())(progress)
+        })
+        .await
+    }
+
+    /// Build the model and report the combined progress of every file to a [`LoadingHandle`]. If the handle is
+    /// cancelled, the load stops and [`LlamaSourceError::Cancelled`] is returned.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let handle = LoadingHandle::new();
+    /// // Cancel the load if it takes longer than 10 minutes
+    /// tokio::spawn({
+    ///     let handle = handle.clone();
+    ///     async move {
+    ///         tokio::time::sleep(std::time::Duration::from_secs(600)).await;
+    ///         handle.cancel();
+    ///     }
+    /// });
+    /// let model = Llama::builder().build_with_handle(&handle).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn build_with_handle(
+        self,
+        handle: &LoadingHandle,
+    ) -> Result<Llama, LlamaSourceError> {
+        handle
+            .run(self.build_with_loading_handler(handle.handler()))
+            .await
+            .map_err(|_| LlamaSourceError::Cancelled)?
     }
 
     /// Build the model (this will download the model if it is not already downloaded)
@@ -288,7 +668,6 @@ impl LlamaBuilder {
     }
 }
 
-#[derive(Debug)]
 pub(crate) struct InferenceSettings {
     prompt: String,
 
@@ -306,6 +685,63 @@ pub(crate) struct InferenceSettings {
 
     /// The seed to use.
     seed: Option<u64>,
+
+    /// Custom criteria to check after every token.
+    stop_criteria: Option<std::sync::Arc<std::sync::Mutex<dyn StopCriteria>>>,
+
+    /// The priority of the request.
+    priority: Priority,
+
+    /// The handler to call with the progress of the prompt prefill.
+    prefill_progress: Option<prefill::PrefillHandler>,
+
+    /// Draft tokens from earlier in the context while generating.
+    prompt_lookup: Option<PromptLookup>,
+
+    /// The number of alternatives and the handler to call with the log probabilities of each generated token.
+    logprobs: Option<(usize, logprobs::LogprobsHandler)>,
+
+    /// The stop sequences to stop on, along with the stop_on string.
+    stop_sequences: Option<StopSequences>,
+
+    /// Back up the last prompt token and constrain the first generated token to continue it.
+    token_healing: bool,
+
+    /// The token that stops the generation at the next token.
+    cancellation_token: Option<CancellationToken>,
+
+    /// The time generation stops at.
+    deadline: Option<std::time::Instant>,
+
+    /// The handler to call with the reason generation stopped.
+    finish_handler: Option<FinishHandler>,
+
+    /// The handler to call with the usage of the generation.
+    usage_handler: Option<UsageHandler>,
+}
+
+/// A handler that is called with the reason generation stopped.
+pub(crate) type FinishHandler = std::sync::Arc<std::sync::Mutex<dyn FnMut(FinishReason) + Send>>;
+
+/// A handler that is called with the usage of a generation.
+pub(crate) type UsageHandler = std::sync::Arc<std::sync::Mutex<dyn FnMut(Usage) + Send>>;
+
+impl std::fmt::Debug for InferenceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InferenceSettings")
+            .field("prompt", &self.prompt)
+            .field("stop_on", &self.stop_on)
+            .field("stop_sequences", &self.stop_sequences)
+            .field("token_healing", &self.token_healing)
+            .field("sampler", &self.sampler)
+            .field("session", &self.session)
+            .field("max_tokens", &self.max_tokens)
+            .field("seed", &self.seed)
+            .field("priority", &self.priority)
+            .field("prompt_lookup", &self.prompt_lookup)
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
 }
 
 impl InferenceSettings {
@@ -324,6 +760,92 @@ impl InferenceSettings {
             session,
             max_tokens,
             seed,
+            stop_criteria: None,
+            priority: Priority::Interactive,
+            prefill_progress: None,
+            prompt_lookup: None,
+            logprobs: None,
+            stop_sequences: None,
+            token_healing: false,
+            cancellation_token: None,
+            deadline: None,
+            finish_handler: None,
+            usage_handler: None,
         }
     }
+
+    /// Set custom criteria to check after every token.
+    pub fn with_stop_criteria(
+        mut self,
+        stop_criteria: Option<std::sync::Arc<std::sync::Mutex<dyn StopCriteria>>>,
+    ) -> Self {
+        self.stop_criteria = stop_criteria;
+        self
+    }
+
+    /// Set the priority of the request.
+    pub fn with_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Set the handler to call with the progress of the prompt prefill.
+    pub fn with_prefill_progress(
+        mut self,
+        prefill_progress: Option<prefill::PrefillHandler>,
+    ) -> Self {
+        self.prefill_progress = prefill_progress;
+        self
+    }
+
+    /// Set the prompt lookup settings to draft tokens with while generating.
+    pub fn with_prompt_lookup(mut self, prompt_lookup: Option<PromptLookup>) -> Self {
+        self.prompt_lookup = prompt_lookup;
+        self
+    }
+
+    /// Set the number of alternatives and the handler to call with the log probabilities of each generated token.
+    pub fn with_logprobs(mut self, logprobs: Option<(usize, logprobs::LogprobsHandler)>) -> Self {
+        self.logprobs = logprobs;
+        self
+    }
+
+    /// Set the stop sequences to stop on, along with the stop_on string.
+    pub fn with_stop_sequences(mut self, stop_sequences: Option<StopSequences>) -> Self {
+        self.stop_sequences = stop_sequences;
+        self
+    }
+
+    /// Set whether to back up the last prompt token and constrain the first generated token to continue it.
+    pub fn with_token_healing(mut self, token_healing: bool) -> Self {
+        self.token_healing = token_healing;
+        self
+    }
+
+    /// Set the token that stops the generation at the next token.
+    pub fn with_cancellation_token(
+        mut self,
+        cancellation_token: Option<CancellationToken>,
+    ) -> Self {
+        self.cancellation_token = cancellation_token;
+        self
+    }
+
+    /// Set the time generation stops at.
+    pub fn with_deadline(mut self, deadline: Option<std::time::Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Set the handler to call with the reason generation stopped.
+    pub fn with_finish_handler(mut self, finish_handler: Option<FinishHandler>) -> Self {
+        self.finish_handler = finish_handler;
+        self
+    }
+
+    /// Set the handler to call with the usage of the generation.
+    pub fn with_usage_handler(mut self, usage_handler: Option<UsageHandler>) -> Self {
+        self.usage_handler = usage_handler;
+        self
+    }
 }
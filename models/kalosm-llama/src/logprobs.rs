@@ -0,0 +1,73 @@
+use std::sync::{Arc, Mutex};
+
+use kalosm_language_model::{TokenLogprob, TokenLogprobs};
+use tokenizers::Tokenizer;
+
+use crate::model::LlamaModelError;
+
+/// A handler that is called with the log probabilities of each generated token.
+pub(crate) type LogprobsHandler = Arc<Mutex<dyn FnMut(TokenLogprobs) + Send>>;
+
+/// Get the log probability of the sampled token and the `top_alternatives` most likely tokens from the logits of
+/// the model.
+pub(crate) fn token_logprobs(
+    logits: &[f32],
+    sampled: u32,
+    top_alternatives: usize,
+    tokenizer: &Tokenizer,
+) -> Result<TokenLogprobs, LlamaModelError> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = logits
+        .iter()
+        .map(|logit| (logit - max).exp())
+        .sum::<f32>()
+        .ln();
+    let logprob = |token: u32| {
+        logits
+            .get(token as usize)
+            .map_or(f32::NEG_INFINITY, |logit| logit - max - log_sum)
+    };
+    let token_logprob = |token: u32| {
+        let text = tokenizer
+            .decode(&[token], false)
+            .map_err(LlamaModelError::Tokenizer)?;
+        Ok::<_, LlamaModelError>(TokenLogprob::new(token, text, logprob(token)))
+    };
+
+    let mut top: Vec<u32> = (0..logits.len() as u32).collect();
+    let top_alternatives = top_alternatives.min(top.len());
+    if top_alternatives < top.len() {
+        top.select_nth_unstable_by(top_alternatives, |a, b| {
+            logits[*b as usize].total_cmp(&logits[*a as usize])
+        });
+    }
+    top.truncate(top_alternatives);
+    top.sort_by(|a, b| logits[*b as usize].total_cmp(&logits[*a as usize]));
+
+    Ok(TokenLogprobs::new(
+        token_logprob(sampled)?,
+        top.into_iter()
+            .map(token_logprob)
+            .collect::<Result<_, _>>()?,
+    ))
+}
+
+#[test]
+fn top_alternatives_are_sorted() {
+    let tokenizer = Tokenizer::new(tokenizers::models::wordlevel::WordLevel::default());
+    let logits = [0.0, 2.0, 1.0, -1.0];
+    let logprobs = token_logprobs(&logits, 2, 2, &tokenizer).unwrap();
+    let top: Vec<_> = logprobs
+        .top_alternatives()
+        .iter()
+        .map(|token| token.token())
+        .collect();
+    assert_eq!(top, [1, 2]);
+    let total: f32 = logits
+        .iter()
+        .enumerate()
+        .map(|(token, _)| token_logprobs(&logits, token as u32, 0, &tokenizer).unwrap())
+        .map(|logprobs| logprobs.sampled().probability())
+        .sum();
+    assert!((total - 1.0).abs() < 1e-5);
+}
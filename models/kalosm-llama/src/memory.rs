@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use candle_core::quantized::{gguf_file, GgmlDType};
+use kalosm_model_types::FileSource;
+
+use crate::{LlamaSource, LlamaSourceError};
+
+/// The number of bytes of the model file that are read first when looking for the end of the gguf header.
+const INITIAL_HEADER_BYTES: u64 = 1024 * 1024;
+
+/// The largest gguf header that is read before giving up. Headers are mostly the tokenizer vocabulary, which is a few
+/// megabytes even for large vocabularies.
+const MAX_HEADER_BYTES: u64 = 128 * 1024 * 1024;
+
+/// An estimate of the memory a model needs, from [`LlamaSource::estimate_memory`].
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let source = LlamaSource::llama_3_1_8b_chat();
+/// let estimate = source.estimate_memory(8192).await?;
+/// let available_vram = 8 * 1024 * 1024 * 1024;
+/// if estimate.total() > available_vram {
+///     println!("The model needs {} bytes, but only {available_vram} are available", estimate.total());
+///     return Ok(());
+/// }
+/// let model = Llama::builder().with_source(source).build().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    embedding_bytes: u64,
+    output_bytes: u64,
+    layer_bytes: u64,
+    kv_cache_bytes_per_layer: u64,
+    layers: usize,
+    context_length: usize,
+}
+
+impl MemoryEstimate {
+    /// Get the number of bytes the weights of the model use once they are loaded.
+    pub fn weights(&self) -> u64 {
+        self.embedding_bytes + self.output_bytes + self.layer_bytes
+    }
+
+    /// Get the number of bytes the key value cache uses when it is filled to the context length.
+    pub fn kv_cache(&self) -> u64 {
+        self.kv_cache_bytes_per_layer * self.layers as u64
+    }
+
+    /// Get the total number of bytes the model uses with a full context.
+    pub fn total(&self) -> u64 {
+        self.weights() + self.kv_cache()
+    }
+
+    /// Get the context length the key value cache was estimated for.
+    pub fn context_length(&self) -> usize {
+        self.context_length
+    }
+
+    /// Get the number of transformer layers in the model.
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    /// Get the number of bytes loaded onto the accelerator when only the first `gpu_layers` layers run on it. See
+    /// [`LlamaBuilder::with_gpu_layers`](crate::LlamaBuilder::with_gpu_layers).
+    pub fn accelerator_memory(&self, gpu_layers: usize) -> u64 {
+        if gpu_layers >= self.layers {
+            return self.total();
+        }
+        // If any layers are offloaded, the output head runs on the CPU as well
+        self.embedding_bytes + self.bytes_per_layer() * gpu_layers as u64
+    }
+
+    /// Get the number of bytes kept in system memory when only the first `gpu_layers` layers run on the accelerator.
+    pub fn host_memory(&self, gpu_layers: usize) -> u64 {
+        self.total() - self.accelerator_memory(gpu_layers)
+    }
+
+    /// Get the weights and key value cache of one layer.
+    fn bytes_per_layer(&self) -> u64 {
+        self.layer_bytes / self.layers.max(1) as u64 + self.kv_cache_bytes_per_layer
+    }
+
+    fn from_gguf(
+        contents: &[gguf_file::Content],
+        context_length: usize,
+        kv_cache_quantization: kalosm_common::KvCacheQuantization,
+    ) -> Result<Self, LlamaSourceError> {
+        let mut estimate = Self {
+            embedding_bytes: 0,
+            output_bytes: 0,
+            layer_bytes: 0,
+            kv_cache_bytes_per_layer: 0,
+            layers: 0,
+            context_length,
+        };
+        for content in contents {
+            for (name, info) in &content.tensor_infos {
+                let elements = info.shape.elem_count() as u64;
+                if name == "token_embd.weight" {
+                    // The token embeddings are dequantized when the model is loaded
+                    estimate.embedding_bytes += elements * 4;
+                } else if name.starts_with("blk.") {
+                    estimate.layer_bytes += tensor_bytes(info.ggml_dtype, elements);
+                } else {
+                    estimate.output_bytes += tensor_bytes(info.ggml_dtype, elements);
+                }
+            }
+        }
+
+        let metadata = &contents
+            .first()
+            .ok_or_else(|| LlamaSourceError::EstimateUnavailable("no model files".to_string()))?
+            .metadata;
+        let get = |key: &str| metadata_u32(metadata, key);
+        let missing = |key: &str| {
+            LlamaSourceError::EstimateUnavailable(format!("the gguf header has no {key}"))
+        };
+        let layers = get(".block_count").ok_or_else(|| missing("block count"))? as usize;
+        let head_count = get(".attention.head_count").ok_or_else(|| missing("head count"))?;
+        let kv_head_count = get(".attention.head_count_kv").unwrap_or(head_count);
+        let head_dimension = match get(".attention.key_length") {
+            Some(key_length) => key_length,
+            None => {
+                get(".embedding_length").ok_or_else(|| missing("embedding length"))? / head_count
+            }
+        };
+        estimate.layers = layers;
+        estimate.kv_cache_bytes_per_layer = kv_cache_bytes_per_layer(
+            context_length,
+            kv_head_count as usize,
+            head_dimension as usize,
+            kv_cache_quantization,
+        );
+
+        Ok(estimate)
+    }
+}
+
+/// The key value cache stores the keys and values of every position as f32, or as quantized integers with one f32
+/// scale per head.
+fn kv_cache_bytes_per_layer(
+    context_length: usize,
+    kv_head_count: usize,
+    head_dimension: usize,
+    quantization: kalosm_common::KvCacheQuantization,
+) -> u64 {
+    let head_bytes = match quantization {
+        kalosm_common::KvCacheQuantization::None => head_dimension as u64 * 4,
+        kalosm_common::KvCacheQuantization::Q8 => head_dimension as u64 + 4,
+        kalosm_common::KvCacheQuantization::Q4 => head_dimension as u64 / 2 + 4,
+    };
+    2 * context_length as u64 * kv_head_count as u64 * head_bytes
+}
+
+fn tensor_bytes(dtype: GgmlDType, elements: u64) -> u64 {
+    elements / dtype.block_size() as u64 * dtype.type_size() as u64
+}
+
+fn metadata_u32(metadata: &HashMap<String, gguf_file::Value>, key: &str) -> Option<u32> {
+    metadata
+        .iter()
+        .find_map(|(name, value)| name.ends_with(key).then_some(value))?
+        .to_u32()
+        .ok()
+}
+
+impl LlamaSource {
+    /// Estimate the memory the model needs with a context of `context_length` tokens without loading it. Only the
+    /// header of each gguf file is read, and remote files are fetched with range requests instead of downloading the
+    /// whole model.
+    ///
+    /// The estimate covers the weights and the key value cache. The memory used for intermediate activations
+    /// depends on the batch size and is not included.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let estimate = LlamaSource::qwen_2_5_7b_instruct()
+    ///     .estimate_memory(4096)
+    ///     .await?;
+    /// println!("weights: {} MiB", estimate.weights() / 1024 / 1024);
+    /// println!("kv cache: {} MiB", estimate.kv_cache() / 1024 / 1024);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn estimate_memory(
+        &self,
+        context_length: usize,
+    ) -> Result<MemoryEstimate, LlamaSourceError> {
+        if self.is_safetensors() {
+            return Err(LlamaSourceError::EstimateUnavailable(
+                "only gguf models can be estimated".to_string(),
+            ));
+        }
+        let mut contents = Vec::new();
+        for file in self.model_files() {
+            contents.push(self.read_gguf_header(&file).await?);
+        }
+        MemoryEstimate::from_gguf(&contents, context_length, self.kv_cache_quantization)
+    }
+
+    /// Read the header of a gguf file, fetching larger prefixes of the file until the whole header is read.
+    async fn read_gguf_header(
+        &self,
+        file: &FileSource,
+    ) -> Result<gguf_file::Content, LlamaSourceError> {
+        let mut bytes = Vec::new();
+        let mut length = INITIAL_HEADER_BYTES;
+        loop {
+            let next = self
+                .cache
+                .read_range(file, bytes.len() as u64..length)
+                .await?;
+            let end_of_file = (next.len() as u64) < length - bytes.len() as u64;
+            bytes.extend(next);
+            match gguf_file::Content::read(&mut Cursor::new(&bytes)) {
+                Ok(content) => return Ok(content),
+                Err(err) if end_of_file || length >= MAX_HEADER_BYTES => {
+                    return Err(LlamaSourceError::EstimateUnavailable(format!(
+                        "failed to read the gguf header: {err}"
+                    )))
+                }
+                Err(_) => length *= 2,
+            }
+        }
+    }
+}
+
+#[test]
+fn kv_cache_estimate() {
+    // Llama 3.1 8b: 32 layers, 8 key value heads with 128 dimensions each
+    let estimate = MemoryEstimate {
+        embedding_bytes: 0,
+        output_bytes: 0,
+        layer_bytes: 32 * 100,
+        kv_cache_bytes_per_layer: kv_cache_bytes_per_layer(
+            8192,
+            8,
+            128,
+            kalosm_common::KvCacheQuantization::None,
+        ),
+        layers: 32,
+        context_length: 8192,
+    };
+    assert_eq!(estimate.kv_cache(), 2 * 32 * 8192 * 8 * 128 * 4);
+    assert_eq!(estimate.accelerator_memory(32), estimate.total());
+    assert_eq!(
+        estimate.accelerator_memory(16) + estimate.host_memory(16),
+        estimate.total()
+    );
+    assert_eq!(tensor_bytes(GgmlDType::Q8_0, 64), 2 * 34);
+}
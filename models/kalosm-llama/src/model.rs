@@ -1,21 +1,17 @@
-use crate::gguf_tokenizer::get_pre_tokenizer;
+use crate::gguf_tokenizer::tokenizer_from_gguf;
 use crate::raw::cache::LlamaCache;
 use crate::raw::Model;
-use crate::token_stream::TokenOutputStream;
+use crate::shards::ModelFile;
 use crate::token_stream::TokenOutputStreamError;
 use kalosm_common::*;
 use kalosm_model_types::ModelLoadingProgress;
-use llm_samplers::types::Logits;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    DType, Device,
-};
+use candle_core::{quantized::ggml_file, DType, Device};
 use tokenizers::Tokenizer;
 
-use crate::{InferenceSettings, LlamaSourceError};
+use crate::LlamaSourceError;
 
 /// An error that can occur when running a [`LlamaModel`].
 #[derive(Debug, thiserror::Error)]
@@ -55,6 +51,41 @@ pub enum LlamaModelError {
     /// Error running the chat template
     #[error("Error running the chat template: {0}")]
     ChatTemplateError(#[from] minijinja::Error),
+
+    /// A message has images, but the model was loaded without a vision projector
+    #[error("The model can't read images because it was loaded without a vision projector")]
+    NoVisionProjector,
+
+    /// The number of image markers in the formatted chat doesn't match the number of images
+    #[error("Expected {expected} images in the formatted chat, but found {found}")]
+    ImageMarkerMismatch {
+        /// The number of images attached to the messages
+        expected: usize,
+        /// The number of image markers in the formatted text
+        found: usize,
+    },
+
+    /// The generation was cancelled with a [`CancellationToken`](kalosm_language_model::CancellationToken)
+    #[error("The generation was cancelled")]
+    Cancelled,
+
+    /// The timeout passed before the constrained output was complete
+    #[error("The generation timed out before the constrained output was complete")]
+    TimedOut,
+
+    /// The text to embed has no tokens
+    #[error("Can't embed text with no tokens")]
+    EmptyEmbeddingInput,
+
+    /// The model failed to load in the background
+    #[error("Failed to load the model: {0}")]
+    Loading(Arc<LlamaSourceError>),
+}
+
+/// The `model.safetensors.index.json` file of a model split into multiple safetensors files.
+#[derive(serde::Deserialize)]
+struct SafetensorsIndex {
+    weight_map: HashMap<String, String>,
 }
 
 /// The inner, synchronous Llama model.
@@ -62,9 +93,48 @@ pub(crate) struct LlamaModel {
     pub(crate) model: Model,
     pub(crate) device: Device,
     pub(crate) tokenizer: Arc<Tokenizer>,
+    /// The queue of waiting tasks while a background task is running. Background tasks yield to interactive tasks
+    /// from this queue between tokens.
+    pub(crate) preemption: Option<Arc<std::sync::Mutex<crate::scheduler::TaskQueue>>>,
+    /// What to try when the device runs out of memory while running the model
+    pub(crate) oom_policy: crate::OomPolicy,
+    /// The vision encoder of multimodal models
+    pub(crate) vision: Option<Arc<crate::raw::VisionEncoder>>,
+    /// A unique id for the loaded weights. Sessions remember the id of the model that filled their cache.
+    pub(crate) id: u64,
+    /// A description of the files the weights were loaded from. See [`crate::session::weights_identity`].
+    pub(crate) weights: String,
+    /// A fingerprint of the weights, architecture and tokenizer that is stable across processes. Saved sessions can
+    /// only be loaded into a model with the same fingerprint.
+    pub(crate) fingerprint: u64,
+    /// The most generations that are decoded together in one batch
+    pub(crate) max_batch_size: usize,
+    /// The kv cache of recent prompts that new sessions with the same prefix can reuse
+    pub(crate) prefix_cache: std::sync::Mutex<crate::prefix_cache::PrefixCache>,
+    /// The tokenizers of models this model replaced with [`crate::Llama::swap_source`], by model id
+    pub(crate) retired_tokenizers: HashMap<u64, Arc<Tokenizer>>,
+    /// A copy of the model on the CPU that is created the first time the [`crate::OomPolicy`] offloads a pass
+    pub(crate) cpu_model: std::sync::OnceLock<Model>,
 }
 
 impl LlamaModel {
+    /// Register special tokens with the tokenizer and grow the model to fit any tokens that are new to the vocabulary.
+    pub(crate) fn add_special_tokens(
+        &mut self,
+        tokens: &[String],
+    ) -> Result<Vec<u32>, LlamaModelError> {
+        let mut tokenizer = Tokenizer::clone(&self.tokenizer);
+        let (ids, new_tokens) = register_special_tokens(&mut tokenizer, tokens)?;
+        self.model
+            .add_tokens(tokenizer.get_vocab_size(true), &new_tokens)?;
+        self.fingerprint =
+            crate::session::model_fingerprint(&self.model.config, &tokenizer, &self.weights);
+        self.tokenizer = Arc::new(tokenizer);
+        // The CPU copy doesn't have the new tokens
+        self.cpu_model = std::sync::OnceLock::new();
+        Ok(ids)
+    }
+
     pub(crate) fn forward(
         model: &Model,
         device: &Device,
@@ -89,163 +159,247 @@ impl LlamaModel {
         builder: crate::LlamaBuilder,
         mut handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
     ) -> Result<Self, LlamaSourceError> {
-        let device = builder.get_device()?;
+        // The embeddings run on the first device of the device map
+        let device_map = builder.get_device_map()?;
+        let device = match device_map.first() {
+            Some(device) => device.clone(),
+            None => builder.get_device()?,
+        };
+        let oom_policy = builder.oom_policy.clone();
+        let flash_attn = match builder.flash_attn {
+            Some(true) if !cfg!(feature = "flash-attn") => {
+                tracing::warn!(
+                    "Ignoring flash attention because kalosm-llama was built without the flash-attn feature"
+                );
+                false
+            }
+            Some(flash_attn) => flash_attn,
+            None => cfg!(feature = "flash-attn"),
+        };
+        let max_batch_size = builder
+            .max_batch_size
+            .unwrap_or(crate::batch::DEFAULT_MAX_BATCH_SIZE)
+            .max(1);
+        let prefix_cache = crate::prefix_cache::PrefixCache::new(
+            builder
+                .prefix_cache_size
+                .unwrap_or(crate::prefix_cache::DEFAULT_PREFIX_CACHE_SIZE),
+            kalosm_common::BlockAllocator::global().block_size(),
+        );
 
         // Download the model and tokenizer. These are relatively cheep operations that can be run in the async runtime
-        let tokenizer_path = match &builder.source.tokenizer {
+        // Unquantized models keep the tokenizer next to the weights
+        let tokenizer_source = builder.source.tokenizer.clone().or_else(|| {
+            builder
+                .source
+                .is_safetensors()
+                .then(|| builder.source.sibling("tokenizer.json"))
+                .flatten()
+        });
+        let tokenizer_bytes = match &tokenizer_source {
             Some(tokenizer) => {
                 let tokenizer_source = format!("Tokenizer ({})", tokenizer);
                 let mut create_progress =
                     ModelLoadingProgress::downloading_progress(tokenizer_source);
-                let tokenizer_path = builder
+                let tokenizer_bytes = builder
+                    .source
+                    .file_bytes(tokenizer, |progress| handler(create_progress(progress)))
+                    .await?;
+                Some(tokenizer_bytes)
+            }
+            None => None,
+        };
+
+        builder.source.check_quantization().await?;
+        let weights = crate::session::weights_identity(&builder.source);
+        let mut filenames = Vec::new();
+        for file in builder.source.model_files() {
+            let source = format!("Model ({})", file);
+            let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+            let filename = builder
+                .source
+                .model_file(&file, |progress| handler(create_progress(progress)))
+                .await?;
+            filenames.push(filename);
+        }
+
+        // Download any LoRA adapters with the config of PEFT adapters if it exists
+        let mut lora_files = Vec::new();
+        for lora in &builder.source.lora {
+            let source = format!("LoRA adapter ({})", lora);
+            let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+            let file = builder
+                .source
+                .model_file(lora, |progress| handler(create_progress(progress)))
+                .await?;
+            let mut config = None;
+            let config_source = crate::source::sibling_of(lora, "adapter_config.json")
+                .filter(|_| file.extension() == Some("safetensors"));
+            if let Some(config_source) = config_source {
+                let source = format!("LoRA config ({})", config_source);
+                let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+                if let Ok(config_bytes) = builder
+                    .source
+                    .file_bytes(&config_source, |progress| {
+                        handler(create_progress(progress))
+                    })
+                    .await
+                {
+                    config = Some(serde_json::from_slice::<crate::raw::LoraConfig>(
+                        &config_bytes,
+                    )?);
+                }
+            }
+            lora_files.push((file, config));
+        }
+
+        let vision_projector = match &builder.source.vision_projector {
+            Some(projector) => {
+                let source = format!("Vision projector ({})", projector);
+                let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+                let file = builder
                     .source
-                    .cache
-                    .get(tokenizer, |progress| handler(create_progress(progress)))
+                    .model_file(projector, |progress| handler(create_progress(progress)))
                     .await?;
-                Some(tokenizer_path)
+                Some(file)
             }
             None => None,
         };
+        let chat_template = builder.source.chat_template.clone();
+
+        // Unquantized models need the config, the chat template and every file in the safetensors index
+        let mut safetensors_config = None;
+        if builder.source.is_safetensors() {
+            let index_file = builder
+                .source
+                .model_files()
+                .into_iter()
+                .next()
+                .filter(|file| file.to_string().ends_with(".index.json"));
+            if let Some(index) = index_file {
+                let index: SafetensorsIndex =
+                    serde_json::from_slice(&builder.source.file_bytes(&index, |_| {}).await?)?;
+                let mut files: Vec<_> = index.weight_map.into_values().collect();
+                files.sort();
+                files.dedup();
+                filenames.clear();
+                for file in files {
+                    let file = builder.source.required_sibling(&file)?;
+                    let source = format!("Model ({})", file);
+                    let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+                    let filename = builder
+                        .source
+                        .file(&file, |progress| handler(create_progress(progress)))
+                        .await?;
+                    filenames.push(ModelFile::Path(filename));
+                }
+            }
 
-        let source = format!("Model ({})", builder.source.model);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(source);
-        let filename = builder
-            .source
-            .model(|progress| handler(create_progress(progress)))
-            .await?;
+            let config = match &builder.source.config {
+                Some(config) => config.clone(),
+                None => builder.source.required_sibling("config.json")?,
+            };
+            let source = format!("Config ({})", config);
+            let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+            let config_bytes = builder
+                .source
+                .file_bytes(&config, |progress| handler(create_progress(progress)))
+                .await?;
+            let config: crate::raw::SafetensorsConfig = serde_json::from_slice(&config_bytes)?;
+
+            // The chat template is optional, so base models without a tokenizer config still load
+            let mut chat_template = None;
+            if let Some(tokenizer_config) = builder.source.sibling("tokenizer_config.json") {
+                let source = format!("Tokenizer config ({})", tokenizer_config);
+                let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+                match builder
+                    .source
+                    .file_bytes(&tokenizer_config, |progress| {
+                        handler(create_progress(progress))
+                    })
+                    .await
+                {
+                    Ok(bytes) => {
+                        chat_template =
+                            serde_json::from_slice::<crate::raw::SafetensorsTokenizerConfig>(&bytes)
+                                .ok()
+                                .and_then(|config| config.chat_template())
+                    }
+                    Err(err) => tracing::warn!("Failed to load the tokenizer config: {err}"),
+                }
+            }
+            safetensors_config = Some((config, chat_template));
+        }
 
         // Then actually load the model and tokenizer. This is expensive, so we do it in a blocking task
         let (model, tokenizer) = tokio::task::spawn_blocking({
             let device = device.clone();
             move || {
-                let tokenizer = match tokenizer_path {
-                    Some(tokenizer_path) => {
-                        let tokenizer = Tokenizer::from_file(tokenizer_path)
+                let tokenizer = match tokenizer_bytes {
+                    Some(tokenizer_bytes) => {
+                        let tokenizer = Tokenizer::from_bytes(&tokenizer_bytes)
                             .map_err(LlamaSourceError::Tokenizer)?;
                         Some(tokenizer)
                     }
                     None => None,
                 };
 
-                let mut file = std::fs::File::open(&filename)
-                    .expect("The path returned by LlamaSource::model should be valid");
-                let override_stop_token_string = builder.source.override_stop_token_string;
-                match filename.extension().and_then(|v| v.to_str()) {
+                let filename = &filenames[0];
+                let override_stop_token_string = builder.source.override_stop_token_string.clone();
+                if let Some((config, chat_template)) = safetensors_config {
+                    let tokenizer = tokenizer.ok_or(LlamaSourceError::NoTokenizer)?;
+                    let model = Model::from_safetensors(
+                        &filenames,
+                        config,
+                        &tokenizer,
+                        chat_template,
+                        &device,
+                        override_stop_token_string,
+                        builder.source.context_options(),
+                        builder.gpu_layers,
+                        &device_map,
+                    )?;
+                    return Ok((model, tokenizer));
+                }
+                // Ollama blobs don't have an extension, so fall back to checking the magic number of the file
+                let extension = filename
+                    .extension()
+                    .or_else(|| filename.is_gguf().then_some("gguf"));
+                match extension {
                     Some("gguf") => {
-                        let model = gguf_file::Content::read(&mut file)?;
+                        // Models split into multiple files are read as one file
+                        let (model, mut file) = crate::shards::read_sharded_gguf(&filenames)?;
                         let tokenizer = match tokenizer {
                             Some(tokenizer) => tokenizer,
-                            None => {
-                                let tokenizer_model = model
-                                    .metadata
-                                    .get("tokenizer.ggml.model")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?
-                                    .to_string()
-                                    .map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                if tokenizer_model != "gpt2" {
-                                    return Err(LlamaSourceError::NoTokenizer);
-                                }
-                                let pre = model
-                                    .metadata
-                                    .get("tokenizer.ggml.pre")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?
-                                    .to_string()
-                                    .map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                let add_bos_token = model
-                                    .metadata
-                                    .get("tokenizer.ggml.add_bos_token")
-                                    .and_then(|v| v.to_bool().ok());
-                                let config = get_pre_tokenizer(pre, add_bos_token);
-
-                                let tokens: Result<Vec<_>, _> = model
-                                    .metadata
-                                    .get("tokenizer.ggml.tokens")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?
-                                    .to_vec()
-                                    .map_err(|_| LlamaSourceError::NoTokenizer)?
-                                    .iter()
-                                    .map(|v| v.to_string().map(|s| s.to_string()))
-                                    .collect();
-                                let tokens = tokens.map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                let types: Result<Vec<_>, _> = model
-                                    .metadata
-                                    .get("tokenizer.ggml.token_type")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?
-                                    .to_vec()
-                                    .map_err(|_| LlamaSourceError::NoTokenizer)?
-                                    .iter()
-                                    .map(|v| {
-                                        v.to_i32()
-                                            .map(|v| v as u8)
-                                            .or_else(|_| v.to_i64().map(|v| v as u8))
-                                            .or_else(|_| v.to_i16().map(|v| v as u8))
-                                            .or_else(|_| v.to_i8().map(|v| v as u8))
-                                            .or_else(|_| v.to_u64().map(|v| v as u8))
-                                            .or_else(|_| v.to_u32().map(|v| v as u8))
-                                            .or_else(|_| v.to_u16().map(|v| v as u8))
-                                            .or_else(|_| v.to_u8())
-                                    })
-                                    .collect();
-                                let types = types.map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                let vocab: HashMap<_, _> = tokens
-                                    .iter()
-                                    .enumerate()
-                                    .map(|(id, v)| (v.clone(), id as u32))
-                                    .collect();
-                                let merges = model
-                                    .metadata
-                                    .get("tokenizer.ggml.merges")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?;
-                                let merges: Result<Vec<_>, _> = merges
-                                    .to_vec()
-                                    .map_err(|_| LlamaSourceError::NoTokenizer)?
-                                    .iter()
-                                    .map(|v| {
-                                        v.to_string()
-                                            .map_err(|_| LlamaSourceError::NoTokenizer)
-                                            .and_then(|v| {
-                                                v.split_once(' ')
-                                                    .ok_or(LlamaSourceError::NoTokenizer)
-                                            })
-                                            .map(|(a, b)| (a.to_string(), b.to_string()))
-                                    })
-                                    .collect();
-                                let merges = merges.map_err(|_| LlamaSourceError::NoTokenizer)?;
-
-                                let eos = model
-                                    .metadata
-                                    .get("tokenizer.ggml.eos_token_id")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?;
-                                let eos =
-                                    eos.to_u32().map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                let eos = &tokens[eos as usize];
-
-                                let bos = model
-                                    .metadata
-                                    .get("tokenizer.ggml.bos_token_id")
-                                    .ok_or(LlamaSourceError::NoTokenizer)?;
-                                let bos =
-                                    bos.to_u32().map_err(|_| LlamaSourceError::NoTokenizer)?;
-                                let bos = &tokens[bos as usize];
-
-                                config
-                                    .build(vocab, types, merges, bos, eos)
-                                    .map_err(LlamaSourceError::Tokenizer)?
-                            }
+                            None => tokenizer_from_gguf(&model.metadata)?,
                         };
                         let model = Model::from_gguf(
                             model,
                             &mut file,
                             &device,
                             override_stop_token_string,
+                            builder.source.context_options(),
+                            builder.gpu_layers,
+                            &device_map,
                         )?;
+                        if let (Some(expected), Some(actual)) = (
+                            builder.source.group_query_attention,
+                            model.group_query_attention(),
+                        ) {
+                            if expected as usize != actual {
+                                tracing::warn!(
+                                    "Ignoring the group query attention of {expected} from the source. The gguf file uses a group query attention of {actual}"
+                                );
+                            }
+                        }
                         Ok((model, tokenizer))
                     }
                     Some("ggml" | "bin") | Some(_) | None => {
+                        let mut file = filename.open().map_err(candle_core::Error::from)?;
                         let model = ggml_file::Content::read(&mut file, &device)?;
                         let tokenizer = tokenizer.ok_or(LlamaSourceError::NoTokenizer)?;
 
-                        let gqa = builder.source.group_query_attention;
+                        let gqa = builder.source.group_query_attention.unwrap_or(1);
                         let vocab = tokenizer.get_vocab(true);
                         let start_token_string = match vocab
                             .get("<s>")
@@ -295,142 +449,152 @@ impl LlamaModel {
         .await
         .map_err(|_| LlamaSourceError::ModelLoadingPanic)??;
 
+        // Merge the LoRA adapters into the weights of the base model
+        let model = if lora_files.is_empty() {
+            model
+        } else {
+            tokio::task::spawn_blocking(move || {
+                let mut model = model;
+                for (file, config) in lora_files {
+                    let adapter = crate::raw::LoraAdapter::load(&file, config)?;
+                    model.apply_lora(&adapter)?;
+                }
+                Ok::<_, LlamaSourceError>(model)
+            })
+            .await
+            .map_err(|_| LlamaSourceError::ModelLoadingPanic)??
+        };
+
+        let mut model = model;
+        model.set_flash_attn(flash_attn);
+        if let Some(chat_template) = chat_template {
+            let chat_template =
+                crate::chat_template::HuggingFaceChatTemplate::create(chat_template)?;
+            match Arc::get_mut(&mut model.config) {
+                Some(config) => config.chat_template = Some(chat_template),
+                None => tracing::warn!(
+                    "Ignoring the chat template from the source because the model config is shared"
+                ),
+            }
+        }
+
+        let vision = match vision_projector {
+            Some(projector) => {
+                let device = device.clone();
+                let encoder = tokio::task::spawn_blocking(move || {
+                    let mut file = projector.open().map_err(candle_core::Error::from)?;
+                    let content = candle_core::quantized::gguf_file::Content::read(&mut file)?;
+                    Ok::<_, LlamaSourceError>(crate::raw::VisionEncoder::from_gguf(
+                        content, &mut file, &device,
+                    )?)
+                })
+                .await
+                .map_err(|_| LlamaSourceError::ModelLoadingPanic)??;
+                Some(Arc::new(encoder))
+            }
+            None => None,
+        };
+
+        let fingerprint = crate::session::model_fingerprint(&model.config, &tokenizer, &weights);
         Ok(Self {
             model,
             tokenizer: Arc::new(tokenizer),
             device,
+            preemption: None,
+            oom_policy,
+            vision,
+            max_batch_size,
+            prefix_cache: std::sync::Mutex::new(prefix_cache),
+            id: crate::swap::next_model_id(),
+            weights,
+            fingerprint,
+            retired_tokenizers: HashMap::new(),
+            cpu_model: std::sync::OnceLock::new(),
         })
     }
 
-    pub(crate) fn _infer(
+    /// Feed the text before each image and the embeddings of the image into the session. This is used for the
+    /// part of a chat prompt before the last image; the text after the last image is fed when generation starts.
+    pub(crate) fn feed_images(
         &mut self,
-        settings: InferenceSettings,
-        mut on_token: Box<dyn FnMut(String) -> Result<(), LlamaModelError> + Send + Sync>,
-        finished: &tokio::sync::oneshot::Sender<Result<(), LlamaModelError>>,
+        session: &crate::LlamaSession,
+        segments: &[String],
+        images: &[kalosm_language_model::MessageImage],
     ) -> Result<(), LlamaModelError> {
-        let InferenceSettings {
-            prompt,
-            stop_on,
-            mut sampler,
-            session,
-            max_tokens,
-            seed,
-        } = settings;
-
-        let mut session = session
+        let vision = self
+            .vision
+            .clone()
+            .ok_or(LlamaModelError::NoVisionProjector)?;
+        let mut cache = session
             .cache
             .write()
             .map_err(|err| LlamaModelError::Session(err.to_string()))?;
-
-        let tokens = self
-            .tokenizer
-            .encode_fast(prompt, false)
-            .map_err(LlamaModelError::Tokenizer)?;
-        let tokens = tokens.get_ids();
-        let mut text_stream = TokenOutputStream::new(self.tokenizer.clone());
-        for &token in tokens {
-            text_stream
-                .next_token(token)
-                .map_err(LlamaModelError::TokenOutputStreamError)?;
-        }
-
-        let mut logit_probs = Vec::new();
-        Self::forward(
-            &self.model,
-            &self.device,
-            tokens,
-            Some(&mut session),
-            &mut logit_probs,
-        )?;
-        let mut logits = Logits::try_from_iter_top_k(logit_probs, 512)
-            .expect("model output should be valid logits");
-        // This stores a buffer of text that has been generated to check against the stop_on string. It should never be longer than the stop_on string.
-        let mut queued_text_matching_stop_on = String::new();
-        let stop_on_lowercase = stop_on.as_ref().map(|s| s.to_lowercase());
-        let stop_on_lowercase = stop_on_lowercase.as_deref();
-        let stop_token = self.model.config.stop_token;
-        let mut tokens_generated = 0;
-        let mut logit_probs = Vec::new();
-
-        'generate: while !finished.is_closed() && tokens_generated < max_tokens {
-            let new_token = text_stream
-                .sample_token(&mut sampler, logits, stop_on.as_deref(), seed)
-                .map_err(LlamaModelError::TokenOutputStreamError)?;
-            if new_token == stop_token {
-                tracing::trace!("Stopping on stop token");
-                break;
-            }
-            if let Some(mut new_text) = text_stream
-                .next_token(new_token)
-                .map_err(LlamaModelError::TokenOutputStreamError)?
-            {
-                tokens_generated += 1;
-                if let Some(stop_on) = stop_on_lowercase {
-                    let lowercase = new_text.to_lowercase();
-
-                    // Check if the string ends with the start of the stop_on string
-                    let mut before_stop_on = None;
-                    let remaining_stop_on = stop_on
-                        .strip_prefix(&queued_text_matching_stop_on)
-                        .unwrap_or(stop_on);
-
-                    // If the remaining stop_on string is empty, we have found a match
-                    if remaining_stop_on.is_empty() {
-                        break;
-                    }
-
-                    for (i, _) in lowercase.char_indices() {
-                        let end_of_new_text = &lowercase[i..];
-                        if end_of_new_text.is_empty() {
-                            break;
-                        }
-
-                        // Check if we have matched all of the stop_on string
-                        if end_of_new_text.starts_with(remaining_stop_on) {
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break 'generate;
-                        }
-
-                        // Check if the string ends with the start of the stop_on string
-                        if remaining_stop_on.starts_with(end_of_new_text) {
-                            before_stop_on = Some(lowercase[..i].to_string());
-                            queued_text_matching_stop_on += end_of_new_text;
-                            break;
-                        }
-                    }
-
-                    match before_stop_on {
-                        Some(before_stop_on) => {
-                            on_token(before_stop_on)?;
-                        }
-                        None => {
-                            new_text =
-                                std::mem::take(&mut queued_text_matching_stop_on) + &new_text;
-                            on_token(new_text)?;
-                        }
-                    }
-                } else {
-                    on_token(new_text)?;
-                }
-            }
-            Self::forward(
-                &self.model,
-                &self.device,
-                &[new_token],
-                Some(&mut session),
-                &mut logit_probs,
-            )?;
-            logits = Logits::try_from_iter_top_k(logit_probs.iter().copied(), 512)
-                .expect("model output should be valid logits");
-        }
-
-        // Flush the queued text
-        if let Some(stop_string) = stop_on_lowercase {
-            if !queued_text_matching_stop_on.starts_with(stop_string) {
-                on_token(queued_text_matching_stop_on)?;
+        let mut logits = Vec::new();
+        for (text, image) in segments.iter().zip(images) {
+            let tokens = self
+                .tokenizer
+                .encode_fast(text.as_str(), false)
+                .map_err(LlamaModelError::Tokenizer)?;
+            if !tokens.get_ids().is_empty() {
+                self.forward_with_recovery(tokens.get_ids(), &mut cache, &mut logits)?;
             }
+            let embeddings = vision.encode_image(image.bytes())?;
+            // The image positions don't correspond to any token, so the cache stores the unknown token in their place
+            self.model
+                .forward_embeddings(&embeddings, 0, &self.device, Some(&mut cache))?;
         }
-
         Ok(())
     }
 }
+
+/// Register special tokens with a tokenizer. Returns the id of each token and the ids of the tokens that were not
+/// already in the vocabulary.
+fn register_special_tokens(
+    tokenizer: &mut Tokenizer,
+    tokens: &[String],
+) -> Result<(Vec<u32>, Vec<u32>), LlamaModelError> {
+    let vocab = tokenizer.get_vocab(true);
+    let added: Vec<_> = tokens
+        .iter()
+        .map(|token| tokenizers::AddedToken::from(token.clone(), true))
+        .collect();
+    tokenizer.add_special_tokens(&added);
+    let ids = tokens
+        .iter()
+        .map(|token| {
+            tokenizer.token_to_id(token).ok_or_else(|| {
+                LlamaModelError::Tokenizer(format!("Failed to add token {token:?}").into())
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let new_tokens = tokens
+        .iter()
+        .zip(&ids)
+        .filter(|(token, _)| !vocab.contains_key(token.as_str()))
+        .map(|(_, id)| *id)
+        .collect();
+    Ok((ids, new_tokens))
+}
+
+/// Get the log probability of a token from the raw logits of the model.
+pub(crate) fn log_softmax(logits: &[f32], token: usize) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f32 = logits.iter().map(|logit| (logit - max).exp()).sum();
+    logits
+        .get(token)
+        .map_or(f32::NEG_INFINITY, |logit| logit - max - sum.ln())
+}
+
+#[test]
+fn special_tokens_are_tokenized_as_one_id() {
+    let mut tokenizer = Tokenizer::new(tokenizers::models::wordlevel::WordLevel::default());
+    tokenizer.add_tokens(&[tokenizers::AddedToken::from("hello", false)]);
+    let tokens = ["<tool_call>".to_string(), "hello".to_string()];
+    let (ids, new_tokens) = register_special_tokens(&mut tokenizer, &tokens).unwrap();
+    assert_eq!(ids, [1, 0]);
+    // Only tokens that were not in the vocabulary are masked in the output
+    assert_eq!(new_tokens, [1]);
+
+    let encoding = tokenizer.encode("hello<tool_call>", false).unwrap();
+    assert_eq!(encoding.get_ids(), [0, 1]);
+}
@@ -0,0 +1,310 @@
+use candle_core::Device;
+use kalosm_language_model::Degradation;
+
+use crate::model::LlamaModel;
+use crate::raw::cache::LlamaCache;
+
+/// What the model should try when the device runs out of memory while processing tokens. If every fallback fails,
+/// the out of memory error is returned. Each fallback that was used is reported in
+/// [`Usage::degradations`](kalosm_language_model::Usage::degradations) and
+/// [`LlamaSession::degradations`](crate::LlamaSession::degradations).
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let model = Llama::builder()
+///     .with_oom_policy(
+///         OomPolicy::new()
+///             .with_min_chunk_size(32)
+///             .with_cpu_offload(true),
+///     )
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OomPolicy {
+    chunked_prefill: bool,
+    min_chunk_size: usize,
+    reduce_context: bool,
+    cpu_offload: bool,
+}
+
+impl Default for OomPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OomPolicy {
+    /// Create the default policy which retries the prompt in smaller chunks but never drops context.
+    pub fn new() -> Self {
+        Self {
+            chunked_prefill: true,
+            min_chunk_size: 16,
+            reduce_context: false,
+            cpu_offload: false,
+        }
+    }
+
+    /// Create a policy that returns out of memory errors immediately.
+    pub fn disabled() -> Self {
+        Self {
+            chunked_prefill: false,
+            min_chunk_size: 16,
+            reduce_context: false,
+            cpu_offload: false,
+        }
+    }
+
+    /// Set whether to retry the prompt in smaller chunks. The chunk size is halved after each failure until it
+    /// reaches the minimum chunk size. (Defaults to true)
+    pub fn with_chunked_prefill(mut self, chunked_prefill: bool) -> Self {
+        self.chunked_prefill = chunked_prefill;
+        self
+    }
+
+    /// Set the smallest chunk size to retry the prompt with. (Defaults to 16)
+    pub fn with_min_chunk_size(mut self, min_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size.max(1);
+        self
+    }
+
+    /// Set whether to drop the oldest half of the context after chunking fails. The context is halved after each
+    /// failure. (Defaults to false)
+    pub fn with_reduce_context(mut self, reduce_context: bool) -> Self {
+        self.reduce_context = reduce_context;
+        self
+    }
+
+    /// Set whether to run the tokens on the CPU after the other fallbacks fail. The first offloaded pass copies the
+    /// weights of the model into system memory and the key value cache is moved to the CPU and back for each
+    /// offloaded pass, so this is much slower than running on the accelerator. (Defaults to false)
+    pub fn with_cpu_offload(mut self, cpu_offload: bool) -> Self {
+        self.cpu_offload = cpu_offload;
+        self
+    }
+}
+
+/// Check if an error is an allocation failure on the device.
+pub(crate) fn is_out_of_memory(error: &candle_core::Error) -> bool {
+    let message = error.to_string().to_lowercase();
+    [
+        "out of memory",
+        "out_of_memory",
+        "outofmemory",
+        "failed to allocate",
+        "insufficient memory",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+/// The state to roll the cache back to if a forward pass runs out of memory.
+enum Checkpoint {
+    /// The new tokens fit after the cached tokens, so rolling back only drops the new tokens from the cache.
+    Length(usize),
+    /// The model trims or evicts the start of the context to fit the new tokens, so the whole cache is restored.
+    Full(Box<LlamaCache>),
+}
+
+impl Checkpoint {
+    fn new(cache: &LlamaCache, new_tokens: usize, context_length: usize) -> Self {
+        if cache.tokens.len() + new_tokens <= context_length {
+            Self::Length(cache.tokens.len())
+        } else {
+            Self::Full(Box::new(cache.clone()))
+        }
+    }
+
+    fn restore(self, cache: &mut LlamaCache) {
+        match self {
+            Self::Length(len) => cache.truncate(len),
+            Self::Full(snapshot) => *cache = *snapshot,
+        }
+    }
+}
+
+impl LlamaModel {
+    /// Run the model on the tokens, falling back to the [`OomPolicy`] of the model if the device runs out of memory.
+    pub(crate) fn forward_with_recovery(
+        &self,
+        tokens: &[u32],
+        cache: &mut LlamaCache,
+        logits_vec: &mut Vec<f32>,
+    ) -> candle_core::Result<()> {
+        let context_length = self.model.config.context_length;
+        let checkpoint = Checkpoint::new(cache, tokens.len(), context_length);
+        let error = match self.forward_chunked(tokens, tokens.len(), cache, logits_vec) {
+            Err(error) if is_out_of_memory(&error) => error,
+            result => return result,
+        };
+        checkpoint.restore(cache);
+        tracing::warn!("Ran out of memory while running the model: {error}");
+        let policy = &self.oom_policy;
+
+        if policy.chunked_prefill {
+            let mut chunk_size = tokens.len() / 2;
+            while chunk_size >= policy.min_chunk_size {
+                let checkpoint = Checkpoint::new(cache, tokens.len(), context_length);
+                match self.forward_chunked(tokens, chunk_size, cache, logits_vec) {
+                    Ok(()) => {
+                        cache
+                            .degradations
+                            .push(Degradation::ChunkedPrefill { chunk_size });
+                        return Ok(());
+                    }
+                    Err(error) if is_out_of_memory(&error) => checkpoint.restore(cache),
+                    Err(error) => return Err(error),
+                }
+                chunk_size /= 2;
+            }
+        }
+
+        if policy.reduce_context {
+            let mut all_tokens = cache.tokens.clone();
+            all_tokens.extend_from_slice(tokens);
+            // Dropping context rebuilds the cache, so keep the original in case every attempt fails
+            let original = cache.clone();
+            let mut keep = all_tokens.len() / 2;
+            while keep > 0 {
+                cache.clear();
+                cache.tokens.clear();
+                let recent = &all_tokens[all_tokens.len() - keep..];
+                let chunk_size = if policy.chunked_prefill {
+                    policy.min_chunk_size
+                } else {
+                    recent.len()
+                };
+                match self.forward_chunked(recent, chunk_size, cache, logits_vec) {
+                    Ok(()) => {
+                        cache.degradations.push(Degradation::ReducedContext {
+                            dropped_tokens: all_tokens.len() - keep,
+                        });
+                        return Ok(());
+                    }
+                    Err(error) if is_out_of_memory(&error) => {}
+                    Err(error) => {
+                        *cache = original;
+                        return Err(error);
+                    }
+                }
+                keep /= 2;
+            }
+            *cache = original;
+        }
+
+        if policy.cpu_offload && !self.model.runs_on_cpu() {
+            let checkpoint = Checkpoint::new(cache, tokens.len(), context_length);
+            match self.forward_on_cpu(tokens, cache, logits_vec) {
+                Ok(()) => {
+                    cache.degradations.push(Degradation::CpuOffload {
+                        tokens: tokens.len(),
+                    });
+                    return Ok(());
+                }
+                Err(offload_error) => {
+                    tracing::warn!("Failed to run the model on the CPU: {offload_error}");
+                    checkpoint.restore(cache);
+                    // The cache may be left on the CPU if moving it back failed
+                    if let Err(error) = cache.move_to_devices(&self.model.layer_devices()) {
+                        tracing::error!("Failed to move the cache back to the model: {error}");
+                        cache.clear();
+                        cache.tokens.clear();
+                    }
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Run the model on the tokens with a copy of the model on the CPU. The cache is moved to the CPU for the pass
+    /// and back to the devices of the layers afterwards.
+    fn forward_on_cpu(
+        &self,
+        tokens: &[u32],
+        cache: &mut LlamaCache,
+        logits_vec: &mut Vec<f32>,
+    ) -> candle_core::Result<()> {
+        let cpu_model = match self.cpu_model.get() {
+            Some(cpu_model) => cpu_model,
+            None => {
+                tracing::info!(
+                    "Copying the model to the CPU to offload passes that run out of memory"
+                );
+                let cpu_model = self.model.to_cpu()?;
+                self.cpu_model.get_or_init(|| cpu_model)
+            }
+        };
+        let cpu_devices = vec![Device::Cpu; cache.blocks.len()];
+        cache.move_to_devices(&cpu_devices)?;
+        Self::forward(
+            cpu_model,
+            &Device::Cpu,
+            tokens,
+            Some(&mut *cache),
+            logits_vec,
+        )?;
+        cache.move_to_devices(&self.model.layer_devices())
+    }
+
+    /// Run the model on the tokens in chunks of `chunk_size` tokens.
+    fn forward_chunked(
+        &self,
+        tokens: &[u32],
+        chunk_size: usize,
+        cache: &mut LlamaCache,
+        logits_vec: &mut Vec<f32>,
+    ) -> candle_core::Result<()> {
+        for chunk in tokens.chunks(chunk_size.max(1)) {
+            Self::forward(
+                &self.model,
+                &self.device,
+                chunk,
+                Some(&mut *cache),
+                logits_vec,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn detects_out_of_memory_errors() {
+    let cuda =
+        candle_core::Error::Msg("DriverError(CUDA_ERROR_OUT_OF_MEMORY, \"out of memory\")".into());
+    assert!(is_out_of_memory(&cuda));
+    let metal = candle_core::Error::Msg("Failed to allocate a buffer of 4096 bytes".into());
+    assert!(is_out_of_memory(&metal));
+    let shape = candle_core::Error::Msg("shape mismatch in matmul".into());
+    assert!(!is_out_of_memory(&shape));
+}
+
+#[test]
+fn checkpoints_only_drop_the_new_tokens() {
+    use candle_core::{DType, Tensor};
+
+    let device = Device::Cpu;
+    let kv = |len| Tensor::zeros((1, 1, len, 4), DType::F32, &device).unwrap();
+    let mut cache = LlamaCache::default();
+    cache.blocks.push(kalosm_common::PagedKvCache::new(2, 16));
+    cache.blocks[0].push(&kv(3), &kv(3)).unwrap();
+    cache.tokens = vec![1, 2, 3];
+
+    let checkpoint = Checkpoint::new(&cache, 2, 16);
+    assert!(matches!(checkpoint, Checkpoint::Length(3)));
+    cache.blocks[0].push(&kv(2), &kv(2)).unwrap();
+    cache.tokens.extend([4, 5]);
+    checkpoint.restore(&mut cache);
+    assert_eq!(cache.tokens, [1, 2, 3]);
+    assert_eq!(cache.blocks[0].current_seq_len(), 3);
+
+    // Tokens that overflow the context make the model rebuild the cache, so the whole cache is kept
+    assert!(matches!(
+        Checkpoint::new(&cache, 14, 16),
+        Checkpoint::Full(_)
+    ));
+}
@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use kalosm_language_model::PrefillProgress;
+
+use crate::model::{LlamaModel, LlamaModelError};
+use crate::raw::cache::LlamaCache;
+
+/// The number of prompt tokens fed into the model between prefill progress events.
+const PREFILL_PROGRESS_CHUNK_SIZE: usize = 256;
+
+/// A handler that is called with the progress of the prompt prefill.
+pub(crate) type PrefillHandler = Arc<Mutex<dyn FnMut(PrefillProgress) + Send>>;
+
+impl LlamaModel {
+    /// Feed the prompt tokens into the cache. If there is a progress handler or a deadline, the prompt is fed in
+    /// chunks. The handler is called after each chunk, and if the deadline passes before the prompt is fed, the cache
+    /// is restored to its state before the prompt and [`LlamaModelError::TimedOut`] is returned.
+    ///
+    /// The first prompt of a session reuses the kv cache of any cached prompt with the same prefix, and is then
+    /// cached for later sessions.
+    pub(crate) fn prefill(
+        &self,
+        tokens: &[u32],
+        cache: &mut LlamaCache,
+        logits_vec: &mut Vec<f32>,
+        progress: Option<&PrefillHandler>,
+        deadline: Option<Instant>,
+    ) -> Result<(), LlamaModelError> {
+        let first_prompt = cache.tokens.is_empty();
+        let start_len = cache.tokens.len();
+        let reused = self.reuse_cached_prefix(tokens, cache);
+        if !self.prefill_uncached(tokens, reused, cache, logits_vec, progress, deadline)? {
+            tracing::trace!("Stopping generation that timed out while reading the prompt");
+            cache.truncate(start_len);
+            return Err(LlamaModelError::TimedOut);
+        }
+        if first_prompt {
+            self.remember_prefix(cache);
+        }
+        Ok(())
+    }
+
+    /// Feed the prompt tokens after the first `reused` tokens into the cache. Returns false if the deadline passed
+    /// before every token was fed.
+    fn prefill_uncached(
+        &self,
+        tokens: &[u32],
+        reused: usize,
+        cache: &mut LlamaCache,
+        logits_vec: &mut Vec<f32>,
+        progress: Option<&PrefillHandler>,
+        deadline: Option<Instant>,
+    ) -> candle_core::Result<bool> {
+        if progress.is_none() && deadline.is_none() {
+            self.forward_with_recovery(&tokens[reused..], cache, logits_vec)?;
+            return Ok(true);
+        }
+        let started = Instant::now();
+        let mut tokens_processed = reused;
+        for chunk in tokens[reused..].chunks(PREFILL_PROGRESS_CHUNK_SIZE) {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
+            self.forward_with_recovery(chunk, cache, logits_vec)?;
+            tokens_processed += chunk.len();
+            if let Some(progress) = progress {
+                let event = PrefillProgress::new(tokens_processed, tokens.len(), started.elapsed());
+                let mut handler = progress.lock().unwrap();
+                (*handler)(event);
+            }
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use kalosm_common::PagedKvCache;
+
+use crate::model::LlamaModel;
+use crate::raw::cache::LlamaCache;
+
+/// The number of prompts whose kv cache is kept if [`crate::LlamaBuilder::with_prefix_cache_size`] is not set. Each
+/// cached prompt holds on to its whole kv cache, so prefix caching is opt-in.
+pub(crate) const DEFAULT_PREFIX_CACHE_SIZE: usize = 0;
+
+struct PrefixEntry {
+    tokens: Vec<u32>,
+    blocks: Vec<PagedKvCache>,
+    /// The hash of each prefix of the tokens that ends on a block boundary
+    hashes: Vec<u64>,
+    last_used: u64,
+}
+
+/// The kv cache of recent prompts, shared with new sessions that start with the same tokens. Prompts are hashed
+/// one block of tokens at a time so the longest cached prefix of a new prompt can be found without comparing it to
+/// every entry.
+///
+/// The cached blocks are shared with the sessions that use them. Only the part of a block the session writes to is
+/// copied.
+pub(crate) struct PrefixCache {
+    capacity: usize,
+    block_size: usize,
+    entries: HashMap<u64, PrefixEntry>,
+    /// The entry that most recently cached each prefix hash
+    prefixes: HashMap<u64, u64>,
+    next_id: u64,
+    clock: u64,
+}
+
+impl PrefixCache {
+    pub(crate) fn new(capacity: usize, block_size: usize) -> Self {
+        Self {
+            capacity,
+            block_size: block_size.max(1),
+            entries: HashMap::new(),
+            prefixes: HashMap::new(),
+            next_id: 0,
+            clock: 0,
+        }
+    }
+
+    /// Hash each prefix of the tokens that ends on a block boundary.
+    fn prefix_hashes<'a>(&self, tokens: &'a [u32]) -> impl Iterator<Item = u64> + 'a {
+        let mut hasher = DefaultHasher::new();
+        tokens.chunks_exact(self.block_size).map(move |block| {
+            block.hash(&mut hasher);
+            hasher.finish()
+        })
+    }
+
+    /// Find the longest cached prefix of the tokens that is at least one block long. Returns the length of the
+    /// prefix and the kv cache of each layer for it.
+    pub(crate) fn get(&mut self, tokens: &[u32]) -> Option<(usize, Vec<PagedKvCache>)> {
+        let id = self
+            .prefix_hashes(tokens)
+            .map_while(|hash| self.prefixes.get(&hash).copied())
+            .last()?;
+        self.clock += 1;
+        let entry = self.entries.get_mut(&id)?;
+        entry.last_used = self.clock;
+        // The entry may share more tokens than the last matching block
+        let len = entry
+            .tokens
+            .iter()
+            .zip(tokens)
+            .take_while(|(cached, token)| cached == token)
+            .count();
+        if len < self.block_size {
+            return None;
+        }
+        let blocks = entry
+            .blocks
+            .iter()
+            .map(|block| {
+                let mut block = block.clone();
+                block.truncate(len);
+                block
+            })
+            .collect();
+        Some((len, blocks))
+    }
+
+    /// Remember the kv cache for a prompt. Prompts shorter than one block are not cached.
+    pub(crate) fn insert(&mut self, tokens: &[u32], blocks: &[PagedKvCache]) {
+        if self.capacity == 0 || tokens.len() < self.block_size {
+            return;
+        }
+        self.clock += 1;
+        let hashes: Vec<_> = self.prefix_hashes(tokens).collect();
+        // Don't store a prompt that another entry already covers
+        if let Some(entry) = hashes
+            .last()
+            .and_then(|hash| self.prefixes.get(hash))
+            .and_then(|id| self.entries.get_mut(id))
+        {
+            if entry.tokens.starts_with(tokens) {
+                entry.last_used = self.clock;
+                return;
+            }
+        }
+
+        while self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        for &hash in &hashes {
+            self.prefixes.insert(hash, id);
+        }
+        self.entries.insert(
+            id,
+            PrefixEntry {
+                tokens: tokens.to_vec(),
+                blocks: blocks.to_vec(),
+                hashes,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let Some(id) = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(id, _)| *id)
+        else {
+            return;
+        };
+        if let Some(entry) = self.entries.remove(&id) {
+            for hash in entry.hashes {
+                if self.prefixes.get(&hash) == Some(&id) {
+                    self.prefixes.remove(&hash);
+                }
+            }
+        }
+    }
+}
+
+impl LlamaModel {
+    /// Fill an empty session cache with the longest cached prefix of the prompt. At least one token is left for the
+    /// model to run on so there are logits for the next token. Returns the number of tokens that were reused.
+    pub(crate) fn reuse_cached_prefix(&self, tokens: &[u32], cache: &mut LlamaCache) -> usize {
+        // Captured activations need every token to run through the model
+        if !cache.tokens.is_empty() || cache.activations.is_some() || tokens.len() < 2 {
+            return 0;
+        }
+        let Some((len, blocks)) = self
+            .prefix_cache
+            .lock()
+            .unwrap()
+            .get(&tokens[..tokens.len() - 1])
+        else {
+            return 0;
+        };
+        tracing::trace!("Reusing the kv cache for {len} prompt tokens from another session");
+        cache.blocks = blocks;
+        cache.tokens = tokens[..len].to_vec();
+        len
+    }
+
+    /// Share the kv cache of a session's prompt with new sessions.
+    pub(crate) fn remember_prefix(&self, cache: &LlamaCache) {
+        self.prefix_cache
+            .lock()
+            .unwrap()
+            .insert(&cache.tokens, &cache.blocks);
+    }
+}
+
+#[cfg(test)]
+fn test_blocks(tokens: &[u32], block_size: usize) -> Vec<PagedKvCache> {
+    let allocator = kalosm_common::BlockAllocator::new(block_size);
+    let mut cache = PagedKvCache::with_allocator(allocator, 2, 64);
+    let values: Vec<f32> = tokens.iter().map(|&token| token as f32).collect();
+    let values =
+        candle_core::Tensor::from_vec(values, (1, 1, tokens.len(), 1), &candle_core::Device::Cpu)
+            .unwrap();
+    cache.append(&values, &values).unwrap();
+    vec![cache]
+}
+
+#[test]
+fn prefix_cache_finds_the_longest_shared_prefix() {
+    let mut cache = PrefixCache::new(2, 4);
+    let system_prompt = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+    cache.insert(&system_prompt, &test_blocks(&system_prompt, 4));
+
+    let (len, blocks) = cache.get(&[1, 2, 3, 4, 5, 6, 7, 0, 0, 0]).unwrap();
+    assert_eq!(len, 7);
+    let k = blocks[0].k().unwrap().unwrap().flatten_all().unwrap();
+    assert_eq!(k.to_vec1::<f32>().unwrap(), [1., 2., 3., 4., 5., 6., 7.]);
+
+    // Prompts that share less than one block are not reused
+    assert!(cache.get(&[1, 2, 3, 0, 0]).is_none());
+
+    // The least recently used prompt is evicted first
+    cache.insert(&[10, 11, 12, 13], &test_blocks(&[10, 11, 12, 13], 4));
+    cache.insert(&[20, 21, 22, 23], &test_blocks(&[20, 21, 22, 23], 4));
+    assert!(cache.get(&[1, 2, 3, 4, 5]).is_none());
+    assert!(cache.get(&[20, 21, 22, 23, 24]).is_some());
+}
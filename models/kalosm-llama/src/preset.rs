@@ -0,0 +1,573 @@
+use crate::{GgufQuantization, LlamaSource};
+
+/// A built-in [`LlamaSource`] preset with metadata about the model. Get every preset with [`LlamaSource::presets`] or
+/// look one up by name with [`LlamaSource::from_name`].
+#[derive(Debug, Clone, Copy)]
+pub struct LlamaPreset {
+    name: &'static str,
+    description: &'static str,
+    parameters: &'static str,
+    quantization: Option<GgufQuantization>,
+    chat: bool,
+    source: fn() -> LlamaSource,
+}
+
+impl LlamaPreset {
+    /// The name of the preset, like `qwen2.5-7b-instruct`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// A human readable name for the model.
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// The number of parameters in the model, like `7B`.
+    pub fn parameters(&self) -> &'static str {
+        self.parameters
+    }
+
+    /// The quantization level of the model file the preset downloads, if it is known.
+    pub fn quantization(&self) -> Option<GgufQuantization> {
+        self.quantization
+    }
+
+    /// Whether the model is tuned for chat and instructions instead of raw text completion.
+    pub fn is_chat(&self) -> bool {
+        self.chat
+    }
+
+    /// Create the [`LlamaSource`] for the preset.
+    pub fn source(&self) -> LlamaSource {
+        (self.source)()
+    }
+}
+
+const PRESETS: &[LlamaPreset] = &[
+    LlamaPreset {
+        name: "mistral-7b",
+        description: "Mistral 7B v0.1",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: false,
+        source: LlamaSource::mistral_7b,
+    },
+    LlamaPreset {
+        name: "mistral-7b-instruct",
+        description: "Mistral 7B Instruct v0.1",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mistral_7b_instruct,
+    },
+    LlamaPreset {
+        name: "mistral-7b-instruct-v0.2",
+        description: "Mistral 7B Instruct v0.2",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mistral_7b_instruct_2,
+    },
+    LlamaPreset {
+        name: "llava-1.5-7b",
+        description: "LLaVA 1.5 7B vision model",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::llava_1_5_7b,
+    },
+    LlamaPreset {
+        name: "mixtral-8x7b-instruct",
+        description: "Mixtral 8x7B Instruct v0.1",
+        parameters: "8x7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mixtral_8x7b_instruct,
+    },
+    LlamaPreset {
+        name: "mistral-nemo-instruct",
+        description: "Mistral NeMo Instruct 2407",
+        parameters: "12B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mistral_nemo_instruct,
+    },
+    LlamaPreset {
+        name: "mistral-small-instruct",
+        description: "Mistral Small Instruct 2409",
+        parameters: "22B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mistral_small_instruct,
+    },
+    LlamaPreset {
+        name: "mistral-small-24b-instruct",
+        description: "Mistral Small 24B Instruct 2501",
+        parameters: "24B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::mistral_small_24b_instruct,
+    },
+    LlamaPreset {
+        name: "neural-hermes-2.5-mistral-7b",
+        description: "NeuralHermes 2.5 Mistral 7B",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: true,
+        source: LlamaSource::neural_hermes_2_5_mistral_7b,
+    },
+    LlamaPreset {
+        name: "neural-chat-7b-v3.3",
+        description: "Neural Chat 7B v3.3",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: true,
+        source: LlamaSource::neural_chat_7b_v3_3,
+    },
+    LlamaPreset {
+        name: "zephyr-7b-alpha",
+        description: "Zephyr 7B Alpha",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::zephyr_7b_alpha,
+    },
+    LlamaPreset {
+        name: "zephyr-7b-beta",
+        description: "Zephyr 7B Beta",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::zephyr_7b_beta,
+    },
+    LlamaPreset {
+        name: "openchat-3.5-7b",
+        description: "OpenChat 3.5 (0106)",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::open_chat_7b,
+    },
+    LlamaPreset {
+        name: "starling-7b-alpha",
+        description: "Starling LM 7B Alpha",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::starling_7b_alpha,
+    },
+    LlamaPreset {
+        name: "starling-7b-beta",
+        description: "Starling LM 7B Beta",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::starling_7b_beta,
+    },
+    LlamaPreset {
+        name: "wizardlm-2-7b",
+        description: "WizardLM 2 7B",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::wizard_lm_7b_v2,
+    },
+    LlamaPreset {
+        name: "tinyllama-1.1b-chat",
+        description: "TinyLlama 1.1B Chat v1.0",
+        parameters: "1.1B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::tiny_llama_1_1b_chat,
+    },
+    LlamaPreset {
+        name: "tinyllama-1.1b",
+        description: "TinyLlama 1.1B v1.0",
+        parameters: "1.1B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: false,
+        source: LlamaSource::tiny_llama_1_1b,
+    },
+    LlamaPreset {
+        name: "smollm2-135m-instruct",
+        description: "SmolLM2 135M Instruct",
+        parameters: "135M",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: true,
+        source: LlamaSource::smollm2_135m_instruct,
+    },
+    LlamaPreset {
+        name: "smollm2-360m-instruct",
+        description: "SmolLM2 360M Instruct",
+        parameters: "360M",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: true,
+        source: LlamaSource::smollm2_360m_instruct,
+    },
+    LlamaPreset {
+        name: "smollm2-1.7b-instruct",
+        description: "SmolLM2 1.7B Instruct",
+        parameters: "1.7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::smollm2_1_7b_instruct,
+    },
+    LlamaPreset {
+        name: "phi-3-mini-4k-instruct",
+        description: "Phi 3 Mini 4k Instruct",
+        parameters: "3.8B",
+        quantization: None,
+        chat: true,
+        source: LlamaSource::phi_3_mini_4k_instruct,
+    },
+    LlamaPreset {
+        name: "phi-3.1-mini-4k-instruct",
+        description: "Phi 3.1 Mini 4k Instruct",
+        parameters: "3.8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::phi_3_1_mini_4k_instruct,
+    },
+    LlamaPreset {
+        name: "phi-3.5-mini-instruct",
+        description: "Phi 3.5 Mini Instruct",
+        parameters: "3.8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::phi_3_5_mini_4k_instruct,
+    },
+    LlamaPreset {
+        name: "phi-4",
+        description: "Phi 4",
+        parameters: "14B",
+        quantization: None,
+        chat: true,
+        source: LlamaSource::phi_4,
+    },
+    LlamaPreset {
+        name: "llama-2-7b",
+        description: "Llama 2 7B",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: false,
+        source: LlamaSource::llama_7b,
+    },
+    LlamaPreset {
+        name: "llama-3-8b",
+        description: "Llama 3 8B",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: false,
+        source: LlamaSource::llama_8b,
+    },
+    LlamaPreset {
+        name: "llama-3-8b-instruct",
+        description: "Llama 3 8B Instruct",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q5_K_M),
+        chat: true,
+        source: LlamaSource::llama_8b_chat,
+    },
+    LlamaPreset {
+        name: "llama-3.1-8b-instruct",
+        description: "Llama 3.1 8B Instruct",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::llama_3_1_8b_chat,
+    },
+    LlamaPreset {
+        name: "llama-3-8b-instruct-q8",
+        description: "Llama 3 8B Instruct",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: true,
+        source: LlamaSource::llama_8b_chat_q8,
+    },
+    LlamaPreset {
+        name: "llama-3-8b-sppo-iter3",
+        description: "Llama 3 Instruct 8B SPPO Iter3",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::llama_8b_sppo_iter3,
+    },
+    LlamaPreset {
+        name: "llama-3.2-1b-instruct",
+        description: "Llama 3.2 1B Instruct",
+        parameters: "1B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::llama_3_2_1b_chat,
+    },
+    LlamaPreset {
+        name: "llama-3.2-3b-instruct",
+        description: "Llama 3.2 3B Instruct",
+        parameters: "3B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::llama_3_2_3b_chat,
+    },
+    LlamaPreset {
+        name: "llama-2-13b",
+        description: "Llama 2 13B",
+        parameters: "13B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: false,
+        source: LlamaSource::llama_13b,
+    },
+    LlamaPreset {
+        name: "llama-2-70b",
+        description: "Llama 2 70B",
+        parameters: "70B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: false,
+        source: LlamaSource::llama_70b,
+    },
+    LlamaPreset {
+        name: "llama-2-7b-chat",
+        description: "Llama 2 7B Chat",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: true,
+        source: LlamaSource::llama_7b_chat,
+    },
+    LlamaPreset {
+        name: "llama-2-13b-chat",
+        description: "Llama 2 13B Chat",
+        parameters: "13B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: true,
+        source: LlamaSource::llama_13b_chat,
+    },
+    LlamaPreset {
+        name: "llama-2-70b-chat",
+        description: "Llama 2 70B Chat",
+        parameters: "70B",
+        quantization: Some(GgufQuantization::Q4_0),
+        chat: true,
+        source: LlamaSource::llama_70b_chat,
+    },
+    LlamaPreset {
+        name: "codellama-7b",
+        description: "Code Llama 7B",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: false,
+        source: LlamaSource::llama_7b_code,
+    },
+    LlamaPreset {
+        name: "codellama-13b",
+        description: "Code Llama 13B",
+        parameters: "13B",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: false,
+        source: LlamaSource::llama_13b_code,
+    },
+    LlamaPreset {
+        name: "codellama-34b",
+        description: "Code Llama 34B",
+        parameters: "34B",
+        quantization: Some(GgufQuantization::Q8_0),
+        chat: false,
+        source: LlamaSource::llama_34b_code,
+    },
+    LlamaPreset {
+        name: "solar-10.7b",
+        description: "SOLAR 10.7B",
+        parameters: "10.7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: false,
+        source: LlamaSource::solar_10_7b,
+    },
+    LlamaPreset {
+        name: "solar-10.7b-instruct",
+        description: "SOLAR 10.7B Instruct",
+        parameters: "10.7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::solar_10_7b_instruct,
+    },
+    LlamaPreset {
+        name: "qwen2.5-0.5b-instruct",
+        description: "Qwen2.5 0.5B Instruct",
+        parameters: "0.5B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::qwen_2_5_0_5b_instruct,
+    },
+    LlamaPreset {
+        name: "qwen2.5-1.5b-instruct",
+        description: "Qwen2.5 1.5B Instruct",
+        parameters: "1.5B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::qwen_2_5_1_5b_instruct,
+    },
+    LlamaPreset {
+        name: "qwen2.5-3b-instruct",
+        description: "Qwen2.5 3B Instruct",
+        parameters: "3B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::qwen_2_5_3b_instruct,
+    },
+    LlamaPreset {
+        name: "qwen2.5-7b-instruct",
+        description: "Qwen2.5 7B Instruct",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::qwen_2_5_7b_instruct,
+    },
+    LlamaPreset {
+        name: "gemma-2-2b-instruct",
+        description: "Gemma 2 2B Instruct",
+        parameters: "2B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::gemma_2_2b_instruct,
+    },
+    LlamaPreset {
+        name: "gemma-2-9b-instruct",
+        description: "Gemma 2 9B Instruct",
+        parameters: "9B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::gemma_2_9b_instruct,
+    },
+    LlamaPreset {
+        name: "granite-3.1-1b-a400m-instruct",
+        description: "Granite 3.1 1B A400M Instruct",
+        parameters: "1B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::granite_3_1_1b_a400m_instruct,
+    },
+    LlamaPreset {
+        name: "granite-3.1-2b-instruct",
+        description: "Granite 3.1 2B Instruct",
+        parameters: "2B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::granite_3_1_2b_instruct,
+    },
+    LlamaPreset {
+        name: "granite-3.1-8b-instruct",
+        description: "Granite 3.1 8B Instruct",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::granite_3_1_8b_instruct,
+    },
+    LlamaPreset {
+        name: "olmo-2-7b-instruct",
+        description: "OLMo 2 7B Instruct",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::olmo_2_7b_instruct,
+    },
+    LlamaPreset {
+        name: "olmo-2-13b-instruct",
+        description: "OLMo 2 13B Instruct",
+        parameters: "13B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::olmo_2_13b_instruct,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-qwen-1.5b",
+        description: "DeepSeek R1 Distill Qwen 1.5B",
+        parameters: "1.5B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_qwen_1_5b,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-qwen-7b",
+        description: "DeepSeek R1 Distill Qwen 7B",
+        parameters: "7B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_qwen_7b,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-qwen-14b",
+        description: "DeepSeek R1 Distill Qwen 14B",
+        parameters: "14B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_qwen_14b,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-llama-8b",
+        description: "DeepSeek R1 Distill Llama 8B",
+        parameters: "8B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_llama_8b,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-qwen-32b",
+        description: "DeepSeek R1 Distill Qwen 32B",
+        parameters: "32B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_qwen_32b,
+    },
+    LlamaPreset {
+        name: "deepseek-r1-distill-llama-70b",
+        description: "DeepSeek R1 Distill Llama 70B",
+        parameters: "70B",
+        quantization: Some(GgufQuantization::Q4_K_M),
+        chat: true,
+        source: LlamaSource::deepseek_r1_distill_llama_70b,
+    },
+];
+
+impl LlamaSource {
+    /// Get every built-in preset. This can be used to show a list of models to pick from.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    ///
+    /// for preset in LlamaSource::presets().iter().filter(|preset| preset.is_chat()) {
+    ///     println!("{} ({}, {})", preset.name(), preset.description(), preset.parameters());
+    /// }
+    /// ```
+    pub fn presets() -> &'static [LlamaPreset] {
+        PRESETS
+    }
+
+    /// Get a built-in preset by name. The name is case insensitive. Returns `None` if there is no preset with that
+    /// name.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let source = LlamaSource::from_name("qwen2.5-7b-instruct").unwrap();
+    /// let model = Llama::builder().with_source(source).build().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_name(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|preset| preset.name.eq_ignore_ascii_case(name))
+            .map(LlamaPreset::source)
+    }
+}
+
+#[test]
+fn preset_names_are_unique() {
+    let mut names: Vec<_> = PRESETS.iter().map(|preset| preset.name).collect();
+    names.sort_unstable();
+    names.dedup();
+    assert_eq!(names.len(), PRESETS.len());
+    assert!(LlamaSource::from_name("Qwen2.5-7B-Instruct").is_some());
+    assert!(LlamaSource::from_name("SmolLM2-135M-Instruct").is_some());
+    assert!(LlamaSource::from_name("not-a-model").is_none());
+}
@@ -0,0 +1,474 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use candle_core::Device;
+use kalosm_model_types::{FileSource, ModelLoadingProgress};
+
+use crate::model::{log_softmax, LlamaModel};
+use crate::raw::cache::LlamaCache;
+use crate::{LlamaBuilder, LlamaSource, LlamaSourceError};
+
+/// Text used to measure the perplexity of each quantization level.
+const CALIBRATION_TEXT: &str = include_str!("../assets/calibration.txt");
+
+/// The maximum number of tokens of the calibration text to evaluate.
+const CALIBRATION_TOKENS: usize = 256;
+
+/// The quantization levels that are compared when picking a quantization automatically.
+const CALIBRATION_CANDIDATES: [GgufQuantization; 5] = [
+    GgufQuantization::Q4_0,
+    GgufQuantization::Q4_K_M,
+    GgufQuantization::Q5_K_M,
+    GgufQuantization::Q6_K,
+    GgufQuantization::Q8_0,
+];
+
+/// The level used when the memory of the device is unknown. Benchmarking every candidate without knowing what fits
+/// could try to load levels that exhaust the memory of the device.
+const FALLBACK_QUANTIZATION: GgufQuantization = GgufQuantization::Q4_K_M;
+
+/// Levels with a perplexity within this fraction of the best level are considered good enough.
+const PERPLEXITY_TOLERANCE: f64 = 0.05;
+
+/// The name of the file in the cache directory that records the recommended quantization for each model and device.
+const RECOMMENDATIONS_FILE: &str = "quantization-recommendations.txt";
+
+/// A quantization level of a gguf model file. Lower levels use less memory and run faster, higher levels are more
+/// accurate.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GgufQuantization {
+    /// 2 bit k-quantization
+    Q2_K,
+    /// 3 bit medium k-quantization
+    Q3_K_M,
+    /// 4 bit quantization
+    Q4_0,
+    /// 4 bit medium k-quantization
+    Q4_K_M,
+    /// 5 bit medium k-quantization
+    Q5_K_M,
+    /// 6 bit k-quantization
+    Q6_K,
+    /// 8 bit quantization
+    Q8_0,
+    /// Unquantized 16 bit floats
+    F16,
+}
+
+impl GgufQuantization {
+    /// All quantization levels from the smallest to the largest.
+    pub const ALL: [GgufQuantization; 8] = [
+        GgufQuantization::Q2_K,
+        GgufQuantization::Q3_K_M,
+        GgufQuantization::Q4_0,
+        GgufQuantization::Q4_K_M,
+        GgufQuantization::Q5_K_M,
+        GgufQuantization::Q6_K,
+        GgufQuantization::Q8_0,
+        GgufQuantization::F16,
+    ];
+
+    /// Get the name of the quantization level as it appears in gguf file names.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GgufQuantization::Q2_K => "Q2_K",
+            GgufQuantization::Q3_K_M => "Q3_K_M",
+            GgufQuantization::Q4_0 => "Q4_0",
+            GgufQuantization::Q4_K_M => "Q4_K_M",
+            GgufQuantization::Q5_K_M => "Q5_K_M",
+            GgufQuantization::Q6_K => "Q6_K",
+            GgufQuantization::Q8_0 => "Q8_0",
+            GgufQuantization::F16 => "F16",
+        }
+    }
+
+    /// Get the approximate number of bits used to store each weight.
+    pub fn bits_per_weight(&self) -> f64 {
+        match self {
+            GgufQuantization::Q2_K => 2.6,
+            GgufQuantization::Q3_K_M => 3.9,
+            GgufQuantization::Q4_0 => 4.5,
+            GgufQuantization::Q4_K_M => 4.85,
+            GgufQuantization::Q5_K_M => 5.7,
+            GgufQuantization::Q6_K => 6.6,
+            GgufQuantization::Q8_0 => 8.5,
+            GgufQuantization::F16 => 16.0,
+        }
+    }
+
+    /// Find the quantization level in a gguf file name. Returns the byte range of the level in the name and the level.
+    pub fn find_in_file_name(file: &str) -> Option<(std::ops::Range<usize>, Self)> {
+        let lowercase = file.to_ascii_lowercase();
+        Self::ALL
+            .iter()
+            .filter_map(|quantization| {
+                let tag = quantization.as_str().to_ascii_lowercase();
+                let start = lowercase.rfind(&tag)?;
+                // The level must be a separate part of the file name
+                let separated =
+                    |byte: Option<u8>| byte.is_none_or(|byte| matches!(byte, b'-' | b'.' | b'_'));
+                let end = start + tag.len();
+                let before = start.checked_sub(1).map(|i| lowercase.as_bytes()[i]);
+                let after = lowercase.as_bytes().get(end).copied();
+                (separated(before) && separated(after)).then_some((start..end, *quantization))
+            })
+            .max_by_key(|(range, _)| range.len())
+    }
+
+    /// Replace the quantization level in a gguf file name with this level. The case of the file name is kept.
+    pub fn rewrite_file_name(&self, file: &str) -> Option<String> {
+        let (range, _) = Self::find_in_file_name(file)?;
+        let tag = if file[range.clone()].chars().any(|c| c.is_ascii_uppercase()) {
+            self.as_str().to_string()
+        } else {
+            self.as_str().to_ascii_lowercase()
+        };
+        Some(format!(
+            "{}{}{}",
+            &file[..range.start],
+            tag,
+            &file[range.end..]
+        ))
+    }
+}
+
+impl std::fmt::Display for GgufQuantization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for GgufQuantization {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|quantization| quantization.as_str().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+/// The quantization to load a [`LlamaSource`] with. Set it with [`LlamaSource::with_quantization`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    /// Load a specific quantization level.
+    Level(GgufQuantization),
+    /// Pick the quantization level for this machine. The first time a preset is loaded with automatic quantization
+    /// on a device, every level the model repository provides that fits in the memory of the device is downloaded
+    /// and benchmarked on a short sample text. The fastest level with a perplexity close to the best level is
+    /// recorded in the cache and used for every later load. The files of the other levels that were downloaded for
+    /// the benchmark are removed from the cache afterwards. If the memory of the device can't be read, Q4_K_M is
+    /// used without benchmarking.
+    Auto,
+}
+
+impl From<GgufQuantization> for Quantization {
+    fn from(quantization: GgufQuantization) -> Self {
+        Self::Level(quantization)
+    }
+}
+
+/// The result of benchmarking one quantization level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct QuantizationBenchmark {
+    quantization: GgufQuantization,
+    tokens_per_second: f64,
+    perplexity: f64,
+}
+
+/// The key a recommendation is recorded under for a model file and device.
+fn recommendation_key(source: &FileSource, device: &Device) -> Option<String> {
+    let FileSource::HuggingFace {
+        model_id,
+        revision,
+        file,
+    } = source
+    else {
+        return None;
+    };
+    let (range, _) = GgufQuantization::find_in_file_name(file)?;
+    let template = format!("{}{{quant}}{}", &file[..range.start], &file[range.end..]);
+    Some(format!(
+        "{model_id}/{revision}/{template} {:?}",
+        device.location()
+    ))
+}
+
+async fn read_recommendation(source: &LlamaSource, key: &str) -> Option<GgufQuantization> {
+    let recommendations =
+        tokio::fs::read_to_string(source.cache.location().join(RECOMMENDATIONS_FILE))
+            .await
+            .ok()?;
+    recommendations.lines().find_map(|line| {
+        let (line_key, quantization) = line.rsplit_once('\t')?;
+        (line_key == key).then(|| quantization.parse().ok())?
+    })
+}
+
+async fn write_recommendation(
+    source: &LlamaSource,
+    key: &str,
+    quantization: GgufQuantization,
+) -> std::io::Result<()> {
+    let path = source.cache.location().join(RECOMMENDATIONS_FILE);
+    let existing = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+    let mut recommendations: Vec<_> = existing
+        .lines()
+        .filter(|line| line.rsplit_once('\t').map(|(line_key, _)| line_key) != Some(key))
+        .map(|line| line.to_string())
+        .collect();
+    recommendations.push(format!("{key}\t{quantization}"));
+    tokio::fs::create_dir_all(source.cache.location()).await?;
+    tokio::fs::write(path, recommendations.join("\n") + "\n").await
+}
+
+/// Get the amount of memory available to load a model on the device in bytes if it is known.
+fn available_memory(device: &Device) -> Option<u64> {
+    match device {
+        Device::Cpu => {
+            if !sysinfo::IS_SUPPORTED_SYSTEM {
+                return None;
+            }
+            let mut system = sysinfo::System::new();
+            system.refresh_memory();
+            Some(system.available_memory()).filter(|&bytes| bytes > 0)
+        }
+        #[cfg(feature = "cuda")]
+        Device::Cuda(cuda) => {
+            use candle_core::cuda_backend::cudarc::driver::result::mem_get_info;
+            // The free memory is read from the context that is bound to the current thread
+            cuda.cuda_device().bind_to_thread().ok()?;
+            let (free, _) = mem_get_info().ok()?;
+            Some(free as u64)
+        }
+        #[cfg(feature = "metal")]
+        Device::Metal(metal) => {
+            let device = metal.device();
+            Some(
+                device
+                    .recommended_max_working_set_size()
+                    .saturating_sub(device.current_allocated_size()),
+            )
+        }
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Get the candidate levels that fit in memory. The level of the preset is always included.
+fn candidates_that_fit(
+    parameters: f64,
+    memory: u64,
+    preset_quantization: GgufQuantization,
+) -> Vec<GgufQuantization> {
+    CALIBRATION_CANDIDATES
+        .into_iter()
+        .filter(|&quantization| {
+            let estimated_size = parameters * quantization.bits_per_weight() / 8.0;
+            // Leave some headroom for the kv cache and activations
+            estimated_size * 1.2 <= memory as f64 || quantization == preset_quantization
+        })
+        .collect()
+}
+
+/// Replace an automatic quantization with the recommended level for the device, benchmarking the available levels
+/// if no recommendation has been recorded yet.
+pub(crate) async fn resolve_auto_quantization(
+    builder: &LlamaBuilder,
+    handler: Arc<Mutex<dyn FnMut(ModelLoadingProgress) + Send + Sync>>,
+) -> Result<LlamaSource, LlamaSourceError> {
+    let source = &builder.source;
+    let device = builder.get_device()?;
+    let Some(key) = recommendation_key(&source.model, &device) else {
+        tracing::warn!(
+            "Automatic quantization requires a Hugging Face gguf file with a quantization level in the name. Using {}",
+            source.model
+        );
+        return Ok(source.clone());
+    };
+    if let Some(quantization) = read_recommendation(source, &key).await {
+        tracing::info!("Using the recorded quantization {quantization} for {key}");
+        return Ok(source.clone().with_quantization(quantization));
+    }
+
+    let Some(memory) = available_memory(&device) else {
+        tracing::warn!(
+            "The available memory on {:?} is unknown. Using {FALLBACK_QUANTIZATION} for {key}",
+            device.location()
+        );
+        return Ok(source.clone().with_quantization(FALLBACK_QUANTIZATION));
+    };
+
+    // Download the preset file first to estimate the number of parameters in the model
+    let FileSource::HuggingFace { file, .. } = &source.model else {
+        unreachable!("only hugging face sources have a recommendation key")
+    };
+    let (_, preset_quantization) =
+        GgufQuantization::find_in_file_name(file).expect("the key requires a quantization level");
+    // Files that are only downloaded for the benchmark are removed again once a level is picked
+    let mut downloaded = Vec::new();
+    if !source.cache.exists(&source.model) {
+        downloaded.push((preset_quantization, source.model.clone()));
+    }
+    let preset_path = source
+        .model({
+            let handler = handler.clone();
+            let mut create_progress =
+                ModelLoadingProgress::downloading_progress(format!("Model ({})", source.model));
+            move |progress| (*handler.lock().unwrap())(create_progress(progress))
+        })
+        .await?;
+    let preset_size = tokio::fs::metadata(&preset_path)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0) as f64;
+    let parameters = preset_size * 8.0 / preset_quantization.bits_per_weight();
+
+    let mut benchmarks = Vec::new();
+    for quantization in candidates_that_fit(parameters, memory, preset_quantization) {
+        let candidate = LlamaBuilder {
+            source: source.clone().with_quantization(quantization),
+            device: Some(device.clone()),
+            device_preference: Vec::new(),
+            ..builder.clone()
+        };
+        if quantization != preset_quantization && !source.cache.exists(&candidate.source.model) {
+            downloaded.push((quantization, candidate.source.model.clone()));
+        }
+        let model = match LlamaModel::from_builder(candidate, {
+            let handler = handler.clone();
+            move |progress| (*handler.lock().unwrap())(progress)
+        })
+        .await
+        {
+            Ok(model) => model,
+            // The repository may not provide every level
+            Err(
+                err @ (LlamaSourceError::Model(_)
+                | LlamaSourceError::QuantizationUnavailable { .. }),
+            ) => {
+                tracing::info!("Skipping quantization {quantization}: {err}");
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let benchmark = tokio::task::spawn_blocking(move || benchmark(&model, quantization))
+            .await
+            .map_err(|_| LlamaSourceError::ModelLoadingPanic)??;
+        tracing::info!(
+            "Quantization {quantization}: {:.1} tokens/s, perplexity {:.2}",
+            benchmark.tokens_per_second,
+            benchmark.perplexity
+        );
+        benchmarks.push(benchmark);
+    }
+
+    let quantization = recommend(&benchmarks).unwrap_or(preset_quantization);
+    if let Err(err) = write_recommendation(source, &key, quantization).await {
+        tracing::warn!("Failed to record the quantization recommendation: {err}");
+    }
+    for (rejected, file) in downloaded {
+        if rejected == quantization {
+            continue;
+        }
+        if let Err(err) = source.cache.remove(&file).await {
+            tracing::warn!("Failed to remove the benchmark download {file}: {err}");
+        }
+    }
+    Ok(source.clone().with_quantization(quantization))
+}
+
+/// Measure the decoding speed and perplexity of a model on the calibration text.
+fn benchmark(
+    model: &LlamaModel,
+    quantization: GgufQuantization,
+) -> Result<QuantizationBenchmark, LlamaSourceError> {
+    let tokens = model
+        .tokenizer
+        .encode(CALIBRATION_TEXT, true)
+        .map_err(LlamaSourceError::Tokenizer)?;
+    let tokens = &tokens.get_ids()[..tokens.get_ids().len().min(CALIBRATION_TOKENS)];
+    let mut cache = LlamaCache::new(&model.model.config);
+    let mut logits = Vec::new();
+    let mut negative_log_likelihood = 0.0;
+    let start = Instant::now();
+    for window in tokens.windows(2) {
+        LlamaModel::forward(
+            &model.model,
+            &model.device,
+            &window[..1],
+            Some(&mut cache),
+            &mut logits,
+        )?;
+        negative_log_likelihood -= log_softmax(&logits, window[1] as usize) as f64;
+    }
+    let evaluated = tokens.len().saturating_sub(1).max(1) as f64;
+    Ok(QuantizationBenchmark {
+        quantization,
+        tokens_per_second: evaluated / start.elapsed().as_secs_f64(),
+        perplexity: (negative_log_likelihood / evaluated).exp(),
+    })
+}
+
+/// Pick the fastest level with a perplexity close to the most accurate level.
+fn recommend(benchmarks: &[QuantizationBenchmark]) -> Option<GgufQuantization> {
+    let best_perplexity = benchmarks
+        .iter()
+        .map(|benchmark| benchmark.perplexity)
+        .filter(|perplexity| perplexity.is_finite())
+        .fold(f64::INFINITY, f64::min);
+    benchmarks
+        .iter()
+        .filter(|benchmark| benchmark.perplexity <= best_perplexity * (1.0 + PERPLEXITY_TOLERANCE))
+        .max_by(|a, b| a.tokens_per_second.total_cmp(&b.tokens_per_second))
+        .map(|benchmark| benchmark.quantization)
+}
+
+#[test]
+fn rewrite_quantization_in_file_names() {
+    assert_eq!(
+        GgufQuantization::Q8_0
+            .rewrite_file_name("Meta-Llama-3.1-8B-Instruct-Q4_K_M.gguf")
+            .as_deref(),
+        Some("Meta-Llama-3.1-8B-Instruct-Q8_0.gguf")
+    );
+    assert_eq!(
+        GgufQuantization::Q6_K
+            .rewrite_file_name("qwen2.5-7b-instruct-q4_k_m.gguf")
+            .as_deref(),
+        Some("qwen2.5-7b-instruct-q6_k.gguf")
+    );
+    assert_eq!(GgufQuantization::Q6_K.rewrite_file_name("model.gguf"), None);
+
+    let benchmark = |quantization, tokens_per_second, perplexity| QuantizationBenchmark {
+        quantization,
+        tokens_per_second,
+        perplexity,
+    };
+    let benchmarks = [
+        benchmark(GgufQuantization::Q4_0, 40.0, 7.5),
+        benchmark(GgufQuantization::Q4_K_M, 35.0, 6.2),
+        benchmark(GgufQuantization::Q8_0, 20.0, 6.0),
+    ];
+    assert_eq!(recommend(&benchmarks), Some(GgufQuantization::Q4_K_M));
+}
+
+#[test]
+fn only_candidates_that_fit_in_memory_are_benchmarked() {
+    let parameters = 8e9;
+    assert_eq!(
+        candidates_that_fit(parameters, 6_000_000_000, GgufQuantization::Q4_K_M),
+        [GgufQuantization::Q4_0, GgufQuantization::Q4_K_M]
+    );
+    // The preset level is kept even if it doesn't look like it fits
+    assert_eq!(
+        candidates_that_fit(parameters, 4_000_000_000, GgufQuantization::Q4_K_M),
+        [GgufQuantization::Q4_K_M]
+    );
+    assert_eq!(
+        candidates_that_fit(parameters, 16_000_000_000, GgufQuantization::Q4_K_M),
+        CALIBRATION_CANDIDATES
+    );
+}
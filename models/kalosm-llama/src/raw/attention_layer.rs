@@ -1,14 +1,15 @@
+use super::norm::RmsNorm;
 use super::rope::RopeCache;
 use super::silu::fast_cpu_silu;
 use candle_core::{quantized::QMatMul, Module, Tensor};
 use candle_core::{Device, D};
-use candle_transformers::quantized_nn::RmsNorm;
 use kalosm_common::AttentionMask;
-use kalosm_common::KvCache;
+use kalosm_common::PagedKvCache;
 
 pub enum FeedForwardVariant {
     Llama(LlamaFeedForward),
     Phi(PhiFeedForward),
+    MixtureOfExperts(MoeFeedForward),
 }
 
 impl FeedForwardVariant {
@@ -16,10 +17,60 @@ impl FeedForwardVariant {
         match self {
             FeedForwardVariant::Llama(ffn) => ffn.forward(x),
             FeedForwardVariant::Phi(ffn) => ffn.forward(x),
+            FeedForwardVariant::MixtureOfExperts(ffn) => ffn.forward(x),
         }
     }
 }
 
+/// A mixture of experts feed forward layer like Mixtral. Each token is routed to the `experts_per_token` experts
+/// with the highest router scores and the outputs are weighted by the normalized scores.
+pub struct MoeFeedForward {
+    pub router: QMatMul,
+    pub experts: Vec<LlamaFeedForward>,
+    pub experts_per_token: usize,
+}
+
+impl MoeFeedForward {
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        let (batch, seq_len, hidden_size) = x.dims3()?;
+        let x = x.reshape((batch * seq_len, hidden_size))?;
+        let router_logits = self.router.forward(&x)?;
+        let routing_weights = candle_nn::ops::softmax_last_dim(&router_logits)?
+            .to_dtype(candle_core::DType::F32)?
+            .to_vec2::<f32>()?;
+
+        // Group the tokens by the experts they are routed to
+        let mut expert_tokens = vec![Vec::new(); self.experts.len()];
+        let mut expert_weights = vec![Vec::new(); self.experts.len()];
+        for (token, weights) in routing_weights.iter().enumerate() {
+            let mut experts: Vec<usize> = (0..weights.len()).collect();
+            experts.sort_by(|&a, &b| weights[b].total_cmp(&weights[a]));
+            let selected = &experts[..self.experts_per_token.min(experts.len())];
+            let total: f32 = selected.iter().map(|&expert| weights[expert]).sum();
+            for &expert in selected {
+                expert_tokens[expert].push(token as u32);
+                expert_weights[expert].push(weights[expert] / total);
+            }
+        }
+
+        let mut output = x.zeros_like()?;
+        for (expert, ffn) in self.experts.iter().enumerate() {
+            let tokens = &expert_tokens[expert];
+            if tokens.is_empty() {
+                continue;
+            }
+            let tokens = Tensor::new(tokens.as_slice(), x.device())?;
+            let expert_output = ffn.forward(&x.index_select(&tokens, 0)?)?;
+            let weights = Tensor::new(expert_weights[expert].as_slice(), x.device())?
+                .reshape(((), 1))?
+                .to_dtype(expert_output.dtype())?;
+            let expert_output = expert_output.broadcast_mul(&weights)?;
+            output = output.index_add(&tokens, &expert_output, 0)?;
+        }
+        output.reshape((batch, seq_len, hidden_size))
+    }
+}
+
 pub struct PhiFeedForward {
     pub up: QMatMul,
     pub down: QMatMul,
@@ -41,10 +92,28 @@ impl PhiFeedForward {
     }
 }
 
+/// The activation applied to the gate of a [`LlamaFeedForward`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedForwardActivation {
+    Silu,
+    /// Gemma models use the tanh approximation of gelu
+    Gelu,
+}
+
+impl FeedForwardActivation {
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        match self {
+            FeedForwardActivation::Silu => fast_cpu_silu(x),
+            FeedForwardActivation::Gelu => x.gelu(),
+        }
+    }
+}
+
 pub struct LlamaFeedForward {
     pub feed_forward_w1: QMatMul,
     pub feed_forward_w2: QMatMul,
     pub feed_forward_w3: QMatMul,
+    pub activation: FeedForwardActivation,
 }
 
 impl LlamaFeedForward {
@@ -54,7 +123,7 @@ impl LlamaFeedForward {
             std::thread::scope(|scope| {
                 let w1 = scope.spawn(|| {
                     let w1 = self.feed_forward_w1.forward(x)?;
-                    fast_cpu_silu(&w1)
+                    self.activation.forward(&w1)
                 });
 
                 let w3 = self.feed_forward_w3.forward(x)?;
@@ -66,7 +135,7 @@ impl LlamaFeedForward {
             })
         } else {
             let w1 = self.feed_forward_w1.forward(x)?;
-            let w1 = fast_cpu_silu(&w1)?;
+            let w1 = self.activation.forward(&w1)?;
 
             let w3 = self.feed_forward_w3.forward(x)?;
 
@@ -91,6 +160,8 @@ pub struct SeparateAttention {
     pub attention_wk: QMatMul,
     pub attention_wv: QMatMul,
     pub bias: Option<AttentionBias>,
+    pub q_norm: Option<RmsNorm>,
+    pub k_norm: Option<RmsNorm>,
     pub interleaved_rope: bool,
 }
 
@@ -116,6 +187,9 @@ impl SeparateAttention {
                     if let Some(bias) = &self.bias {
                         query_states = query_states.broadcast_add(&bias.bias_q)?;
                     }
+                    if let Some(norm) = &self.q_norm {
+                        query_states = norm.forward(&query_states)?;
+                    }
 
                     query_states
                         .reshape((b_sz, seq_len, num_heads, head_dim))?
@@ -127,6 +201,9 @@ impl SeparateAttention {
                     if let Some(bias) = &self.bias {
                         key_states = key_states.broadcast_add(&bias.bias_k)?;
                     }
+                    if let Some(norm) = &self.k_norm {
+                        key_states = norm.forward(&key_states)?;
+                    }
 
                     key_states
                         .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
@@ -164,39 +241,8 @@ impl SeparateAttention {
                 Ok((query_states, key_states, value_states))
             })
         } else {
-            let query_states = {
-                let mut query_states = self.attention_wq.forward(hidden_states)?;
-
-                if let Some(bias) = &self.bias {
-                    query_states = query_states.broadcast_add(&bias.bias_q)?;
-                }
-
-                query_states
-                    .reshape((b_sz, seq_len, num_heads, head_dim))?
-                    .transpose(1, 2)?
-            };
-            let key_states = {
-                let mut key_states = self.attention_wk.forward(hidden_states)?;
-
-                if let Some(bias) = &self.bias {
-                    key_states = key_states.broadcast_add(&bias.bias_k)?;
-                }
-
-                key_states
-                    .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
-                    .transpose(1, 2)?
-            };
-            let value_states = {
-                let mut value_states = self.attention_wv.forward(hidden_states)?;
-
-                if let Some(bias) = &self.bias {
-                    value_states = value_states.broadcast_add(&bias.bias_v)?;
-                }
-
-                value_states
-                    .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
-                    .transpose(1, 2)?
-            };
+            let (query_states, key_states, value_states) =
+                self.project(num_heads, head_dim, num_key_value_heads, hidden_states)?;
 
             let (query_states, key_states) = if self.interleaved_rope {
                 rope_cache.forward_i(&query_states, &key_states, start_pos)?
@@ -207,6 +253,59 @@ impl SeparateAttention {
             Ok((query_states, key_states, value_states))
         }
     }
+
+    /// Project the hidden states into the query, key and value states without the position embeddings.
+    fn project(
+        &self,
+        num_heads: usize,
+        head_dim: usize,
+        num_key_value_heads: usize,
+        hidden_states: &Tensor,
+    ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        let b_sz = hidden_states.dims()[0];
+        let seq_len = hidden_states.dims()[1];
+        let query_states = {
+            let mut query_states = self.attention_wq.forward(hidden_states)?;
+
+            if let Some(bias) = &self.bias {
+                query_states = query_states.broadcast_add(&bias.bias_q)?;
+            }
+            if let Some(norm) = &self.q_norm {
+                query_states = norm.forward(&query_states)?;
+            }
+
+            query_states
+                .reshape((b_sz, seq_len, num_heads, head_dim))?
+                .transpose(1, 2)?
+        };
+        let key_states = {
+            let mut key_states = self.attention_wk.forward(hidden_states)?;
+
+            if let Some(bias) = &self.bias {
+                key_states = key_states.broadcast_add(&bias.bias_k)?;
+            }
+            if let Some(norm) = &self.k_norm {
+                key_states = norm.forward(&key_states)?;
+            }
+
+            key_states
+                .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
+                .transpose(1, 2)?
+        };
+        let value_states = {
+            let mut value_states = self.attention_wv.forward(hidden_states)?;
+
+            if let Some(bias) = &self.bias {
+                value_states = value_states.broadcast_add(&bias.bias_v)?;
+            }
+
+            value_states
+                .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
+                .transpose(1, 2)?
+        };
+
+        Ok((query_states, key_states, value_states))
+    }
 }
 
 pub struct GroupedAttention {
@@ -222,6 +321,23 @@ impl GroupedAttention {
         x: &Tensor,
         rope_cache: &RopeCache,
         start_pos: usize,
+    ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
+        let (query_states, key_states, value_states) =
+            self.project(num_heads, head_dim, num_key_value_heads, x)?;
+
+        let (query_states, key_states) =
+            rope_cache.forward(&query_states, &key_states, start_pos)?;
+
+        Ok((query_states, key_states, value_states))
+    }
+
+    /// Project the hidden states into the query, key and value states without the position embeddings.
+    fn project(
+        &self,
+        num_heads: usize,
+        head_dim: usize,
+        num_key_value_heads: usize,
+        x: &Tensor,
     ) -> candle_core::Result<(Tensor, Tensor, Tensor)> {
         let b_sz = x.dims()[0];
         let seq_len = x.dims()[1];
@@ -246,9 +362,6 @@ impl GroupedAttention {
             .reshape((b_sz, seq_len, num_key_value_heads, head_dim))?
             .transpose(1, 2)?;
 
-        let (query_states, key_states) =
-            rope_cache.forward(&query_states, &key_states, start_pos)?;
-
         Ok((query_states, key_states, value_states))
     }
 }
@@ -256,14 +369,24 @@ impl GroupedAttention {
 pub struct LlamaAttention {
     pub attention_variant: AttentionVariant,
     pub attention_wo: QMatMul,
-    pub attention_norm: RmsNorm,
+    pub attention_norm: Option<RmsNorm>,
+    pub post_attention_norm: Option<RmsNorm>,
     pub feed_forward_variant: FeedForwardVariant,
-    pub ffn_norm: RmsNorm,
+    pub ffn_norm: Option<RmsNorm>,
+    pub post_ffn_norm: Option<RmsNorm>,
+    pub attention_scale: Option<f64>,
+    /// Gemma 2 caps the attention logits with `tanh(logits / cap) * cap`
+    pub attention_logit_cap: Option<f64>,
+    /// Only attend to this many of the most recent tokens
+    pub sliding_window: Option<usize>,
     pub n_head: usize,
     pub n_kv_head: usize,
     pub head_dim: usize,
     pub hidden_size: usize,
     pub rope_cache: RopeCache,
+    pub device: Device,
+    /// Use the flash attention kernel on CUDA devices
+    pub flash_attn: bool,
 }
 
 impl LlamaAttention {
@@ -272,11 +395,11 @@ impl LlamaAttention {
         hidden_states: &Tensor,
         attention_mask: Option<&AttentionMask>,
         start_pos: usize,
-        cache: Option<&mut KvCache>,
+        cache: Option<&mut PagedKvCache>,
+        attention_weights_out: Option<&mut Option<Tensor>>,
     ) -> candle_core::Result<Tensor> {
         let bsz = hidden_states.dims()[0];
         let q_len = hidden_states.dims()[1];
-        let hidden_size = self.hidden_size;
         let num_heads = self.n_head;
         let head_dim = self.head_dim;
         let num_key_value_heads = self.n_kv_head;
@@ -304,30 +427,131 @@ impl LlamaAttention {
         let key_states = repeat_kv(key_states.clone(), num_key_value_groups)?;
         let value_states = repeat_kv(value_states, num_key_value_groups)?;
 
-        let (key_states, value_states) = match cache {
+        let scale = self
+            .attention_scale
+            .unwrap_or_else(|| 1. / (head_dim as f64).sqrt());
+
+        let (mut key_states, mut value_states) = match cache {
             None => (key_states, value_states),
+            // A single query reads the blocks of the cache in place instead of copying them into one tensor
+            Some(cache) if q_len == 1 && attention_weights_out.is_none() => {
+                cache.push(&key_states, &value_states)?;
+                let attn_output = cache.attend(
+                    &query_states,
+                    scale,
+                    self.attention_logit_cap,
+                    self.sliding_window,
+                )?;
+                return self.project_output(attn_output, bsz, q_len);
+            }
             Some(cache) => cache.append(&key_states, &value_states)?,
         };
 
-        let scale = 1. / (head_dim as f64).sqrt();
+        // Layers with a sliding window only attend to the most recent tokens
+        let mut attention_mask = attention_mask.cloned();
+        let mut sliding_window_mask = None;
+        if let Some(window) = self.sliding_window {
+            let kv_len = key_states.dim(2)?;
+            if kv_len > window {
+                if q_len == 1 {
+                    // A single query can drop the old keys instead of masking them
+                    key_states = key_states.narrow(2, kv_len - window, window)?;
+                    value_states = value_states.narrow(2, kv_len - window, window)?;
+                    attention_mask = None;
+                } else {
+                    sliding_window_mask = Some(create_sliding_window_mask(
+                        q_len,
+                        kv_len,
+                        window,
+                        hidden_states.device(),
+                    )?);
+                }
+            }
+        }
 
-        let mut attn_output = if query_states.device().is_metal() && q_len == 1 {
+        // The fused kernels can only apply a causal mask that aligns the queries with the last keys
+        let kv_len = key_states.dim(2)?;
+        let causal = match &attention_mask {
+            Some(mask) => mask.causal && mask.mask.dim(D::Minus1)? == kv_len,
+            None => false,
+        };
+        let kernel_supports_mask = attention_mask.is_none() || causal;
+
+        // The fused kernels don't expose the attention weights, so skip them when they are captured
+        let fused = query_states.device().is_metal() && attention_weights_out.is_none();
+        let fused_head_dim = matches!(head_dim, 32 | 64 | 96 | 128 | 256);
+        let flash = self.flash_attn
+            && query_states.device().is_cuda()
+            && attention_weights_out.is_none()
+            && self.attention_logit_cap.is_none()
+            && sliding_window_mask.is_none()
+            && kernel_supports_mask
+            && head_dim.is_multiple_of(8)
+            && head_dim <= 256;
+        let attn_output = if flash {
+            flash_attention(
+                &query_states,
+                &key_states,
+                &value_states,
+                scale as f32,
+                attention_mask.is_some(),
+            )?
+        } else if fused && q_len == 1 {
             // SDPA use fuzed softmax(qk^T*scale)v kernel on metal
-            candle_nn::ops::sdpa(&query_states, &key_states, &value_states, scale as f32, 1.)
-                .unwrap()
+            candle_nn::ops::sdpa(
+                &query_states,
+                &key_states,
+                &value_states,
+                scale as f32,
+                self.attention_logit_cap.unwrap_or(1.) as f32,
+            )
+            .unwrap()
+        } else if fused && causal && fused_head_dim {
+            // The full SDPA kernel on metal can't mask future keys, so prefill runs the single query kernel on the
+            // keys each query can see
+            attend_each_query(
+                &query_states,
+                &key_states,
+                &value_states,
+                self.sliding_window,
+                |query_states, key_states, value_states| {
+                    candle_nn::ops::sdpa(
+                        &query_states.contiguous()?,
+                        &key_states.contiguous()?,
+                        &value_states.contiguous()?,
+                        scale as f32,
+                        self.attention_logit_cap.unwrap_or(1.) as f32,
+                    )
+                },
+            )?
         } else {
-            let mut attn_weights = (query_states.matmul(&key_states.t()?)? * scale)?;
-
-            if let Some(attention_mask) = attention_mask {
-                attention_mask.forward(&mut attn_weights)?;
+            let attn_weights = attention_weights(
+                &query_states,
+                &key_states,
+                scale,
+                self.attention_logit_cap,
+                attention_mask.as_ref(),
+                sliding_window_mask.as_ref(),
+            )?;
+            if let Some(out) = attention_weights_out {
+                *out = Some(attn_weights.clone());
             }
 
-            attn_weights = candle_nn::ops::softmax_last_dim(&attn_weights)?;
-
             attn_weights.matmul(&value_states)?
         };
 
-        if attn_output.dims() != [bsz, num_heads, q_len, head_dim] {
+        self.project_output(attn_output, bsz, q_len)
+    }
+
+    /// Merge the heads of the attention output and run the output projection.
+    fn project_output(
+        &self,
+        attn_output: Tensor,
+        bsz: usize,
+        q_len: usize,
+    ) -> candle_core::Result<Tensor> {
+        let head_dim = self.head_dim;
+        if attn_output.dims() != [bsz, self.n_head, q_len, head_dim] {
             return Err(candle_core::Error::Msg(format!(
                 "`attn_output` should be of size {:?}, but is {:?}",
                 [bsz, self.n_head, q_len, head_dim],
@@ -335,17 +559,212 @@ impl LlamaAttention {
             )));
         }
 
-        attn_output = attn_output.transpose(1, 2).unwrap();
+        let attn_output = attn_output.transpose(1, 2)?;
+        let attn_output = attn_output.reshape(&[bsz, q_len, self.hidden_size])?;
 
-        attn_output = attn_output.reshape(&[bsz, q_len, hidden_size]).unwrap();
+        self.attention_wo.forward(&attn_output)
+    }
+}
 
-        attn_output = self.attention_wo.forward(&attn_output).unwrap();
+impl LlamaAttention {
+    /// Remove `len` tokens starting at `start` from the cache. The keys after the removed tokens are rotated back so
+    /// their positions stay contiguous.
+    pub(crate) fn evict_from_cache(
+        &self,
+        cache: &mut PagedKvCache,
+        start: usize,
+        len: usize,
+    ) -> candle_core::Result<()> {
+        let (Some(k), Some(v)) = (cache.k()?, cache.v()?) else {
+            return Ok(());
+        };
+        let cached = k.dim(2)?;
+        let end = (start + len).min(cached);
+        let mut keys = Vec::with_capacity(2);
+        let mut values = Vec::with_capacity(2);
+        if start > 0 {
+            keys.push(k.narrow(2, 0, start)?);
+            values.push(v.narrow(2, 0, start)?);
+        }
+        if end < cached {
+            let interleaved = matches!(
+                self.attention_variant,
+                AttentionVariant::Separate(ref attention) if attention.interleaved_rope
+            );
+            let tail = k.narrow(2, end, cached - end)?;
+            keys.push(
+                self.rope_cache
+                    .shift_keys_back(&tail, end - start, interleaved)?,
+            );
+            values.push(v.narrow(2, end, cached - end)?);
+        }
+        cache.reset();
+        if !keys.is_empty() {
+            cache.append(&Tensor::cat(&keys, 2)?, &Tensor::cat(&values, 2)?)?;
+        }
+        Ok(())
+    }
 
-        Ok(attn_output)
+    /// Run attention for a batch of sequences that each add one token. Every sequence has its own cache and
+    /// position, so the projections run once for the whole batch and the attention itself runs per sequence.
+    pub(crate) fn forward_batch(
+        &self,
+        hidden_states: &Tensor,
+        start_positions: &[usize],
+        caches: &mut [&mut PagedKvCache],
+    ) -> candle_core::Result<Tensor> {
+        let bsz = hidden_states.dims()[0];
+        let num_heads = self.n_head;
+        let head_dim = self.head_dim;
+        let num_key_value_heads = self.n_kv_head;
+        let num_key_value_groups = num_heads / num_key_value_heads;
+
+        let (query_states, key_states, value_states) = match self.attention_variant {
+            AttentionVariant::Separate(ref attention) => {
+                attention.project(num_heads, head_dim, num_key_value_heads, hidden_states)?
+            }
+            AttentionVariant::Grouped(ref attention) => {
+                attention.project(num_heads, head_dim, num_key_value_heads, hidden_states)?
+            }
+        };
+
+        let scale = self
+            .attention_scale
+            .unwrap_or_else(|| 1. / (head_dim as f64).sqrt());
+
+        let mut outputs = Vec::with_capacity(bsz);
+        for (i, (cache, &start_pos)) in caches.iter_mut().zip(start_positions).enumerate() {
+            let query_states = query_states.narrow(0, i, 1)?;
+            let key_states = key_states.narrow(0, i, 1)?;
+            let value_states = value_states.narrow(0, i, 1)?.contiguous()?;
+            let (query_states, key_states) = match self.attention_variant {
+                AttentionVariant::Separate(ref attention) if attention.interleaved_rope => self
+                    .rope_cache
+                    .forward_i(&query_states, &key_states, start_pos)?,
+                _ => self
+                    .rope_cache
+                    .forward(&query_states, &key_states, start_pos)?,
+            };
+
+            let key_states = repeat_kv(key_states, num_key_value_groups)?;
+            let value_states = repeat_kv(value_states, num_key_value_groups)?;
+            cache.push(&key_states, &value_states)?;
+            outputs.push(cache.attend(
+                &query_states,
+                scale,
+                self.attention_logit_cap,
+                self.sliding_window,
+            )?);
+        }
+
+        let attn_output =
+            Tensor::cat(&outputs, 0)?
+                .transpose(1, 2)?
+                .reshape((bsz, 1, self.hidden_size))?;
+
+        self.attention_wo.forward(&attn_output)
+    }
+}
+
+/// Compute the attention weights `softmax(qk^T*scale)` of the queries for each key. This is the reference attention
+/// the fused kernels are checked against.
+fn attention_weights(
+    query_states: &Tensor,
+    key_states: &Tensor,
+    scale: f64,
+    attention_logit_cap: Option<f64>,
+    attention_mask: Option<&AttentionMask>,
+    sliding_window_mask: Option<&Tensor>,
+) -> candle_core::Result<Tensor> {
+    let mut attn_weights = (query_states.matmul(&key_states.t()?)? * scale)?;
+
+    if let Some(cap) = attention_logit_cap {
+        attn_weights = ((attn_weights / cap)?.tanh()? * cap)?;
+    }
+
+    if let Some(attention_mask) = attention_mask {
+        attention_mask.forward(&mut attn_weights)?;
+    }
+
+    if let Some(mask) = sliding_window_mask {
+        let shape = attn_weights.shape();
+        let on_true = Tensor::new(f32::NEG_INFINITY, attn_weights.device())?.broadcast_as(shape)?;
+        attn_weights = mask
+            .broadcast_as(shape)?
+            .where_cond(&on_true, &attn_weights)?;
     }
+
+    candle_nn::ops::softmax_last_dim(&attn_weights)
 }
 
-fn repeat_kv(x: Tensor, num_key_value_groups: usize) -> candle_core::Result<Tensor> {
+/// Run causal attention with a kernel that attends a single query. Each query only reads the keys up to its own
+/// position and inside the sliding window, so the full attention matrix is never created. When there are more keys
+/// than queries, the queries are aligned with the last keys.
+fn attend_each_query(
+    query_states: &Tensor,
+    key_states: &Tensor,
+    value_states: &Tensor,
+    sliding_window: Option<usize>,
+    attend: impl Fn(&Tensor, &Tensor, &Tensor) -> candle_core::Result<Tensor>,
+) -> candle_core::Result<Tensor> {
+    let q_len = query_states.dim(2)?;
+    let kv_len = key_states.dim(2)?;
+    let outputs = (0..q_len)
+        .map(|i| {
+            let end = kv_len - q_len + i + 1;
+            let start = sliding_window.map_or(0, |window| end.saturating_sub(window));
+            attend(
+                &query_states.narrow(2, i, 1)?,
+                &key_states.narrow(2, start, end - start)?,
+                &value_states.narrow(2, start, end - start)?,
+            )
+        })
+        .collect::<candle_core::Result<Vec<_>>>()?;
+    Tensor::cat(&outputs, 2)
+}
+
+/// Run the flash attention kernel. The inputs and output have the shape `(batch, heads, seq_len, head_dim)`. If
+/// `causal` is set, each query only attends to the keys up to its position, with the queries aligned with the last
+/// keys when there are more keys than queries.
+#[cfg(feature = "flash-attn")]
+fn flash_attention(
+    query_states: &Tensor,
+    key_states: &Tensor,
+    value_states: &Tensor,
+    scale: f32,
+    causal: bool,
+) -> candle_core::Result<Tensor> {
+    use candle_core::DType;
+
+    let dtype = query_states.dtype();
+    // The kernel only supports half precision
+    let kernel_dtype = match dtype {
+        DType::BF16 => DType::BF16,
+        _ => DType::F16,
+    };
+    let prepare = |tensor: &Tensor| tensor.transpose(1, 2)?.to_dtype(kernel_dtype)?.contiguous();
+    let output = candle_flash_attn::flash_attn(
+        &prepare(query_states)?,
+        &prepare(key_states)?,
+        &prepare(value_states)?,
+        scale,
+        causal,
+    )?;
+    output.transpose(1, 2)?.to_dtype(dtype)
+}
+
+#[cfg(not(feature = "flash-attn"))]
+fn flash_attention(
+    _query_states: &Tensor,
+    _key_states: &Tensor,
+    _value_states: &Tensor,
+    _scale: f32,
+    _causal: bool,
+) -> candle_core::Result<Tensor> {
+    candle_core::bail!("kalosm-llama was built without the flash-attn feature")
+}
+
+pub(super) fn repeat_kv(x: Tensor, num_key_value_groups: usize) -> candle_core::Result<Tensor> {
     if num_key_value_groups == 1 {
         Ok(x)
     } else {
@@ -358,3 +777,73 @@ fn repeat_kv(x: Tensor, num_key_value_groups: usize) -> candle_core::Result<Tens
         ))
     }
 }
+
+/// Create a mask that hides keys more than `window` tokens before each query. The queries are the last `q_len` of
+/// the `kv_len` tokens.
+fn create_sliding_window_mask(
+    q_len: usize,
+    kv_len: usize,
+    window: usize,
+    device: &Device,
+) -> candle_core::Result<Tensor> {
+    let offset = kv_len - q_len;
+    let mask: Vec<_> = (0..q_len)
+        .flat_map(|i| (0..kv_len).map(move |j| u8::from(j + window <= i + offset)))
+        .collect();
+    Tensor::from_slice(&mask, (q_len, kv_len), device)
+}
+
+#[test]
+fn sliding_window_masks_old_tokens() {
+    let mask = create_sliding_window_mask(2, 4, 2, &Device::Cpu).unwrap();
+    assert_eq!(mask.to_vec2::<u8>().unwrap(), [[1, 0, 0, 0], [1, 1, 0, 0]]);
+}
+
+#[test]
+fn attending_each_query_matches_the_reference_attention() {
+    let device = Device::Cpu;
+    let (q_len, kv_len, head_dim) = (3, 5, 4);
+    let query_states = Tensor::randn(0f32, 1., (1, 2, q_len, head_dim), &device).unwrap();
+    let key_states = Tensor::randn(0f32, 1., (1, 2, kv_len, head_dim), &device).unwrap();
+    let value_states = Tensor::randn(0f32, 1., (1, 2, kv_len, head_dim), &device).unwrap();
+    let scale = 1. / (head_dim as f64).sqrt();
+    let mask = kalosm_common::MaskCache::default()
+        .get_mask(q_len, kv_len - q_len, &device)
+        .unwrap();
+
+    for window in [None, Some(2)] {
+        let sliding_window_mask = window
+            .map(|window| create_sliding_window_mask(q_len, kv_len, window, &device).unwrap());
+        let expected = attention_weights(
+            &query_states,
+            &key_states,
+            scale,
+            None,
+            Some(&mask),
+            sliding_window_mask.as_ref(),
+        )
+        .unwrap()
+        .matmul(&value_states)
+        .unwrap();
+        let actual = attend_each_query(
+            &query_states,
+            &key_states,
+            &value_states,
+            window,
+            |query_states, key_states, value_states| {
+                attention_weights(query_states, key_states, scale, None, None, None)?
+                    .matmul(value_states)
+            },
+        )
+        .unwrap();
+        let difference = (expected - actual)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(difference < 1e-5, "{window:?}: {difference}");
+    }
+}
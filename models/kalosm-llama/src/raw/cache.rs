@@ -1,19 +1,33 @@
 use candle_core::{Device, Tensor};
-use candle_nn::kv_cache::Cache;
-use kalosm_common::KvCache;
+use kalosm_common::PagedKvCache;
+use kalosm_language_model::RecordedSampling;
 use std::collections::HashMap;
 
 use super::LlamaConfig;
+use crate::capture::ActivationRecorder;
+use crate::{AttentionSinks, Degradation};
 
 /// The dimension along which the attention cache is concatenated with attention for new tokens.
 const CONCAT_DIMENSION: usize = 2;
 
 /// A cache for llama inference. This cache will speed up generation of sequential text significantly.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct LlamaCache {
     max_seq_len: usize,
     pub(crate) tokens: Vec<u32>,
-    pub(crate) blocks: Vec<KvCache>,
+    pub(crate) blocks: Vec<PagedKvCache>,
+    /// The ways the last generation was degraded to recover from running out of memory
+    pub(crate) degradations: Vec<Degradation>,
+    /// Records activations while capturing is enabled for the session
+    pub(crate) activations: Option<ActivationRecorder>,
+    /// The id of the model that filled the cache, if it has been used
+    pub(crate) model_id: Option<u64>,
+    /// Drop tokens after the attention sinks instead of re-processing the end of the context when it is full
+    pub(crate) attention_sinks: Option<AttentionSinks>,
+    /// The fingerprint of the model that filled the cache, if it has been used
+    pub(crate) model_fingerprint: Option<u64>,
+    /// The sampler of the last generation in the session
+    pub(crate) sampler: Option<RecordedSampling>,
 }
 
 impl LlamaCache {
@@ -22,15 +36,61 @@ impl LlamaCache {
         let max_seq_len = config.context_length;
         let mut blocks = Vec::with_capacity(config.n_layer);
         for _ in 0..config.n_layer {
-            blocks.push(KvCache::new(CONCAT_DIMENSION, max_seq_len))
+            blocks.push(
+                PagedKvCache::new(CONCAT_DIMENSION, max_seq_len)
+                    .with_quantization(config.kv_cache_quantization),
+            )
         }
         Self {
             max_seq_len,
             tokens: Vec::new(),
             blocks,
+            degradations: Vec::new(),
+            activations: None,
+            model_id: None,
+            attention_sinks: None,
+            model_fingerprint: None,
+            sampler: None,
         }
     }
 
+    /// Add a key value cache for every layer of the model that doesn't have one yet. Caches for sessions that were
+    /// created before the model loaded start without any layers.
+    pub(crate) fn ensure_layers(&mut self, config: &LlamaConfig) {
+        if self.blocks.len() < config.n_layer {
+            if self.blocks.is_empty() {
+                self.max_seq_len = config.context_length;
+            }
+            let max_seq_len = self.max_seq_len;
+            self.blocks.resize(
+                config.n_layer,
+                PagedKvCache::new(CONCAT_DIMENSION, max_seq_len)
+                    .with_quantization(config.kv_cache_quantization),
+            );
+        }
+    }
+
+    /// Drop every token after the first `len` tokens from the cache.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.tokens.truncate(len);
+        for block in &mut self.blocks {
+            block.truncate(len);
+        }
+    }
+
+    /// Move the key value cache of each layer to the device the layer runs on.
+    pub(crate) fn move_to_devices(&mut self, devices: &[Device]) -> candle_core::Result<()> {
+        for (block, device) in self.blocks.iter_mut().zip(devices) {
+            let mut moved = PagedKvCache::new(CONCAT_DIMENSION, self.max_seq_len)
+                .with_quantization(block.quantization());
+            if let (Some(k), Some(v)) = (block.k()?, block.v()?) {
+                moved.push(&k.to_device(device)?, &v.to_device(device)?)?;
+            }
+            *block = moved;
+        }
+        Ok(())
+    }
+
     /// Clear the cache.
     pub fn clear(&mut self) {
         for block in &mut self.blocks {
@@ -42,7 +102,7 @@ impl LlamaCache {
     pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
         let mut map = HashMap::with_capacity(self.blocks.len());
         for (i, kv_cache) in self.blocks.iter().enumerate() {
-            if let (Ok(Some(k)), Ok(Some(v))) = (kv_cache.cache().k(), kv_cache.cache().v()) {
+            if let (Ok(Some(k)), Ok(Some(v))) = (kv_cache.k(), kv_cache.v()) {
                 map.insert(
                     format!("llama.cache.blocks.{}.key", i),
                     k.to_device(device).unwrap(),
@@ -77,57 +137,41 @@ impl LlamaCache {
             .get("llama.cache.max_seq_len")
             .and_then(|max_seq_len| max_seq_len.to_scalar::<u32>().ok())
             .unwrap_or(2048) as usize;
-        let mut blocks = Vec::with_capacity(24);
+        let mut layers: Vec<(Option<Tensor>, Option<Tensor>)> = Vec::with_capacity(24);
         for (k, v) in map {
             if let Some(i) = k.strip_prefix("llama.cache.blocks.") {
                 let i = i
                     .strip_suffix(".key")
                     .unwrap_or_else(|| i.strip_suffix(".value").unwrap());
                 let i = i.parse::<usize>().unwrap_or(0);
-                if i >= blocks.len() {
-                    blocks.resize(i + 1, KvCache::new(CONCAT_DIMENSION, max_seq_len));
+                if i >= layers.len() {
+                    layers.resize(i + 1, (None, None));
                 }
                 if k.ends_with(".key") {
-                    match blocks.get_mut(i) {
-                        Some(cache) => {
-                            let key_cache = cache.cache_mut().k_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *key_cache = Cache::new(CONCAT_DIMENSION, len);
-                            key_cache.append(&v)?;
-                        }
-                        _ => {
-                            let mut cache = KvCache::new(CONCAT_DIMENSION, max_seq_len);
-                            let key_cache = cache.cache_mut().k_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *key_cache = Cache::new(CONCAT_DIMENSION, len);
-                            key_cache.append(&v)?;
-                            blocks[i] = cache;
-                        }
-                    }
+                    layers[i].0 = Some(v);
                 } else if k.ends_with(".value") {
-                    match blocks.get_mut(i) {
-                        Some(cache) => {
-                            let value_cache = cache.cache_mut().v_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *value_cache = Cache::new(CONCAT_DIMENSION, len);
-                            value_cache.append(&v)?;
-                        }
-                        _ => {
-                            let mut cache = KvCache::new(CONCAT_DIMENSION, max_seq_len);
-                            let value_cache = cache.cache_mut().v_cache_mut();
-                            let len = v.dim(CONCAT_DIMENSION)?;
-                            *value_cache = Cache::new(CONCAT_DIMENSION, len);
-                            value_cache.append(&v)?;
-                            blocks[i] = cache;
-                        }
-                    }
+                    layers[i].1 = Some(v);
                 }
             }
         }
+        let mut blocks = Vec::with_capacity(layers.len());
+        for layer in layers {
+            let mut cache = PagedKvCache::new(CONCAT_DIMENSION, max_seq_len);
+            if let (Some(k), Some(v)) = layer {
+                cache.push(&k, &v)?;
+            }
+            blocks.push(cache);
+        }
         Ok(Self {
             tokens,
             blocks,
             max_seq_len,
+            degradations: Vec::new(),
+            activations: None,
+            model_id: None,
+            attention_sinks: None,
+            model_fingerprint: None,
+            sampler: None,
         })
     }
 }
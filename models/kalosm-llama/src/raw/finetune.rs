@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use candle_core::quantized::{gguf_file, GgmlDType, QMatMul, QTensor};
+use candle_core::{DType, Device, Module, Result, Tensor, Var, D};
+
+use super::attention_layer::{
+    repeat_kv, AttentionVariant, FeedForwardActivation, FeedForwardVariant, LlamaAttention,
+};
+use super::norm::RmsNorm;
+use super::{LoraTarget, Model};
+
+/// LoRA weights that are trained on top of the frozen, possibly quantized weights of a [`Model`].
+pub(crate) struct TrainableLora {
+    weights: HashMap<(usize, LoraTarget), (Var, Var)>,
+    rank: usize,
+    alpha: f32,
+}
+
+impl TrainableLora {
+    /// Create LoRA weights for the targets in every layer of the model. The `a` weights start random and the `b`
+    /// weights start at zero, so the adapter doesn't change the output of the model before it is trained.
+    pub(crate) fn new(
+        model: &Model,
+        targets: &[LoraTarget],
+        rank: usize,
+        alpha: f32,
+    ) -> Result<Self> {
+        let mut weights = HashMap::new();
+        for (index, layer) in model.layers.iter().enumerate() {
+            for &target in targets {
+                let (rows, columns) = weight_shape(frozen_weight(layer, target)?)?;
+                let a = Var::randn(
+                    0f32,
+                    (1. / columns as f64).sqrt() as f32,
+                    (rank, columns),
+                    &layer.device,
+                )?;
+                let b = Var::zeros((rows, rank), DType::F32, &layer.device)?;
+                weights.insert((index, target), (a, b));
+            }
+        }
+        Ok(Self {
+            weights,
+            rank,
+            alpha,
+        })
+    }
+
+    /// Get the weights the optimizer should update.
+    pub(crate) fn vars(&self) -> Vec<Var> {
+        self.weights
+            .values()
+            .flat_map(|(a, b)| [a.clone(), b.clone()])
+            .collect()
+    }
+
+    /// Save the weights as a gguf LoRA adapter that can be loaded with [`crate::LlamaSource::with_lora`].
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let mut tensors = Vec::new();
+        for ((layer, target), (a, b)) in &self.weights {
+            for (half, weight) in [("lora_a", a), ("lora_b", b)] {
+                let name = format!("blk.{layer}.{}.weight.{half}", target.gguf_name());
+                let weight = weight.as_tensor().to_device(&Device::Cpu)?;
+                tensors.push((name, QTensor::quantize(&weight, GgmlDType::F32)?));
+            }
+        }
+        tensors.sort_by(|(first, _), (second, _)| first.cmp(second));
+        let tensors: Vec<_> = tensors
+            .iter()
+            .map(|(name, tensor)| (name.as_str(), tensor))
+            .collect();
+
+        let metadata = [
+            (
+                "general.type",
+                gguf_file::Value::String("adapter".to_string()),
+            ),
+            ("adapter.type", gguf_file::Value::String("lora".to_string())),
+            ("adapter.lora.alpha", gguf_file::Value::F32(self.alpha)),
+        ];
+        let metadata: Vec<_> = metadata.iter().map(|(key, value)| (*key, value)).collect();
+
+        let mut file = std::fs::File::create(path)?;
+        gguf_file::write(&mut file, &metadata, &tensors)
+    }
+
+    /// Run a frozen weight with the LoRA weights for it added.
+    fn linear(
+        &self,
+        layer: usize,
+        target: LoraTarget,
+        weight: &QMatMul,
+        x: &Tensor,
+    ) -> Result<Tensor> {
+        let frozen = frozen_linear(weight, x)?;
+        let Some((a, b)) = self.weights.get(&(layer, target)) else {
+            return Ok(frozen);
+        };
+        let scale = self.alpha as f64 / self.rank as f64;
+        let delta = x.broadcast_matmul(&a.t()?)?.broadcast_matmul(&b.t()?)?;
+        frozen + (delta * scale)?
+    }
+
+    fn attention(&self, index: usize, layer: &LlamaAttention, x: &Tensor) -> Result<Tensor> {
+        let AttentionVariant::Separate(attention) = &layer.attention_variant else {
+            candle_core::bail!("Fine-tuning is not supported for fused attention weights");
+        };
+        let (b_sz, seq_len, _) = x.dims3()?;
+
+        let mut query_states = self.linear(index, LoraTarget::Query, &attention.attention_wq, x)?;
+        let mut key_states = self.linear(index, LoraTarget::Key, &attention.attention_wk, x)?;
+        let mut value_states = self.linear(index, LoraTarget::Value, &attention.attention_wv, x)?;
+        if let Some(bias) = &attention.bias {
+            query_states = query_states.broadcast_add(&bias.bias_q)?;
+            key_states = key_states.broadcast_add(&bias.bias_k)?;
+            value_states = value_states.broadcast_add(&bias.bias_v)?;
+        }
+        if let Some(norm) = &attention.q_norm {
+            query_states = norm.forward_differentiable(&query_states)?;
+        }
+        if let Some(norm) = &attention.k_norm {
+            key_states = norm.forward_differentiable(&key_states)?;
+        }
+
+        let query_states = query_states
+            .reshape((b_sz, seq_len, layer.n_head, layer.head_dim))?
+            .transpose(1, 2)?;
+        let key_states = key_states
+            .reshape((b_sz, seq_len, layer.n_kv_head, layer.head_dim))?
+            .transpose(1, 2)?;
+        let value_states = value_states
+            .reshape((b_sz, seq_len, layer.n_kv_head, layer.head_dim))?
+            .transpose(1, 2)?
+            .contiguous()?;
+        let (query_states, key_states) = layer.rope_cache.forward_differentiable(
+            &query_states,
+            &key_states,
+            0,
+            attention.interleaved_rope,
+        )?;
+
+        let num_key_value_groups = layer.n_head / layer.n_kv_head;
+        let key_states = repeat_kv(key_states, num_key_value_groups)?;
+        let value_states = repeat_kv(value_states, num_key_value_groups)?;
+
+        let scale = layer
+            .attention_scale
+            .unwrap_or_else(|| 1. / (layer.head_dim as f64).sqrt());
+        let mut attn_weights = (query_states.matmul(&key_states.t()?)? * scale)?;
+        if let Some(cap) = layer.attention_logit_cap {
+            attn_weights = ((attn_weights / cap)?.tanh()? * cap)?;
+        }
+        let shape = attn_weights.shape();
+        let mask = causal_mask(seq_len, layer.sliding_window, x.device())?.broadcast_as(shape)?;
+        let on_true = Tensor::new(f32::NEG_INFINITY, x.device())?.broadcast_as(shape)?;
+        let attn_weights = mask.where_cond(&on_true, &attn_weights)?;
+        let attn_weights = candle_nn::ops::softmax(&attn_weights, D::Minus1)?;
+
+        let attn_output = attn_weights
+            .matmul(&value_states)?
+            .transpose(1, 2)?
+            .reshape((b_sz, seq_len, layer.hidden_size))?;
+        self.linear(index, LoraTarget::Output, &layer.attention_wo, &attn_output)
+    }
+
+    fn feed_forward(&self, index: usize, layer: &LlamaAttention, x: &Tensor) -> Result<Tensor> {
+        let FeedForwardVariant::Llama(feed_forward) = &layer.feed_forward_variant else {
+            candle_core::bail!("Fine-tuning is only supported for gated feed forward layers");
+        };
+        let gate = self.linear(index, LoraTarget::Gate, &feed_forward.feed_forward_w1, x)?;
+        let gate = match feed_forward.activation {
+            FeedForwardActivation::Silu => gate.silu()?,
+            FeedForwardActivation::Gelu => gate.gelu()?,
+        };
+        let up = self.linear(index, LoraTarget::Up, &feed_forward.feed_forward_w3, x)?;
+        self.linear(
+            index,
+            LoraTarget::Down,
+            &feed_forward.feed_forward_w2,
+            &(gate * up)?,
+        )
+    }
+}
+
+/// Get the frozen weight a LoRA target changes in a layer.
+fn frozen_weight(layer: &LlamaAttention, target: LoraTarget) -> Result<&QMatMul> {
+    let AttentionVariant::Separate(attention) = &layer.attention_variant else {
+        candle_core::bail!("Fine-tuning is not supported for fused attention weights");
+    };
+    let FeedForwardVariant::Llama(feed_forward) = &layer.feed_forward_variant else {
+        candle_core::bail!("Fine-tuning is only supported for gated feed forward layers");
+    };
+    Ok(match target {
+        LoraTarget::Query => &attention.attention_wq,
+        LoraTarget::Key => &attention.attention_wk,
+        LoraTarget::Value => &attention.attention_wv,
+        LoraTarget::Output => &layer.attention_wo,
+        LoraTarget::Gate => &feed_forward.feed_forward_w1,
+        LoraTarget::Up => &feed_forward.feed_forward_w3,
+        LoraTarget::Down => &feed_forward.feed_forward_w2,
+    })
+}
+
+/// Get the number of output and input features of a weight.
+fn weight_shape(weight: &QMatMul) -> Result<(usize, usize)> {
+    match weight {
+        QMatMul::QTensor(tensor) => tensor.shape().dims2(),
+        QMatMul::Tensor(tensor) | QMatMul::TensorF16(tensor) => tensor.dims2(),
+    }
+}
+
+/// Run a frozen weight with operations that support backpropagation into the input. Quantized weights are
+/// dequantized for each call so the model stays quantized in memory.
+fn frozen_linear(weight: &QMatMul, x: &Tensor) -> Result<Tensor> {
+    let weight = match weight {
+        QMatMul::QTensor(tensor) => tensor.dequantize(x.device())?,
+        QMatMul::Tensor(tensor) => tensor.clone(),
+        QMatMul::TensorF16(tensor) => tensor.to_dtype(DType::F32)?,
+    };
+    x.broadcast_matmul(&weight.t()?)
+}
+
+fn optional_norm(norm: &Option<RmsNorm>, x: Tensor) -> Result<Tensor> {
+    match norm {
+        Some(norm) => norm.forward_differentiable(&x),
+        None => Ok(x),
+    }
+}
+
+/// Create a mask that hides future tokens and tokens outside of the sliding window.
+fn causal_mask(seq_len: usize, sliding_window: Option<usize>, device: &Device) -> Result<Tensor> {
+    let mask: Vec<_> = (0..seq_len)
+        .flat_map(|i| {
+            (0..seq_len).map(move |j| {
+                let outside_window = sliding_window.is_some_and(|window| j + window <= i);
+                u8::from(j > i || outside_window)
+            })
+        })
+        .collect();
+    Tensor::from_slice(&mask, (seq_len, seq_len), device)
+}
+
+impl Model {
+    /// Run the model on a sequence with LoRA weights added to the frozen weights. Unlike [`Model::forward`], this
+    /// doesn't use a cache, returns the logits for every position and supports backpropagation into the LoRA
+    /// weights.
+    pub(crate) fn forward_lora(
+        &self,
+        tokens: &[u32],
+        lora: &TrainableLora,
+        device: &Device,
+    ) -> Result<Tensor> {
+        let x = Tensor::new(tokens, device)?.unsqueeze(0)?;
+        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        if let Some(scale) = self.config.embedding_scale {
+            layer_in = (layer_in * scale)?;
+        }
+
+        for (index, layer) in self.layers.iter().enumerate() {
+            let residual = layer_in.to_device(&layer.device)?;
+            let x = optional_norm(&layer.attention_norm, residual.clone())?;
+            let mut attn = lora.attention(index, layer, &x)?;
+            attn = optional_norm(&layer.post_attention_norm, attn)?;
+            if let Some(scale) = self.config.residual_scale {
+                attn = (attn * scale)?;
+            }
+            let residual = (attn + residual)?;
+
+            let x = optional_norm(&layer.ffn_norm, residual.clone())?;
+            let mut mlp = lora.feed_forward(index, layer, &x)?;
+            mlp = optional_norm(&layer.post_ffn_norm, mlp)?;
+            if let Some(scale) = self.config.residual_scale {
+                mlp = (mlp * scale)?;
+            }
+            layer_in = (mlp + residual)?;
+        }
+
+        let x = self
+            .norm
+            .forward_differentiable(&layer_in.to_device(&self.output_device)?)?
+            .squeeze(0)?;
+        self.scale_logits(frozen_linear(&self.output, &x)?)
+    }
+}
+
+#[test]
+fn causal_mask_hides_future_and_old_tokens() {
+    let mask = causal_mask(3, Some(2), &Device::Cpu).unwrap();
+    assert_eq!(
+        mask.to_vec2::<u8>().unwrap(),
+        [[0, 1, 1], [0, 0, 1], [1, 0, 0]]
+    );
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use candle_core::quantized::{gguf_file, QMatMul, QTensor};
+use candle_core::{DType, Device, Tensor};
+use serde::Deserialize;
+
+use super::attention_layer::{AttentionVariant, FeedForwardVariant};
+use super::Model;
+use crate::shards::ModelFile;
+use crate::LlamaSourceError;
+
+/// A weight a LoRA adapter can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum LoraTarget {
+    Query,
+    Key,
+    Value,
+    Output,
+    Gate,
+    Up,
+    Down,
+}
+
+impl LoraTarget {
+    /// Parse the name of a projection in a Hugging Face PEFT adapter.
+    pub(crate) fn from_peft(name: &str) -> Option<Self> {
+        match name {
+            "q_proj" => Some(Self::Query),
+            "k_proj" => Some(Self::Key),
+            "v_proj" => Some(Self::Value),
+            "o_proj" => Some(Self::Output),
+            "gate_proj" => Some(Self::Gate),
+            "up_proj" => Some(Self::Up),
+            "down_proj" => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    /// Parse the name of a tensor in a gguf adapter.
+    fn from_gguf(name: &str) -> Option<Self> {
+        match name {
+            "attn_q" => Some(Self::Query),
+            "attn_k" => Some(Self::Key),
+            "attn_v" => Some(Self::Value),
+            "attn_output" => Some(Self::Output),
+            "ffn_gate" => Some(Self::Gate),
+            "ffn_up" => Some(Self::Up),
+            "ffn_down" => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    /// Get the name of the tensor in a gguf adapter.
+    pub(crate) fn gguf_name(&self) -> &'static str {
+        match self {
+            Self::Query => "attn_q",
+            Self::Key => "attn_k",
+            Self::Value => "attn_v",
+            Self::Output => "attn_output",
+            Self::Gate => "ffn_gate",
+            Self::Up => "ffn_up",
+            Self::Down => "ffn_down",
+        }
+    }
+}
+
+#[derive(Default)]
+struct LoraPair {
+    a: Option<Tensor>,
+    b: Option<Tensor>,
+}
+
+/// The `adapter_config.json` of a Hugging Face PEFT adapter.
+#[derive(Deserialize)]
+pub(crate) struct LoraConfig {
+    r: Option<usize>,
+    lora_alpha: Option<f32>,
+}
+
+/// A LoRA adapter that can be merged into the weights of a [`Model`].
+pub(crate) struct LoraAdapter {
+    weights: HashMap<(usize, LoraTarget), LoraPair>,
+    alpha: Option<f32>,
+    /// PEFT adapters are trained on the Hugging Face layout of the query and key weights. Gguf files permute those
+    /// weights for interleaved rope, so the adapter needs to be permuted the same way before it is merged.
+    hugging_face_layout: bool,
+}
+
+impl LoraAdapter {
+    /// Load a LoRA adapter from a PEFT safetensors file or a llama.cpp gguf adapter.
+    pub(crate) fn load(
+        model_file: &ModelFile,
+        config: Option<LoraConfig>,
+    ) -> Result<Self, LlamaSourceError> {
+        let cpu = Device::Cpu;
+        let mut weights: HashMap<(usize, LoraTarget), LoraPair> = HashMap::new();
+        let is_gguf = model_file.extension() == Some("gguf");
+        let alpha = if is_gguf {
+            let mut file = model_file.open().map_err(candle_core::Error::from)?;
+            let content = gguf_file::Content::read(&mut file)?;
+            // Tensors are named like `blk.0.attn_q.weight.lora_a`
+            for name in content.tensor_infos.keys() {
+                let mut parts = name.split('.');
+                let (Some("blk"), Some(layer), Some(target), Some("weight"), Some(half)) = (
+                    parts.next(),
+                    parts.next(),
+                    parts.next(),
+                    parts.next(),
+                    parts.next(),
+                ) else {
+                    continue;
+                };
+                let (Ok(layer), Some(target)) = (layer.parse(), LoraTarget::from_gguf(target))
+                else {
+                    continue;
+                };
+                let tensor = content.tensor(&mut file, name, &cpu)?.dequantize(&cpu)?;
+                let pair = weights.entry((layer, target)).or_default();
+                match half {
+                    "lora_a" => pair.a = Some(tensor),
+                    "lora_b" => pair.b = Some(tensor),
+                    _ => {}
+                }
+            }
+            content
+                .metadata
+                .get("adapter.lora.alpha")
+                .and_then(|alpha| alpha.to_f32().ok())
+        } else {
+            // Tensors are named like `base_model.model.model.layers.0.self_attn.q_proj.lora_A.weight`
+            let tensors = match model_file {
+                ModelFile::Path(path) => candle_core::safetensors::load(path, &cpu)?,
+                ModelFile::Bytes { bytes, .. } => {
+                    candle_core::safetensors::load_buffer(bytes, &cpu)?
+                }
+            };
+            for (name, tensor) in tensors {
+                let Some((_, rest)) = name.split_once("layers.") else {
+                    continue;
+                };
+                let parts: Vec<_> = rest.split('.').collect();
+                let [layer, _, target, half, "weight"] = parts.as_slice() else {
+                    continue;
+                };
+                let (Ok(layer), Some(target)) = (layer.parse(), LoraTarget::from_peft(target))
+                else {
+                    continue;
+                };
+                let pair = weights.entry((layer, target)).or_default();
+                match *half {
+                    "lora_A" => pair.a = Some(tensor.to_dtype(DType::F32)?),
+                    "lora_B" => pair.b = Some(tensor.to_dtype(DType::F32)?),
+                    _ => {}
+                }
+            }
+            config.and_then(|config| config.lora_alpha.or(config.r.map(|r| r as f32)))
+        };
+
+        if weights.is_empty() {
+            return Err(LlamaSourceError::InvalidLora(format!(
+                "no LoRA weights were found in {model_file}"
+            )));
+        }
+
+        Ok(Self {
+            weights,
+            alpha,
+            hugging_face_layout: !is_gguf,
+        })
+    }
+
+    /// Get the change to a weight from the adapter.
+    fn delta(&self, layer: usize, target: LoraTarget) -> candle_core::Result<Option<Tensor>> {
+        let Some(LoraPair {
+            a: Some(a),
+            b: Some(b),
+        }) = self.weights.get(&(layer, target))
+        else {
+            return Ok(None);
+        };
+        let rank = a.dim(0)?;
+        let scale = self.alpha.unwrap_or(rank as f32) / rank as f32;
+        Ok(Some((b.matmul(a)? * scale as f64)?))
+    }
+}
+
+/// Permute the rows of a query or key weight from the Hugging Face layout to the interleaved rope layout used in
+/// gguf files.
+fn permute_for_interleaved_rope(delta: &Tensor, heads: usize) -> candle_core::Result<Tensor> {
+    let (rows, columns) = delta.dims2()?;
+    delta
+        .reshape((heads, 2, rows / heads / 2, columns))?
+        .transpose(1, 2)?
+        .reshape((rows, columns))
+}
+
+/// Add a change to a weight. Quantized weights are dequantized, merged and quantized again with the same format.
+fn merge(weight: &mut QMatMul, delta: &Tensor) -> candle_core::Result<()> {
+    *weight = match &*weight {
+        QMatMul::QTensor(tensor) => {
+            let device = tensor.device();
+            let merged = (tensor.dequantize(&device)? + delta.to_device(&device)?)?;
+            QMatMul::from_qtensor(QTensor::quantize(&merged, tensor.dtype())?)?
+        }
+        QMatMul::Tensor(tensor) => {
+            let delta = delta.to_device(tensor.device())?.to_dtype(tensor.dtype())?;
+            QMatMul::Tensor((tensor + delta)?)
+        }
+        QMatMul::TensorF16(tensor) => {
+            let delta = delta.to_device(tensor.device())?.to_dtype(tensor.dtype())?;
+            QMatMul::TensorF16((tensor + delta)?)
+        }
+    };
+    Ok(())
+}
+
+impl Model {
+    /// Merge a LoRA adapter into the weights of the model. Adapters that change the feed forward weights of a mixture
+    /// of experts model are not supported.
+    pub(crate) fn apply_lora(&mut self, adapter: &LoraAdapter) -> Result<(), LlamaSourceError> {
+        let mut merged = 0;
+        for (index, layer) in self.layers.iter_mut().enumerate() {
+            let mut targets: Vec<(LoraTarget, &mut QMatMul, Option<usize>)> = Vec::new();
+            match &mut layer.attention_variant {
+                AttentionVariant::Separate(attention) => {
+                    let permute = attention.interleaved_rope && adapter.hugging_face_layout;
+                    targets.push((
+                        LoraTarget::Query,
+                        &mut attention.attention_wq,
+                        permute.then_some(layer.n_head),
+                    ));
+                    targets.push((
+                        LoraTarget::Key,
+                        &mut attention.attention_wk,
+                        permute.then_some(layer.n_kv_head),
+                    ));
+                    targets.push((LoraTarget::Value, &mut attention.attention_wv, None));
+                }
+                AttentionVariant::Grouped(_) => {
+                    tracing::warn!("LoRA adapters are not supported for fused attention weights");
+                }
+            }
+            targets.push((LoraTarget::Output, &mut layer.attention_wo, None));
+            match &mut layer.feed_forward_variant {
+                FeedForwardVariant::Llama(feed_forward) => {
+                    targets.push((LoraTarget::Gate, &mut feed_forward.feed_forward_w1, None));
+                    targets.push((LoraTarget::Down, &mut feed_forward.feed_forward_w2, None));
+                    targets.push((LoraTarget::Up, &mut feed_forward.feed_forward_w3, None));
+                }
+                FeedForwardVariant::Phi(feed_forward) => {
+                    targets.push((LoraTarget::Down, &mut feed_forward.down, None));
+                }
+                FeedForwardVariant::MixtureOfExperts(_) => {
+                    // The adapter has one weight per layer, but each expert has its own feed forward weights
+                    for target in [LoraTarget::Gate, LoraTarget::Up, LoraTarget::Down] {
+                        if adapter.delta(index, target)?.is_some() {
+                            return Err(LlamaSourceError::InvalidLora(format!(
+                                "the adapter changes the {} weights of layer {index}, but LoRA adapters for the experts of mixture of experts models are not supported",
+                                target.gguf_name()
+                            )));
+                        }
+                    }
+                }
+            }
+
+            for (target, weight, permute_heads) in targets {
+                let Some(mut delta) = adapter.delta(index, target)? else {
+                    continue;
+                };
+                if let Some(heads) = permute_heads {
+                    delta = permute_for_interleaved_rope(&delta, heads)?;
+                }
+                merge(weight, &delta)?;
+                merged += 1;
+            }
+        }
+
+        let total = adapter
+            .weights
+            .values()
+            .filter(|pair| pair.a.is_some() && pair.b.is_some())
+            .count();
+        if merged < total {
+            tracing::warn!(
+                "Only {merged} of the {total} weights in the LoRA adapter match the model"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn interleaved_rope_permutation() {
+    // Two heads with a head dimension of four. The first half of each head is interleaved with the second half
+    let delta = Tensor::arange(0f32, 8., &Device::Cpu)
+        .unwrap()
+        .reshape((8, 1))
+        .unwrap();
+    let permuted = permute_for_interleaved_rope(&delta, 2).unwrap();
+    assert_eq!(
+        permuted.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        [0., 2., 1., 3., 4., 6., 5., 7.]
+    );
+}
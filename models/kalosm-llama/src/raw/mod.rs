@@ -2,13 +2,17 @@ use std::sync::Arc;
 
 use crate::chat_template::HuggingFaceChatTemplate;
 use crate::raw::attention_layer::LlamaAttention;
+use crate::raw::placement::LayerPlacement;
 use crate::raw::rope::RopeCache;
+use crate::rope_scaling::{ContextOptions, RopeScaling};
 use crate::LlamaSourceError;
 use attention_layer::AttentionBias;
 use attention_layer::AttentionVariant;
+use attention_layer::FeedForwardActivation;
 use attention_layer::FeedForwardVariant;
 use attention_layer::GroupedAttention;
 use attention_layer::LlamaFeedForward;
+use attention_layer::MoeFeedForward;
 use attention_layer::PhiFeedForward;
 use attention_layer::SeparateAttention;
 use candle_core::quantized::*;
@@ -16,15 +20,31 @@ use candle_core::IndexOp;
 use candle_core::Module;
 use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::Embedding;
-use candle_transformers::quantized_nn::RmsNorm;
 use kalosm_common::MaskCache;
+use norm::RmsNorm;
 
 mod attention_layer;
 pub mod cache;
+mod finetune;
+mod lora;
+mod norm;
+mod offload;
+mod placement;
 mod rope;
+mod safetensors;
 mod silu;
+mod vision;
 
 use cache::LlamaCache;
+pub(crate) use finetune::TrainableLora;
+pub(crate) use lora::{LoraAdapter, LoraConfig, LoraTarget};
+pub(crate) use safetensors::{SafetensorsConfig, SafetensorsTokenizerConfig};
+pub(crate) use vision::VisionEncoder;
+
+/// The logit for tokens added with [`Model::add_tokens`]. The output head has no trained rows for them, or only the
+/// untrained padding rows of the vocabulary, so they are masked to a very low logit and only generated when a
+/// constraint forces them.
+const ADDED_TOKEN_LOGIT: f32 = -1e4;
 
 fn decode_norm(tensor: QTensor, eps: f64) -> candle_core::Result<RmsNorm> {
     RmsNorm::from_qtensor(tensor, eps)
@@ -34,6 +54,8 @@ fn decode_norm(tensor: QTensor, eps: f64) -> candle_core::Result<RmsNorm> {
 pub struct LlamaConfig {
     rope_freq_weight: Option<Tensor>,
     rope_theta: f32,
+    rope_scaling: Option<RopeScaling>,
+    original_context_length: usize,
     pub(crate) context_length: usize,
     head_dimension: usize,
     n_head: usize,
@@ -42,6 +64,11 @@ pub struct LlamaConfig {
     pub(crate) stop_token: u32,
     pub(crate) stop_token_string: String,
     pub(crate) chat_template: Option<HuggingFaceChatTemplate>,
+    embedding_scale: Option<f64>,
+    residual_scale: Option<f64>,
+    logit_scale: Option<f64>,
+    final_logit_cap: Option<f64>,
+    pub(crate) kv_cache_quantization: kalosm_common::KvCacheQuantization,
 }
 
 impl LlamaConfig {
@@ -49,11 +76,26 @@ impl LlamaConfig {
         self.head_dimension * self.n_head
     }
 
+    /// Describe the parts of the config that change the values in the kv cache.
+    pub(crate) fn cache_layout(&self) -> String {
+        format!(
+            "layers={};heads={};head_dim={};rope_theta={};original_context={};rope_scaling={:?}",
+            self.n_layer,
+            self.n_head,
+            self.head_dimension,
+            self.rope_theta,
+            self.original_context_length,
+            self.rope_scaling
+        )
+    }
+
     #[cfg(test)]
     pub(crate) fn mock_test() -> Self {
         Self {
             rope_freq_weight: None,
             rope_theta: 5000.,
+            rope_scaling: None,
+            original_context_length: 6,
             context_length: 6,
             head_dimension: 2,
             n_head: 0,
@@ -62,6 +104,11 @@ impl LlamaConfig {
             stop_token: 0,
             stop_token_string: "<|endoftext|>".to_string(),
             chat_template: None,
+            embedding_scale: None,
+            residual_scale: None,
+            logit_scale: None,
+            final_logit_cap: None,
+            kv_cache_quantization: Default::default(),
         }
     }
 }
@@ -72,10 +119,29 @@ pub struct Model {
     layers: Vec<LlamaAttention>,
     norm: RmsNorm,
     output: QMatMul,
+    output_device: Device,
+    added_tokens: usize,
+    /// A mask with one column per output logit that is set for tokens added with [`Model::add_tokens`]
+    added_token_mask: Option<Tensor>,
     masks: MaskCache,
+    /// Masks for the layers that run on a different device than the embeddings
+    device_masks: Vec<(Device, MaskCache)>,
 }
 
 impl Model {
+    /// Set whether the attention layers use the flash attention kernel on CUDA devices.
+    pub(crate) fn set_flash_attn(&mut self, flash_attn: bool) {
+        for layer in &mut self.layers {
+            layer.flash_attn = flash_attn;
+        }
+    }
+
+    /// Get the number of query heads that share each key value head in the first layer.
+    pub(crate) fn group_query_attention(&self) -> Option<usize> {
+        let layer = self.layers.first()?;
+        Some(layer.n_head / layer.n_kv_head.max(1))
+    }
+
     pub fn from_ggml(
         mut ct: ggml_file::Content,
         gqa: usize,
@@ -89,6 +155,8 @@ impl Model {
         let config = LlamaConfig {
             rope_freq_weight: None,
             rope_theta: 10000.,
+            rope_scaling: None,
+            original_context_length: 4096,
             head_dimension: head_dim,
             n_head: ct.hparams.n_head as usize,
             n_layer,
@@ -97,6 +165,11 @@ impl Model {
             stop_token,
             stop_token_string,
             chat_template: None,
+            embedding_scale: None,
+            residual_scale: None,
+            logit_scale: None,
+            final_logit_cap: None,
+            kv_cache_quantization: Default::default(),
         };
         let config = Arc::new(config);
         let rope = RopeCache::new(&config, DType::F32, device)?;
@@ -126,23 +199,33 @@ impl Model {
                 attention_wv: QMatMul::from_qtensor(attention_wv)?,
                 interleaved_rope: true,
                 bias: None,
+                q_norm: None,
+                k_norm: None,
             });
             let feed_forward_variant = FeedForwardVariant::Llama(LlamaFeedForward {
                 feed_forward_w1: QMatMul::from_qtensor(feed_forward_w1)?,
                 feed_forward_w2: QMatMul::from_qtensor(feed_forward_w2)?,
                 feed_forward_w3: QMatMul::from_qtensor(feed_forward_w3)?,
+                activation: FeedForwardActivation::Silu,
             });
             layers.push(LlamaAttention {
                 attention_variant,
                 attention_wo: QMatMul::from_qtensor(attention_wo)?,
-                attention_norm: decode_norm(attention_norm, 1e-5)?,
+                attention_norm: Some(decode_norm(attention_norm, 1e-5)?),
+                post_attention_norm: None,
                 feed_forward_variant,
-                ffn_norm: decode_norm(ffn_norm, 1e-5)?,
+                ffn_norm: Some(decode_norm(ffn_norm, 1e-5)?),
+                post_ffn_norm: None,
+                attention_scale: None,
+                attention_logit_cap: None,
+                sliding_window: None,
                 n_head: ct.hparams.n_head as usize,
                 n_kv_head: ct.hparams.n_head as usize / gqa,
                 head_dim: (ct.hparams.n_embd / ct.hparams.n_head) as usize,
                 hidden_size: config.hidden_size(),
                 rope_cache: rope.clone(),
+                device: device.clone(),
+                flash_attn: false,
             })
         }
 
@@ -152,7 +235,11 @@ impl Model {
             layers,
             norm: decode_norm(ct.remove("norm.weight")?, 1e-5)?,
             output,
+            output_device: device.clone(),
+            added_tokens: 0,
+            added_token_mask: None,
             masks: Default::default(),
+            device_masks: Vec::new(),
         })
     }
 
@@ -161,6 +248,9 @@ impl Model {
         reader: &mut R,
         device: &Device,
         override_stop_token_string: Option<String>,
+        context_options: ContextOptions,
+        gpu_layers: Option<usize>,
+        device_map: &[Device],
     ) -> std::result::Result<Self, LlamaSourceError> {
         let md_get = |s: &str| {
             let value = if s.starts_with('.') {
@@ -211,9 +301,16 @@ impl Model {
         };
 
         // Parameter extraction from metadata.
-        let head_count = md_get(".attention.head_count")?.to_u32()? as usize;
-        let head_count_kv = md_get(".attention.head_count_kv")?.to_u32()? as usize;
+        let architecture = md_get("general.architecture")?.to_string()?.clone();
+        let gemma = matches!(architecture.as_str(), "gemma" | "gemma2");
         let block_count = md_get(".block_count")?.to_u32()? as usize;
+        let head_count = md_get(".attention.head_count")?.to_u32()? as usize;
+        // The number of key value heads may be a single value or one value per layer. Models without grouped query
+        // attention may leave it out entirely
+        let head_count_kv = match md_get(".attention.head_count_kv") {
+            Ok(value) => per_layer_counts(value, block_count)?,
+            Err(_) => vec![head_count; block_count],
+        };
         let embedding_length = md_get(".embedding_length")?.to_u32()? as usize;
         // Strangely this value is generally 1e-6 in GGUF file but used to be 1e-5 by default.
         let rms_norm_eps = md_get(".attention.layer_norm_rms_epsilon")?.to_f32()? as f64;
@@ -222,8 +319,71 @@ impl Model {
             .and_then(|m| m.to_f32())
             .unwrap_or(10_000f32);
 
-        let context_length = md_get(".context_length")?.to_u32()? as usize;
-        let head_dim = embedding_length / head_count;
+        let trained_context_length = md_get(".context_length")?.to_u32()? as usize;
+        let file_rope_scaling = match md_get(".rope.scaling.type").and_then(|m| m.to_string()) {
+            Ok(scaling_type) => {
+                let factor = md_get(".rope.scaling.factor")
+                    .and_then(|m| m.to_f32())
+                    .unwrap_or(1.);
+                let attention_factor = md_get(".rope.scaling.attn_factor")
+                    .and_then(|m| m.to_f32())
+                    .ok();
+                RopeScaling::from_metadata(scaling_type, factor, attention_factor)
+            }
+            Err(_) => None,
+        };
+        let original_context_length = md_get(".rope.scaling.original_context_length")
+            .and_then(|m| m.to_u32())
+            .map(|length| length as usize)
+            .unwrap_or(trained_context_length);
+        let (context_length, rope_scaling) =
+            context_options.resolve(trained_context_length, file_rope_scaling);
+        // Some models (like Mistral NeMo) have a head dimension that is not embedding_length / head_count
+        let head_dim = md_get(".attention.key_length")
+            .and_then(|m| m.to_u32())
+            .map(|head_dim| head_dim as usize)
+            .unwrap_or(embedding_length / head_count);
+
+        // Granite models scale the embeddings, residual connections, attention and logits
+        let embedding_scale = md_get(".embedding_scale")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|scale| scale as f64)
+            // Gemma models always scale the embeddings by the square root of the hidden size
+            .or_else(|| gemma.then(|| (embedding_length as f64).sqrt()));
+        let residual_scale = md_get(".residual_scale")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|scale| scale as f64);
+        let attention_scale = md_get(".attention.scale")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|scale| scale as f64)
+            // Gemma 2 27b scales the queries by the hidden size per head instead of the head dimension. The gguf
+            // metadata doesn't record it, so detect the model by the number of layers like llama.cpp
+            .or_else(|| {
+                (architecture == "gemma2" && block_count == 46)
+                    .then(|| 1. / ((embedding_length / head_count) as f64).sqrt())
+            });
+        let logit_scale = md_get(".logit_scale")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|scale| scale as f64);
+
+        // Gemma 2 soft caps the attention and output logits and alternates between sliding window and global
+        // attention layers
+        let attention_logit_cap = md_get(".attn_logit_softcapping")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|cap| cap as f64);
+        let final_logit_cap = md_get(".final_logit_softcapping")
+            .and_then(|m| m.to_f32())
+            .ok()
+            .map(|cap| cap as f64);
+        let sliding_window = md_get(".attention.sliding_window")
+            .and_then(|m| m.to_u32())
+            .ok()
+            .map(|window| window as usize);
 
         let config = LlamaConfig {
             rope_freq_weight: match ct.tensor(reader, "rope_freqs.weight", device).ok() {
@@ -231,6 +391,8 @@ impl Model {
                 None => None,
             },
             rope_theta: rope_freq_base,
+            rope_scaling,
+            original_context_length,
             context_length,
             head_dimension: head_dim,
             n_head: head_count,
@@ -239,25 +401,36 @@ impl Model {
             stop_token,
             stop_token_string,
             chat_template,
+            embedding_scale,
+            residual_scale,
+            logit_scale,
+            final_logit_cap,
+            kv_cache_quantization: context_options.kv_cache_quantization,
         };
         let config = Arc::new(config);
 
-        let rope = RopeCache::new(&config, DType::F32, device)?;
+        // The output head runs on the same device as the last layer
+        let placement = LayerPlacement::new(&config, device, block_count, gpu_layers, device_map)?;
+        let output_device = placement.output_device();
 
         let tok_embeddings_q = ct.tensor(reader, "token_embd.weight", device)?;
         let tok_embeddings = tok_embeddings_q.dequantize(device)?;
 
-        let norm = ct.tensor(reader, "output_norm.weight", device)?;
+        let norm = ct.tensor(reader, "output_norm.weight", output_device)?;
         let norm = decode_norm(norm, rms_norm_eps)?;
-        let output = if let Ok(output) = ct.tensor(reader, "output.weight", device) {
+        let output = if let Ok(output) = ct.tensor(reader, "output.weight", output_device) {
             QMatMul::from_qtensor(output)?
-        } else {
+        } else if output_device.same_device(device) {
             // If there is no output layer, assume the word embeddings are tied to the output
             QMatMul::from_qtensor(tok_embeddings_q)?
+        } else {
+            QMatMul::from_qtensor(ct.tensor(reader, "token_embd.weight", output_device)?)?
         };
         let mut layers = Vec::with_capacity(block_count);
-        for layer_idx in 0..block_count {
+        for (layer_idx, &n_kv_head) in head_count_kv.iter().enumerate() {
             let prefix = format!("blk.{layer_idx}");
+            let device = placement.layer_device(layer_idx);
+            let rope = placement.rope(layer_idx);
             let attention_variant =
                 if let Ok(qkv) = ct.tensor(reader, &format!("{prefix}.attn_qkv.weight"), device) {
                     AttentionVariant::Grouped(GroupedAttention {
@@ -280,20 +453,66 @@ impl Model {
                     } else {
                         None
                     };
-                    let architecture = ct.metadata["general.architecture"].to_string().unwrap();
+                    // OLMo 2 normalizes the queries and keys before applying rope
+                    let q_norm = ct
+                        .tensor(reader, &format!("{prefix}.attn_q_norm.weight"), device)
+                        .ok()
+                        .map(|norm| decode_norm(norm, rms_norm_eps))
+                        .transpose()?;
+                    let k_norm = ct
+                        .tensor(reader, &format!("{prefix}.attn_k_norm.weight"), device)
+                        .ok()
+                        .map(|norm| decode_norm(norm, rms_norm_eps))
+                        .transpose()?;
                     let separate = SeparateAttention {
                         attention_wq: QMatMul::from_qtensor(q)?,
                         attention_wk: QMatMul::from_qtensor(k)?,
                         attention_wv: QMatMul::from_qtensor(v)?,
-                        interleaved_rope: architecture != "qwen2",
+                        interleaved_rope: !matches!(
+                            architecture.as_str(),
+                            "qwen2" | "olmo2" | "gemma" | "gemma2"
+                        ),
                         bias,
+                        q_norm,
+                        k_norm,
                     };
                     AttentionVariant::Separate(separate)
                 };
             let attention_wo =
                 ct.tensor(reader, &format!("{prefix}.attn_output.weight"), device)?;
-            // Try to read from the up, down and gate weights
-            let feed_forward_variant = if let Ok(ffn_gate) =
+            let activation = if gemma {
+                FeedForwardActivation::Gelu
+            } else {
+                FeedForwardActivation::Silu
+            };
+            // Mixture of experts models like Mixtral have a router that picks the experts for each token
+            let feed_forward_variant = if let Ok(router) =
+                ct.tensor(reader, &format!("{prefix}.ffn_gate_inp.weight"), device)
+            {
+                let expert_count = md_get(".expert_count")?.to_u32()? as usize;
+                let experts_per_token = md_get(".expert_used_count")?.to_u32()? as usize;
+                let gates = read_experts(&ct, reader, &prefix, "ffn_gate", expert_count, device)?;
+                let downs = read_experts(&ct, reader, &prefix, "ffn_down", expert_count, device)?;
+                let ups = read_experts(&ct, reader, &prefix, "ffn_up", expert_count, device)?;
+                let experts = gates
+                    .into_iter()
+                    .zip(downs)
+                    .zip(ups)
+                    .map(|((gate, down), up)| {
+                        Ok(LlamaFeedForward {
+                            feed_forward_w1: QMatMul::from_qtensor(gate)?,
+                            feed_forward_w2: QMatMul::from_qtensor(down)?,
+                            feed_forward_w3: QMatMul::from_qtensor(up)?,
+                            activation,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                FeedForwardVariant::MixtureOfExperts(MoeFeedForward {
+                    router: QMatMul::from_qtensor(router)?,
+                    experts,
+                    experts_per_token,
+                })
+            } else if let Ok(ffn_gate) =
                 ct.tensor(reader, &format!("{prefix}.ffn_gate.weight"), device)
             {
                 let feed_forward_w1 = ffn_gate;
@@ -305,6 +524,7 @@ impl Model {
                     feed_forward_w1: QMatMul::from_qtensor(feed_forward_w1)?,
                     feed_forward_w2: QMatMul::from_qtensor(feed_forward_w2)?,
                     feed_forward_w3: QMatMul::from_qtensor(feed_forward_w3)?,
+                    activation,
                 })
             } else {
                 // Otherwise, try to read from the up, and down weights
@@ -319,20 +539,43 @@ impl Model {
                     feed_forward_length,
                 })
             };
-            let attention_norm =
-                ct.tensor(reader, &format!("{prefix}.attn_norm.weight"), device)?;
-            let ffn_norm = ct.tensor(reader, &format!("{prefix}.ffn_norm.weight"), device)?;
+            // Most models normalize the input of each block, but OLMo 2 normalizes the output instead
+            let mut norm = |name: &str| {
+                ct.tensor(reader, &format!("{prefix}.{name}.weight"), device)
+                    .ok()
+                    .map(|norm| decode_norm(norm, rms_norm_eps))
+                    .transpose()
+            };
+            let attention_norm = norm("attn_norm")?;
+            let post_attention_norm = norm("post_attention_norm")?;
+            let ffn_norm = norm("ffn_norm")?;
+            let post_ffn_norm = norm("post_ffw_norm")?;
+            if attention_norm.is_none() && post_attention_norm.is_none() {
+                return Err(candle_core::Error::Msg(format!(
+                    "cannot find {prefix}.attn_norm.weight in the model"
+                ))
+                .into());
+            }
             layers.push(LlamaAttention {
                 attention_variant,
                 attention_wo: QMatMul::from_qtensor(attention_wo)?,
-                attention_norm: decode_norm(attention_norm, rms_norm_eps)?,
+                attention_norm,
+                post_attention_norm,
                 feed_forward_variant,
-                ffn_norm: decode_norm(ffn_norm, rms_norm_eps)?,
+                ffn_norm,
+                post_ffn_norm,
+                attention_scale,
+                attention_logit_cap,
+                // Even layers use the sliding window in Gemma 2
+                sliding_window: sliding_window
+                    .filter(|_| architecture == "gemma2" && layer_idx % 2 == 0),
                 n_head: head_count,
-                n_kv_head: head_count_kv,
+                n_kv_head,
                 head_dim,
                 hidden_size: config.hidden_size(),
                 rope_cache: rope.clone(),
+                device: device.clone(),
+                flash_attn: false,
             })
         }
         Ok(Self {
@@ -341,7 +584,11 @@ impl Model {
             layers,
             norm,
             output,
+            output_device: output_device.clone(),
+            added_tokens: 0,
+            added_token_mask: None,
             masks: Default::default(),
+            device_masks: placement.device_masks(),
         })
     }
 
@@ -352,6 +599,9 @@ impl Model {
         mut cache: Option<&mut LlamaCache>,
     ) -> Result<Tensor> {
         let mut seq_len = tokens.len();
+        if let Some(cache) = cache.as_deref_mut() {
+            self.evict_after_attention_sinks(cache, seq_len)?;
+        }
         let cached_tokens = cache.as_ref().map(|c| c.tokens.len()).unwrap_or_default();
         // We use a lower cutoff than the context length to avoid recomputing the attention every single token
         let cutoff_len: usize = self.config.context_length.saturating_sub(32).max(8);
@@ -380,29 +630,550 @@ impl Model {
             }
             (Tensor::new(tokens, device)?.unsqueeze(0)?, index_pos)
         };
-        let mask = self.masks.get_mask(seq_len, index_pos, device)?;
 
         let mut layer_in = self.tok_embeddings.forward(&x)?;
+        if let Some(scale) = self.config.embedding_scale {
+            layer_in = (layer_in * scale)?;
+        }
+        self.forward_layers(layer_in, seq_len, index_pos, device, cache)
+    }
+
+    /// Run the model on tokens that fit in the context after the cached tokens and get the logits for every new
+    /// position. The context is never truncated, so the cache can be rolled back to any of the new tokens.
+    pub(crate) fn forward_all_positions(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: &mut LlamaCache,
+    ) -> Result<Tensor> {
+        let x = self.hidden_states_all_positions(tokens, device, cache)?;
+        self.logits(&x)
+    }
+
+    /// Run new tokens through the model and get the hidden states after the final norm at every position with the
+    /// shape `(new_tokens, hidden_size)`. Fails if the tokens don't fit in the context.
+    pub(crate) fn hidden_states_all_positions(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        cache: &mut LlamaCache,
+    ) -> Result<Tensor> {
+        let seq_len = tokens.len();
+        let index_pos = cache.tokens.len();
+        if index_pos + seq_len > self.config.context_length {
+            candle_core::bail!(
+                "{seq_len} tokens do not fit in the context of {} tokens with {index_pos} tokens already in the session",
+                self.config.context_length
+            );
+        }
+        cache.tokens.extend_from_slice(tokens);
+        let x = Tensor::new(tokens, device)?.unsqueeze(0)?;
+        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        if let Some(scale) = self.config.embedding_scale {
+            layer_in = (layer_in * scale)?;
+        }
+        let x = self.hidden_states(layer_in, seq_len, index_pos, device, Some(cache))?;
+        x.squeeze(0)
+    }
+
+    /// If the session has attention sinks and the new tokens don't fit in the context, drop the oldest tokens after
+    /// the sinks from the cache.
+    fn evict_after_attention_sinks(&self, cache: &mut LlamaCache, new_tokens: usize) -> Result<()> {
+        let Some(sinks) = cache.attention_sinks else {
+            return Ok(());
+        };
+        let Some(evict) =
+            sinks.tokens_to_evict(cache.tokens.len(), new_tokens, self.config.context_length)
+        else {
+            return Ok(());
+        };
+        tracing::trace!(
+            "The context is full, dropping {evict} tokens after the {} attention sink tokens",
+            sinks.sink_tokens()
+        );
+        cache.ensure_layers(&self.config);
+        let start = sinks.sink_tokens();
+        for (layer, block) in self.layers.iter().zip(&mut cache.blocks) {
+            layer.evict_from_cache(block, start, evict)?;
+        }
+        cache.tokens.drain(start..start + evict);
+        Ok(())
+    }
+
+    /// Run the model on embeddings in place of tokens, like the projected features of an image. The placeholder token
+    /// is stored in the cache for each embedding.
+    pub(crate) fn forward_embeddings(
+        &self,
+        embeddings: &Tensor,
+        placeholder: u32,
+        device: &Device,
+        mut cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        let seq_len = embeddings.dim(0)?;
+        let index_pos = cache.as_ref().map(|c| c.tokens.len()).unwrap_or_default();
+        // The embeddings can't be recomputed from the tokens, so they must fit in the context without truncation
+        if index_pos + seq_len > self.config.context_length {
+            candle_core::bail!(
+                "{seq_len} embeddings do not fit in the context of {} tokens with {index_pos} tokens already in the session",
+                self.config.context_length
+            );
+        }
+        if let Some(cache) = cache.as_mut() {
+            cache
+                .tokens
+                .extend(std::iter::repeat_n(placeholder, seq_len));
+        }
+        let layer_in = embeddings
+            .to_device(device)?
+            .to_dtype(self.tok_embeddings.embeddings().dtype())?
+            .unsqueeze(0)?;
+        self.forward_layers(layer_in, seq_len, index_pos, device, cache)
+    }
+
+    fn forward_layers(
+        &self,
+        layer_in: Tensor,
+        seq_len: usize,
+        index_pos: usize,
+        device: &Device,
+        cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        let x = self.hidden_states(layer_in, seq_len, index_pos, device, cache)?;
+        let x = x.i((.., seq_len - 1, ..))?;
+        self.logits(&x)
+    }
+
+    /// Run the layers of the model and the final norm on the embeddings of new tokens.
+    fn hidden_states(
+        &self,
+        mut layer_in: Tensor,
+        seq_len: usize,
+        index_pos: usize,
+        device: &Device,
+        mut cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
+        if let Some(cache) = cache.as_deref_mut() {
+            cache.ensure_layers(&self.config);
+        }
+        let mask = self.masks.get_mask(seq_len, index_pos, device)?;
+        // Layers on other devices or offloaded to the CPU need a mask on their device
+        let device_masks = self
+            .device_masks
+            .iter()
+            .map(|(device, masks)| Ok((device, masks.get_mask(seq_len, index_pos, device)?)))
+            .collect::<Result<Vec<_>>>()?;
+
         for (i, layer) in self.layers.iter().enumerate() {
-            let x = layer_in;
+            let (x, mask) = match device_masks
+                .iter()
+                .find(|(device, _)| layer.device.same_device(device))
+            {
+                Some((device, mask)) => (layer_in.to_device(device)?, mask),
+                None => (layer_in.to_device(device)?, &mask),
+            };
             let residual = &x;
-            let x = layer.attention_norm.forward(&x)?;
-            let attn = layer.forward(
+            let x = match &layer.attention_norm {
+                Some(norm) => norm.forward(&x)?,
+                None => x.clone(),
+            };
+            let (block_cache, mut recorder) = match cache.as_deref_mut() {
+                Some(cache) => (Some(&mut cache.blocks[i]), cache.activations.as_mut()),
+                None => (None, None),
+            };
+            let mut attention_weights = None;
+            let capture_attention = recorder
+                .as_ref()
+                .is_some_and(|recorder| recorder.wants_attention_weights(i));
+            let mut attn = layer.forward(
                 &x,
-                Some(&mask),
+                Some(mask),
                 index_pos,
-                cache.as_mut().map(|c| &mut c.blocks[i]),
+                block_cache,
+                capture_attention.then_some(&mut attention_weights),
             )?;
+            if let Some(norm) = &layer.post_attention_norm {
+                attn = norm.forward(&attn)?;
+            }
+            if let Some(scale) = self.config.residual_scale {
+                attn = (attn * scale)?;
+            }
             let x = (attn + residual)?;
 
             // MLP
             let residual = &x;
-            let x = layer.ffn_norm.forward(&x)?;
+            let x = match &layer.ffn_norm {
+                Some(norm) => norm.forward(&x)?,
+                None => x.clone(),
+            };
+            let mut mlp = layer.feed_forward_variant.forward(&x)?;
+            if let Some(norm) = &layer.post_ffn_norm {
+                mlp = norm.forward(&mlp)?;
+            }
+            if let Some(scale) = self.config.residual_scale {
+                mlp = (mlp * scale)?;
+            }
 
-            layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
+            layer_in = (&mlp + residual)?;
+
+            if let Some(recorder) = recorder.as_mut() {
+                let hidden_states = recorder.wants_hidden_states(i).then(|| layer_in.clone());
+                recorder.record(i, index_pos, attention_weights, hidden_states)?;
+            }
         }
-        let x = self.norm.forward(&layer_in)?;
-        let x = x.i((.., seq_len - 1, ..))?;
-        self.output.forward(&x)
+        self.norm.forward(&layer_in.to_device(&self.output_device)?)
+    }
+
+    /// Run the model on one new token for each sequence in a batch. Each sequence has its own cache and must have
+    /// room for another token in the context. Returns the logits for each sequence. If the batch fails, every cache is
+    /// truncated back to the tokens it had before the batch.
+    pub(crate) fn forward_batch(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        caches: &mut [&mut LlamaCache],
+    ) -> Result<Tensor> {
+        let lengths: Vec<usize> = caches.iter().map(|cache| cache.tokens.len()).collect();
+        let result = self.batch_logits(tokens, device, caches);
+        if result.is_err() {
+            for (cache, len) in caches.iter_mut().zip(lengths) {
+                cache.truncate(len);
+            }
+        }
+        result
+    }
+
+    /// Run [`Model::forward_batch`] without rolling back the caches if it fails.
+    fn batch_logits(
+        &self,
+        tokens: &[u32],
+        device: &Device,
+        caches: &mut [&mut LlamaCache],
+    ) -> Result<Tensor> {
+        let start_positions: Vec<usize> = caches
+            .iter_mut()
+            .zip(tokens)
+            .map(|(cache, &token)| {
+                cache.ensure_layers(&self.config);
+                let start_pos = cache.tokens.len();
+                cache.tokens.push(token);
+                start_pos
+            })
+            .collect();
+
+        let x = Tensor::new(tokens, device)?.unsqueeze(1)?;
+        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        if let Some(scale) = self.config.embedding_scale {
+            layer_in = (layer_in * scale)?;
+        }
+
+        for (i, layer) in self.layers.iter().enumerate() {
+            let x = layer_in.to_device(&layer.device)?;
+            let residual = &x;
+            let x = match &layer.attention_norm {
+                Some(norm) => norm.forward(&x)?,
+                None => x.clone(),
+            };
+            let mut block_caches: Vec<_> = caches
+                .iter_mut()
+                .map(|cache| &mut cache.blocks[i])
+                .collect();
+            let mut attn = layer.forward_batch(&x, &start_positions, &mut block_caches)?;
+            if let Some(norm) = &layer.post_attention_norm {
+                attn = norm.forward(&attn)?;
+            }
+            if let Some(scale) = self.config.residual_scale {
+                attn = (attn * scale)?;
+            }
+            let x = (attn + residual)?;
+
+            // MLP
+            let residual = &x;
+            let x = match &layer.ffn_norm {
+                Some(norm) => norm.forward(&x)?,
+                None => x.clone(),
+            };
+            let mut mlp = layer.feed_forward_variant.forward(&x)?;
+            if let Some(norm) = &layer.post_ffn_norm {
+                mlp = norm.forward(&mlp)?;
+            }
+            if let Some(scale) = self.config.residual_scale {
+                mlp = (mlp * scale)?;
+            }
+
+            layer_in = (&mlp + residual)?;
+        }
+        let x = self
+            .norm
+            .forward(&layer_in.to_device(&self.output_device)?)?;
+        let x = x.i((.., 0, ..))?;
+        self.logits(&x)
+    }
+
+    /// Get the logits from the final hidden state of each sequence.
+    fn logits(&self, x: &Tensor) -> Result<Tensor> {
+        self.scale_logits(self.output.forward(x)?)
+    }
+
+    /// Apply the logit scale and cap of the model and mask any added tokens.
+    fn scale_logits(&self, logits: Tensor) -> Result<Tensor> {
+        let logits = match self.config.logit_scale {
+            Some(scale) => (logits / scale)?,
+            None => logits,
+        };
+        let logits = match self.config.final_logit_cap {
+            Some(cap) => ((logits / cap)?.tanh()? * cap)?,
+            None => logits,
+        };
+        let Some(mask) = &self.added_token_mask else {
+            return Ok(logits);
+        };
+        let logits = if self.added_tokens > 0 {
+            let added = Tensor::zeros(
+                (logits.dim(0)?, self.added_tokens),
+                logits.dtype(),
+                logits.device(),
+            )?;
+            Tensor::cat(&[&logits, &added], 1)?
+        } else {
+            logits
+        };
+        mask_added_tokens(&logits, mask)
+    }
+
+    /// Grow the vocabulary of the model to `vocab_size` tokens and mask the `new_tokens` in the output. The embedding
+    /// rows of the new tokens are initialized to the mean of the existing embeddings, including new tokens that land
+    /// in untrained padding rows of the vocabulary.
+    pub(crate) fn add_tokens(&mut self, vocab_size: usize, new_tokens: &[u32]) -> Result<()> {
+        if new_tokens.is_empty() {
+            return Ok(());
+        }
+        let embeddings = self.tok_embeddings.embeddings();
+        let (rows, hidden_size) = embeddings.dims2()?;
+        let mean = embeddings.mean_keepdim(0)?;
+        let mut embeddings = embeddings.clone();
+        if vocab_size > rows {
+            let new_rows = mean.broadcast_as((vocab_size - rows, hidden_size))?;
+            embeddings = Tensor::cat(&[&embeddings, &new_rows], 0)?;
+            self.added_tokens += vocab_size - rows;
+        }
+        for &token in new_tokens.iter().filter(|&&token| (token as usize) < rows) {
+            let token = token as usize;
+            embeddings = embeddings.slice_assign(&[token..token + 1, 0..hidden_size], &mean)?;
+        }
+        let vocab_size = embeddings.dim(0)?;
+        self.tok_embeddings = Embedding::new(embeddings, hidden_size);
+
+        let mut mask = match &self.added_token_mask {
+            Some(mask) => mask.flatten_all()?.to_vec1::<u8>()?,
+            None => Vec::new(),
+        };
+        mask.resize(vocab_size, 0);
+        for &token in new_tokens {
+            mask[token as usize] = 1;
+        }
+        self.added_token_mask = Some(Tensor::from_vec(
+            mask,
+            (1, vocab_size),
+            &self.output_device,
+        )?);
+        Ok(())
+    }
+}
+
+/// Set the logits of every token in the mask to [`ADDED_TOKEN_LOGIT`].
+fn mask_added_tokens(logits: &Tensor, mask: &Tensor) -> Result<Tensor> {
+    let shape = logits.shape();
+    let masked = Tensor::new(ADDED_TOKEN_LOGIT, logits.device())?
+        .to_dtype(logits.dtype())?
+        .broadcast_as(shape)?;
+    mask.broadcast_as(shape)?.where_cond(&masked, logits)
+}
+
+/// Read the weights of each expert in a mixture of experts layer. Newer gguf files stack every expert in one tensor
+/// and older files store one tensor per expert.
+fn read_experts<R: std::io::Seek + std::io::Read>(
+    ct: &gguf_file::Content,
+    reader: &mut R,
+    prefix: &str,
+    name: &str,
+    expert_count: usize,
+    device: &Device,
+) -> Result<Vec<QTensor>> {
+    // The raw data of quantized tensors can only be read on the CPU
+    if let Ok(stacked) = ct.tensor(
+        reader,
+        &format!("{prefix}.{name}_exps.weight"),
+        &Device::Cpu,
+    ) {
+        let dims = stacked.shape().dims().to_vec();
+        if dims.len() != 3 || dims[0] != expert_count {
+            candle_core::bail!(
+                "expected {expert_count} stacked experts in {prefix}.{name}_exps.weight, found shape {dims:?}"
+            );
+        }
+        // The experts are the outermost dimension, so each expert is a contiguous block of the data
+        let data = stacked.data()?;
+        let expert_size = data.len() / expert_count;
+        return data
+            .chunks_exact(expert_size)
+            .map(|expert| {
+                ggml_file::qtensor_from_ggml(stacked.dtype(), expert, dims[1..].to_vec(), device)
+            })
+            .collect();
     }
+    (0..expert_count)
+        .map(|expert| ct.tensor(reader, &format!("{prefix}.{name}.{expert}.weight"), device))
+        .collect()
+}
+
+/// Read a count from gguf metadata that may be a single value for every layer or an array with one value per layer.
+fn per_layer_counts(value: &gguf_file::Value, block_count: usize) -> Result<Vec<usize>> {
+    match value {
+        gguf_file::Value::Array(values) => {
+            if values.len() != block_count {
+                candle_core::bail!(
+                    "expected {block_count} per layer values in metadata, found {}",
+                    values.len()
+                );
+            }
+            values
+                .iter()
+                .map(|value| value.to_u32().map(|value| value as usize))
+                .collect()
+        }
+        value => Ok(vec![value.to_u32()? as usize; block_count]),
+    }
+}
+
+#[test]
+fn added_tokens_are_never_sampled_without_a_constraint() {
+    use kalosm_language_model::GenerationParameters;
+    use llm_samplers::prelude::*;
+    use rand::SeedableRng;
+
+    let device = Device::Cpu;
+    // The added token 2 has the highest raw logit, like an untrained padding row might
+    let logits = Tensor::new(&[[0.5f32, 1.0, 8.0, 0.0]], &device).unwrap();
+    let mask = Tensor::new(&[[0u8, 0, 1, 0]], &device).unwrap();
+    let logits = mask_added_tokens(&logits, &mask).unwrap();
+    let logits = logits.flatten_all().unwrap().to_vec1::<f32>().unwrap();
+    assert_eq!(logits[2], ADDED_TOKEN_LOGIT);
+
+    let mut sampler = GenerationParameters::new();
+    let mut resources = SimpleSamplerResources::new(
+        Some(Box::new(rand::rngs::StdRng::seed_from_u64(0))),
+        Some(Vec::new()),
+    );
+    for _ in 0..100 {
+        let mut candidates = Logits::try_from_iter(logits.iter().copied()).unwrap();
+        let token = sampler
+            .sample_token(&mut resources, &mut candidates)
+            .unwrap()
+            .unwrap();
+        assert_ne!(token, 2);
+    }
+
+    // A constraint that only allows the added token still generates it
+    let mut candidates = Logits::try_from_iter(logits.iter().copied()).unwrap();
+    candidates.retain(|logit| logit.token_id == 2);
+    let token = sampler
+        .sample_token(&mut resources, &mut candidates)
+        .unwrap()
+        .unwrap();
+    assert_eq!(token, 2);
+}
+
+#[test]
+fn per_layer_head_counts() {
+    let single = gguf_file::Value::U32(8);
+    assert_eq!(per_layer_counts(&single, 3).unwrap(), [8, 8, 8]);
+    let array = gguf_file::Value::Array(vec![
+        gguf_file::Value::U32(4),
+        gguf_file::Value::U32(0),
+        gguf_file::Value::U32(8),
+    ]);
+    assert_eq!(per_layer_counts(&array, 3).unwrap(), [4, 0, 8]);
+    assert!(per_layer_counts(&array, 2).is_err());
+}
+
+#[test]
+fn batched_logits_match_sequential_logits() {
+    let model = crate::test_model::tiny_model();
+    let device = Device::Cpu;
+    let prompts: [&[u32]; 3] = [&[3, 4, 5, 5, 6], &[9, 10, 11], &[7]];
+    let next_tokens = [10, 8, 6];
+    let mut caches: Vec<LlamaCache> = prompts
+        .iter()
+        .map(|prompt| {
+            let mut cache = LlamaCache::new(&model.config);
+            model.forward(prompt, &device, Some(&mut cache)).unwrap();
+            cache
+        })
+        .collect();
+    let mut sequential_caches = caches.clone();
+
+    let mut batch_caches: Vec<&mut LlamaCache> = caches.iter_mut().collect();
+    let batched = model
+        .forward_batch(&next_tokens, &device, &mut batch_caches)
+        .unwrap();
+    assert_eq!(batched.dim(0).unwrap(), prompts.len());
+
+    for (row, (cache, &token)) in sequential_caches.iter_mut().zip(&next_tokens).enumerate() {
+        let sequential = model
+            .forward(&[token], &device, Some(cache))
+            .unwrap()
+            .flatten_all()
+            .unwrap()
+            .to_vec1::<f32>()
+            .unwrap();
+        let batched = batched.get(row).unwrap().to_vec1::<f32>().unwrap();
+        assert_eq!(batched.len(), sequential.len());
+        for (batched, sequential) in batched.iter().zip(&sequential) {
+            assert!(
+                (batched - sequential).abs() < 1e-4,
+                "sequence {row}: {batched} != {sequential}"
+            );
+        }
+    }
+    for (batched, sequential) in caches.iter().zip(&sequential_caches) {
+        assert_eq!(batched.tokens, sequential.tokens);
+    }
+}
+
+#[test]
+fn failed_batches_leave_the_caches_unchanged() {
+    let model = crate::test_model::tiny_model();
+    let device = Device::Cpu;
+    let kv = |cache: &LlamaCache| -> Vec<Vec<f32>> {
+        cache
+            .blocks
+            .iter()
+            .flat_map(|block| [block.k().unwrap(), block.v().unwrap()])
+            .map(|tensor| tensor.unwrap().flatten_all().unwrap().to_vec1().unwrap())
+            .collect()
+    };
+    let mut caches: Vec<LlamaCache> = [&[3, 4, 5][..], &[9, 10]]
+        .iter()
+        .map(|prompt| {
+            let mut cache = LlamaCache::new(&model.config);
+            model.forward(prompt, &device, Some(&mut cache)).unwrap();
+            cache
+        })
+        .collect();
+    let before: Vec<_> = caches
+        .iter()
+        .map(|cache| (cache.tokens.clone(), kv(cache)))
+        .collect();
+
+    // The second token is outside of the vocabulary, so the batch fails after both tokens were added to the caches
+    let mut batch_caches: Vec<&mut LlamaCache> = caches.iter_mut().collect();
+    assert!(model
+        .forward_batch(&[6, u32::MAX], &device, &mut batch_caches)
+        .is_err());
+
+    let after: Vec<_> = caches
+        .iter()
+        .map(|cache| (cache.tokens.clone(), kv(cache)))
+        .collect();
+    assert_eq!(before, after);
 }
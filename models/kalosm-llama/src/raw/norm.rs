@@ -0,0 +1,40 @@
+use candle_core::quantized::QTensor;
+use candle_core::{Module, Tensor};
+
+/// An RMS norm layer. It keeps the weight around so fine-tuning can run the norm with operations that support
+/// backpropagation.
+#[derive(Debug, Clone)]
+pub struct RmsNorm {
+    weight: Tensor,
+    eps: f64,
+}
+
+impl RmsNorm {
+    pub fn from_qtensor(weight: QTensor, eps: f64) -> candle_core::Result<Self> {
+        let weight = weight.dequantize(&weight.device())?;
+        Ok(Self { weight, eps })
+    }
+
+    /// Copy the norm to another device.
+    pub(crate) fn to_device(&self, device: &candle_core::Device) -> candle_core::Result<Self> {
+        Ok(Self {
+            weight: self.weight.to_device(device)?,
+            eps: self.eps,
+        })
+    }
+
+    /// Run the norm with operations that support backpropagation.
+    pub(crate) fn forward_differentiable(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        candle_nn::ops::rms_norm_slow(x, &self.weight, self.eps as f32)
+    }
+}
+
+impl Module for RmsNorm {
+    fn forward(&self, x: &Tensor) -> candle_core::Result<Tensor> {
+        if x.is_contiguous() {
+            candle_nn::ops::rms_norm(x, &self.weight, self.eps as f32)
+        } else {
+            self.forward_differentiable(x)
+        }
+    }
+}
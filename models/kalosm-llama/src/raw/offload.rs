@@ -0,0 +1,143 @@
+use candle_core::quantized::{QMatMul, QTensor};
+use candle_core::{Device, Result};
+use candle_nn::Embedding;
+use kalosm_common::MaskCache;
+
+use super::attention_layer::{
+    AttentionBias, AttentionVariant, FeedForwardVariant, GroupedAttention, LlamaAttention,
+    LlamaFeedForward, MoeFeedForward, PhiFeedForward, SeparateAttention,
+};
+use super::Model;
+
+/// Copy a weight to the CPU. Quantized weights keep their format.
+fn weight_to_cpu(weight: &QMatMul) -> Result<QMatMul> {
+    Ok(match weight {
+        QMatMul::QTensor(tensor) if tensor.device().is_cpu() => QMatMul::QTensor(tensor.clone()),
+        // Quantized weights can only be read back from the accelerator as floats
+        QMatMul::QTensor(tensor) => QMatMul::from_qtensor(QTensor::quantize(
+            &tensor.dequantize(&Device::Cpu)?,
+            tensor.dtype(),
+        )?)?,
+        QMatMul::Tensor(tensor) => QMatMul::Tensor(tensor.to_device(&Device::Cpu)?),
+        QMatMul::TensorF16(tensor) => QMatMul::TensorF16(tensor.to_device(&Device::Cpu)?),
+    })
+}
+
+fn feed_forward_to_cpu(feed_forward: &LlamaFeedForward) -> Result<LlamaFeedForward> {
+    Ok(LlamaFeedForward {
+        feed_forward_w1: weight_to_cpu(&feed_forward.feed_forward_w1)?,
+        feed_forward_w2: weight_to_cpu(&feed_forward.feed_forward_w2)?,
+        feed_forward_w3: weight_to_cpu(&feed_forward.feed_forward_w3)?,
+        activation: feed_forward.activation,
+    })
+}
+
+fn layer_to_cpu(layer: &LlamaAttention) -> Result<LlamaAttention> {
+    let cpu = Device::Cpu;
+    let norm_to_cpu =
+        |norm: &Option<super::RmsNorm>| norm.as_ref().map(|norm| norm.to_device(&cpu)).transpose();
+    let attention_variant = match &layer.attention_variant {
+        AttentionVariant::Separate(attention) => AttentionVariant::Separate(SeparateAttention {
+            attention_wq: weight_to_cpu(&attention.attention_wq)?,
+            attention_wk: weight_to_cpu(&attention.attention_wk)?,
+            attention_wv: weight_to_cpu(&attention.attention_wv)?,
+            bias: match &attention.bias {
+                Some(bias) => Some(AttentionBias {
+                    bias_q: bias.bias_q.to_device(&cpu)?,
+                    bias_k: bias.bias_k.to_device(&cpu)?,
+                    bias_v: bias.bias_v.to_device(&cpu)?,
+                }),
+                None => None,
+            },
+            q_norm: norm_to_cpu(&attention.q_norm)?,
+            k_norm: norm_to_cpu(&attention.k_norm)?,
+            interleaved_rope: attention.interleaved_rope,
+        }),
+        AttentionVariant::Grouped(attention) => AttentionVariant::Grouped(GroupedAttention {
+            attention_qkv: weight_to_cpu(&attention.attention_qkv)?,
+        }),
+    };
+    let feed_forward_variant = match &layer.feed_forward_variant {
+        FeedForwardVariant::Llama(feed_forward) => {
+            FeedForwardVariant::Llama(feed_forward_to_cpu(feed_forward)?)
+        }
+        FeedForwardVariant::Phi(feed_forward) => FeedForwardVariant::Phi(PhiFeedForward {
+            up: weight_to_cpu(&feed_forward.up)?,
+            down: weight_to_cpu(&feed_forward.down)?,
+            feed_forward_length: feed_forward.feed_forward_length,
+        }),
+        FeedForwardVariant::MixtureOfExperts(feed_forward) => {
+            FeedForwardVariant::MixtureOfExperts(MoeFeedForward {
+                router: weight_to_cpu(&feed_forward.router)?,
+                experts: feed_forward
+                    .experts
+                    .iter()
+                    .map(feed_forward_to_cpu)
+                    .collect::<Result<_>>()?,
+                experts_per_token: feed_forward.experts_per_token,
+            })
+        }
+    };
+    Ok(LlamaAttention {
+        attention_variant,
+        attention_wo: weight_to_cpu(&layer.attention_wo)?,
+        attention_norm: norm_to_cpu(&layer.attention_norm)?,
+        post_attention_norm: norm_to_cpu(&layer.post_attention_norm)?,
+        feed_forward_variant,
+        ffn_norm: norm_to_cpu(&layer.ffn_norm)?,
+        post_ffn_norm: norm_to_cpu(&layer.post_ffn_norm)?,
+        attention_scale: layer.attention_scale,
+        attention_logit_cap: layer.attention_logit_cap,
+        sliding_window: layer.sliding_window,
+        n_head: layer.n_head,
+        n_kv_head: layer.n_kv_head,
+        head_dim: layer.head_dim,
+        hidden_size: layer.hidden_size,
+        rope_cache: layer.rope_cache.to_device(&cpu)?,
+        device: cpu,
+        flash_attn: false,
+    })
+}
+
+impl Model {
+    /// Check if every layer of the model already runs on the CPU.
+    pub(crate) fn runs_on_cpu(&self) -> bool {
+        self.output_device.is_cpu() && self.layers.iter().all(|layer| layer.device.is_cpu())
+    }
+
+    /// Get the device each layer runs on.
+    pub(crate) fn layer_devices(&self) -> Vec<Device> {
+        self.layers
+            .iter()
+            .map(|layer| layer.device.clone())
+            .collect()
+    }
+
+    /// Copy the model to the CPU. The weights of layers that already run on the CPU are shared with this model.
+    pub(crate) fn to_cpu(&self) -> Result<Self> {
+        let cpu = Device::Cpu;
+        Ok(Self {
+            config: self.config.clone(),
+            tok_embeddings: Embedding::new(
+                self.tok_embeddings.embeddings().to_device(&cpu)?,
+                self.tok_embeddings.hidden_size(),
+            ),
+            layers: self
+                .layers
+                .iter()
+                .map(layer_to_cpu)
+                .collect::<Result<_>>()?,
+            norm: self.norm.to_device(&cpu)?,
+            output: weight_to_cpu(&self.output)?,
+            output_device: cpu.clone(),
+            added_tokens: self.added_tokens,
+            added_token_mask: self
+                .added_token_mask
+                .as_ref()
+                .map(|mask| mask.to_device(&cpu))
+                .transpose()?,
+            masks: MaskCache::default(),
+            device_masks: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use candle_core::{DType, Device};
+use kalosm_common::MaskCache;
+
+use super::rope::RopeCache;
+use super::LlamaConfig;
+
+/// The device each layer of a model runs on. The first `gpu_layers` layers are split into even contiguous chunks
+/// between the accelerators and the rest of the layers are offloaded to the CPU. See
+/// [`LlamaBuilder::with_gpu_layers`](crate::LlamaBuilder::with_gpu_layers) and
+/// [`LlamaBuilder::with_device_map`](crate::LlamaBuilder::with_device_map).
+pub(crate) struct LayerPlacement {
+    device: Device,
+    layer_devices: Vec<Device>,
+    ropes: Vec<(Device, RopeCache)>,
+}
+
+impl LayerPlacement {
+    /// Place the layers of a model. The embeddings run on `device`. If the device map is empty, every accelerated
+    /// layer runs on `device`.
+    pub(crate) fn new(
+        config: &LlamaConfig,
+        device: &Device,
+        block_count: usize,
+        gpu_layers: Option<usize>,
+        device_map: &[Device],
+    ) -> candle_core::Result<Self> {
+        let gpu_layers = match gpu_layers {
+            Some(gpu_layers) if !device.is_cpu() => gpu_layers,
+            _ => block_count,
+        };
+        let accelerators = match device_map {
+            [] => std::slice::from_ref(device),
+            devices => devices,
+        };
+        let layer_devices: Vec<_> = layer_accelerators(block_count, gpu_layers, accelerators.len())
+            .into_iter()
+            .map(|accelerator| match accelerator {
+                Some(accelerator) => accelerators[accelerator].clone(),
+                None => Device::Cpu,
+            })
+            .collect();
+
+        let mut ropes = vec![(device.clone(), RopeCache::new(config, DType::F32, device)?)];
+        for layer_device in &layer_devices {
+            if !ropes
+                .iter()
+                .any(|(device, _)| device.same_device(layer_device))
+            {
+                let rope = RopeCache::new(config, DType::F32, layer_device)?;
+                ropes.push((layer_device.clone(), rope));
+            }
+        }
+
+        Ok(Self {
+            device: device.clone(),
+            layer_devices,
+            ropes,
+        })
+    }
+
+    /// Get the device a layer runs on.
+    pub(crate) fn layer_device(&self, layer: usize) -> &Device {
+        &self.layer_devices[layer]
+    }
+
+    /// Get the rope cache on the device a layer runs on.
+    pub(crate) fn rope(&self, layer: usize) -> &RopeCache {
+        let device = self.layer_device(layer);
+        self.ropes
+            .iter()
+            .find_map(|(rope_device, rope)| rope_device.same_device(device).then_some(rope))
+            .expect("every layer device has a rope cache")
+    }
+
+    /// Get the device the final norm and output head run on. This is the device of the last layer.
+    pub(crate) fn output_device(&self) -> &Device {
+        self.layer_devices.last().unwrap_or(&self.device)
+    }
+
+    /// Create an empty mask cache for every layer device other than the device of the embeddings.
+    pub(crate) fn device_masks(&self) -> Vec<(Device, MaskCache)> {
+        self.ropes
+            .iter()
+            .skip(1)
+            .map(|(device, _)| (device.clone(), MaskCache::default()))
+            .collect()
+    }
+}
+
+/// Get the index of the accelerator each layer runs on, or `None` for layers that are offloaded to the CPU. The first
+/// `gpu_layers` layers are split into even contiguous chunks between the accelerators.
+fn layer_accelerators(
+    block_count: usize,
+    gpu_layers: usize,
+    accelerators: usize,
+) -> Vec<Option<usize>> {
+    let gpu_layers = gpu_layers.min(block_count);
+    (0..block_count)
+        .map(|layer| (layer < gpu_layers).then(|| layer * accelerators / gpu_layers))
+        .collect()
+}
+
+#[test]
+fn layers_are_split_between_accelerators_and_the_cpu() {
+    // No accelerated layers
+    assert_eq!(layer_accelerators(4, 0, 1), [None; 4]);
+    // Partial offload keeps the first layers on the accelerator
+    assert_eq!(layer_accelerators(4, 2, 1), [Some(0), Some(0), None, None]);
+    // Asking for more layers than the model has accelerates every layer
+    assert_eq!(layer_accelerators(4, 10, 1), [Some(0); 4]);
+    // A device map splits the accelerated layers into even contiguous chunks
+    assert_eq!(
+        layer_accelerators(6, 6, 2),
+        [Some(0), Some(0), Some(0), Some(1), Some(1), Some(1)]
+    );
+    assert_eq!(
+        layer_accelerators(6, 4, 2),
+        [Some(0), Some(0), Some(1), Some(1), None, None]
+    );
+    assert_eq!(
+        layer_accelerators(5, 5, 3),
+        [Some(0), Some(0), Some(1), Some(1), Some(2)]
+    );
+}
+
+#[test]
+fn cpu_models_run_every_layer_on_the_cpu() {
+    let model = crate::test_model::tiny_model();
+    for gpu_layers in [None, Some(0), Some(1), Some(100)] {
+        let placement =
+            LayerPlacement::new(&model.config, &Device::Cpu, 3, gpu_layers, &[]).unwrap();
+        for layer in 0..3 {
+            assert!(placement.layer_device(layer).is_cpu());
+            placement.rope(layer);
+        }
+        assert!(placement.output_device().is_cpu());
+        assert!(placement.device_masks().is_empty());
+    }
+
+    // An explicit device map places the layers on the mapped devices
+    let placement = LayerPlacement::new(
+        &model.config,
+        &Device::Cpu,
+        2,
+        None,
+        &[Device::Cpu, Device::Cpu],
+    )
+    .unwrap();
+    assert!((0..2).all(|layer| placement.layer_device(layer).is_cpu()));
+    assert!(placement.device_masks().is_empty());
+}
@@ -5,23 +5,36 @@ use candle_core::{DType, Device, Tensor};
 pub struct RopeCache {
     sin: Tensor,
     cos: Tensor,
+    inverse_frequency: Tensor,
 }
 
 impl RopeCache {
     pub fn new(config: &LlamaConfig, dtype: DType, device: &Device) -> candle_core::Result<Self> {
-        let inverse_frequency = (0..config.head_dimension)
-            .step_by(2)
-            .map(|i| {
-                1. / (config
-                    .rope_theta
-                    .powf(i as f32 / config.head_dimension as f32))
-            })
-            .collect::<Vec<_>>();
+        let (inverse_frequency, attention_factor) = match &config.rope_scaling {
+            Some(scaling) => scaling.inverse_frequencies(
+                config.rope_theta,
+                config.head_dimension,
+                config.original_context_length,
+            ),
+            None => (
+                (0..config.head_dimension)
+                    .step_by(2)
+                    .map(|i| {
+                        1. / (config
+                            .rope_theta
+                            .powf(i as f32 / config.head_dimension as f32))
+                    })
+                    .collect::<Vec<_>>(),
+                1.,
+            ),
+        };
         let inverse_frequency_len = inverse_frequency.len();
         let mut inverse_frequency =
             Tensor::from_vec(inverse_frequency, (1, inverse_frequency_len), device)?
                 .to_dtype(dtype)?;
         if let Some(weight) = &config.rope_freq_weight {
+            // The weights are loaded on the device of the embeddings, which may not be the device of this layer
+            let weight = weight.to_device(device)?;
             inverse_frequency = inverse_frequency.mul(&weight.reshape((1, ()))?)?;
         }
 
@@ -31,11 +44,86 @@ impl RopeCache {
                 .to_dtype(dtype)?;
 
         let outer_product = llama_context_length_indices.matmul(&inverse_frequency)?;
+        let inverse_frequency = inverse_frequency.to_dtype(DType::F32)?;
+
+        let mut sin = outer_product.sin()?;
+        let mut cos = outer_product.cos()?;
+        // YaRN scales the attention logits by scaling the rotary embeddings of the queries and keys
+        if attention_factor != 1. {
+            sin = (sin * attention_factor as f64)?;
+            cos = (cos * attention_factor as f64)?;
+        }
+
+        Ok(Self {
+            sin,
+            cos,
+            inverse_frequency,
+        })
+    }
 
-        let sin = outer_product.sin()?;
-        let cos = outer_product.cos()?;
+    /// Copy the cache to another device.
+    pub(crate) fn to_device(&self, device: &Device) -> candle_core::Result<Self> {
+        Ok(Self {
+            sin: self.sin.to_device(device)?,
+            cos: self.cos.to_device(device)?,
+            inverse_frequency: self.inverse_frequency.to_device(device)?,
+        })
+    }
 
-        Ok(Self { sin, cos })
+    /// Rotate keys that already have position embeddings back by `shift` positions. This moves keys in the cache to
+    /// earlier positions after the tokens before them are evicted.
+    pub fn shift_keys_back(
+        &self,
+        k: &Tensor,
+        shift: usize,
+        interleaved: bool,
+    ) -> candle_core::Result<Tensor> {
+        let (_b_sz, _n_head, seq_len, _n_embd) = k.dims4()?;
+        let angles = (&self.inverse_frequency * -(shift as f64))?;
+        let half_dim = angles.dim(1)?;
+        let cos = angles
+            .cos()?
+            .broadcast_as((seq_len, half_dim))?
+            .to_dtype(k.dtype())?
+            .contiguous()?;
+        let sin = angles
+            .sin()?
+            .broadcast_as((seq_len, half_dim))?
+            .to_dtype(k.dtype())?
+            .contiguous()?;
+        if interleaved {
+            candle_nn::rotary_emb::rope_i(&k.contiguous()?, &cos, &sin)
+        } else {
+            candle_nn::rotary_emb::rope(&k.contiguous()?, &cos, &sin)
+        }
+    }
+
+    /// Apply the position embeddings with operations that support backpropagation.
+    pub(crate) fn forward_differentiable(
+        &self,
+        q: &Tensor,
+        k: &Tensor,
+        start_pos: usize,
+        interleaved: bool,
+    ) -> candle_core::Result<(Tensor, Tensor)> {
+        let apply_rotary_emb = if interleaved {
+            candle_nn::rotary_emb::rope_i_slow
+        } else {
+            candle_nn::rotary_emb::rope_slow
+        };
+        let (_b_sz, _n_head, seq_len, _n_embd) = q.dims4()?;
+        let cos = self
+            .cos
+            .narrow(0, start_pos, seq_len)?
+            .to_dtype(q.dtype())?;
+        let sin = self
+            .sin
+            .narrow(0, start_pos, seq_len)?
+            .to_dtype(q.dtype())?;
+        Ok((
+            apply_rotary_emb(&q.contiguous()?, &cos, &sin)?,
+            apply_rotary_emb(&k.contiguous()?, &cos, &sin)?,
+        ))
     }
 
     fn forward_with_embed(
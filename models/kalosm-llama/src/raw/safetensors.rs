@@ -0,0 +1,437 @@
+use std::sync::Arc;
+
+use candle_core::quantized::{GgmlDType, QMatMul, QTensor};
+use candle_core::safetensors::{BufferedSafetensors, MmapedSafetensors};
+use candle_core::{DType, Device, Tensor};
+use candle_nn::Embedding;
+use serde::Deserialize;
+use tokenizers::Tokenizer;
+
+use super::attention_layer::{
+    AttentionBias, AttentionVariant, FeedForwardActivation, FeedForwardVariant, LlamaAttention,
+    LlamaFeedForward, SeparateAttention,
+};
+use super::norm::RmsNorm;
+use super::placement::LayerPlacement;
+use super::{decode_norm, LlamaConfig, Model};
+use crate::chat_template::HuggingFaceChatTemplate;
+use crate::rope_scaling::ContextOptions;
+use crate::shards::ModelFile;
+use crate::LlamaSourceError;
+
+/// One or more token ids in a Hugging Face `config.json`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TokenIds {
+    One(u32),
+    Many(Vec<u32>),
+}
+
+impl TokenIds {
+    fn first(&self) -> Option<u32> {
+        match self {
+            TokenIds::One(id) => Some(*id),
+            TokenIds::Many(ids) => ids.first().copied(),
+        }
+    }
+}
+
+/// The Llama 3 rope scaling parameters in a Hugging Face `config.json`.
+#[derive(Debug, Clone, Deserialize)]
+struct RopeScaling {
+    #[serde(alias = "type")]
+    rope_type: String,
+    factor: f32,
+    #[serde(default = "default_low_freq_factor")]
+    low_freq_factor: f32,
+    #[serde(default = "default_high_freq_factor")]
+    high_freq_factor: f32,
+    original_max_position_embeddings: Option<usize>,
+}
+
+fn default_low_freq_factor() -> f32 {
+    1.
+}
+
+fn default_high_freq_factor() -> f32 {
+    4.
+}
+
+fn default_rope_theta() -> f32 {
+    10_000.
+}
+
+fn default_rms_norm_eps() -> f64 {
+    1e-5
+}
+
+/// The subset of a Hugging Face `config.json` needed to load an unquantized Llama style model.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SafetensorsConfig {
+    model_type: String,
+    hidden_size: usize,
+    num_attention_heads: usize,
+    num_key_value_heads: Option<usize>,
+    num_hidden_layers: usize,
+    head_dim: Option<usize>,
+    #[serde(default = "default_rms_norm_eps")]
+    rms_norm_eps: f64,
+    #[serde(default = "default_rope_theta")]
+    rope_theta: f32,
+    rope_scaling: Option<RopeScaling>,
+    max_position_embeddings: usize,
+    #[serde(default)]
+    tie_word_embeddings: bool,
+    bos_token_id: Option<TokenIds>,
+    eos_token_id: Option<TokenIds>,
+}
+
+/// The subset of a Hugging Face `tokenizer_config.json` needed to load the chat template.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct SafetensorsTokenizerConfig {
+    chat_template: Option<String>,
+}
+
+impl SafetensorsTokenizerConfig {
+    pub(crate) fn chat_template(self) -> Option<String> {
+        self.chat_template
+    }
+}
+
+/// Get the factor to multiply each rope frequency by for Llama 3 style rope scaling.
+fn llama_3_rope_frequency_factors(
+    scaling: &RopeScaling,
+    rope_theta: f32,
+    head_dim: usize,
+    original_context_length: usize,
+) -> Vec<f32> {
+    let low_freq_wavelen = original_context_length as f32 / scaling.low_freq_factor;
+    let high_freq_wavelen = original_context_length as f32 / scaling.high_freq_factor;
+    (0..head_dim)
+        .step_by(2)
+        .map(|i| {
+            let frequency = 1. / rope_theta.powf(i as f32 / head_dim as f32);
+            let wavelen = 2. * std::f32::consts::PI / frequency;
+            if wavelen < high_freq_wavelen {
+                1.
+            } else if wavelen > low_freq_wavelen {
+                1. / scaling.factor
+            } else {
+                let smooth = (original_context_length as f32 / wavelen - scaling.low_freq_factor)
+                    / (scaling.high_freq_factor - scaling.low_freq_factor);
+                (1. - smooth) / scaling.factor + smooth
+            }
+        })
+        .collect()
+}
+
+/// The weights of an unquantized model split over one or more safetensors files.
+struct SafetensorsWeights {
+    /// The files on disk. They are memory mapped
+    mmaped: Option<MmapedSafetensors>,
+    /// The in-memory files. They are read from their buffer instead of being written to disk first
+    buffered: Vec<BufferedSafetensors>,
+}
+
+impl SafetensorsWeights {
+    fn new(files: &[ModelFile]) -> candle_core::Result<Self> {
+        let paths: Vec<_> = files.iter().filter_map(ModelFile::path).collect();
+        let mmaped = if paths.is_empty() {
+            None
+        } else {
+            Some(unsafe { MmapedSafetensors::multi(&paths)? })
+        };
+        let buffered = files
+            .iter()
+            .filter_map(|file| match file {
+                ModelFile::Bytes { bytes, .. } => Some(BufferedSafetensors::new(bytes.to_vec())),
+                ModelFile::Path(_) => None,
+            })
+            .collect::<candle_core::Result<_>>()?;
+        Ok(Self { mmaped, buffered })
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.buffered.iter().any(|file| file.get(name).is_ok())
+            || self
+                .mmaped
+                .as_ref()
+                .is_some_and(|mmaped| mmaped.get(name).is_ok())
+    }
+
+    fn load(&self, name: &str, device: &Device) -> candle_core::Result<Tensor> {
+        if let Some(file) = self.buffered.iter().find(|file| file.get(name).is_ok()) {
+            return file.load(name, device);
+        }
+        match &self.mmaped {
+            Some(mmaped) => mmaped.load(name, device),
+            None => Err(candle_core::Error::CannotFindTensor {
+                path: name.to_string(),
+            }),
+        }
+    }
+
+    fn tensor(&self, name: &str, device: &Device) -> candle_core::Result<Tensor> {
+        self.load(name, device)?.to_dtype(DType::F32)
+    }
+
+    fn linear(&self, name: &str, device: &Device) -> candle_core::Result<QMatMul> {
+        let weight = self.load(name, device)?;
+        // Accelerators keep the weights in f16 to halve the memory use
+        if device.is_cpu() {
+            Ok(QMatMul::Tensor(weight.to_dtype(DType::F32)?))
+        } else {
+            Ok(QMatMul::TensorF16(weight.to_dtype(DType::F16)?))
+        }
+    }
+
+    fn norm(&self, name: &str, eps: f64, device: &Device) -> candle_core::Result<RmsNorm> {
+        let weight = self.tensor(name, device)?;
+        decode_norm(QTensor::quantize(&weight, GgmlDType::F32)?, eps)
+    }
+
+    fn optional_norm(
+        &self,
+        name: &str,
+        eps: f64,
+        device: &Device,
+    ) -> candle_core::Result<Option<RmsNorm>> {
+        self.contains(name)
+            .then(|| self.norm(name, eps, device))
+            .transpose()
+    }
+}
+
+impl Model {
+    /// Load an unquantized Llama, Mistral, Qwen 2 or OLMo 2 model from Hugging Face safetensors files.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_safetensors(
+        files: &[ModelFile],
+        config: SafetensorsConfig,
+        tokenizer: &Tokenizer,
+        chat_template: Option<String>,
+        device: &Device,
+        override_stop_token_string: Option<String>,
+        context_options: ContextOptions,
+        gpu_layers: Option<usize>,
+        device_map: &[Device],
+    ) -> std::result::Result<Self, LlamaSourceError> {
+        if !matches!(
+            config.model_type.as_str(),
+            "llama" | "mistral" | "qwen2" | "olmo2"
+        ) {
+            return Err(LlamaSourceError::UnsupportedArchitecture(config.model_type));
+        }
+        // OLMo 2 normalizes the output of each block instead of the input
+        let post_norm = config.model_type == "olmo2";
+        let tie_word_embeddings = config.tie_word_embeddings;
+        let weights = SafetensorsWeights::new(files)?;
+
+        let token_string = |id: Option<u32>| id.and_then(|id| tokenizer.id_to_token(id));
+        let (stop_token, stop_token_string) = match override_stop_token_string {
+            Some(stop_token_string) => (
+                tokenizer
+                    .token_to_id(&stop_token_string)
+                    .ok_or(LlamaSourceError::NoStopToken)?,
+                stop_token_string,
+            ),
+            None => {
+                let stop_token = config
+                    .eos_token_id
+                    .as_ref()
+                    .and_then(TokenIds::first)
+                    .ok_or(LlamaSourceError::NoStopToken)?;
+                let stop_token_string =
+                    token_string(Some(stop_token)).ok_or(LlamaSourceError::NoStopToken)?;
+                (stop_token, stop_token_string)
+            }
+        };
+        let start_token_string =
+            token_string(config.bos_token_id.as_ref().and_then(TokenIds::first))
+                .unwrap_or_default();
+        let chat_template = chat_template
+            .map(HuggingFaceChatTemplate::create)
+            .transpose()
+            .map_err(LlamaSourceError::ChatTemplate)?;
+
+        let head_count = config.num_attention_heads;
+        let head_count_kv = config.num_key_value_heads.unwrap_or(head_count);
+        let head_dim = config
+            .head_dim
+            .unwrap_or(config.hidden_size / config.num_attention_heads);
+        let block_count = config.num_hidden_layers;
+        let eps = config.rms_norm_eps;
+        let file_rope_scaling = config.rope_scaling.as_ref().and_then(|scaling| {
+            crate::RopeScaling::from_metadata(&scaling.rope_type, scaling.factor, None)
+        });
+        let (context_length, rope_scaling) =
+            context_options.resolve(config.max_position_embeddings, file_rope_scaling);
+        let rope_freq_weight = match &config.rope_scaling {
+            Some(scaling) if scaling.rope_type == "llama3" => {
+                let factors = llama_3_rope_frequency_factors(
+                    scaling,
+                    config.rope_theta,
+                    head_dim,
+                    scaling
+                        .original_max_position_embeddings
+                        .unwrap_or(config.max_position_embeddings),
+                );
+                let len = factors.len();
+                Some(Tensor::from_vec(factors, len, device)?)
+            }
+            _ => None,
+        };
+
+        let config = Arc::new(LlamaConfig {
+            rope_freq_weight,
+            rope_theta: config.rope_theta,
+            rope_scaling,
+            original_context_length: config
+                .rope_scaling
+                .as_ref()
+                .and_then(|scaling| scaling.original_max_position_embeddings)
+                .unwrap_or(config.max_position_embeddings),
+            context_length,
+            head_dimension: head_dim,
+            n_head: head_count,
+            n_layer: block_count,
+            start_token_string,
+            stop_token,
+            stop_token_string,
+            chat_template,
+            embedding_scale: None,
+            residual_scale: None,
+            logit_scale: None,
+            final_logit_cap: None,
+            kv_cache_quantization: context_options.kv_cache_quantization,
+        });
+        // The output head runs on the same device as the last layer
+        let placement = LayerPlacement::new(&config, device, block_count, gpu_layers, device_map)?;
+        let output_device = placement.output_device();
+
+        let tok_embeddings = weights.tensor("model.embed_tokens.weight", device)?;
+        let embedding_length = tok_embeddings.dim(1)?;
+        let norm = weights.norm("model.norm.weight", eps, output_device)?;
+        let output = if tie_word_embeddings || !weights.contains("lm_head.weight") {
+            // If there is no output layer, the word embeddings are tied to the output
+            weights.linear("model.embed_tokens.weight", output_device)?
+        } else {
+            weights.linear("lm_head.weight", output_device)?
+        };
+
+        let mut layers = Vec::with_capacity(block_count);
+        for layer_idx in 0..block_count {
+            let device = placement.layer_device(layer_idx);
+            let rope = placement.rope(layer_idx);
+            let prefix = format!("model.layers.{layer_idx}");
+            let attention = format!("{prefix}.self_attn");
+            let bias = if weights.contains(&format!("{attention}.q_proj.bias")) {
+                Some(AttentionBias {
+                    bias_q: weights.tensor(&format!("{attention}.q_proj.bias"), device)?,
+                    bias_k: weights.tensor(&format!("{attention}.k_proj.bias"), device)?,
+                    bias_v: weights.tensor(&format!("{attention}.v_proj.bias"), device)?,
+                })
+            } else {
+                None
+            };
+            // Hugging Face checkpoints are not permuted for interleaved rope like gguf files
+            let attention_variant = AttentionVariant::Separate(SeparateAttention {
+                attention_wq: weights.linear(&format!("{attention}.q_proj.weight"), device)?,
+                attention_wk: weights.linear(&format!("{attention}.k_proj.weight"), device)?,
+                attention_wv: weights.linear(&format!("{attention}.v_proj.weight"), device)?,
+                interleaved_rope: false,
+                bias,
+                q_norm: weights.optional_norm(
+                    &format!("{attention}.q_norm.weight"),
+                    eps,
+                    device,
+                )?,
+                k_norm: weights.optional_norm(
+                    &format!("{attention}.k_norm.weight"),
+                    eps,
+                    device,
+                )?,
+            });
+            let mlp = format!("{prefix}.mlp");
+            let feed_forward_variant = FeedForwardVariant::Llama(LlamaFeedForward {
+                feed_forward_w1: weights.linear(&format!("{mlp}.gate_proj.weight"), device)?,
+                feed_forward_w2: weights.linear(&format!("{mlp}.down_proj.weight"), device)?,
+                feed_forward_w3: weights.linear(&format!("{mlp}.up_proj.weight"), device)?,
+                activation: FeedForwardActivation::Silu,
+            });
+            let post_attention_layernorm = weights.norm(
+                &format!("{prefix}.post_attention_layernorm.weight"),
+                eps,
+                device,
+            )?;
+            let (attention_norm, post_attention_norm, ffn_norm, post_ffn_norm) = if post_norm {
+                (
+                    None,
+                    Some(post_attention_layernorm),
+                    None,
+                    Some(weights.norm(
+                        &format!("{prefix}.post_feedforward_layernorm.weight"),
+                        eps,
+                        device,
+                    )?),
+                )
+            } else {
+                (
+                    Some(weights.norm(&format!("{prefix}.input_layernorm.weight"), eps, device)?),
+                    None,
+                    Some(post_attention_layernorm),
+                    None,
+                )
+            };
+            layers.push(LlamaAttention {
+                attention_variant,
+                attention_wo: weights.linear(&format!("{attention}.o_proj.weight"), device)?,
+                attention_norm,
+                post_attention_norm,
+                feed_forward_variant,
+                ffn_norm,
+                post_ffn_norm,
+                attention_scale: None,
+                attention_logit_cap: None,
+                sliding_window: None,
+                n_head: head_count,
+                n_kv_head: head_count_kv,
+                head_dim,
+                hidden_size: config.hidden_size(),
+                rope_cache: rope.clone(),
+                device: device.clone(),
+                flash_attn: false,
+            })
+        }
+
+        Ok(Self {
+            config,
+            tok_embeddings: Embedding::new(tok_embeddings, embedding_length),
+            layers,
+            norm,
+            output,
+            output_device: output_device.clone(),
+            added_tokens: 0,
+            added_token_mask: None,
+            masks: Default::default(),
+            device_masks: placement.device_masks(),
+        })
+    }
+}
+
+#[test]
+fn llama_3_rope_scaling() {
+    let scaling = RopeScaling {
+        rope_type: "llama3".to_string(),
+        factor: 8.,
+        low_freq_factor: 1.,
+        high_freq_factor: 4.,
+        original_max_position_embeddings: Some(8192),
+    };
+    let factors = llama_3_rope_frequency_factors(&scaling, 500_000., 128, 8192);
+    assert_eq!(factors.len(), 64);
+    // High frequencies are not scaled and the lowest frequencies are scaled by the full factor
+    assert_eq!(factors[0], 1.);
+    assert_eq!(factors[63], 1. / 8.);
+    assert!(factors.windows(2).all(|window| window[1] <= window[0]));
+}
@@ -0,0 +1,269 @@
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, IndexOp, Module, Result, Tensor};
+use candle_nn::{Conv2d, Conv2dConfig, LayerNorm, Linear};
+use image::imageops::FilterType;
+
+/// The mean and standard deviation CLIP normalizes pixels with if the projector doesn't include them.
+const CLIP_IMAGE_MEAN: [f32; 3] = [0.481_454_66, 0.457_827_5, 0.408_210_73];
+const CLIP_IMAGE_STD: [f32; 3] = [0.268_629_54, 0.261_302_6, 0.275_777_1];
+
+/// A CLIP vision encoder and the projector that maps its features into the embedding space of the language model.
+/// This is loaded from the llama.cpp `mmproj` gguf files distributed with LLaVA models.
+pub(crate) struct VisionEncoder {
+    patch_embedding: Conv2d,
+    class_embedding: Tensor,
+    position_embedding: Tensor,
+    pre_norm: Option<LayerNorm>,
+    layers: Vec<VisionLayer>,
+    post_norm: Option<LayerNorm>,
+    /// The linear layers of the projector with a GELU between each layer
+    projector: Vec<Linear>,
+    image_size: usize,
+    image_mean: [f32; 3],
+    image_std: [f32; 3],
+    n_head: usize,
+    /// CLIP uses `x * sigmoid(1.702 * x)` instead of GELU unless the projector says otherwise
+    quick_gelu: bool,
+    device: Device,
+}
+
+struct VisionLayer {
+    norm1: LayerNorm,
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    out: Linear,
+    norm2: LayerNorm,
+    fc1: Linear,
+    fc2: Linear,
+}
+
+fn load<R: std::io::Seek + std::io::Read>(
+    ct: &gguf_file::Content,
+    reader: &mut R,
+    name: &str,
+    device: &Device,
+) -> Result<Tensor> {
+    ct.tensor(reader, name, device)?.dequantize(device)
+}
+
+fn linear<R: std::io::Seek + std::io::Read>(
+    ct: &gguf_file::Content,
+    reader: &mut R,
+    prefix: &str,
+    device: &Device,
+) -> Result<Linear> {
+    let weight = load(ct, reader, &format!("{prefix}.weight"), device)?;
+    let bias = load(ct, reader, &format!("{prefix}.bias"), device).ok();
+    Ok(Linear::new(weight, bias))
+}
+
+fn layer_norm<R: std::io::Seek + std::io::Read>(
+    ct: &gguf_file::Content,
+    reader: &mut R,
+    prefix: &str,
+    eps: f64,
+    device: &Device,
+) -> Result<LayerNorm> {
+    let weight = load(ct, reader, &format!("{prefix}.weight"), device)?;
+    let bias = load(ct, reader, &format!("{prefix}.bias"), device)?;
+    Ok(LayerNorm::new(weight, bias, eps))
+}
+
+impl VisionEncoder {
+    pub(crate) fn from_gguf<R: std::io::Seek + std::io::Read>(
+        ct: gguf_file::Content,
+        reader: &mut R,
+        device: &Device,
+    ) -> Result<Self> {
+        let md_get = |key: &str| match ct.metadata.get(key) {
+            Some(value) => Ok(value),
+            None => candle_core::bail!("cannot find {key} in the vision projector metadata"),
+        };
+        let channels = |key: &str, default: [f32; 3]| -> Result<[f32; 3]> {
+            let Ok(values) = md_get(key) else {
+                return Ok(default);
+            };
+            let values = values
+                .to_vec()?
+                .iter()
+                .map(|value| value.to_f32())
+                .collect::<Result<Vec<_>>>()?;
+            values
+                .try_into()
+                .map_err(|_| candle_core::Error::Msg(format!("{key} should have three channels")))
+        };
+
+        let image_size = md_get("clip.vision.image_size")?.to_u32()? as usize;
+        let patch_size = md_get("clip.vision.patch_size")?.to_u32()? as usize;
+        let hidden_size = md_get("clip.vision.embedding_length")?.to_u32()? as usize;
+        let block_count = md_get("clip.vision.block_count")?.to_u32()? as usize;
+        let n_head = md_get("clip.vision.attention.head_count")?.to_u32()? as usize;
+        let eps = md_get("clip.vision.attention.layer_norm_epsilon")?.to_f32()? as f64;
+        let quick_gelu = !md_get("clip.use_gelu")
+            .and_then(|value| value.to_bool())
+            .unwrap_or(false);
+        let image_mean = channels("clip.vision.image_mean", CLIP_IMAGE_MEAN)?;
+        let image_std = channels("clip.vision.image_std", CLIP_IMAGE_STD)?;
+
+        let patch_embedding = Conv2d::new(
+            load(&ct, reader, "v.patch_embd.weight", device)?,
+            load(&ct, reader, "v.patch_embd.bias", device).ok(),
+            Conv2dConfig {
+                stride: patch_size,
+                ..Default::default()
+            },
+        );
+        let class_embedding = load(&ct, reader, "v.class_embd", device)?;
+        let position_embedding = load(&ct, reader, "v.position_embd.weight", device)?;
+        let pre_norm = layer_norm(&ct, reader, "v.pre_ln", eps, device).ok();
+        let post_norm = layer_norm(&ct, reader, "v.post_ln", eps, device).ok();
+
+        let mut layers = Vec::with_capacity(block_count);
+        for i in 0..block_count {
+            let prefix = format!("v.blk.{i}");
+            // The llama.cpp converter stores the first feed forward layer as `ffn_down`. Check the shape in case a
+            // file uses the other order.
+            let ffn_down = linear(&ct, reader, &format!("{prefix}.ffn_down"), device)?;
+            let ffn_up = linear(&ct, reader, &format!("{prefix}.ffn_up"), device)?;
+            let (fc1, fc2) = if ffn_down.weight().dim(1)? == hidden_size {
+                (ffn_down, ffn_up)
+            } else {
+                (ffn_up, ffn_down)
+            };
+            layers.push(VisionLayer {
+                norm1: layer_norm(&ct, reader, &format!("{prefix}.ln1"), eps, device)?,
+                q: linear(&ct, reader, &format!("{prefix}.attn_q"), device)?,
+                k: linear(&ct, reader, &format!("{prefix}.attn_k"), device)?,
+                v: linear(&ct, reader, &format!("{prefix}.attn_v"), device)?,
+                out: linear(&ct, reader, &format!("{prefix}.attn_out"), device)?,
+                norm2: layer_norm(&ct, reader, &format!("{prefix}.ln2"), eps, device)?,
+                fc1,
+                fc2,
+            });
+        }
+
+        // The LLaVA MLP projector is stored as `mm.0` and `mm.2` with the activation between them
+        let projector = (0..8)
+            .filter_map(|i| linear(&ct, reader, &format!("mm.{i}"), device).ok())
+            .collect::<Vec<_>>();
+        if projector.is_empty() {
+            candle_core::bail!("the vision projector file doesn't contain an MLP projector");
+        }
+
+        Ok(Self {
+            patch_embedding,
+            class_embedding,
+            position_embedding,
+            pre_norm,
+            layers,
+            post_norm,
+            projector,
+            image_size,
+            image_mean,
+            image_std,
+            n_head,
+            quick_gelu,
+            device: device.clone(),
+        })
+    }
+
+    /// Encode an image file into embeddings for the language model with the shape `(patches, hidden_size)`.
+    pub(crate) fn encode_image(&self, bytes: &[u8]) -> Result<Tensor> {
+        let image = image::load_from_memory(bytes).map_err(candle_core::Error::wrap)?;
+        let pixels = self.preprocess(image)?;
+
+        let patches = self.patch_embedding.forward(&pixels)?;
+        let hidden_size = patches.dim(1)?;
+        let patches = patches.flatten_from(2)?.transpose(1, 2)?;
+        let class = self.class_embedding.reshape((1, 1, hidden_size))?;
+        let mut x = Tensor::cat(&[&class, &patches], 1)?
+            .broadcast_add(&self.position_embedding.unsqueeze(0)?)?;
+        if let Some(norm) = &self.pre_norm {
+            x = norm.forward(&x)?;
+        }
+        for layer in &self.layers {
+            x = layer.forward(&x, self.n_head, self.quick_gelu)?;
+        }
+        if let Some(norm) = &self.post_norm {
+            x = norm.forward(&x)?;
+        }
+
+        // LLaVA drops the class token and projects the features of each patch
+        let mut x = x.i((0, 1.., ..))?;
+        for (i, linear) in self.projector.iter().enumerate() {
+            if i > 0 {
+                x = x.gelu()?;
+            }
+            x = linear.forward(&x)?;
+        }
+        Ok(x)
+    }
+
+    /// Pad the image to a square with the mean color like LLaVA 1.5, resize it to the input size of the encoder and
+    /// normalize the pixels.
+    fn preprocess(&self, image: image::DynamicImage) -> Result<Tensor> {
+        let image = image.to_rgb8();
+        let (width, height) = image.dimensions();
+        let side = width.max(height);
+        let background = image::Rgb(self.image_mean.map(|mean| (mean * 255.).round() as u8));
+        let mut square = image::RgbImage::from_pixel(side, side, background);
+        image::imageops::overlay(
+            &mut square,
+            &image,
+            ((side - width) / 2) as i64,
+            ((side - height) / 2) as i64,
+        );
+        let size = self.image_size as u32;
+        let resized = image::imageops::resize(&square, size, size, FilterType::CatmullRom);
+
+        let pixels = Tensor::from_vec(
+            resized.into_raw(),
+            (self.image_size, self.image_size, 3),
+            &Device::Cpu,
+        )?
+        .permute((2, 0, 1))?
+        .to_dtype(DType::F32)?;
+        let pixels = (pixels / 255.)?;
+        let mean = Tensor::new(&self.image_mean, &Device::Cpu)?.reshape((3, 1, 1))?;
+        let std = Tensor::new(&self.image_std, &Device::Cpu)?.reshape((3, 1, 1))?;
+        pixels
+            .broadcast_sub(&mean)?
+            .broadcast_div(&std)?
+            .unsqueeze(0)?
+            .to_device(&self.device)
+    }
+}
+
+impl VisionLayer {
+    fn forward(&self, x: &Tensor, n_head: usize, quick_gelu: bool) -> Result<Tensor> {
+        let residual = x;
+        let hidden_states = self.norm1.forward(x)?;
+        let (batch, seq_len, hidden_size) = hidden_states.dims3()?;
+        let head_dim = hidden_size / n_head;
+        let split_heads = |x: Tensor| {
+            x.reshape((batch, seq_len, n_head, head_dim))?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let q = split_heads((self.q.forward(&hidden_states)? / (head_dim as f64).sqrt())?)?;
+        let k = split_heads(self.k.forward(&hidden_states)?)?;
+        let v = split_heads(self.v.forward(&hidden_states)?)?;
+        let attention = candle_nn::ops::softmax_last_dim(&q.matmul(&k.t()?)?)?;
+        let attention =
+            attention
+                .matmul(&v)?
+                .transpose(1, 2)?
+                .reshape((batch, seq_len, hidden_size))?;
+        let x = (self.out.forward(&attention)? + residual)?;
+
+        let residual = &x;
+        let hidden_states = self.fc1.forward(&self.norm2.forward(&x)?)?;
+        let hidden_states = if quick_gelu {
+            (&hidden_states * candle_nn::ops::sigmoid(&(&hidden_states * 1.702)?)?)?
+        } else {
+            hidden_states.gelu()?
+        };
+        self.fc2.forward(&hidden_states)? + residual
+    }
+}
@@ -0,0 +1,229 @@
+/// How the rotary position embeddings are stretched to run a model past the context length it was trained on. Set
+/// it with [`LlamaSource::with_rope_scaling`](crate::LlamaSource::with_rope_scaling) or
+/// [`LlamaBuilder::with_rope_scaling`](crate::LlamaBuilder::with_rope_scaling).
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// // Run a model trained on 8k tokens with a 32k context
+/// let model = Llama::builder()
+///     .with_source(LlamaSource::llama_8b_chat())
+///     .with_rope_scaling(RopeScaling::yarn(4.0))
+///     .with_context_length(32 * 1024)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RopeScaling {
+    /// Divide every position by the factor (position interpolation). This works well for small factors, but the
+    /// model usually needs fine tuning to recover the quality of short contexts.
+    Linear {
+        /// How much longer the new context is than the trained context.
+        factor: f32,
+    },
+    /// Raise the rope base frequency so high frequencies are extrapolated and low frequencies are interpolated
+    /// (NTK-aware scaling). This works without fine tuning for moderate factors.
+    Ntk {
+        /// How much longer the new context is than the trained context.
+        factor: f32,
+    },
+    /// Blend interpolation and extrapolation per dimension and scale the attention logits (YaRN).
+    Yarn {
+        /// How much longer the new context is than the trained context.
+        factor: f32,
+        /// Dimensions that rotate more than this many times in the trained context are extrapolated. (Defaults to 32)
+        beta_fast: f32,
+        /// Dimensions that rotate fewer than this many times in the trained context are interpolated. (Defaults to 1)
+        beta_slow: f32,
+        /// The factor the rotary embeddings are multiplied by. (Defaults to `0.1 * ln(factor) + 1`)
+        attention_factor: Option<f32>,
+    },
+}
+
+impl RopeScaling {
+    /// Create linear rope scaling with the given factor.
+    pub fn linear(factor: f32) -> Self {
+        Self::Linear { factor }
+    }
+
+    /// Create NTK-aware rope scaling with the given factor.
+    pub fn ntk(factor: f32) -> Self {
+        Self::Ntk { factor }
+    }
+
+    /// Create YaRN rope scaling with the given factor and the default betas from the YaRN paper.
+    pub fn yarn(factor: f32) -> Self {
+        Self::Yarn {
+            factor,
+            beta_fast: 32.,
+            beta_slow: 1.,
+            attention_factor: None,
+        }
+    }
+
+    /// Get how much longer the scaled context is than the trained context.
+    pub fn factor(&self) -> f32 {
+        match self {
+            Self::Linear { factor } | Self::Ntk { factor } | Self::Yarn { factor, .. } => *factor,
+        }
+    }
+
+    /// Read the scaling type and factor recorded in a gguf file or Hugging Face config.
+    pub(crate) fn from_metadata(
+        scaling_type: &str,
+        factor: f32,
+        attention_factor: Option<f32>,
+    ) -> Option<Self> {
+        if factor <= 1. {
+            return None;
+        }
+        match scaling_type {
+            "linear" => Some(Self::linear(factor)),
+            "yarn" => Some(Self::Yarn {
+                factor,
+                beta_fast: 32.,
+                beta_slow: 1.,
+                attention_factor,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the inverse frequency of each pair of dimensions and the factor the sin and cos tables are multiplied by.
+    pub(crate) fn inverse_frequencies(
+        &self,
+        rope_theta: f32,
+        head_dimension: usize,
+        original_context_length: usize,
+    ) -> (Vec<f32>, f32) {
+        let frequencies = |theta: f32| {
+            (0..head_dimension)
+                .step_by(2)
+                .map(|i| 1. / theta.powf(i as f32 / head_dimension as f32))
+                .collect::<Vec<_>>()
+        };
+        match *self {
+            Self::Linear { factor } => (
+                frequencies(rope_theta)
+                    .into_iter()
+                    .map(|frequency| frequency / factor)
+                    .collect(),
+                1.,
+            ),
+            Self::Ntk { factor } => {
+                let dimension = head_dimension as f32;
+                let theta = rope_theta * factor.powf(dimension / (dimension - 2.));
+                (frequencies(theta), 1.)
+            }
+            Self::Yarn {
+                factor,
+                beta_fast,
+                beta_slow,
+                attention_factor,
+            } => {
+                // The dimension that completes `rotations` full rotations over the trained context
+                let correction_dimension = |rotations: f32| {
+                    head_dimension as f32
+                        * (original_context_length as f32 / (rotations * 2. * std::f32::consts::PI))
+                            .ln()
+                        / (2. * rope_theta.ln())
+                };
+                let low = correction_dimension(beta_fast).floor().max(0.);
+                let high = correction_dimension(beta_slow)
+                    .ceil()
+                    .min(head_dimension as f32 - 1.);
+                let high = if low == high { high + 0.001 } else { high };
+                let frequencies = frequencies(rope_theta)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, frequency)| {
+                        let ramp = ((i as f32 - low) / (high - low)).clamp(0., 1.);
+                        let extrapolation = 1. - ramp;
+                        frequency / factor * ramp + frequency * extrapolation
+                    })
+                    .collect();
+                let attention_factor = attention_factor.unwrap_or(0.1 * factor.ln() + 1.);
+                (frequencies, attention_factor)
+            }
+        }
+    }
+}
+
+/// The context length settings from the source of a model.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ContextOptions {
+    pub(crate) context_length: Option<usize>,
+    pub(crate) max_context_length: Option<usize>,
+    pub(crate) rope_scaling: Option<RopeScaling>,
+    pub(crate) kv_cache_quantization: kalosm_common::KvCacheQuantization,
+}
+
+impl ContextOptions {
+    /// Get the context length and rope scaling for a model trained with `trained_context_length` tokens. The scaling
+    /// set on the source replaces the scaling the model file was converted with.
+    pub(crate) fn resolve(
+        &self,
+        trained_context_length: usize,
+        file_scaling: Option<RopeScaling>,
+    ) -> (usize, Option<RopeScaling>) {
+        let rope_scaling = self.rope_scaling.or(file_scaling);
+        let mut context_length = match (self.context_length, self.rope_scaling) {
+            (Some(context_length), _) => context_length,
+            (None, Some(scaling)) => (trained_context_length as f32 * scaling.factor()) as usize,
+            (None, None) => trained_context_length,
+        };
+        if let Some(max_context_length) = self.max_context_length {
+            context_length = context_length.min(max_context_length);
+        }
+        if rope_scaling.is_none() && context_length > trained_context_length {
+            tracing::warn!(
+                "The context length {context_length} is longer than the {trained_context_length} tokens the model was trained on. Set a rope scaling with `with_rope_scaling` to keep the output coherent past the trained context"
+            );
+        }
+        (context_length, rope_scaling)
+    }
+}
+
+#[test]
+fn rope_scaling_frequencies() {
+    let base = RopeScaling::linear(1.)
+        .inverse_frequencies(10_000., 64, 4096)
+        .0;
+
+    let (linear, attention_factor) = RopeScaling::linear(4.).inverse_frequencies(10_000., 64, 4096);
+    assert_eq!(attention_factor, 1.);
+    for (scaled, base) in linear.iter().zip(&base) {
+        assert!((scaled * 4. - base).abs() < 1e-6);
+    }
+
+    // NTK scaling keeps the highest frequency and interpolates the lowest frequency by the full factor
+    let (ntk, _) = RopeScaling::ntk(4.).inverse_frequencies(10_000., 64, 4096);
+    assert_eq!(ntk[0], base[0]);
+    assert!(ntk[31] < base[31]);
+
+    // YaRN extrapolates the high frequencies and interpolates the low frequencies
+    let (yarn, attention_factor) = RopeScaling::yarn(4.).inverse_frequencies(10_000., 64, 4096);
+    assert!((attention_factor - (0.1 * 4f32.ln() + 1.)).abs() < 1e-6);
+    assert_eq!(yarn[0], base[0]);
+    assert!((yarn[31] * 4. - base[31]).abs() < 1e-6);
+}
+
+#[test]
+fn context_options_extend_the_trained_context() {
+    let options = ContextOptions {
+        rope_scaling: Some(RopeScaling::yarn(4.)),
+        ..Default::default()
+    };
+    assert_eq!(options.resolve(8192, None).0, 32768);
+
+    let options = ContextOptions {
+        context_length: Some(16384),
+        max_context_length: Some(12000),
+        ..Default::default()
+    };
+    let file_scaling = Some(RopeScaling::linear(2.));
+    assert_eq!(options.resolve(8192, file_scaling), (12000, file_scaling));
+}
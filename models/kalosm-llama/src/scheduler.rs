@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use kalosm_language_model::Priority;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::batch::Generation;
+use crate::model::LlamaModel;
+use crate::{LlamaSession, StructuredGenerationTask, Task};
+
+impl Task {
+    fn priority(&self) -> Priority {
+        match self {
+            Task::UnstructuredGeneration(task) => task.settings.priority,
+            Task::StructuredGeneration(task) => task.priority,
+            Task::SwapModel(_) => Priority::Background,
+        }
+    }
+
+    /// Get the session the task writes to, if any.
+    fn session(&self) -> Option<&LlamaSession> {
+        match self {
+            Task::UnstructuredGeneration(task) => Some(&task.settings.session),
+            Task::StructuredGeneration(task) => task.session.as_ref(),
+            Task::SwapModel(_) => None,
+        }
+    }
+
+    /// Check if the task can run while the given generations are in the batch.
+    fn can_run_with(&self, active: &[Generation]) -> bool {
+        match self {
+            // The weights can't change under a generation that is in progress
+            Task::SwapModel(_) => active.is_empty(),
+            _ => !self.session().is_some_and(|session| {
+                active
+                    .iter()
+                    .any(|generation| Arc::ptr_eq(&generation.session.cache, &session.cache))
+            }),
+        }
+    }
+
+    fn run(self, model: &mut LlamaModel) {
+        if let Some(session) = self.session() {
+            model.adopt_session(session);
+        }
+        match self {
+            Task::UnstructuredGeneration(task) => {
+                if let Some(generation) = model.start_generation(*task) {
+                    model.run_generation(generation);
+                }
+            }
+            Task::StructuredGeneration(StructuredGenerationTask { runner, .. }) => {
+                runner(model);
+            }
+            Task::SwapModel(task) => task.run(model),
+        }
+    }
+}
+
+/// The tasks waiting for the model, split by priority.
+pub(crate) struct TaskQueue {
+    receiver: UnboundedReceiver<Task>,
+    interactive: VecDeque<Task>,
+    background: VecDeque<Task>,
+}
+
+impl TaskQueue {
+    fn new(receiver: UnboundedReceiver<Task>) -> Self {
+        Self {
+            receiver,
+            interactive: VecDeque::new(),
+            background: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, task: Task) {
+        match task.priority() {
+            Priority::Interactive => self.interactive.push_back(task),
+            Priority::Background => self.background.push_back(task),
+        }
+    }
+
+    /// Move every task that was sent since the last call into the queues without blocking.
+    fn drain(&mut self) {
+        while let Ok(task) = self.receiver.try_recv() {
+            self.push(task);
+        }
+    }
+
+    /// Wait for the next task. Interactive tasks always run before background tasks. Returns `None` once every
+    /// sender is dropped and the queues are empty.
+    fn next(&mut self) -> Option<Task> {
+        loop {
+            self.drain();
+            if let Some(task) = self
+                .interactive
+                .pop_front()
+                .or_else(|| self.background.pop_front())
+            {
+                return Some(task);
+            }
+            let task = self.receiver.blocking_recv()?;
+            self.push(task);
+        }
+    }
+
+    /// Take the next waiting task that can run while the generations in the batch are in progress without
+    /// blocking. Interactive tasks are taken before background tasks.
+    fn next_ready(&mut self, active: &[Generation]) -> Option<Task> {
+        self.drain();
+        for queue in [&mut self.interactive, &mut self.background] {
+            if let Some(index) = queue.iter().position(|task| task.can_run_with(active)) {
+                return queue.remove(index);
+            }
+        }
+        None
+    }
+
+    /// Take the next waiting interactive task that doesn't use the session of the paused task. Tasks that use the
+    /// same session wait until the paused task finishes.
+    fn next_interactive(&mut self, paused: &LlamaSession) -> Option<Task> {
+        self.drain();
+        let index = self.interactive.iter().position(|task| {
+            !task
+                .session()
+                .is_some_and(|session| Arc::ptr_eq(&session.cache, &paused.cache))
+        })?;
+        self.interactive.remove(index)
+    }
+}
+
+/// Run tasks from the channel on the model until every sender is dropped.
+///
+/// Text generations are decoded together: each iteration admits every waiting generation (up to the max batch size of
+/// the model) and then decodes one token for every active generation in a single batch. Other tasks run between
+/// iterations.
+pub(crate) fn run_tasks(mut model: LlamaModel, receiver: UnboundedReceiver<Task>) {
+    let queue = Arc::new(Mutex::new(TaskQueue::new(receiver)));
+    let mut active: Vec<Generation> = Vec::new();
+    loop {
+        if active.is_empty() {
+            let Some(task) = queue.lock().unwrap().next() else {
+                break;
+            };
+            model.admit(task, &mut active, &queue);
+        }
+        while active.len() < model.max_batch_size {
+            let Some(task) = queue.lock().unwrap().next_ready(&active) else {
+                break;
+            };
+            model.admit(task, &mut active, &queue);
+        }
+        if !active.is_empty() {
+            model.step_generations(&mut active);
+        }
+    }
+}
+
+impl LlamaModel {
+    /// Add a text generation to the batch, or run any other task right away.
+    fn admit(&mut self, task: Task, active: &mut Vec<Generation>, queue: &Arc<Mutex<TaskQueue>>) {
+        match task {
+            Task::UnstructuredGeneration(task) if self.max_batch_size > 1 => {
+                self.adopt_session(&task.settings.session);
+                if let Some(generation) = self.start_generation(*task) {
+                    active.push(generation);
+                }
+            }
+            task if task.priority() == Priority::Background => {
+                // Let the background task hand the model to interactive tasks between tokens
+                self.preemption = Some(queue.clone());
+                task.run(self);
+                self.preemption = None;
+            }
+            task => task.run(self),
+        }
+    }
+}
+
+impl LlamaModel {
+    /// Run any interactive tasks that are waiting for the model. Background generation calls this between tokens.
+    /// The KV cache of the paused task stays parked in its session until generation resumes, so no work is lost.
+    pub(crate) fn yield_to_interactive(&mut self, paused: &LlamaSession) {
+        // Taking the queue also keeps the interactive tasks from yielding themselves
+        let Some(queue) = self.preemption.take() else {
+            return;
+        };
+        loop {
+            let task = queue.lock().unwrap().next_interactive(paused);
+            let Some(task) = task else {
+                break;
+            };
+            task.run(self);
+        }
+        self.preemption = Some(queue);
+    }
+}
+
+#[test]
+fn interactive_tasks_run_first() {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let task = |priority| {
+        Task::StructuredGeneration(StructuredGenerationTask {
+            priority,
+            session: None,
+            runner: Box::new(|_| {}),
+        })
+    };
+    sender.send(task(Priority::Background)).unwrap();
+    sender.send(task(Priority::Interactive)).unwrap();
+    sender.send(task(Priority::Background)).unwrap();
+    drop(sender);
+
+    let mut queue = TaskQueue::new(receiver);
+    let priorities: Vec<_> = std::iter::from_fn(|| queue.next())
+        .map(|task| task.priority())
+        .collect();
+    assert_eq!(
+        priorities,
+        [
+            Priority::Interactive,
+            Priority::Background,
+            Priority::Background
+        ]
+    );
+}
@@ -1,9 +1,23 @@
+use crate::capture::ActivationRecorder;
 use crate::raw::cache::LlamaCache;
-use crate::{accelerated_device_if_available, raw::LlamaConfig};
+use crate::{
+    accelerated_device_if_available, raw::LlamaConfig, ActivationCapture, AttentionSinks,
+    CapturedActivations, Degradation, Llama, LlamaSource,
+};
 use candle_core::{Device, Tensor};
-use kalosm_language_model::TextCompletionSession;
+use kalosm_language_model::{GenerationParameters, TextCompletionSession};
+use kalosm_model_types::FileSource;
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
+use tokenizers::Tokenizer;
+
+/// The version of the file format written by [`LlamaSession::save`].
+const SESSION_FILE_VERSION: &str = "1";
+const VERSION_KEY: &str = "kalosm.session.version";
+const FINGERPRINT_KEY: &str = "kalosm.session.model_fingerprint";
+const SAMPLER_KEY: &str = "kalosm.session.sampler";
+const ATTENTION_SINKS_KEY: &str = "kalosm.session.attention_sinks";
 
 /// An error that can occur when saving or loading a [`LlamaSession`].
 #[derive(Debug, thiserror::Error)]
@@ -17,6 +31,65 @@ pub enum LlamaSessionLoadingError {
     /// The chat messages deserialized from the session are invalid.
     #[error("Chat messages deserialized from the session are invalid")]
     InvalidChatMessages,
+    /// An error reading or writing a session file.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The session file was written by an unsupported version of kalosm.
+    #[error("Unsupported session file version {0:?}")]
+    UnsupportedVersion(Option<String>),
+    /// The session file was saved from a different model.
+    #[error("The session was saved from a different model (expected fingerprint {expected}, found {found})")]
+    ModelMismatch {
+        /// The fingerprint of the model the session is loaded into.
+        expected: String,
+        /// The fingerprint of the model the session was saved from.
+        found: String,
+    },
+    /// The metadata in the session file is invalid.
+    #[error("Invalid session metadata {key}: {value:?}")]
+    InvalidMetadata {
+        /// The metadata key.
+        key: String,
+        /// The invalid value.
+        value: String,
+    },
+}
+
+/// A 64 bit FNV-1a hash. This is stable across versions of Rust, unlike the standard library hasher, so sessions saved
+/// by one build can be checked by another.
+fn stable_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Describe the weights a source loads in a way that is stable across processes: the location of every model and LoRA
+/// file. Files held in memory are identified by their name and size.
+pub(crate) fn weights_identity(source: &LlamaSource) -> String {
+    source
+        .model_files()
+        .iter()
+        .chain(&source.lora)
+        .map(|file| match file {
+            FileSource::Bytes { name, bytes } => format!("memory://{name}:{}", bytes.len()),
+            file => file.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Get a fingerprint of the weights, architecture and tokenizer of a model. Sessions saved from one model can only be
+/// loaded into a model with the same fingerprint.
+pub(crate) fn model_fingerprint(config: &LlamaConfig, tokenizer: &Tokenizer, weights: &str) -> u64 {
+    let mut hash = stable_hash(0xcbf2_9ce4_8422_2325, weights.as_bytes());
+    hash = stable_hash(hash, config.cache_layout().as_bytes());
+    let mut vocab: Vec<_> = tokenizer.get_vocab(true).into_iter().collect();
+    vocab.sort_unstable_by_key(|(_, id)| *id);
+    for (token, id) in vocab {
+        hash = stable_hash(hash, &id.to_le_bytes());
+        hash = stable_hash(hash, token.as_bytes());
+    }
+    hash
 }
 
 /// A Llama session with cached state for the current fed prompt
@@ -62,6 +135,179 @@ impl LlamaSession {
         }
     }
 
+    /// Create a session for a model that hasn't loaded yet. The cache is sized for the model the first time it runs.
+    pub(crate) fn unsized_session() -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(LlamaCache::default())),
+        }
+    }
+
+    /// Get the ways the last generation in this session was degraded to recover from running out of device memory.
+    /// This is empty if the generation ran normally.
+    pub fn degradations(&self) -> Vec<Degradation> {
+        self.cache.read().unwrap().degradations.clone()
+    }
+
+    /// Get the number of tokens in the session. This includes both the prompt and the generated tokens.
+    pub fn token_count(&self) -> usize {
+        self.cache.read().unwrap().tokens.len()
+    }
+
+    /// Set how the session continues once its context is full. With [`AttentionSinks`], the oldest tokens after the
+    /// sink tokens are dropped so the session can continue indefinitely. Without them, the end of the context is
+    /// re-processed from scratch. (Defaults to `None`)
+    pub fn set_attention_sinks(&self, attention_sinks: Option<AttentionSinks>) {
+        self.cache.write().unwrap().attention_sinks = attention_sinks;
+    }
+
+    /// Start capturing the activations of every forward pass the model runs with this session. Any activations that
+    /// were captured before are discarded.
+    pub fn capture_activations(&self, capture: ActivationCapture) {
+        self.cache.write().unwrap().activations = Some(ActivationRecorder::new(capture));
+    }
+
+    /// Take the activations captured since capturing started or since the last call to this method. Capturing
+    /// continues until [`LlamaSession::stop_capturing_activations`] is called.
+    pub fn take_activations(&self) -> CapturedActivations {
+        self.cache
+            .write()
+            .unwrap()
+            .activations
+            .as_mut()
+            .map(ActivationRecorder::take)
+            .unwrap_or_default()
+    }
+
+    /// Stop capturing activations and take any activations that were captured.
+    pub fn stop_capturing_activations(&self) -> CapturedActivations {
+        self.cache
+            .write()
+            .unwrap()
+            .activations
+            .take()
+            .map(|mut recorder| recorder.take())
+            .unwrap_or_default()
+    }
+
+    /// Get the sampler of the last generation in this session, if it used [`GenerationParameters`]. The sampler is
+    /// saved with [`LlamaSession::save`], so a restored session can continue with the same settings.
+    pub fn last_sampler(&self) -> Option<GenerationParameters> {
+        self.cache
+            .read()
+            .unwrap()
+            .sampler
+            .as_ref()
+            .map(GenerationParameters::from)
+    }
+
+    /// Save the session to a versioned safetensors file. The file includes the tokens and kv cache of the session,
+    /// the sampler of the last generation, the attention sink settings and a fingerprint of the model that filled
+    /// the cache.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::new_chat().await?;
+    /// let mut session = model.new_session()?;
+    /// let prompt = "The capital of France is";
+    /// model
+    ///     .stream_text_with_callback(&mut session, prompt, GenerationParameters::new(), |_| Ok(()))
+    ///     .await?;
+    /// session.save("session.safetensors")?;
+    ///
+    /// // Later, restore the session and continue generating
+    /// let mut session = LlamaSession::load("session.safetensors", &model)?;
+    /// let sampler = session.last_sampler().unwrap_or_default();
+    /// model
+    ///     .stream_text_with_callback(&mut session, " It is known for", sampler, |token| {
+    ///         print!("{token}");
+    ///         Ok(())
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), LlamaSessionLoadingError> {
+        let cache = self.cache.read().unwrap();
+        let tensors = cache.get_tensor_map(&Device::Cpu);
+        let mut metadata = HashMap::new();
+        metadata.insert(VERSION_KEY.to_string(), SESSION_FILE_VERSION.to_string());
+        if let Some(fingerprint) = cache.model_fingerprint {
+            metadata.insert(FINGERPRINT_KEY.to_string(), format!("{fingerprint:016x}"));
+        }
+        if let Some(sampler) = &cache.sampler {
+            let sampler = serde_json::to_string(sampler)
+                .expect("the sampler only contains serializable values");
+            metadata.insert(SAMPLER_KEY.to_string(), sampler);
+        }
+        if let Some(sinks) = cache.attention_sinks {
+            metadata.insert(
+                ATTENTION_SINKS_KEY.to_string(),
+                format!("{},{}", sinks.sink_tokens(), sinks.evict_tokens()),
+            );
+        }
+        drop(cache);
+        safetensors::serialize_to_file(&tensors, &Some(metadata), path.as_ref())?;
+        Ok(())
+    }
+
+    /// Load a session saved with [`LlamaSession::save`]. Fails if the session was saved from a model with different
+    /// weights, architecture or tokenizer than `model`.
+    pub fn load(path: impl AsRef<Path>, model: &Llama) -> Result<Self, LlamaSessionLoadingError> {
+        Self::load_for_fingerprint(path, model.fingerprint())
+    }
+
+    fn load_for_fingerprint(
+        path: impl AsRef<Path>,
+        fingerprint: u64,
+    ) -> Result<Self, LlamaSessionLoadingError> {
+        let bytes = std::fs::read(path)?;
+        let (_, header) = safetensors::SafeTensors::read_metadata(&bytes)?;
+        let metadata = header.metadata().clone().unwrap_or_default();
+        match metadata.get(VERSION_KEY) {
+            Some(version) if version == SESSION_FILE_VERSION => {}
+            version => {
+                return Err(LlamaSessionLoadingError::UnsupportedVersion(
+                    version.cloned(),
+                ))
+            }
+        }
+        if let Some(found) = metadata.get(FINGERPRINT_KEY) {
+            let expected = format!("{fingerprint:016x}");
+            if *found != expected {
+                return Err(LlamaSessionLoadingError::ModelMismatch {
+                    expected,
+                    found: found.clone(),
+                });
+            }
+        }
+        let invalid = |key: &str, value: &str| LlamaSessionLoadingError::InvalidMetadata {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        let device = accelerated_device_if_available()?;
+        let tensors = candle_core::safetensors::load_buffer(&bytes, &device)?;
+        let mut cache = LlamaCache::from_tensor_map(tensors)?;
+        cache.model_fingerprint = Some(fingerprint);
+        if let Some(sampler) = metadata.get(SAMPLER_KEY) {
+            cache.sampler =
+                Some(serde_json::from_str(sampler).map_err(|_| invalid(SAMPLER_KEY, sampler))?);
+        }
+        if let Some(sinks) = metadata.get(ATTENTION_SINKS_KEY) {
+            let (sink_tokens, evict_tokens) = sinks
+                .split_once(',')
+                .and_then(|(sink, evict)| Some((sink.parse().ok()?, evict.parse().ok()?)))
+                .ok_or_else(|| invalid(ATTENTION_SINKS_KEY, sinks))?;
+            cache.attention_sinks =
+                Some(AttentionSinks::new(sink_tokens).with_evict_tokens(evict_tokens));
+        }
+        Ok(Self {
+            cache: Arc::new(RwLock::new(cache)),
+        })
+    }
+
     /// Export the current cache tensor map.
     pub fn get_tensor_map(&self, device: &Device) -> HashMap<String, Tensor> {
         let cache = self.cache.read().unwrap();
@@ -82,3 +328,57 @@ impl LlamaSession {
         })
     }
 }
+
+#[test]
+fn saved_sessions_round_trip_and_reject_other_models() {
+    let session = LlamaSession::unsized_session();
+    {
+        let mut cache = session.cache.write().unwrap();
+        let mut single_layer = LlamaConfig::mock_test();
+        single_layer.n_layer = 1;
+        cache.ensure_layers(&single_layer);
+        let values = Tensor::arange(0f32, 6., &Device::Cpu)
+            .unwrap()
+            .reshape((1, 1, 3, 2))
+            .unwrap();
+        cache.blocks[0].push(&values, &values).unwrap();
+        cache.tokens = vec![1, 2, 3];
+        cache.model_fingerprint = Some(42);
+        cache.attention_sinks = Some(AttentionSinks::new(4).with_evict_tokens(8));
+    }
+    let path =
+        std::env::temp_dir().join(format!("kalosm-session-{}.safetensors", std::process::id()));
+    session.save(&path).unwrap();
+
+    let loaded = LlamaSession::load_for_fingerprint(&path, 42).unwrap();
+    assert_eq!(loaded.token_count(), 3);
+    let cache = loaded.cache.read().unwrap();
+    let keys = cache.blocks[0].k().unwrap().unwrap();
+    assert_eq!(
+        keys.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+        [0., 1., 2., 3., 4., 5.]
+    );
+    assert_eq!(
+        cache.attention_sinks,
+        Some(AttentionSinks::new(4).with_evict_tokens(8))
+    );
+    drop(cache);
+
+    // Models with the same architecture and tokenizer but different weights have different fingerprints
+    let config = LlamaConfig::mock_test();
+    let tokenizer = Tokenizer::new(tokenizers::models::wordlevel::WordLevel::default());
+    let weights = |source: LlamaSource| weights_identity(&source);
+    assert_ne!(
+        model_fingerprint(
+            &config,
+            &tokenizer,
+            &weights(LlamaSource::llama_3_1_8b_chat())
+        ),
+        model_fingerprint(&config, &tokenizer, &weights(LlamaSource::llama_8b())),
+    );
+    assert!(matches!(
+        LlamaSession::load_for_fingerprint(&path, 43),
+        Err(LlamaSessionLoadingError::ModelMismatch { .. })
+    ));
+    std::fs::remove_file(path).unwrap();
+}
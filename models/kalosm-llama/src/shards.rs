@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use candle_core::quantized::gguf_file;
+
+/// Get the file names of every shard of a model split with `gguf-split` from the name of one shard. Shards are named
+/// like `model-00001-of-00003.gguf`. Returns `None` if the file name is not a shard name.
+pub(crate) fn shard_file_names(file: &str) -> Option<Vec<String>> {
+    let stem = file.strip_suffix(".gguf")?;
+    let (rest, count) = stem.rsplit_once("-of-")?;
+    let (prefix, index) = rest.rsplit_once('-')?;
+    let is_shard_number =
+        |number: &str| number.len() == 5 && number.bytes().all(|b| b.is_ascii_digit());
+    if !is_shard_number(index) || !is_shard_number(count) {
+        return None;
+    }
+    let count: usize = count.parse().ok()?;
+    if count < 2 {
+        return None;
+    }
+    Some(
+        (1..=count)
+            .map(|index| format!("{prefix}-{index:05}-of-{count:05}.gguf"))
+            .collect(),
+    )
+}
+
+/// A model file that was downloaded to disk or is held in memory.
+#[derive(Debug, Clone)]
+pub(crate) enum ModelFile {
+    Path(PathBuf),
+    Bytes { name: String, bytes: Arc<[u8]> },
+}
+
+impl ModelFile {
+    /// Get the path of the file if it is on disk.
+    pub(crate) fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) => Some(path),
+            Self::Bytes { .. } => None,
+        }
+    }
+
+    /// Get the extension of the file name.
+    pub(crate) fn extension(&self) -> Option<&str> {
+        match self {
+            Self::Path(path) => path.extension().and_then(|extension| extension.to_str()),
+            Self::Bytes { name, .. } => Path::new(name)
+                .extension()
+                .and_then(|extension| extension.to_str()),
+        }
+    }
+
+    /// Open the file for reading.
+    pub(crate) fn open(&self) -> std::io::Result<ModelFileReader> {
+        match self {
+            Self::Path(path) => Ok(ModelFileReader::File(File::open(path)?)),
+            Self::Bytes { bytes, .. } => Ok(ModelFileReader::Bytes(Cursor::new(bytes.clone()))),
+        }
+    }
+
+    /// Check if the file starts with the gguf magic number.
+    pub(crate) fn is_gguf(&self) -> bool {
+        let mut magic = [0; 4];
+        self.open()
+            .and_then(|mut file| file.read_exact(&mut magic))
+            .is_ok()
+            && &magic == b"GGUF"
+    }
+}
+
+impl std::fmt::Display for ModelFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Bytes { name, .. } => write!(f, "{name} (in memory)"),
+        }
+    }
+}
+
+/// A reader over a [`ModelFile`].
+pub(crate) enum ModelFileReader {
+    File(File),
+    Bytes(Cursor<Arc<[u8]>>),
+}
+
+impl ModelFileReader {
+    fn len(&self) -> std::io::Result<u64> {
+        match self {
+            Self::File(file) => Ok(file.metadata()?.len()),
+            Self::Bytes(cursor) => Ok(cursor.get_ref().len() as u64),
+        }
+    }
+}
+
+impl Read for ModelFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Bytes(cursor) => cursor.read(buf),
+        }
+    }
+}
+
+impl Seek for ModelFileReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Bytes(cursor) => cursor.seek(pos),
+        }
+    }
+}
+
+/// A reader over the shards of a gguf model as if they were one file.
+pub(crate) struct ShardedReader {
+    files: Vec<ModelFileReader>,
+    /// The position of the start of each file in the combined file
+    starts: Vec<u64>,
+    len: u64,
+    position: u64,
+}
+
+impl ShardedReader {
+    fn new(files: Vec<ModelFileReader>) -> std::io::Result<Self> {
+        let mut starts = Vec::with_capacity(files.len());
+        let mut len = 0;
+        for file in &files {
+            starts.push(len);
+            len += file.len()?;
+        }
+        Ok(Self {
+            files,
+            starts,
+            len,
+            position: 0,
+        })
+    }
+
+    /// Get the index of the file that contains the current position.
+    fn current_file(&self) -> usize {
+        self.starts
+            .partition_point(|start| *start <= self.position)
+            .saturating_sub(1)
+    }
+}
+
+impl Read for ShardedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.current_file();
+        let file = &mut self.files[index];
+        file.seek(SeekFrom::Start(self.position - self.starts[index]))?;
+        let read = file.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ShardedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Read the shards of a gguf model and stitch them into one model. The metadata of every shard is merged, and the
+/// tensor offsets are moved so they point into a [`ShardedReader`] over all of the shards.
+pub(crate) fn read_sharded_gguf(
+    model_files: &[ModelFile],
+) -> candle_core::Result<(gguf_file::Content, ShardedReader)> {
+    let mut files = Vec::with_capacity(model_files.len());
+    let mut shards = Vec::with_capacity(model_files.len());
+    for model_file in model_files {
+        let mut file = model_file.open()?;
+        shards.push(gguf_file::Content::read(&mut file)?);
+        files.push(file);
+    }
+    let reader = ShardedReader::new(files)?;
+
+    let mut shards = shards.into_iter().zip(reader.starts.iter().copied());
+    let (mut content, _) = shards
+        .next()
+        .ok_or_else(|| candle_core::Error::Msg("no model files were provided".to_string()))?;
+    for (shard, start) in shards {
+        for (key, value) in shard.metadata {
+            content.metadata.entry(key).or_insert(value);
+        }
+        // Tensor offsets are relative to the start of the tensor data in the first shard
+        let base = start + shard.tensor_data_offset - content.tensor_data_offset;
+        for (name, mut info) in shard.tensor_infos {
+            info.offset += base;
+            content.tensor_infos.insert(name, info);
+        }
+    }
+
+    Ok((content, reader))
+}
+
+#[test]
+fn shard_names() {
+    assert_eq!(
+        shard_file_names("Meta-Llama-3.1-70B-Instruct-Q4_K_M-00002-of-00003.gguf").unwrap(),
+        [
+            "Meta-Llama-3.1-70B-Instruct-Q4_K_M-00001-of-00003.gguf",
+            "Meta-Llama-3.1-70B-Instruct-Q4_K_M-00002-of-00003.gguf",
+            "Meta-Llama-3.1-70B-Instruct-Q4_K_M-00003-of-00003.gguf",
+        ]
+    );
+    assert_eq!(shard_file_names("llama-3.1-8b-instruct-q4_k_m.gguf"), None);
+    assert_eq!(shard_file_names("model-1-of-2.gguf"), None);
+}
@@ -0,0 +1,79 @@
+/// Settings for keeping a session running after its context fills up, like StreamingLLM. Instead of re-processing
+/// the end of the conversation, the first `sink_tokens` tokens are kept as attention sinks and the oldest tokens
+/// after them are dropped from the cache to make room for new tokens.
+///
+/// The model keeps attending to the start of the session (usually the system prompt) and the most recent tokens,
+/// but it forgets everything in between.
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let model = Llama::new_chat().await?;
+/// let session = model.new_session()?;
+/// session.set_attention_sinks(Some(AttentionSinks::new(4)));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttentionSinks {
+    sink_tokens: usize,
+    evict_tokens: usize,
+}
+
+impl Default for AttentionSinks {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+impl AttentionSinks {
+    /// Keep the first `sink_tokens` tokens of the session when the context is full.
+    pub fn new(sink_tokens: usize) -> Self {
+        Self {
+            sink_tokens,
+            evict_tokens: 32,
+        }
+    }
+
+    /// Set the number of tokens to drop at once when the context is full. Dropping more tokens at once means the
+    /// cache is rebuilt less often. (Defaults to 32)
+    pub fn with_evict_tokens(mut self, evict_tokens: usize) -> Self {
+        self.evict_tokens = evict_tokens.max(1);
+        self
+    }
+
+    /// Get the number of tokens at the start of the session that are always kept.
+    pub fn sink_tokens(&self) -> usize {
+        self.sink_tokens
+    }
+
+    /// Get the number of tokens that are dropped at once when the context is full.
+    pub fn evict_tokens(&self) -> usize {
+        self.evict_tokens
+    }
+
+    /// Get the number of tokens to drop after the sink tokens so `new_tokens` fit in a context of `context_length`
+    /// tokens with `cached_tokens` already in the cache. Returns `None` if dropping tokens can't make room.
+    pub(crate) fn tokens_to_evict(
+        &self,
+        cached_tokens: usize,
+        new_tokens: usize,
+        context_length: usize,
+    ) -> Option<usize> {
+        let overflow = (cached_tokens + new_tokens).checked_sub(context_length)?;
+        let evictable = cached_tokens.saturating_sub(self.sink_tokens);
+        let evict = overflow.max(self.evict_tokens).min(evictable);
+        (overflow > 0 && evict >= overflow).then_some(evict)
+    }
+}
+
+#[test]
+fn evicts_enough_tokens_to_fit() {
+    let sinks = AttentionSinks::new(4).with_evict_tokens(8);
+    assert_eq!(sinks.tokens_to_evict(100, 1, 100), Some(8));
+    assert_eq!(sinks.tokens_to_evict(100, 20, 100), Some(20));
+    assert_eq!(sinks.tokens_to_evict(50, 1, 100), None);
+    // The new tokens don't fit even after dropping everything except the sinks
+    assert_eq!(sinks.tokens_to_evict(10, 100, 100), None);
+}
@@ -19,6 +19,9 @@ fn llama_v3_tokenizer() -> FileSource {
     )
 }
 
+/// The chat template LLaVA 1.5 was trained with. Images are placed at the start of the user message.
+const LLAVA_CHAT_TEMPLATE: &str = "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'system' %}{{ message['content'] }}\n\n{% elif message['role'] == 'user' %}USER: {{ message['content'] }}\n{% else %}ASSISTANT: {{ message['content'] }}{{ eos_token }}\n{% endif %}{% endfor %}{% if add_generation_prompt %}ASSISTANT:{% endif %}";
+
 fn mistral_tokenizer() -> FileSource {
     FileSource::huggingface(
         "mistralai/Mistral-7B-v0.1".to_string(),
@@ -35,14 +38,48 @@ fn qwen_tokenizer() -> FileSource {
     )
 }
 
+fn smollm2_tokenizer() -> FileSource {
+    FileSource::huggingface(
+        "HuggingFaceTB/SmolLM2-135M-Instruct".to_string(),
+        "main".to_string(),
+        "tokenizer.json".to_string(),
+    )
+}
+
+const SMOLLM2_CHAT_TEMPLATE: &str = "{% for message in messages %}{% if loop.first and messages[0]['role'] != 'system' %}{{ '<|im_start|>system\nYou are a helpful AI assistant named SmolLM, trained by Hugging Face<|im_end|>\n' }}{% endif %}{{ '<|im_start|>' + message['role'] + '\n' + message['content'] + '<|im_end|>' + '\n' }}{% endfor %}{% if add_generation_prompt %}{{ '<|im_start|>assistant\n' }}{% endif %}";
+
+// The template in the Granite gguf files calls `strftime_now` to add today's date to the default system prompt,
+// which the minijinja environment doesn't provide
+const GRANITE_CHAT_TEMPLATE: &str = "{%- if messages[0]['role'] == 'system' %}{%- set system_message = messages[0]['content'] %}{%- set loop_messages = messages[1:] %}{%- else %}{%- set system_message = 'Knowledge Cutoff Date: April 2024.\nYou are Granite, developed by IBM. You are a helpful AI assistant.' %}{%- set loop_messages = messages %}{%- endif %}{{- '<|start_of_role|>system<|end_of_role|>' + system_message + '<|end_of_text|>\n' }}{%- for message in loop_messages %}{{- '<|start_of_role|>' + message['role'] + '<|end_of_role|>' + message['content'] + '<|end_of_text|>\n' }}{%- endfor %}{%- if add_generation_prompt %}{{- '<|start_of_role|>assistant<|end_of_role|>' }}{%- endif %}";
+
+const OLMO2_CHAT_TEMPLATE: &str = "{{ bos_token }}{% for message in messages %}{% if message['role'] == 'system' %}{{ '<|system|>\n' + message['content'] + '\n' }}{% elif message['role'] == 'user' %}{{ '<|user|>\n' + message['content'] + '\n' }}{% elif message['role'] == 'assistant' %}{{ '<|assistant|>\n' + message['content'] + eos_token + '\n' }}{% endif %}{% endfor %}{% if add_generation_prompt %}{{ '<|assistant|>\n' }}{% endif %}";
+
+fn gemma_tokenizer() -> FileSource {
+    FileSource::huggingface(
+        "unsloth/gemma-2-2b-it".to_string(),
+        "main".to_string(),
+        "tokenizer.json".to_string(),
+    )
+}
+
 /// A source for the Llama model.
 #[derive(Clone, Debug)]
 pub struct LlamaSource {
     pub(crate) model: FileSource,
+    pub(crate) shards: Vec<FileSource>,
     pub(crate) tokenizer: Option<FileSource>,
-    pub(crate) group_query_attention: u8,
+    pub(crate) config: Option<FileSource>,
+    pub(crate) lora: Vec<FileSource>,
+    pub(crate) vision_projector: Option<FileSource>,
+    pub(crate) chat_template: Option<String>,
+    pub(crate) group_query_attention: Option<u8>,
     pub(crate) cache: kalosm_common::Cache,
     pub(crate) override_stop_token_string: Option<String>,
+    pub(crate) max_context_length: Option<usize>,
+    pub(crate) context_length: Option<usize>,
+    pub(crate) rope_scaling: Option<crate::RopeScaling>,
+    pub(crate) quantization: Option<crate::Quantization>,
+    pub(crate) kv_cache_quantization: kalosm_common::KvCacheQuantization,
 }
 
 /// Errors that can occur when loading the Llama model.
@@ -70,6 +107,59 @@ pub enum LlamaSourceError {
     /// The task loading the model panicked.
     #[error("The task loading the model panicked")]
     ModelLoadingPanic,
+    /// The load was cancelled with a [`LoadingHandle`](kalosm_model_types::LoadingHandle).
+    #[error("The model load was cancelled")]
+    Cancelled,
+    /// Failed to create the thread pool for CPU inference.
+    #[error("Failed to create the CPU thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    /// A local model file does not exist.
+    #[error("The model file {0} does not exist")]
+    ModelNotFound(PathBuf),
+    /// The config of an unquantized model could not be read.
+    #[error("Failed to read the model config: {0}")]
+    Config(#[from] serde_json::Error),
+    /// A LoRA adapter could not be read.
+    #[error("Failed to load the LoRA adapter: {0}")]
+    InvalidLora(String),
+    /// The architecture of an unquantized model is not supported.
+    #[error("Unquantized {0} models are not supported")]
+    UnsupportedArchitecture(String),
+    /// The quantization level set with [`LlamaSource::with_quantization`] is not provided by the model repository.
+    #[error(
+        "The quantization level {quantization} is not available for {model}. Available levels: {}",
+        format_levels(.available)
+    )]
+    QuantizationUnavailable {
+        /// The requested quantization level.
+        quantization: crate::GgufQuantization,
+        /// The model file the level was requested for.
+        model: FileSource,
+        /// The levels the model repository provides.
+        available: Vec<crate::GgufQuantization>,
+    },
+    /// The memory the model needs could not be estimated.
+    #[error("Unable to estimate the memory the model needs: {0}")]
+    EstimateUnavailable(String),
+    /// A file that is read from the folder of the model was not set on the source, and the model has no folder.
+    #[error("{model} has no folder to read {file} from, so it must be set on the source")]
+    MissingSibling {
+        /// The model file the other file would be next to.
+        model: FileSource,
+        /// The name of the file that is missing.
+        file: String,
+    },
+}
+
+fn format_levels(levels: &[crate::GgufQuantization]) -> String {
+    if levels.is_empty() {
+        return "none".to_string();
+    }
+    levels
+        .iter()
+        .map(|level| level.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 impl LlamaSource {
@@ -77,25 +167,279 @@ impl LlamaSource {
     pub fn new(model: FileSource) -> Self {
         Self {
             model,
+            shards: Vec::new(),
             tokenizer: None,
-            group_query_attention: 1,
+            config: None,
+            lora: Vec::new(),
+            vision_projector: None,
+            chat_template: None,
+            group_query_attention: None,
             cache: Default::default(),
             override_stop_token_string: None,
+            max_context_length: None,
+            context_length: None,
+            rope_scaling: None,
+            quantization: None,
+            kv_cache_quantization: Default::default(),
         }
     }
 
+    /// Create a source for a gguf model file on disk. The tokenizer, chat template and group query attention are
+    /// read from the gguf file, and nothing is downloaded or written to the kalosm cache. This is useful for
+    /// air-gapped deployments where Hugging Face is not reachable.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::from_gguf_path("/models/qwen2.5-7b-instruct-q4_k_m.gguf"))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_gguf_path(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        // Keep any files the cache would write next to the model instead of in the data directory
+        let cache_location = path
+            .parent()
+            .map(|parent| parent.to_path_buf())
+            .unwrap_or_default();
+        Self::new(FileSource::Local(path)).with_cache(kalosm_common::Cache::new(cache_location))
+    }
+
+    /// Create a source for a gguf model held in memory. This is useful for models embedded in the binary with
+    /// `include_bytes!` or received over the network, because the model is read without touching the filesystem.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let bytes = std::fs::read("smollm2-135m-instruct-q8_0.gguf")?;
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::from_bytes(bytes))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_bytes(bytes: impl Into<std::sync::Arc<[u8]>>) -> Self {
+        Self::new(FileSource::bytes("model.gguf", bytes))
+    }
+
+    /// Create a source for a model in the [Ollama library](https://ollama.com/library) like `llama3.2:3b`. The model is
+    /// downloaded from the Ollama registry the same way `ollama pull` does. If the model was already pulled with
+    /// Ollama, the local copy is used instead. The tokenizer and chat template are read from the gguf file.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::ollama("qwen2.5:7b"))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ollama(name: impl AsRef<str>) -> Self {
+        Self::new(FileSource::ollama(name))
+    }
+
+    /// Create a source for an unquantized model in a Hugging Face repo with safetensors weights. This loads the
+    /// full precision weights instead of a quantized gguf file, so it uses much more memory but works with any
+    /// Llama, Mistral, Qwen 2 or OLMo 2 checkpoint without converting it first. The `config.json`, `tokenizer.json`
+    /// and `tokenizer_config.json` files are read from the same repo.
+    ///
+    /// Models split into multiple files can be loaded by setting the model to the `model.safetensors.index.json`
+    /// file of the repo.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::safetensors("HuggingFaceTB/SmolLM2-360M-Instruct"))
+    ///     .build()
+    ///     .await?;
+    /// let model = Llama::builder()
+    ///     .with_source(
+    ///         LlamaSource::safetensors("Qwen/Qwen2.5-3B-Instruct").with_model(FileSource::huggingface(
+    ///             "Qwen/Qwen2.5-3B-Instruct",
+    ///             "main",
+    ///             "model.safetensors.index.json",
+    ///         )),
+    ///     )
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn safetensors(model_id: impl ToString) -> Self {
+        Self::new(FileSource::huggingface(
+            model_id,
+            "main",
+            "model.safetensors",
+        ))
+    }
+
     /// Set the model to use for the model
     pub fn with_model(mut self, model: FileSource) -> Self {
         self.model = model;
         self
     }
 
+    /// Set every shard of a model that is split into multiple gguf files. The first file replaces the model file and
+    /// the shards are stitched back together when the model is loaded.
+    ///
+    /// Shards named like `model-00001-of-00003.gguf` are discovered automatically from the name of the model file,
+    /// so this is only required for shards with other names.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::from_gguf_path("./model-part-a.gguf").with_shards([
+    ///         FileSource::Local("./model-part-a.gguf".into()),
+    ///         FileSource::Local("./model-part-b.gguf".into()),
+    ///     ]))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_shards(mut self, shards: impl IntoIterator<Item = FileSource>) -> Self {
+        let mut shards = shards.into_iter();
+        if let Some(first) = shards.next() {
+            self.model = first;
+        }
+        self.shards = shards.collect();
+
+        self
+    }
+
+    /// Get the files of the model. This is the model file followed by any shards that were set with
+    /// [`LlamaSource::with_shards`] or discovered from a `-00001-of-0000N.gguf` file name.
+    pub(crate) fn model_files(&self) -> Vec<FileSource> {
+        if !self.shards.is_empty() {
+            return std::iter::once(self.model.clone())
+                .chain(self.shards.iter().cloned())
+                .collect();
+        }
+        match &self.model {
+            FileSource::HuggingFace {
+                model_id,
+                revision,
+                file,
+            } => match crate::shards::shard_file_names(file) {
+                Some(files) => files
+                    .into_iter()
+                    .map(|file| FileSource::huggingface(model_id.clone(), revision.clone(), file))
+                    .collect(),
+                None => vec![self.model.clone()],
+            },
+            FileSource::Local(path) => {
+                let shards = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(crate::shards::shard_file_names);
+                match shards {
+                    Some(files) => files
+                        .into_iter()
+                        .map(|file| FileSource::Local(path.with_file_name(file)))
+                        .collect(),
+                    None => vec![self.model.clone()],
+                }
+            }
+            // Ollama models are always a single blob, and shards of in-memory models are added with `with_shards`
+            FileSource::Ollama { .. } | FileSource::Bytes { .. } => vec![self.model.clone()],
+        }
+    }
+
     /// Set the tokenizer to use for the model. Kalosm will try to load the tokenizer from the gguf file if no tokenizer is provided.
     pub fn with_tokenizer(mut self, tokenizer: FileSource) -> Self {
         self.tokenizer = Some(tokenizer);
         self
     }
 
+    /// Set the `config.json` of an unquantized safetensors model. Kalosm will look for the config next to the model
+    /// file if no config is provided.
+    pub fn with_config(mut self, config: FileSource) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Add a LoRA adapter to merge into the weights of the model when it is loaded. This lets you run a fine-tuned
+    /// variant of a model without downloading or quantizing the whole fine-tuned model. Adapters can be Hugging Face
+    /// PEFT `adapter_model.safetensors` files or gguf adapters created with llama.cpp. The `adapter_config.json`
+    /// next to a PEFT adapter is used to scale the adapter if it exists.
+    ///
+    /// Quantized weights are dequantized, merged and quantized again, so the merged model uses the same amount of
+    /// memory as the base model. Adding more than one adapter merges all of them in order.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat().with_lora(FileSource::huggingface(
+    ///         "my-org/llama-3.1-8b-support-lora",
+    ///         "main",
+    ///         "adapter_model.safetensors",
+    ///     )))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_lora(mut self, lora: FileSource) -> Self {
+        self.lora.push(lora);
+        self
+    }
+
+    /// Set the vision projector of a multimodal model. The projector is a llama.cpp `mmproj` gguf file with a CLIP
+    /// vision encoder that turns images attached with [`Chat::add_image`](kalosm_language_model::Chat::add_image)
+    /// into embeddings for the language model.
+    pub fn with_vision_projector(mut self, projector: FileSource) -> Self {
+        self.vision_projector = Some(projector);
+        self
+    }
+
+    /// Set the chat template of the model. This overrides the chat template in the model file, which is useful for
+    /// models that are distributed without one.
+    pub fn with_chat_template(mut self, chat_template: impl ToString) -> Self {
+        self.chat_template = Some(chat_template.to_string());
+        self
+    }
+
+    /// Check if the model is an unquantized safetensors model.
+    pub(crate) fn is_safetensors(&self) -> bool {
+        let name = match &self.model {
+            FileSource::HuggingFace { file, .. } => file.as_str(),
+            FileSource::Local(path) => path.to_str().unwrap_or_default(),
+            FileSource::Bytes { name, .. } => name.as_str(),
+            FileSource::Ollama { .. } => return false,
+        };
+        name.ends_with(".safetensors") || name.ends_with(".safetensors.index.json")
+    }
+
+    /// Get a file in the same Hugging Face repo or local folder as the model. Returns `None` if the model has no
+    /// folder.
+    pub(crate) fn sibling(&self, name: &str) -> Option<FileSource> {
+        sibling_of(&self.model, name)
+    }
+
+    /// Get a file in the same Hugging Face repo or local folder as the model that the model can't load without.
+    pub(crate) fn required_sibling(&self, name: &str) -> Result<FileSource, LlamaSourceError> {
+        self.sibling(name)
+            .ok_or_else(|| LlamaSourceError::MissingSibling {
+                model: self.model.clone(),
+                file: name.to_string(),
+            })
+    }
+
     /// Set the cache location to use for the model (defaults DATA_DIR/kalosm/cache)
     pub fn with_cache(mut self, cache: kalosm_common::Cache) -> Self {
         self.cache = cache;
@@ -107,9 +451,10 @@ impl LlamaSource {
     /// For the llama family of models, this is typically 1
     /// For the mistral family of models, this is typically 8
     ///
-    /// This is determined automatically for any gguf models
+    /// This is only used for legacy ggml models. Gguf models read the number of key value heads from the file and
+    /// ignore this value with a warning if it doesn't match. (Defaults to 1 for ggml models)
     pub fn with_group_query_attention(mut self, group_query_attention: u8) -> Self {
-        self.group_query_attention = group_query_attention;
+        self.group_query_attention = Some(group_query_attention);
 
         self
     }
@@ -121,14 +466,217 @@ impl LlamaSource {
         self
     }
 
+    /// Limit the context length of the model. Some models advertise a much longer context than fits in memory, so
+    /// the rope and key value caches are only allocated up to this length.
+    ///
+    /// This only applies to gguf models
+    pub fn with_max_context_length(mut self, max_context_length: usize) -> Self {
+        self.max_context_length = Some(max_context_length);
+
+        self
+    }
+
+    /// Set the context length of the model. Unlike [`Self::with_max_context_length`], this can be longer than the
+    /// context the model was trained on. Combine it with [`Self::with_rope_scaling`] to keep the output coherent
+    /// past the trained context. (Defaults to the trained context length times the rope scaling factor)
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = Some(context_length);
+
+        self
+    }
+
+    /// Set how the rotary position embeddings are scaled to extend the context of the model. This replaces any
+    /// scaling recorded in the model file.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_8b_chat().with_rope_scaling(RopeScaling::linear(2.0)))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_rope_scaling(mut self, rope_scaling: crate::RopeScaling) -> Self {
+        self.rope_scaling = Some(rope_scaling);
+
+        self
+    }
+
+    /// Store the key value cache of sessions as 8 or 4 bit integers instead of the type the model runs in. This
+    /// roughly halves the memory of long contexts with [`KvCacheQuantization::Q8`](crate::KvCacheQuantization::Q8)
+    /// at the cost of a small loss in accuracy. (Defaults to [`KvCacheQuantization::None`](crate::KvCacheQuantization::None))
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(
+    ///         LlamaSource::llama_3_1_8b_chat().with_kv_cache_quantization(KvCacheQuantization::Q8),
+    ///     )
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_kv_cache_quantization(
+        mut self,
+        kv_cache_quantization: kalosm_common::KvCacheQuantization,
+    ) -> Self {
+        self.kv_cache_quantization = kv_cache_quantization;
+
+        self
+    }
+
+    /// Get the context length settings of the source.
+    pub(crate) fn context_options(&self) -> crate::rope_scaling::ContextOptions {
+        crate::rope_scaling::ContextOptions {
+            context_length: self.context_length,
+            max_context_length: self.max_context_length,
+            rope_scaling: self.rope_scaling,
+            kv_cache_quantization: self.kv_cache_quantization,
+        }
+    }
+
+    /// Set the quantization level of the model. A specific [`GgufQuantization`](crate::GgufQuantization) level
+    /// replaces the level in the file name of the preset. [`Quantization::Auto`](crate::Quantization::Auto) picks
+    /// the level that works best on this machine the first time the model is loaded.
+    ///
+    /// This only applies to gguf models from Hugging Face with the quantization level in the file name. Loading the
+    /// model returns [`LlamaSourceError::QuantizationUnavailable`] if the repository does not provide the level.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Llama::builder()
+    ///     .with_source(LlamaSource::llama_3_1_8b_chat().with_quantization(Quantization::Auto))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_quantization(mut self, quantization: impl Into<crate::Quantization>) -> Self {
+        let quantization = quantization.into();
+        if let crate::Quantization::Level(level) = quantization {
+            if let FileSource::HuggingFace { file, .. } = &mut self.model {
+                // If the file name has no level, loading the model returns an error
+                if let Some(rewritten) = level.rewrite_file_name(file) {
+                    *file = rewritten;
+                }
+            }
+        }
+        self.quantization = Some(quantization);
+
+        self
+    }
+
+    /// Check that the model repository provides the quantization level set with [`Self::with_quantization`].
+    pub(crate) async fn check_quantization(&self) -> Result<(), LlamaSourceError> {
+        let Some(crate::Quantization::Level(quantization)) = self.quantization else {
+            return Ok(());
+        };
+        let FileSource::HuggingFace {
+            model_id,
+            revision,
+            file,
+        } = &self.model
+        else {
+            return Ok(());
+        };
+        let unavailable = |available| LlamaSourceError::QuantizationUnavailable {
+            quantization,
+            model: self.model.clone(),
+            available,
+        };
+        // The level could not be written into a file name without a level
+        if crate::GgufQuantization::find_in_file_name(file).map(|(_, level)| level)
+            != Some(quantization)
+        {
+            return Err(unavailable(Vec::new()));
+        }
+        if self.cache.exists(&self.model) {
+            return Ok(());
+        }
+        // If the repository can't be listed, the download reports the error instead
+        let files = match self.cache.huggingface_files(model_id, revision).await {
+            Ok(files) => files,
+            Err(err) => {
+                tracing::warn!("Failed to list the files in {model_id}: {err}");
+                return Ok(());
+            }
+        };
+        if files.contains(file) {
+            return Ok(());
+        }
+        let available = crate::GgufQuantization::ALL
+            .into_iter()
+            .filter(|level| {
+                level
+                    .rewrite_file_name(file)
+                    .is_some_and(|name| files.contains(&name))
+            })
+            .collect();
+        Err(unavailable(available))
+    }
+
     pub(crate) async fn model(
         &self,
         progress: impl FnMut(FileLoadingProgress),
     ) -> Result<PathBuf, LlamaSourceError> {
-        let path = self.cache.get(&self.model, progress).await?;
+        self.file(&self.model, progress).await
+    }
+
+    /// Get the path of a model file, downloading it into the cache if it is not local.
+    pub(crate) async fn file(
+        &self,
+        file: &FileSource,
+        progress: impl FnMut(FileLoadingProgress),
+    ) -> Result<PathBuf, LlamaSourceError> {
+        if let FileSource::Local(path) = file {
+            if !path.exists() {
+                return Err(LlamaSourceError::ModelNotFound(path.clone()));
+            }
+            return Ok(path.clone());
+        }
+        let path = self.cache.get(file, progress).await?;
         Ok(path)
     }
 
+    /// Get the contents of a small file like a tokenizer or config.
+    pub(crate) async fn file_bytes(
+        &self,
+        file: &FileSource,
+        progress: impl FnMut(FileLoadingProgress),
+    ) -> Result<std::sync::Arc<[u8]>, LlamaSourceError> {
+        if let FileSource::Local(path) = file {
+            if !path.exists() {
+                return Err(LlamaSourceError::ModelNotFound(path.clone()));
+            }
+        }
+        Ok(self.cache.get_bytes(file, progress).await?)
+    }
+
+    /// Get a model file, keeping in-memory files in memory instead of writing them to disk.
+    pub(crate) async fn model_file(
+        &self,
+        file: &FileSource,
+        progress: impl FnMut(FileLoadingProgress),
+    ) -> Result<crate::shards::ModelFile, LlamaSourceError> {
+        match file {
+            FileSource::Bytes { name, bytes } => Ok(crate::shards::ModelFile::Bytes {
+                name: name.clone(),
+                bytes: bytes.clone(),
+            }),
+            _ => Ok(crate::shards::ModelFile::Path(
+                self.file(file, progress).await?,
+            )),
+        }
+    }
+
     /// A preset for Mistral7b
     pub fn mistral_7b() -> Self {
         Self::new(FileSource::huggingface(
@@ -137,7 +685,6 @@ impl LlamaSource {
             "mistral-7b-v0.1.Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for Mistral7bInstruct
@@ -148,7 +695,6 @@ impl LlamaSource {
             "mistral-7b-instruct-v0.1.Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for Mistral7bInstruct v0.2
@@ -159,7 +705,68 @@ impl LlamaSource {
             "mistral-7b-instruct-v0.2.Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
+    }
+
+    /// A preset for Mixtral-8x7B-Instruct-v0.1. Mixtral is a mixture of experts model that routes each token to 2
+    /// of 8 experts, so it runs about as fast as a 13b model but needs enough memory for all 47b parameters.
+    pub fn mixtral_8x7b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "TheBloke/Mixtral-8x7B-Instruct-v0.1-GGUF".to_string(),
+            "main".to_string(),
+            "mixtral-8x7b-instruct-v0.1.Q4_K_M.gguf".to_string(),
+        ))
+        .with_tokenizer(mistral_tokenizer())
+    }
+
+    /// A preset for LLaVA 1.5 7b, a vision model that can chat about images attached with
+    /// [`Chat::add_image`](kalosm_language_model::Chat::add_image).
+    pub fn llava_1_5_7b() -> Self {
+        Self::new(FileSource::huggingface(
+            "second-state/Llava-v1.5-7B-GGUF".to_string(),
+            "main".to_string(),
+            "llava-v1.5-7b-Q4_K_M.gguf".to_string(),
+        ))
+        .with_vision_projector(FileSource::huggingface(
+            "second-state/Llava-v1.5-7B-GGUF".to_string(),
+            "main".to_string(),
+            "llava-v1.5-7b-mmproj-model-f16.gguf".to_string(),
+        ))
+        .with_tokenizer(llama_tokenizer())
+        .with_chat_template(LLAVA_CHAT_TEMPLATE)
+    }
+
+    /// A preset for Mistral-NeMo-Instruct-2407 (12b). The model uses the Tekken tokenizer and supports up to a 128k context.
+    pub fn mistral_nemo_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/Mistral-Nemo-Instruct-2407-GGUF".to_string(),
+            "main".to_string(),
+            "Mistral-Nemo-Instruct-2407-Q4_K_M.gguf".to_string(),
+        ))
+        .with_max_context_length(128 * 1024)
+    }
+
+    /// A preset for Mistral-Small-Instruct-2409 (22b)
+    pub fn mistral_small_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/Mistral-Small-Instruct-2409-GGUF".to_string(),
+            "main".to_string(),
+            "Mistral-Small-Instruct-2409-Q4_K_M.gguf".to_string(),
+        ))
+        .with_tokenizer(FileSource::huggingface(
+            "unsloth/Mistral-Small-Instruct-2409".to_string(),
+            "main".to_string(),
+            "tokenizer.json".to_string(),
+        ))
+    }
+
+    /// A preset for Mistral-Small-24B-Instruct-2501. The model uses the Tekken tokenizer and supports up to a 32k context.
+    pub fn mistral_small_24b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/Mistral-Small-24B-Instruct-2501-GGUF".to_string(),
+            "main".to_string(),
+            "Mistral-Small-24B-Instruct-2501-Q4_K_M.gguf".to_string(),
+        ))
+        .with_max_context_length(32 * 1024)
     }
 
     /// A preset for NeuralHermes-2.5-Mistral-7B-GGUF
@@ -170,7 +777,6 @@ impl LlamaSource {
             "neuralhermes-2.5-mistral-7b.Q4_0.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for Neural Chat v3.3
@@ -185,7 +791,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(8)
     }
 
     /// A preset for Zephyr7bAlpha
@@ -196,7 +801,6 @@ impl LlamaSource {
             "zephyr-7b-alpha.Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for Zephyr7bBeta
@@ -207,7 +811,6 @@ impl LlamaSource {
             "zephyr-7b-beta.Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for [Open chat 3.5 (0106)](https://huggingface.co/openchat/openchat-3.5-0106)
@@ -222,7 +825,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(8)
     }
 
     /// A preset for Starling 7b Alpha
@@ -237,7 +839,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(8)
     }
 
     /// A preset for Starling 7b Beta
@@ -252,7 +853,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(8)
     }
 
     /// A preset for WizardLM 2 7B
@@ -263,7 +863,6 @@ impl LlamaSource {
             "WizardLM-2-7B-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(mistral_tokenizer())
-        .with_group_query_attention(8)
     }
 
     /// A preset for tiny llama 1.1b 1.0 Chat
@@ -278,7 +877,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(4)
     }
 
     /// A preset for tiny llama 1.1b 1.0
@@ -293,7 +891,43 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(4)
+    }
+
+    /// A preset for SmolLM2-135M-Instruct. The model is small enough to run on almost any device, but it is best
+    /// suited for simple tasks like classification or rewriting short text.
+    pub fn smollm2_135m_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "HuggingFaceTB/SmolLM2-135M-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "smollm2-135m-instruct-q8_0.gguf".to_string(),
+        ))
+        .with_tokenizer(smollm2_tokenizer())
+        .with_chat_template(SMOLLM2_CHAT_TEMPLATE)
+        .with_override_stop_token_string("<|im_end|>".to_string())
+    }
+
+    /// A preset for SmolLM2-360M-Instruct
+    pub fn smollm2_360m_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "HuggingFaceTB/SmolLM2-360M-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "smollm2-360m-instruct-q8_0.gguf".to_string(),
+        ))
+        .with_tokenizer(smollm2_tokenizer())
+        .with_chat_template(SMOLLM2_CHAT_TEMPLATE)
+        .with_override_stop_token_string("<|im_end|>".to_string())
+    }
+
+    /// A preset for SmolLM2-1.7B-Instruct
+    pub fn smollm2_1_7b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "HuggingFaceTB/SmolLM2-1.7B-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "smollm2-1.7b-instruct-q4_k_m.gguf".to_string(),
+        ))
+        .with_tokenizer(smollm2_tokenizer())
+        .with_chat_template(SMOLLM2_CHAT_TEMPLATE)
+        .with_override_stop_token_string("<|im_end|>".to_string())
     }
 
     /// A preset for Phi-3-mini-4k-instruct
@@ -308,7 +942,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(1)
         .with_override_stop_token_string("<|end|>".to_string())
     }
 
@@ -324,7 +957,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(1)
         .with_override_stop_token_string("<|end|>".to_string())
     }
 
@@ -340,7 +972,6 @@ impl LlamaSource {
             "main".to_string(),
             "tokenizer.json".to_string(),
         ))
-        .with_group_query_attention(1)
         .with_override_stop_token_string("<|end|>".to_string())
     }
 
@@ -378,7 +1009,6 @@ impl LlamaSource {
             "Meta-Llama-3-8B-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama8b v3
@@ -389,7 +1019,6 @@ impl LlamaSource {
             "Meta-Llama-3-8B-Instruct-Q5_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama8b v3.1 Instruct
@@ -400,7 +1029,6 @@ impl LlamaSource {
             "Meta-Llama-3.1-8B-Instruct-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama8b v3 at the Q8_0 quantization level. This file will be larger than [`llama_8b_chat`](Self::llama_8b_chat) but the model output will be more accurate.
@@ -411,7 +1039,6 @@ impl LlamaSource {
             "Meta-Llama-3-8B-Instruct-Q8_0.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama8b SPPO Iter3
@@ -422,7 +1049,6 @@ impl LlamaSource {
             "Llama-3-Instruct-8B-SPPO-Iter3-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama 2.3 1b
@@ -433,7 +1059,6 @@ impl LlamaSource {
             "Llama-3.2-1B-Instruct-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama 2.3 3b
@@ -444,7 +1069,6 @@ impl LlamaSource {
             "Llama-3.2-3B-Instruct-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(llama_v3_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama13b
@@ -509,7 +1133,6 @@ impl LlamaSource {
             "codellama-7b.Q8_0.gguf".to_string(),
         ))
         .with_tokenizer(llama_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama13bCode
@@ -520,7 +1143,6 @@ impl LlamaSource {
             "codellama-13b.Q8_0.gguf".to_string(),
         ))
         .with_tokenizer(llama_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for Llama34bCode
@@ -531,7 +1153,6 @@ impl LlamaSource {
             "codellama-34b.Q8_0.gguf".to_string(),
         ))
         .with_tokenizer(llama_tokenizer())
-        .with_group_query_attention(1)
     }
 
     /// A preset for the SOLAR 10.7B model
@@ -570,7 +1191,6 @@ impl LlamaSource {
             "qwen2.5-0.5b-instruct-q4_k_m.gguf".to_string(),
         ))
         .with_tokenizer(qwen_tokenizer())
-        .with_group_query_attention(7)
     }
 
     /// A preset for the Qwen2.5-1.5B Chat model
@@ -581,7 +1201,6 @@ impl LlamaSource {
             "qwen2.5-1.5b-instruct-q4_k_m.gguf".to_string(),
         ))
         .with_tokenizer(qwen_tokenizer())
-        .with_group_query_attention(7)
     }
 
     /// A preset for the Qwen2.5-3B Chat model
@@ -592,7 +1211,6 @@ impl LlamaSource {
             "qwen2.5-3b-instruct-q4_k_m.gguf".to_string(),
         ))
         .with_tokenizer(qwen_tokenizer())
-        .with_group_query_attention(7)
     }
 
     /// A preset for the Qwen2.5-7B Chat model
@@ -603,7 +1221,81 @@ impl LlamaSource {
             "Qwen2.5-7B-Instruct-Q4_K_M.gguf".to_string(),
         ))
         .with_tokenizer(qwen_tokenizer())
-        .with_group_query_attention(7)
+    }
+
+    /// A preset for the Gemma 2 2B Instruct model. Gemma 2 models alternate between sliding window and global
+    /// attention layers and support up to an 8k context.
+    pub fn gemma_2_2b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/gemma-2-2b-it-GGUF".to_string(),
+            "main".to_string(),
+            "gemma-2-2b-it-Q4_K_M.gguf".to_string(),
+        ))
+        .with_tokenizer(gemma_tokenizer())
+        .with_override_stop_token_string("<end_of_turn>".to_string())
+    }
+
+    /// A preset for the Gemma 2 9B Instruct model. Gemma 2 models alternate between sliding window and global
+    /// attention layers and support up to an 8k context.
+    pub fn gemma_2_9b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/gemma-2-9b-it-GGUF".to_string(),
+            "main".to_string(),
+            "gemma-2-9b-it-Q4_K_M.gguf".to_string(),
+        ))
+        .with_tokenizer(gemma_tokenizer())
+        .with_override_stop_token_string("<end_of_turn>".to_string())
+    }
+
+    /// A preset for the IBM Granite 3.1 1B A400M Instruct model. This is a mixture of experts model that only uses
+    /// 400M parameters for each token. Granite models are released under the Apache 2.0 license.
+    pub fn granite_3_1_1b_a400m_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/granite-3.1-1b-a400m-instruct-GGUF".to_string(),
+            "main".to_string(),
+            "granite-3.1-1b-a400m-instruct-Q4_K_M.gguf".to_string(),
+        ))
+        .with_chat_template(GRANITE_CHAT_TEMPLATE)
+    }
+
+    /// A preset for the IBM Granite 3.1 2B Instruct model. Granite models are released under the Apache 2.0 license.
+    pub fn granite_3_1_2b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/granite-3.1-2b-instruct-GGUF".to_string(),
+            "main".to_string(),
+            "granite-3.1-2b-instruct-Q4_K_M.gguf".to_string(),
+        ))
+        .with_chat_template(GRANITE_CHAT_TEMPLATE)
+    }
+
+    /// A preset for the IBM Granite 3.1 8B Instruct model. Granite models are released under the Apache 2.0 license.
+    pub fn granite_3_1_8b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/granite-3.1-8b-instruct-GGUF".to_string(),
+            "main".to_string(),
+            "granite-3.1-8b-instruct-Q4_K_M.gguf".to_string(),
+        ))
+        .with_chat_template(GRANITE_CHAT_TEMPLATE)
+    }
+
+    /// A preset for the OLMo 2 7B Instruct model. OLMo models are fully open with the training data and code released under the Apache 2.0 license.
+    pub fn olmo_2_7b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/OLMo-2-1124-7B-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "OLMo-2-1124-7B-Instruct-Q4_K_M.gguf".to_string(),
+        ))
+        .with_chat_template(OLMO2_CHAT_TEMPLATE)
+    }
+
+    /// A preset for the OLMo 2 13B Instruct model. OLMo models are fully open with the training data and code released under the Apache 2.0 license.
+    pub fn olmo_2_13b_instruct() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/OLMo-2-1124-13B-Instruct-GGUF".to_string(),
+            "main".to_string(),
+            "OLMo-2-1124-13B-Instruct-Q4_K_M.gguf".to_string(),
+        ))
+        .with_chat_template(OLMO2_CHAT_TEMPLATE)
     }
 
     /// A preset for the DeepSeek-R1 distill qwen 1.5b model
@@ -641,6 +1333,28 @@ impl LlamaSource {
             "DeepSeek-R1-Distill-Llama-8B-Q4_K_M.gguf".to_string(),
         ))
     }
+
+    /// A preset for the DeepSeek-R1 distill qwen 32b model. Use
+    /// [`ChatResponseBuilder::split_reasoning`](kalosm_language_model::ChatResponseBuilder::split_reasoning) to
+    /// separate the reasoning from the answer.
+    pub fn deepseek_r1_distill_qwen_32b() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/DeepSeek-R1-Distill-Qwen-32B-GGUF".to_string(),
+            "main".to_string(),
+            "DeepSeek-R1-Distill-Qwen-32B-Q4_K_M.gguf".to_string(),
+        ))
+    }
+
+    /// A preset for the DeepSeek-R1 distill llama 70b model. Use
+    /// [`ChatResponseBuilder::split_reasoning`](kalosm_language_model::ChatResponseBuilder::split_reasoning) to
+    /// separate the reasoning from the answer.
+    pub fn deepseek_r1_distill_llama_70b() -> Self {
+        Self::new(FileSource::huggingface(
+            "bartowski/DeepSeek-R1-Distill-Llama-70B-GGUF".to_string(),
+            "main".to_string(),
+            "DeepSeek-R1-Distill-Llama-70B-Q4_K_M.gguf".to_string(),
+        ))
+    }
 }
 
 impl Default for LlamaSource {
@@ -648,3 +1362,136 @@ impl Default for LlamaSource {
         Self::llama_3_1_8b_chat()
     }
 }
+
+/// Get a file in the same Hugging Face repo or local folder as another file. Ollama models are a single gguf blob and
+/// in-memory files have no folder, so they have no files next to them and this returns `None`.
+pub(crate) fn sibling_of(file: &FileSource, name: &str) -> Option<FileSource> {
+    match file {
+        FileSource::HuggingFace {
+            model_id, revision, ..
+        } => Some(FileSource::huggingface(
+            model_id.clone(),
+            revision.clone(),
+            name.to_string(),
+        )),
+        FileSource::Local(path) => Some(FileSource::Local(path.with_file_name(name))),
+        FileSource::Ollama { .. } | FileSource::Bytes { .. } => None,
+    }
+}
+
+#[test]
+fn only_files_in_a_folder_have_siblings() {
+    let sibling = |file| sibling_of(&file, "config.json").map(|sibling| sibling.to_string());
+    let huggingface = |file: &str| {
+        FileSource::huggingface(
+            "org/model".to_string(),
+            "main".to_string(),
+            file.to_string(),
+        )
+    };
+    assert_eq!(
+        sibling(huggingface("model.safetensors")),
+        Some(huggingface("config.json").to_string())
+    );
+    assert_eq!(
+        sibling(FileSource::Local(PathBuf::from("models/llama/model.gguf"))),
+        Some(FileSource::Local(PathBuf::from("models/llama/config.json")).to_string())
+    );
+    // Files without a folder never resolve to a path relative to the working directory
+    assert_eq!(sibling(FileSource::ollama("llama3.2")), None);
+    assert_eq!(
+        sibling(FileSource::bytes("model.safetensors", Vec::new())),
+        None
+    );
+}
+
+#[test]
+fn granite_and_olmo_presets_resolve_to_their_files_and_chat_templates() {
+    use crate::chat_template::HuggingFaceChatTemplate;
+    use kalosm_language_model::{ChatMessage, MessageType};
+
+    let granite = "<|start_of_role|>system<|end_of_role|>Answer briefly.<|end_of_text|>\n<|start_of_role|>user<|end_of_role|>Hi<|end_of_text|>\n<|start_of_role|>assistant<|end_of_role|>";
+    let olmo = "<|endoftext|><|system|>\nAnswer briefly.\n<|user|>\nHi\n<|assistant|>\n";
+    let presets = [
+        (
+            LlamaSource::granite_3_1_1b_a400m_instruct(),
+            "bartowski/granite-3.1-1b-a400m-instruct-GGUF",
+            "granite-3.1-1b-a400m-instruct-Q4_K_M.gguf",
+            "<|end_of_text|>",
+            granite,
+        ),
+        (
+            LlamaSource::granite_3_1_2b_instruct(),
+            "bartowski/granite-3.1-2b-instruct-GGUF",
+            "granite-3.1-2b-instruct-Q4_K_M.gguf",
+            "<|end_of_text|>",
+            granite,
+        ),
+        (
+            LlamaSource::granite_3_1_8b_instruct(),
+            "bartowski/granite-3.1-8b-instruct-GGUF",
+            "granite-3.1-8b-instruct-Q4_K_M.gguf",
+            "<|end_of_text|>",
+            granite,
+        ),
+        (
+            LlamaSource::olmo_2_7b_instruct(),
+            "bartowski/OLMo-2-1124-7B-Instruct-GGUF",
+            "OLMo-2-1124-7B-Instruct-Q4_K_M.gguf",
+            "<|endoftext|>",
+            olmo,
+        ),
+        (
+            LlamaSource::olmo_2_13b_instruct(),
+            "bartowski/OLMo-2-1124-13B-Instruct-GGUF",
+            "OLMo-2-1124-13B-Instruct-Q4_K_M.gguf",
+            "<|endoftext|>",
+            olmo,
+        ),
+    ];
+    let messages = [
+        ChatMessage::new(MessageType::SystemPrompt, "Answer briefly.".to_string()),
+        ChatMessage::new(MessageType::UserMessage, "Hi".to_string()),
+    ];
+    for (source, expected_model_id, expected_file, special_token, expected_prompt) in presets {
+        match &source.model {
+            FileSource::HuggingFace { model_id, file, .. } => {
+                assert_eq!(model_id, expected_model_id);
+                assert_eq!(file, expected_file);
+            }
+            _ => panic!("{expected_model_id} should download from Hugging Face"),
+        }
+        let template =
+            HuggingFaceChatTemplate::create(source.chat_template.as_deref().unwrap()).unwrap();
+        let prompt = template
+            .format(special_token, special_token, &messages, true)
+            .unwrap();
+        assert_eq!(prompt, expected_prompt);
+    }
+}
+
+#[tokio::test]
+async fn in_memory_models_load_without_writing_to_the_cache() {
+    use crate::test_model::{tiny_gguf, tiny_safetensors};
+    use crate::Llama;
+    use candle_core::Device;
+
+    // A directory can't be created inside /dev/null, so the load fails if it writes anything to the cache
+    let cache = kalosm_common::Cache::new(PathBuf::from("/dev/null/kalosm-cache"));
+    let (weights, config, tokenizer) = tiny_safetensors();
+    let sources = [
+        LlamaSource::new(FileSource::bytes("tiny.gguf", tiny_gguf())),
+        LlamaSource::new(FileSource::bytes("model.safetensors", weights))
+            .with_config(FileSource::bytes("config.json", config))
+            .with_tokenizer(FileSource::bytes("tokenizer.json", tokenizer)),
+    ];
+    for source in sources {
+        let model = Llama::builder()
+            .with_source(source.with_cache(cache.clone()))
+            .with_device(Device::Cpu)
+            .build()
+            .await
+            .unwrap();
+        assert!(model.tokenizer().token_to_id("hello").is_some());
+    }
+}
@@ -1,3 +1,4 @@
+use kalosm_language_model::{CancellationToken, Usage};
 use kalosm_sample::CreateParserState;
 use kalosm_sample::{LiteralParser, ParseStatus, Parser, ParserExt};
 use llm_samplers::prelude::{Logit, Logits};
@@ -7,17 +8,20 @@ use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::{
     fmt::{Debug, Display, Formatter},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 use tokenizers::tokenizer::Tokenizer;
 
+use crate::logprobs::{token_logprobs, LogprobsHandler};
 use crate::model::LlamaModelError;
+use crate::prefill::PrefillHandler;
 use crate::token_stream::TokenOutputStream;
-use crate::{LlamaModel, LlamaSession};
+use crate::{LlamaModel, LlamaSession, UsageHandler};
 
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn generate_structured<P: Parser>(
     prompt: impl Display,
-    llm: &LlamaModel,
+    llm: &mut LlamaModel,
     session: &mut LlamaSession,
     parser: P,
     parser_state: P::PartialState,
@@ -25,6 +29,12 @@ pub(crate) fn generate_structured<P: Parser>(
     mut on_token: impl FnMut(String) -> Result<(), LlamaModelError>,
     top_k: Option<usize>,
     seed: Option<u64>,
+    mut prefill_progress: Option<PrefillHandler>,
+    logprobs: Option<(usize, LogprobsHandler)>,
+    cancellation_token: Option<CancellationToken>,
+    stopped_waiting: impl Fn() -> bool,
+    deadline: Option<Instant>,
+    usage_handler: Option<UsageHandler>,
 ) -> Result<P::Output, LlamaModelError> {
     let eos_token = llm.model.config.stop_token_string.clone();
     let mut on_token = move |tok: String| {
@@ -33,11 +43,14 @@ pub(crate) fn generate_structured<P: Parser>(
         }
         on_token(tok)
     };
+    let paused_session = session.clone();
     let mut session = session
         .cache
         .write()
         .map_err(|err| LlamaModelError::Session(err.to_string()))?;
-    let tokenizer = &llm.tokenizer;
+    session.degradations.clear();
+    let prompt_start = session.tokens.len();
+    let tokenizer = llm.tokenizer.clone();
 
     let prompt_text = prompt.to_string();
     let prompt_tokens = tokenizer
@@ -65,6 +78,7 @@ pub(crate) fn generate_structured<P: Parser>(
             .next_token(*token)
             .map_err(LlamaModelError::TokenOutputStreamError)?;
     }
+    let prompt_token_count = token_stream.tokens().len();
 
     let remaining_prompt_text = last_token
         .map(|token| {
@@ -103,16 +117,38 @@ pub(crate) fn generate_structured<P: Parser>(
     let mut token_cache = DetokenizationCache::new();
     let mut logits = Logits::default();
     let mut logit_probs = Vec::new();
+    let started = Instant::now();
+    let mut prefill_time = None;
 
     loop {
+        // The caller dropped the result, so stop without touching the session
+        if stopped_waiting() {
+            return Err(LlamaModelError::Cancelled);
+        }
+        if cancellation_token
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            tracing::trace!("Stopping cancelled generation");
+            // Drop the prompt and the tokens generated so far, but keep the context from earlier generations
+            session.truncate(prompt_start);
+            return Err(LlamaModelError::Cancelled);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            tracing::trace!("Stopping generation that timed out");
+            return Err(LlamaModelError::TimedOut);
+        }
+        llm.yield_to_interactive(&paused_session);
         let tokens = token_stream.tokens();
-        LlamaModel::forward(
-            &llm.model,
-            &llm.device,
+        // Only the prompt is reported as prefill progress
+        llm.prefill(
             &tokens[tokens.len() - unprocessed_token_count..],
-            Some(&mut *session),
+            &mut session,
             &mut logit_probs,
+            prefill_progress.take().as_ref(),
+            deadline,
         )?;
+        let prefill_time = *prefill_time.get_or_insert_with(|| started.elapsed());
         let resources = &mut SamplerResources {
             previous_tokens: tokens,
             rng: &mut rng,
@@ -214,6 +250,10 @@ pub(crate) fn generate_structured<P: Parser>(
             .sample_token(resources, &mut logits)
             .map_err(|err| LlamaModelError::SamplerError(err.into()))?
             .ok_or(LlamaModelError::NoValidTokens)?;
+        if let Some((top_alternatives, handler)) = &logprobs {
+            let logprobs = token_logprobs(&logit_probs, token_id, *top_alternatives, &tokenizer)?;
+            (handler.lock().unwrap())(logprobs);
+        }
 
         unprocessed_token_count = 1;
         let (result, parsed_bytes) = state_map
@@ -240,11 +280,21 @@ pub(crate) fn generate_structured<P: Parser>(
             &parser,
             &mut parser_state,
             result,
-            tokenizer,
+            &tokenizer,
             &mut token_stream,
             &mut on_token,
             &mut unprocessed_token_count,
         )? {
+            if let Some(usage_handler) = &usage_handler {
+                let usage = Usage::new(
+                    prompt_token_count,
+                    token_stream.tokens().len() - prompt_token_count,
+                    prefill_time,
+                    started.elapsed().saturating_sub(prefill_time),
+                )
+                .with_degradations(session.degradations.clone());
+                (usage_handler.lock().unwrap())(usage);
+            }
             return Ok(result);
         }
     }
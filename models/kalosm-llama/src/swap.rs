@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use kalosm_model_types::ModelLoadingProgress;
+use tokenizers::Tokenizer;
+
+use crate::model::{LlamaModel, LlamaModelError};
+use crate::raw::cache::LlamaCache;
+use crate::{Llama, LlamaBuilder, LlamaSession, LlamaSource, LoadedModel, Task};
+
+static NEXT_MODEL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Get a new unique id for a loaded model.
+pub(crate) fn next_model_id() -> u64 {
+    NEXT_MODEL_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Llama {
+    /// Load a different model and switch this handle over to it once it is ready. The current model keeps running
+    /// tasks while the new weights load, and tasks that are already queued run on the new model after the switch.
+    ///
+    /// Sessions that were used with the old model keep their text: the first time a session is used with the new
+    /// model, its tokens are decoded with the old tokenizer, re-tokenized and fed into the new model.
+    ///
+    /// Every clone of the handle uses the tokenizer and chat template of the new model once this returns.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let mut model = Llama::builder()
+    ///     .with_source(LlamaSource::qwen_2_5_0_5b_instruct())
+    ///     .build()
+    ///     .await?;
+    /// // Serve requests with clones of the model...
+    ///
+    /// model.swap_source(LlamaSource::llama_3_1_8b_chat()).await?;
+    /// // New clones format prompts for the new model
+    /// let mut chat = model.chat();
+    /// chat("Hello!").to_std_out().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn swap_source(&mut self, source: LlamaSource) -> Result<(), LlamaModelError> {
+        self.swap_builder(Llama::builder().with_source(source), |_| {})
+            .await
+    }
+
+    /// Load the model from a builder and switch this handle over to it once it is ready, with a handler for progress
+    /// as the download and loading progresses. See [`Llama::swap_source`].
+    pub async fn swap_builder(
+        &mut self,
+        builder: LlamaBuilder,
+        handler: impl FnMut(ModelLoadingProgress) + Send + Sync + 'static,
+    ) -> Result<(), LlamaModelError> {
+        let new_model = builder
+            .load_model(handler)
+            .await
+            .map_err(|err| LlamaModelError::Loading(Arc::new(err)))?;
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.task_sender
+            .send(Task::SwapModel(SwapModelTask {
+                model: Box::new(new_model),
+                finished: tx,
+            }))
+            .map_err(|_| LlamaModelError::ModelStopped)?;
+        let loaded = rx.await.map_err(|_| LlamaModelError::ModelStopped)?;
+        *self.loaded.write().unwrap() = loaded;
+        Ok(())
+    }
+}
+
+/// A task that replaces the weights of the model thread.
+pub(crate) struct SwapModelTask {
+    model: Box<LlamaModel>,
+    finished: tokio::sync::oneshot::Sender<LoadedModel>,
+}
+
+impl SwapModelTask {
+    /// Replace the model. This must only run between tasks so no generation is in progress on the old model.
+    pub(crate) fn run(self, model: &mut LlamaModel) {
+        let old_model = std::mem::replace(model, *self.model);
+        model.retire(old_model);
+        _ = self.finished.send(LoadedModel::new(model));
+    }
+}
+
+impl LlamaModel {
+    /// Take over the task queue of the model this model replaces and remember its tokenizer so sessions from the old
+    /// model can be migrated.
+    fn retire(&mut self, old_model: LlamaModel) {
+        let LlamaModel {
+            tokenizer,
+            preemption,
+            id,
+            mut retired_tokenizers,
+            ..
+        } = old_model;
+        retired_tokenizers.insert(id, tokenizer);
+        self.retired_tokenizers = retired_tokenizers;
+        self.preemption = preemption;
+    }
+
+    /// Make sure the session's cache belongs to this model. Sessions that were filled by a model this model replaced
+    /// are re-tokenized and fed into this model. If that fails, the session starts over.
+    pub(crate) fn adopt_session(&mut self, session: &LlamaSession) {
+        let mut cache = session.cache.write().unwrap();
+        if let Some(old_id) = cache.model_id.filter(|id| *id != self.id) {
+            if let Some(old_tokenizer) = self.retired_tokenizers.get(&old_id).cloned() {
+                let tokens = std::mem::take(&mut cache.tokens);
+                let mut new_cache = LlamaCache::new(&self.model.config);
+                new_cache.degradations = std::mem::take(&mut cache.degradations);
+                new_cache.activations = cache.activations.take();
+                new_cache.attention_sinks = cache.attention_sinks;
+                new_cache.sampler = cache.sampler.take();
+                *cache = new_cache;
+                if let Err(err) = self.refill_cache(&old_tokenizer, &tokens, &mut cache) {
+                    tracing::error!("Failed to move the session to the new model: {err}");
+                    cache.clear();
+                    cache.tokens.clear();
+                }
+            }
+        }
+        cache.model_id = Some(self.id);
+        cache.model_fingerprint = Some(self.fingerprint);
+    }
+
+    fn refill_cache(
+        &self,
+        old_tokenizer: &Tokenizer,
+        old_tokens: &[u32],
+        cache: &mut LlamaCache,
+    ) -> Result<(), LlamaModelError> {
+        if old_tokens.is_empty() {
+            return Ok(());
+        }
+        let text = old_tokenizer
+            .decode(old_tokens, false)
+            .map_err(LlamaModelError::Tokenizer)?;
+        let tokens = self
+            .tokenizer
+            .encode_fast(text, false)
+            .map_err(LlamaModelError::Tokenizer)?;
+        let tokens = tokens.get_ids();
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let mut logits = Vec::new();
+        LlamaModel::forward(&self.model, &self.device, tokens, Some(cache), &mut logits)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+//! A tiny llama model with random weights for tests that need to run the model without downloading one.
+
+use std::collections::HashMap;
+
+use candle_core::quantized::{gguf_file, GgmlDType, QTensor};
+use candle_core::{Device, Tensor};
+use kalosm_model_types::FileSource;
+use rand::{Rng, SeedableRng};
+
+use crate::raw::Model;
+use crate::rope_scaling::ContextOptions;
+use crate::{Llama, LlamaSource};
+
+const HIDDEN_SIZE: usize = 16;
+const FEED_FORWARD_SIZE: usize = 32;
+const HEADS: usize = 2;
+const KV_HEADS: usize = 1;
+const LAYERS: usize = 2;
+pub(crate) const CONTEXT_LENGTH: usize = 512;
+
+/// The tokens of the vocabulary other than the 256 byte fallback tokens.
+const TOKENS: &[(&str, i32)] = &[
+    ("<unk>", 2),
+    ("<s>", 3),
+    ("</s>", 3),
+    ("h", 1),
+    ("e", 1),
+    ("l", 1),
+    ("o", 1),
+    ("he", 1),
+    ("hel", 1),
+    ("hello", 1),
+    ("▁", 1),
+    ("▁world", 1),
+];
+
+/// The id of the `</s>` stop token.
+pub(crate) const STOP_TOKEN: u32 = 2;
+
+/// A chat template with a marker for the role of each message.
+const CHAT_TEMPLATE: &str = "{% for message in messages %}<s>{{ message['role'] }}: {{ message['content'] }}</s>{% endfor %}{% if add_generation_prompt %}<s>assistant: {% endif %}";
+
+/// Create a gguf file for a two layer llama model with a SentencePiece vocabulary. Every weight is drawn from a
+/// seeded random number generator, so the file is the same every time.
+pub(crate) fn tiny_gguf() -> Vec<u8> {
+    use gguf_file::Value;
+
+    let mut tokens: Vec<(String, i32)> = TOKENS
+        .iter()
+        .map(|(token, ty)| (token.to_string(), *ty))
+        .collect();
+    tokens.extend((0..=255u8).map(|byte| (format!("<0x{byte:02X}>"), 6)));
+    let vocab_size = tokens.len();
+
+    let metadata = [
+        ("general.architecture", Value::String("llama".to_string())),
+        ("llama.block_count", Value::U32(LAYERS as u32)),
+        ("llama.attention.head_count", Value::U32(HEADS as u32)),
+        ("llama.attention.head_count_kv", Value::U32(KV_HEADS as u32)),
+        ("llama.embedding_length", Value::U32(HIDDEN_SIZE as u32)),
+        ("llama.attention.layer_norm_rms_epsilon", Value::F32(1e-5)),
+        ("llama.context_length", Value::U32(CONTEXT_LENGTH as u32)),
+        ("tokenizer.ggml.model", Value::String("llama".to_string())),
+        (
+            "tokenizer.ggml.tokens",
+            Value::Array(
+                tokens
+                    .iter()
+                    .map(|(token, _)| Value::String(token.clone()))
+                    .collect(),
+            ),
+        ),
+        (
+            "tokenizer.ggml.token_type",
+            Value::Array(tokens.iter().map(|(_, ty)| Value::I32(*ty)).collect()),
+        ),
+        (
+            "tokenizer.ggml.scores",
+            Value::Array(
+                tokens
+                    .iter()
+                    .map(|(token, _)| Value::F32(token.len() as f32))
+                    .collect(),
+            ),
+        ),
+        ("tokenizer.ggml.unknown_token_id", Value::U32(0)),
+        ("tokenizer.ggml.bos_token_id", Value::U32(1)),
+        ("tokenizer.ggml.eos_token_id", Value::U32(STOP_TOKEN)),
+        ("tokenizer.ggml.add_bos_token", Value::Bool(false)),
+        ("tokenizer.ggml.add_space_prefix", Value::Bool(false)),
+        (
+            "tokenizer.chat_template",
+            Value::String(CHAT_TEMPLATE.to_string()),
+        ),
+    ];
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut random = |shape: &[usize]| {
+        let len = shape.iter().product();
+        let values: Vec<f32> = (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let tensor = Tensor::from_vec(values, shape, &Device::Cpu).unwrap();
+        QTensor::quantize(&tensor, GgmlDType::F32).unwrap()
+    };
+    let ones = |len: usize| {
+        let tensor = Tensor::ones(len, candle_core::DType::F32, &Device::Cpu).unwrap();
+        QTensor::quantize(&tensor, GgmlDType::F32).unwrap()
+    };
+    let kv_size = HIDDEN_SIZE / HEADS * KV_HEADS;
+    let mut tensors = vec![
+        (
+            "token_embd.weight".to_string(),
+            random(&[vocab_size, HIDDEN_SIZE]),
+        ),
+        ("output_norm.weight".to_string(), ones(HIDDEN_SIZE)),
+    ];
+    for layer in 0..LAYERS {
+        let prefix = format!("blk.{layer}");
+        tensors.extend([
+            (format!("{prefix}.attn_norm.weight"), ones(HIDDEN_SIZE)),
+            (
+                format!("{prefix}.attn_q.weight"),
+                random(&[HIDDEN_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.attn_k.weight"),
+                random(&[kv_size, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.attn_v.weight"),
+                random(&[kv_size, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.attn_output.weight"),
+                random(&[HIDDEN_SIZE, HIDDEN_SIZE]),
+            ),
+            (format!("{prefix}.ffn_norm.weight"), ones(HIDDEN_SIZE)),
+            (
+                format!("{prefix}.ffn_gate.weight"),
+                random(&[FEED_FORWARD_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.ffn_up.weight"),
+                random(&[FEED_FORWARD_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.ffn_down.weight"),
+                random(&[HIDDEN_SIZE, FEED_FORWARD_SIZE]),
+            ),
+        ]);
+    }
+
+    let metadata: Vec<_> = metadata.iter().map(|(key, value)| (*key, value)).collect();
+    let tensors: Vec<_> = tensors
+        .iter()
+        .map(|(name, tensor)| (name.as_str(), tensor))
+        .collect();
+    let mut file = std::io::Cursor::new(Vec::new());
+    gguf_file::write(&mut file, &metadata, &tensors).unwrap();
+    file.into_inner()
+}
+
+/// Load the raw [`Model`] of [`tiny_gguf`] on the CPU.
+pub(crate) fn tiny_model() -> Model {
+    let mut file = std::io::Cursor::new(tiny_gguf());
+    let content = gguf_file::Content::read(&mut file).unwrap();
+    Model::from_gguf(
+        content,
+        &mut file,
+        &Device::Cpu,
+        None,
+        ContextOptions::default(),
+        None,
+        &[],
+    )
+    .unwrap()
+}
+
+/// Load the tokenizer of [`tiny_gguf`].
+pub(crate) fn tiny_tokenizer() -> tokenizers::Tokenizer {
+    let mut file = std::io::Cursor::new(tiny_gguf());
+    let content = gguf_file::Content::read(&mut file).unwrap();
+    crate::gguf_tokenizer::tokenizer_from_gguf(&content.metadata).unwrap()
+}
+
+/// Create the tiny model as an unquantized Hugging Face model. Returns the safetensors weights, the `config.json` and
+/// the `tokenizer.json` of the model.
+pub(crate) fn tiny_safetensors() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let tokenizer = tiny_tokenizer();
+    let vocab_size = tokenizer.get_vocab_size(true);
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut random = |shape: &[usize]| {
+        let len = shape.iter().product();
+        let values: Vec<f32> = (0..len).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        Tensor::from_vec(values, shape, &Device::Cpu).unwrap()
+    };
+    let ones = |len: usize| Tensor::ones(len, candle_core::DType::F32, &Device::Cpu).unwrap();
+    let kv_size = HIDDEN_SIZE / HEADS * KV_HEADS;
+    let mut tensors = HashMap::from([
+        (
+            "model.embed_tokens.weight".to_string(),
+            random(&[vocab_size, HIDDEN_SIZE]),
+        ),
+        ("model.norm.weight".to_string(), ones(HIDDEN_SIZE)),
+    ]);
+    for layer in 0..LAYERS {
+        let prefix = format!("model.layers.{layer}");
+        tensors.extend([
+            (
+                format!("{prefix}.input_layernorm.weight"),
+                ones(HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.self_attn.q_proj.weight"),
+                random(&[HIDDEN_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.self_attn.k_proj.weight"),
+                random(&[kv_size, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.self_attn.v_proj.weight"),
+                random(&[kv_size, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.self_attn.o_proj.weight"),
+                random(&[HIDDEN_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.post_attention_layernorm.weight"),
+                ones(HIDDEN_SIZE),
+            ),
+            (
+                format!("{prefix}.mlp.gate_proj.weight"),
+                random(&[FEED_FORWARD_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.mlp.up_proj.weight"),
+                random(&[FEED_FORWARD_SIZE, HIDDEN_SIZE]),
+            ),
+            (
+                format!("{prefix}.mlp.down_proj.weight"),
+                random(&[HIDDEN_SIZE, FEED_FORWARD_SIZE]),
+            ),
+        ]);
+    }
+    let weights = safetensors::serialize(&tensors, &None).unwrap();
+
+    let config = serde_json::json!({
+        "model_type": "llama",
+        "hidden_size": HIDDEN_SIZE,
+        "intermediate_size": FEED_FORWARD_SIZE,
+        "num_attention_heads": HEADS,
+        "num_key_value_heads": KV_HEADS,
+        "num_hidden_layers": LAYERS,
+        "max_position_embeddings": CONTEXT_LENGTH,
+        "tie_word_embeddings": true,
+        "bos_token_id": 1,
+        "eos_token_id": STOP_TOKEN,
+    });
+    let config = serde_json::to_vec(&config).unwrap();
+    let tokenizer = tokenizer.to_string(false).unwrap().into_bytes();
+    (weights, config, tokenizer)
+}
+
+/// Load a [`Llama`] chat model from [`tiny_gguf`] on the CPU.
+pub(crate) async fn tiny_llama() -> Llama {
+    Llama::builder()
+        .with_source(LlamaSource::new(FileSource::bytes(
+            "tiny.gguf",
+            tiny_gguf(),
+        )))
+        .with_device(Device::Cpu)
+        .build()
+        .await
+        .unwrap()
+}
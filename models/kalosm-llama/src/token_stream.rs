@@ -1,7 +1,6 @@
 use std::sync::Arc;
 
 use llm_samplers::types::{HasSamplerResources, Logits, Sampler, SamplerError};
-use rand::SeedableRng;
 use rayon::iter::{IntoParallelIterator, ParallelExtend, ParallelIterator};
 use thiserror::Error;
 use tokenizers::tokenizer::Tokenizer;
@@ -44,6 +43,11 @@ impl TokenOutputStream {
         }
     }
 
+    /// Get the tokenizer the stream decodes tokens with.
+    pub(crate) fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
     fn decode(&self, tokens: &[u32]) -> Result<String, TokenOutputStreamError> {
         self.tokenizer
             .decode(tokens, false)
@@ -56,7 +60,7 @@ impl TokenOutputStream {
         sampler: &mut impl Sampler,
         mut logits: Logits,
         stop_on: Option<&str>,
-        seed: Option<u64>,
+        rng: &mut impl rand::Rng,
     ) -> Result<u32, TokenOutputStreamError> {
         struct SamplerResources<'a, 'b, R: rand::Rng> {
             rng: &'a mut R,
@@ -91,11 +95,6 @@ impl TokenOutputStream {
                 Ok(())
             }
         }
-        let mut rng = if let Some(seed) = seed {
-            rand::rngs::StdRng::seed_from_u64(seed)
-        } else {
-            rand::rngs::StdRng::from_entropy()
-        };
         let tokenizer = &self.tokenizer;
         let previous_tokens = &self.tokens;
 
@@ -134,7 +133,7 @@ impl TokenOutputStream {
             .sample_token(
                 &mut SamplerResources {
                     previous_tokens,
-                    rng: &mut rng,
+                    rng,
                 },
                 sampler,
             )
@@ -188,7 +187,12 @@ impl TokenOutputStream {
         let prev_text = &self.current_text;
         self.tokens.push(token);
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
+        // Byte fallback tokens that don't form valid utf8 together decode to replacement characters, so the text
+        // decoded before this token may not be a prefix of the new text until the rest of the character arrives
+        if text.len() > prev_text.len()
+            && text.starts_with(prev_text.as_str())
+            && text.chars().last().unwrap().is_ascii()
+        {
             let text = text.split_at(prev_text.len());
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
@@ -207,7 +211,10 @@ impl TokenOutputStream {
         let prev_text = &self.current_text;
         self.tokens.extend(tokens.iter().copied());
         let text = self.decode(&self.tokens[self.prev_index..])?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text.len()
+            && text.starts_with(prev_text.as_str())
+            && text.chars().last().unwrap().is_ascii()
+        {
             let text = text.split_at(prev_text.len());
             self.prev_index = self.current_index;
             self.current_index = self.tokens.len();
@@ -227,7 +234,10 @@ impl TokenOutputStream {
         let prev_text = &self.current_text;
         current_tokens.extend(tokens);
         let text = self.decode(&current_tokens)?;
-        if text.len() > prev_text.len() && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text.len()
+            && text.starts_with(prev_text.as_str())
+            && text.chars().last().unwrap().is_ascii()
+        {
             let text = text.split_at(prev_text.len());
             Ok(Some(text.1.to_string()))
         } else {
@@ -249,7 +259,10 @@ impl TokenOutputStream {
                 tokens.push(token);
                 let text = self.decode(tokens).ok()?;
                 tokens.pop();
-                if text.len() > prev_text_len && text.chars().last().unwrap().is_ascii() {
+                if text.len() > prev_text_len
+                    && text.starts_with(prev_text.as_str())
+                    && text.chars().last().unwrap().is_ascii()
+                {
                     let text = text.split_at(prev_text_len);
                     Some(text.1.to_string())
                 } else {
@@ -267,7 +280,10 @@ impl TokenOutputStream {
         tokens.push(token);
         let text = self.decode(&tokens)?;
         tokens.pop();
-        if text.len() > prev_text_len && text.chars().last().unwrap().is_ascii() {
+        if text.len() > prev_text_len
+            && text.starts_with(prev_text.as_str())
+            && text.chars().last().unwrap().is_ascii()
+        {
             let text = text.split_at(prev_text_len);
             Ok(Some(text.1.to_string()))
         } else {
@@ -275,8 +291,54 @@ impl TokenOutputStream {
         }
     }
 
+    /// Get the tokens in the vocabulary that would decode to text after the current tokens that either starts with the
+    /// prefix or is the start of the prefix. Tokens that don't decode to text on their own are skipped. The tokens are
+    /// returned in ascending order.
+    pub(crate) fn tokens_continuing(&self, vocab_size: u32, prefix: &str) -> Vec<u32> {
+        let mut texts = Vec::with_capacity(vocab_size as usize);
+        self.peek_tokens(0..vocab_size, &mut texts);
+        texts
+            .into_iter()
+            .zip(0..)
+            .filter_map(|(text, token)| {
+                let text = text?;
+                (text.starts_with(prefix) || (!text.is_empty() && prefix.starts_with(&text)))
+                    .then_some(token)
+            })
+            .collect()
+    }
+
     /// Get the tokens
     pub fn tokens(&self) -> &[u32] {
         &self.tokens
     }
 }
+
+#[test]
+fn tokens_continuing_a_prefix_are_found_in_the_whole_vocabulary() {
+    let tokenizer = Arc::new(crate::test_model::tiny_tokenizer());
+    let vocab_size = tokenizer.get_vocab_size(true) as u32;
+    let token = |text: &str| tokenizer.token_to_id(text).unwrap();
+    let mut stream = TokenOutputStream::new(tokenizer.clone());
+    stream.next_token(token("o")).unwrap();
+
+    // Tokens that start with the prefix or that are the start of the prefix are allowed, including the byte fallback
+    // token for the first character
+    let allowed = stream.tokens_continuing(vocab_size, "hel");
+    let mut expected = vec![
+        token("h"),
+        token("he"),
+        token("hel"),
+        token("hello"),
+        token("<0x68>"),
+    ];
+    expected.sort();
+    assert_eq!(allowed, expected);
+
+    // Once part of the prefix has been generated, only tokens that continue the rest of it are allowed
+    stream.next_token(token("he")).unwrap();
+    let allowed = stream.tokens_continuing(vocab_size, "l");
+    let mut expected = vec![token("l"), token("<0x6C>")];
+    expected.sort();
+    assert_eq!(allowed, expected);
+}
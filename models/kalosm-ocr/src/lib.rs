@@ -147,6 +147,14 @@ impl OcrSource {
         device: &Device,
         mut handler: impl FnMut(ModelLoadingProgress) + Send + Sync,
     ) -> Result<VarBuilder, LoadOcrError> {
+        // In-memory weights are read from the buffer instead of being memory mapped from disk
+        if let FileSource::Bytes { bytes, .. } = &self.model {
+            return Ok(VarBuilder::from_buffered_safetensors(
+                bytes.to_vec(),
+                DType::F32,
+                device,
+            )?);
+        }
         let source = format!("Model ({})", self.model);
         let mut create_progress = ModelLoadingProgress::downloading_progress(source);
         let cache = Cache::default();
@@ -170,14 +178,11 @@ impl OcrSource {
             let source = format!("Config ({})", self.model);
             let mut create_progress = ModelLoadingProgress::downloading_progress(source);
             let cache = Cache::default();
-            let config_filename = cache
-                .get(&self.config, |progress| handler(create_progress(progress)))
+            let config_bytes = cache
+                .get_bytes(&self.config, |progress| handler(create_progress(progress)))
                 .await?;
-            let config: Config = serde_json::from_reader(
-                std::fs::File::open(config_filename)
-                    .expect("FileSource::download should return a valid path"),
-            )
-            .map_err(LoadOcrError::LoadConfig)?;
+            let config: Config =
+                serde_json::from_slice(&config_bytes).map_err(LoadOcrError::LoadConfig)?;
             (config.encoder, config.decoder)
         };
 
@@ -51,7 +51,7 @@ extern crate accelerate_src;
 use candle_core::{IndexOp, Tensor};
 use candle_nn::VarBuilder;
 use kalosm_common::*;
-use kalosm_model_types::ModelLoadingProgress;
+use kalosm_model_types::{FileSource, LoadingHandle, ModelLoadingProgress};
 use std::sync::{Arc, RwLock};
 use tokenizers::{Encoding, PaddingParams, Tokenizer};
 
@@ -120,6 +120,15 @@ impl BertBuilder {
     ) -> Result<Bert, BertLoadingError> {
         Bert::from_builder(self, loading_handler).await
     }
+
+    /// Build the model and report the combined progress of every file to a [`LoadingHandle`]. If the handle is
+    /// cancelled, the load stops and [`BertLoadingError::Cancelled`] is returned.
+    pub async fn build_with_handle(self, handle: &LoadingHandle) -> Result<Bert, BertLoadingError> {
+        handle
+            .run(self.build_with_loading_handler(handle.handler()))
+            .await
+            .map_err(|_| BertLoadingError::Cancelled)?
+    }
 }
 
 /// An error that can occur when loading a Bert model.
@@ -140,6 +149,9 @@ pub enum BertLoadingError {
     /// A config was not found
     #[error("Config not found")]
     ConfigNotFound,
+    /// The load was cancelled with a [`LoadingHandle`].
+    #[error("The model load was cancelled")]
+    Cancelled,
 }
 
 /// An error that can occur when running a Bert model.
@@ -243,36 +255,45 @@ impl Bert {
 
         let source = format!("Config ({})", config);
         let mut create_progress = ModelLoadingProgress::downloading_progress(source);
-        let config_filename = cache
-            .get(&config, |progress| {
+        let config_bytes = cache
+            .get_bytes(&config, |progress| {
                 progress_handler(create_progress(progress))
             })
             .await?;
         let tokenizer_source = format!("Tokenizer ({})", tokenizer);
         let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
-        let tokenizer_filename = cache
-            .get(&tokenizer, |progress| {
-                progress_handler(create_progress(progress))
-            })
-            .await?;
-        let model_source = format!("Model ({})", model);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
-        let weights_filename = cache
-            .get(&model, |progress| {
+        let tokenizer_bytes = cache
+            .get_bytes(&tokenizer, |progress| {
                 progress_handler(create_progress(progress))
             })
             .await?;
 
-        let config = std::fs::read_to_string(config_filename)
-            .map_err(|_| BertLoadingError::ConfigNotFound)?;
-        let config: Config = serde_json::from_str(&config).map_err(BertLoadingError::LoadConfig)?;
+        let config =
+            std::str::from_utf8(&config_bytes).map_err(|_| BertLoadingError::ConfigNotFound)?;
+        let config: Config = serde_json::from_str(config).map_err(BertLoadingError::LoadConfig)?;
 
         let device = accelerated_device_if_available()?;
-        let vb =
-            unsafe { VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)? };
+        let vb = match &model {
+            // In-memory weights are read from the buffer instead of being memory mapped from disk
+            FileSource::Bytes { bytes, .. } => {
+                VarBuilder::from_buffered_safetensors(bytes.to_vec(), DTYPE, &device)?
+            }
+            _ => {
+                let model_source = format!("Model ({})", model);
+                let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+                let weights_filename = cache
+                    .get(&model, |progress| {
+                        progress_handler(create_progress(progress))
+                    })
+                    .await?;
+                unsafe {
+                    VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)?
+                }
+            }
+        };
         let model = BertModel::load(vb, &config)?;
         let mut tokenizer =
-            Tokenizer::from_file(&tokenizer_filename).map_err(BertLoadingError::LoadTokenizer)?;
+            Tokenizer::from_bytes(&tokenizer_bytes).map_err(BertLoadingError::LoadTokenizer)?;
         tokenizer.with_padding(None);
 
         Ok(Bert {
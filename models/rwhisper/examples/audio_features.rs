@@ -0,0 +1,40 @@
+use kalosm::sound::*;
+use rodio::Decoder;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    tracing_subscriber::fmt::init();
+
+    // Create a new small whisper model
+    let model = WhisperBuilder::default()
+        .with_source(WhisperSource::QuantizedLargeV3Turbo)
+        .build()
+        .await?;
+
+    // Load audio from a file
+    let file = BufReader::new(File::open("./models/rwhisper/examples/samples_jfk.wav").unwrap());
+    // Decode that sound file into a source
+    let audio = Decoder::new(file).unwrap();
+
+    // Run the encoder without transcribing the audio
+    let features = model.encode_audio(audio).await?;
+    println!(
+        "{} frames of {} features over {:?}",
+        features.frames(),
+        features.hidden_size(),
+        features.duration()
+    );
+
+    // Compare the first and second half of the clip
+    let half = features.duration() / 2;
+    let first = features.slice(Duration::ZERO..half)?.mean_pooled()?;
+    let second = features.slice(half..features.duration())?.mean_pooled()?;
+    let dot: f32 = first.iter().zip(&second).map(|(a, b)| a * b).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    println!("similarity: {}", dot / (norm(&first) * norm(&second)));
+
+    Ok(())
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use candle_transformers::models::whisper as m;
+use serde_json::{json, Value};
+
+use crate::{DecodingResult, TokenChunk};
+
+/// A cache of transcribed 30 second windows of audio. Each window is keyed by the model, the language, the
+/// transcription options and a hash of the audio in the window, so transcribing a recording again only runs the
+/// model on the windows that changed.
+///
+/// Windows are aligned to the start of the audio passed to [`Whisper::transcribe`](crate::Whisper::transcribe).
+/// Edits that shift the audio (like inserting a few seconds at the start) change every later window, so transcribe
+/// long recordings in chunks (for example one chunk per scene or file) to get the most cache hits.
+///
+/// Clones of the cache share the same entries.
+///
+/// ```rust, no_run
+/// use kalosm::sound::*;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), anyhow::Error> {
+/// let cache = TranscriptionCache::at("./transcription-cache")?;
+/// let model = Whisper::builder()
+///     .with_transcription_cache(cache.clone())
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TranscriptionCache {
+    inner: Arc<CacheInner>,
+}
+
+#[derive(Debug)]
+struct CacheInner {
+    entries: Mutex<CacheEntries>,
+    directory: Option<PathBuf>,
+    max_entries: usize,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    results: HashMap<u64, DecodingResult>,
+    insertion_order: VecDeque<u64>,
+}
+
+impl Default for TranscriptionCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TranscriptionCache {
+    /// Create a new cache that only keeps up to 4096 windows (about 34 hours of audio) in memory.
+    pub fn new() -> Self {
+        Self::with_options(None, 4096)
+    }
+
+    /// Create a new cache that also stores every window in a directory, so results survive restarts. The directory is
+    /// created if it doesn't exist.
+    pub fn at(directory: impl AsRef<Path>) -> std::io::Result<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self::with_options(Some(directory), 4096))
+    }
+
+    /// Set the maximum number of windows kept in memory. Windows stored on disk are not limited.
+    pub fn with_max_entries(self, max_entries: usize) -> Self {
+        Self::with_options(self.inner.directory.clone(), max_entries.max(1))
+    }
+
+    fn with_options(directory: Option<PathBuf>, max_entries: usize) -> Self {
+        Self {
+            inner: Arc::new(CacheInner {
+                entries: Default::default(),
+                directory,
+                max_entries,
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Get the number of windows in memory.
+    pub fn len(&self) -> usize {
+        self.inner.entries.lock().unwrap().results.len()
+    }
+
+    /// Check if there are no windows in memory.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of windows that were read from the cache instead of transcribed.
+    pub fn hits(&self) -> usize {
+        self.inner.hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of windows that were not in the cache and had to be transcribed.
+    pub fn misses(&self) -> usize {
+        self.inner.misses.load(Ordering::Relaxed)
+    }
+
+    /// Remove every window from memory and from the cache directory.
+    pub fn clear(&self) -> std::io::Result<()> {
+        let mut entries = self.inner.entries.lock().unwrap();
+        entries.results.clear();
+        entries.insertion_order.clear();
+        if let Some(directory) = &self.inner.directory {
+            for entry in std::fs::read_dir(directory)? {
+                let path = entry?.path();
+                if path
+                    .extension()
+                    .is_some_and(|extension| extension == "json")
+                {
+                    std::fs::remove_file(path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get(&self, key: u64) -> Option<DecodingResult> {
+        let cached = self
+            .inner
+            .entries
+            .lock()
+            .unwrap()
+            .results
+            .get(&key)
+            .cloned();
+        let cached = cached.or_else(|| {
+            let path = self.path(key)?;
+            let bytes = std::fs::read(path).ok()?;
+            let result = decoding_result_from_json(&serde_json::from_slice(&bytes).ok()?)?;
+            self.insert_in_memory(key, result.clone());
+            Some(result)
+        });
+        match cached {
+            Some(_) => self.inner.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.inner.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        cached
+    }
+
+    pub(crate) fn insert(&self, key: u64, result: DecodingResult) {
+        if let Some(path) = self.path(key) {
+            if let Err(err) = std::fs::write(path, decoding_result_to_json(&result).to_string()) {
+                tracing::error!("Failed to write transcription to the cache: {err}");
+            }
+        }
+        self.insert_in_memory(key, result);
+    }
+
+    fn insert_in_memory(&self, key: u64, result: DecodingResult) {
+        let mut entries = self.inner.entries.lock().unwrap();
+        if entries.results.insert(key, result).is_none() {
+            entries.insertion_order.push_back(key);
+        }
+        while entries.results.len() > self.inner.max_entries {
+            let Some(oldest) = entries.insertion_order.pop_front() else {
+                break;
+            };
+            entries.results.remove(&oldest);
+        }
+    }
+
+    fn path(&self, key: u64) -> Option<PathBuf> {
+        let directory = self.inner.directory.as_ref()?;
+        Some(directory.join(format!("{key:016x}.json")))
+    }
+}
+
+/// Hash each 30 second window of the audio along with everything else that changes the transcription. The hash is
+/// stable across runs so it can be used as a key on disk.
+pub(crate) fn window_keys(namespace: &str, word_level_time_stamps: bool, pcm: &[f32]) -> Vec<u64> {
+    pcm.chunks(m::N_SAMPLES)
+        .map(|window| {
+            let mut hash = Fnv1a::new();
+            hash.write(namespace.as_bytes());
+            hash.write(&[word_level_time_stamps as u8]);
+            hash.write(&(window.len() as u64).to_le_bytes());
+            for sample in window {
+                hash.write(&sample.to_le_bytes());
+            }
+            hash.finish()
+        })
+        .collect()
+}
+
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn decoding_result_to_json(result: &DecodingResult) -> Value {
+    json!({
+        "text": result.text,
+        "avg_logprob": result.avg_logprob,
+        "no_speech_prob": result.no_speech_prob,
+        "compression_ratio": result.compression_ratio,
+        "chunks": result.chunks.iter().map(|chunk| json!({
+            "text_range": [chunk.text_range.start, chunk.text_range.end],
+            "timestamp": chunk.timestamp.as_ref().map(|timestamp| [timestamp.start, timestamp.end]),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn decoding_result_from_json(value: &Value) -> Option<DecodingResult> {
+    let range = |value: &Value| {
+        let [start, end] = value.as_array()?.as_slice() else {
+            return None;
+        };
+        Some((start.as_f64()?, end.as_f64()?))
+    };
+    let chunks = value["chunks"]
+        .as_array()?
+        .iter()
+        .map(|chunk| {
+            let (start, end) = range(&chunk["text_range"])?;
+            let timestamp = match &chunk["timestamp"] {
+                Value::Null => None,
+                timestamp => {
+                    let (start, end) = range(timestamp)?;
+                    Some(start as f32..end as f32)
+                }
+            };
+            Some(TokenChunk {
+                text_range: start as usize..end as usize,
+                timestamp,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(DecodingResult {
+        text: value["text"].as_str()?.to_string(),
+        avg_logprob: value["avg_logprob"].as_f64()?,
+        no_speech_prob: value["no_speech_prob"].as_f64()?,
+        compression_ratio: value["compression_ratio"].as_f64()?,
+        chunks,
+    })
+}
+
+#[test]
+fn transcription_cache_round_trip() {
+    let pcm = vec![0.25; m::N_SAMPLES + 100];
+    let keys = window_keys("tiny-en", false, &pcm);
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys, window_keys("tiny-en", false, &pcm));
+    assert_ne!(keys, window_keys("tiny-en", true, &pcm));
+
+    let result = DecodingResult {
+        text: " And so my fellow Americans".to_string(),
+        avg_logprob: -0.25,
+        no_speech_prob: 0.01,
+        compression_ratio: 1.2,
+        chunks: vec![TokenChunk {
+            text_range: 0..4,
+            timestamp: Some(0.0..0.5),
+        }],
+    };
+    assert_eq!(
+        decoding_result_from_json(&decoding_result_to_json(&result)),
+        Some(result.clone())
+    );
+
+    let cache = TranscriptionCache::new().with_max_entries(1);
+    assert_eq!(cache.get(keys[0]), None);
+    cache.insert(keys[0], result.clone());
+    assert_eq!(cache.get(keys[0]), Some(result.clone()));
+    cache.insert(keys[1], result.clone());
+    assert_eq!(cache.len(), 1);
+    assert_eq!(cache.get(keys[0]), None);
+    assert_eq!((cache.hits(), cache.misses()), (1, 2));
+}
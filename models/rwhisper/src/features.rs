@@ -0,0 +1,87 @@
+use candle_core::{Device, Tensor};
+use std::{ops::Range, time::Duration};
+
+use crate::WhisperError;
+
+/// The audio features produced by whisper's encoder. There is one feature vector for every 20ms of audio.
+///
+/// The features can be used to build audio classification or audio search on top of whisper without running the
+/// text decoder.
+///
+/// ```rust, no_run
+/// use kalosm::sound::*;
+///
+/// #[tokio::main]
+/// async fn main() -> Result<(), anyhow::Error> {
+///     let model = Whisper::new().await?;
+///     let audio = MicInput::default()
+///         .record_until(std::time::Instant::now() + std::time::Duration::from_secs(5))
+///         .await;
+///     let features = model.encode_audio(audio).await?;
+///     println!("{} frames of {} features", features.frames(), features.hidden_size());
+///     let embedding = features.mean_pooled()?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AudioFeatures {
+    features: Tensor,
+}
+
+impl AudioFeatures {
+    /// The duration of audio each frame of features covers.
+    pub const FRAME_DURATION: Duration = Duration::from_millis(20);
+
+    pub(crate) fn new(features: Tensor) -> Self {
+        Self { features }
+    }
+
+    /// Get the features as a tensor with the shape `(frames, hidden_size)`.
+    pub fn tensor(&self) -> &Tensor {
+        &self.features
+    }
+
+    /// Get the number of frames of features.
+    pub fn frames(&self) -> usize {
+        self.features.dims()[0]
+    }
+
+    /// Get the size of the feature vector of each frame.
+    pub fn hidden_size(&self) -> usize {
+        self.features.dims()[1]
+    }
+
+    /// Get the duration of audio the features cover.
+    pub fn duration(&self) -> Duration {
+        Self::FRAME_DURATION * self.frames() as u32
+    }
+
+    /// Get the features of the frames that overlap a range of time in the audio.
+    pub fn slice(&self, range: Range<Duration>) -> Result<Self, WhisperError> {
+        let frame = |time: Duration| {
+            ((time.as_secs_f64() / Self::FRAME_DURATION.as_secs_f64()) as usize).min(self.frames())
+        };
+        let start = frame(range.start);
+        let end = frame(range.end).max(start);
+        Ok(Self::new(self.features.narrow(0, start, end - start)?))
+    }
+
+    /// Get the feature vector of each frame.
+    pub fn to_vec(&self) -> Result<Vec<Vec<f32>>, WhisperError> {
+        Ok(self.features.to_vec2()?)
+    }
+
+    /// Average the features over time into one vector. This is a simple embedding of the whole clip that can be used
+    /// to classify or search audio.
+    pub fn mean_pooled(&self) -> Result<Vec<f32>, WhisperError> {
+        if self.frames() == 0 {
+            return Ok(vec![0.; self.hidden_size()]);
+        }
+        Ok(self.features.mean(0)?.to_vec1()?)
+    }
+
+    /// Move the features to a device.
+    pub fn to_device(&self, device: &Device) -> Result<Self, WhisperError> {
+        Ok(Self::new(self.features.to_device(device)?))
+    }
+}
@@ -36,11 +36,14 @@
 use cpal::FromSample;
 use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use kalosm_common::Cache;
+pub use kalosm_common::{ConfigError, DevicePreference, KalosmConfig};
 use kalosm_language_model::ModelBuilder;
-pub use kalosm_model_types::{FileSource, ModelLoadingProgress};
-use model::{WhisperInner, WhisperLoadingError};
-use rodio::{source::UniformSourceIterator, Source};
+pub use kalosm_model_types::{FileSource, LoadingHandle, LoadingStatus, ModelLoadingProgress};
+pub use model::WhisperError;
+use model::{WhisperInner, WhisperLoadingError, WhisperWeights};
+use rodio::{buffer::SamplesBuffer, source::UniformSourceIterator, Source};
 use std::{
+    collections::VecDeque,
     fmt::Display,
     ops::Range,
     str::FromStr,
@@ -48,14 +51,21 @@ use std::{
     time::Duration,
 };
 
+use candle_core::Device;
 use candle_transformers::models::whisper::{self as m};
 
 use futures_util::{Stream, StreamExt};
 
+mod cache;
+pub use cache::TranscriptionCache;
+mod features;
+pub use features::*;
 mod model;
 mod source;
 pub use source::*;
+mod post_process;
 mod quantized;
+pub use post_process::*;
 
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -121,9 +131,48 @@ pub struct Segment {
     remaining_time: Duration,
     progress: f32,
     result: DecodingResult,
+    #[cfg_attr(feature = "serde", serde(default))]
+    no_speech: bool,
 }
 
 impl Segment {
+    /// Shift the segment by a number of samples (at whisper's sample rate) in the original audio.
+    fn offset_by(&mut self, samples: usize) {
+        self.sample_range = self.sample_range.start + samples..self.sample_range.end + samples;
+        self.start += samples as f64 / m::SAMPLE_RATE as f64;
+    }
+
+    /// Run post-processors on the text of the segment. If the text changes, the token chunks are merged into one
+    /// chunk that covers the whole segment because the old chunk boundaries no longer line up with the text.
+    fn post_process(&mut self, post_processors: &PostProcessorChain) {
+        if self.no_speech {
+            return;
+        }
+        let Some(text) = post_processors.process(&self.result.text) else {
+            return;
+        };
+        if !self.result.chunks.is_empty() {
+            let timestamp = match (
+                self.result
+                    .chunks
+                    .first()
+                    .and_then(|chunk| chunk.timestamp.clone()),
+                self.result
+                    .chunks
+                    .last()
+                    .and_then(|chunk| chunk.timestamp.clone()),
+            ) {
+                (Some(first), Some(last)) => Some(first.start..last.end),
+                _ => None,
+            };
+            self.result.chunks = vec![TokenChunk {
+                text_range: 0..text.len(),
+                timestamp,
+            }];
+        }
+        self.result.text = text;
+    }
+
     /// Get the range this segment covers in the original audio.
     pub fn sample_range(&self) -> Range<usize> {
         self.sample_range.clone()
@@ -134,6 +183,32 @@ impl Segment {
         self.result.no_speech_prob
     }
 
+    /// Check if the segment is a gap where no speech was detected. Gaps cover the skipped audio with their
+    /// [`Segment::start`] and [`Segment::duration`], but their text is always empty.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::new().await?;
+    /// let file = std::io::BufReader::new(std::fs::File::open("./models/rwhisper/examples/samples_jfk.wav")?);
+    /// let audio = rodio::Decoder::new(file)?;
+    /// let mut segments = model.transcribe(audio);
+    /// while let Some(segment) = segments.next().await {
+    ///     let end = segment.start() + segment.duration();
+    ///     if segment.is_no_speech() {
+    ///         println!("[{:.1}s - {end:.1}s] (silence)", segment.start());
+    ///     } else {
+    ///         println!("[{:.1}s - {end:.1}s] {}", segment.start(), segment.text());
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn is_no_speech(&self) -> bool {
+        self.no_speech
+    }
+
     /// Get the text of the segment.
     pub fn text(&self) -> &str {
         &self.result.text
@@ -188,6 +263,32 @@ impl AsRef<str> for Segment {
     }
 }
 
+/// A chunk of audio in a stream that is transcribed with [`TranscribeChunkedAudioStreamExt::transcribe`]. Chunks
+/// that know where they start in the stream shift the timestamps of their segments to match.
+pub trait TranscriptionChunk: Source
+where
+    Self::Item: rodio::Sample,
+{
+    /// Get the offset of the first sample of the chunk from the start of the stream at the sample rate of the chunk,
+    /// or `None` if the segments should be timed from the start of the chunk.
+    fn offset_in_stream(&self) -> Option<usize> {
+        None
+    }
+}
+
+impl<T: rodio::Sample> TranscriptionChunk for SamplesBuffer<T> {}
+
+impl TranscriptionChunk for OffsetSamplesBuffer {
+    fn offset_in_stream(&self) -> Option<usize> {
+        Some(self.sample_offset)
+    }
+}
+
+/// Convert an offset at the sample rate of the audio to an offset at whisper's sample rate.
+fn whisper_sample_offset(sample_offset: usize, sample_rate: u32) -> usize {
+    (sample_offset as u64 * m::SAMPLE_RATE as u64 / sample_rate as u64) as usize
+}
+
 /// An extension trait to transcribe pre-chunked audio streams
 pub trait TranscribeChunkedAudioStreamExt<S> {
     /// Transcribe each chunk of the audio stream with whisper and stream the result
@@ -197,16 +298,18 @@ pub trait TranscribeChunkedAudioStreamExt<S> {
 impl<S> TranscribeChunkedAudioStreamExt<S> for S
 where
     S: Stream + std::marker::Unpin + Send + 'static,
-    <S as Stream>::Item: Source + Send + 'static,
+    <S as Stream>::Item: TranscriptionChunk + Send + 'static,
     <<S as Stream>::Item as Iterator>::Item: rodio::Sample,
     f32: FromSample<<<S as Stream>::Item as Iterator>::Item>,
 {
     fn transcribe(self, model: Whisper) -> ChunkedTranscriptionTask<S> {
         ChunkedTranscriptionTask {
             word_level_time_stamps: false,
+            seed: None,
             stream: self,
             whisper: model,
             current_segment_task: None,
+            error: None,
         }
     }
 }
@@ -214,9 +317,11 @@ where
 /// A chunked audio transcription task which can be streamed from a [`Whisper`] model.
 pub struct ChunkedTranscriptionTask<S> {
     word_level_time_stamps: bool,
+    seed: Option<u64>,
     stream: S,
     whisper: Whisper,
     current_segment_task: Option<TranscriptionTask>,
+    error: Option<WhisperError>,
 }
 
 impl<S> ChunkedTranscriptionTask<S> {
@@ -225,12 +330,24 @@ impl<S> ChunkedTranscriptionTask<S> {
         self.word_level_time_stamps = true;
         self
     }
+
+    /// Set the seed for every chunk of the transcription. See [`TranscriptionTask::with_seed`].
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Take the error from the last chunk that failed to transcribe. A chunk that fails stops producing segments,
+    /// but the rest of the stream is still transcribed. See [`TranscriptionTask::take_error`].
+    pub fn take_error(&mut self) -> Option<WhisperError> {
+        self.error.take()
+    }
 }
 
 impl<S> Stream for ChunkedTranscriptionTask<S>
 where
     S: Stream + std::marker::Unpin + Send + 'static,
-    <S as Stream>::Item: Source + Send + 'static,
+    <S as Stream>::Item: TranscriptionChunk + Send + 'static,
     <<S as Stream>::Item as Iterator>::Item: rodio::Sample,
     f32: FromSample<<<S as Stream>::Item as Iterator>::Item>,
 {
@@ -246,6 +363,9 @@ where
             if let Some(task) = &mut myself.current_segment_task {
                 match task.poll_next_unpin(cx) {
                     std::task::Poll::Ready(ready) => {
+                        if let Some(error) = task.take_error() {
+                            myself.error = Some(error);
+                        }
                         myself.current_segment_task = None;
                         if let Some(ready) = ready {
                             return std::task::Poll::Ready(Some(ready));
@@ -257,10 +377,20 @@ where
 
             match myself.stream.poll_next_unpin(cx) {
                 std::task::Poll::Ready(Some(source)) => {
+                    // If the chunk knows where it starts in the overall stream, offset the segments to match
+                    let sample_offset = source
+                        .offset_in_stream()
+                        .map(|offset| whisper_sample_offset(offset, source.sample_rate()));
                     let mut task = myself.whisper.transcribe(source);
+                    if let Some(sample_offset) = sample_offset {
+                        task = task.with_sample_offset(sample_offset);
+                    }
                     if myself.word_level_time_stamps {
                         task = task.timestamped();
                     }
+                    if let Some(seed) = myself.seed {
+                        task = task.with_seed(seed);
+                    }
                     myself.current_segment_task = Some(task);
                 }
                 std::task::Poll::Ready(None) => return std::task::Poll::Ready(None),
@@ -309,6 +439,30 @@ pub struct WhisperBuilder {
 
     /// The cache location to use for the model (defaults DATA_DIR/kalosm/cache)
     cache: kalosm_common::Cache,
+
+    /// The maximum number of audio windows from different streams to encode in one pass.
+    batch_size: usize,
+
+    /// The post-processors that are run on every segment.
+    post_processors: PostProcessorChain,
+
+    /// The cache of transcribed windows.
+    transcription_cache: Option<TranscriptionCache>,
+
+    /// The device to run the model with.
+    device: Option<Device>,
+
+    /// The devices to try running the model with, in order of preference.
+    device_preference: Vec<DevicePreference>,
+
+    /// The number of threads used for inference on the CPU.
+    threads: Option<usize>,
+
+    /// Whether each inference thread is pinned to its own core.
+    pin_threads: bool,
+
+    /// The seed for sampling when the decoder falls back to a higher temperature.
+    seed: u64,
 }
 
 impl Default for WhisperBuilder {
@@ -317,6 +471,14 @@ impl Default for WhisperBuilder {
             model: WhisperSource::default(),
             language: Some(WhisperLanguage::English),
             cache: kalosm_common::Cache::default(),
+            batch_size: 1,
+            post_processors: PostProcessorChain::default(),
+            transcription_cache: None,
+            device: None,
+            device_preference: Vec::new(),
+            threads: None,
+            pin_threads: false,
+            seed: 0,
         }
     }
 }
@@ -464,6 +626,18 @@ impl WhisperBuilder {
             .await
     }
 
+    /// Build the model and report the combined progress of every file to a [`LoadingHandle`]. If the handle is
+    /// cancelled, the load stops and [`WhisperLoadingError::Cancelled`] is returned.
+    pub async fn build_with_handle(
+        self,
+        handle: &LoadingHandle,
+    ) -> Result<Whisper, WhisperLoadingError> {
+        handle
+            .run(self.build_with_loading_handler(handle.handler()))
+            .await
+            .map_err(|_| WhisperLoadingError::Cancelled)?
+    }
+
     /// Build the model with a handler for progress as the download and loading progresses.
     ///
     /// ```rust, no_run
@@ -500,41 +674,97 @@ impl WhisperBuilder {
         let display_tokenizer_source = format!("Tokenizer ({})", tokenizer_source);
         let mut create_progress =
             ModelLoadingProgress::downloading_progress(display_tokenizer_source);
-        let tokenizer_filename = self
+        let tokenizer = self
             .cache
-            .get(&tokenizer_source, |progress| {
+            .get_bytes(&tokenizer_source, |progress| {
                 progress_handler(create_progress(progress))
             })
             .await?;
 
-        let display_model_source = format!("Model ({})", model_source);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(display_model_source);
-        let filename = self
-            .cache
-            .get(&model_source, |progress| {
-                progress_handler(create_progress(progress))
-            })
-            .await?;
+        let weights = match &model_source {
+            // In-memory weights are read from the buffer instead of being memory mapped from disk
+            FileSource::Bytes { bytes, .. } => WhisperWeights::Bytes(bytes.clone()),
+            _ => {
+                let display_model_source = format!("Model ({})", model_source);
+                let mut create_progress =
+                    ModelLoadingProgress::downloading_progress(display_model_source);
+                let filename = self
+                    .cache
+                    .get(&model_source, |progress| {
+                        progress_handler(create_progress(progress))
+                    })
+                    .await?;
+                WhisperWeights::Path(filename)
+            }
+        };
 
         let display_config_source = format!("Config ({})", config_source);
         let mut create_progress = ModelLoadingProgress::downloading_progress(display_config_source);
         let config = self
             .cache
-            .get(&config_source, |progress| {
+            .get_bytes(&config_source, |progress| {
                 progress_handler(create_progress(progress))
             })
             .await?;
 
+        let batch_size = self.batch_size;
+        let post_processors = self.post_processors.clone();
+        let thread_pool = self
+            .threads
+            .or_else(|| KalosmConfig::global().threads())
+            .map(|threads| kalosm_common::cpu_thread_pool(threads, self.pin_threads))
+            .transpose()?;
         let (rx, tx) = std::sync::mpsc::channel();
         let thread = std::thread::spawn(move || {
-            let mut model = WhisperInner::new(self, filename, tokenizer_filename, config).unwrap();
-            while let Ok(message) = tx.recv() {
-                match message {
-                    WhisperMessage::Kill => return,
-                    WhisperMessage::Transcribe(input, word_level_time_stamps, result) => {
-                        model.transcribe(input, word_level_time_stamps, result);
+            let run = move || {
+                let mut model = WhisperInner::new(self, weights, &tokenizer, &config).unwrap();
+                let mut jobs = VecDeque::new();
+                loop {
+                    // Wait for new audio if there is nothing to transcribe, otherwise just pick up any new streams
+                    let message = if jobs.is_empty() {
+                        match tx.recv() {
+                            Ok(message) => Some(message),
+                            Err(_) => return,
+                        }
+                    } else {
+                        tx.try_recv().ok()
+                    };
+                    for message in message
+                        .into_iter()
+                        .chain(std::iter::from_fn(|| tx.try_recv().ok()))
+                    {
+                        match message {
+                            WhisperMessage::Kill => return,
+                            WhisperMessage::Encode(input, result) => {
+                                _ = result.send(model.encode_audio(&input).map_err(Into::into));
+                            }
+                            WhisperMessage::Transcribe(
+                                input,
+                                word_level_time_stamps,
+                                seed,
+                                result,
+                            ) => {
+                                match model.start_job(
+                                    input,
+                                    word_level_time_stamps,
+                                    seed,
+                                    result.clone(),
+                                ) {
+                                    Ok(job) => jobs.push_back(job),
+                                    Err(err) => {
+                                        tracing::error!("Error transcribing audio: {err}");
+                                        _ = result.unbounded_send(Err(err.into()));
+                                    }
+                                }
+                            }
+                        }
                     }
+                    model.step(&mut jobs, batch_size);
                 }
+            };
+            match thread_pool {
+                Some(thread_pool) => thread_pool.install(run),
+                None => run(),
             }
         });
 
@@ -542,6 +772,7 @@ impl WhisperBuilder {
             inner: Arc::new(WhisperDrop {
                 thread: Some(thread),
                 sender: rx,
+                post_processors,
             }),
         })
     }
@@ -564,6 +795,163 @@ impl WhisperBuilder {
 
         self
     }
+
+    /// Set the maximum number of 30 second audio windows to encode in one pass (defaults to 1).
+    ///
+    /// Every [`Whisper::transcribe`] call on a clone of the model shares the same loaded weights. Streams that are
+    /// transcribed at the same time take turns one window at a time, and with a batch size larger than one the
+    /// encoder runs on a window from several streams at once. Larger batches increase throughput when many streams
+    /// are transcribed concurrently at the cost of more memory.
+    ///
+    /// ```rust, no_run
+    /// use futures_util::StreamExt;
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::builder().with_batch_size(4).build().await?;
+    /// let calls = ["call-1.wav", "call-2.wav", "call-3.wav"];
+    /// let transcriptions = calls.map(|path| {
+    ///     let model = model.clone();
+    ///     tokio::spawn(async move {
+    ///         let file = std::fs::File::open(path).unwrap();
+    ///         let audio = rodio::Decoder::new(std::io::BufReader::new(file)).unwrap();
+    ///         model.transcribe(audio).map(|segment| segment.text().to_string()).collect::<String>().await
+    ///     })
+    /// });
+    /// for transcription in transcriptions {
+    ///     println!("{}", transcription.await?);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Add a post-processor that is run on the text of every segment before it is returned. Post-processors run in
+    /// the order they are added.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::builder()
+    ///     .with_post_processor(NumberNormalizer)
+    ///     .with_post_processor(RegexReplace::new(r"(?i)\bkay losm\b", "Kalosm")?)
+    ///     .with_post_processor(ProfanityFilter::new(["darn", "heck"]))
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_post_processor(mut self, post_processor: impl TranscriptPostProcessor) -> Self {
+        self.post_processors.push(post_processor);
+        self
+    }
+
+    /// Reuse the transcription of any 30 second window of audio that was already transcribed with the same model,
+    /// language and options. Post-processors still run on cached windows. (Defaults to no cache)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::builder()
+    ///     .with_transcription_cache(TranscriptionCache::at("./transcription-cache")?)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_transcription_cache(mut self, cache: TranscriptionCache) -> Self {
+        self.transcription_cache = Some(cache);
+        self
+    }
+
+    /// Set the device to run the model with. This overrides [`WhisperBuilder::with_device_preference`]. (Defaults to
+    /// the device in the [`KalosmConfig`] or an accelerator if available, otherwise the CPU)
+    pub fn with_device(mut self, device: Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Set the devices to try running the model with, in order of preference. The first device that is available is
+    /// used.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// // Keep the GPU free for a language model and transcribe on the CPU
+    /// let model = Whisper::builder()
+    ///     .with_device_preference([DevicePreference::Cpu])
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_device_preference(
+        mut self,
+        preference: impl IntoIterator<Item = DevicePreference>,
+    ) -> Self {
+        self.device_preference = preference.into_iter().collect();
+        self
+    }
+
+    /// Set the number of threads the model uses for inference on the CPU. By default candle uses every core, which
+    /// can starve the rest of your application while audio is transcribed. This overrides the thread count in the
+    /// [`KalosmConfig`] for this model.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::builder()
+    ///     .with_device_preference([DevicePreference::Cpu])
+    ///     .with_threads(2)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Set whether each inference thread is pinned to its own core. This only applies if the thread count is set
+    /// with [`WhisperBuilder::with_threads`]. (Defaults to false)
+    pub fn with_thread_affinity(mut self, pin_threads: bool) -> Self {
+        self.pin_threads = pin_threads;
+        self
+    }
+
+    /// Set the seed for transcriptions that don't set their own seed with [`TranscriptionTask::with_seed`]. Whisper
+    /// decodes greedily, and only samples when a window falls back to a higher temperature because the first result
+    /// was repetitive or unlikely. The random number generator is seeded again for each window, so the same audio
+    /// and seed always give the same transcription. (Defaults to 0)
+    ///
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// let model = Whisper::builder().with_seed(42).build().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Get the device or the default device if not set.
+    pub(crate) fn get_device(&self) -> candle_core::Result<Device> {
+        match self.device.clone() {
+            Some(device) => Ok(device),
+            None => kalosm_common::first_available_device(self.device_preference.iter().copied()),
+        }
+    }
 }
 
 /// A language whisper can use
@@ -899,6 +1287,7 @@ impl Display for WhisperLanguage {
 struct WhisperDrop {
     thread: Option<std::thread::JoinHandle<()>>,
     sender: std::sync::mpsc::Sender<WhisperMessage>,
+    post_processors: PostProcessorChain,
 }
 
 impl Drop for WhisperDrop {
@@ -928,6 +1317,9 @@ impl Whisper {
 
     /// Transcribe some audio into text.
     ///
+    /// Every 30 second window of the audio produces a segment. Windows without speech are sent as gap segments with
+    /// empty text instead of being skipped. You can check for them with [`Segment::is_no_speech`].
+    ///
     /// Dropping the returned channel will stop the transcription early.
     pub fn transcribe<S: Source>(&self, input: S) -> TranscriptionTask
     where
@@ -937,19 +1329,60 @@ impl Whisper {
         let pcm_data: Vec<_> = normalize_audio(input);
         TranscriptionTask {
             word_level_time_stamps: false,
+            seed: None,
+            sample_offset: 0,
             audio: pcm_data,
             sender: self.inner.sender.clone(),
+            post_processors: self.inner.post_processors.clone(),
             receiver: Default::default(),
+            error: None,
         }
     }
+
+    /// Run only the encoder of the model on some audio and get the [`AudioFeatures`] instead of a transcription. This
+    /// is much faster than transcribing because the text decoder never runs.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::sound::*;
+    /// use rodio::Decoder;
+    /// use std::fs::File;
+    /// use std::io::BufReader;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), anyhow::Error> {
+    ///     let model = Whisper::new().await?;
+    ///     let file = BufReader::new(File::open("./models/rwhisper/examples/samples_jfk.wav")?);
+    ///     let features = model.encode_audio(Decoder::new(file)?).await?;
+    ///     println!("{:?}", features.tensor().shape());
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn encode_audio<S: Source>(&self, input: S) -> Result<AudioFeatures, WhisperError>
+    where
+        <S as Iterator>::Item: rodio::Sample,
+        f32: FromSample<<S as Iterator>::Item>,
+    {
+        let pcm_data: Vec<_> = normalize_audio(input);
+        let (sender, receiver) = futures_channel::oneshot::channel();
+        self.inner
+            .sender
+            .send(WhisperMessage::Encode(pcm_data, sender))
+            .map_err(|_| WhisperError::ModelStopped)?;
+        receiver.await.map_err(|_| WhisperError::ModelStopped)?
+    }
 }
 
 /// A transcription task which can be streamed from a [`Whisper`] model.
 pub struct TranscriptionTask {
     word_level_time_stamps: bool,
+    seed: Option<u64>,
+    sample_offset: usize,
     audio: Vec<f32>,
     sender: std::sync::mpsc::Sender<WhisperMessage>,
-    receiver: RwLock<Option<UnboundedReceiver<Segment>>>,
+    post_processors: PostProcessorChain,
+    receiver: RwLock<Option<UnboundedReceiver<Result<Segment, WhisperError>>>>,
+    error: Option<WhisperError>,
 }
 
 impl TranscriptionTask {
@@ -958,6 +1391,33 @@ impl TranscriptionTask {
         self.word_level_time_stamps = true;
         self
     }
+
+    /// Offset the [`Segment::sample_range`] and [`Segment::start`] of every segment by the given number of samples
+    /// at whisper's sample rate (16khz). This is useful when the audio is a chunk of a longer stream.
+    pub fn with_sample_offset(mut self, sample_offset: usize) -> Self {
+        self.sample_offset = sample_offset;
+        self
+    }
+
+    /// Set the seed for this transcription instead of the seed set with [`WhisperBuilder::with_seed`]. The same
+    /// audio and seed always give the same transcription.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Add a post-processor that is only run on the segments of this transcription. It runs after the
+    /// post-processors set on the [`WhisperBuilder`].
+    pub fn with_post_processor(mut self, post_processor: impl TranscriptPostProcessor) -> Self {
+        self.post_processors.push(post_processor);
+        self
+    }
+
+    /// Take the error that ended the stream early. If the model fails to transcribe part of the audio, the stream
+    /// ends and the error is kept here. Returns `None` if the stream has not failed.
+    pub fn take_error(&mut self) -> Option<WhisperError> {
+        self.error.take()
+    }
 }
 
 impl Stream for TranscriptionTask {
@@ -976,19 +1436,110 @@ impl Stream for TranscriptionTask {
             _ = myself.sender.send(WhisperMessage::Transcribe(
                 pcm_data,
                 myself.word_level_time_stamps,
+                myself.seed,
                 sender,
             ));
 
             *write = Some(receiver);
         }
 
-        write.as_mut().unwrap().poll_next_unpin(cx)
+        let sample_offset = myself.sample_offset;
+        let post_processors = &myself.post_processors;
+        let error = &mut myself.error;
+        write
+            .as_mut()
+            .unwrap()
+            .poll_next_unpin(cx)
+            .map(|segment| match segment? {
+                Ok(mut segment) => {
+                    segment.offset_by(sample_offset);
+                    segment.post_process(post_processors);
+                    Some(segment)
+                }
+                Err(err) => {
+                    *error = Some(err);
+                    None
+                }
+            })
+    }
+}
+
+/// A buffer of audio samples that knows where it starts in a longer stream of audio. When a stream of these
+/// buffers is transcribed with [`TranscribeChunkedAudioStreamExt::transcribe`], the segments refer to positions in the
+/// overall stream instead of the current chunk.
+#[derive(Debug, Clone)]
+pub struct OffsetSamplesBuffer {
+    samples: SamplesBuffer<f32>,
+    sample_offset: usize,
+}
+
+impl OffsetSamplesBuffer {
+    /// Create a new buffer that starts `sample_offset` samples (at the sample rate of the buffer) into the stream.
+    pub fn new(samples: SamplesBuffer<f32>, sample_offset: usize) -> Self {
+        Self {
+            samples,
+            sample_offset,
+        }
+    }
+
+    /// Get the offset of the first sample in the buffer from the start of the stream at the sample rate of the buffer.
+    pub fn sample_offset(&self) -> usize {
+        self.sample_offset
+    }
+
+    /// Get the offset of the first sample in the buffer from the start of the stream.
+    pub fn start(&self) -> Duration {
+        Duration::from_secs_f64(self.sample_offset as f64 / self.samples.sample_rate() as f64)
+    }
+
+    /// Get the inner samples buffer.
+    pub fn into_inner(self) -> SamplesBuffer<f32> {
+        self.samples
+    }
+}
+
+impl Iterator for OffsetSamplesBuffer {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.samples.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.samples.size_hint()
+    }
+}
+
+impl Source for OffsetSamplesBuffer {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.samples.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.samples.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.samples.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.samples.total_duration()
     }
 }
 
 enum WhisperMessage {
     Kill,
-    Transcribe(Vec<f32>, bool, UnboundedSender<Segment>),
+    Encode(
+        Vec<f32>,
+        futures_channel::oneshot::Sender<Result<AudioFeatures, WhisperError>>,
+    ),
+    Transcribe(
+        Vec<f32>,
+        bool,
+        Option<u64>,
+        UnboundedSender<Result<Segment, WhisperError>>,
+    ),
 }
 
 pub(crate) fn normalize_audio<S: Source>(input: S) -> Vec<f32>
@@ -1001,3 +1552,35 @@ where
 
     pass_filter.collect::<Vec<f32>>()
 }
+
+#[test]
+fn chunks_offset_their_segments_at_whisper_sample_rate() {
+    let chunk = OffsetSamplesBuffer::new(SamplesBuffer::new(1, 8000, vec![0.0f32; 8000]), 24000);
+    assert_eq!(chunk.start(), Duration::from_secs(3));
+    let offset = chunk.offset_in_stream().unwrap();
+    assert_eq!(whisper_sample_offset(offset, chunk.sample_rate()), 48000);
+    assert_eq!(
+        SamplesBuffer::new(1, 16000, vec![0.0f32; 16000]).offset_in_stream(),
+        None
+    );
+
+    let mut segment = Segment {
+        sample_range: 1600..3200,
+        start: 0.1,
+        duration: 0.1,
+        elapsed_time: Duration::ZERO,
+        remaining_time: Duration::ZERO,
+        progress: 1.0,
+        result: DecodingResult {
+            text: "hello".to_string(),
+            avg_logprob: 0.0,
+            no_speech_prob: 0.0,
+            compression_ratio: 1.0,
+            chunks: Vec::new(),
+        },
+        no_speech: false,
+    };
+    segment.offset_by(48000);
+    assert_eq!(segment.sample_range(), 49600..51200);
+    assert!((segment.start() - 3.1).abs() < 1e-9);
+}
@@ -3,22 +3,34 @@ use candle_nn::ops::softmax;
 use candle_transformers::models::whisper::{self as m, audio, Config};
 use flate2::{write::ZlibEncoder, Compression};
 use futures_channel::mpsc::UnboundedSender;
-use kalosm_common::{accelerated_device_if_available, CacheError, TensorCache};
+use kalosm_common::{CacheError, TensorCache};
 use rand::{distributions::Distribution, SeedableRng};
 use std::{
+    collections::VecDeque,
     io::Write,
     num::NonZeroUsize,
-    ops::RangeInclusive,
+    ops::{Range, RangeInclusive},
     path::PathBuf,
+    sync::Arc,
     time::{Duration, Instant},
 };
 use tokenizers::Tokenizer;
 
 use super::{DecodingResult, Segment};
 use crate::{
-    quantized::TextDecoderCache, Task, TaskType, TokenChunk, WhisperBuilder, WhisperLanguage,
+    cache::window_keys, quantized::TextDecoderCache, AudioFeatures, Task, TaskType, TokenChunk,
+    TranscriptionCache, WhisperBuilder, WhisperLanguage,
 };
 
+/// The weights of a whisper model.
+pub(crate) enum WhisperWeights {
+    /// Weights in a file on disk. The file is memory mapped.
+    Path(PathBuf),
+    /// Weights from a [`FileSource::Bytes`](crate::FileSource::Bytes) source. The weights are read from memory
+    /// instead of being written to the cache first.
+    Bytes(Arc<[u8]>),
+}
+
 enum ModelType {
     Quantized(crate::quantized::Whisper),
     Unquantized(m::model::Whisper),
@@ -26,23 +38,33 @@ enum ModelType {
 
 impl ModelType {
     fn load(
-        weights_filename: &PathBuf,
+        weights: &WhisperWeights,
         device: &Device,
         config: Config,
         quantized: bool,
     ) -> candle_core::Result<Self> {
         if quantized {
-            let vb = crate::m::quantized_model::VarBuilder::from_gguf(weights_filename, device)?;
+            let vb = match weights {
+                WhisperWeights::Path(path) => {
+                    crate::m::quantized_model::VarBuilder::from_gguf(path, device)?
+                }
+                WhisperWeights::Bytes(bytes) => {
+                    crate::m::quantized_model::VarBuilder::from_gguf_buffer(bytes, device)?
+                }
+            };
             Ok(Self::Quantized(crate::quantized::Whisper::load(
                 &vb, config,
             )?))
         } else {
-            let vb = unsafe {
-                candle_nn::VarBuilder::from_mmaped_safetensors(
-                    &[weights_filename],
+            let vb = match weights {
+                WhisperWeights::Path(path) => unsafe {
+                    candle_nn::VarBuilder::from_mmaped_safetensors(&[path], m::DTYPE, device)?
+                },
+                WhisperWeights::Bytes(bytes) => candle_nn::VarBuilder::from_buffered_safetensors(
+                    bytes.to_vec(),
                     m::DTYPE,
                     device,
-                )?
+                )?,
             };
             Ok(Self::Unquantized(m::model::Whisper::load(&vb, config)?))
         }
@@ -77,6 +99,12 @@ pub enum WhisperLoadingError {
     /// Language not supported
     #[error("Language not supported: {0}")]
     UnsupportedLanguage(WhisperLanguage),
+    /// The load was cancelled with a [`LoadingHandle`](crate::LoadingHandle).
+    #[error("The model load was cancelled")]
+    Cancelled,
+    /// Failed to create the thread pool for CPU inference.
+    #[error("Failed to create the CPU thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
 }
 
 /// An error that can occur when running a [`Whisper`] model.
@@ -91,6 +119,9 @@ pub enum WhisperError {
     /// An error that can occur when compressing the text the model generates to determine the compression ratio.
     #[error("Compression error: {0}")]
     Compression(std::io::Error),
+    /// The model thread stopped before the request finished.
+    #[error("The model thread stopped before the request finished")]
+    ModelStopped,
 }
 
 pub(crate) struct WhisperInner {
@@ -98,21 +129,24 @@ pub(crate) struct WhisperInner {
     device: Device,
     decoder: Decoder,
     config: Config,
+    transcription_cache: Option<TranscriptionCache>,
+    cache_namespace: String,
+    /// The seed for jobs that don't set their own seed
+    seed: u64,
 }
 
 impl WhisperInner {
     pub(crate) fn new(
         settings: WhisperBuilder,
-        weights_filename: PathBuf,
-        tokenizer_filename: PathBuf,
-        config_filename: PathBuf,
+        weights: WhisperWeights,
+        tokenizer: &[u8],
+        config: &[u8],
     ) -> Result<Self, WhisperLoadingError> {
-        let device = accelerated_device_if_available()?;
+        let device = settings.get_device()?;
         let tokenizer =
-            Tokenizer::from_file(tokenizer_filename).map_err(WhisperLoadingError::LoadTokenizer)?;
+            Tokenizer::from_bytes(tokenizer).map_err(WhisperLoadingError::LoadTokenizer)?;
         let config: Config =
-            serde_json::from_str(&std::fs::read_to_string(config_filename).unwrap())
-                .map_err(WhisperLoadingError::LoadConfig)?;
+            serde_json::from_slice(config).map_err(WhisperLoadingError::LoadConfig)?;
 
         let mel_bytes = match config.num_mel_bins {
             80 => include_bytes!("melfilters.bytes").as_slice(),
@@ -125,9 +159,11 @@ impl WhisperInner {
             &mut mel_filters,
         );
         let attention_heads = settings.model.timestamp_attention_heads();
+        // Everything about the model that changes the transcription of a window
+        let cache_namespace = format!("{:?}-{:?}", settings.model, settings.language);
 
         let model = ModelType::load(
-            &weights_filename,
+            &weights,
             &device,
             config.clone(),
             settings.model.is_quantized(),
@@ -144,7 +180,7 @@ impl WhisperInner {
         let decoder = Decoder::new(
             model,
             tokenizer,
-            0,
+            settings.seed,
             &device,
             language_token,
             attention_heads,
@@ -155,36 +191,288 @@ impl WhisperInner {
             device,
             decoder,
             config,
+            transcription_cache: settings.transcription_cache,
+            cache_namespace,
+            seed: settings.seed,
         })
     }
 
-    pub(crate) fn transcribe(
-        &mut self,
+    /// Convert the audio to a mel spectrogram and queue it as a new transcription job.
+    pub(crate) fn start_job(
+        &self,
         pcm_data: Vec<f32>,
         word_level_time_stamps: bool,
-        result: UnboundedSender<Segment>,
-    ) {
-        let mel = audio::pcm_to_mel(&self.config, &pcm_data, &self.mel_filters);
-        let mel_len = mel.len();
-        let mel = Tensor::from_vec(
-            mel,
-            (self.config.num_mel_bins, mel_len / self.config.num_mel_bins),
-            &self.device,
-        )
-        .unwrap();
+        seed: Option<u64>,
+        result: UnboundedSender<Result<Segment, WhisperError>>,
+    ) -> candle_core::Result<TranscriptionJob> {
+        let mel = self.mel_spectrogram(&pcm_data)?;
+        let (_, content_frames) = mel.dims2()?;
+        let window_keys = match self.transcription_cache {
+            Some(_) => window_keys(&self.cache_namespace, word_level_time_stamps, &pcm_data),
+            None => Vec::new(),
+        };
 
-        if let Err(err) = self.decoder.run(
-            &mel,
-            pcm_data.len(),
-            Task {
+        Ok(TranscriptionJob {
+            mel,
+            window_keys,
+            audio_frames: pcm_data.len(),
+            content_frames,
+            task: Task {
                 task_type: TaskType::Unset,
                 word_level_time_stamps,
                 without_timestamps: true,
             },
             result,
-        ) {
-            tracing::error!("Error transcribing audio: {err}");
+            seek: 0,
+            start_time: Instant::now(),
+            seed: seed.unwrap_or(self.seed),
+            sentence_fragment: Vec::new(),
+        })
+    }
+
+    fn mel_spectrogram(&self, pcm_data: &[f32]) -> candle_core::Result<Tensor> {
+        let mel = audio::pcm_to_mel(&self.config, pcm_data, &self.mel_filters);
+        let mel_len = mel.len();
+        Tensor::from_vec(
+            mel,
+            (self.config.num_mel_bins, mel_len / self.config.num_mel_bins),
+            &self.device,
+        )
+    }
+
+    /// Run the encoder over each 30 second window of the audio and collect the features of the frames that contain
+    /// audio.
+    pub(crate) fn encode_audio(&mut self, pcm_data: &[f32]) -> candle_core::Result<AudioFeatures> {
+        let mel = self.mel_spectrogram(pcm_data)?;
+        let (_, content_frames) = mel.dims2()?;
+        let mut windows = Vec::new();
+        let mut seek = 0;
+        while seek < content_frames {
+            let segment_size = usize::min(content_frames - seek, m::N_FRAMES);
+            let window = mel.narrow(1, seek, segment_size)?.unsqueeze(0)?;
+            windows.push(self.decoder.encode(&window)?.squeeze(0)?);
+            seek += segment_size;
+        }
+        if windows.is_empty() {
+            return Ok(AudioFeatures::new(Tensor::zeros(
+                (0, self.config.d_model),
+                candle_core::DType::F32,
+                &Device::Cpu,
+            )?));
+        }
+        let features = Tensor::cat(&windows, 0)?;
+        // The mel spectrogram is padded, so drop the frames past the end of the audio. The encoder halves the mel
+        // frame rate, so each frame covers two hops of samples.
+        let audio_frames = pcm_data
+            .len()
+            .div_ceil(m::HOP_LENGTH * 2)
+            .min(features.dim(0)?);
+        let features = features
+            .narrow(0, 0, audio_frames)?
+            .to_dtype(candle_core::DType::F32)?
+            .to_device(&Device::Cpu)?;
+        Ok(AudioFeatures::new(features))
+    }
+
+    /// Transcribe the next 30 second window of up to `batch_size` jobs from the front of the queue. The encoder pass
+    /// for every full window is batched together, then each window is decoded in turn. Jobs that still have audio left
+    /// are moved to the back of the queue so every stream makes progress. If a window fails to encode or decode, the
+    /// error is sent to that job's stream and the job stops without affecting the rest of the batch.
+    pub(crate) fn step(&mut self, jobs: &mut VecDeque<TranscriptionJob>, batch_size: usize) {
+        let mut batch = Vec::new();
+        while batch.len() < batch_size.max(1) {
+            let Some(job) = jobs.pop_front() else {
+                break;
+            };
+            // If the receiver was dropped, stop transcribing the stream
+            if job.result.is_closed() || job.seek >= job.content_frames {
+                continue;
+            }
+            batch.push(job);
+        }
+        if batch.is_empty() {
+            return;
+        }
+
+        let mut windows = Vec::with_capacity(batch.len());
+        let mut uncached = Vec::with_capacity(batch.len());
+        let mut mels = Vec::with_capacity(batch.len());
+        for mut job in batch {
+            let segment_size = usize::min(job.content_frames - job.seek, m::N_FRAMES);
+            let range = job.seek..job.seek + segment_size;
+            job.seek += segment_size;
+            // Windows that were already transcribed skip the encoder and decoder entirely
+            let cached = self
+                .transcription_cache
+                .as_ref()
+                .zip(job.window_key(&range))
+                .and_then(|(cache, key)| cache.get(key));
+            if let Some(result) = cached {
+                job.sentence_fragment = self.decoder.sentence_fragment(&result);
+                job.send_segment(result, range);
+                if job.seek < job.content_frames {
+                    jobs.push_back(job);
+                }
+                continue;
+            }
+            match job.mel.narrow(1, range.start, range.len()) {
+                Ok(mel) => {
+                    mels.push(mel);
+                    windows.push(range);
+                    uncached.push(job);
+                }
+                Err(err) => job.fail(err.into()),
+            }
+        }
+        let batch = uncached;
+        if batch.is_empty() {
+            return;
+        }
+
+        let audio_features = encode_windows(&mels, |mel| self.decoder.encode(mel));
+
+        for ((mut job, range), audio_features) in batch.into_iter().zip(windows).zip(audio_features)
+        {
+            let audio_features = match audio_features {
+                Ok(audio_features) => audio_features,
+                Err(err) => {
+                    tracing::error!("Error encoding audio: {err}");
+                    job.fail(err.into());
+                    continue;
+                }
+            };
+            match self.decoder.decode_window(&job, &audio_features, &range) {
+                Ok(result) => {
+                    if let Some((cache, key)) = self
+                        .transcription_cache
+                        .as_ref()
+                        .zip(job.window_key(&range))
+                    {
+                        cache.insert(key, result.clone());
+                    }
+                    job.sentence_fragment = self.decoder.sentence_fragment(&result);
+                    job.send_segment(result, range);
+                    if job.seek < job.content_frames {
+                        jobs.push_back(job);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("Error transcribing audio: {err}");
+                    job.fail(err);
+                }
+            }
+        }
+    }
+}
+
+/// Run the encoder on the mel spectrogram of one window from each job. Windows that are a full 30 seconds long are
+/// encoded in a single batch. Shorter windows at the end of a stream have different shapes, so they are encoded on
+/// their own. If the batch fails to encode, each window is retried on its own so an error only affects the window
+/// that caused it.
+fn encode_windows(
+    mels: &[Tensor],
+    mut encode: impl FnMut(&Tensor) -> candle_core::Result<Tensor>,
+) -> Vec<candle_core::Result<Tensor>> {
+    let mut audio_features: Vec<Option<candle_core::Result<Tensor>>> =
+        (0..mels.len()).map(|_| None).collect();
+
+    let full: Vec<usize> = (0..mels.len())
+        .filter(|&i| mels[i].dim(1).ok() == Some(m::N_FRAMES))
+        .collect();
+    if full.len() > 1 {
+        let encoded = full.iter().map(|&i| mels[i].clone()).collect::<Vec<_>>();
+        let encoded = Tensor::stack(&encoded, 0)
+            .and_then(|mels| encode(&mels))
+            .and_then(|encoded| encoded.chunk(full.len(), 0));
+        match encoded {
+            Ok(encoded) => {
+                for (&i, features) in full.iter().zip(encoded) {
+                    audio_features[i] = Some(Ok(features));
+                }
+            }
+            Err(err) => tracing::warn!(
+                "Error encoding a batch of audio, encoding each window on its own: {err}"
+            ),
+        }
+    }
+
+    mels.iter()
+        .zip(audio_features)
+        .map(|(mel, features)| features.unwrap_or_else(|| encode(&mel.unsqueeze(0)?)))
+        .collect()
+}
+
+/// A transcription request that is being processed by the model thread.
+pub(crate) struct TranscriptionJob {
+    mel: Tensor,
+    audio_frames: usize,
+    content_frames: usize,
+    task: Task,
+    result: UnboundedSender<Result<Segment, WhisperError>>,
+    seek: usize,
+    start_time: Instant,
+    /// The key of each window in the transcription cache.
+    window_keys: Vec<u64>,
+    /// The seed for sampling at fallback temperatures
+    seed: u64,
+    /// The tokens after the last finished sentence in the previous window. They prompt the next window so a sentence
+    /// split across two windows is decoded with its beginning as context.
+    sentence_fragment: Vec<u32>,
+}
+
+impl TranscriptionJob {
+    /// Get the key of the window in the transcription cache.
+    fn window_key(&self, window: &Range<usize>) -> Option<u64> {
+        self.window_keys.get(window.start / m::N_FRAMES).copied()
+    }
+
+    /// Send the decoded segment for a window to the job's receiver. Windows without speech are not skipped. They are
+    /// sent as gap segments with empty text so the timeline of the stream stays continuous.
+    fn send_segment(&mut self, mut dr: DecodingResult, range: Range<usize>) {
+        let seek = self.seek;
+        let content_frames = self.content_frames;
+        let audio_frames = self.audio_frames;
+        let segment_size = range.end - range.start;
+        let end = range.end;
+        let time_offset = (end * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+        let segment_duration = (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
+
+        let no_speech = is_no_speech(&dr);
+        if no_speech {
+            tracing::trace!("no speech detected, sending a gap {end} {dr:?}");
+            // Any text decoded from silence is a hallucination
+            dr.text.clear();
+            dr.chunks.clear();
         }
+
+        let elapsed = self.start_time.elapsed();
+        let remaining = Duration::from_millis(
+            ((elapsed.as_millis() as usize / seek) * (content_frames - seek)) as u64,
+        );
+        let progress = end as f32 / content_frames as f32;
+        let segment = Segment {
+            sample_range: (range.start * m::HOP_LENGTH)
+                ..audio_frames.min(range.end * m::HOP_LENGTH),
+            start: time_offset,
+            duration: segment_duration,
+            remaining_time: remaining,
+            elapsed_time: elapsed,
+            progress,
+            result: dr,
+            no_speech,
+        };
+
+        if let Err(err) = self.result.start_send(Ok(segment)) {
+            tracing::error!("Error sending segment: {err}");
+            // Stop transcribing the rest of the stream
+            self.seek = content_frames;
+        }
+    }
+
+    /// Send an error to the job's receiver. The job is dropped afterwards, which ends the stream.
+    fn fail(&mut self, err: WhisperError) {
+        _ = self.result.unbounded_send(Err(err));
+        self.seek = self.content_frames;
     }
 }
 
@@ -573,121 +861,59 @@ impl Decoder {
         unreachable!()
     }
 
-    fn run(
+    /// Decode one window of a job that was already encoded.
+    fn decode_window(
         &mut self,
-        mel: &Tensor,
-        audio_frames: usize,
-        task: Task,
-        mut result: UnboundedSender<Segment>,
-    ) -> Result<(), WhisperError> {
-        // TODO: This should be dynamic based on how much memory the model uses and how much memory is available
-        const MAX_CHUNKS: usize = 1;
-
-        let (_, content_frames) = mel.dims2()?;
-        let mut seek = 0;
-        let start_time = Instant::now();
-        let mut chunk_indices = Vec::new();
-        let mut chunked = Vec::new();
-        // Keep looping until we have all the chunks we need
-        while seek < content_frames {
-            // Take a chunk up to the maximum size
-            chunk_indices.clear();
-            chunked.clear();
-            while chunk_indices.len() < MAX_CHUNKS && seek < content_frames {
-                let remaining_frames = content_frames - seek;
-                let segment_size = usize::min(remaining_frames, m::N_FRAMES);
-                // If the new frame doesn't fit into a perfect chunk, just include it in the next chunk
-                if remaining_frames < m::N_FRAMES && !chunk_indices.is_empty() {
-                    break;
-                }
-                chunk_indices.push(seek..seek + segment_size);
-                let mel_segment = mel.narrow(1, seek, segment_size)?;
-                chunked.push(mel_segment);
-                seek += segment_size;
-            }
-
-            // Encode all of the chunks
-            let batched_mel_segment = Tensor::stack(&chunked, 0)?;
-            let batched_audio_features = self.encode(&batched_mel_segment)?;
-            let split = batched_audio_features.chunk(chunk_indices.len(), 0)?;
-
-            // Tokens that are remaining in the last chunk's sentence fragment
-            let mut tokens_in_sentence_fragment = Vec::new();
-
-            for (audio_features, range) in split.iter().zip(chunk_indices.iter()) {
-                let segment_size = range.end - range.start;
-                let end = range.end;
-                let time_offset = (end * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-
-                let segment_duration =
-                    (segment_size * m::HOP_LENGTH) as f64 / m::SAMPLE_RATE as f64;
-                let total_frames = (audio_frames as f64 / m::HOP_LENGTH as f64).round() as usize;
-                let n_frames = segment_size.min(
-                    total_frames
-                        .checked_sub(seek)
-                        .or_else(|| {
-                            seek.checked_sub(m::N_FRAMES)
-                                .and_then(|seek| total_frames.checked_sub(seek))
-                        })
-                        .unwrap_or_default(),
-                );
-                let dr = self.decode_with_fallback(
-                    audio_features,
-                    task,
-                    &tokens_in_sentence_fragment,
-                    n_frames,
-                )?;
-                tokens_in_sentence_fragment.clear();
-                if dr.no_speech_prob > m::NO_SPEECH_THRESHOLD
-                    && dr.avg_logprob < m::LOGPROB_THRESHOLD
-                {
-                    tracing::trace!("no speech detected, skipping {end} {dr:?}");
-                    continue;
-                }
-
-                // Grab any text that was in the previous sentence fragment
-                if let Some(index) = dr.text.char_indices().rev().find_map(|(idx, c)| {
-                    if c == '.' || c == '?' || c == '!' {
-                        Some(idx)
-                    } else {
-                        None
-                    }
-                }) {
-                    let text_after_last_sentence = &dr.text[index + 1..];
-                    let tokens = self
-                        .tokenizer
-                        .encode(text_after_last_sentence, false)
-                        .map_err(WhisperError::Tokenizer)?;
-                    tokens_in_sentence_fragment.extend(tokens.get_ids());
-                };
-
-                let elapsed = start_time.elapsed();
-                let remaining = Duration::from_millis(
-                    ((elapsed.as_millis() as usize / seek) * (content_frames - seek)) as u64,
-                );
-                let progress = end as f32 / content_frames as f32;
-                let segment = Segment {
-                    sample_range: (range.start * m::HOP_LENGTH)
-                        ..audio_frames.min(range.end * m::HOP_LENGTH),
-                    start: time_offset,
-                    duration: segment_duration,
-                    remaining_time: remaining,
-                    elapsed_time: elapsed,
-                    progress,
-                    result: dr,
-                };
+        job: &TranscriptionJob,
+        audio_features: &Tensor,
+        range: &Range<usize>,
+    ) -> Result<DecodingResult, WhisperError> {
+        let seek = job.seek;
+        let segment_size = range.end - range.start;
+        let total_frames = (job.audio_frames as f64 / m::HOP_LENGTH as f64).round() as usize;
+        let n_frames = segment_size.min(
+            total_frames
+                .checked_sub(seek)
+                .or_else(|| {
+                    seek.checked_sub(m::N_FRAMES)
+                        .and_then(|seek| total_frames.checked_sub(seek))
+                })
+                .unwrap_or_default(),
+        );
+        // Seed each window on its own so the result doesn't depend on which jobs were decoded before it
+        self.rng = rand::rngs::StdRng::seed_from_u64(job.seed ^ seek as u64);
+        self.decode_with_fallback(audio_features, job.task, &job.sentence_fragment, n_frames)
+    }
 
-                if let Err(err) = result.start_send(segment) {
-                    tracing::error!("Error sending segment: {err}");
-                    break;
-                }
+    /// Get the tokens after the last finished sentence in a decoded window. Windows without speech or without a
+    /// sentence ending return no tokens.
+    fn sentence_fragment(&self, dr: &DecodingResult) -> Vec<u32> {
+        if is_no_speech(dr) {
+            return Vec::new();
+        }
+        let Some(index) = dr
+            .text
+            .char_indices()
+            .rev()
+            .find_map(|(idx, c)| matches!(c, '.' | '?' | '!').then_some(idx))
+        else {
+            return Vec::new();
+        };
+        match self.tokenizer.encode(&dr.text[index + 1..], false) {
+            Ok(tokens) => tokens.get_ids().to_vec(),
+            Err(err) => {
+                tracing::error!("Error tokenizing the sentence fragment: {err}");
+                Vec::new()
             }
         }
-
-        Ok(())
     }
 }
 
+/// Check if a decoded window is likely to contain no speech.
+fn is_no_speech(dr: &DecodingResult) -> bool {
+    dr.no_speech_prob > m::NO_SPEECH_THRESHOLD && dr.avg_logprob < m::LOGPROB_THRESHOLD
+}
+
 pub fn token_id(tokenizer: &Tokenizer, token: &str) -> candle_core::Result<u32> {
     match tokenizer.token_to_id(token) {
         None => candle_core::bail!("no token-id for {token}"),
@@ -783,3 +1009,67 @@ impl Decoder {
         Ok(logits)
     }
 }
+
+#[test]
+fn batched_encoding_matches_encoding_each_window() {
+    let device = Device::Cpu;
+    let config = Config {
+        num_mel_bins: 4,
+        max_source_positions: m::N_FRAMES / 2,
+        d_model: 8,
+        encoder_attention_heads: 2,
+        encoder_layers: 1,
+        vocab_size: 8,
+        max_target_positions: 8,
+        decoder_attention_heads: 2,
+        decoder_layers: 1,
+        suppress_tokens: Vec::new(),
+    };
+    let varmap = candle_nn::VarMap::new();
+    let vb = candle_nn::VarBuilder::from_varmap(&varmap, candle_core::DType::F32, &device);
+    let mut model = m::model::Whisper::load(&vb, config).unwrap();
+    let mels = [m::N_FRAMES, 1000, m::N_FRAMES]
+        .map(|frames| Tensor::randn(0f32, 1., (4, frames), &device).unwrap());
+
+    let sequential: Vec<Tensor> = mels
+        .iter()
+        .map(|mel| model.encoder.forward(&mel.unsqueeze(0)?, true))
+        .collect::<candle_core::Result<_>>()
+        .unwrap();
+    let assert_matches = |encoded: Vec<candle_core::Result<Tensor>>| {
+        for (encoded, expected) in encoded.into_iter().zip(&sequential) {
+            let difference = (encoded.unwrap() - expected)
+                .unwrap()
+                .abs()
+                .unwrap()
+                .max_all()
+                .unwrap()
+                .to_scalar::<f32>()
+                .unwrap();
+            assert!(difference < 1e-4, "difference {difference}");
+        }
+    };
+
+    let batched = encode_windows(&mels, |mel| model.encoder.forward(mel, true));
+    assert_matches(batched);
+
+    // If the batch fails, every window is encoded on its own
+    let retried = encode_windows(&mels, |mel| {
+        if mel.dim(0)? > 1 {
+            candle_core::bail!("out of memory")
+        }
+        model.encoder.forward(mel, true)
+    });
+    assert_matches(retried);
+
+    // An error only affects the window that caused it
+    let encoded = encode_windows(&mels, |mel| {
+        if mel.dim(2)? != m::N_FRAMES {
+            candle_core::bail!("bad window")
+        }
+        model.encoder.forward(mel, true)
+    });
+    assert!(encoded[0].is_ok());
+    assert!(encoded[1].is_err());
+    assert!(encoded[2].is_ok());
+}
@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use regex::Regex;
+
+/// A post-processor that cleans up the text of each [`Segment`](crate::Segment) before it is sent to the caller.
+///
+/// Any `Fn(&str) -> String` closure can be used as a post-processor.
+pub trait TranscriptPostProcessor: Send + Sync + 'static {
+    /// Process the text of a segment.
+    fn process(&self, text: &str) -> String;
+}
+
+impl<F: Fn(&str) -> String + Send + Sync + 'static> TranscriptPostProcessor for F {
+    fn process(&self, text: &str) -> String {
+        self(text)
+    }
+}
+
+/// A chain of [`TranscriptPostProcessor`]s that are applied in order.
+#[derive(Clone, Default)]
+pub(crate) struct PostProcessorChain {
+    processors: Vec<Arc<dyn TranscriptPostProcessor>>,
+}
+
+impl std::fmt::Debug for PostProcessorChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostProcessorChain")
+            .field("processors", &self.processors.len())
+            .finish()
+    }
+}
+
+impl PostProcessorChain {
+    /// Add a post-processor to the end of the chain.
+    pub(crate) fn push(&mut self, processor: impl TranscriptPostProcessor) {
+        self.processors.push(Arc::new(processor));
+    }
+
+    /// Run every post-processor in the chain on the text. Returns `None` if the text was not changed.
+    pub(crate) fn process(&self, text: &str) -> Option<String> {
+        let mut processed: Option<String> = None;
+        for processor in &self.processors {
+            let current = processed.as_deref().unwrap_or(text);
+            let next = processor.process(current);
+            if next != current {
+                processed = Some(next);
+            }
+        }
+        processed
+    }
+}
+
+/// A post-processor that replaces every match of a regex.
+///
+/// # Example
+/// ```rust
+/// use rwhisper::*;
+///
+/// let replace = RegexReplace::new(r"(?i)\bkay losm\b", "Kalosm").unwrap();
+/// assert_eq!(replace.process("I use kay losm daily"), "I use Kalosm daily");
+/// ```
+#[derive(Debug, Clone)]
+pub struct RegexReplace {
+    regex: Regex,
+    replacement: String,
+}
+
+impl RegexReplace {
+    /// Create a new post-processor that replaces every match of `pattern` with `replacement`. The replacement can
+    /// refer to capture groups with `$name` or `$1`.
+    pub fn new(pattern: &str, replacement: impl ToString) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+impl TranscriptPostProcessor for RegexReplace {
+    fn process(&self, text: &str) -> String {
+        self.regex
+            .replace_all(text, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+/// A post-processor that masks a list of words with `*`. Words are matched case insensitively and only as whole
+/// words.
+///
+/// # Example
+/// ```rust
+/// use rwhisper::*;
+///
+/// let filter = ProfanityFilter::new(["darn"]);
+/// assert_eq!(filter.process("Darn, it broke"), "****, it broke");
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProfanityFilter {
+    regex: Option<Regex>,
+    mask: char,
+}
+
+impl ProfanityFilter {
+    /// Create a new filter that masks the given words.
+    pub fn new(words: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let words: Vec<_> = words
+            .into_iter()
+            .map(|word| regex::escape(word.as_ref()))
+            .filter(|word| !word.is_empty())
+            .collect();
+        let regex = (!words.is_empty()).then(|| {
+            Regex::new(&format!(r"(?i)\b(?:{})\b", words.join("|")))
+                .expect("escaped words always form a valid regex")
+        });
+        Self { regex, mask: '*' }
+    }
+
+    /// Set the character used to mask filtered words. (Defaults to `*`)
+    pub fn with_mask(mut self, mask: char) -> Self {
+        self.mask = mask;
+        self
+    }
+}
+
+impl TranscriptPostProcessor for ProfanityFilter {
+    fn process(&self, text: &str) -> String {
+        match &self.regex {
+            Some(regex) => regex
+                .replace_all(text, |captures: &regex::Captures| {
+                    self.mask.to_string().repeat(captures[0].chars().count())
+                })
+                .into_owned(),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// A post-processor that converts English numbers that are spelled out into digits. Ordinals keep their suffix, so
+/// "march twenty first" becomes "march 21st".
+///
+/// Single words with a value below ten like "one" or "first" are left as they are because they are usually not
+/// meant as numbers.
+///
+/// # Example
+/// ```rust
+/// use rwhisper::*;
+///
+/// let normalizer = NumberNormalizer;
+/// assert_eq!(
+///     normalizer.process("We sold two hundred and forty-five units on June third."),
+///     "We sold 245 units on June third."
+/// );
+/// assert_eq!(normalizer.process("The twenty first of May"), "The 21st of May");
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NumberNormalizer;
+
+impl TranscriptPostProcessor for NumberNormalizer {
+    fn process(&self, text: &str) -> String {
+        let words: Vec<&str> = text.split(' ').collect();
+        let mut output = Vec::with_capacity(words.len());
+        let mut i = 0;
+        while i < words.len() {
+            match parse_number(&words[i..]) {
+                Some((consumed, number)) => {
+                    output.push(number);
+                    i += consumed;
+                }
+                None => {
+                    output.push(words[i].to_string());
+                    i += 1;
+                }
+            }
+        }
+        output.join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumberWord {
+    Unit(u64),
+    Teen(u64),
+    Tens(u64),
+    Hundred,
+    Scale(u64),
+}
+
+fn number_word(word: &str) -> Option<(NumberWord, bool)> {
+    use NumberWord::*;
+    let word = match word {
+        "zero" => (Unit(0), false),
+        "one" => (Unit(1), false),
+        "first" => (Unit(1), true),
+        "two" => (Unit(2), false),
+        "second" => (Unit(2), true),
+        "three" => (Unit(3), false),
+        "third" => (Unit(3), true),
+        "four" => (Unit(4), false),
+        "fourth" => (Unit(4), true),
+        "five" => (Unit(5), false),
+        "fifth" => (Unit(5), true),
+        "six" => (Unit(6), false),
+        "sixth" => (Unit(6), true),
+        "seven" => (Unit(7), false),
+        "seventh" => (Unit(7), true),
+        "eight" => (Unit(8), false),
+        "eighth" => (Unit(8), true),
+        "nine" => (Unit(9), false),
+        "ninth" => (Unit(9), true),
+        "ten" => (Teen(10), false),
+        "tenth" => (Teen(10), true),
+        "eleven" => (Teen(11), false),
+        "eleventh" => (Teen(11), true),
+        "twelve" => (Teen(12), false),
+        "twelfth" => (Teen(12), true),
+        "thirteen" => (Teen(13), false),
+        "fourteen" => (Teen(14), false),
+        "fifteen" => (Teen(15), false),
+        "sixteen" => (Teen(16), false),
+        "seventeen" => (Teen(17), false),
+        "eighteen" => (Teen(18), false),
+        "nineteen" => (Teen(19), false),
+        "twenty" => (Tens(20), false),
+        "thirty" => (Tens(30), false),
+        "forty" => (Tens(40), false),
+        "fifty" => (Tens(50), false),
+        "sixty" => (Tens(60), false),
+        "seventy" => (Tens(70), false),
+        "eighty" => (Tens(80), false),
+        "ninety" => (Tens(90), false),
+        "hundred" => (Hundred, false),
+        "hundredth" => (Hundred, true),
+        "thousand" => (Scale(1_000), false),
+        "thousandth" => (Scale(1_000), true),
+        "million" => (Scale(1_000_000), false),
+        "millionth" => (Scale(1_000_000), true),
+        "billion" => (Scale(1_000_000_000), false),
+        "billionth" => (Scale(1_000_000_000), true),
+        _ => {
+            // Ordinals like "thirteenth" and "twentieth"
+            let stem = word.strip_suffix("ieth").map(|stem| format!("{stem}y"));
+            let stem = stem.or_else(|| word.strip_suffix("th").map(str::to_string))?;
+            let (word, ordinal) = number_word(&stem)?;
+            if ordinal || !matches!(word, Teen(_) | Tens(_)) {
+                return None;
+            }
+            (word, true)
+        }
+    };
+    Some(word)
+}
+
+/// Check if a number word can follow the previous number word in the same number.
+fn can_follow(previous: Option<NumberWord>, next: NumberWord) -> bool {
+    use NumberWord::*;
+    match (previous, next) {
+        (None, _) => true,
+        (Some(Tens(_)), Unit(unit)) => unit != 0,
+        (Some(Unit(_) | Teen(_) | Tens(_)), Hundred) => true,
+        (Some(Unit(_) | Teen(_) | Tens(_) | Hundred), Scale(_)) => true,
+        (Some(Hundred | Scale(_)), Unit(unit)) => unit != 0,
+        (Some(Hundred | Scale(_)), Teen(_) | Tens(_)) => true,
+        _ => false,
+    }
+}
+
+/// Try to parse a spelled out number at the start of the words. Returns the number of words consumed and the
+/// number formatted as digits.
+fn parse_number(words: &[&str]) -> Option<(usize, String)> {
+    let mut total = 0u64;
+    let mut current = 0u64;
+    let mut previous = None;
+    let mut word_count = 0;
+    let mut consumed = 0;
+    let mut ordinal = false;
+    let mut trailing = "";
+
+    'words: for (i, word) in words.iter().enumerate() {
+        let core = word.trim_end_matches(|c: char| c.is_ascii_punctuation() && c != '-');
+        let punctuation = &word[core.len()..];
+        let lowercase = core.to_lowercase();
+        if lowercase == "and" {
+            // "and" only continues a number like "one hundred and five"
+            let next = words.get(i + 1).and_then(|next| {
+                let part = next.split('-').next()?.to_lowercase();
+                number_word(&part)
+            });
+            match (previous, next) {
+                (Some(NumberWord::Hundred | NumberWord::Scale(_)), Some((next, _)))
+                    if punctuation.is_empty() && can_follow(previous, next) =>
+                {
+                    continue;
+                }
+                _ => break,
+            }
+        }
+        let mut parts = Vec::new();
+        for part in lowercase.split('-') {
+            match number_word(part) {
+                Some(part) => parts.push(part),
+                None => break 'words,
+            }
+        }
+        if parts.is_empty() || parts[..parts.len() - 1].iter().any(|(_, ordinal)| *ordinal) {
+            break;
+        }
+        let mut state = (total, current, previous);
+        for &(part, _) in &parts {
+            if !can_follow(state.2, part) {
+                break 'words;
+            }
+            let (total, current, _) = &mut state;
+            match part {
+                NumberWord::Unit(value) | NumberWord::Teen(value) | NumberWord::Tens(value) => {
+                    *current += value
+                }
+                NumberWord::Hundred => *current = (*current).max(1) * 100,
+                NumberWord::Scale(scale) => {
+                    *total += (*current).max(1) * scale;
+                    *current = 0;
+                }
+            }
+            state.2 = Some(part);
+        }
+        (total, current, previous) = state;
+        word_count += parts.len();
+        consumed = i + 1;
+        ordinal = parts.last().is_some_and(|(_, ordinal)| *ordinal);
+        trailing = punctuation;
+        if ordinal || !punctuation.is_empty() {
+            break;
+        }
+    }
+
+    let value = total + current;
+    if consumed == 0 || (word_count == 1 && value < 10) {
+        return None;
+    }
+    let suffix = match (ordinal, value % 100, value % 10) {
+        (false, _, _) => "",
+        (true, 11..=13, _) => "th",
+        (true, _, 1) => "st",
+        (true, _, 2) => "nd",
+        (true, _, 3) => "rd",
+        (true, _, _) => "th",
+    };
+    Some((consumed, format!("{value}{suffix}{trailing}")))
+}
+
+#[test]
+fn normalize_numbers() {
+    let normalizer = NumberNormalizer;
+    let cases = [
+        ("one of them", "one of them"),
+        ("twenty five people", "25 people"),
+        ("one hundred and three", "103"),
+        ("two thousand and twenty-four.", "2024."),
+        ("three million four hundred thousand", "3400000"),
+        ("the fifty third time", "the 53rd time"),
+        ("the twelfth night", "the 12th night"),
+        ("a hundred and fine", "a 100 and fine"),
+        ("twenty, thirty", "20, 30"),
+        ("one two three", "one two three"),
+        ("ninety ninth percentile", "99th percentile"),
+        ("the twentieth century", "the 20th century"),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(normalizer.process(input), expected, "{input}");
+    }
+}